@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rstar::RTree;
+
+use bevy_voxel::{
+    lighting::shaded_light_map,
+    mesh::{generate_chunk_buffers, MeshOrigin},
+    simple::Block,
+    world::{Chunk, Map},
+};
+
+const CHUNK_SIZE: u32 = 4;
+const CHUNKS: i32 = 6;
+/// Every run benchmarks the same maps and lookups.
+const SEED: u64 = 4954;
+
+/// The maps the workloads run on.
+const SHAPES: [Shape; 3] = [Shape::Hills, Shape::Caves, Shape::Scattered];
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Solid rolling hills, two chunks deep.
+    Hills,
+    /// Three chunks of stone riddled with random holes.
+    Caves,
+    /// Random chunks, a quarter of the grid, with random pillars.
+    Scattered,
+}
+
+fn block() -> Block {
+    Block {
+        color: Color::rgb(0.4, 0.6, 0.2),
+        ..Default::default()
+    }
+}
+
+fn chunks(shape: Shape) -> Vec<Chunk<Block>> {
+    let width = 1 << CHUNK_SIZE;
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let mut chunks = Vec::new();
+    let layers = match shape {
+        Shape::Hills => 2,
+        Shape::Caves | Shape::Scattered => 3,
+    };
+    for cx in 0..CHUNKS {
+        for cy in 0..layers {
+            for cz in 0..CHUNKS {
+                let position = (cx * width, cy * width, cz * width);
+                let mut chunk = Chunk::new(CHUNK_SIZE, position);
+                match shape {
+                    Shape::Hills => {
+                        for x in 0..width {
+                            for z in 0..width {
+                                let (wx, wz) = ((position.0 + x) as f32, (position.2 + z) as f32);
+                                let height =
+                                    (20.0 + 6.0 * (wx / 9.0).sin() * (wz / 7.0).cos()) as i32;
+                                for y in 0..width.min(height - position.1) {
+                                    chunk.insert((x, y, z), block());
+                                }
+                            }
+                        }
+                    }
+                    Shape::Caves => {
+                        for x in 0..width {
+                            for y in 0..width {
+                                for z in 0..width {
+                                    if rng.gen_bool(0.8) {
+                                        chunk.insert((x, y, z), block());
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    Shape::Scattered => {
+                        if !rng.gen_bool(0.25) {
+                            continue;
+                        }
+                        for _ in 0..8 {
+                            let (x, z) = (rng.gen_range(0, width), rng.gen_range(0, width));
+                            for y in 0..rng.gen_range(1, width) {
+                                chunk.insert((x, y, z), block());
+                            }
+                        }
+                    }
+                }
+                chunks.push(chunk);
+            }
+        }
+    }
+    chunks
+}
+
+/// Random coordinates in and around the maps, some of them outside of any chunk.
+fn coords() -> Vec<(i32, i32, i32)> {
+    let max = CHUNKS << CHUNK_SIZE;
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    (0..4096)
+        .map(|_| {
+            (
+                rng.gen_range(-8, max + 8),
+                rng.gen_range(-8, 56),
+                rng.gen_range(-8, max + 8),
+            )
+        })
+        .collect()
+}
+
+fn chunk_lookup(c: &mut Criterion) {
+    let coords = coords();
+    let mut group = c.benchmark_group("chunk_lookup");
+    for &shape in &SHAPES {
+        let name = format!("{:?}", shape);
+
+        let map = Map::try_with_chunks(chunks(shape)).unwrap();
+        group.bench_with_input(BenchmarkId::new("map", &name), &coords, |b, coords| {
+            b.iter(|| {
+                for &coords in coords {
+                    black_box(map.chunk_containing(coords));
+                }
+            })
+        });
+
+        let rtree = RTree::bulk_load(chunks(shape));
+        group.bench_with_input(BenchmarkId::new("rtree", &name), &coords, |b, coords| {
+            b.iter(|| {
+                for &(x, y, z) in coords {
+                    black_box(rtree.locate_at_point(&[x, y, z]));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn meshing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mesh_map");
+    for &shape in &SHAPES {
+        let map = Map::try_with_chunks(chunks(shape)).unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{:?}", shape)),
+            &map,
+            |b, map| {
+                b.iter(|| {
+                    for chunk in map.iter() {
+                        black_box(generate_chunk_buffers(map, chunk, MeshOrigin::Corner));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn lighting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shaded_light_map");
+    for &shape in &SHAPES {
+        let map = Map::try_with_chunks(chunks(shape)).unwrap();
+        let positions = map.iter().map(Chunk::position).collect::<Vec<_>>();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{:?}", shape)),
+            &map,
+            |b, map| {
+                b.iter(|| {
+                    for &position in &positions {
+                        black_box(shaded_light_map(map, position));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, chunk_lookup, meshing, lighting);
+criterion_main!(benches);
@@ -0,0 +1,321 @@
+//! Implements `Voxel` and `VoxelExt` for a voxel type of its own and renders a small chunk of
+//! it with a texture.
+//!
+//! The chunk is meshed with texture coordinates and face ids, see `MeshAttributes`, and
+//! `ShaderSnippets` extend the voxel shaders to sample one tile of a texture atlas per face,
+//! grass on top, dirt on the sides and gravel below, tinted by the colors of the voxels.
+//!
+//! ```text
+//! cargo run --example custom_voxel
+//! ```
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::Mesh,
+        render_graph::{base, AssetRenderResourcesNode, RenderGraph},
+        renderer::RenderResources,
+        texture::{Texture, TextureFormat},
+    },
+};
+
+use bevy_fly_camera::FlyCamera;
+
+use bevy_voxel::{
+    lighting,
+    mesh::{Face, MeshAttributes, MeshPart, Neighbours, Transparent},
+    prelude::*,
+    render::entity::{
+        chunk_translation, generate_chunk_mesh_with_attributes, ChunkRenderComponents,
+    },
+};
+
+pub const CHUNK_SIZE: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Grass,
+    Dirt,
+    Stone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub kind: Kind,
+    /// The light of every face, in `Face::ALL` order.
+    pub shade: [f32; 6],
+}
+
+impl Tile {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            shade: [1.0; 6],
+        }
+    }
+
+    /// The tint of the texture of every kind.
+    fn color(&self) -> [f32; 4] {
+        match self.kind {
+            Kind::Grass => [0.4, 0.8, 0.3, 1.0],
+            Kind::Dirt => [0.6, 0.45, 0.3, 1.0],
+            Kind::Stone => [0.6, 0.6, 0.6, 1.0],
+        }
+    }
+}
+
+impl Voxel for Tile {
+    fn average(data: &[Self]) -> Option<Self> {
+        // the most common kind stands in for the others at lower levels of detail
+        let count = |kind| data.iter().filter(|tile| tile.kind == kind).count();
+        let kind = [Kind::Grass, Kind::Dirt, Kind::Stone]
+            .iter()
+            .copied()
+            .max_by_key(|&kind| count(kind))?;
+        data.iter().find(|tile| tile.kind == kind).copied()
+    }
+
+    fn can_merge(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl bevy_voxel::serialize::SerDePartialEq<Self> for Tile {
+    fn serde_eq(&self, other: &Self) -> bool {
+        // shades aren't saved
+        self.kind == other.kind
+    }
+}
+
+impl VoxelExt for Tile {
+    fn mesh(
+        &self,
+        coords: (i32, i32, i32),
+        map: &Map<Self>,
+        chunk: &Chunk<Self>,
+        width: usize,
+    ) -> MeshPart {
+        let mut part = MeshPart {
+            positions: Vec::new(),
+            shades: Vec::new(),
+            colors: Vec::new(),
+            indices: Vec::new(),
+            transparent: Transparent::No,
+        };
+        let neighbours = Neighbours::new(map, chunk, coords, width);
+        let (x, y, z) = coords;
+        let size = width as f32;
+        for (i, &face) in Face::ALL.iter().enumerate() {
+            // every tile is solid, so any neighbour hides the face
+            if neighbours.facing(face).is_some() {
+                continue;
+            }
+            let n = part.positions.len() as u32;
+            for corner in face_corners(face).iter() {
+                part.positions.push([
+                    x as f32 + corner[0] * size,
+                    y as f32 + corner[1] * size,
+                    z as f32 + corner[2] * size,
+                ]);
+                part.shades.push(self.shade[i]);
+                part.colors.push(self.color());
+            }
+            part.indices
+                .extend_from_slice(&[n, n + 1, n + 2, n, n + 2, n + 3]);
+        }
+        part
+    }
+
+    fn face_hidden_by(&self, _neighbour: &Self) -> bool {
+        true
+    }
+
+    fn set_shade(&mut self, face: Face, light: f32) {
+        let i = Face::ALL.iter().position(|&f| f == face).unwrap();
+        self.shade[i] = light;
+    }
+
+    fn shade(&mut self, face: Face) -> Option<f32> {
+        let i = Face::ALL.iter().position(|&f| f == face).unwrap();
+        Some(self.shade[i])
+    }
+}
+
+/// The corners of a face of the unit cube, counter-clockwise seen from outside.
+fn face_corners(face: Face) -> [[f32; 3]; 4] {
+    match face {
+        Face::Top => [[0., 1., 1.], [1., 1., 1.], [1., 1., 0.], [0., 1., 0.]],
+        Face::Bottom => [[0., 0., 0.], [1., 0., 0.], [1., 0., 1.], [0., 0., 1.]],
+        Face::Front => [[0., 0., 1.], [1., 0., 1.], [1., 1., 1.], [0., 1., 1.]],
+        Face::Back => [[1., 0., 0.], [0., 0., 0.], [0., 1., 0.], [1., 1., 0.]],
+        Face::Left => [[1., 0., 1.], [1., 0., 0.], [1., 1., 0.], [1., 1., 1.]],
+        Face::Right => [[0., 0., 0.], [0., 0., 1.], [0., 1., 1.], [0., 1., 0.]],
+    }
+}
+
+/// The number of tiles in the atlas and the width of a tile in pixels.
+const TILES: usize = 3;
+const TILE_WIDTH: usize = 16;
+
+/// The texture atlas of the chunk, bound as `TileAtlas_texture` in the shader snippets.
+#[derive(RenderResources)]
+pub struct TileAtlas {
+    pub texture: Handle<Texture>,
+}
+
+/// Passes the texture coordinates and face ids to the fragment shader, where the tile of the
+/// face is sampled and multiplied into the color. Voxels are tinted by their colors, so one
+/// greyscale tile serves every kind.
+fn snippets() -> ShaderSnippets {
+    ShaderSnippets {
+        vertex_declarations: r#"
+layout(location = 8) in vec2 Voxel_Uv;
+# ifndef VOXELMATERIAL_FACE_SHADING
+layout(location = 7) in float Voxel_FaceId;
+# endif
+layout(location = 8) out vec2 v_uv;
+layout(location = 9) out flat float v_tile;
+"#
+        .into(),
+        vertex_main: r#"
+    v_uv = Voxel_Uv;
+    // top, sides, bottom
+    int face = int(Voxel_FaceId + 0.5);
+    v_tile = face == 0 ? 0.0 : face == 1 ? 2.0 : 1.0;
+"#
+        .into(),
+        fragment_declarations: r#"
+layout(location = 8) in vec2 v_uv;
+layout(location = 9) in flat float v_tile;
+layout(set = 3, binding = 0) uniform texture2D TileAtlas_texture;
+layout(set = 3, binding = 1) uniform sampler TileAtlas_texture_sampler;
+"#
+        .into(),
+        fragment_main: format!(
+            r#"
+    vec2 uv = vec2((v_tile + fract(v_uv.x)) / {}.0, fract(v_uv.y));
+    o_Target.rgb *= texture(sampler2D(TileAtlas_texture, TileAtlas_texture_sampler), uv).rgb;
+"#,
+            TILES
+        ),
+    }
+}
+
+/// Paints a greyscale atlas of `TILES` tiles side by side: blades of grass, layered dirt and
+/// coarse gravel.
+fn atlas() -> Texture {
+    let width = TILES * TILE_WIDTH;
+    let mut data = Vec::with_capacity(width * TILE_WIDTH * 4);
+    for y in 0..TILE_WIDTH {
+        for x in 0..width {
+            let (tile, u) = (x / TILE_WIDTH, x % TILE_WIDTH);
+            let noise = hash(x as u32, y as u32) as f32 / u32::MAX as f32;
+            let value = match tile {
+                0 => 0.7 + 0.3 * noise,
+                1 => 0.75 + 0.15 * noise + 0.1 * ((y / 3) % 2) as f32,
+                _ => 0.6 + 0.4 * (hash((u / 4) as u32, (y / 4) as u32) % 4) as f32 / 3.0,
+            };
+            let value = (value * 255.0) as u8;
+            data.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    Texture::new(
+        Vec2::new(width as f32, TILE_WIDTH as f32),
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn hash(x: u32, y: u32) -> u32 {
+    let mut h = x.wrapping_mul(0x27d4_eb2d) ^ y.wrapping_mul(0x1656_67b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^ (h >> 13)
+}
+
+/// A hill of stone under dirt under grass.
+fn hill() -> Chunk<Tile> {
+    let mut chunk = Chunk::new(CHUNK_SIZE, (0, 0, 0));
+    let width = chunk.width() as i32;
+    for x in 0..width {
+        for z in 0..width {
+            let height = 2 + (x + z) / 4;
+            for y in 0..=height {
+                let kind = match height - y {
+                    0 => Kind::Grass,
+                    1 | 2 => Kind::Dirt,
+                    _ => Kind::Stone,
+                };
+                chunk.insert((x, y, z), Tile::new(kind));
+            }
+        }
+    }
+    chunk
+}
+
+pub fn main() {
+    App::build()
+        .add_default_plugins()
+        .add_plugin(VoxelRenderPlugin {
+            shaders: VoxelShaders {
+                snippets: snippets(),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .add_plugin(bevy_fly_camera::FlyCameraPlugin)
+        .add_asset::<TileAtlas>()
+        .add_startup_system(setup.system())
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut render_graph: ResMut<RenderGraph>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut atlases: ResMut<Assets<TileAtlas>>,
+) {
+    render_graph.add_system_node(
+        "tile_atlas",
+        AssetRenderResourcesNode::<TileAtlas>::new(true),
+    );
+    render_graph
+        .add_node_edge("tile_atlas", base::node::MAIN_PASS)
+        .unwrap();
+
+    let mut chunk = hill();
+    let directional = DirectionalLight {
+        direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+        intensity: 0.8,
+    };
+    let ambient = AmbientLight::new(0.2);
+    lighting::simple_light(&mut chunk, &directional, &ambient);
+
+    let mut map = Map::new();
+    map.try_insert(chunk).expect("chunk has the wrong width");
+    let chunk = map.get((0, 0, 0)).unwrap();
+    let attributes = MeshAttributes {
+        uvs: true,
+        tangents: false,
+        face_ids: true,
+    };
+    let (mesh, _) =
+        generate_chunk_mesh_with_attributes(&map, chunk, MeshOrigin::Corner, &attributes);
+    let texture = textures.add(atlas());
+
+    commands
+        .spawn(ChunkRenderComponents {
+            mesh: meshes.add(mesh.expect("the hill has faces")),
+            material: materials.add(VoxelMaterial::default()),
+            translation: chunk_translation(chunk, MeshOrigin::Corner),
+            ..Default::default()
+        })
+        .with(atlases.add(TileAtlas { texture }))
+        .spawn(FlyCamera {
+            translation: Translation::new(4.0, 8.0, 18.0),
+            ..Default::default()
+        });
+}
@@ -0,0 +1,259 @@
+//! Generates a wide world and flies the camera around it in circles, so that chunks keep
+//! switching their level of detail. Columns near the edge of the world are drawn as impostors.
+//! Prints the frame time and how many chunks are at every level of detail once a second.
+//!
+//! ```text
+//! cargo run --release --example lod_stress [radius in chunks]
+//! ```
+
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, PrintDiagnosticsPlugin},
+    prelude::*,
+    render::mesh::Mesh,
+    render::{camera::ActiveCameras, render_graph::base},
+};
+
+use bevy_fly_camera::FlyCamera;
+
+use bevy_voxel::{
+    mesh::update_visibility,
+    render::{
+        entity::{chunk_translation, generate_lod_chunk_mesh, MeshOrigin, VoxelExt},
+        impostor::impostor_update,
+        light::*,
+        lod::lod_update,
+        prelude::*,
+    },
+    simple::Block,
+    terrain::*,
+    world::{ChunkUpdate, Map, MapComponents, MapLayout, MapUpdates},
+};
+
+pub const CHUNK_SIZE: u32 = 4;
+/// The radius of the circle the camera flies, in voxels.
+pub const ORBIT: f32 = 128.0;
+/// How long one circle takes, in seconds.
+pub const PERIOD: f64 = 20.0;
+
+pub fn main() {
+    let radius = std::env::args()
+        .nth(1)
+        .map(|radius| radius.parse().expect("the radius has to be a number"))
+        .unwrap_or(24);
+
+    App::build()
+        .add_default_plugins()
+        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(PrintDiagnosticsPlugin::default())
+        .add_plugin(VoxelRenderPlugin::default())
+        .add_plugin(bevy_fly_camera::FlyCameraPlugin)
+        .add_resource(Radius(radius))
+        .add_startup_system(setup.system())
+        .add_resource(DirectionalLight {
+            direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+            intensity: 0.8,
+        })
+        .add_resource(AmbientLight::new(0.05))
+        // short lod distances, so the lod changes often while the camera flies
+        .add_resource(LodConfig {
+            distance: 48,
+            hysteresis: 8,
+            simplify_lod: 2,
+            simplify_error: 0.02,
+            ..Default::default()
+        })
+        .add_resource(ImpostorConfig {
+            distance: 256,
+            hysteresis: 16,
+        })
+        .add_resource(LightingMode::Auto)
+        .add_resource(program())
+        .add_plugin(TerrainPlugin::<Block>::default())
+        .init_resource::<LodStats>()
+        .add_stage_before(stage::PRE_UPDATE, "stage_terrain_generation")
+        .add_stage_after("stage_terrain_generation", "stage_lod_update")
+        .add_system_to_stage(
+            "stage_terrain_generation",
+            terrain_generation::<Block>.system(),
+        )
+        .add_system_to_stage("stage_lod_update", lod_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, orbit_update.system())
+        .add_system_to_stage(stage::UPDATE, lod_stats_update.system())
+        .add_system_to_stage(
+            stage::UPDATE,
+            light_map_update::<Block, line_drawing::Bresenham3d<i32>>.system(),
+        )
+        .add_system_to_stage(stage::UPDATE, shaded_light_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, simple_light_update::<Block>.system())
+        .add_system_to_stage(stage::POST_UPDATE, chunk_update::<Block>.system())
+        // after meshing, which shows the chunks an impostor hides
+        .add_system_to_stage(stage::POST_UPDATE, impostor_update::<Block>.system())
+        .run();
+}
+
+/// The radius of the generated world, in chunks.
+pub struct Radius(pub i32);
+
+#[derive(Default)]
+pub struct LodStats {
+    elapsed: f32,
+}
+
+fn program() -> Program<Block> {
+    Program::build()
+        .seed(0)
+        .noise_type(NoiseType::SuperSimplex)
+        .noise_dimensions(NoiseDimensions::Two)
+        .chunk_size(CHUNK_SIZE)
+        .biome(
+            Biome::build()
+                .name("hills")
+                .octave(Octave::new(12.0, 0.01))
+                .octave(Octave::new(2.0, 0.05))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.5, 0.5, 0.5),
+                        ..Default::default()
+                    },
+                    f64::INFINITY,
+                ))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.0, 0.416, 0.306),
+                        ..Default::default()
+                    },
+                    1.0,
+                ))
+                .build(),
+        )
+        .build()
+        .expect("invalid terrain program")
+}
+
+/// requests every chunk of the world up front
+fn setup(mut commands: Commands, radius: Res<Radius>) {
+    let width = 1 << CHUNK_SIZE;
+    let mut updates = MapUpdates::default();
+    for x in -radius.0..radius.0 {
+        for y in -1..2 {
+            for z in -radius.0..radius.0 {
+                updates.request(
+                    (x * width, y * width, z * width),
+                    ChunkUpdate::GenerateChunk,
+                );
+            }
+        }
+    }
+    commands
+        .spawn(FlyCamera {
+            translation: Translation::new(ORBIT, 32.0, 0.0),
+            ..Default::default()
+        })
+        .spawn(MapComponents {
+            map_update: updates,
+        })
+        .with(Map::<Block>::with_layout(MapLayout::new(CHUNK_SIZE)))
+        .with(Impostors::default());
+}
+
+/// moves the camera along a circle around the origin
+fn orbit_update(time: Res<Time>, camera: Res<ActiveCameras>, translation: Query<&mut Translation>) {
+    let camera = match camera.get(base::camera::CAMERA3D) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let angle = (time.seconds_since_startup / PERIOD * std::f64::consts::PI * 2.0) as f32;
+    if let Ok(mut translation) = translation.get_mut::<Translation>(camera) {
+        translation.0 = Vec3::new(ORBIT * angle.cos(), 32.0, ORBIT * angle.sin());
+    }
+}
+
+/// prints how many chunks are at every level of detail once a second
+fn lod_stats_update(
+    time: Res<Time>,
+    mut stats: ResMut<LodStats>,
+    mut maps: Query<(&Map<Block>, &Impostors)>,
+) {
+    stats.elapsed += time.delta_seconds;
+    if stats.elapsed < 1.0 {
+        return;
+    }
+    stats.elapsed = 0.0;
+    for (map, impostors) in &mut maps.iter() {
+        let mut lods = Vec::new();
+        for chunk in map.iter() {
+            if lods.len() <= chunk.lod() {
+                lods.resize(chunk.lod() + 1, 0);
+            }
+            lods[chunk.lod()] += 1;
+        }
+        println!(
+            "{} chunks by lod: {:?}, {} columns as impostors",
+            map.len(),
+            lods,
+            impostors.len()
+        );
+    }
+}
+
+fn chunk_update<T: VoxelExt>(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut pool: ResMut<ChunkPool>,
+    lod_config: Res<LodConfig>,
+    mut maps: Query<(&mut Map<T>, &mut MapUpdates)>,
+    chunks: Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+) {
+    for (mut map, mut update) in &mut maps.iter() {
+        for coords in update.drain_kind(ChunkUpdate::UpdateMesh, usize::MAX) {
+            update_visibility(&mut map, coords);
+            let chunk = if let Some(chunk) = map.get(coords) {
+                chunk
+            } else {
+                continue;
+            };
+            let (mesh, t_mesh) =
+                generate_lod_chunk_mesh(&map, &chunk, MeshOrigin::Center, &lod_config);
+            let translation = chunk_translation(&chunk, MeshOrigin::Center);
+
+            let chunk = map.get_mut(coords).unwrap();
+            for (mesh, transparent) in vec![(mesh, false), (t_mesh, true)] {
+                let mesh = match mesh {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                let entity = if transparent {
+                    chunk.transparent_entity()
+                } else {
+                    chunk.entity()
+                };
+                let old_mesh = entity
+                    .and_then(|e| chunks.get::<Handle<Mesh>>(e).ok())
+                    .and_then(|handle| meshes.get_mut(&handle));
+                if let Some(old_mesh) = old_mesh {
+                    *old_mesh = mesh;
+                    continue;
+                }
+                let e = pool.acquire(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &chunks,
+                    mesh,
+                    translation,
+                    transparent,
+                );
+                if transparent {
+                    chunk.set_transparent_entity(e);
+                } else {
+                    chunk.set_entity(e);
+                }
+            }
+
+            if let Err(e) = update.complete(chunk, ChunkUpdate::UpdateMesh) {
+                log::warn!("{}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Generates, lights and saves a region of the world without opening a window, e.g. to ship a
+//! prebaked spawn area with a game. The saved map loads like any other, see `save_load`.
+//!
+//! ```text
+//! cargo run --release --example pregenerate [save directory] [radius]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use bevy_voxel::{prelude::*, terrain::*};
+
+pub const CHUNK_SIZE: u32 = 4;
+
+pub fn main() {
+    let mut args = std::env::args().skip(1);
+    let save_directory = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("bevy_voxel_pregenerate"));
+    let radius = args
+        .next()
+        .map(|radius| radius.parse().expect("the radius has to be a number"))
+        .unwrap_or(64);
+    let map = run(&save_directory, radius);
+    println!("saved {} chunks to {}", map.len(), save_directory.display());
+}
+
+fn run(save_directory: &Path, radius: i32) -> Map<Block> {
+    let directional = DirectionalLight {
+        direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+        intensity: 0.8,
+    };
+    let ambient = AmbientLight::new(0.05);
+
+    let mut last = None;
+    pregenerate(
+        &program(),
+        (-radius, -16, -radius),
+        (radius - 1, 31, radius - 1),
+        &directional,
+        &ambient,
+        save_directory,
+        |stage, done, total| {
+            // one line per stage and tenth of the way
+            let step = (stage, done * 10 / total);
+            if last != Some(step) {
+                println!("{:?}: {}/{} chunks", stage, done, total);
+                last = Some(step);
+            }
+        },
+    )
+    .expect("couldn't pregenerate the world")
+}
+
+fn program() -> Program<Block> {
+    Program::build()
+        .seed(0)
+        .noise_type(NoiseType::SuperSimplex)
+        .noise_dimensions(NoiseDimensions::Two)
+        .chunk_size(CHUNK_SIZE)
+        .biome(
+            Biome::build()
+                .name("hills")
+                .octave(Octave::new(12.0, 0.01))
+                .octave(Octave::new(2.0, 0.05))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.5, 0.5, 0.5),
+                        ..Default::default()
+                    },
+                    f64::INFINITY,
+                ))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.396, 0.263, 0.129),
+                        ..Default::default()
+                    },
+                    3.0,
+                ))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.0, 0.416, 0.306),
+                        ..Default::default()
+                    },
+                    1.0,
+                ))
+                .water(Layer::new(
+                    Block {
+                        color: Color::rgba(0.4, 0.8, 1.0, 0.5),
+                        ..Default::default()
+                    },
+                    0.0,
+                ))
+                .build(),
+        )
+        .build()
+        .expect("invalid terrain program")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn pregenerate() {
+        let save_directory = std::env::temp_dir().join("bevy_voxel_pregenerate_test");
+        let map = run(&save_directory, 16);
+        // 2 by 3 by 2 chunks of 16 voxels
+        assert_eq!(map.len(), 12);
+        let loaded = Map::<Block>::load(&save_directory).unwrap();
+        assert_eq!(loaded.len(), map.len());
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
+}
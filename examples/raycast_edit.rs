@@ -0,0 +1,226 @@
+//! Digs and builds in a small generated world with the mouse: left click blows a hole where
+//! the camera looks, right click places a block on the face it looks at.
+//!
+//! ```text
+//! cargo run --example raycast_edit
+//! ```
+
+use bevy::{
+    prelude::*,
+    render::mesh::Mesh,
+    render::{camera::ActiveCameras, render_graph::base},
+};
+
+use bevy_fly_camera::FlyCamera;
+
+use bevy_voxel::{
+    mesh::update_visibility,
+    render::{
+        entity::{chunk_translation, generate_chunk_mesh_with, MeshOrigin, VoxelExt},
+        light::*,
+        prelude::*,
+    },
+    simple::Block,
+    terrain::*,
+    world::{ChunkUpdate, Map, MapComponents, MapLayout, MapUpdates},
+};
+
+pub const CHUNK_SIZE: u32 = 4;
+/// How far the camera reaches, in voxels.
+pub const REACH: f32 = 64.0;
+
+pub fn main() {
+    App::build()
+        .add_default_plugins()
+        .add_plugin(VoxelRenderPlugin::default())
+        .add_plugin(bevy_fly_camera::FlyCameraPlugin)
+        .add_startup_system(setup.system())
+        .add_resource(DirectionalLight {
+            direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+            intensity: 0.8,
+        })
+        .add_resource(AmbientLight::new(0.05))
+        .add_resource(program())
+        .add_plugin(TerrainPlugin::<Block>::default())
+        .add_system_to_stage(stage::PRE_UPDATE, terrain_generation::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, edit_update.system())
+        .add_system_to_stage(
+            stage::UPDATE,
+            light_map_update::<Block, line_drawing::Bresenham3d<i32>>.system(),
+        )
+        .add_system_to_stage(stage::UPDATE, shaded_light_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, simple_light_update::<Block>.system())
+        .add_system_to_stage(stage::POST_UPDATE, chunk_update::<Block>.system())
+        .run();
+}
+
+fn program() -> Program<Block> {
+    Program::build()
+        .seed(0)
+        .noise_type(NoiseType::SuperSimplex)
+        .noise_dimensions(NoiseDimensions::Two)
+        .chunk_size(CHUNK_SIZE)
+        .biome(
+            Biome::build()
+                .name("plains")
+                .octave(Octave::new(6.0, 0.02))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.5, 0.5, 0.5),
+                        ..Default::default()
+                    },
+                    f64::INFINITY,
+                ))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.0, 0.416, 0.306),
+                        ..Default::default()
+                    },
+                    1.0,
+                ))
+                .build(),
+        )
+        .build()
+        .expect("invalid terrain program")
+}
+
+/// set up a world of 8 by 8 chunks around the origin
+fn setup(mut commands: Commands) {
+    let width = 1 << CHUNK_SIZE;
+    let mut updates = MapUpdates::default();
+    for x in -4..4 {
+        for y in -1..2 {
+            for z in -4..4 {
+                updates.request(
+                    (x * width, y * width, z * width),
+                    ChunkUpdate::GenerateChunk,
+                );
+            }
+        }
+    }
+    commands
+        .spawn(FlyCamera {
+            translation: Translation::new(0.0, 24.0, 0.0),
+            ..Default::default()
+        })
+        .spawn(MapComponents {
+            map_update: updates,
+        })
+        .with(Map::<Block>::with_layout(MapLayout::new(CHUNK_SIZE)));
+}
+
+/// casts a ray from the camera on every click and edits the voxels it hits
+fn edit_update(
+    buttons: Res<Input<MouseButton>>,
+    camera: Res<ActiveCameras>,
+    mut maps: Query<(&mut Map<Block>, &mut MapUpdates)>,
+    transforms: Query<(&Translation, &Rotation)>,
+) {
+    let dig = buttons.just_pressed(MouseButton::Left);
+    let build = buttons.just_pressed(MouseButton::Right);
+    if !dig && !build {
+        return;
+    }
+    let camera = match camera.get(base::camera::CAMERA3D) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let (origin, direction) = match (
+        transforms.get::<Translation>(camera),
+        transforms.get::<Rotation>(camera),
+    ) {
+        (Ok(translation), Ok(rotation)) => (translation.0, rotation.0 * -Vec3::unit_z()),
+        _ => return,
+    };
+
+    for (mut map, mut updates) in &mut maps.iter() {
+        // water and plants don't stop the ray
+        let hit = match map.raycast_solid(origin, direction, REACH) {
+            Some(hit) => hit,
+            None => continue,
+        };
+        if build {
+            let block = Block {
+                color: Color::rgb(0.8, 0.2, 0.2),
+                ..Default::default()
+            };
+            map.set_voxel(hit.adjacent(), Some(block), &mut updates);
+            continue;
+        }
+        // the chunks around the hole are relit and meshed once, not once per voxel
+        let (x, y, z) = hit.position;
+        let removed = map.batch(&mut updates, |editor| {
+            for dx in -2..=2 {
+                for dy in -2..=2 {
+                    for dz in -2..=2 {
+                        if dx * dx + dy * dy + dz * dz <= 4 {
+                            editor.set_voxel((x + dx, y + dy, z + dz), None);
+                        }
+                    }
+                }
+            }
+            editor.edits()
+        });
+        println!("removed {} voxels around {:?}", removed, hit.position);
+    }
+}
+
+fn chunk_update<T: VoxelExt>(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut pool: ResMut<ChunkPool>,
+    mut maps: Query<(&mut Map<T>, &mut MapUpdates)>,
+    chunks: Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+) {
+    for (mut map, mut update) in &mut maps.iter() {
+        for coords in update.drain_kind(ChunkUpdate::UpdateMesh, usize::MAX) {
+            update_visibility(&mut map, coords);
+            let chunk = if let Some(chunk) = map.get(coords) {
+                chunk
+            } else {
+                continue;
+            };
+            let (mesh, t_mesh) = generate_chunk_mesh_with(&map, &chunk, MeshOrigin::Center);
+            let translation = chunk_translation(&chunk, MeshOrigin::Center);
+
+            let chunk = map.get_mut(coords).unwrap();
+            for (mesh, transparent) in vec![(mesh, false), (t_mesh, true)] {
+                let mesh = match mesh {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                let entity = if transparent {
+                    chunk.transparent_entity()
+                } else {
+                    chunk.entity()
+                };
+                let old_mesh = entity
+                    .and_then(|e| chunks.get::<Handle<Mesh>>(e).ok())
+                    .and_then(|handle| meshes.get_mut(&handle));
+                if let Some(old_mesh) = old_mesh {
+                    *old_mesh = mesh;
+                    continue;
+                }
+                let e = pool.acquire(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &chunks,
+                    mesh,
+                    translation,
+                    transparent,
+                );
+                if transparent {
+                    chunk.set_transparent_entity(e);
+                } else {
+                    chunk.set_entity(e);
+                }
+            }
+
+            if let Err(e) = update.complete(chunk, ChunkUpdate::UpdateMesh) {
+                log::warn!("{}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Generates a few chunks, builds a tower into them, saves the map and loads it back.
+//!
+//! ```text
+//! cargo run --example save_load [save directory]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use bevy_voxel::prelude::*;
+
+pub const CHUNK_SIZE: u32 = 4;
+
+pub fn main() {
+    let save_directory = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("bevy_voxel_save_load"));
+    run(&save_directory);
+}
+
+fn run(save_directory: &Path) {
+    let program = program();
+    let width = 1 << CHUNK_SIZE;
+    let mut height_map = HeightMap::new();
+    let mut map = Map::with_layout(MapLayout::new(CHUNK_SIZE));
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let coords = (x * width, y * width, z * width);
+                let chunk = program
+                    .execute(&mut height_map, coords)
+                    .expect("couldn't generate chunk");
+                map.try_insert(chunk).expect("chunk has the wrong width");
+            }
+        }
+    }
+
+    // edits are what makes saving worthwhile
+    let tower = Block {
+        color: Color::rgb(0.8, 0.2, 0.2),
+        ..Default::default()
+    };
+    let mut updates = MapUpdates::default();
+    let edits = map.batch(&mut updates, |editor| {
+        for y in -4..12 {
+            editor.set_voxel((0, y, 0), Some(tower));
+        }
+        editor.edits()
+    });
+    println!("built a tower of {} blocks", edits);
+
+    map.save(save_directory).expect(&format!(
+        "couldn't save map to {}",
+        save_directory.display()
+    ));
+    println!("saved {} chunks to {}", map.len(), save_directory.display());
+
+    let loaded = Map::<Block>::load(save_directory).expect(&format!(
+        "couldn't load map from {}",
+        save_directory.display()
+    ));
+    assert_eq!(loaded.len(), map.len());
+    for y in -4..12 {
+        assert_eq!(loaded.voxel((0, y, 0)).as_deref(), Some(&tower));
+    }
+    println!("loaded {} chunks with the tower intact", loaded.len());
+}
+
+fn program() -> Program<Block> {
+    Program::build()
+        .seed(0)
+        .noise_type(NoiseType::SuperSimplex)
+        .noise_dimensions(NoiseDimensions::Two)
+        .chunk_size(CHUNK_SIZE)
+        .biome(
+            Biome::build()
+                .name("plains")
+                .octave(Octave::new(4.0, 0.05))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.5, 0.5, 0.5),
+                        ..Default::default()
+                    },
+                    f64::INFINITY,
+                ))
+                .layer(Layer::new(
+                    Block {
+                        color: Color::rgb(0.0, 0.416, 0.306),
+                        ..Default::default()
+                    },
+                    1.0,
+                ))
+                .build(),
+        )
+        .build()
+        .expect("invalid terrain program")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn save_load() {
+        let save_directory = std::env::temp_dir().join("bevy_voxel_save_load_test");
+        run(&save_directory);
+        std::fs::remove_dir_all(&save_directory).unwrap();
+    }
+}
@@ -17,15 +17,28 @@ use bevy_fly_camera::FlyCamera;
 
 use bevy_voxel::{
     collections::lod_tree::Voxel,
+    config::ConfigPlugin,
+    mesh::update_visibility,
     render::{
-        entity::{generate_chunk_mesh, VoxelExt},
+        entity::{chunk_translation, generate_lod_chunk_mesh, MeshOrigin, VoxelExt},
+        grading::color_grading_update,
         light::*,
+        layer::map_layer_update,
+        loading::loading_marker_update,
         lod::lod_update,
+        origin::floating_origin_update,
         prelude::*,
     },
     simple::{Block, MeshType},
     terrain::*,
-    world::{ChunkUpdate, Map, MapComponents, MapUpdates},
+    world::{
+        area_trigger_update, chunk_unload_update, defrag_update, find_spawn,
+        generation_cancel_update, invalidation_update, prefetch_update, update_cause_diagnostics,
+        update_limits_update, warm_up_update, world_reset_update, AreaEvent, AreaTracker,
+        ChunkUnloaded, ChunkUnloader, ChunkUpdate, DefragBudget, FileBackend, Map, MapComponents,
+        MapLayout, MapUpdates, Prefetch, PrefetchViewer, SaveManifest, TriggerAreas,
+        UpdateBackpressure, UpdateCause, WarmUp, WarmUpProgress, WorldMeta, WorldReady, WorldReset,
+    },
 };
 
 pub const CHUNK_SIZE: u32 = 4;
@@ -93,13 +106,16 @@ pub fn main() {
                     },
                     f64::INFINITY,
                 ))
-                .layer(Layer::new(
-                    Block {
-                        color: Color::rgb(0.5, 0.5, 0.5),
-                        ..Default::default()
-                    },
-                    16.0,
-                ))
+                .layer(
+                    Layer::new(
+                        Block {
+                            color: Color::rgb(0.5, 0.5, 0.5),
+                            ..Default::default()
+                        },
+                        16.0,
+                    )
+                    .with_strata(Strata::new(2.0, 0.08).with_warp(3.0, 0.02), Block::strata),
+                )
                 .layer(Layer::new(
                     Block {
                         color: Color::rgb(0.396, 0.263, 0.129),
@@ -110,6 +126,7 @@ pub fn main() {
                 .layer(Layer::new(
                     Block {
                         color: Color::rgb(0.0, 0.416, 0.306),
+                        covered_color: Some(Color::rgb(0.396, 0.263, 0.129)),
                         ..Default::default()
                     },
                     1.0,
@@ -124,13 +141,16 @@ pub fn main() {
                 .per_xz(
                     Expression::Ratio(3, 10)
                         .is_true()
+                        .and_then(BlockQuery::slope_below(1.0))
                         .and_then(BlockQuery::y_top())
                         .set_block(Block {
                             color: Color::rgb(0.0, 0.6, 0.2),
                             mesh_type: MeshType::Cross,
+                            tinted: true,
                             ..Default::default()
                         }),
                 )
+                .tint(Tint::gradient([1.0, 1.0, 1.0], [1.2, 1.1, 0.7], 0.0, 24.0))
                 .build(),
         )
         .biome(
@@ -165,6 +185,7 @@ pub fn main() {
                 .layer(Layer::new(
                     Block {
                         color: Color::rgb(0.0, 0.416, 0.306),
+                        covered_color: Some(Color::rgb(0.396, 0.263, 0.129)),
                         ..Default::default()
                     },
                     1.0,
@@ -220,6 +241,7 @@ pub fn main() {
                 .layer(Layer::new(
                     Block {
                         color: Color::rgb(0.0, 0.416, 0.306),
+                        covered_color: Some(Color::rgb(0.396, 0.263, 0.129)),
                         ..Default::default()
                     },
                     1.0,
@@ -233,22 +255,51 @@ pub fn main() {
                 ))
                 .build(),
         )
-        .build();
+        .build()
+        .expect("invalid terrain program");
     App::build()
         .add_default_plugins()
         .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
         .add_plugin(bevy::diagnostic::PrintDiagnosticsPlugin::default())
         .add_plugin(VoxelRenderPlugin::default())
         .add_plugin(bevy_fly_camera::FlyCameraPlugin)
-        .add_startup_system(setup::<Block>.system())
+        .add_startup_system(setup.system())
+        .add_event::<WarmUpProgress>()
+        .add_event::<WorldReady>()
+        .add_event::<UpdateBackpressure>()
+        .add_event::<WorldReset>()
+        .add_event::<AreaEvent>()
+        .add_event::<ChunkUnloaded<Block>>()
+        .init_resource::<TriggerAreas>()
         .add_resource(DirectionalLight {
             direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
             intensity: 0.8,
         })
-        .add_resource(AmbientLight { intensity: 0.05 })
+        // more light from the sky than from the ground, so faces in the shade keep some depth
+        .add_resource(AmbientLight {
+            intensity: 0.05,
+            sky: Some(SkyAmbient {
+                sky: 0.08,
+                horizon: 0.05,
+                ground: 0.03,
+            }),
+        })
+        .add_resource(LightingMode::Auto)
+        .add_resource(LodConfig {
+            dither: 0.06,
+            ..Default::default()
+        })
         .add_resource(params)
+        .add_resource(Prefetch {
+            heights: Some((-16, WORLD_HEIGHT - 32)),
+            ..Default::default()
+        })
+        // tune lighting and streaming in voxel.ron while the example runs
+        .add_plugin(ConfigPlugin::new("voxel.ron").with_hot_reload(1.0))
         .init_resource::<ExitListenerState>()
-        .init_resource::<HeightMap>()
+        .init_resource::<WarmUpListenerState>()
+        .add_plugin(TerrainPlugin::<Block>::default())
+        .init_resource::<DefragBudget>()
         .add_stage_before(stage::PRE_UPDATE, "stage_terrain_generation")
         .add_stage_after("stage_terrain_generation", "stage_lod_update")
         .add_system_to_stage(
@@ -256,167 +307,264 @@ pub fn main() {
             terrain_generation::<Block>.system(),
         )
         .add_system_to_stage("stage_lod_update", lod_update::<Block>.system())
+        .add_system_to_stage(
+            "stage_lod_update",
+            floating_origin_update::<Block>.system(),
+        )
+        .add_system_to_stage(stage::UPDATE, warm_up_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, warm_up_listener.system())
         .add_system_to_stage(stage::UPDATE, infinite_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, generation_cancel_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, chunk_unload_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, prefetch_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, update_limits_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, defrag_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, area_trigger_update.system())
+        .add_system_to_stage(stage::FIRST, world_reset_update::<Block>.system())
+        .add_system_to_stage(stage::FIRST, height_map_reset_update.system())
+        .add_system_to_stage(stage::UPDATE, light_region_update.system())
+        .add_system_to_stage(stage::UPDATE, invalidation_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, update_cause_diagnostics.system())
         .add_system_to_stage(
             stage::UPDATE,
             light_map_update::<Block, line_drawing::Bresenham3d<i32>>.system(),
         )
         .add_system_to_stage(stage::UPDATE, shaded_light_update::<Block>.system())
-        //.add_system_to_stage(stage::UPDATE, simple_light_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, simple_light_update::<Block>.system())
+        .add_system_to_stage(stage::UPDATE, unlit_mode_update.system())
         .add_system_to_stage(stage::POST_UPDATE, chunk_update::<Block>.system())
+        .add_system_to_stage(stage::POST_UPDATE, map_layer_update::<Block>.system())
+        .add_system_to_stage(stage::POST_UPDATE, color_grading_update::<Block>.system())
+        .add_system_to_stage(stage::POST_UPDATE, loading_marker_update::<Block>.system())
         .add_system_to_stage(stage::POST_UPDATE, save_game::<Block>.system())
         .run();
 }
 
 /// set up a simple 3D scene
-fn setup<T: Voxel>(mut commands: Commands, params: Res<Program<T>>, mut height_map: ResMut<HeightMap>) {
+fn setup(mut commands: Commands, program: Res<Program<Block>>) {
     let mut update = MapUpdates::default();
+    update.track_causes = true;
+    update.limits.generate = Some(2048);
     let chunk_size = 2_i32.pow(CHUNK_SIZE as u32);
-    let world_width_2 = WORLD_WIDTH / chunk_size / 2;
-    let world_height = WORLD_HEIGHT / chunk_size;
-
-    commands.spawn(FlyCamera {
-        translation: Translation::new(0.0, WORLD_HEIGHT as f32 - chunk_size as f32, 0.0),
-        ..Default::default()
-    });
+    let layout = MapLayout::new(CHUNK_SIZE);
+    // the initial world is streamed in around the origin while the game already runs
+    let warm_up = WarmUp::new(layout, (0, 0, 0), WORLD_WIDTH / chunk_size / 2)
+        .with_heights(-chunk_size, WORLD_HEIGHT - 2 * chunk_size);
+    commands.insert_resource(warm_up);
 
     if let Some(save_directory) = std::env::args().skip(1).next() {
         let save_directory: &Path = save_directory.as_ref();
         if save_directory.exists() {
-            for cx in -world_width_2..world_width_2 {
-                for cy in -1..world_height - 1 {
-                    for cz in -world_width_2..world_width_2 {
-                        let x = cx * chunk_size;
-                        let y = cy * chunk_size;
-                        let z = cz * chunk_size;
-                        update
-                            .updates
-                            .insert((x, y, z), ChunkUpdate::UpdateLightMap);
-                    }
-                }
-            }
-            commands.spawn(MapComponents { map_update: update }).with(
-                Map::<Block>::load(save_directory).expect(&format!(
-                    "couldn't load map from {}",
+            let mut map = Map::<Block>::load(save_directory).expect(&format!(
+                "couldn't load map from {}",
+                save_directory.display()
+            ));
+            map.invalidate_all(ChunkUpdate::UpdateLightMap, &mut update);
+            // warns if chunks generated from now on won't match the saved ones
+            let backend = FileBackend::new(save_directory);
+            SaveManifest::check_generator(&backend, program.fingerprint()).expect(&format!(
+                "couldn't read the manifest of {}",
+                save_directory.display()
+            ));
+            let meta = WorldMeta::load(save_directory)
+                .expect(&format!(
+                    "couldn't load world metadata from {}",
                     save_directory.display()
-                )),
-            );
+                ))
+                .unwrap_or_default();
+            let areas = TriggerAreas::load(save_directory).expect(&format!(
+                "couldn't load trigger areas from {}",
+                save_directory.display()
+            ));
+            let spawn = meta.spawn.or_else(|| spawn_point(&map));
+            spawn_camera(&mut commands, spawn);
+            commands
+                .insert_resource(WorldMeta { spawn, ..meta })
+                .insert_resource(areas)
+                .spawn(MapComponents { map_update: update })
+                .with(map)
+                .with(ChunkUnloader::default())
+                .with(ColorGrading::default())
+                .with(MapLayer::default());
             return;
         }
     }
 
-    for cx in -world_width_2..world_width_2 {
-        for cy in -1..world_height - 1 {
-            for cz in -world_width_2..world_width_2 {
-                let x = cx * chunk_size;
-                let y = cy * chunk_size;
-                let z = cz * chunk_size;
-                update.updates.insert((x, y, z), ChunkUpdate::GenerateChunk);
-            }
-        }
-    }
-    
-    let mut map = Vec::new();
+    // the spawn point is found once the world is ready
+    spawn_camera(&mut commands, None);
+    commands
+        .insert_resource(WorldMeta::default())
+        .spawn(MapComponents { map_update: update })
+        .with(Map::<Block>::with_layout(layout))
+        .with(ChunkUnloader::default())
+        .with(ColorGrading::default())
+        .with(MapLayer::default());
+}
 
-    for x in -world_width_2..world_width_2 {
-        for y in -1..world_height - 1 {
-            for z in -world_width_2..world_width_2 {
-                let x = x * chunk_size;
-                let y = y * chunk_size;
-                let z = z * chunk_size;
-                let chunk = params.execute(&mut height_map, (x, y, z));
-                map.push(chunk);
-                update.updates.insert((x, y, z), ChunkUpdate::UpdateLightMap);
-            }
+#[derive(Default)]
+pub struct WarmUpListenerState {
+    progress: EventReader<WarmUpProgress>,
+    ready: EventReader<WorldReady>,
+    /// The last logged tenth of the progress.
+    logged: Option<u32>,
+}
+
+/// logs the loading progress in steps of 10% and moves the camera to solid ground once the
+/// world is ready
+fn warm_up_listener(
+    mut state: ResMut<WarmUpListenerState>,
+    progress_events: Res<Events<WarmUpProgress>>,
+    ready_events: Res<Events<WorldReady>>,
+    camera: Res<ActiveCameras>,
+    origin: Res<FloatingOrigin>,
+    mut meta: ResMut<WorldMeta>,
+    mut maps: Query<&Map<Block>>,
+    translation: Query<&mut Translation>,
+) {
+    let mut tenth = None;
+    for progress in state.progress.iter(&progress_events) {
+        tenth = Some(progress.percent() as u32 / 10);
+    }
+    if tenth.is_some() && tenth != state.logged {
+        state.logged = tenth;
+        log::info!("loading world: {}%", tenth.unwrap() * 10);
+    }
+    if state.ready.iter(&ready_events).next().is_none() || meta.spawn.is_some() {
+        return;
+    }
+    let spawn = if let Some(map) = (&mut maps.iter()).into_iter().next() {
+        spawn_point(&map)
+    } else {
+        return;
+    };
+    meta.spawn = spawn;
+    let camera = camera.get(base::camera::CAMERA3D);
+    if let (Some((x, y, z)), Some(camera)) = (spawn, camera) {
+        if let Ok(mut translation) = translation.get_mut::<Translation>(camera) {
+            translation.0 = origin.to_local((x, y + 1, z)) + Vec3::new(0.5, 0.5, 0.5);
         }
     }
+}
+
+/// finds solid ground near the origin with room for the camera above it
+fn spawn_point(map: &Map<Block>) -> Option<(i32, i32, i32)> {
+    find_spawn(map, (0, 0), 64, 2, |block| !block.transparent())
+}
+
+fn spawn_camera(commands: &mut Commands, spawn: Option<(i32, i32, i32)>) {
+    // fall back to the top of the world if there's nowhere to stand
+    let translation = match spawn {
+        Some((x, y, z)) => Translation::new(x as f32 + 0.5, y as f32 + 1.5, z as f32 + 0.5),
+        None => Translation::new(0.0, WORLD_HEIGHT as f32 - 16.0, 0.0),
+    };
     commands
-        .spawn(MapComponents { map_update: update })
-        .with(Map::<T>::with_chunks(map));
+        .spawn(FlyCamera {
+            translation,
+            ..Default::default()
+        })
+        .with(PrefetchViewer::default())
+        .with(AreaTracker::default());
 }
 
 fn chunk_update<T: VoxelExt>(
     mut commands: Commands,
+    origin: Res<FloatingOrigin>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut pool: ResMut<ChunkPool>,
+    lod_config: Res<LodConfig>,
     mut maps: Query<(&mut Map<T>, &mut MapUpdates)>,
-    chunks: Query<&Handle<Mesh>>,
+    chunks: Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
 ) {
     for (mut map, mut update) in &mut maps.iter() {
-        let mut remove = Vec::new();
-        for (&(x, y, z), update) in &update.updates {
-            match update {
-                ChunkUpdate::UpdateMesh => {}
-                _ => continue,
-            }
-            remove.push((x, y, z));
+        for (x, y, z) in update.drain_kind(ChunkUpdate::UpdateMesh, usize::MAX) {
+            update_visibility(&mut map, (x, y, z));
+            let chunk = if let Some(chunk) = map.get((x, y, z)) {
+                chunk
+            } else {
+                // the chunk was unloaded before it could be meshed
+                continue;
+            };
 
-            let chunk = map.get((x, y, z)).unwrap();
-
-            let (mesh, t_mesh) = generate_chunk_mesh(&map, &chunk);
+            let (mesh, t_mesh) =
+                generate_lod_chunk_mesh(&map, &chunk, MeshOrigin::Center, &lod_config);
+            let mut translation = chunk_translation(&chunk, MeshOrigin::Center);
+            translation.0 -= origin.offset();
 
+            let chunk = map.get_mut((x, y, z)).unwrap();
             if let Some(mesh) = mesh {
-                let chunk = map.get_mut((x, y, z)).unwrap();
-                if let Some(e) = chunk.entity() {
-                    *meshes.get_mut(&chunks.get(e).unwrap()).unwrap() = mesh;
+                let old_mesh = chunk
+                    .entity()
+                    .and_then(|e| chunks.get::<Handle<Mesh>>(e).ok())
+                    .and_then(|handle| meshes.get_mut(&handle));
+                if let Some(old_mesh) = old_mesh {
+                    *old_mesh = mesh;
                 } else {
-                    let e = Entity::new();
-                    commands.spawn_as_entity(e, ChunkRenderComponents {
-                        mesh: meshes.add(mesh),
-                        material: materials.add(VoxelMaterial {
-                            albedo: Color::WHITE,
-                        }),
-                        translation: Translation::new(x as f32, y as f32, z as f32),
-                        ..Default::default()
-                    });
+                    let e = pool.acquire(
+                        &mut commands,
+                        &mut meshes,
+                        &mut materials,
+                        &chunks,
+                        mesh,
+                        translation,
+                        false,
+                    );
                     chunk.set_entity(e);
                 }
             }
             
             if let Some(mesh) = t_mesh {
-                let chunk = map.get_mut((x, y, z)).unwrap();
-                if let Some(e) = chunk.transparent_entity() {
-                    *meshes.get_mut(&chunks.get(e).unwrap()).unwrap() = mesh;
+                let old_mesh = chunk
+                    .transparent_entity()
+                    .and_then(|e| chunks.get::<Handle<Mesh>>(e).ok())
+                    .and_then(|handle| meshes.get_mut(&handle));
+                if let Some(old_mesh) = old_mesh {
+                    *old_mesh = mesh;
                 } else {
-                    let e = Entity::new();
-                    commands.spawn_as_entity(e, ChunkRenderComponents {
-                        mesh: meshes.add(mesh),
-                        material: materials.add(VoxelMaterial {
-                            albedo: Color::WHITE,
-                        }),
-                        translation: Translation::new(x as f32, y as f32, z as f32),
-                        ..Default::default()
-                    });
+                    let e = pool.acquire(
+                        &mut commands,
+                        &mut meshes,
+                        &mut materials,
+                        &chunks,
+                        mesh,
+                        translation,
+                        true,
+                    );
                     chunk.set_transparent_entity(e);
                 }
             }
-        }
-        for coords in remove {
-            update.updates.remove(&coords);
+
+            if let Err(e) = update.complete(chunk, ChunkUpdate::UpdateMesh) {
+                log::warn!("{}", e);
+            }
         }
     }
 }
 
 pub fn infinite_update<T: Voxel>(
     camera: Res<ActiveCameras>,
+    origin: Res<FloatingOrigin>,
     mut query: Query<(&Map<T>, &mut MapUpdates)>,
     translation: Query<&Translation>,
 ) {
-    let (camera_x, camera_z) = if let Some(camera) = camera.get(base::camera::CAMERA3D) {
-        let position = translation.get::<Translation>(camera).unwrap();
-        (
-            position.0.x() as i32,
-            position.0.z() as i32,
-        )
-    } else {
-        (0, 0)
-    };
+    let (camera_x, _, camera_z) = camera
+        .get(base::camera::CAMERA3D)
+        .and_then(|camera| translation.get::<Translation>(camera).ok())
+        .map(|position| origin.to_world(position.0))
+        .unwrap_or(origin.origin);
     
     let range = 8;
     let chunk_size = 2_i32.pow(CHUNK_SIZE as u32);
     let world_height = WORLD_HEIGHT / chunk_size;
     
     for (map, mut update) in &mut query.iter() {
+        update.focus = Some((camera_x, 0, camera_z));
+        // a chunk of margin, so chunks at the edge aren't requested and cancelled in turn
+        update.interest = Some((range + 2) * chunk_size);
+        // let the queue drain before asking for more
+        if update.is_saturated(ChunkUpdate::GenerateChunk) {
+            continue;
+        }
         let x = camera_x / chunk_size;
         let z = camera_z / chunk_size;
         for x in x - range..=x + range {
@@ -426,7 +574,11 @@ pub fn infinite_update<T: Voxel>(
                     let y = y * chunk_size;
                     let z = z * chunk_size;
                     if map.get((x, y, z)).is_none() {
-                        update.updates.insert((x, y, z), ChunkUpdate::GenerateChunk);
+                        update.request_because(
+                            (x, y, z),
+                            ChunkUpdate::GenerateChunk,
+                            UpdateCause::Streaming,
+                        );
                     }
                 }
             }
@@ -444,17 +596,33 @@ pub struct ExitListenerState {
 fn save_game<T: VoxelExt + Serialize + DeserializeOwned>(
     mut state: ResMut<ExitListenerState>,
     exit_events: Res<Events<AppExit>>,
+    meta: Res<WorldMeta>,
+    areas: Res<TriggerAreas>,
+    program: Res<Program<T>>,
     mut query: Query<&Map<T>>,
 ) {
     if let Some(_) = state.reader.iter(&exit_events).next() {
         if let Some(save_directory) = std::env::args().skip(1).next() {
             let save_directory: &Path = save_directory.as_ref();
+            meta.save(save_directory).expect(&format!(
+                "couldn't save world metadata to {}",
+                save_directory.display()
+            ));
+            areas.save(save_directory).expect(&format!(
+                "couldn't save trigger areas to {}",
+                save_directory.display()
+            ));
             for map in &mut query.iter() {
                 map.save(save_directory).expect(&format!(
                     "couldn't save map to {}",
                     save_directory.display()
                 ));
             }
+            let backend = FileBackend::new(save_directory);
+            SaveManifest::record_generator(&backend, program.fingerprint()).expect(&format!(
+                "couldn't record the generator of {}",
+                save_directory.display()
+            ));
         }
     }
 }
@@ -0,0 +1,310 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::collections::{lod_tree::Voxel, LodTree, VoxelStorage};
+
+/// The number of bits of a coordinate inside a brick.
+const BRICK_BITS: i32 = 2;
+/// The width of a brick.
+pub const BRICK_WIDTH: usize = 1 << BRICK_BITS;
+const BRICK_MASK: i32 = BRICK_WIDTH as i32 - 1;
+const BRICK_VOLUME: usize = BRICK_WIDTH * BRICK_WIDTH * BRICK_WIDTH;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Brick<T> {
+    /// One bit per voxel, set if the voxel isn't empty.
+    occupancy: u64,
+    voxels: Vec<Option<T>>,
+}
+
+impl<T> Brick<T> {
+    fn new() -> Self {
+        Self {
+            occupancy: 0,
+            voxels: (0..BRICK_VOLUME).map(|_| None).collect(),
+        }
+    }
+}
+
+/// Voxels stored in 4³ bricks, laid out x-major, with a bitmask of the occupied voxels of
+/// every brick.
+///
+/// Unlike `LodTree` there are no references to follow, so lookups take constant time and
+/// iterating skips empty bricks and voxels without touching them, which suits meshing and
+/// neighbour queries. It has no levels of detail.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BrickMap<T> {
+    width: usize,
+    /// The number of bricks along each side.
+    side: usize,
+    len: usize,
+    bricks: Vec<Option<Box<Brick<T>>>>,
+}
+
+impl<T: Voxel> BrickMap<T> {
+    pub fn new(width: usize) -> Self {
+        let side = (width + BRICK_WIDTH - 1) / BRICK_WIDTH;
+        Self {
+            width,
+            side,
+            len: 0,
+            bricks: (0..side * side * side).map(|_| None).collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.bricks.iter_mut().for_each(|brick| *brick = None);
+        self.len = 0;
+    }
+
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        let (brick, voxel) = self.index(coords)?;
+        self.bricks[brick].as_ref()?.voxels[voxel].as_ref()
+    }
+
+    pub fn get_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut T> {
+        let (brick, voxel) = self.index(coords)?;
+        self.bricks[brick].as_mut()?.voxels[voxel].as_mut()
+    }
+
+    /// Only reads the bitmask of the brick the voxel is in.
+    pub fn contains_key(&self, coords: (i32, i32, i32)) -> bool {
+        match self.index(coords) {
+            Some((brick, voxel)) => self.bricks[brick]
+                .as_ref()
+                .map(|brick| brick.occupancy & (1 << voxel) != 0)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Replaces the voxel at `coords`, returning the old one. Does nothing if `coords` are
+    /// outside the map.
+    pub fn insert(&mut self, coords: (i32, i32, i32), value: T) -> Option<T> {
+        let (brick, voxel) = self.index(coords)?;
+        let brick = self.bricks[brick].get_or_insert_with(|| Box::new(Brick::new()));
+        brick.occupancy |= 1 << voxel;
+        let old = brick.voxels[voxel].replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, coords: (i32, i32, i32)) -> Option<T> {
+        let (index, voxel) = self.index(coords)?;
+        let brick = self.bricks[index].as_mut()?;
+        let old = brick.voxels[voxel].take()?;
+        brick.occupancy &= !(1 << voxel);
+        if brick.occupancy == 0 {
+            self.bricks[index] = None;
+        }
+        self.len -= 1;
+        Some(old)
+    }
+
+    /// Returns the bitmask of the occupied voxels of the brick at brick coordinates
+    /// `(bx, by, bz)`, with bit `(x * 4 + y) * 4 + z` for the voxel at `(x, y, z)` inside it.
+    pub fn occupancy(&self, (bx, by, bz): (i32, i32, i32)) -> u64 {
+        let side = self.side as i32;
+        if bx < 0 || by < 0 || bz < 0 || bx >= side || by >= side || bz >= side {
+            return 0;
+        }
+        let index = ((bx * side + by) * side + bz) as usize;
+        self.bricks[index]
+            .as_ref()
+            .map(|brick| brick.occupancy)
+            .unwrap_or(0)
+    }
+
+    /// Iterates over all voxels brick by brick.
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32, i32), &T)> {
+        let side = self.side as i32;
+        self.bricks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, brick)| Some((index as i32, brick.as_ref()?)))
+            .flat_map(move |(index, brick)| {
+                brick
+                    .voxels
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(voxel, value)| {
+                        Some((coords(side, index, voxel as i32), value.as_ref()?))
+                    })
+            })
+    }
+
+    /// Like `iter`, but the voxels can be changed in place.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ((i32, i32, i32), &mut T)> {
+        let side = self.side as i32;
+        self.bricks
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, brick)| Some((index as i32, brick.as_mut()?)))
+            .flat_map(move |(index, brick)| {
+                brick
+                    .voxels
+                    .iter_mut()
+                    .enumerate()
+                    .filter_map(move |(voxel, value)| {
+                        Some((coords(side, index, voxel as i32), value.as_mut()?))
+                    })
+            })
+    }
+
+    /// Returns the index of the brick and of the voxel inside it.
+    fn index(&self, (x, y, z): (i32, i32, i32)) -> Option<(usize, usize)> {
+        let width = self.width as i32;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= width || z >= width {
+            return None;
+        }
+        let side = self.side as i32;
+        let brick = ((x >> BRICK_BITS) * side + (y >> BRICK_BITS)) * side + (z >> BRICK_BITS);
+        let voxel = ((x & BRICK_MASK) << (2 * BRICK_BITS))
+            | ((y & BRICK_MASK) << BRICK_BITS)
+            | (z & BRICK_MASK);
+        Some((brick as usize, voxel as usize))
+    }
+}
+
+/// Returns the coordinates of voxel `voxel` of brick `brick` in a map `side` bricks wide.
+fn coords(side: i32, brick: i32, voxel: i32) -> (i32, i32, i32) {
+    let x = (brick / (side * side)) << BRICK_BITS;
+    let y = (brick / side % side) << BRICK_BITS;
+    let z = (brick % side) << BRICK_BITS;
+    (
+        x + (voxel >> (2 * BRICK_BITS)),
+        y + ((voxel >> BRICK_BITS) & BRICK_MASK),
+        z + (voxel & BRICK_MASK),
+    )
+}
+
+impl<'a, T: Voxel> From<&'a LodTree<T>> for BrickMap<T> {
+    /// Copies the voxels of `tree` at full detail.
+    fn from(tree: &'a LodTree<T>) -> Self {
+        let mut map = Self::new(tree.width());
+        for elem in tree.opt_elements() {
+            let value = match elem.value {
+                Some(value) => value,
+                None => continue,
+            };
+            let width = elem.width as i32;
+            for x in elem.x..elem.x + width {
+                for y in elem.y..elem.y + width {
+                    for z in elem.z..elem.z + width {
+                        map.insert((x, y, z), value.clone());
+                    }
+                }
+            }
+        }
+        map
+    }
+}
+
+impl<'a, T: Voxel> From<&'a BrickMap<T>> for LodTree<T> {
+    /// Copies the voxels of `map` and merges them.
+    fn from(map: &'a BrickMap<T>) -> Self {
+        let mut tree = Self::new(map.width());
+        for (coords, value) in map.iter() {
+            tree.insert(coords, value.clone());
+        }
+        tree.merge();
+        tree
+    }
+}
+
+impl<T: Voxel> VoxelStorage<T> for BrickMap<T> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        self.get(coords)
+    }
+
+    fn set_voxel(&mut self, coords: (i32, i32, i32), value: Option<T>) {
+        match value {
+            Some(value) => {
+                self.insert(coords, value);
+            }
+            None => {
+                self.remove(coords);
+            }
+        }
+    }
+
+    fn for_each_voxel<F: FnMut((i32, i32, i32), &T)>(&self, mut f: F) {
+        self.iter().for_each(|(coords, value)| f(coords, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn insert() {
+        let mut map = BrickMap::new(8);
+        assert_eq!(map.insert((5, 1, 6), 1), None);
+        assert_eq!(map.insert((5, 1, 6), 2), Some(1));
+        map.insert((0, 7, 0), 3);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get((5, 1, 6)), Some(&2));
+        assert!(map.contains_key((0, 7, 0)));
+        assert!(!map.contains_key((8, 0, 0)));
+        assert_eq!(map.occupancy((1, 0, 1)), 1 << ((1 * 4 + 1) * 4 + 2));
+
+        assert_eq!(map.remove((5, 1, 6)), Some(2));
+        assert_eq!(map.occupancy((1, 0, 1)), 0);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![((0, 7, 0), &3)]);
+    }
+
+    #[test]
+    pub fn from_lod_tree() {
+        let mut tree = LodTree::new(8);
+        for x in 0..8 {
+            for z in 0..8 {
+                tree.insert((x, 0, z), 1);
+            }
+        }
+        tree.insert((3, 5, 2), 2);
+        tree.merge();
+
+        let map = BrickMap::from(&tree);
+        assert_eq!(map.len(), 65);
+        let mut voxels = Vec::new();
+        map.for_each_voxel(|coords, &value| voxels.push((coords, value)));
+        voxels.sort_unstable();
+        let mut expected = Vec::new();
+        tree.for_each_voxel(|coords, &value| expected.push((coords, value)));
+        expected.sort_unstable();
+        assert_eq!(voxels, expected);
+
+        let mut map = map;
+        map.iter_mut()
+            .filter(|&((_, y, _), _)| y == 0)
+            .for_each(|(_, value)| *value = 3);
+        let tree = LodTree::from(&map);
+        assert_eq!(tree.get((2, 0, 7)).as_deref(), Some(&3));
+        assert_eq!(tree.get((3, 5, 2)).as_deref(), Some(&2));
+    }
+}
@@ -6,8 +6,20 @@ use std::{
 
 use int_traits::IntTraits;
 
+use crate::collections::VoxelStorage;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "savedata")]
+use crate::{
+    collections::RleTree,
+    serialize::{ContentHasher, SerDePartialEq},
+};
+
+/// The voxels that differ between two trees, see `LodTree::diff`.
 #[cfg(feature = "savedata")]
-use crate::{collections::RleTree, serialize::SerDePartialEq};
+type Diff<T> = Vec<((i32, i32, i32), Option<T>)>;
 
 fn depth_index(mut x: i32, mut y: i32, mut z: i32, depth: usize) -> usize {
     let mut idx = 0;
@@ -89,31 +101,75 @@ impl Voxel for i32 {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Node<T> {
     Ref(usize),
     Value(Option<T>, usize),
 }
 
+/// An octree of voxels stored as a flat array of `width`³ nodes.
+///
+/// The array is only allocated when the first voxel is inserted, so trees that stay empty,
+/// e.g. of chunks of sky or their light, take no more memory than the struct itself.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LodTree<T> {
     lod: usize,
     depth: usize,
     len: usize,
+    /// Empty until the first insert.
+    array: Vec<Node<T>>,
+}
+
+/// The progress of a defragmentation spread over several calls to `LodTree::defragment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Defrag<T> {
     array: Vec<Node<T>>,
+    /// `0` while copying the voxels, then the level being merged.
+    level: usize,
+    next: usize,
+    done: bool,
+}
+
+impl<T> Defrag<T> {
+    /// Whether the defragmented tree replaced the old one.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+impl<T> Default for Defrag<T> {
+    fn default() -> Self {
+        Self {
+            array: Vec::new(),
+            level: 0,
+            next: 0,
+            done: false,
+        }
+    }
 }
 
 impl<T: Voxel> LodTree<T> {
     pub fn new(width: usize) -> Self {
-        let mut array = Vec::with_capacity(width.pow(3));
-        for _ in 0..width.pow(3) {
-            array.push(Node::Value(None, 1));
-        }
         Self {
             lod: 0,
             depth: width.log2(),
             len: 0,
-            array,
+            array: Vec::new(),
+        }
+    }
+
+    /// Whether the nodes of the tree were allocated, which happens on the first insert.
+    pub fn is_allocated(&self) -> bool {
+        !self.array.is_empty()
+    }
+
+    fn allocate(&mut self) {
+        if self.array.is_empty() {
+            let capacity = self.capacity();
+            self.array.reserve_exact(capacity);
+            self.array.resize_with(capacity, || Node::Value(None, 1));
         }
     }
 
@@ -148,6 +204,7 @@ impl<T: Voxel> LodTree<T> {
     }
 
     pub fn merge(&mut self) {
+        let _span = span!("LodTree::merge");
         for d in 1..=self.depth {
             let skip = 8_usize.pow(d as u32 - 1);
 
@@ -219,6 +276,85 @@ impl<T: Voxel> LodTree<T> {
         }
     }
 
+    /// Rebuilds the tree without the broken merges and `Ref` chains that edits leave behind,
+    /// so that it merges as well as a freshly generated one. Visits about `budget` nodes per
+    /// call and returns how many it visited, the rebuilt tree replaces this one in the call
+    /// that finishes `defrag`.
+    ///
+    /// The tree is only read until then, so it stays usable between calls, but it must not
+    /// be edited: start over with a new `Defrag` after edits, or they are lost.
+    pub fn defragment(&mut self, defrag: &mut Defrag<T>, budget: usize) -> usize {
+        let _span = span!("LodTree::defragment");
+        if defrag.done {
+            return 0;
+        }
+        let len = self.array.len();
+        let mut visited = 0;
+        if defrag.level == 0 {
+            if defrag.array.is_empty() {
+                defrag.array.reserve_exact(len);
+            }
+            while visited < budget && defrag.next < len {
+                let mut node = &self.array[defrag.next];
+                let value = loop {
+                    match node {
+                        Node::Ref(idx) => node = &self.array[*idx],
+                        Node::Value(value, _) => break value.clone(),
+                    }
+                };
+                defrag.array.push(Node::Value(value, 1));
+                defrag.next += 1;
+                visited += 1;
+            }
+            if defrag.next < len {
+                return visited;
+            }
+            defrag.level = 1;
+            defrag.next = 0;
+        }
+        // the same merges as `merge`, but on a flat copy, one group of 8 at a time
+        while defrag.level <= self.depth {
+            let skip = 8_usize.pow(defrag.level as u32 - 1);
+            let width = 1 << (defrag.level - 1);
+            while visited < budget && defrag.next < len {
+                let pivot_idx = defrag.next;
+                defrag.next += skip * 8;
+                visited += 8;
+
+                let array = &mut defrag.array;
+                let mergeable = match &array[pivot_idx] {
+                    Node::Value(pivot, pivot_width) if *pivot_width == width => {
+                        pivot.as_ref().map(|v| v.can_merge()).unwrap_or(true)
+                            && (1..8).all(|k| match &array[pivot_idx + k * skip] {
+                                Node::Value(elem, elem_width) => {
+                                    *elem_width == width && elem == pivot
+                                }
+                                Node::Ref(_) => false,
+                            })
+                    }
+                    _ => false,
+                };
+                if mergeable {
+                    for k in 1..8 {
+                        array[pivot_idx + k * skip] = Node::Ref(pivot_idx);
+                    }
+                    if let Node::Value(_, width) = &mut array[pivot_idx] {
+                        *width *= 2;
+                    }
+                }
+            }
+            if defrag.next < len {
+                return visited;
+            }
+            defrag.level += 1;
+            defrag.next = 0;
+        }
+        mem::swap(&mut self.array, &mut defrag.array);
+        defrag.array = Vec::new();
+        defrag.done = true;
+        visited
+    }
+
     pub fn insert(&mut self, (x, y, z): (i32, i32, i32), value: T) -> Option<Cow<'_, T>> {
         if x >= self.width() as i32
             || x < 0
@@ -229,6 +365,7 @@ impl<T: Voxel> LodTree<T> {
         {
             return None;
         }
+        self.allocate();
         let idx = depth_index(x, y, z, self.depth);
         let mut result = Node::Value(Some(value), 1);
         mem::swap(&mut self.array[idx], &mut result);
@@ -266,6 +403,7 @@ impl<T: Voxel> LodTree<T> {
             || y < 0
             || z >= self.width() as i32
             || z < 0
+            || !self.is_allocated()
         {
             return None;
         }
@@ -306,6 +444,7 @@ impl<T: Voxel> LodTree<T> {
             || y < 0
             || z >= self.width() as i32
             || z < 0
+            || !self.is_allocated()
         {
             return None;
         }
@@ -333,44 +472,55 @@ impl<T: Voxel> LodTree<T> {
         }
     }
 
-    pub fn get(&self, (x, y, z): (i32, i32, i32)) -> Option<Cow<'_, T>> {
+    /// Returns the voxel at `coords` at full detail, whatever the lod of the tree is, like
+    /// `get_mut` and `contains_key`. Use `sampled_get` for the voxel as it's rendered.
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        self.voxel(coords).map(Cow::Borrowed)
+    }
+
+    /// Returns the voxel at `coords` as it looks at the lod of the tree, the average of the
+    /// `2^lod`³ voxels around it.
+    pub fn sampled_get(&self, (x, y, z): (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        if self.lod == 0 {
+            return self.get((x, y, z));
+        }
         if x >= self.width() as i32
             || x < 0
             || y >= self.width() as i32
             || y < 0
             || z >= self.width() as i32
             || z < 0
+            || !self.is_allocated()
         {
             return None;
         }
 
-        if self.lod == 0 {
-            self.get_impl((x, y, z)).map(Cow::Borrowed)
-        } else {
-            let width = 1 << self.lod;
-            let mask = width - 1;
-            let x = x & !mask;
-            let y = y & !mask;
-            let z = z & !mask;
-            let start = depth_index(x, y, z, self.depth);
-            let end = start + width.pow(3) as usize;
-            // TODO: optimize this
-            let array = self.array[start..end]
-                .iter()
-                .flat_map(|mut value| loop {
-                    match value {
-                        Node::Ref(idx) => {
-                            value = &self.array[*idx];
-                        }
-                        Node::Value(value, _) => return value.clone(),
+        let width = 1 << self.lod;
+        let mask = width - 1;
+        let x = x & !mask;
+        let y = y & !mask;
+        let z = z & !mask;
+        let start = depth_index(x, y, z, self.depth);
+        let end = start + width.pow(3) as usize;
+        // TODO: optimize this
+        let array = self.array[start..end]
+            .iter()
+            .flat_map(|mut value| loop {
+                match value {
+                    Node::Ref(idx) => {
+                        value = &self.array[*idx];
                     }
-                })
-                .collect::<Vec<_>>();
-            T::average(&array).map(Cow::Owned)
-        }
+                    Node::Value(value, _) => return value.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        T::average(&array).map(Cow::Owned)
     }
 
     fn get_impl(&self, (x, y, z): (i32, i32, i32)) -> Option<&T> {
+        if !self.is_allocated() {
+            return None;
+        }
         let idx = depth_index(x, y, z, self.depth);
         let mut result_ref = &self.array[idx];
 
@@ -384,10 +534,63 @@ impl<T: Voxel> LodTree<T> {
         }
     }
 
+    /// Whether there is a voxel at `coords` at full detail.
     pub fn contains_key(&self, coords: (i32, i32, i32)) -> bool {
-        self.get_impl(coords).is_some()
+        self.voxel(coords).is_some()
+    }
+
+    /// Returns the coordinates and values of all voxels that differ from `baseline`,
+    /// ignoring the lod of both trees, or `None` if the trees have different widths.
+    #[cfg(feature = "savedata")]
+    pub fn diff(&self, baseline: &Self) -> Option<Diff<T>> {
+        if self.depth != baseline.depth {
+            return None;
+        }
+        let diff = (0..self.capacity())
+            .filter_map(|idx| {
+                let coords = array_index(idx, self.depth);
+                let value = self.get_impl(coords);
+                let same = match (value, baseline.get_impl(coords)) {
+                    (Some(a), Some(b)) => a.serde_eq(b),
+                    (None, None) => true,
+                    _ => false,
+                };
+                if same {
+                    None
+                } else {
+                    Some((coords, value.cloned()))
+                }
+            })
+            .collect();
+        Some(diff)
     }
 
+    /// Hashes the width and voxels of the tree, visited in index order.
+    ///
+    /// Voxels are hashed through their bincode encoding, so the hash doesn't depend on the
+    /// lod, on how nodes are merged or on the platform.
+    #[cfg(feature = "savedata")]
+    pub fn content_hash(&self) -> u64
+    where
+        T: Serialize,
+    {
+        let mut hasher = ContentHasher::new();
+        hasher.update(&(self.width() as u64).to_le_bytes());
+        for idx in 0..self.capacity() {
+            match self.get_impl(array_index(idx, self.depth)) {
+                Some(value) => {
+                    hasher.update(&[1]);
+                    bincode::serialize_into(&mut hasher, value)
+                        .expect("failed to serialize voxel");
+                }
+                None => hasher.update(&[0]),
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Iterates over the merged nodes of the tree, empty ones included. Trees that aren't
+    /// allocated have no nodes.
     pub fn opt_elements(&self) -> impl Iterator<Item = OptElement<'_, T>> {
         let depth = self.depth;
         let mut set = HashSet::new();
@@ -544,6 +747,45 @@ impl<T: PartialEq> LodTree<T> {
     }
 }
 
+impl<T: Voxel> VoxelStorage<T> for LodTree<T> {
+    fn width(&self) -> usize {
+        self.width()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        let width = self.width() as i32;
+        let (x, y, z) = coords;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= width || z >= width {
+            return None;
+        }
+        self.get_impl(coords)
+    }
+
+    fn set_voxel(&mut self, coords: (i32, i32, i32), value: Option<T>) {
+        match value {
+            Some(value) => {
+                self.insert(coords, value);
+            }
+            None => {
+                self.remove(coords);
+            }
+        }
+    }
+
+    fn for_each_voxel<F: FnMut((i32, i32, i32), &T)>(&self, mut f: F) {
+        for idx in 0..self.capacity() {
+            let coords = array_index(idx, self.depth);
+            if let Some(value) = self.get_impl(coords) {
+                f(coords, value);
+            }
+        }
+    }
+}
+
 #[cfg(feature = "savedata")]
 impl<T: Voxel> From<RleTree<T>> for LodTree<T> {
     fn from(tree: RleTree<T>) -> Self {
@@ -558,9 +800,14 @@ impl<T: Voxel> From<RleTree<T>> for LodTree<T> {
                 array.push(Node::Ref(idx));
             }
         }
+        let depth = array.len().cbrt().log2();
+        if len == 0 {
+            // empty trees stay unallocated
+            return Self::new(1 << depth);
+        }
         Self {
             lod: 0,
-            depth: array.len().cbrt().log2(),
+            depth,
             len,
             array,
         }
@@ -663,6 +910,31 @@ mod tests {
             }));
     }
 
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn diff() {
+        let mut base = LodTree::<i32>::new(4);
+        base.insert((0, 0, 0), 0);
+        base.insert((1, 0, 0), 1);
+        let mut vt = base.clone();
+        vt.insert((1, 0, 0), 2);
+        vt.insert((3, 3, 3), 3);
+        vt.remove((0, 0, 0));
+
+        let mut diff = vt.diff(&base).unwrap();
+        diff.sort_by_key(|(coords, _)| *coords);
+        assert_eq!(
+            diff,
+            vec![
+                ((0, 0, 0), None),
+                ((1, 0, 0), Some(2)),
+                ((3, 3, 3), Some(3)),
+            ]
+        );
+        assert!(vt.diff(&vt).unwrap().is_empty());
+        assert!(vt.diff(&LodTree::new(8)).is_none());
+    }
+
     #[test]
     pub fn merge() {
         let mut vt = LodTree::<i32>::new(4);
@@ -707,4 +979,121 @@ mod tests {
         assert_eq!(a, g);
         assert_eq!(a, h);
     }
+
+    #[test]
+    pub fn defragment() {
+        let mut vt = LodTree::<i32>::new(4);
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    vt.insert((x, y, z), 0);
+                }
+            }
+        }
+        vt.merge();
+        assert_eq!(vt.elements().count(), 1);
+
+        // an edit undone again leaves the merges broken
+        vt.insert((1, 2, 3), 1);
+        vt.insert((1, 2, 3), 0);
+        assert!(vt.elements().count() > 1);
+
+        let mut defrag = Defrag::default();
+        let mut calls = 0;
+        while !defrag.is_done() {
+            assert!(vt.defragment(&mut defrag, 16) <= 16);
+            for x in 0..4 {
+                for y in 0..4 {
+                    for z in 0..4 {
+                        assert_eq!(vt.get((x, y, z)).unwrap().into_owned(), 0);
+                    }
+                }
+            }
+            calls += 1;
+        }
+        assert!(calls > 4);
+        assert_eq!(vt.elements().count(), 1);
+        assert_eq!(vt.defragment(&mut defrag, 16), 0);
+    }
+
+    #[test]
+    pub fn lod() {
+        let mut vt = LodTree::<i32>::new(4);
+        vt.insert((0, 0, 0), 2);
+        vt.insert((1, 1, 1), 4);
+        vt.set_lod(1);
+
+        // structural queries ignore the lod
+        assert_eq!(vt.get((0, 0, 0)).unwrap().into_owned(), 2);
+        assert_eq!(vt.get((1, 0, 0)), None);
+        assert!(!vt.contains_key((1, 0, 0)));
+        assert_eq!(vt.get_mut((1, 1, 1)), Some(&mut 4));
+        assert_eq!(vt.sampled_get((1, 0, 0)).unwrap().into_owned(), 3);
+        assert_eq!(vt.sampled_get((2, 2, 2)), None);
+
+        assert!(!vt.contains_key((4, 0, 0)));
+        assert!(!vt.contains_key((-1, 0, 0)));
+        assert_eq!(vt.sampled_get((4, 0, 0)), None);
+    }
+
+    #[test]
+    pub fn lazy() {
+        let mut vt = LodTree::<i32>::new(8);
+        assert!(!vt.is_allocated());
+        assert_eq!(vt.get((1, 2, 3)), None);
+        assert_eq!(vt.remove((1, 2, 3)), None);
+        assert_eq!(vt.elements().count(), 0);
+        vt.merge();
+        assert!(!vt.is_allocated());
+
+        vt.insert((1, 2, 3), 1);
+        assert!(vt.is_allocated());
+        assert_eq!(vt.get((1, 2, 3)).unwrap().into_owned(), 1);
+
+        #[cfg(feature = "savedata")]
+        {
+            let empty = LodTree::<i32>::from(RleTree::with_tree(&LodTree::new(8)));
+            assert!(!empty.is_allocated());
+            assert_eq!(empty.width(), 8);
+            let tree = LodTree::from(RleTree::with_tree(&vt));
+            assert_eq!(tree.get((1, 2, 3)).unwrap().into_owned(), 1);
+        }
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn serde() {
+        let mut vt = LodTree::<i32>::new(4);
+        vt.insert((0, 0, 0), 0);
+        vt.insert((1, 2, 3), 1);
+        vt.set_lod(1);
+
+        let bytes = bincode::serialize(&vt).unwrap();
+        let de = bincode::deserialize::<LodTree<i32>>(&bytes).unwrap();
+        assert_eq!(vt, de);
+        assert_eq!(de.lod(), 1);
+        assert_eq!(de.get_impl((1, 2, 3)), Some(&1));
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn content_hash() {
+        let mut a = LodTree::<i32>::new(4);
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    a.insert((x, y, z), 1);
+                }
+            }
+        }
+        let mut b = a.clone();
+        b.merge();
+        b.set_lod(1);
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_eq!(a.content_hash(), 11_966_826_544_332_306_721);
+
+        b.insert((3, 3, 3), 1);
+        assert_ne!(a.content_hash(), b.content_hash());
+        assert_ne!(LodTree::<i32>::new(4).content_hash(), LodTree::<i32>::new(8).content_hash());
+    }
 }
@@ -1,9 +1,18 @@
 #[cfg(feature = "savedata")]
 pub use self::rle_tree::RleTree;
 
-pub use self::{lod_tree::LodTree, volumetric_tree::VolumetricTree};
+pub use self::{
+    brick_map::BrickMap,
+    lod_tree::{LodTree, Voxel},
+    sparse_octree::SparseOctree,
+    storage::VoxelStorage,
+    volumetric_tree::VolumetricTree,
+};
 
+pub mod brick_map;
 pub mod lod_tree;
 #[cfg(feature = "savedata")]
 pub mod rle_tree;
+pub mod sparse_octree;
+mod storage;
 pub mod volumetric_tree;
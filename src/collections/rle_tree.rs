@@ -19,6 +19,12 @@ pub struct RleTree<T> {
 impl<T: Voxel> RleTree<T> {
     pub fn with_tree(tree: &LodTree<T>) -> Self {
         let mut array = Vec::<Node<T>>::new();
+        if !tree.is_allocated() {
+            array.push(Node {
+                value: None,
+                len: tree.capacity(),
+            });
+        }
         for elem in tree.opt_elements() {
             array.push(Node {
                 value: elem.value.clone(),
@@ -29,6 +35,39 @@ impl<T: Voxel> RleTree<T> {
     }
 }
 
+impl<T> RleTree<T> {
+    /// Converts every value with `f`, keeping the runs.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> RleTree<U> {
+        let array = self
+            .array
+            .into_iter()
+            .map(|node| Node {
+                value: node.value.map(&mut f),
+                len: node.len,
+            })
+            .collect();
+        RleTree { array }
+    }
+
+    /// Like `map`, but stops at the first value `f` fails on.
+    pub fn try_map<U, E, F>(self, mut f: F) -> Result<RleTree<U>, E>
+    where
+        F: FnMut(T) -> Result<U, E>,
+    {
+        let array = self
+            .array
+            .into_iter()
+            .map(|node| {
+                Ok(Node {
+                    value: node.value.map(&mut f).transpose()?,
+                    len: node.len,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RleTree { array })
+    }
+}
+
 impl<T: Voxel> IntoIterator for RleTree<T> {
     type IntoIter = std::vec::IntoIter<Self::Item>;
     type Item = Node<T>;
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::collections::{lod_tree::Voxel, LodTree};
+
+/// A node of a `SparseOctree`, with eight children half as wide as the node.
+///
+/// Child `i` is the octant offset by `(i & 1, i >> 1 & 1, i >> 2 & 1)` times half the width of
+/// the node from its corner, the same order `LodTree` lays out its voxels in.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OctreeNode {
+    /// Bit `i` is set if child `i` isn't empty.
+    pub child_mask: u8,
+    /// Bit `i` is set if child `i` is filled with a single value. Always a subset of
+    /// `child_mask`.
+    pub leaf_mask: u8,
+    /// The index in `SparseOctree::nodes` of the first child that isn't a leaf, the others
+    /// follow it in order. `0` if all children are leaves or empty.
+    pub children: u32,
+    /// The index in `SparseOctree::values` of the value of the first leaf child, the others
+    /// follow it in order. `0` if no child is a leaf.
+    pub values: u32,
+}
+
+impl OctreeNode {
+    /// Returns the index of child `child` in `SparseOctree::nodes` if it's neither empty nor
+    /// a leaf.
+    pub fn child(&self, child: usize) -> Option<usize> {
+        let nodes = self.child_mask & !self.leaf_mask;
+        if nodes & 1 << child == 0 {
+            return None;
+        }
+        let before = (nodes as u32 & ((1 << child) - 1)).count_ones();
+        Some((self.children + before) as usize)
+    }
+
+    /// Returns the index of the value of child `child` in `SparseOctree::values` if it's a
+    /// leaf.
+    pub fn value(&self, child: usize) -> Option<usize> {
+        if self.leaf_mask & 1 << child == 0 {
+            return None;
+        }
+        let before = (self.leaf_mask as u32 & ((1 << child) - 1)).count_ones();
+        Some((self.values + before) as usize)
+    }
+
+    /// Packs the node into three words for a GPU buffer: the child mask in the lowest byte of
+    /// the first and the leaf mask in the byte above it, then `children` and `values`.
+    pub fn to_words(&self) -> [u32; 3] {
+        [
+            self.child_mask as u32 | (self.leaf_mask as u32) << 8,
+            self.children,
+            self.values,
+        ]
+    }
+}
+
+/// The voxels of a `LodTree` as a flattened sparse octree, to be uploaded to the GPU for
+/// raymarching or culling in a compute shader. `get` is a reference for the traversal.
+///
+/// The layout is stable, and guarantees that:
+///
+/// - `nodes()[0]` is the root, as wide as the tree, even if the tree is empty or filled with
+///   a single value,
+/// - the nodes are stored breadth first, so every node comes after its parent and the
+///   children of a node that aren't leaves are next to each other,
+/// - nodes two voxels wide only have leaves as children, so traversal stops at single voxels,
+/// - no node but the root has eight leaves of the same value that could be merged.
+///
+/// Trees are exported at full detail, whatever their lod is.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SparseOctree<T> {
+    width: usize,
+    nodes: Vec<OctreeNode>,
+    values: Vec<T>,
+}
+
+impl<T: Voxel> SparseOctree<T> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn nodes(&self) -> &[OctreeNode] {
+        &self.nodes
+    }
+
+    /// The values of the leaves, indexed by `OctreeNode::values`.
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    /// Returns the voxel at `coords` by walking down from the root.
+    pub fn get(&self, (x, y, z): (i32, i32, i32)) -> Option<&T> {
+        let width = self.width as i32;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= width || z >= width {
+            return None;
+        }
+        let mut node = &self.nodes[0];
+        let mut half = width / 2;
+        loop {
+            let child = (x & half != 0) as usize
+                | ((y & half != 0) as usize) << 1
+                | ((z & half != 0) as usize) << 2;
+            if let Some(value) = node.value(child) {
+                return Some(&self.values[value]);
+            }
+            node = &self.nodes[node.child(child)?];
+            half /= 2;
+        }
+    }
+
+    /// The nodes packed with `OctreeNode::to_words`, one after the other.
+    pub fn to_words(&self) -> Vec<u32> {
+        self.nodes
+            .iter()
+            .flat_map(|node| node.to_words().to_vec())
+            .collect()
+    }
+}
+
+/// A node while the octree is built, before it's flattened.
+enum Build<T> {
+    Empty,
+    Leaf(T),
+    Branch(Vec<Build<T>>),
+}
+
+impl<T: Voxel> Build<T> {
+    /// Fills the cube of `width` at `coords` inside this node of `node_width` with `value`.
+    fn fill(&mut self, node_width: i32, (x, y, z): (i32, i32, i32), width: i32, value: Option<&T>) {
+        if width >= node_width {
+            *self = match value {
+                Some(value) => Build::Leaf(value.clone()),
+                None => Build::Empty,
+            };
+            return;
+        }
+        if let Build::Empty = self {
+            *self = Build::Branch((0..8).map(|_| Build::Empty).collect());
+        }
+        let half = node_width / 2;
+        let child = (x & half != 0) as usize
+            | ((y & half != 0) as usize) << 1
+            | ((z & half != 0) as usize) << 2;
+        match self {
+            Build::Branch(children) => children[child].fill(half, (x, y, z), width, value),
+            // merged nodes of a `LodTree` never overlap
+            _ => unreachable!(),
+        }
+    }
+
+    /// Replaces branches of eight empty children or eight leaves of the same value with a
+    /// single node, bottom up.
+    fn collapse(&mut self) {
+        let children = match self {
+            Build::Branch(children) => children,
+            _ => return,
+        };
+        for child in children.iter_mut() {
+            child.collapse();
+        }
+        let collapsed = match &children[0] {
+            Build::Empty if children.iter().all(|child| matches!(child, Build::Empty)) => {
+                Some(Build::Empty)
+            }
+            Build::Leaf(first) if first.can_merge() => {
+                let same = children.iter().all(|child| match child {
+                    Build::Leaf(value) => value == first,
+                    _ => false,
+                });
+                if same {
+                    Some(Build::Leaf(first.clone()))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if let Some(collapsed) = collapsed {
+            *self = collapsed;
+        }
+    }
+}
+
+impl<'a, T: Voxel> From<&'a LodTree<T>> for SparseOctree<T> {
+    /// Copies the voxels of `tree` at full detail. Panics if the tree is less than two voxels
+    /// wide.
+    fn from(tree: &'a LodTree<T>) -> Self {
+        let width = tree.width();
+        assert!(width >= 2, "octrees have to be at least 2 wide");
+        let mut root = Build::Empty;
+        for elem in tree.opt_elements() {
+            let coords = (elem.x, elem.y, elem.z);
+            root.fill(width as i32, coords, elem.width as i32, elem.value.as_ref());
+        }
+        root.collapse();
+        // the root stays a node, whatever it collapsed to
+        let root = match root {
+            Build::Empty => Vec::new(),
+            Build::Leaf(value) => (0..8).map(|_| Build::Leaf(value.clone())).collect(),
+            Build::Branch(children) => children,
+        };
+
+        let mut nodes = vec![OctreeNode::default()];
+        let mut values = Vec::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((0, &root));
+        while let Some((idx, children)) = queue.pop_front() {
+            let mut node = OctreeNode {
+                children: nodes.len() as u32,
+                values: values.len() as u32,
+                ..Default::default()
+            };
+            for (i, child) in children.iter().enumerate() {
+                match child {
+                    Build::Empty => {}
+                    Build::Leaf(value) => {
+                        node.child_mask |= 1 << i;
+                        node.leaf_mask |= 1 << i;
+                        values.push(value.clone());
+                    }
+                    Build::Branch(children) => {
+                        node.child_mask |= 1 << i;
+                        queue.push_back((nodes.len(), children));
+                        nodes.push(OctreeNode::default());
+                    }
+                }
+            }
+            // nodes without children of a kind point at the start instead of past the end
+            if node.child_mask == node.leaf_mask {
+                node.children = 0;
+            }
+            if node.leaf_mask == 0 {
+                node.values = 0;
+            }
+            nodes[idx] = node;
+        }
+
+        Self {
+            width,
+            nodes,
+            values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the guarantees of `SparseOctree` and returns the width of every node.
+    fn check_layout<T: Voxel>(octree: &SparseOctree<T>) -> Vec<usize> {
+        let nodes = octree.nodes();
+        let mut widths = vec![0; nodes.len()];
+        widths[0] = octree.width();
+        let mut next = 1;
+        for (i, node) in nodes.iter().enumerate() {
+            assert_eq!(node.leaf_mask & !node.child_mask, 0);
+            let branches = node.child_mask & !node.leaf_mask;
+            if branches != 0 {
+                // breadth first, with the children of every node next to each other
+                assert_eq!(node.children as usize, next);
+                next += branches.count_ones() as usize;
+                assert!(widths[i] > 2);
+            }
+            for child in (0..8).filter_map(|child| node.child(child)) {
+                assert!(child > i);
+                assert_ne!(nodes[child].child_mask, 0);
+                widths[child] = widths[i] / 2;
+                let leaves = (0..8)
+                    .filter_map(|c| nodes[child].value(c))
+                    .map(|v| &octree.values()[v])
+                    .collect::<Vec<_>>();
+                let mergeable = leaves.len() == 8 && leaves.iter().all(|&v| v == leaves[0]);
+                assert!(!mergeable || !leaves[0].can_merge());
+            }
+        }
+        assert_eq!(next, nodes.len());
+        let leaves = nodes
+            .iter()
+            .map(|node| node.leaf_mask.count_ones() as usize);
+        assert_eq!(leaves.sum::<usize>(), octree.values().len());
+        widths
+    }
+
+    #[test]
+    pub fn from_lod_tree() {
+        let mut tree = LodTree::new(8);
+        for x in 0..8 {
+            for z in 0..8 {
+                tree.insert((x, 0, z), 1);
+                tree.insert((x, 1, z), 1);
+            }
+        }
+        tree.insert((3, 5, 2), 2);
+        tree.insert((6, 7, 7), 3);
+        tree.merge();
+
+        let octree = SparseOctree::from(&tree);
+        assert_eq!(octree.width(), 8);
+        let widths = check_layout(&octree);
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    let voxel = tree.get((x, y, z));
+                    assert_eq!(octree.get((x, y, z)), voxel.as_deref());
+                }
+            }
+        }
+        assert_eq!(octree.get((8, 0, 0)), None);
+        // the floor is four 2³ leaves per 4³ octant, the two single voxels 1³ leaves
+        assert_eq!(octree.values().len(), 4 * 4 + 2);
+        assert_eq!(widths, vec![8, 4, 4, 4, 4, 4, 4, 2, 2]);
+
+        let words = octree.to_words();
+        assert_eq!(words.len(), 3 * octree.nodes().len());
+        let root = octree.nodes()[0];
+        assert_eq!(
+            words[0],
+            root.child_mask as u32 | (root.leaf_mask as u32) << 8
+        );
+        assert_eq!(words[1], 1);
+    }
+
+    #[test]
+    pub fn uniform() {
+        let empty = SparseOctree::from(&LodTree::<i32>::new(4));
+        assert_eq!(empty.nodes(), &[OctreeNode::default()]);
+        assert_eq!(empty.get((1, 2, 3)), None);
+
+        let mut tree = LodTree::new(4);
+        for i in 0..64 {
+            tree.insert((i / 16, i / 4 % 4, i % 4), 7);
+        }
+        let octree = SparseOctree::from(&tree);
+        check_layout(&octree);
+        assert_eq!(octree.nodes().len(), 1);
+        assert_eq!(octree.nodes()[0].leaf_mask, 0xff);
+        assert_eq!(octree.values(), &[7; 8]);
+        assert_eq!(octree.get((3, 3, 3)), Some(&7));
+    }
+}
@@ -0,0 +1,23 @@
+/// The voxels of a chunk at full detail, independent of how they are laid out.
+///
+/// `LodTree` merges equal voxels and supports levels of detail, `BrickMap` is faster to
+/// iterate and query neighbours in. Code that only needs the voxels can take either, and
+/// chunks can keep their voxels in either, see `Chunk::set_storage`.
+pub trait VoxelStorage<T> {
+    fn width(&self) -> usize;
+
+    /// The number of voxels that aren't empty.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T>;
+
+    /// Replaces the voxel at `coords`, `None` removes it.
+    fn set_voxel(&mut self, coords: (i32, i32, i32), value: Option<T>);
+
+    /// Calls `f` with the coordinates of every voxel that isn't empty.
+    fn for_each_voxel<F: FnMut((i32, i32, i32), &T)>(&self, f: F);
+}
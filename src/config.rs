@@ -0,0 +1,373 @@
+//! Lighting, streaming and save settings loaded from a file at startup, so that they can be
+//! tuned without recompiling the game.
+//!
+//! Files are RON, or TOML with the `toml` feature if their extension is `.toml`. Every field
+//! is optional and falls back to its default, e.g. this RON file only changes the lighting:
+//!
+//! ```ron
+//! (lighting: Auto, lod: (distance: 96, max_shaded_lod: 2, hysteresis: 16, dither: 0.0))
+//! ```
+//!
+//! The lighting and lod settings and `ConfigPlugin` need the `bevy` feature.
+
+use std::{error, fmt, fs, io, path::Path};
+#[cfg(feature = "bevy")]
+use std::{path::PathBuf, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+use crate::world::{Compression, SaveOptions, TickPolicy, UnloadPolicy, UpdateLimits};
+#[cfg(feature = "bevy")]
+use crate::{
+    render::{light::LightingMode, lod::LodConfig},
+    world::{ChunkUnloader, MapUpdates},
+};
+
+/// How often and how a game saves its maps. The game does the saving, this only holds the
+/// settings.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavePolicy {
+    /// Seconds between autosaves, `None` to only save on exit.
+    pub autosave: Option<f32>,
+    pub backups: usize,
+    pub compression: Compression,
+}
+
+impl SavePolicy {
+    pub fn options(&self) -> SaveOptions {
+        SaveOptions {
+            backups: self.backups,
+            compression: self.compression,
+            thinning: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoxelConfig {
+    #[cfg(feature = "bevy")]
+    pub lighting: LightingMode,
+    #[cfg(feature = "bevy")]
+    pub lod: LodConfig,
+    /// Copied to the `ChunkUnloader` of every map.
+    pub unload: UnloadPolicy,
+    /// The caps on pending chunk updates, copied to the `MapUpdates` of every map.
+    pub limits: UpdateLimits,
+    pub ticks: TickPolicy,
+    pub save: SavePolicy,
+}
+
+impl VoxelConfig {
+    pub fn from_ron(ron: &str) -> Result<Self, ConfigError> {
+        let config: Self = ron::de::from_str(ron).map_err(ConfigError::Ron)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(toml).map_err(ConfigError::Toml)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads and validates the config file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        #[cfg(feature = "toml")]
+        {
+            if path.extension().map_or(false, |ext| ext == "toml") {
+                return Self::from_toml(&text);
+            }
+        }
+        Self::from_ron(&text)
+    }
+
+    /// Checks the settings that parse but can't work.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        #[cfg(feature = "bevy")]
+        {
+            if self.lod.distance <= 0 {
+                return Err(ConfigError::LodDistance(self.lod.distance));
+            }
+            if self.lod.detail_bias < 0.0 || self.lod.detail_bias.is_nan() {
+                return Err(ConfigError::DetailBias(self.lod.detail_bias));
+            }
+        }
+        if self.unload.distance < 0 {
+            return Err(ConfigError::UnloadDistance(self.unload.distance));
+        }
+        Ok(())
+    }
+}
+
+/// A reason `VoxelConfig::load` rejected a config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Ron(ron::Error),
+    #[cfg(feature = "toml")]
+    Toml(toml::de::Error),
+    /// A `LodConfig::distance` that isn't positive.
+    LodDistance(i32),
+    /// A `LodConfig::detail_bias` that is negative or NaN.
+    DetailBias(f32),
+    /// A negative `UnloadPolicy::distance`, which would unload chunks as soon as they're
+    /// streamed in.
+    UnloadDistance(i32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Ron(e) => e.fmt(f),
+            #[cfg(feature = "toml")]
+            Self::Toml(e) => e.fmt(f),
+            Self::LodDistance(distance) => {
+                write!(f, "a lod distance of {} isn't positive", distance)
+            }
+            Self::DetailBias(bias) => write!(f, "a detail bias of {} isn't zero or positive", bias),
+            Self::UnloadDistance(distance) => {
+                write!(f, "an unload distance of {} is negative", distance)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Ron(e) => Some(e),
+            #[cfg(feature = "toml")]
+            Self::Toml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Where `ConfigPlugin` loads the config from, and when it last did.
+#[cfg(feature = "bevy")]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    /// Seconds between checks for changes to the file, `None` to only load it at startup.
+    pub hot_reload: Option<f32>,
+    modified: Option<SystemTime>,
+    elapsed: f32,
+    /// Whether the resources haven't been updated from `VoxelConfig` yet.
+    pending: bool,
+}
+
+#[cfg(feature = "bevy")]
+impl ConfigSource {
+    /// Loads the config at `path`, or returns the default config if there is no file. A file
+    /// that can't be read or parsed is logged and ignored too, so a typo doesn't keep the game
+    /// from starting.
+    pub fn load(path: PathBuf, hot_reload: Option<f32>) -> (Self, VoxelConfig) {
+        let mut source = Self {
+            path,
+            hot_reload,
+            modified: None,
+            elapsed: 0.0,
+            pending: false,
+        };
+        let config = source.reload().unwrap_or_default();
+        (source, config)
+    }
+
+    /// Loads the file again if it changed since it was last loaded.
+    fn reload(&mut self) -> Option<VoxelConfig> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        match VoxelConfig::load(&self.path) {
+            Ok(config) => {
+                self.pending = true;
+                Some(config)
+            }
+            Err(e) => {
+                log::error!("couldn't load config {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Reloads the config when its file changes, and copies a newly loaded config to the
+/// `LightingMode`, `LodConfig` and `TickPolicy` resources and to every map's `MapUpdates` and
+/// `ChunkUnloader`.
+///
+/// Only a loaded file overrides those settings, without one they keep the values the app
+/// gave them.
+#[cfg(feature = "bevy")]
+#[allow(clippy::too_many_arguments)]
+pub fn config_update(
+    time: Res<Time>,
+    mut source: ResMut<ConfigSource>,
+    mut config: ResMut<VoxelConfig>,
+    mut lighting: ResMut<LightingMode>,
+    mut lod: ResMut<LodConfig>,
+    mut ticks: ResMut<TickPolicy>,
+    mut maps: Query<&mut MapUpdates>,
+    mut unloaders: Query<&mut ChunkUnloader>,
+) {
+    if let Some(interval) = source.hot_reload {
+        source.elapsed += time.delta_seconds;
+        if source.elapsed >= interval {
+            source.elapsed = 0.0;
+            if let Some(reloaded) = source.reload() {
+                log::info!("reloaded config {}", source.path.display());
+                *config = reloaded;
+            }
+        }
+    }
+    if !source.pending {
+        return;
+    }
+    source.pending = false;
+    *lighting = config.lighting;
+    *lod = config.lod;
+    *ticks = config.ticks;
+    for mut updates in &mut maps.iter() {
+        updates.limits = config.limits;
+    }
+    for mut unloader in &mut unloaders.iter() {
+        unloader.policy = config.unload;
+    }
+}
+
+/// Loads a `VoxelConfig` at startup and adds it as a resource, along with `config_update`.
+///
+/// The settings of a loaded file replace the app's own `LightingMode`, `LodConfig` and
+/// `TickPolicy` resources on the first frame, whichever was added first.
+#[cfg(feature = "bevy")]
+pub struct ConfigPlugin {
+    pub path: PathBuf,
+    pub hot_reload: Option<f32>,
+}
+
+#[cfg(feature = "bevy")]
+impl ConfigPlugin {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            hot_reload: None,
+        }
+    }
+
+    /// Checks the file for changes every `interval` seconds.
+    pub fn with_hot_reload(mut self, interval: f32) -> Self {
+        self.hot_reload = Some(interval);
+        self
+    }
+}
+
+#[cfg(feature = "bevy")]
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let (source, config) = ConfigSource::load(self.path.clone(), self.hot_reload);
+        app.add_resource(source)
+            .add_resource(config)
+            .init_resource::<LightingMode>()
+            .init_resource::<LodConfig>()
+            .init_resource::<TickPolicy>()
+            .add_system_to_stage(stage::FIRST, config_update.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn config() {
+        let config = VoxelConfig::from_ron(
+            "(lighting: Auto, unload: (distance: 14), limits: (generate: Some(64)))",
+        )
+        .unwrap();
+        #[cfg(feature = "bevy")]
+        {
+            assert_eq!(config.lighting, LightingMode::Auto);
+            assert_eq!(config.lod, LodConfig::default());
+        }
+        assert_eq!(config.unload.distance, 14);
+        assert_eq!(config.limits.generate, Some(64));
+        assert_eq!(config.limits.mesh, None);
+        assert_eq!(config.save, SavePolicy::default());
+
+        let ron = ron::ser::to_string(&config).unwrap();
+        assert_eq!(VoxelConfig::from_ron(&ron).unwrap(), config);
+        assert!(matches!(
+            VoxelConfig::from_ron("(save: (backups: -1))"),
+            Err(ConfigError::Ron(_))
+        ));
+
+        // a missing file leaves the defaults
+        #[cfg(feature = "bevy")]
+        {
+            let (source, config) = ConfigSource::load(PathBuf::from("missing.ron"), None);
+            assert!(!source.pending);
+            assert_eq!(config, VoxelConfig::default());
+        }
+    }
+
+    #[test]
+    pub fn config_validation() {
+        assert!(VoxelConfig::default().validate().is_ok());
+        #[cfg(feature = "bevy")]
+        {
+            assert!(matches!(
+                VoxelConfig::from_ron("(lod: (distance: 0))"),
+                Err(ConfigError::LodDistance(0))
+            ));
+            assert!(matches!(
+                VoxelConfig::from_ron("(lod: (detail_bias: -0.5))"),
+                Err(ConfigError::DetailBias(_))
+            ));
+        }
+        assert!(matches!(
+            VoxelConfig::from_ron("(unload: (distance: -2))"),
+            Err(ConfigError::UnloadDistance(-2))
+        ));
+        assert!(VoxelConfig::from_ron("(unload: (distance: 0))").is_ok());
+
+        // an invalid file keeps the defaults like a broken one
+        let path = std::env::temp_dir().join(format!("voxel_config_{}.ron", std::process::id()));
+        fs::write(&path, "(unload: (distance: -1))").unwrap();
+        assert!(matches!(
+            VoxelConfig::load(&path),
+            Err(ConfigError::UnloadDistance(-1))
+        ));
+        #[cfg(feature = "bevy")]
+        {
+            let (source, config) = ConfigSource::load(path.clone(), None);
+            assert!(!source.pending);
+            assert_eq!(config, VoxelConfig::default());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    pub fn toml_config() {
+        let config = VoxelConfig::from_toml(
+            "[limits]\nmesh = 32\n[save]\nautosave = 60.0\n[unload]\ngrace = 2.0\n",
+        )
+        .unwrap();
+        assert_eq!(config.limits.mesh, Some(32));
+        assert_eq!(config.save.autosave, Some(60.0));
+        assert_eq!(config.unload.grace, 2.0);
+        assert_eq!(config.unload.distance, 10);
+    }
+}
@@ -0,0 +1,396 @@
+//! The foundation of a creative mode or level editor: a brush that places, erases or paints
+//! voxels where the cursor points.
+//!
+//! The brush is driven by the mouse and by `EditorCommand` events, which any UI can send from
+//! its buttons and palettes, and reports every stroke as a `BrushApplied` event and the edits
+//! its `PlacementRules` rejected as `PlacementRejected` events.
+
+use std::marker::PhantomData;
+
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{ActiveCameras, Camera},
+        render_graph::base,
+    },
+};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    render::{debug::cursor_ray, origin::FloatingOrigin},
+    world::{Map, MapUpdates, PlacementRejected, PlacementRules, RaycastHit},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Cube,
+    Sphere,
+}
+
+impl Default for BrushShape {
+    fn default() -> Self {
+        Self::Cube
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushMode {
+    /// Fills the empty voxels under the brush, in front of the face under the cursor.
+    Place,
+    /// Clears the voxels under the brush.
+    Erase,
+    /// Replaces the voxels under the brush that aren't empty.
+    Paint,
+}
+
+impl Default for BrushMode {
+    fn default() -> Self {
+        Self::Place
+    }
+}
+
+impl BrushMode {
+    /// Returns where the brush is centered for a ray that hit `hit`, in front of the hit face
+    /// when placing and on the hit voxel otherwise.
+    pub fn target(self, hit: &RaycastHit) -> (i32, i32, i32) {
+        match self {
+            Self::Place => hit.adjacent(),
+            Self::Erase | Self::Paint => hit.position,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brush<T> {
+    /// The voxel that is placed or painted, the brush does nothing but erase without one.
+    pub voxel: Option<T>,
+    /// `0` edits a single voxel.
+    pub radius: u32,
+    pub shape: BrushShape,
+    pub mode: BrushMode,
+}
+
+impl<T> Default for Brush<T> {
+    fn default() -> Self {
+        Self {
+            voxel: None,
+            radius: 0,
+            shape: BrushShape::default(),
+            mode: BrushMode::default(),
+        }
+    }
+}
+
+impl<T: Voxel> Brush<T> {
+    pub fn new(voxel: T) -> Self {
+        Self {
+            voxel: Some(voxel),
+            ..Default::default()
+        }
+    }
+
+    /// The world coordinates of the voxels under the brush centered at `center`.
+    pub fn positions(&self, (cx, cy, cz): (i32, i32, i32)) -> Vec<(i32, i32, i32)> {
+        let r = self.radius as i32;
+        let mut positions = Vec::new();
+        for x in -r..=r {
+            for y in -r..=r {
+                for z in -r..=r {
+                    if self.shape == BrushShape::Sphere && x * x + y * y + z * z > r * r {
+                        continue;
+                    }
+                    positions.push((cx + x, cy + y, cz + z));
+                }
+            }
+        }
+        positions
+    }
+
+    /// Applies the brush centered at `center` with `mode`, going through `rules` for every
+    /// voxel. Returns the number of voxels that changed, the edits `rules` rejected are added
+    /// to `rejected`.
+    pub fn apply(
+        &self,
+        map: &mut Map<T>,
+        center: (i32, i32, i32),
+        mode: BrushMode,
+        rules: &PlacementRules<T>,
+        updates: &mut MapUpdates,
+        rejected: &mut Vec<PlacementRejected>,
+    ) -> usize {
+        let mut changed = 0;
+        for coords in self.positions(center) {
+            let present = map.voxel(coords).is_some();
+            let voxel = match (mode, &self.voxel) {
+                (BrushMode::Place, Some(voxel)) if !present => Some(voxel.clone()),
+                (BrushMode::Paint, Some(voxel)) if present => Some(voxel.clone()),
+                (BrushMode::Erase, _) if present => None,
+                _ => continue,
+            };
+            match map.place(coords, voxel, rules, updates) {
+                Ok(true) => changed += 1,
+                Ok(false) => {}
+                Err(e) => rejected.push(e),
+            }
+        }
+        changed
+    }
+}
+
+/// What a UI can ask of the editor, e.g. when a block of a palette is clicked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditorCommand<T> {
+    SetVoxel(Option<T>),
+    SetRadius(u32),
+    SetShape(BrushShape),
+    SetMode(BrushMode),
+    SetEnabled(bool),
+    /// Applies the brush at world coordinates, e.g. for scripted edits.
+    Apply((i32, i32, i32)),
+}
+
+/// Sent for every stroke of the brush.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrushApplied {
+    pub center: (i32, i32, i32),
+    pub mode: BrushMode,
+    /// The number of voxels that changed.
+    pub changed: usize,
+}
+
+/// The state of the editor, a resource added by `EditorPlugin`.
+pub struct Editor<T: Voxel> {
+    pub brush: Brush<T>,
+    /// Turns the mouse input off, e.g. while a menu is open.
+    pub enabled: bool,
+    /// Applies the brush with its mode.
+    pub apply_button: MouseButton,
+    /// Applies the brush in `BrushMode::Erase`, whatever its mode.
+    pub erase_button: MouseButton,
+    pub max_distance: f32,
+    /// Checked for every voxel the brush edits, rejected edits are sent as `PlacementRejected`
+    /// events.
+    pub rules: PlacementRules<T>,
+    cursor: Option<Vec2>,
+    cursor_reader: EventReader<CursorMoved>,
+    command_reader: EventReader<EditorCommand<T>>,
+}
+
+impl<T: Voxel> Default for Editor<T> {
+    fn default() -> Self {
+        Self {
+            brush: Brush::default(),
+            enabled: true,
+            apply_button: MouseButton::Left,
+            erase_button: MouseButton::Right,
+            max_distance: 256.0,
+            rules: PlacementRules::default(),
+            cursor: None,
+            cursor_reader: Default::default(),
+            command_reader: Default::default(),
+        }
+    }
+}
+
+/// Applies the `EditorCommand`s sent since the last frame. Strokes go to the first map.
+pub fn editor_command_update<T: Voxel>(
+    mut editor: ResMut<Editor<T>>,
+    commands: Res<Events<EditorCommand<T>>>,
+    mut applied: ResMut<Events<BrushApplied>>,
+    mut rejections: ResMut<Events<PlacementRejected>>,
+    mut maps: Query<(&mut Map<T>, &mut MapUpdates)>,
+) {
+    let commands = editor
+        .command_reader
+        .iter(&commands)
+        .cloned()
+        .collect::<Vec<_>>();
+    for command in commands {
+        match command {
+            EditorCommand::SetVoxel(voxel) => editor.brush.voxel = voxel,
+            EditorCommand::SetRadius(radius) => editor.brush.radius = radius,
+            EditorCommand::SetShape(shape) => editor.brush.shape = shape,
+            EditorCommand::SetMode(mode) => editor.brush.mode = mode,
+            EditorCommand::SetEnabled(enabled) => editor.enabled = enabled,
+            EditorCommand::Apply(center) => {
+                if let Some((mut map, mut updates)) = (&mut maps.iter()).into_iter().next() {
+                    let mode = editor.brush.mode;
+                    let mut rejected = Vec::new();
+                    let changed = editor.brush.apply(
+                        &mut map,
+                        center,
+                        mode,
+                        &editor.rules,
+                        &mut updates,
+                        &mut rejected,
+                    );
+                    applied.send(BrushApplied {
+                        center,
+                        mode,
+                        changed,
+                    });
+                    for rejection in rejected {
+                        rejections.send(rejection);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Applies the brush to the voxel under the cursor when one of the editor's mouse buttons is
+/// pressed, in the map whose voxel is the closest.
+#[allow(clippy::too_many_arguments)]
+pub fn editor_input_update<T: Voxel>(
+    mut editor: ResMut<Editor<T>>,
+    buttons: Res<Input<MouseButton>>,
+    cursor_events: Res<Events<CursorMoved>>,
+    windows: Res<Windows>,
+    cameras: Res<ActiveCameras>,
+    origin: Res<FloatingOrigin>,
+    mut applied: ResMut<Events<BrushApplied>>,
+    mut rejections: ResMut<Events<PlacementRejected>>,
+    mut maps: Query<(Entity, &mut Map<T>, &mut MapUpdates)>,
+    camera_query: Query<(&Camera, &Transform)>,
+) {
+    if let Some(event) = editor.cursor_reader.iter(&cursor_events).last() {
+        editor.cursor = Some(event.position);
+    }
+    let mode = if buttons.just_pressed(editor.erase_button) {
+        BrushMode::Erase
+    } else if buttons.just_pressed(editor.apply_button) {
+        editor.brush.mode
+    } else {
+        return;
+    };
+    if !editor.enabled {
+        return;
+    }
+    let (cursor, window) = match (editor.cursor, windows.get_primary()) {
+        (Some(cursor), Some(window)) => (cursor, window),
+        _ => return,
+    };
+    let camera = match cameras.get(base::camera::CAMERA3D) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let (projection, transform) = match (
+        camera_query.get::<Camera>(camera),
+        camera_query.get::<Transform>(camera),
+    ) {
+        (Ok(camera), Ok(transform)) => (camera.projection_matrix, transform.value),
+        _ => return,
+    };
+    let size = Vec2::new(window.width() as f32, window.height() as f32);
+    let (local, direction) = cursor_ray(cursor, size, &projection, &transform);
+    let (ox, oy, oz) = origin.origin;
+    let start = local + Vec3::new(ox as f32, oy as f32, oz as f32);
+
+    let hit = (&mut maps.iter())
+        .into_iter()
+        .filter_map(|(e, map, _)| Some((e, map.raycast(start, direction, editor.max_distance)?)))
+        .min_by(|(_, a), (_, b)| a.distance.partial_cmp(&b.distance).unwrap());
+    let (e, hit) = match hit {
+        Some(hit) => hit,
+        None => return,
+    };
+    let center = mode.target(&hit);
+    if let (Ok(mut map), Ok(mut updates)) =
+        (maps.get_mut::<Map<T>>(e), maps.get_mut::<MapUpdates>(e))
+    {
+        let mut rejected = Vec::new();
+        let changed = editor.brush.apply(
+            &mut map,
+            center,
+            mode,
+            &editor.rules,
+            &mut updates,
+            &mut rejected,
+        );
+        applied.send(BrushApplied {
+            center,
+            mode,
+            changed,
+        });
+        for rejection in rejected {
+            rejections.send(rejection);
+        }
+    }
+}
+
+/// Adds the `Editor` resource, its events and systems for maps of `T`.
+pub struct EditorPlugin<T>(PhantomData<T>);
+
+impl<T> Default for EditorPlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: Voxel> Plugin for EditorPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Editor<T>>()
+            .add_event::<EditorCommand<T>>()
+            .add_event::<BrushApplied>()
+            .add_event::<PlacementRejected>()
+            .add_system(editor_command_update::<T>.system())
+            .add_system(editor_input_update::<T>.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::{Chunk, Map, MapUpdates, PlacementRules};
+
+    use super::*;
+
+    #[test]
+    pub fn brush() {
+        // applies the brush and returns how many voxels changed and how many were rejected
+        fn stroke(
+            brush: &Brush<i32>,
+            map: &mut Map<i32>,
+            center: (i32, i32, i32),
+            mode: BrushMode,
+            rules: &PlacementRules<i32>,
+        ) -> (usize, usize) {
+            let mut updates = MapUpdates::default();
+            let mut rejected = Vec::new();
+            let changed = brush.apply(map, center, mode, rules, &mut updates, &mut rejected);
+            (changed, rejected.len())
+        }
+
+        let mut map = Map::try_with_chunks(vec![Chunk::new(3, (0, 0, 0))]).unwrap();
+        let rules = PlacementRules::default();
+        let mut brush = Brush::new(1);
+        brush.radius = 1;
+        assert_eq!(brush.positions((4, 4, 4)).len(), 27);
+        let placed = stroke(&brush, &mut map, (4, 4, 4), BrushMode::Place, &rules);
+        assert_eq!(placed, (27, 0));
+
+        brush.shape = BrushShape::Sphere;
+        assert_eq!(brush.positions((4, 4, 4)).len(), 7);
+        brush.voxel = Some(2);
+        let painted = stroke(&brush, &mut map, (4, 4, 5), BrushMode::Paint, &rules);
+        assert_eq!(painted, (6, 0));
+        assert_eq!(map.voxel((4, 4, 5)).unwrap().into_owned(), 2);
+        assert_eq!(map.voxel((3, 3, 3)).unwrap().into_owned(), 1);
+        assert!(map.voxel((4, 4, 6)).is_none());
+
+        brush.radius = 0;
+        let erased = stroke(&brush, &mut map, (4, 4, 4), BrushMode::Erase, &rules);
+        assert_eq!(erased, (1, 0));
+        let erased = stroke(&brush, &mut map, (4, 4, 4), BrushMode::Erase, &rules);
+        assert_eq!(erased, (0, 0));
+        // voxels outside the map's chunks aren't counted
+        brush.radius = 1;
+        let placed = stroke(&brush, &mut map, (0, 0, 0), BrushMode::Place, &rules);
+        assert_eq!(placed, (4, 0));
+
+        // rejected edits are reported instead of made
+        let rules = PlacementRules::new().require_support(|_| true, |&v| v == 3);
+        let placed = stroke(&brush, &mut map, (6, 6, 6), BrushMode::Place, &rules);
+        assert_eq!(placed, (0, 7));
+        assert!(map.voxel((6, 6, 6)).is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use std::{error, fmt};
+
+use crate::{
+    terrain::{Type, Value},
+    world::{ChunkStateError, ChunkWidthError},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    /// A pipeline stage ran on a chunk out of order.
+    ChunkState(ChunkStateError),
+    /// A chunk didn't match the width of the map's chunks.
+    ChunkWidth(ChunkWidthError),
+    /// A terrain program produced a value of the wrong type.
+    Type { value: Value, expected: Type },
+    /// A column query was executed without a xz coordinate.
+    MissingColumn,
+    /// A terrain program uses something that isn't implemented yet.
+    Unsupported(&'static str),
+    /// A column query asked for a climate field the program doesn't have.
+    UnknownClimate(String),
+    /// A terrain program was built with an invalid configuration.
+    Program(ProgramError),
+    /// A map's chunks aren't as wide as the chunks of the program generating it.
+    LayoutWidth { layout: usize, program: usize },
+    #[cfg(feature = "savedata")]
+    Save(bincode::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChunkState(e) => e.fmt(f),
+            Self::ChunkWidth(e) => e.fmt(f),
+            Self::Type { value, expected } => write!(
+                f,
+                "{}: {} is not of type {}",
+                value,
+                value.type_of(),
+                expected
+            ),
+            Self::MissingColumn => {
+                write!(f, "column queries must be supplied with a xz coordinate")
+            }
+            Self::Unsupported(what) => write!(f, "{} is not supported yet", what),
+            Self::UnknownClimate(field) => write!(f, "unknown climate field {}", field),
+            Self::Program(e) => e.fmt(f),
+            Self::LayoutWidth { layout, program } => write!(
+                f,
+                "the map's chunks are {} voxels wide, but the program generates chunks {} \
+                 voxels wide",
+                layout, program
+            ),
+            #[cfg(feature = "savedata")]
+            Self::Save(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::ChunkState(e) => Some(e),
+            Self::ChunkWidth(e) => Some(e),
+            Self::Program(e) => Some(e),
+            #[cfg(feature = "savedata")]
+            Self::Save(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChunkStateError> for Error {
+    fn from(e: ChunkStateError) -> Self {
+        Self::ChunkState(e)
+    }
+}
+
+impl From<ChunkWidthError> for Error {
+    fn from(e: ChunkWidthError) -> Self {
+        Self::ChunkWidth(e)
+    }
+}
+
+impl From<ProgramError> for Error {
+    fn from(e: ProgramError) -> Self {
+        Self::Program(e)
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Self::Save(e)
+    }
+}
+
+/// A reason `ProgramBuilder::build` rejected a terrain program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    /// The program has no biomes to pick from.
+    NoBiomes,
+    /// A biome has a negative, infinite or NaN spawn probability.
+    BiomeProbability {
+        biome: Option<&'static str>,
+        prob: f64,
+    },
+    /// The spawn probabilities of all biomes add up to zero.
+    ZeroProbability,
+    /// The chunk width of `2^chunk_size` doesn't fit world coordinates.
+    ChunkSize(u32),
+    /// More subdivisions than the chunk size, which would leave chunks without any units.
+    Subdivisions { subdivisions: u32, chunk_size: u32 },
+    /// A bilinear filter width that isn't a positive power of two.
+    FilterWidth(i32),
+    /// A biome frequency that isn't positive and finite.
+    BiomeFrequency(f64),
+    /// An ore with a negative, infinite or NaN number of veins per chunk.
+    OreVeins(f64),
+    /// A climate field frequency that isn't positive and finite.
+    ClimateFrequency { field: &'static str, frequency: f64 },
+    /// A biome is limited to a range of a climate field the program doesn't have.
+    UnknownClimate {
+        biome: Option<&'static str>,
+        field: &'static str,
+    },
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoBiomes => write!(f, "terrain programs need at least one biome"),
+            Self::BiomeProbability { biome, prob } => write!(
+                f,
+                "biome {} has an invalid spawn probability of {}",
+                biome.unwrap_or("<unnamed>"),
+                prob
+            ),
+            Self::ZeroProbability => write!(f, "the biome spawn probabilities add up to zero"),
+            Self::ChunkSize(size) => write!(f, "a chunk size of {} is too large", size),
+            Self::Subdivisions {
+                subdivisions,
+                chunk_size,
+            } => write!(
+                f,
+                "{} subdivisions are more than the chunk size of {}",
+                subdivisions, chunk_size
+            ),
+            Self::FilterWidth(width) => write!(
+                f,
+                "bilinear filter must have a power of two width, not {}",
+                width
+            ),
+            Self::BiomeFrequency(freq) => write!(f, "invalid biome frequency {}", freq),
+            Self::OreVeins(veins) => write!(f, "invalid number of ore veins {}", veins),
+            Self::ClimateFrequency { field, frequency } => write!(
+                f,
+                "invalid frequency {} of climate field {}",
+                frequency, field
+            ),
+            Self::UnknownClimate { biome, field } => write!(
+                f,
+                "biome {} uses the unknown climate field {}",
+                biome.unwrap_or("<unnamed>"),
+                field
+            ),
+        }
+    }
+}
+
+impl error::Error for ProgramError {}
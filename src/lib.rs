@@ -1,7 +1,78 @@
+#[macro_use]
+mod trace;
+
 pub mod collections;
+#[cfg(feature = "savedata")]
+pub mod config;
+#[cfg(feature = "editor")]
+pub mod editor;
+pub mod error;
+pub mod lighting;
+pub mod mesh;
+#[cfg(feature = "bevy")]
 pub mod render;
+pub mod sdf;
+pub mod seams;
 #[cfg(feature = "savedata")]
 pub mod serialize;
+#[cfg(feature = "bevy")]
 pub mod simple;
 pub mod terrain;
+#[cfg(feature = "headless")]
+pub mod testing;
+pub mod voxel_enum;
 pub mod world;
+
+/// The types most games need, so that a single `use bevy_voxel::prelude::*;` is enough to get
+/// going. Everything else stays at its module path.
+pub mod prelude {
+    #[cfg(all(feature = "bevy", feature = "savedata"))]
+    pub use crate::config::ConfigPlugin;
+    #[cfg(feature = "savedata")]
+    pub use crate::config::VoxelConfig;
+    #[cfg(feature = "editor")]
+    pub use crate::editor::{Editor, EditorPlugin};
+    pub use crate::{
+        collections::Voxel,
+        lighting::{AmbientLight, DirectionalLight, LightQuality, SkyAmbient},
+        mesh::{MeshOrigin, VoxelExt},
+        terrain::{
+            Biome, BiomeBuilder, GenerationCache, GenerationHooks, HeightMap, Layer,
+            NoiseDimensions, NoiseType, Octave, OreBuilder, Program, ProgramBuilder, Strata,
+        },
+        world::{
+            Chunk, ChunkState, ChunkUpdate, Map, MapLayout, MapUpdates, MergePolicy, Neighborhood,
+            VoxelWorld,
+        },
+    };
+    #[cfg(feature = "bevy")]
+    pub use crate::{
+        render::prelude::*, simple::Block, terrain::TerrainPlugin, world::MapComponents,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    pub fn prelude() {
+        // a world can be generated and edited with nothing but the prelude
+        let program = Program::<i32>::build()
+            .chunk_size(2)
+            .biome(Biome::build().height(2.0).layer(Layer::new(1, 2.0)).build())
+            .build()
+            .unwrap();
+        let mut world = VoxelWorld::new(program);
+        assert_eq!(world.request_area((0, 0, 0), (3, 3, 3)), 1);
+        assert_eq!(world.generate(&mut Vec::new()), 1);
+        assert_eq!(world.map().voxel((0, 0, 0)).as_deref(), Some(&1));
+
+        let mut map = Map::<i32>::with_layout(MapLayout::new(2));
+        map.try_insert(Chunk::new(2, (0, 0, 0))).unwrap();
+        let mut updates = MapUpdates::default();
+        assert!(map.set_voxel((1, 1, 1), Some(2), &mut updates));
+        let state = map.get((0, 0, 0)).map(Chunk::state);
+        assert_eq!(state, Some(ChunkState::Generated));
+    }
+}
@@ -0,0 +1,884 @@
+use std::{collections::HashMap, sync::mpsc};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use glam::Vec3;
+
+use line_drawing::{Bresenham3d, VoxelOrigin, WalkVoxels};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    mesh::{Face, VoxelExt},
+    world::{Chunk, Map, MergePolicy, Neighborhood},
+};
+
+pub trait VoxelTracer: Iterator<Item = (i32, i32, i32)> {
+    fn new(start: (i32, i32, i32), end: (i32, i32, i32)) -> Self;
+}
+
+impl VoxelTracer for Bresenham3d<i32> {
+    fn new(start: (i32, i32, i32), end: (i32, i32, i32)) -> Self {
+        Self::new(start, end)
+    }
+}
+
+impl VoxelTracer for WalkVoxels<f32, i32> {
+    fn new(start: (i32, i32, i32), end: (i32, i32, i32)) -> Self {
+        Self::new(
+            (start.0 as f32, start.1 as f32, start.2 as f32),
+            (end.0 as f32, end.1 as f32, end.2 as f32),
+            &VoxelOrigin::Center,
+        )
+    }
+}
+
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub intensity: f32,
+}
+
+pub struct AmbientLight {
+    pub intensity: f32,
+    /// Replaces `intensity` with a different ambient light per direction if set.
+    pub sky: Option<SkyAmbient>,
+}
+
+impl AmbientLight {
+    /// The same ambient light from every direction.
+    pub fn new(intensity: f32) -> Self {
+        Self {
+            intensity,
+            sky: None,
+        }
+    }
+
+    /// Returns the ambient light a face pointing towards `face` in the world gets.
+    pub fn face(&self, face: Face) -> f32 {
+        match &self.sky {
+            Some(sky) => sky.face(face),
+            None => self.intensity,
+        }
+    }
+}
+
+/// Ambient light like a cubemap reduced to the six faces of a voxel: the sky lights faces
+/// pointing up, light bounced off the ground faces pointing down and the horizon the sides.
+/// Gives faces in the shade of the directional light some depth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyAmbient {
+    pub sky: f32,
+    pub horizon: f32,
+    pub ground: f32,
+}
+
+impl SkyAmbient {
+    pub fn face(&self, face: Face) -> f32 {
+        match face {
+            Face::Top => self.sky,
+            Face::Bottom => self.ground,
+            _ => self.horizon,
+        }
+    }
+}
+
+/// How `shaded_light_map_with` smooths the light maps of a chunk and its neighbours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightQuality {
+    /// How far around every voxel the light is averaged, at most the chunk width. Smaller
+    /// radii give sharper shadows.
+    pub blur_radius: i32,
+    /// How many times light bounces from every voxel to its neighbours after smoothing,
+    /// which brightens shadows next to lit areas.
+    pub bounces: usize,
+    /// Smooths, bounces and shades light with fixed-point numbers, so that the same chunks
+    /// get the same shades and content hashes on every machine, e.g. for lockstep games.
+    /// Float sums can differ in their last bits between platforms and compilers.
+    pub fixed_point: bool,
+    /// How many rays `light_map_with` traces towards the light from every voxel. More than
+    /// one spreads them in a small cone around the light direction and averages them, which
+    /// softens the edges of shadows but takes as many times longer.
+    pub shadow_rays: usize,
+}
+
+impl LightQuality {
+    pub const STANDARD: Self = Self {
+        blur_radius: 1,
+        bounces: 0,
+        fixed_point: false,
+        shadow_rays: 1,
+    };
+    pub const HIGH: Self = Self {
+        blur_radius: 0,
+        bounces: 2,
+        fixed_point: false,
+        shadow_rays: 1,
+    };
+    /// Like `STANDARD`, with soft shadow edges.
+    pub const SOFT_SHADOWS: Self = Self {
+        shadow_rays: 8,
+        ..Self::STANDARD
+    };
+}
+
+impl Default for LightQuality {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// How much of the light of its neighbours a voxel receives per bounce.
+const BOUNCE: f32 = 0.25;
+
+/// The radius of the cone `LightQuality::shadow_rays` are spread in, per unit of distance
+/// towards the light. Far wider than the sun, so that the soft edges span a few voxels.
+const SHADOW_SPREAD: f32 = 0.15;
+
+/// The angle between successive rays of a bundle, which spreads any number of them evenly.
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+/// One in the fixed-point light of `LightQuality::fixed_point`. A power of two, so that
+/// converting from and to floats is exact.
+const FIXED_ONE: i64 = 1 << 16;
+
+fn to_fixed(light: f32) -> i64 {
+    (light * FIXED_ONE as f32).round() as i64
+}
+
+fn from_fixed(light: i64) -> f32 {
+    light as f32 / FIXED_ONE as f32
+}
+
+/// Shades every face of a chunk by its angle to the light, without any shadows.
+pub fn simple_light<T: VoxelExt>(
+    chunk: &mut Chunk<T>,
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+) {
+    simple_light_with(chunk, directional, ambient, MergePolicy::Always);
+}
+
+/// Like `simple_light`, but merges the voxels afterwards as `merge` says.
+pub fn simple_light_with<T: VoxelExt>(
+    chunk: &mut Chunk<T>,
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+    merge: MergePolicy,
+) {
+    let shades = face_shades(directional);
+    set_shades(chunk, merge, |_, face| {
+        shades[face as usize] + ambient.face(face)
+    });
+}
+
+/// Sets the shade of every face of every voxel of `chunk` to `shade(coords, face)` and merges
+/// the voxels as `merge` says.
+///
+/// Chunks whose shades are all up to date are left as they are, so that they aren't
+/// flattened and merged for nothing.
+fn set_shades<T, F>(chunk: &mut Chunk<T>, merge: MergePolicy, shade: F)
+where
+    T: VoxelExt,
+    F: Fn((i32, i32, i32), Face) -> f32,
+{
+    let unchanged = chunk.iter().all(|elem| {
+        let width = elem.width as i32;
+        let (x, y, z) = (elem.x, elem.y, elem.z);
+        let mut value = elem.value.into_owned();
+        let faces = value.face_map();
+        (0..width.pow(3)).all(|i| {
+            let coords = (x + i / width / width, y + i / width % width, z + i % width);
+            Face::ALL
+                .iter()
+                .all(|&face| value.shade(faces.to_local(face)) == Some(shade(coords, face)))
+        })
+    });
+    if unchanged {
+        return;
+    }
+
+    for elem in chunk.iter_mut() {
+        let coords = (elem.x, elem.y, elem.z);
+        let faces = elem.value.face_map();
+        for &face in &Face::ALL {
+            elem.value
+                .set_shade(faces.to_local(face), shade(coords, face));
+        }
+    }
+
+    chunk.merge_with(merge);
+}
+
+/// Returns the part of `directional` every face gets, in the order of `Face::ALL`.
+pub fn face_shades(directional: &DirectionalLight) -> [f32; 6] {
+    let light = -directional.direction;
+    let mut shades = [0.0; 6];
+    for (shade, &face) in shades.iter_mut().zip(&Face::ALL) {
+        *shade = light.dot(face_normal(face)).max(0.0).min(1.0) * directional.intensity;
+    }
+    shades
+}
+
+/// Traces the directional light through a chunk and writes the result to the back buffer of
+/// its light map.
+///
+/// Call `Chunk::swap_light` once all chunks of a pass are done, so that shading never sees a
+/// mix of old and new light maps.
+pub fn light_map<T: Voxel, R: VoxelTracer>(chunk: &mut Chunk<T>, directional: &DirectionalLight) {
+    light_map_with::<T, R>(chunk, directional, &LightQuality::STANDARD);
+}
+
+/// Like `light_map`, but traces `quality.shadow_rays` rays per voxel.
+pub fn light_map_with<T: Voxel, R: VoxelTracer>(
+    chunk: &mut Chunk<T>,
+    directional: &DirectionalLight,
+    quality: &LightQuality,
+) {
+    let lm_width = chunk.width() as i32;
+    let directions = shadow_directions(directional.direction, quality.shadow_rays);
+    let mut light_map = vec![0.0; chunk.width().pow(3)];
+    for &direction in &directions {
+        let traced = trace_light::<T, R>(chunk, direction);
+        for (light, traced) in light_map.iter_mut().zip(traced) {
+            *light += traced.unwrap_or_default();
+        }
+    }
+
+    let rays = directions.len() as f32;
+    for x in 0..lm_width {
+        for y in 0..lm_width {
+            for z in 0..lm_width {
+                let idx =
+                    (x * lm_width * lm_width) as usize + (y * lm_width) as usize + z as usize;
+                chunk.insert_next_light((x, y, z), light_map[idx] / rays);
+            }
+        }
+    }
+}
+
+/// Returns the directions of `rays` rays towards the light, spread in a cone around
+/// `direction`. A single ray keeps `direction`.
+fn shadow_directions(direction: Vec3, rays: usize) -> Vec<Vec3> {
+    if rays <= 1 {
+        return vec![direction];
+    }
+    // any two axes perpendicular to the light span the cone's base
+    let helper = if direction.x().abs() < 0.9 {
+        Vec3::unit_x()
+    } else {
+        Vec3::unit_y()
+    };
+    let u = direction.cross(helper).normalize();
+    let v = direction.cross(u);
+    (0..rays)
+        .map(|i| {
+            let radius = SHADOW_SPREAD * ((i as f32 + 0.5) / rays as f32).sqrt();
+            let angle = i as f32 * GOLDEN_ANGLE;
+            (direction + u * radius * angle.cos() + v * radius * angle.sin()).normalize()
+        })
+        .collect()
+}
+
+/// Traces light coming from `direction` through a chunk, returning the light of every voxel
+/// a ray passed through.
+fn trace_light<T: Voxel, R: VoxelTracer>(chunk: &Chunk<T>, direction: Vec3) -> Vec<Option<f32>> {
+    let mut light_map = vec![None; chunk.width().pow(3)];
+
+    let lm_width = chunk.width() as i32;
+
+    // nothing casts a shadow in an empty chunk
+    if chunk.occupancy().is_empty() {
+        light_map = vec![Some(1.0); light_map.len()];
+    }
+    // averaged voxels stay within their cell as long as they aren't wider than it, so empty
+    // cells can be skipped without sampling
+    let skip_cells = 1 << chunk.lod() <= chunk.occupancy().cell_width();
+
+    for y in 0..lm_width {
+        for x in 0..lm_width {
+            for z in 0..lm_width {
+                let idx =
+                    (x * lm_width * lm_width) as usize + (y * lm_width) as usize + z as usize;
+                if light_map[idx].is_some() {
+                    continue;
+                }
+
+                let light_source = Vec3::new(x as _, y as _, z as _) + direction * -100.0;
+                let mut light = 1.0;
+                for (x, y, z) in R::new(
+                    (
+                        light_source.x() as _,
+                        light_source.y() as _,
+                        light_source.z() as _,
+                    ),
+                    (x, y, z),
+                ) {
+                    if x < 0 || y < 0 || z < 0 || x >= lm_width || y >= lm_width || z >= lm_width
+                    {
+                        continue;
+                    }
+                    let empty = skip_cells && chunk.occupancy().empty_cell((x, y, z)).is_some();
+                    if !empty && chunk.sampled_get((x, y, z)).is_some() {
+                        light = 0.0;
+                    }
+                    let idx =
+                        (x * lm_width * lm_width) as usize + (y * lm_width) as usize + z as usize;
+                    if let Some(map) = light_map.get_mut(idx) {
+                        if map.is_none() {
+                            *map = Some(light);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    light_map
+}
+
+/// Averages the light maps of the chunk at `coords` and its neighbours into a smoothed light
+/// map one voxel wider on every side, to be passed to `shaded_light`.
+///
+/// Returns `None` if there is no chunk at `coords`.
+pub fn shaded_light_map<T: Voxel>(map: &Map<T>, coords: (i32, i32, i32)) -> Option<Vec<f32>> {
+    shaded_light_map_with(map, coords, &LightQuality::STANDARD)
+}
+
+/// Like `shaded_light_map`, but smooths the light with `quality`.
+pub fn shaded_light_map_with<T: Voxel>(
+    map: &Map<T>,
+    coords: (i32, i32, i32),
+    quality: &LightQuality,
+) -> Option<Vec<f32>> {
+    let chunk = map.get_at_origin(coords)?;
+
+    let width = chunk.width() as i32;
+
+    let lm_width = chunk.width() as i32 + 2;
+
+    let neighbours = map
+        .neighbors(coords, Neighborhood::All)
+        .collect::<HashMap<_, _>>();
+    // neighbours of other widths don't line up with the offsets, so their voxels are looked up
+    // by world coordinates
+    let mixed = matches!(map.layout(), Some(layout) if layout.is_mixed());
+
+    let (tx, rx) = mpsc::channel();
+
+    let slice = |tx: &mut mpsc::Sender<_>, x: i32| {
+        for y in -1..lm_width - 1 {
+            for z in -1..lm_width - 1 {
+                let mut light = 0.0;
+                let mut fixed = 0;
+                let mut count = 0;
+                let range = quality.blur_radius;
+                for lx in -range..=range {
+                    for ly in -range..=range {
+                        for lz in -range..=range {
+                            let x = x + lx;
+                            let y = y + ly;
+                            let z = z + lz;
+                            if x < 0 || x >= width || y < 0 || y >= width || z < 0 || z >= width {
+                                let sx = if x < 0 {
+                                    -1
+                                } else if x >= width {
+                                    1
+                                } else {
+                                    0
+                                };
+                                let sy = if y < 0 {
+                                    -1
+                                } else if y >= width {
+                                    1
+                                } else {
+                                    0
+                                };
+                                let sz = if z < 0 {
+                                    -1
+                                } else if z >= width {
+                                    1
+                                } else {
+                                    0
+                                };
+                                let neighbour = if mixed {
+                                    let (cx, cy, cz) = coords;
+                                    let world = (cx + x, cy + y, cz + z);
+                                    map.chunk_containing(world)
+                                        .map(|chunk| (chunk, chunk.to_local(world)))
+                                } else {
+                                    neighbours
+                                        .get(&(sx, sy, sz))
+                                        .map(|chunk| (*chunk, (x % width, y % width, z % width)))
+                                };
+                                if let Some((chunk, local)) = neighbour {
+                                    if !chunk.has_light() {
+                                        return;
+                                    }
+                                    if let Some(l) = chunk.light(local) {
+                                        light += l;
+                                        fixed += to_fixed(l);
+                                        count += 1;
+                                    }
+                                } else if let Some(border) = chunk.border_light() {
+                                    // the light the neighbour had when the chunk was saved
+                                    if let Some(l) = border.get((x, y, z)) {
+                                        light += l;
+                                        fixed += to_fixed(l);
+                                        count += 1;
+                                    }
+                                }
+                            } else if let Some(l) = chunk.light((x, y, z)) {
+                                light += l;
+                                fixed += to_fixed(l);
+                                count += 1;
+                            }
+                        }
+                    }
+                }
+                if count == 0 {
+                    count = 1;
+                }
+                let light = if quality.fixed_point {
+                    from_fixed(fixed / count as i64)
+                } else {
+                    light / count as f32
+                };
+                tx.send(((x, y, z), light)).unwrap();
+            }
+        }
+    };
+    #[cfg(feature = "parallel")]
+    (-1..lm_width - 1).into_par_iter().for_each_with(tx, slice);
+    #[cfg(not(feature = "parallel"))]
+    {
+        let mut tx = tx;
+        (-1..lm_width - 1).for_each(|x| slice(&mut tx, x));
+    }
+
+    let mut light_map = rx.try_iter().collect::<Vec<_>>();
+    light_map.sort_unstable_by_key(|(coords, _)| *coords);
+
+    let mut light_map = light_map.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+    if light_map.len() == lm_width.pow(3) as usize {
+        for _ in 0..quality.bounces {
+            light_map = if quality.fixed_point {
+                bounce_fixed(&light_map, lm_width)
+            } else {
+                bounce(&light_map, lm_width)
+            };
+        }
+    }
+    Some(light_map)
+}
+
+/// The voxels light bounces to.
+const NEIGHBOURS: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+/// Lets every voxel of a smoothed light map receive some of the light of its neighbours.
+fn bounce(light_map: &[f32], lm_width: i32) -> Vec<f32> {
+    let idx = |x: i32, y: i32, z: i32| (x * lm_width * lm_width + y * lm_width + z) as usize;
+    let mut bounced = light_map.to_vec();
+    for x in 0..lm_width {
+        for y in 0..lm_width {
+            for z in 0..lm_width {
+                let mut light = 0.0;
+                let mut count = 0;
+                for &(dx, dy, dz) in &NEIGHBOURS {
+                    let (x, y, z) = (x + dx, y + dy, z + dz);
+                    if x < 0 || y < 0 || z < 0 || x >= lm_width || y >= lm_width || z >= lm_width
+                    {
+                        continue;
+                    }
+                    light += light_map[idx(x, y, z)];
+                    count += 1;
+                }
+                let own = light_map[idx(x, y, z)];
+                bounced[idx(x, y, z)] = own + (1.0 - own) * BOUNCE * light / count as f32;
+            }
+        }
+    }
+    bounced
+}
+
+/// Like `bounce`, but with fixed-point numbers.
+fn bounce_fixed(light_map: &[f32], lm_width: i32) -> Vec<f32> {
+    let fixed = light_map.iter().map(|&l| to_fixed(l)).collect::<Vec<_>>();
+    let idx = |x: i32, y: i32, z: i32| (x * lm_width * lm_width + y * lm_width + z) as usize;
+    let bounce = to_fixed(BOUNCE);
+    let mut bounced = light_map.to_vec();
+    for x in 0..lm_width {
+        for y in 0..lm_width {
+            for z in 0..lm_width {
+                let mut light = 0;
+                let mut count = 0;
+                for &(dx, dy, dz) in &NEIGHBOURS {
+                    let (x, y, z) = (x + dx, y + dy, z + dz);
+                    if x < 0 || y < 0 || z < 0 || x >= lm_width || y >= lm_width || z >= lm_width
+                    {
+                        continue;
+                    }
+                    light += fixed[idx(x, y, z)];
+                    count += 1;
+                }
+                let own = fixed[idx(x, y, z)];
+                let received = (FIXED_ONE - own) * bounce / FIXED_ONE * light / count / FIXED_ONE;
+                bounced[idx(x, y, z)] = from_fixed(own + received);
+            }
+        }
+    }
+    bounced
+}
+
+/// Shades every face of a chunk using a light map from `shaded_light_map`.
+pub fn shaded_light<T: VoxelExt>(
+    chunk: &mut Chunk<T>,
+    light_map: &[f32],
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+) {
+    shaded_light_with(chunk, light_map, directional, ambient, &LightQuality::STANDARD);
+}
+
+/// Like `shaded_light`, but with fixed-point numbers if the light map was smoothed with
+/// `LightQuality::fixed_point`.
+pub fn shaded_light_with<T: VoxelExt>(
+    chunk: &mut Chunk<T>,
+    light_map: &[f32],
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+    quality: &LightQuality,
+) {
+    shaded_light_with_merge(
+        chunk,
+        light_map,
+        directional,
+        ambient,
+        quality,
+        MergePolicy::Always,
+    );
+}
+
+/// Like `shaded_light_with`, but merges the voxels afterwards as `merge` says.
+pub fn shaded_light_with_merge<T: VoxelExt>(
+    chunk: &mut Chunk<T>,
+    light_map: &[f32],
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+    quality: &LightQuality,
+    merge: MergePolicy,
+) {
+    let lm_width = chunk.width() as i32 + 2;
+
+    let dir = -directional.direction;
+
+    set_shades(chunk, merge, |coords, face| {
+        let light = face_light(light_map, lm_width, coords, face);
+        let facing = dir.dot(face_normal(face)).max(0.0).min(1.0);
+        if quality.fixed_point {
+            let direct = to_fixed(light) * to_fixed(facing) / FIXED_ONE
+                * to_fixed(directional.intensity)
+                / FIXED_ONE;
+            from_fixed(direct + to_fixed(ambient.face(face)))
+        } else {
+            light * facing * directional.intensity + ambient.face(face)
+        }
+    });
+}
+
+/// Like `simple_light`, but leaves the angle to the light and the ambient light to the
+/// shader, see `VoxelMaterial::shader_light`. Every face is fully lit.
+pub fn simple_visibility<T: VoxelExt>(chunk: &mut Chunk<T>) {
+    simple_visibility_with(chunk, MergePolicy::Always);
+}
+
+/// Like `simple_visibility`, but merges the voxels afterwards as `merge` says.
+pub fn simple_visibility_with<T: VoxelExt>(chunk: &mut Chunk<T>, merge: MergePolicy) {
+    set_shades(chunk, merge, |_, _| 1.0);
+}
+
+/// Like `shaded_light`, but only stores how much of the directional light reaches every face
+/// and leaves the angle to the light and the ambient light to the shader, see
+/// `VoxelMaterial::shader_light`.
+pub fn shaded_visibility<T: VoxelExt>(chunk: &mut Chunk<T>, light_map: &[f32]) {
+    shaded_visibility_with(chunk, light_map, MergePolicy::Always);
+}
+
+/// Like `shaded_visibility`, but merges the voxels afterwards as `merge` says.
+pub fn shaded_visibility_with<T: VoxelExt>(
+    chunk: &mut Chunk<T>,
+    light_map: &[f32],
+    merge: MergePolicy,
+) {
+    let lm_width = chunk.width() as i32 + 2;
+    set_shades(chunk, merge, |coords, face| {
+        face_light(light_map, lm_width, coords, face)
+    });
+}
+
+fn face_normal(face: Face) -> Vec3 {
+    let (x, y, z) = face.normal();
+    Vec3::new(x as f32, y as f32, z as f32)
+}
+
+/// Looks up the light in front of `face` of the voxel at `(x, y, z)` in a smoothed light map.
+fn face_light(light_map: &[f32], lm_width: i32, (x, y, z): (i32, i32, i32), face: Face) -> f32 {
+    // the light map starts one voxel before the chunk
+    let (dx, dy, dz) = match face {
+        Face::Top => (1, 2, 1),
+        Face::Bottom => (1, 0, 1),
+        Face::Front => (1, 1, 2),
+        Face::Back => (1, 1, 0),
+        Face::Left => (2, 1, 1),
+        Face::Right => (0, 1, 1),
+    };
+    let idx = ((x + dx) * lm_width * lm_width) as usize
+        + ((y + dy) * lm_width) as usize
+        + (z + dz) as usize;
+    light_map[idx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn face_shades() {
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, -1.0).normalize(),
+            intensity: 0.8,
+        };
+        let shades = super::face_shades(&directional);
+        assert!((shades[0] - 0.8 * 0.5_f32.sqrt()).abs() < 1e-6);
+        assert!((shades[2] - shades[0]).abs() < 1e-6);
+        assert_eq!(shades[1], 0.0);
+        assert_eq!(shades[3], 0.0);
+        assert_eq!(shades[4], 0.0);
+    }
+
+    #[test]
+    pub fn fixed_point() {
+        let mut chunks = Vec::new();
+        for &x in &[0, 8] {
+            let mut chunk = Chunk::<i32>::new(3, (x, 0, 0));
+            for (i, j) in (0..8).flat_map(|i| (0..8).map(move |j| (i, j))) {
+                chunk.insert((i, 2, j), 1);
+                chunk.insert((i, 3 + (i + j) % 3, j), 1);
+            }
+            chunks.push(chunk);
+        }
+        let mut map = Map::try_with_chunks(chunks).unwrap();
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.3, -1.0, 0.2).normalize(),
+            intensity: 0.8,
+        };
+        for &position in &[(0, 0, 0), (8, 0, 0)] {
+            light_map::<_, Bresenham3d<i32>>(map.get_mut(position).unwrap(), &directional);
+        }
+        for &position in &[(0, 0, 0), (8, 0, 0)] {
+            map.get_mut(position).unwrap().swap_light();
+        }
+
+        let float = LightQuality {
+            bounces: 2,
+            ..LightQuality::STANDARD
+        };
+        let fixed = LightQuality {
+            fixed_point: true,
+            ..float
+        };
+        let float = shaded_light_map_with(&map, (0, 0, 0), &float).unwrap();
+        let fixed = shaded_light_map_with(&map, (0, 0, 0), &fixed).unwrap();
+        assert_eq!(fixed.len(), float.len());
+        for (&fixed, &float) in fixed.iter().zip(&float) {
+            assert_eq!(from_fixed(to_fixed(fixed)), fixed);
+            assert!((fixed - float).abs() < 1.0e-3);
+        }
+    }
+
+    #[test]
+    pub fn soft_shadows() {
+        // a roof over half of the chunk, lit from straight above
+        let mut chunk = Chunk::<i32>::new(3, (0, 0, 0));
+        for (x, z) in (0..4).flat_map(|x| (0..8).map(move |z| (x, z))) {
+            chunk.insert((x, 6, z), 1);
+        }
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 1.0,
+        };
+        let direction = directional.direction;
+        assert_eq!(shadow_directions(direction, 1), vec![direction]);
+        let directions = shadow_directions(direction, 8);
+        assert_eq!(directions.len(), 8);
+        assert!(directions.iter().all(|d| d.dot(direction) > 0.95));
+
+        let lights = |quality: &LightQuality| {
+            let mut chunk = chunk.clone();
+            light_map_with::<_, Bresenham3d<i32>>(&mut chunk, &directional, quality);
+            chunk.swap_light();
+            (0..8)
+                .map(|x| chunk.light((x, 0, 4)).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let hard = lights(&LightQuality::STANDARD);
+        assert_eq!(hard, vec![0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]);
+
+        // the edge of the shadow fades, the rest stays as it was
+        let soft = lights(&LightQuality::SOFT_SHADOWS);
+        assert!(soft[3] > 0.0 && soft[3] < 0.5);
+        assert!(soft[4] > 0.5 && soft[4] < 1.0);
+        assert_eq!(&soft[1..3], &hard[1..3]);
+        assert_eq!(&soft[5..], &hard[5..]);
+    }
+
+    #[test]
+    pub fn smoothed_light() {
+        // the same roof as in `soft_shadows`; without the `parallel` feature, e.g. on wasm32,
+        // the light map is smoothed on a single thread, which has to give the same result
+        let mut chunk = Chunk::<i32>::new(3, (0, 0, 0));
+        for (x, z) in (0..4).flat_map(|x| (0..8).map(move |z| (x, z))) {
+            chunk.insert((x, 6, z), 1);
+        }
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 1.0,
+        };
+        light_map::<_, Bresenham3d<i32>>(&mut chunk, &directional);
+        chunk.swap_light();
+        let map = Map::try_with_chunks(vec![chunk]).unwrap();
+
+        // the smoothed map has a border of one voxel around the chunk
+        let light = super::shaded_light_map(&map, (0, 0, 0)).unwrap();
+        assert_eq!(light.len(), 10 * 10 * 10);
+        let at = |x: usize, y: usize, z: usize| light[(x + 1) * 100 + (y + 1) * 10 + z + 1];
+        assert_eq!(at(1, 0, 4), 0.0);
+        assert!((at(3, 0, 4) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((at(4, 0, 4) - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(at(6, 0, 4), 1.0);
+    }
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn shader_light() {
+        use crate::{
+            mesh::{generate_chunk_buffers, MeshOrigin},
+            simple::Block,
+        };
+
+        let mut chunk = Chunk::<Block>::new(3, (0, 0, 0));
+        chunk.insert((1, 1, 1), Block::default());
+        let light_map = vec![0.5; 10 * 10 * 10];
+        let shade = |chunk: &Chunk<Block>, face| {
+            let mut block = chunk.get((1, 1, 1)).unwrap().into_owned();
+            block.shade(face).unwrap()
+        };
+
+        // the shader applies the angle to the light and the ambient light to the visibility
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 0.8,
+        };
+        let ambient = AmbientLight::new(0.2);
+        let mut baked = chunk.clone();
+        shaded_light(&mut baked, &light_map, &directional, &ambient);
+        assert!((shade(&baked, Face::Top) - 0.6).abs() < 1e-6);
+        assert!((shade(&baked, Face::Left) - 0.2).abs() < 1e-6);
+        shaded_visibility(&mut chunk, &light_map);
+        assert_eq!(shade(&chunk, Face::Top), 0.5);
+        assert_eq!(shade(&chunk, Face::Left), 0.5);
+        simple_visibility(&mut chunk);
+        assert_eq!(shade(&chunk, Face::Bottom), 1.0);
+
+        // the mesh carries the normals of the faces for the shader
+        let map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let chunk = map.get((0, 0, 0)).unwrap();
+        let (buffers, _) = generate_chunk_buffers(&map, chunk, MeshOrigin::Corner);
+        let normals = buffers.unwrap().normals;
+        assert_eq!(normals.len(), 6 * 4);
+        let count = |normal| normals.iter().filter(|&&n| n == normal).count();
+        assert_eq!(count([0.0, 1.0, 0.0]), 4);
+        assert_eq!(count([-1.0, 0.0, 0.0]), 4);
+    }
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn merge_policy() {
+        use crate::simple::Block;
+
+        let mut chunk = Chunk::<Block>::new(3, (0, 0, 0));
+        for (x, z) in (0..8).flat_map(|x| (0..8).map(move |z| (x, z))) {
+            chunk.insert((x, 0, z), Block::default());
+            chunk.insert((x, 1, z), Block::default());
+        }
+        let ambient = AmbientLight::new(0.2);
+        let light = |intensity| DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity,
+        };
+        simple_light(&mut chunk, &light(0.8), &ambient);
+        assert!(!chunk.is_fragmented());
+        let lit = chunk.iter().count();
+        let top = chunk.get((3, 1, 3)).unwrap().into_owned().shade(Face::Top);
+        assert!((top.unwrap() - 1.0).abs() < 1e-6);
+
+        // nothing changed, so nothing is flattened or marked for merging
+        simple_light_with(&mut chunk, &light(0.8), &ambient, MergePolicy::Idle);
+        assert!(!chunk.is_fragmented());
+        assert_eq!(chunk.iter().count(), lit);
+
+        simple_light_with(&mut chunk, &light(0.5), &ambient, MergePolicy::Idle);
+        assert!(chunk.is_fragmented());
+        assert!(chunk.iter().count() > lit);
+
+        // two edits aren't enough, three are
+        let policy = MergePolicy::AfterEdits(3);
+        chunk.merge();
+        chunk.insert((0, 2, 0), Block::default());
+        chunk.insert((1, 2, 0), Block::default());
+        simple_light_with(&mut chunk, &light(0.8), &ambient, policy);
+        assert!(chunk.is_fragmented());
+        chunk.insert((2, 2, 0), Block::default());
+        simple_light_with(&mut chunk, &light(0.5), &ambient, policy);
+        assert!(!chunk.is_fragmented());
+        assert!(chunk.iter().count() < 8 * 8 * 2);
+    }
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn sky_ambient() {
+        use crate::simple::Block;
+
+        let mut chunk = Chunk::<Block>::new(3, (0, 0, 0));
+        chunk.insert((3, 3, 3), Block::default());
+        // without directional light only the ambient light is left
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 0.0,
+        };
+        let mut ambient = AmbientLight::new(0.2);
+        ambient.sky = Some(SkyAmbient {
+            sky: 0.3,
+            horizon: 0.2,
+            ground: 0.1,
+        });
+        simple_light(&mut chunk, &directional, &ambient);
+        let mut block = chunk.get((3, 3, 3)).unwrap().into_owned();
+        assert_eq!(block.shade(Face::Top), Some(0.3));
+        assert_eq!(block.shade(Face::Bottom), Some(0.1));
+        assert_eq!(block.shade(Face::Left), Some(0.2));
+        assert_eq!(block.shade(Face::Back), Some(0.2));
+
+        ambient.sky = None;
+        simple_light(&mut chunk, &directional, &ambient);
+        let mut block = chunk.get((3, 3, 3)).unwrap().into_owned();
+        for &face in &Face::ALL {
+            assert_eq!(block.shade(face), Some(0.2));
+        }
+    }
+}
@@ -0,0 +1,1520 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::{BitOr, BitOrAssign},
+};
+
+use glam::Vec3;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collections::{lod_tree::Voxel, LodTree},
+    world::{Chunk, Map, VoxelTicks},
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparent {
+    No,
+    Yes,
+}
+
+impl From<bool> for Transparent {
+    fn from(p: bool) -> Self {
+        if p {
+            Self::Yes
+        } else {
+            Self::No
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeshPart {
+    pub positions: Vec<[f32; 3]>,
+    pub shades: Vec<f32>,
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    pub transparent: Transparent,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Face {
+    Top,
+    Bottom,
+    Front,
+    Back,
+    Left,
+    Right,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::Top,
+        Face::Bottom,
+        Face::Front,
+        Face::Back,
+        Face::Left,
+        Face::Right,
+    ];
+
+    /// The direction the face points in.
+    pub fn normal(self) -> (i32, i32, i32) {
+        match self {
+            Face::Top => (0, 1, 0),
+            Face::Bottom => (0, -1, 0),
+            Face::Front => (0, 0, 1),
+            Face::Back => (0, 0, -1),
+            Face::Left => (1, 0, 0),
+            Face::Right => (-1, 0, 0),
+        }
+    }
+
+    /// Returns the face closest to the direction `normal`, e.g. for the diagonal faces of
+    /// plants.
+    pub fn nearest(normal: Vec3) -> Self {
+        let abs = normal.abs();
+        if abs.y() >= abs.x() && abs.y() >= abs.z() {
+            if normal.y() >= 0.0 {
+                Face::Top
+            } else {
+                Face::Bottom
+            }
+        } else if abs.z() >= abs.x() {
+            if normal.z() >= 0.0 {
+                Face::Front
+            } else {
+                Face::Back
+            }
+        } else if normal.x() >= 0.0 {
+            Face::Left
+        } else {
+            Face::Right
+        }
+    }
+
+    /// Returns the face pointing in the axis-aligned direction `normal`.
+    pub fn from_normal(normal: (i32, i32, i32)) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|face| face.normal() == normal)
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Maps the faces of a rotated voxel to the faces of the world they point to.
+///
+/// Lighting calls `VoxelExt::set_shade` with the local face of every world face, so voxels
+/// keep their shades in their own frame and mesh them like unrotated voxels.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FaceMap {
+    world: [Face; 6],
+}
+
+impl Default for FaceMap {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl FaceMap {
+    pub const IDENTITY: Self = Self { world: Face::ALL };
+
+    /// Quarter turns around the y axis, counter-clockwise seen from above.
+    pub fn rotate_y(turns: i32) -> Self {
+        Self::turns(turns, |(x, y, z)| (z, y, -x))
+    }
+
+    /// Quarter turns around the x axis, counter-clockwise seen from the left.
+    pub fn rotate_x(turns: i32) -> Self {
+        Self::turns(turns, |(x, y, z)| (x, -z, y))
+    }
+
+    /// Quarter turns around the z axis, counter-clockwise seen from the front.
+    pub fn rotate_z(turns: i32) -> Self {
+        Self::turns(turns, |(x, y, z)| (-y, x, z))
+    }
+
+    fn turns<F>(turns: i32, turn: F) -> Self
+    where
+        F: Fn((i32, i32, i32)) -> (i32, i32, i32),
+    {
+        let mut world = Face::ALL;
+        for face in &mut world {
+            let mut normal = face.normal();
+            for _ in 0..turns.rem_euclid(4) {
+                normal = turn(normal);
+            }
+            *face = Face::from_normal(normal).unwrap();
+        }
+        Self { world }
+    }
+
+    /// Returns the rotation of `self` followed by `other`.
+    pub fn then(self, other: Self) -> Self {
+        let mut world = self.world;
+        for face in &mut world {
+            *face = other.to_world(*face);
+        }
+        Self { world }
+    }
+
+    /// Returns the world face the `local` face of the voxel points to.
+    pub fn to_world(&self, local: Face) -> Face {
+        self.world[local.index()]
+    }
+
+    /// Returns the local face of the voxel that points to `world`.
+    pub fn to_local(&self, world: Face) -> Face {
+        Face::ALL
+            .iter()
+            .copied()
+            .find(|&local| self.to_world(local) == world)
+            .unwrap()
+    }
+}
+
+/// What the rest of the crate needs to know about a voxel besides its mesh, queried by the
+/// mesher, raycasts and random ticks through `VoxelExt::flags`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct VoxelFlags(u8);
+
+impl VoxelFlags {
+    /// Gets its `VoxelExt::emission` added to the shades of its vertices.
+    pub const EMITS_LIGHT: Self = Self(1);
+    /// A liquid, like water. `Map::raycast_solid` goes through it.
+    pub const FLUID: Self = Self(1 << 1);
+    /// Has holes in it but is otherwise opaque, like leaves, so it's meshed with the opaque
+    /// voxels even if its mesh is transparent, instead of being sorted with them.
+    pub const CUTOUT: Self = Self(1 << 2);
+    /// Nothing bumps into it, like tall grass. `Map::raycast_solid` goes through it.
+    pub const NO_COLLISION: Self = Self(1 << 3);
+    /// Gets random ticks if its type sets `RandomTick::FLAGGED_ONLY`.
+    pub const RANDOM_TICK: Self = Self(1 << 4);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all of `other` is set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether any of `other` is set.
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for VoxelFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for VoxelFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.insert(other);
+    }
+}
+
+/// Implements `VoxelExt::flags` in an `impl VoxelExt` block from a condition per flag, binding
+/// the voxel to the name between the bars.
+///
+/// ```ignore
+/// impl VoxelExt for Water {
+///     voxel_flags!(|water| {
+///         FLUID: true,
+///         RANDOM_TICK: water.level < 8,
+///     });
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! voxel_flags {
+    (|$voxel:ident| { $($flag:ident: $condition:expr),* $(,)? }) => {
+        fn flags(&self) -> $crate::mesh::VoxelFlags {
+            #[allow(unused_variables)]
+            let $voxel = self;
+            let mut flags = $crate::mesh::VoxelFlags::empty();
+            $(
+                if $condition {
+                    flags.insert($crate::mesh::VoxelFlags::$flag);
+                }
+            )*
+            flags
+        }
+    };
+}
+
+pub trait VoxelExt: Voxel {
+    fn mesh(
+        &self,
+        coords: (i32, i32, i32),
+        map: &Map<Self>,
+        chunk: &Chunk<Self>,
+        width: usize,
+    ) -> MeshPart;
+
+    /// Meshes a voxel with an animated change to `target` scheduled in `VoxelTicks`, where
+    /// `progress` goes from `0.0` to `1.0`. Voxels look unchanged until the change is applied
+    /// by default.
+    fn mesh_transition(
+        &self,
+        coords: (i32, i32, i32),
+        map: &Map<Self>,
+        chunk: &Chunk<Self>,
+        width: usize,
+        _target: Option<&Self>,
+        _progress: f32,
+    ) -> MeshPart {
+        self.mesh(coords, map, chunk, width)
+    }
+
+    /// Faces between two transparent voxels are only culled if both are in the same merge
+    /// group, so that e.g. water next to glass keeps its faces.
+    fn merge_group(&self) -> u32 {
+        0
+    }
+
+    /// How much of a sound passing through the voxel is absorbed, from `0.0` to `1.0`. Used
+    /// by `Map::occlusion_between`.
+    fn density(&self) -> f32 {
+        1.0
+    }
+
+    /// Whether `neighbour` hides the face of the voxel it touches, see `VisibilityMask`.
+    /// Faces are never hidden by default.
+    fn face_hidden_by(&self, _neighbour: &Self) -> bool {
+        false
+    }
+
+    /// Light given off by the voxel, added to the shade of its vertices. Emissive voxels get
+    /// colors above `1.0`, which bloom picks up when rendering to an HDR target.
+    fn emission(&self) -> f32 {
+        0.0
+    }
+
+    /// Whether the colors of the voxel are multiplied by the biome tint of its column, see
+    /// `Tint`.
+    fn tinted(&self) -> bool {
+        false
+    }
+
+    /// See `VoxelFlags`, which can be implemented with `voxel_flags!`. Only
+    /// `VoxelFlags::EMITS_LIGHT` is set by default, for voxels with an `emission`.
+    fn flags(&self) -> VoxelFlags {
+        if self.emission() != 0.0 {
+            VoxelFlags::EMITS_LIGHT
+        } else {
+            VoxelFlags::empty()
+        }
+    }
+
+    /// The rotation of the voxel. Lighting maps the world faces it shades through it, so
+    /// `set_shade` and `shade` always get local faces.
+    fn face_map(&self) -> FaceMap {
+        FaceMap::IDENTITY
+    }
+
+    /// Returns a voxel whose looks replace the voxel's own on `face`, depending on the voxels
+    /// around it, e.g. dirt for the sides of grass with more grass on top. Only the looks of
+    /// the returned voxel are used, which faces are drawn still depends on the voxel itself.
+    ///
+    /// Meshers have to ask for it, like the cube mesher of `Block` does. Faces look like the
+    /// voxel by default.
+    fn face_material(&self, _face: Face, _neighbours: &Neighbours<'_, Self>) -> Option<Self> {
+        None
+    }
+
+    fn set_shade(&mut self, _face: Face, _light: f32) {}
+
+    fn shade(&mut self, _face: Face) -> Option<f32> {
+        None
+    }
+}
+
+/// The voxels around a voxel that is being meshed, see `VoxelExt::face_material`.
+pub struct Neighbours<'a, T: Voxel> {
+    map: &'a Map<T>,
+    chunk: &'a Chunk<T>,
+    coords: (i32, i32, i32),
+    width: usize,
+}
+
+impl<'a, T: Voxel> Neighbours<'a, T> {
+    /// Takes the arguments of `VoxelExt::mesh`.
+    pub fn new(
+        map: &'a Map<T>,
+        chunk: &'a Chunk<T>,
+        coords: (i32, i32, i32),
+        width: usize,
+    ) -> Self {
+        Self {
+            map,
+            chunk,
+            coords,
+            width,
+        }
+    }
+
+    /// Returns the voxel `offset` away, in widths of the meshed voxel, e.g. `(0, 1, 0)` for the
+    /// one on top. Voxels in chunks that aren't loaded are `None`.
+    pub fn get(&self, (dx, dy, dz): (i32, i32, i32)) -> Option<Cow<'a, T>> {
+        let w = self.width as i32;
+        let (x, y, z) = self.coords;
+        let local = (x + dx * w, y + dy * w, z + dz * w);
+        let inside = 0..self.chunk.width() as i32;
+        if inside.contains(&local.0) && inside.contains(&local.1) && inside.contains(&local.2) {
+            return self.chunk.sampled_get(local);
+        }
+        let (cx, cy, cz) = self.chunk.position();
+        let world = (cx + local.0, cy + local.1, cz + local.2);
+        let chunk = self.map.chunk_containing(world)?;
+        chunk.sampled_get(chunk.to_local(world))
+    }
+
+    /// Returns the voxel that touches `face` of the meshed voxel.
+    pub fn facing(&self, face: Face) -> Option<Cow<'a, T>> {
+        self.get(face.normal())
+    }
+}
+
+/// Which faces of every voxel of a chunk are visible, one bit per face in `Face::ALL` order.
+///
+/// Meshing and lighting both need to know which neighbours hide the faces of a voxel, so
+/// `update_visibility` works it out once and stores it in the chunk until it is edited.
+/// Faces next to chunks that aren't loaded count as hidden.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisibilityMask {
+    width: usize,
+    masks: Vec<u8>,
+}
+
+impl VisibilityMask {
+    /// Works out the visible faces of every voxel of `chunk` at full detail.
+    pub fn compute<T: VoxelExt>(map: &Map<T>, chunk: &Chunk<T>) -> Self {
+        let _span = span!("VisibilityMask::compute");
+        let width = chunk.width();
+        let w = width as i32;
+        let (cx, cy, cz) = chunk.position();
+        let mut masks = vec![0; width * width * width];
+        for x in 0..w {
+            for y in 0..w {
+                for z in 0..w {
+                    let voxel = match chunk.voxel((x, y, z)) {
+                        Some(voxel) => voxel,
+                        None => continue,
+                    };
+                    let mut mask = 0;
+                    for &face in &Face::ALL {
+                        let (dx, dy, dz) = face.normal();
+                        let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                        let inside = |v: i32| v >= 0 && v < w;
+                        let visible = if inside(nx) && inside(ny) && inside(nz) {
+                            chunk
+                                .voxel((nx, ny, nz))
+                                .map(|other| !voxel.face_hidden_by(other))
+                                .unwrap_or(true)
+                        } else {
+                            let neighbor = (cx + nx, cy + ny, cz + nz);
+                            match map.chunk_containing(neighbor) {
+                                Some(chunk) => chunk
+                                    .voxel(chunk.to_local(neighbor))
+                                    .map(|other| !voxel.face_hidden_by(other))
+                                    .unwrap_or(true),
+                                None => false,
+                            }
+                        };
+                        if visible {
+                            mask |= 1 << face.index();
+                        }
+                    }
+                    masks[((x * w + y) * w + z) as usize] = mask;
+                }
+            }
+        }
+        Self { width, masks }
+    }
+
+    /// Returns the visible faces of the voxel at chunk-local `coords`, `0` for empty voxels.
+    pub fn get(&self, (x, y, z): (i32, i32, i32)) -> u8 {
+        let w = self.width as i32;
+        if x < 0 || y < 0 || z < 0 || x >= w || y >= w || z >= w {
+            return 0;
+        }
+        self.masks[((x * w + y) * w + z) as usize]
+    }
+
+    pub fn is_visible(&self, coords: (i32, i32, i32), face: Face) -> bool {
+        self.get(coords) & (1 << face.index()) != 0
+    }
+}
+
+/// Works out the `VisibilityMask` of the chunk at `position` and stores it in the chunk.
+/// Returns `false` if there is no such chunk.
+///
+/// Run it right before meshing, edits to a chunk and its border clear the mask again.
+pub fn update_visibility<T: VoxelExt>(map: &mut Map<T>, position: (i32, i32, i32)) -> bool {
+    let visibility = match map.get_at_origin(position) {
+        Some(chunk) => VisibilityMask::compute(map, chunk),
+        None => return false,
+    };
+    if let Some(chunk) = map.get_at_origin_mut(position) {
+        chunk.set_visibility(Some(visibility));
+    }
+    true
+}
+
+/// Where the origin of a chunk mesh lies relative to the chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshOrigin {
+    /// Vertices range from `0` to the chunk width.
+    Corner,
+    /// Vertices are centered around the middle of the chunk, which halves their magnitude.
+    Center,
+}
+
+impl Default for MeshOrigin {
+    fn default() -> Self {
+        Self::Corner
+    }
+}
+
+impl MeshOrigin {
+    pub(crate) fn offset(self, width: usize) -> f32 {
+        match self {
+            Self::Corner => 0.0,
+            Self::Center => width as f32 * 0.5,
+        }
+    }
+}
+
+/// Which optional vertex attributes to generate for chunk meshes, so meshes don't carry
+/// attributes no shader reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MeshAttributes {
+    /// Texture coordinates, see `MeshBuffers::generate_attributes`.
+    pub uvs: bool,
+    /// Tangents for normal mapping, pointing along the u axis of the texture coordinates.
+    pub tangents: bool,
+    /// The face every vertex belongs to, for `VoxelMaterial::face_shading`.
+    pub face_ids: bool,
+}
+
+/// The vertex and index buffers of one half of a chunk mesh.
+#[derive(Debug, Clone, Default)]
+pub struct MeshBuffers {
+    pub positions: Vec<[f32; 3]>,
+    pub shades: Vec<f32>,
+    pub colors: Vec<[f32; 4]>,
+    /// The normal of the face every vertex belongs to, worked out from the triangles.
+    pub normals: Vec<[f32; 3]>,
+    /// The part of every shade that comes from `VoxelExt::emission`.
+    pub emissions: Vec<f32>,
+    /// The biome tint of every vertex, white for voxels that aren't tinted.
+    pub tints: Vec<[f32; 3]>,
+    /// The `voxel_random` value of the voxel every vertex belongs to, for shaders to vary
+    /// the colors of otherwise equal voxels. Merged voxels share the value of their first one.
+    pub randoms: Vec<f32>,
+    /// Empty unless generated with `generate_attributes`.
+    pub uvs: Vec<[f32; 2]>,
+    /// Empty unless generated with `generate_attributes`. The `w` component is the sign of
+    /// the bitangent, which is always `-1.0`.
+    pub tangents: Vec<[f32; 4]>,
+    /// Empty unless generated with `generate_attributes`. The index in `Face::ALL` of the
+    /// face nearest to the normal of every vertex.
+    pub face_ids: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshBuffers {
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Scales the color of every vertex by a random factor between `1.0 - strength` and
+    /// `1.0 + strength`, so large areas averaged into one color don't look flat. The factors
+    /// only depend on the vertex positions relative to `seed`, usually the chunk position.
+    pub fn dither(&mut self, seed: (i32, i32, i32), strength: f32) {
+        for (position, color) in self.positions.iter().zip(&mut self.colors) {
+            // positions are at most half a voxel apart
+            let [x, y, z] = *position;
+            let x = (x * 2.0).round() as i32 + seed.0 * 2;
+            let y = (y * 2.0).round() as i32 + seed.1 * 2;
+            let z = (z * 2.0).round() as i32 + seed.2 * 2;
+            let factor = 1.0 + strength * (hash_unit((x, y, z)) * 2.0 - 1.0);
+            for channel in &mut color[..3] {
+                *channel *= factor;
+            }
+        }
+    }
+
+    /// Generates the texture coordinates and tangents requested by `attributes` from the
+    /// positions and normals, so it works for the faces of every mesher.
+    ///
+    /// Every face is projected onto its own plane, one voxel being one unit, so textures line
+    /// up across merged faces and repeat once per voxel. Textures are upright on side faces,
+    /// and on top and bottom faces their u axis is the x axis.
+    pub fn generate_attributes(&mut self, attributes: &MeshAttributes) {
+        self.uvs.clear();
+        self.tangents.clear();
+        self.face_ids.clear();
+        for (position, normal) in self.positions.iter().zip(&self.normals) {
+            let (tangent, bitangent) = face_frame(Vec3::from(*normal));
+            if attributes.uvs {
+                let position = Vec3::from(*position);
+                self.uvs
+                    .push([position.dot(tangent), position.dot(bitangent)]);
+            }
+            if attributes.tangents {
+                self.tangents
+                    .push([tangent.x(), tangent.y(), tangent.z(), -1.0]);
+            }
+            if attributes.face_ids {
+                let face = Face::nearest(Vec3::from(*normal));
+                self.face_ids.push(face.index() as f32);
+            }
+        }
+    }
+
+    /// Welds vertices that are equal in every attribute and drops triangles without area and
+    /// triangles that are there twice, which the meshers emit where merged voxels meet. This
+    /// shrinks the buffers and lets corners shared by faces be shaded once.
+    ///
+    /// Vertices no triangle uses anymore are dropped too.
+    pub fn weld(&mut self) {
+        let mut welded = HashMap::new();
+        // the first vertex of every welded vertex
+        let mut first = Vec::new();
+        let remap = (0..self.positions.len())
+            .map(|i| {
+                *welded.entry(self.vertex_key(i, true)).or_insert_with(|| {
+                    first.push(i);
+                    first.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut triangles = HashSet::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for triangle in self.indices.chunks_exact(3) {
+            let (a, b, c) = (
+                remap[triangle[0] as usize],
+                remap[triangle[1] as usize],
+                remap[triangle[2] as usize],
+            );
+            if a == b || b == c || c == a || triangle_normal(&self.positions, triangle) == [0.0; 3]
+            {
+                continue;
+            }
+            // the same triangle starts at its lowest index, whichever corner it was given from
+            let key = if a < b && a < c {
+                (a, b, c)
+            } else if b < c {
+                (b, c, a)
+            } else {
+                (c, a, b)
+            };
+            if triangles.insert(key) {
+                indices.extend_from_slice(&[a, b, c]);
+            }
+        }
+
+        let mut used = vec![None; first.len()];
+        let mut kept = Vec::new();
+        for index in &mut indices {
+            let new = used[*index as usize].get_or_insert_with(|| {
+                kept.push(first[*index as usize]);
+                kept.len() as u32 - 1
+            });
+            *index = *new;
+        }
+        self.indices = indices;
+        self.retain_vertices(&kept);
+    }
+
+    /// Simplifies the mesh for far levels of detail, where detail smaller than `max_error`
+    /// can't be seen anyway, and welds it, see `weld`.
+    ///
+    /// Vertices are snapped to a grid of the largest power of two up to `max_error`, which
+    /// drops the faces smaller than that, and vertices inside flat areas of one shade are
+    /// collapsed into a neighbour, which merges the triangles of coplanar faces. Chunk widths
+    /// are powers of two, so as long as `max_error` is at most half the chunk width, vertices
+    /// on the border of a chunk stay there and the chunk still meets its neighbours.
+    pub fn simplify(&mut self, max_error: f32) {
+        if max_error >= 1.0 {
+            let cell = 2f32.powi(max_error.log2().floor() as i32);
+            for position in &mut self.positions {
+                for v in position {
+                    *v = (*v / cell).round() * cell;
+                }
+            }
+        }
+        self.weld();
+        while self.collapse_flat_vertices() > 0 {
+            self.weld();
+        }
+    }
+
+    /// Collapses vertices that are surrounded by coplanar triangles into a neighbour with the
+    /// same attributes, returning how many were collapsed. The triangles that lost their area
+    /// are left for `weld` to drop.
+    fn collapse_flat_vertices(&mut self) -> usize {
+        let mut fans = vec![Vec::new(); self.positions.len()];
+        for (t, triangle) in self.indices.chunks_exact(3).enumerate() {
+            for &i in triangle {
+                fans[i as usize].push(t);
+            }
+        }
+        // the positions and texture coordinates change with the collapse anyway
+        let keys = (0..self.positions.len())
+            .map(|i| self.vertex_key(i, false))
+            .collect::<Vec<_>>();
+        // vertices next to a collapsed one have stale fans until the next pass
+        let mut touched = vec![false; self.positions.len()];
+        let mut collapsed = 0;
+        for (v, fan) in fans.iter().enumerate() {
+            if touched[v] || fan.is_empty() {
+                continue;
+            }
+            let triangle = |t: usize| {
+                let corners = &self.indices[t * 3..t * 3 + 3];
+                [corners[0], corners[1], corners[2]]
+            };
+            let normal = triangle_normal(&self.positions, &triangle(fan[0]));
+            let mut edges = BTreeMap::new();
+            let mut flat = true;
+            for &t in fan {
+                let triangle = triangle(t);
+                flat &= same_normal(triangle_normal(&self.positions, &triangle), normal);
+                for &i in triangle.iter().filter(|&&i| i as usize != v) {
+                    *edges.entry(i).or_insert(0) += 1;
+                }
+            }
+            // every edge of a vertex inside the mesh is shared by two triangles
+            if !flat || edges.values().any(|&count| count != 2) {
+                continue;
+            }
+            let collapses_to = |u: u32| {
+                fan.iter().all(|&t| {
+                    let mut triangle = triangle(t);
+                    if triangle.contains(&u) {
+                        return true;
+                    }
+                    for i in &mut triangle {
+                        if *i as usize == v {
+                            *i = u;
+                        }
+                    }
+                    // the triangle must not flip or lose its area
+                    same_normal(triangle_normal(&self.positions, &triangle), normal)
+                })
+            };
+            let target = edges
+                .keys()
+                .copied()
+                .find(|&u| keys[u as usize] == keys[v] && collapses_to(u));
+            if let Some(u) = target {
+                for &t in fan {
+                    for i in &mut self.indices[t * 3..t * 3 + 3] {
+                        if *i as usize == v {
+                            *i = u;
+                        }
+                    }
+                }
+                touched[v] = true;
+                for &i in edges.keys() {
+                    touched[i as usize] = true;
+                }
+                collapsed += 1;
+            }
+        }
+        collapsed
+    }
+
+    /// The bits of every attribute of the vertex `i`, without its position and texture
+    /// coordinates unless `position` is set.
+    fn vertex_key(&self, i: usize, position: bool) -> Vec<u32> {
+        let mut key = Vec::with_capacity(24);
+        let mut push = |values: Option<&[f32]>| {
+            // adding 0.0 turns -0.0 into 0.0, so the two weld
+            key.extend(values.unwrap_or(&[]).iter().map(|v| (v + 0.0).to_bits()));
+        };
+        if position {
+            push(self.positions.get(i).map(|v| &v[..]));
+            push(self.uvs.get(i).map(|v| &v[..]));
+        }
+        push(self.shades.get(i).map(std::slice::from_ref));
+        push(self.colors.get(i).map(|v| &v[..]));
+        push(self.normals.get(i).map(|v| &v[..]));
+        push(self.emissions.get(i).map(std::slice::from_ref));
+        push(self.tints.get(i).map(|v| &v[..]));
+        push(self.randoms.get(i).map(std::slice::from_ref));
+        push(self.tangents.get(i).map(|v| &v[..]));
+        push(self.face_ids.get(i).map(std::slice::from_ref));
+        key
+    }
+
+    /// Keeps the vertices at `kept`, in that order.
+    fn retain_vertices(&mut self, kept: &[usize]) {
+        fn retain<V: Copy>(values: &mut Vec<V>, kept: &[usize]) {
+            if !values.is_empty() {
+                *values = kept.iter().map(|&i| values[i]).collect();
+            }
+        }
+        retain(&mut self.positions, kept);
+        retain(&mut self.shades, kept);
+        retain(&mut self.colors, kept);
+        retain(&mut self.normals, kept);
+        retain(&mut self.emissions, kept);
+        retain(&mut self.tints, kept);
+        retain(&mut self.randoms, kept);
+        retain(&mut self.uvs, kept);
+        retain(&mut self.tangents, kept);
+        retain(&mut self.face_ids, kept);
+    }
+
+    fn push(&mut self, mut part: MeshPart, emission: f32, tint: [f32; 3], random: f32) {
+        let n = self.positions.len() as u32;
+
+        let mut normals = vec![[0.0; 3]; part.positions.len()];
+        for triangle in part.indices.chunks_exact(3) {
+            let normal = triangle_normal(&part.positions, triangle);
+            for &i in triangle {
+                normals[i as usize] = normal;
+            }
+        }
+        part.indices.iter_mut().for_each(|i| *i += n);
+
+        self.emissions
+            .extend(std::iter::repeat(emission).take(part.positions.len()));
+        self.tints
+            .extend(std::iter::repeat(tint).take(part.positions.len()));
+        self.randoms
+            .extend(std::iter::repeat(random).take(part.positions.len()));
+        self.positions.extend(part.positions);
+        self.shades.extend(part.shades);
+        self.colors.extend(part.colors);
+        self.normals.extend(normals);
+        self.indices.extend(part.indices);
+    }
+}
+
+/// Returns the unit normal of a counter-clockwise triangle.
+fn triangle_normal(positions: &[[f32; 3]], triangle: &[u32]) -> [f32; 3] {
+    let vertex = |i: usize| Vec3::from(positions[triangle[i] as usize]);
+    let (a, b, c) = (vertex(0), vertex(1), vertex(2));
+    let normal = (b - a).cross(c - a);
+    if normal.length_squared() > 0.0 {
+        normal.normalize().into()
+    } else {
+        [0.0; 3]
+    }
+}
+
+fn same_normal(a: [f32; 3], b: [f32; 3]) -> bool {
+    Vec3::from(a).dot(Vec3::from(b)) > 0.999
+}
+
+/// Returns the directions in which the texture coordinates of a face with `normal` grow.
+fn face_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let tangent = if normal.y().abs() > 0.99 {
+        Vec3::unit_x()
+    } else {
+        // right when looking at the face, also for the diagonal faces of plants
+        Vec3::unit_y().cross(normal).normalize()
+    };
+    // down on side faces, so that textures aren't upside down
+    (tangent, tangent.cross(normal))
+}
+
+/// A random number between `0.0` and `1.0` that only depends on the world coordinates of a
+/// voxel, so it stays the same across remeshing, lods and sessions without being stored.
+pub fn voxel_random(coords: (i32, i32, i32)) -> f32 {
+    hash_unit(coords)
+}
+
+/// Hashes `coords` to a number between `0.0` and `1.0`.
+fn hash_unit((x, y, z): (i32, i32, i32)) -> f32 {
+    let mut h = (x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((y as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add((z as u32).wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+/// Generates the opaque and transparent vertex buffers of a chunk with vertices relative to
+/// `origin`, or `None` for halves without any faces.
+pub fn generate_chunk_buffers<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+) -> (Option<MeshBuffers>, Option<MeshBuffers>) {
+    chunk_buffers(map, chunk, origin, None)
+}
+
+/// Like `generate_chunk_buffers`, but meshes voxels with animated changes scheduled in
+/// `ticks` with `VoxelExt::mesh_transition`.
+pub fn generate_animated_chunk_buffers<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+    ticks: &VoxelTicks<T>,
+) -> (Option<MeshBuffers>, Option<MeshBuffers>) {
+    chunk_buffers(map, chunk, origin, Some(ticks))
+}
+
+fn chunk_buffers<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+    ticks: Option<&VoxelTicks<T>>,
+) -> (Option<MeshBuffers>, Option<MeshBuffers>) {
+    let _span = span!("generate_chunk_buffers");
+    let offset = origin.offset(chunk.width());
+
+    let mut opaque = MeshBuffers::default();
+    let mut transparent = MeshBuffers::default();
+
+    let (cx, cy, cz) = chunk.position();
+
+    for elem in chunk.iter() {
+        let coords = (elem.x, elem.y, elem.z);
+        let scheduled = ticks
+            .and_then(|ticks| {
+                let scheduled = ticks.get((cx + elem.x, cy + elem.y, cz + elem.z))?;
+                Some((scheduled, ticks.tick()))
+            })
+            .filter(|(scheduled, _)| scheduled.animated);
+        let mesh = if let Some((scheduled, tick)) = scheduled {
+            elem.value.mesh_transition(
+                coords,
+                map,
+                chunk,
+                elem.width,
+                scheduled.voxel.as_ref(),
+                scheduled.progress(tick),
+            )
+        } else {
+            elem.value.mesh(coords, map, chunk, elem.width)
+        };
+
+        push_voxel(
+            chunk,
+            coords,
+            &elem.value,
+            mesh,
+            offset,
+            &mut opaque,
+            &mut transparent,
+        );
+    }
+
+    let opaque = if opaque.is_empty() { None } else { Some(opaque) };
+    let transparent = if transparent.is_empty() {
+        None
+    } else {
+        Some(transparent)
+    };
+
+    (opaque, transparent)
+}
+
+/// Meshes the overlay voxels `overlay` of the chunk at `position` on top of its terrain,
+/// like `generate_chunk_buffers` meshes the terrain.
+///
+/// The overlay voxels replace the terrain voxels at their coordinates, so that their faces
+/// are culled against the terrain around them. Faces towards overlay voxels in other chunks
+/// are only culled if the terrain there hides them.
+pub fn generate_overlay_buffers<T: VoxelExt>(
+    map: &Map<T>,
+    position: (i32, i32, i32),
+    overlay: &LodTree<T>,
+    origin: MeshOrigin,
+) -> (Option<MeshBuffers>, Option<MeshBuffers>) {
+    let _span = span!("generate_overlay_buffers");
+    let mut composite = match map.get_at_origin(position) {
+        Some(chunk) => chunk.clone(),
+        None => Chunk::new(overlay.width().trailing_zeros(), position),
+    };
+    composite.set_lod(0);
+    for elem in overlay.elements() {
+        for x in 0..elem.width as i32 {
+            for y in 0..elem.width as i32 {
+                for z in 0..elem.width as i32 {
+                    let coords = (elem.x + x, elem.y + y, elem.z + z);
+                    composite.insert(coords, elem.value.clone().into_owned());
+                }
+            }
+        }
+    }
+
+    let offset = origin.offset(composite.width());
+    let mut opaque = MeshBuffers::default();
+    let mut transparent = MeshBuffers::default();
+    for elem in overlay.elements() {
+        let coords = (elem.x, elem.y, elem.z);
+        let mesh = elem.value.mesh(coords, map, &composite, elem.width);
+        push_voxel(
+            &composite,
+            coords,
+            &elem.value,
+            mesh,
+            offset,
+            &mut opaque,
+            &mut transparent,
+        );
+    }
+
+    let opaque = if opaque.is_empty() {
+        None
+    } else {
+        Some(opaque)
+    };
+    let transparent = if transparent.is_empty() {
+        None
+    } else {
+        Some(transparent)
+    };
+
+    (opaque, transparent)
+}
+
+/// Adds the emission, tint and random value of `voxel` to its mesh and pushes it into the
+/// buffers it belongs to.
+fn push_voxel<T: VoxelExt>(
+    chunk: &Chunk<T>,
+    (x, y, z): (i32, i32, i32),
+    voxel: &T,
+    mut mesh: MeshPart,
+    offset: f32,
+    opaque: &mut MeshBuffers,
+    transparent: &mut MeshBuffers,
+) {
+    let flags = voxel.flags();
+    let emission = if flags.contains(VoxelFlags::EMITS_LIGHT) {
+        voxel.emission()
+    } else {
+        0.0
+    };
+    if emission != 0.0 {
+        mesh.shades.iter_mut().for_each(|shade| *shade += emission);
+    }
+
+    let tint = if voxel.tinted() {
+        chunk
+            .meta()
+            .and_then(|meta| meta.tint(chunk.width(), (x, z)))
+            .unwrap_or([1.0; 3])
+    } else {
+        [1.0; 3]
+    };
+
+    let (cx, cy, cz) = chunk.position();
+    let random = voxel_random((cx + x, cy + y, cz + z));
+
+    if offset != 0.0 {
+        for position in &mut mesh.positions {
+            position.iter_mut().for_each(|p| *p -= offset);
+        }
+    }
+
+    if mesh.transparent == Transparent::Yes && !flags.contains(VoxelFlags::CUTOUT) {
+        transparent.push(mesh, emission, tint, random);
+    } else {
+        opaque.push(mesh, emission, tint, random);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn face_map() {
+        let turn = FaceMap::rotate_y(1);
+        assert_eq!(turn.to_world(Face::Front), Face::Left);
+        assert_eq!(turn.to_world(Face::Top), Face::Top);
+        assert_eq!(turn.to_local(Face::Left), Face::Front);
+        assert_eq!(FaceMap::rotate_y(4), FaceMap::IDENTITY);
+        assert_eq!(FaceMap::rotate_y(-1), FaceMap::rotate_y(3));
+        assert_eq!(turn.then(turn), FaceMap::rotate_y(2));
+
+        let tilt = FaceMap::rotate_x(1);
+        assert_eq!(tilt.to_world(Face::Top), Face::Front);
+        for &face in &Face::ALL {
+            let rotation = turn.then(tilt);
+            assert_eq!(rotation.to_local(rotation.to_world(face)), face);
+        }
+    }
+
+    #[test]
+    pub fn attributes() {
+        let mut buffers = MeshBuffers::default();
+        // a top face and a front face of the voxel at (1, 2, 3)
+        let top = [
+            [1.0, 3.0, 3.0],
+            [1.0, 3.0, 4.0],
+            [2.0, 3.0, 4.0],
+            [2.0, 3.0, 3.0],
+        ];
+        let front = [
+            [1.0, 2.0, 4.0],
+            [2.0, 2.0, 4.0],
+            [2.0, 3.0, 4.0],
+            [1.0, 3.0, 4.0],
+        ];
+        buffers.positions.extend(top.iter().chain(&front));
+        buffers.normals.extend(vec![[0.0, 1.0, 0.0]; 4]);
+        buffers.normals.extend(vec![[0.0, 0.0, 1.0]; 4]);
+
+        buffers.generate_attributes(&MeshAttributes::default());
+        assert!(buffers.uvs.is_empty());
+        assert!(buffers.tangents.is_empty());
+        assert!(buffers.face_ids.is_empty());
+
+        buffers.generate_attributes(&MeshAttributes {
+            uvs: true,
+            tangents: true,
+            face_ids: true,
+        });
+        assert_eq!(buffers.uvs.len(), 8);
+        assert_eq!(buffers.uvs[0], [1.0, 3.0]);
+        assert_eq!(buffers.uvs[2], [2.0, 4.0]);
+        // upright, so the top of the face has the lower v
+        assert_eq!(buffers.uvs[4], [1.0, -2.0]);
+        assert_eq!(buffers.uvs[6], [2.0, -3.0]);
+        assert_eq!(buffers.tangents[0], [1.0, 0.0, 0.0, -1.0]);
+        assert_eq!(buffers.tangents[4], [1.0, 0.0, 0.0, -1.0]);
+        assert_eq!(buffers.face_ids[0], 0.0);
+        assert_eq!(buffers.face_ids[4], 2.0);
+        assert_eq!(Face::nearest(Vec3::new(-0.7, 0.1, 0.7)), Face::Front);
+    }
+
+    #[test]
+    pub fn weld() {
+        let mut buffers = MeshBuffers::default();
+        // two top faces side by side, as separate quads
+        buffers.positions.extend(&[
+            [0.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [2.0, 1.0, 1.0],
+            [2.0, 1.0, 0.0],
+        ]);
+        buffers.shades.extend(vec![1.0; 8]);
+        buffers.normals.extend(vec![[0.0, 1.0, 0.0]; 8]);
+        buffers
+            .indices
+            .extend(&[0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]);
+        // the first triangle again, starting at another corner
+        buffers.indices.extend(&[1, 2, 0]);
+        // a triangle along an edge and one with a corner twice
+        buffers.indices.extend(&[0, 3, 7, 1, 1, 2]);
+
+        buffers.weld();
+        assert_eq!(buffers.positions.len(), 6);
+        assert_eq!(buffers.shades.len(), 6);
+        assert!(buffers.colors.is_empty());
+        assert_eq!(buffers.indices.len(), 4 * 3);
+        assert_eq!(buffers.indices[..6], [0, 1, 2, 0, 2, 3]);
+        assert_eq!(buffers.indices[6..9], [3, 2, 4]);
+
+        // corners in one place but with different shades aren't welded, the triangle still
+        // has no area
+        let mut buffers = MeshBuffers::default();
+        buffers
+            .positions
+            .extend(&[[0.0; 3], [1.0, 0.0, 0.0], [0.0; 3]]);
+        buffers.shades.extend(&[0.5, 0.5, 1.0]);
+        buffers.indices.extend(&[0, 1, 2]);
+        buffers.weld();
+        assert!(buffers.indices.is_empty());
+        assert!(buffers.is_empty());
+    }
+
+    #[test]
+    pub fn simplify() {
+        // a flat 4 by 4 area of top faces, as separate quads
+        let grid = || {
+            let mut buffers = MeshBuffers::default();
+            for x in 0..4 {
+                for z in 0..4 {
+                    let n = buffers.positions.len() as u32;
+                    let (x, z) = (x as f32, z as f32);
+                    buffers.positions.extend(&[
+                        [x, 1.0, z],
+                        [x, 1.0, z + 1.0],
+                        [x + 1.0, 1.0, z + 1.0],
+                        [x + 1.0, 1.0, z],
+                    ]);
+                    buffers.indices.extend(&[n, n + 1, n + 2, n, n + 2, n + 3]);
+                }
+            }
+            buffers.shades.extend(vec![1.0; 64]);
+            buffers.normals.extend(vec![[0.0, 1.0, 0.0]; 64]);
+            buffers
+        };
+        let area = |buffers: &MeshBuffers| {
+            buffers
+                .indices
+                .chunks_exact(3)
+                .map(|triangle| {
+                    let vertex = |i: usize| Vec3::from(buffers.positions[triangle[i] as usize]);
+                    let normal = (vertex(1) - vertex(0)).cross(vertex(2) - vertex(0));
+                    assert!(normal.y() > 0.0);
+                    normal.length() * 0.5
+                })
+                .sum::<f32>()
+        };
+
+        let mut buffers = grid();
+        buffers.simplify(0.5);
+        // only the 16 vertices around the area are left
+        assert_eq!(buffers.positions.len(), 16);
+        assert_eq!(buffers.indices.len(), 14 * 3);
+        assert_eq!(area(&buffers), 16.0);
+
+        let mut buffers = grid();
+        buffers.simplify(2.0);
+        assert_eq!(buffers.positions.len(), 8);
+        assert_eq!(area(&buffers), 16.0);
+        for position in &buffers.positions {
+            assert!(position[0] % 2.0 == 0.0 && position[2] % 2.0 == 0.0);
+        }
+
+        // a voxel of another shade in the middle keeps its corners
+        let mut buffers = grid();
+        for shade in &mut buffers.shades[20..24] {
+            *shade = 0.5;
+        }
+        buffers.simplify(0.5);
+        assert_eq!(area(&buffers), 16.0);
+        assert!(buffers.positions.len() > 16);
+    }
+
+    #[test]
+    pub fn voxel_random() {
+        let random = super::voxel_random((12, -3, 40));
+        assert!((0.0..=1.0).contains(&random));
+        assert_eq!(super::voxel_random((12, -3, 40)), random);
+        assert_ne!(super::voxel_random((13, -3, 40)), random);
+    }
+
+    // 1 is stone, 2 water, 3 grass and 4 a lamp
+    impl VoxelExt for i32 {
+        fn mesh(
+            &self,
+            (x, y, z): (i32, i32, i32),
+            _map: &Map<Self>,
+            _chunk: &Chunk<Self>,
+            _width: usize,
+        ) -> MeshPart {
+            let (x, y, z) = (x as f32, y as f32, z as f32);
+            MeshPart {
+                positions: vec![[x, y, z], [x + 1.0, y, z], [x, y + 1.0, z]],
+                shades: vec![1.0; 3],
+                colors: vec![[1.0; 4]; 3],
+                indices: vec![0, 1, 2],
+                transparent: if *self == 2 || *self == 3 {
+                    Transparent::Yes
+                } else {
+                    Transparent::No
+                },
+            }
+        }
+
+        fn emission(&self) -> f32 {
+            if *self == 4 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        voxel_flags!(|voxel| {
+            EMITS_LIGHT: voxel.emission() > 0.0,
+            FLUID: *voxel == 2,
+            CUTOUT: *voxel == 3,
+            NO_COLLISION: *voxel == 3,
+        });
+    }
+
+    #[test]
+    pub fn flags() {
+        assert_eq!(4.flags(), VoxelFlags::EMITS_LIGHT);
+        let grass = 3.flags();
+        assert!(grass.contains(VoxelFlags::CUTOUT | VoxelFlags::NO_COLLISION));
+        assert!(!grass.intersects(VoxelFlags::FLUID | VoxelFlags::RANDOM_TICK));
+        assert_eq!(VoxelFlags::from_bits(grass.bits()), grass);
+
+        // grass and water on stone
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((0, 0, 0), 1);
+        chunk.insert((0, 1, 0), 2);
+        chunk.insert((0, 2, 0), 3);
+        chunk.insert((1, 0, 0), 4);
+        let map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let (opaque, transparent) =
+            generate_chunk_buffers(&map, map.get((0, 0, 0)).unwrap(), MeshOrigin::Corner);
+        let (opaque, transparent) = (opaque.unwrap(), transparent.unwrap());
+        // only the water is sorted
+        assert_eq!(transparent.indices.len(), 3);
+        assert_eq!(opaque.indices.len(), 3 * 3);
+        assert_eq!(opaque.emissions.iter().filter(|&&e| e == 1.0).count(), 3);
+
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let hit = map.raycast(Vec3::new(0.5, 3.5, 0.5), down, 8.0).unwrap();
+        assert_eq!(hit.position, (0, 2, 0));
+        let hit = map.raycast_solid(Vec3::new(0.5, 3.5, 0.5), down, 8.0);
+        assert_eq!(hit.unwrap().position, (0, 0, 0));
+    }
+
+    #[test]
+    pub fn mesh_origin() {
+        // a chunk far from the world origin
+        let mut chunk = Chunk::new(2, (1 << 20, 0, 0));
+        chunk.insert((1, 1, 1), 1);
+        let map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let chunk = map.get((1 << 20, 0, 0)).unwrap();
+        let (corner, _) = generate_chunk_buffers(&map, chunk, MeshOrigin::Corner);
+        let (center, _) = generate_chunk_buffers(&map, chunk, MeshOrigin::Center);
+        let (corner, center) = (corner.unwrap(), center.unwrap());
+        assert_eq!(corner.positions.len(), center.positions.len());
+        for (corner, center) in corner.positions.iter().zip(&center.positions) {
+            assert!(center.iter().all(|v| v.abs() <= 2.0));
+            for axis in 0..3 {
+                assert_eq!(center[axis], corner[axis] - 2.0);
+            }
+        }
+
+        // either way the vertices end up in the same place in the world
+        #[cfg(feature = "bevy")]
+        {
+            use crate::render::entity::chunk_translation;
+
+            let corner = chunk_translation(chunk, MeshOrigin::Corner).0;
+            let center = chunk_translation(chunk, MeshOrigin::Center).0;
+            assert_eq!(center - corner, Vec3::new(2.0, 2.0, 2.0));
+        }
+    }
+
+    #[test]
+    pub fn standalone() {
+        use crate::{
+            lighting::{self, DirectionalLight},
+            terrain::{Biome, HeightMap, Layer, Program},
+        };
+        use line_drawing::Bresenham3d;
+
+        // generation, lighting and meshing all work on plain maps, without an app
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .biome(Biome::build().height(4.0).layer(Layer::new(1, 4.0)).build())
+            .build()
+            .unwrap();
+        let chunk = program.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        let mut map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 1.0,
+        };
+        let chunk = map.get_mut((0, 0, 0)).unwrap();
+        lighting::light_map::<_, Bresenham3d<i32>>(chunk, &directional);
+        chunk.swap_light();
+        assert_eq!(chunk.light((0, 7, 0)), Some(1.0));
+        assert_eq!(chunk.light((0, 0, 0)), Some(0.0));
+
+        let chunk = map.get((0, 0, 0)).unwrap();
+        let (opaque, _) = generate_chunk_buffers(&map, chunk, MeshOrigin::Corner);
+        let opaque = opaque.unwrap();
+        assert!(!opaque.positions.is_empty());
+        assert_eq!(opaque.positions.len(), opaque.shades.len());
+    }
+
+    #[test]
+    pub fn emission() {
+        // a stone and a lamp, which shines brighter than full light so bloom can pick it up
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((1, 1, 1), 1);
+        chunk.insert((3, 1, 1), 4);
+        let map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let chunk = map.get((0, 0, 0)).unwrap();
+        let (buffers, _) = generate_chunk_buffers(&map, chunk, MeshOrigin::Corner);
+        let buffers = buffers.unwrap();
+        assert_eq!(buffers.emissions.len(), 6);
+        assert_eq!(buffers.emissions.iter().sum::<f32>(), 3.0);
+        for (&shade, &emission) in buffers.shades.iter().zip(&buffers.emissions) {
+            assert_eq!(shade, 1.0 + emission);
+        }
+    }
+
+    #[test]
+    pub fn dither() {
+        let mut buffers = MeshBuffers::default();
+        for x in 0..16 {
+            buffers.positions.push([x as f32 * 0.5, 1.0, 2.0]);
+            buffers.colors.push([0.5, 0.5, 0.5, 0.8]);
+        }
+        let flat = buffers.clone();
+        buffers.dither((8, 0, 0), 0.0);
+        assert_eq!(buffers.colors, flat.colors);
+
+        buffers.dither((8, 0, 0), 0.2);
+        for color in &buffers.colors {
+            assert!(color[0] >= 0.4 && color[0] <= 0.6);
+            assert_eq!(color[0], color[2]);
+            assert_eq!(color[3], 0.8);
+        }
+        let first = buffers.colors[0][0];
+        assert!(buffers.colors.iter().any(|color| color[0] != first));
+
+        // the same voxels get the same colors when the chunk is meshed again
+        let mut again = flat.clone();
+        again.dither((8, 0, 0), 0.2);
+        assert_eq!(again.colors, buffers.colors);
+        let mut elsewhere = flat;
+        elsewhere.dither((16, 0, 0), 0.2);
+        assert_ne!(elsewhere.colors, buffers.colors);
+    }
+
+    #[test]
+    pub fn brick_storage() {
+        use crate::world::StorageKind;
+
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        for x in 0..4 {
+            for z in 0..4 {
+                chunk.insert((x, 0, z), 1);
+            }
+        }
+        chunk.insert((1, 1, 1), 2);
+        chunk.insert((2, 1, 1), 4);
+        chunk.merge();
+        let mut bricks = chunk.clone();
+        bricks.set_storage(StorageKind::BrickMap);
+
+        let tree_map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let brick_map = Map::try_with_chunks(vec![bricks]).unwrap();
+        let mesh = |map: &Map<i32>| {
+            let (opaque, transparent) =
+                generate_chunk_buffers(map, map.get((0, 0, 0)).unwrap(), MeshOrigin::Corner);
+            let opaque = opaque.unwrap();
+            let mut faces = opaque
+                .indices
+                .chunks(3)
+                .map(|triangle| {
+                    let mut positions = triangle
+                        .iter()
+                        .map(|&i| opaque.positions[i as usize])
+                        .map(|[x, y, z]| (x.to_bits(), y.to_bits(), z.to_bits()))
+                        .collect::<Vec<_>>();
+                    positions.sort_unstable();
+                    positions
+                })
+                .collect::<Vec<_>>();
+            faces.sort_unstable();
+            (faces, transparent.is_some())
+        };
+        assert_eq!(mesh(&brick_map), mesh(&tree_map));
+    }
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn overlay() {
+        use crate::{simple::Block, world::OverlayLayer};
+
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        for x in 0..4 {
+            for z in 0..4 {
+                chunk.insert((x, 0, z), Block::default());
+            }
+        }
+        let mut map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let mut layer = OverlayLayer::new(map.layout().unwrap());
+        layer.insert((1, 1, 1), Block::default());
+        map.insert_overlay(0, layer);
+
+        let overlay = map.overlay(0).unwrap().chunk((0, 0, 0)).unwrap();
+        let (opaque, transparent) =
+            generate_overlay_buffers(&map, (0, 0, 0), overlay, MeshOrigin::Corner);
+        assert!(transparent.is_none());
+        // every face but the one on the ground
+        let opaque = opaque.unwrap();
+        assert_eq!(opaque.indices.len(), 5 * 6);
+        assert_eq!(opaque.randoms.len(), opaque.positions.len());
+        let down = [0.0, -1.0, 0.0];
+        assert!(opaque.normals.iter().all(|&normal| normal != down));
+    }
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn face_material() {
+        use crate::simple::Block;
+        use bevy::prelude::Color;
+
+        let dirt = Color::rgb(0.4, 0.3, 0.1);
+        let grass = Block {
+            color: Color::rgb(0.0, 0.4, 0.3),
+            covered_color: Some(dirt),
+            ..Default::default()
+        };
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((1, 1, 1), grass);
+        chunk.insert((1, 2, 1), grass);
+        let map = Map::try_with_chunks(vec![chunk]).unwrap();
+        let chunk = map.get((0, 0, 0)).unwrap();
+
+        let neighbours = Neighbours::new(&map, chunk, (1, 1, 1), 1);
+        assert_eq!(neighbours.facing(Face::Top).unwrap().color, grass.color);
+        assert!(neighbours.facing(Face::Bottom).is_none());
+        assert!(grass.face_material(Face::Top, &neighbours).is_none());
+
+        // the bottom face keeps the grass color, the four sides are dirt
+        let covered = grass.mesh((1, 1, 1), &map, chunk, 1);
+        let dirt: [f32; 4] = dirt.into();
+        assert_eq!(covered.colors.len(), 20);
+        assert_eq!(covered.colors.iter().filter(|&&c| c == dirt).count(), 16);
+        let top = grass.mesh((1, 2, 1), &map, chunk, 1);
+        assert!(top.colors.iter().all(|&c| c != dirt));
+    }
+}
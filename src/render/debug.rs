@@ -0,0 +1,392 @@
+use bevy::{
+    asset::Handle,
+    ecs::Bundle,
+    prelude::*,
+    render::{
+        camera::{ActiveCameras, Camera},
+        draw::Draw,
+        mesh::Mesh,
+        pipeline::RenderPipelines,
+        render_graph::base::{self, MainPass},
+    },
+    transform::prelude::{Rotation, Scale, Transform, Translation},
+};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    mesh::MeshBuffers,
+    render::{
+        entity::ChunkRenderComponents,
+        highlight::{overlay_mesh, FACES},
+        material::VoxelMaterial,
+        origin::FloatingOrigin,
+    },
+    world::{ChunkPick, ChunkState, ChunkUpdate, Map, MapUpdates, UpdateCause},
+};
+
+/// Draws the edges of every chunk, colored by how far it has made it through the update
+/// pipeline, to make streaming problems visible during development.
+///
+/// The grid is rebuilt every frame, so it shouldn't be left on in release builds.
+#[derive(Debug, Clone)]
+pub struct ChunkGrid {
+    pub line_width: f32,
+    /// Chunks waiting to be generated. These are only drawn once the map has a chunk to take
+    /// the width from.
+    pub generating: Color,
+    /// Chunks waiting for a light map or lighting.
+    pub lighting: Color,
+    /// Chunks waiting for a mesh.
+    pub meshing: Color,
+    /// Meshed chunks with nothing pending.
+    pub done: Color,
+    /// Colors chunks with pending updates by `UpdateCause` instead, if the map tracks causes.
+    pub by_cause: bool,
+}
+
+impl Default for ChunkGrid {
+    fn default() -> Self {
+        Self {
+            line_width: 0.1,
+            generating: Color::rgb(1.0, 0.2, 0.2),
+            lighting: Color::rgb(1.0, 0.8, 0.2),
+            meshing: Color::rgb(0.2, 0.4, 1.0),
+            done: Color::rgb(0.2, 1.0, 0.2),
+            by_cause: false,
+        }
+    }
+}
+
+impl ChunkGrid {
+    /// Returns the color of a chunk in `state`, or without data yet, with `pending` queued.
+    pub fn color(&self, state: Option<ChunkState>, pending: Option<&ChunkUpdate>) -> Color {
+        match (pending, state) {
+            (Some(ChunkUpdate::GenerateChunk), _) | (None, None) => self.generating,
+            (Some(ChunkUpdate::UpdateLightMap), _) | (Some(ChunkUpdate::UpdateLight), _) => {
+                self.lighting
+            }
+            (Some(ChunkUpdate::UpdateMesh), _) => self.meshing,
+            (None, Some(ChunkState::Meshed)) => self.done,
+            (None, Some(ChunkState::Generated)) | (None, Some(ChunkState::LightMapped)) => {
+                self.lighting
+            }
+            (None, Some(ChunkState::Lit)) => self.meshing,
+        }
+    }
+
+    pub fn cause_color(&self, cause: UpdateCause) -> Color {
+        match cause {
+            UpdateCause::Streaming => Color::rgb(0.2, 0.4, 1.0),
+            UpdateCause::Edit => Color::rgb(1.0, 0.2, 0.2),
+            UpdateCause::Dependency => Color::rgb(1.0, 0.5, 0.1),
+            UpdateCause::Pipeline => Color::rgb(0.6, 0.6, 0.6),
+            UpdateCause::Invalidation => Color::rgb(1.0, 0.9, 0.2),
+            UpdateCause::Lod => Color::rgb(0.2, 0.9, 0.9),
+            UpdateCause::Tick => Color::rgb(0.9, 0.3, 0.9),
+            UpdateCause::Unknown => Color::rgb(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn chunk_color(
+        &self,
+        state: Option<ChunkState>,
+        updates: &MapUpdates,
+        coords: (i32, i32, i32),
+    ) -> Color {
+        match updates.cause(coords) {
+            Some(cause) if self.by_cause => self.cause_color(cause),
+            _ => self.color(state, updates.updates.get(&coords)),
+        }
+    }
+}
+
+#[derive(Bundle)]
+pub struct ChunkGridComponents {
+    pub grid: ChunkGrid,
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<VoxelMaterial>,
+    pub main_pass: MainPass,
+    pub draw: Draw,
+    pub render_pipelines: RenderPipelines,
+    pub transform: Transform,
+    pub translation: Translation,
+    pub rotation: Rotation,
+    pub scale: Scale,
+}
+
+impl ChunkGridComponents {
+    pub fn new(grid: ChunkGrid, material: Handle<VoxelMaterial>) -> Self {
+        let components = ChunkRenderComponents::default();
+        Self {
+            grid,
+            mesh: Default::default(),
+            material,
+            main_pass: components.main_pass,
+            draw: components.draw,
+            render_pipelines: components.render_pipelines,
+            transform: components.transform,
+            translation: components.translation,
+            rotation: components.rotation,
+            scale: components.scale,
+        }
+    }
+}
+
+#[derive(Default)]
+struct GridBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl GridBuilder {
+    /// Adds a box from `min` to `max`.
+    fn cuboid(&mut self, min: [f32; 3], max: [f32; 3], color: Color) {
+        for ((nx, ny, nz), corners) in &FACES {
+            let n = self.positions.len() as u32;
+            for corner in corners {
+                let mut position = [0.0; 3];
+                for i in 0..3 {
+                    position[i] = min[i] + corner[i] * (max[i] - min[i]);
+                }
+                self.positions.push(position);
+                self.normals.push([*nx as f32, *ny as f32, *nz as f32]);
+                self.colors.push(color.into());
+            }
+            self.indices.extend(&[n, n + 1, n + 2, n + 2, n + 3, n]);
+        }
+    }
+
+    /// Adds the twelve edges of a chunk at render space position `p`.
+    fn chunk(&mut self, p: Vec3, width: f32, line_width: f32, color: Color) {
+        let h = line_width * 0.5;
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        for &a in &[0.0, width] {
+            for &b in &[0.0, width] {
+                // along x, y and z
+                self.cuboid(
+                    [x - h, y + a - h, z + b - h],
+                    [x + width + h, y + a + h, z + b + h],
+                    color,
+                );
+                self.cuboid(
+                    [x + a - h, y - h, z + b - h],
+                    [x + a + h, y + width + h, z + b + h],
+                    color,
+                );
+                self.cuboid(
+                    [x + a - h, y + b - h, z - h],
+                    [x + a + h, y + b + h, z + width + h],
+                    color,
+                );
+            }
+        }
+    }
+
+    fn build(self) -> Option<Mesh> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        Some(overlay_mesh(MeshBuffers {
+            positions: self.positions,
+            colors: self.colors,
+            normals: self.normals,
+            indices: self.indices,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Generates the grid mesh of a map in render space relative to `origin`.
+pub fn generate_chunk_grid_mesh<T: Voxel>(
+    grid: &ChunkGrid,
+    map: &Map<T>,
+    updates: &MapUpdates,
+    origin: &FloatingOrigin,
+) -> Option<Mesh> {
+    let mut builder = GridBuilder::default();
+    let mut width = None;
+    for chunk in map.iter() {
+        let coords = chunk.position();
+        let color = grid.chunk_color(Some(chunk.state()), updates, coords);
+        width = Some(chunk.width() as f32);
+        builder.chunk(origin.to_local(coords), chunk.width() as f32, grid.line_width, color);
+    }
+    if let Some(width) = width {
+        for &coords in updates.updates.keys() {
+            if map.get_at_origin(coords).is_none() {
+                let color = grid.chunk_color(None, updates, coords);
+                builder.chunk(origin.to_local(coords), width, grid.line_width, color);
+            }
+        }
+    }
+    builder.build()
+}
+
+pub fn chunk_grid_update<T: Voxel>(
+    origin: Res<FloatingOrigin>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut maps: Query<(&Map<T>, &MapUpdates)>,
+    mut grids: Query<(&ChunkGrid, &mut Handle<Mesh>, &mut Draw)>,
+) {
+    let mut maps = maps.iter();
+    let map = (&mut maps).into_iter().next();
+
+    for (grid, mut mesh, mut draw) in &mut grids.iter() {
+        let new_mesh = map
+            .as_ref()
+            .and_then(|(map, updates)| generate_chunk_grid_mesh(grid, map, updates, &origin));
+        match new_mesh {
+            Some(new_mesh) => {
+                if let Some(old_mesh) = meshes.get_mut(&mesh) {
+                    *old_mesh = new_mesh;
+                } else {
+                    *mesh = meshes.add(new_mesh);
+                }
+                draw.is_visible = true;
+            }
+            None => draw.is_visible = false,
+        }
+    }
+}
+
+/// Picks the chunk under the cursor when `key` is pressed and records its state, to find out
+/// why a chunk has holes or looks wrongly lit.
+pub struct ChunkPicker {
+    pub key: KeyCode,
+    pub max_distance: f32,
+    /// Logs every pick.
+    pub log: bool,
+    /// The last pick, `None` if the ray didn't hit anything.
+    pub last: Option<ChunkPick>,
+    cursor: Option<Vec2>,
+    cursor_reader: EventReader<CursorMoved>,
+}
+
+impl Default for ChunkPicker {
+    fn default() -> Self {
+        Self {
+            key: KeyCode::P,
+            max_distance: 256.0,
+            log: true,
+            last: None,
+            cursor: None,
+            cursor_reader: Default::default(),
+        }
+    }
+}
+
+/// Returns the origin and direction in render space of the ray through the pixel at `cursor`
+/// of a window of `size`, seen by a camera with `projection` and `transform`.
+pub fn cursor_ray(cursor: Vec2, size: Vec2, projection: &Mat4, transform: &Mat4) -> (Vec3, Vec3) {
+    let x = cursor.x() / size.x() * 2.0 - 1.0;
+    let y = cursor.y() / size.y() * 2.0 - 1.0;
+    let inverse = *transform * projection.inverse();
+    let unproject = |depth: f32| {
+        let point = inverse * Vec4::new(x, y, depth, 1.0);
+        point.truncate() / point.w()
+    };
+    let near = unproject(0.0);
+    let far = unproject(1.0);
+    (near, far - near)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn chunk_pick_update<T: Voxel>(
+    mut picker: ResMut<ChunkPicker>,
+    keys: Res<Input<KeyCode>>,
+    cursor_events: Res<Events<CursorMoved>>,
+    windows: Res<Windows>,
+    cameras: Res<ActiveCameras>,
+    origin: Res<FloatingOrigin>,
+    mut maps: Query<(&Map<T>, &MapUpdates)>,
+    camera_query: Query<(&Camera, &Transform)>,
+) {
+    if let Some(event) = picker.cursor_reader.iter(&cursor_events).last() {
+        picker.cursor = Some(event.position);
+    }
+    if !keys.just_pressed(picker.key) {
+        return;
+    }
+    let (cursor, window) = match (picker.cursor, windows.get_primary()) {
+        (Some(cursor), Some(window)) => (cursor, window),
+        _ => return,
+    };
+    let camera = match cameras.get(base::camera::CAMERA3D) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let (projection, transform) = match (
+        camera_query.get::<Camera>(camera),
+        camera_query.get::<Transform>(camera),
+    ) {
+        (Ok(camera), Ok(transform)) => (camera.projection_matrix, transform.value),
+        _ => return,
+    };
+    let size = Vec2::new(window.width() as f32, window.height() as f32);
+    let (local, direction) = cursor_ray(cursor, size, &projection, &transform);
+    let (ox, oy, oz) = origin.origin;
+    let start = local + Vec3::new(ox as f32, oy as f32, oz as f32);
+
+    let pick = (&mut maps.iter())
+        .into_iter()
+        .filter_map(|(map, updates)| map.pick(start, direction, picker.max_distance, &updates))
+        .min_by(|a, b| a.hit.distance.partial_cmp(&b.hit.distance).unwrap());
+    if picker.log {
+        match &pick {
+            Some(pick) => log::info!(
+                "picked voxel {:?} in chunk {:?}: {:?} at lod {}, light {:?} (has light: {}), \
+                 pending {:?} ({:?}), prefetched: {}",
+                pick.hit.position,
+                pick.chunk,
+                pick.state,
+                pick.lod,
+                pick.light,
+                pick.has_light,
+                pick.pending,
+                pick.cause,
+                pick.prefetched,
+            ),
+            None => log::info!("nothing under the cursor"),
+        }
+    }
+    picker.last = pick;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Chunk;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    pub fn chunk_grid() {
+        let grid = ChunkGrid::default();
+        let meshing = Some(&ChunkUpdate::UpdateMesh);
+        assert_eq!(grid.color(Some(ChunkState::Lit), meshing), grid.meshing);
+        assert_eq!(grid.color(Some(ChunkState::Meshed), None), grid.done);
+        assert_eq!(grid.color(Some(ChunkState::Generated), None), grid.lighting);
+        assert_eq!(grid.color(None, None), grid.generating);
+
+        // a loaded chunk and one still waiting to be generated
+        let map = Map::try_with_chunks(vec![Chunk::<i32>::new(2, (0, 0, 0))]).unwrap();
+        let mut updates = MapUpdates::default();
+        updates.request((4, 0, 0), ChunkUpdate::GenerateChunk);
+        let origin = FloatingOrigin::default();
+        let mesh = generate_chunk_grid_mesh(&grid, &map, &updates, &origin).unwrap();
+        // twelve edges of six faces of four vertices per chunk
+        let vertices = 12 * 6 * 4;
+        let colors = match &mesh.attributes[2].values {
+            VertexAttributeValues::Float4(colors) => colors,
+            _ => panic!("colors aren't rgba"),
+        };
+        assert_eq!(colors.len(), 2 * vertices);
+        let lighting: [f32; 4] = grid.lighting.into();
+        let generating: [f32; 4] = grid.generating.into();
+        assert!(colors[..vertices].iter().all(|&color| color == lighting));
+        assert!(colors[vertices..].iter().all(|&color| color == generating));
+
+        assert!(generate_chunk_grid_mesh(&grid, &Map::<i32>::new(), &updates, &origin).is_none());
+    }
+}
@@ -12,60 +12,20 @@ use bevy::{
 
 use crate::{
     collections::lod_tree::Voxel,
-    render::{material::VoxelMaterial, render_graph::pipeline},
-    world::{Chunk, Map},
+    mesh::{self, MeshBuffers},
+    render::{lod::LodConfig, material::VoxelMaterial, render_graph::pipeline},
+    world::{Chunk, Map, VoxelTicks},
 };
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Transparent {
-    No,
-    Yes,
-}
-
-impl From<bool> for Transparent {
-    fn from(p: bool) -> Self {
-        if p {
-            Self::Yes
-        } else {
-            Self::No
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct MeshPart {
-    pub positions: Vec<[f32; 3]>,
-    pub shades: Vec<f32>,
-    pub colors: Vec<[f32; 4]>,
-    pub indices: Vec<u32>,
-    pub transparent: Transparent,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Face {
-    Top,
-    Bottom,
-    Front,
-    Back,
-    Left,
-    Right,
-}
-
-pub trait VoxelExt: Voxel {
-    fn mesh(
-        &self,
-        coords: (i32, i32, i32),
-        map: &Map<Self>,
-        chunk: &Chunk<Self>,
-        width: usize,
-    ) -> MeshPart;
-
-    fn set_shade(&mut self, _face: Face, _light: f32) {}
+pub use crate::mesh::{
+    Face, FaceMap, MeshAttributes, MeshOrigin, MeshPart, Transparent, VoxelExt, VoxelFlags,
+};
 
-    fn shade(&mut self, _face: Face) -> Option<f32> {
-        None
-    }
+/// Returns the translation of a chunk's render entities for meshes generated with `origin`.
+pub fn chunk_translation<T: Voxel>(chunk: &Chunk<T>, origin: MeshOrigin) -> Translation {
+    let (x, y, z) = chunk.position();
+    let offset = origin.offset(chunk.width());
+    Translation::new(x as f32 + offset, y as f32 + offset, z as f32 + offset)
 }
 
 #[derive(Bundle)]
@@ -98,6 +58,22 @@ impl Default for ChunkRenderComponents {
                             bind_group: 1,
                             binding: 0,
                         },
+                        DynamicBinding {
+                            bind_group: 1,
+                            binding: 1,
+                        },
+                        DynamicBinding {
+                            bind_group: 1,
+                            binding: 2,
+                        },
+                        DynamicBinding {
+                            bind_group: 1,
+                            binding: 3,
+                        },
+                        DynamicBinding {
+                            bind_group: 1,
+                            binding: 4,
+                        },
                     ],
                     ..Default::default()
                 },
@@ -115,89 +91,147 @@ impl Default for ChunkRenderComponents {
 }
 
 pub fn generate_chunk_mesh<T: VoxelExt>(map: &Map<T>, chunk: &Chunk<T>) -> (Option<Mesh>, Option<Mesh>) {
-    let mut positions = Vec::new();
-    let mut shades = Vec::new();
-    let mut colors = Vec::new();
-    let mut indices = Vec::new();
-    let mut n = 0;
-    
-    let mut t_positions = Vec::new();
-    let mut t_shades = Vec::new();
-    let mut t_colors = Vec::new();
-    let mut t_indices = Vec::new();
-    let mut t_n = 0;
+    generate_chunk_mesh_with(map, chunk, MeshOrigin::Corner)
+}
 
-    for elem in chunk.iter() {
-        let mut mesh = elem
-            .value
-            .mesh((elem.x, elem.y, elem.z), map, chunk, elem.width);
+/// Generates the opaque and transparent meshes of a chunk with vertices relative to `origin`.
+///
+/// The meshes should be placed with `chunk_translation` using the same origin.
+pub fn generate_chunk_mesh_with<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+) -> (Option<Mesh>, Option<Mesh>) {
+    let _span = span!("generate_chunk_mesh");
+    let (opaque, transparent) = mesh::generate_chunk_buffers(map, chunk, origin);
+    (opaque.map(buffers_to_mesh), transparent.map(buffers_to_mesh))
+}
 
-        if mesh.transparent == Transparent::Yes {
-            let count = mesh.positions.len();
-            mesh.indices.iter_mut().for_each(|i| *i += t_n as u32);
-            t_n += count;
+/// Like `generate_chunk_mesh_with`, but simplifies the meshes of far chunks, see
+/// `LodConfig::simplification`, and dithers the colors of chunks with a level of detail above
+/// `0` by `config.dither`.
+pub fn generate_lod_chunk_mesh<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+    config: &LodConfig,
+) -> (Option<Mesh>, Option<Mesh>) {
+    let _span = span!("generate_chunk_mesh");
+    let (mut opaque, mut transparent) = mesh::generate_chunk_buffers(map, chunk, origin);
+    if let Some(max_error) = config.simplification(chunk.lod()) {
+        // larger errors would move the borders of the chunk
+        let max_error = max_error.min(chunk.width() as f32 * 0.5);
+        for buffers in opaque.iter_mut().chain(transparent.iter_mut()) {
+            buffers.simplify(max_error);
+        }
+    }
+    // after simplifying, which only welds vertices of the same color
+    if chunk.lod() > 0 && config.dither > 0.0 {
+        for buffers in opaque.iter_mut().chain(transparent.iter_mut()) {
+            buffers.dither(chunk.position(), config.dither);
+        }
+    }
+    (opaque.map(buffers_to_mesh), transparent.map(buffers_to_mesh))
+}
 
-            t_positions.extend(mesh.positions);
-            t_shades.extend(mesh.shades);
-            t_colors.extend(mesh.colors);
-            t_indices.extend(mesh.indices);
-        } else {
-            let count = mesh.positions.len();
-            mesh.indices.iter_mut().for_each(|i| *i += n as u32);
-            n += count;
+/// Like `generate_chunk_mesh_with`, but also generates the vertex attributes requested by
+/// `attributes`, e.g. for textured or normal-mapped materials.
+pub fn generate_chunk_mesh_with_attributes<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+    attributes: &MeshAttributes,
+) -> (Option<Mesh>, Option<Mesh>) {
+    let _span = span!("generate_chunk_mesh");
+    let (mut opaque, mut transparent) = mesh::generate_chunk_buffers(map, chunk, origin);
+    for buffers in opaque.iter_mut().chain(transparent.iter_mut()) {
+        buffers.generate_attributes(attributes);
+    }
+    (opaque.map(buffers_to_mesh), transparent.map(buffers_to_mesh))
+}
 
-            positions.extend(mesh.positions);
-            shades.extend(mesh.shades);
-            colors.extend(mesh.colors);
-            indices.extend(mesh.indices);
-        }
+/// Like `generate_chunk_mesh_with`, but welds the vertices of the meshes and drops their
+/// degenerate triangles, see `MeshBuffers::weld`.
+pub fn generate_welded_chunk_mesh<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+) -> (Option<Mesh>, Option<Mesh>) {
+    let _span = span!("generate_chunk_mesh");
+    let (mut opaque, mut transparent) = mesh::generate_chunk_buffers(map, chunk, origin);
+    for buffers in opaque.iter_mut().chain(transparent.iter_mut()) {
+        buffers.weld();
     }
+    (opaque.map(buffers_to_mesh), transparent.map(buffers_to_mesh))
+}
 
-    let mesh = if positions.is_empty() {
-        None
-    } else {
-        Some(Mesh {
-            primitive_topology: bevy::render::pipeline::PrimitiveTopology::TriangleList,
-            attributes: vec![
-                bevy::render::mesh::VertexAttribute {
-                    name: From::from("Voxel_Position"),
-                    values: bevy::render::mesh::VertexAttributeValues::Float3(positions),
-                },
-                bevy::render::mesh::VertexAttribute {
-                    name: From::from("Voxel_Shade"),
-                    values: bevy::render::mesh::VertexAttributeValues::Float(shades),
-                },
-                bevy::render::mesh::VertexAttribute {
-                    name: From::from("Voxel_Color"),
-                    values: bevy::render::mesh::VertexAttributeValues::Float4(colors),
-                },
-            ],
-            indices: Some(indices),
-        })
-    };
-    
-    let t_mesh = if t_positions.is_empty() {
-        None
-    } else {
-        Some(Mesh {
-            primitive_topology: bevy::render::pipeline::PrimitiveTopology::TriangleList,
-            attributes: vec![
-                bevy::render::mesh::VertexAttribute {
-                    name: From::from("Voxel_Position"),
-                    values: bevy::render::mesh::VertexAttributeValues::Float3(t_positions),
-                },
-                bevy::render::mesh::VertexAttribute {
-                    name: From::from("Voxel_Shade"),
-                    values: bevy::render::mesh::VertexAttributeValues::Float(t_shades),
-                },
-                bevy::render::mesh::VertexAttribute {
-                    name: From::from("Voxel_Color"),
-                    values: bevy::render::mesh::VertexAttributeValues::Float4(t_colors),
-                },
-            ],
-            indices: Some(t_indices),
-        })
-    };
+/// Like `generate_chunk_mesh_with`, but meshes voxels with animated changes scheduled in
+/// `ticks` with `VoxelExt::mesh_transition`.
+pub fn generate_animated_chunk_mesh<T: VoxelExt>(
+    map: &Map<T>,
+    chunk: &Chunk<T>,
+    origin: MeshOrigin,
+    ticks: &VoxelTicks<T>,
+) -> (Option<Mesh>, Option<Mesh>) {
+    let _span = span!("generate_chunk_mesh");
+    let (opaque, transparent) = mesh::generate_animated_chunk_buffers(map, chunk, origin, ticks);
+    (opaque.map(buffers_to_mesh), transparent.map(buffers_to_mesh))
+}
 
-    (mesh, t_mesh)
+/// Converts vertex buffers into a mesh with the attributes the voxel pipeline expects, plus
+/// `Voxel_Uv`, `Voxel_Tangent` and `Voxel_FaceId` if they were generated.
+pub fn buffers_to_mesh(buffers: MeshBuffers) -> Mesh {
+    let mut mesh = Mesh {
+        primitive_topology: bevy::render::pipeline::PrimitiveTopology::TriangleList,
+        attributes: vec![
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Position"),
+                values: bevy::render::mesh::VertexAttributeValues::Float3(buffers.positions),
+            },
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Shade"),
+                values: bevy::render::mesh::VertexAttributeValues::Float(buffers.shades),
+            },
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Color"),
+                values: bevy::render::mesh::VertexAttributeValues::Float4(buffers.colors),
+            },
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Normal"),
+                values: bevy::render::mesh::VertexAttributeValues::Float3(buffers.normals),
+            },
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Emission"),
+                values: bevy::render::mesh::VertexAttributeValues::Float(buffers.emissions),
+            },
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Tint"),
+                values: bevy::render::mesh::VertexAttributeValues::Float3(buffers.tints),
+            },
+            bevy::render::mesh::VertexAttribute {
+                name: From::from("Voxel_Random"),
+                values: bevy::render::mesh::VertexAttributeValues::Float(buffers.randoms),
+            },
+        ],
+        indices: Some(buffers.indices),
+    };
+    if !buffers.uvs.is_empty() {
+        mesh.attributes.push(bevy::render::mesh::VertexAttribute {
+            name: From::from("Voxel_Uv"),
+            values: bevy::render::mesh::VertexAttributeValues::Float2(buffers.uvs),
+        });
+    }
+    if !buffers.tangents.is_empty() {
+        mesh.attributes.push(bevy::render::mesh::VertexAttribute {
+            name: From::from("Voxel_Tangent"),
+            values: bevy::render::mesh::VertexAttributeValues::Float4(buffers.tangents),
+        });
+    }
+    if !buffers.face_ids.is_empty() {
+        mesh.attributes.push(bevy::render::mesh::VertexAttribute {
+            name: From::from("Voxel_FaceId"),
+            values: bevy::render::mesh::VertexAttributeValues::Float(buffers.face_ids),
+        });
+    }
+    mesh
 }
@@ -0,0 +1,174 @@
+use bevy::{
+    asset::Handle,
+    ecs::Bundle,
+    prelude::*,
+    render::{draw::Draw, mesh::Mesh, pipeline::RenderPipelines, render_graph::base::MainPass},
+    transform::prelude::{Rotation, Scale, Transform, Translation},
+};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    render::{
+        entity::{generate_chunk_mesh_with, ChunkRenderComponents, MeshOrigin, VoxelExt},
+        material::VoxelMaterial,
+        origin::FloatingOrigin,
+    },
+    world::{Chunk, Map},
+};
+
+/// A translucent preview of a voxel, e.g. the block a player is about to place.
+///
+/// Spawn it together with `GhostBlockComponents`, whose material should have `ghost` set.
+#[derive(Debug, Clone)]
+pub struct GhostBlock<T> {
+    voxel: Option<T>,
+    position: Option<(i32, i32, i32)>,
+    dirty: bool,
+}
+
+impl<T> Default for GhostBlock<T> {
+    fn default() -> Self {
+        Self {
+            voxel: None,
+            position: None,
+            dirty: true,
+        }
+    }
+}
+
+impl<T: Voxel> GhostBlock<T> {
+    pub fn new(voxel: T) -> Self {
+        Self {
+            voxel: Some(voxel),
+            ..Default::default()
+        }
+    }
+
+    pub fn voxel(&self) -> Option<&T> {
+        self.voxel.as_ref()
+    }
+
+    pub fn position(&self) -> Option<(i32, i32, i32)> {
+        self.position
+    }
+
+    pub fn set_voxel(&mut self, voxel: Option<T>) {
+        self.voxel = voxel;
+        self.dirty = true;
+    }
+
+    /// Moves the preview, hiding it if `position` is `None`.
+    pub fn set_position(&mut self, position: Option<(i32, i32, i32)>) {
+        self.position = position;
+    }
+
+    /// Moves the preview in front of the voxel targeted by a ray, given in world space.
+    pub fn target<U: Voxel>(&mut self, map: &Map<U>, origin: Vec3, direction: Vec3, max_distance: f32) {
+        self.position = map
+            .raycast(origin, direction, max_distance)
+            .map(|hit| hit.adjacent());
+    }
+}
+
+#[derive(Bundle)]
+pub struct GhostBlockComponents {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<VoxelMaterial>,
+    pub main_pass: MainPass,
+    pub draw: Draw,
+    pub render_pipelines: RenderPipelines,
+    pub transform: Transform,
+    pub translation: Translation,
+    pub rotation: Rotation,
+    pub scale: Scale,
+}
+
+impl GhostBlockComponents {
+    pub fn new(material: Handle<VoxelMaterial>) -> Self {
+        let components = ChunkRenderComponents::default();
+        Self {
+            mesh: Default::default(),
+            material,
+            main_pass: components.main_pass,
+            draw: Draw {
+                is_visible: false,
+                is_transparent: true,
+                ..Default::default()
+            },
+            render_pipelines: components.render_pipelines,
+            transform: components.transform,
+            translation: components.translation,
+            rotation: components.rotation,
+            scale: components.scale,
+        }
+    }
+}
+
+/// Generates the mesh of a single voxel, with its minimum corner at `(1, 1, 1)`.
+fn generate_voxel_mesh<T: VoxelExt>(voxel: T) -> Option<Mesh> {
+    // the voxel is surrounded by empty space inside its chunk, so that no faces get culled
+    let mut chunk = Chunk::new(2, (0, 0, 0));
+    chunk.insert((1, 1, 1), voxel);
+    let (mesh, t_mesh) = generate_chunk_mesh_with(&Map::new(), &chunk, MeshOrigin::Corner);
+    mesh.or(t_mesh)
+}
+
+pub fn ghost_block_update<T: VoxelExt>(
+    origin: Res<FloatingOrigin>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut ghosts: Query<(&mut GhostBlock<T>, &mut Handle<Mesh>, &mut Draw, &mut Translation)>,
+) {
+    for (mut ghost, mut mesh, mut draw, mut translation) in &mut ghosts.iter() {
+        if ghost.dirty {
+            ghost.dirty = false;
+            match ghost.voxel.clone().and_then(generate_voxel_mesh) {
+                Some(new_mesh) => {
+                    if let Some(old_mesh) = meshes.get_mut(&mesh) {
+                        *old_mesh = new_mesh;
+                    } else {
+                        *mesh = meshes.add(new_mesh);
+                    }
+                }
+                None => ghost.voxel = None,
+            }
+        }
+
+        match (ghost.position, &ghost.voxel) {
+            (Some(position), Some(_)) => {
+                translation.0 = origin.to_local(position) - Vec3::one();
+                draw.is_visible = true;
+            }
+            _ => draw.is_visible = false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::MapUpdates;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    pub fn ghost_block() {
+        let mut map = Map::try_with_chunks(vec![Chunk::new(2, (0, 0, 0))]).unwrap();
+        map.set_voxel((3, 0, 0), Some(1), &mut MapUpdates::default());
+
+        // the preview goes in front of the face the ray hits
+        let mut ghost = GhostBlock::new(1);
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+        assert_eq!(ghost.position(), None);
+        ghost.target(&map, origin, Vec3::new(1.0, 0.0, 0.0), 10.0);
+        assert_eq!(ghost.position(), Some((2, 0, 0)));
+        ghost.target(&map, origin, Vec3::new(-1.0, 0.0, 0.0), 10.0);
+        assert_eq!(ghost.position(), None);
+
+        let mesh = generate_voxel_mesh(1).unwrap();
+        match &mesh.attributes[0].values {
+            VertexAttributeValues::Float3(positions) => {
+                assert_eq!(positions[0], [1.0, 1.0, 1.0])
+            }
+            _ => panic!("positions aren't vectors"),
+        }
+    }
+}
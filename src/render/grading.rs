@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+
+use crate::{collections::lod_tree::Voxel, render::material::VoxelMaterial, world::Map};
+
+/// A color grade applied to every chunk of a map in the fragment shader, so that the same
+/// blocks can look different in another dimension, e.g. tinted red and desaturated, without
+/// defining them twice or remeshing anything.
+///
+/// Add it to the map's entity, `color_grading_update` copies it to the materials of the map's
+/// chunks. Set it back to the default rather than removing it to undo the grade.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorGrading {
+    /// Multiplies the lit color of every fragment.
+    pub multiply: Color,
+    /// Added to the color after `multiply`.
+    pub offset: Color,
+    /// `0.0` for grayscale, `1.0` to keep the colors, more to make them more vivid. Applied
+    /// before `multiply`.
+    pub saturation: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            multiply: Color::WHITE,
+            offset: Color::rgba(0.0, 0.0, 0.0, 0.0),
+            saturation: 1.0,
+        }
+    }
+}
+
+impl ColorGrading {
+    /// Multiplies every color by `tint`.
+    pub fn tint(tint: Color) -> Self {
+        Self {
+            multiply: tint,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the grade leaves every color as it is, so the shader can skip it.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether `material` is already graded by `self`.
+    pub fn is_applied(&self, material: &VoxelMaterial) -> bool {
+        material.color_grading != self.is_identity()
+            && material.grading_multiply == self.multiply
+            && material.grading_offset == self.offset
+            && material.grading_saturation == self.saturation
+    }
+
+    pub fn apply(&self, material: &mut VoxelMaterial) {
+        material.color_grading = !self.is_identity();
+        material.grading_multiply = self.multiply;
+        material.grading_offset = self.offset;
+        material.grading_saturation = self.saturation;
+    }
+}
+
+/// Copies the `ColorGrading` of every map to the materials of its chunks, only touching the
+/// materials that are stale.
+pub fn color_grading_update<T: Voxel>(
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut maps: Query<(&Map<T>, &ColorGrading)>,
+    handles: Query<&Handle<VoxelMaterial>>,
+) {
+    for (map, grading) in &mut maps.iter() {
+        let entities = map
+            .iter()
+            .flat_map(|chunk| chunk.entity().into_iter().chain(chunk.transparent_entity()));
+        for e in entities {
+            let handle = match handles.get::<Handle<VoxelMaterial>>(e) {
+                Ok(handle) => *handle,
+                Err(_) => continue,
+            };
+            // only stale materials are borrowed mutably, so the others aren't re-uploaded
+            let stale = match materials.get(&handle) {
+                Some(material) => !grading.is_applied(material),
+                None => false,
+            };
+            if !stale {
+                continue;
+            }
+            if let Some(material) = materials.get_mut(&handle) {
+                grading.apply(material);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn color_grading() {
+        let mut material = VoxelMaterial::default();
+        assert!(ColorGrading::default().is_identity());
+        assert!(ColorGrading::default().is_applied(&material));
+
+        let nether = ColorGrading {
+            saturation: 0.5,
+            ..ColorGrading::tint(Color::rgb(1.0, 0.4, 0.3))
+        };
+        assert!(!nether.is_identity());
+        assert!(!nether.is_applied(&material));
+        nether.apply(&mut material);
+        assert!(nether.is_applied(&material));
+        assert!(material.color_grading);
+        assert_eq!(material.grading_multiply, Color::rgb(1.0, 0.4, 0.3));
+        assert_eq!(material.grading_saturation, 0.5);
+
+        // going back to the default turns the grade off in the shader
+        ColorGrading::default().apply(&mut material);
+        assert!(!material.color_grading);
+        assert!(!nether.is_applied(&material));
+    }
+}
@@ -0,0 +1,347 @@
+use bevy::{
+    asset::Handle,
+    ecs::Bundle,
+    prelude::*,
+    render::{draw::Draw, mesh::Mesh, pipeline::RenderPipelines, render_graph::base::MainPass},
+    transform::prelude::{Rotation, Scale, Transform, Translation},
+};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    mesh::MeshBuffers,
+    render::{
+        entity::{buffers_to_mesh, ChunkRenderComponents},
+        material::VoxelMaterial,
+        origin::FloatingOrigin,
+    },
+    world::{ChunkUpdate, Map, MapUpdates},
+};
+
+/// How far the overlay is pushed outwards to avoid z-fighting with the voxels below.
+const INFLATE: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighlightShape {
+    /// A box around the whole region.
+    Box,
+    /// The outer faces of all voxels inside the region.
+    Voxels,
+}
+
+/// Highlights a region of voxels with an overlay mesh, e.g. a selection box or the
+/// footprint of a building preview.
+///
+/// The region spans from `min` to `max`, both inclusive, in world coordinates.
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    min: (i32, i32, i32),
+    max: (i32, i32, i32),
+    color: Color,
+    shape: HighlightShape,
+    dirty: bool,
+}
+
+impl Highlight {
+    pub fn new(a: (i32, i32, i32), b: (i32, i32, i32), color: Color, shape: HighlightShape) -> Self {
+        let mut highlight = Self {
+            min: (0, 0, 0),
+            max: (0, 0, 0),
+            color,
+            shape,
+            dirty: true,
+        };
+        highlight.set_region(a, b);
+        highlight
+    }
+
+    pub fn min(&self) -> (i32, i32, i32) {
+        self.min
+    }
+
+    pub fn max(&self) -> (i32, i32, i32) {
+        self.max
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn shape(&self) -> HighlightShape {
+        self.shape
+    }
+
+    pub fn set_region(&mut self, a: (i32, i32, i32), b: (i32, i32, i32)) {
+        self.min = (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2));
+        self.max = (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2));
+        self.dirty = true;
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+        self.dirty = true;
+    }
+
+    pub fn set_shape(&mut self, shape: HighlightShape) {
+        self.shape = shape;
+        self.dirty = true;
+    }
+
+    pub fn contains(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        x >= self.min.0
+            && y >= self.min.1
+            && z >= self.min.2
+            && x <= self.max.0
+            && y <= self.max.1
+            && z <= self.max.2
+    }
+
+    fn intersects(&self, (x, y, z): (i32, i32, i32), width: i32) -> bool {
+        x <= self.max.0
+            && y <= self.max.1
+            && z <= self.max.2
+            && x + width > self.min.0
+            && y + width > self.min.1
+            && z + width > self.min.2
+    }
+}
+
+#[derive(Bundle)]
+pub struct HighlightComponents {
+    pub highlight: Highlight,
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<VoxelMaterial>,
+    pub main_pass: MainPass,
+    pub draw: Draw,
+    pub render_pipelines: RenderPipelines,
+    pub transform: Transform,
+    pub translation: Translation,
+    pub rotation: Rotation,
+    pub scale: Scale,
+}
+
+impl HighlightComponents {
+    pub fn new(highlight: Highlight, material: Handle<VoxelMaterial>) -> Self {
+        let components = ChunkRenderComponents::default();
+        Self {
+            highlight,
+            mesh: Default::default(),
+            material,
+            main_pass: components.main_pass,
+            draw: Draw {
+                is_transparent: true,
+                ..Default::default()
+            },
+            render_pipelines: components.render_pipelines,
+            transform: components.transform,
+            translation: components.translation,
+            rotation: components.rotation,
+            scale: components.scale,
+        }
+    }
+}
+
+/// The faces of a unit cube, as their normal and corners.
+pub(crate) const FACES: [((i32, i32, i32), [[f32; 3]; 4]); 6] = [
+    ((0, 1, 0), [[1.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0]]),
+    ((0, -1, 0), [[1.0, 0.0, 1.0], [0.0, 0.0, 1.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]),
+    ((0, 0, 1), [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]]),
+    ((0, 0, -1), [[0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 0.0]]),
+    ((-1, 0, 0), [[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]]),
+    ((1, 0, 0), [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 1.0]]),
+];
+
+/// Fills in the attributes of overlay `buffers` with positions, normals and colors, so that
+/// the voxel shaders draw them at full brightness, regardless of the light around them.
+pub(crate) fn overlay_mesh(mut buffers: MeshBuffers) -> Mesh {
+    let count = buffers.positions.len();
+    buffers.shades = vec![1.0; count];
+    // all of the shade is emitted, so neither the sun nor the ambient light darken it
+    buffers.emissions = vec![1.0; count];
+    buffers.tints = vec![[1.0; 3]; count];
+    // the middle of the color variation keeps the color as it is
+    buffers.randoms = vec![0.5; count];
+    buffers_to_mesh(buffers)
+}
+
+#[derive(Default)]
+struct OverlayBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl OverlayBuilder {
+    fn face(&mut self, offset: [f32; 3], size: [f32; 3], (normal, corners): &((i32, i32, i32), [[f32; 3]; 4])) {
+        let n = self.positions.len() as u32;
+        let normal = [normal.0 as f32, normal.1 as f32, normal.2 as f32];
+        for corner in corners {
+            let mut position = [0.0; 3];
+            for i in 0..3 {
+                position[i] = offset[i] + corner[i] * size[i] + normal[i] * INFLATE;
+            }
+            self.positions.push(position);
+            self.normals.push(normal);
+        }
+        self.indices.extend(&[n, n + 1, n + 2, n + 2, n + 3, n]);
+    }
+
+    fn build(self, color: Color) -> Option<Mesh> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        let count = self.positions.len();
+        Some(overlay_mesh(MeshBuffers {
+            positions: self.positions,
+            colors: vec![color.into(); count],
+            normals: self.normals,
+            indices: self.indices,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Generates the overlay mesh of a highlight, relative to its minimum corner.
+pub fn generate_highlight_mesh<T: Voxel>(highlight: &Highlight, map: Option<&Map<T>>) -> Option<Mesh> {
+    let mut builder = OverlayBuilder::default();
+    let (x0, y0, z0) = highlight.min;
+    let (x1, y1, z1) = highlight.max;
+    match highlight.shape {
+        HighlightShape::Box => {
+            let size = [(x1 - x0 + 1) as f32, (y1 - y0 + 1) as f32, (z1 - z0 + 1) as f32];
+            for face in &FACES {
+                builder.face([0.0; 3], size, face);
+            }
+        }
+        HighlightShape::Voxels => {
+            let map = map?;
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    for z in z0..=z1 {
+                        if map.voxel((x, y, z)).is_none() {
+                            continue;
+                        }
+                        let offset = [(x - x0) as f32, (y - y0) as f32, (z - z0) as f32];
+                        for face in &FACES {
+                            let (nx, ny, nz) = face.0;
+                            let neighbor = (x + nx, y + ny, z + nz);
+                            if highlight.contains(neighbor) && map.voxel(neighbor).is_some() {
+                                continue;
+                            }
+                            builder.face(offset, [1.0; 3], face);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    builder.build(highlight.color)
+}
+
+pub fn highlight_update<T: Voxel>(
+    origin: Res<FloatingOrigin>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut maps: Query<(&Map<T>, &MapUpdates)>,
+    mut highlights: Query<(&mut Highlight, &mut Handle<Mesh>, &mut Draw, &mut Translation)>,
+) {
+    let mut maps = maps.iter();
+    let map = (&mut maps).into_iter().next();
+
+    for (mut highlight, mut mesh, mut draw, mut translation) in &mut highlights.iter() {
+        translation.0 = origin.to_local(highlight.min);
+
+        // voxel overlays follow the content of the chunks they cover
+        if let (Some((map, update)), HighlightShape::Voxels) = (&map, highlight.shape) {
+            if !highlight.dirty {
+                highlight.dirty = update.updates.iter().any(|(&coords, update)| {
+                    *update == ChunkUpdate::UpdateMesh
+                        && map
+                            .get_at_origin(coords)
+                            .map(|chunk| highlight.intersects(coords, chunk.width() as i32))
+                            .unwrap_or(false)
+                });
+            }
+        }
+
+        if !highlight.dirty {
+            continue;
+        }
+        highlight.dirty = false;
+
+        match generate_highlight_mesh(&highlight, map.as_ref().map(|(map, _)| &**map)) {
+            Some(new_mesh) => {
+                if let Some(old_mesh) = meshes.get_mut(&mesh) {
+                    *old_mesh = new_mesh;
+                } else {
+                    *mesh = meshes.add(new_mesh);
+                }
+                draw.is_visible = true;
+            }
+            None => draw.is_visible = false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Chunk;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    pub fn overlay_attributes() {
+        let highlight = Highlight::new((0, 0, 0), (1, 2, 3), Color::RED, HighlightShape::Box);
+        let mesh = generate_highlight_mesh::<i32>(&highlight, None).unwrap();
+        // every input of the voxel vertex shader
+        let names = mesh
+            .attributes
+            .iter()
+            .map(|attribute| attribute.name.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "Voxel_Position",
+                "Voxel_Shade",
+                "Voxel_Color",
+                "Voxel_Normal",
+                "Voxel_Emission",
+                "Voxel_Tint",
+                "Voxel_Random",
+            ]
+        );
+        for attribute in &mesh.attributes {
+            assert_eq!(attribute.values.len(), 24);
+        }
+        match &mesh.attributes[3].values {
+            VertexAttributeValues::Float3(normals) => assert_eq!(normals[0], [0.0, 1.0, 0.0]),
+            _ => panic!("normals aren't vectors"),
+        }
+    }
+
+    #[test]
+    pub fn highlight_region() {
+        let mut highlight = Highlight::new((2, -1, 0), (0, 1, 0), Color::RED, HighlightShape::Box);
+        assert_eq!(highlight.min(), (0, -1, 0));
+        assert_eq!(highlight.max(), (2, 1, 0));
+        assert!(highlight.contains((1, 0, 0)));
+        assert!(!highlight.contains((1, 0, 1)));
+
+        let mut map = Map::try_with_chunks(vec![Chunk::new(2, (0, 0, 0))]).unwrap();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((0, 0, 0), Some(1), &mut updates);
+        map.set_voxel((1, 0, 0), Some(1), &mut updates);
+
+        // only the outline of the voxels inside the region is drawn
+        highlight.set_shape(HighlightShape::Voxels);
+        highlight.set_region((0, 0, 0), (1, 0, 0));
+        let mesh = generate_highlight_mesh(&highlight, Some(&map)).unwrap();
+        assert_eq!(mesh.attributes[0].values.len(), 10 * 4);
+        highlight.set_region((0, 0, 0), (0, 0, 0));
+        let mesh = generate_highlight_mesh(&highlight, Some(&map)).unwrap();
+        assert_eq!(mesh.attributes[0].values.len(), 6 * 4);
+
+        highlight.set_region((2, 0, 0), (3, 3, 3));
+        assert!(generate_highlight_mesh(&highlight, Some(&map)).is_none());
+        assert!(generate_highlight_mesh::<i32>(&highlight, None).is_none());
+    }
+}
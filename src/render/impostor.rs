@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use bevy::{
+    prelude::*,
+    render::{camera::ActiveCameras, draw::Draw, render_graph::base},
+    transform::prelude::{Rotation, Translation},
+};
+
+use crate::{
+    mesh::{self, MeshBuffers},
+    render::{
+        entity::{buffers_to_mesh, ChunkRenderComponents, MeshOrigin, VoxelExt},
+        material::VoxelMaterial,
+        origin::FloatingOrigin,
+    },
+    world::{Chunk, Map},
+};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImpostorConfig {
+    /// The horizontal distance from the camera beyond which whole chunk columns are drawn as
+    /// impostors instead of their meshes. It should be past the lods whose meshes are still
+    /// worth drawing, see `LodConfig`.
+    pub distance: i32,
+    /// How far past `distance` the camera has to move before a column switches between its
+    /// meshes and its impostor, so that columns near the boundary don't keep switching.
+    pub hysteresis: i32,
+}
+
+impl Default for ImpostorConfig {
+    fn default() -> Self {
+        Self {
+            distance: 1024,
+            hysteresis: 32,
+        }
+    }
+}
+
+impl ImpostorConfig {
+    /// Returns whether a column at `distance` from the camera should be drawn as an impostor,
+    /// `current` being whether it is one now.
+    pub fn is_impostor(&self, distance: i32, current: bool) -> bool {
+        if current {
+            distance > self.distance - self.hysteresis
+        } else {
+            distance > self.distance + self.hysteresis
+        }
+    }
+}
+
+/// The colors a chunk column is seen with from afar, baked from the meshes of its chunks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImpostorCapture {
+    /// The average color of the faces looking up, or of the sides if there are none.
+    pub top: [f32; 4],
+    /// The average color of the faces looking sideways, or of the top if there are none.
+    pub side: [f32; 4],
+    /// The average shade of all faces.
+    pub shade: f32,
+    /// The lowest and highest world heights of the faces.
+    pub heights: (f32, f32),
+}
+
+impl ImpostorCapture {
+    /// Bakes the colors of the column of `chunks`, weighting every triangle of their meshes
+    /// by its area. Returns `None` if the chunks have no faces.
+    pub fn bake<T: VoxelExt>(map: &Map<T>, chunks: &[&Chunk<T>]) -> Option<Self> {
+        // the color and shade times the area, and the area, of the top and side faces
+        let mut top = ([0.0; 4], 0.0, 0.0);
+        let mut side = ([0.0; 4], 0.0, 0.0);
+        let mut heights = (f32::INFINITY, f32::NEG_INFINITY);
+        for chunk in chunks {
+            let (opaque, transparent) =
+                mesh::generate_chunk_buffers(map, chunk, MeshOrigin::Corner);
+            let y = chunk.position().1 as f32;
+            for buffers in opaque.iter().chain(transparent.iter()) {
+                for triangle in buffers.indices.chunks_exact(3) {
+                    let corner = |i: usize| triangle[i] as usize;
+                    let position = |i: usize| Vec3::from(buffers.positions[corner(i)]);
+                    let normal = (position(1) - position(0)).cross(position(2) - position(0));
+                    let area = normal.length() * 0.5;
+                    if area == 0.0 {
+                        continue;
+                    }
+                    let sum = if normal.y() > normal.length() * 0.5 {
+                        &mut top
+                    } else if normal.y().abs() < normal.length() * 0.5 {
+                        &mut side
+                    } else {
+                        continue;
+                    };
+                    for i in 0..3 {
+                        let color = buffers.colors[corner(i)];
+                        for (sum, channel) in sum.0.iter_mut().zip(&color) {
+                            *sum += channel * area / 3.0;
+                        }
+                        sum.1 += buffers.shades[corner(i)] * area / 3.0;
+                        heights.0 = heights.0.min(y + position(i).y());
+                        heights.1 = heights.1.max(y + position(i).y());
+                    }
+                    sum.2 += area;
+                }
+            }
+        }
+        let average = |(color, _, area): ([f32; 4], f32, f32)| {
+            if area > 0.0 {
+                // impostors are drawn opaque, also for the tops of water
+                Some([color[0] / area, color[1] / area, color[2] / area, 1.0])
+            } else {
+                None
+            }
+        };
+        let (top_color, side_color) = (average(top), average(side));
+        let area = top.2 + side.2;
+        Some(Self {
+            top: top_color.or(side_color)?,
+            side: side_color.or(top_color)?,
+            shade: (top.1 + side.1) / area,
+            heights,
+        })
+    }
+
+    /// Generates a billboard `width` wide, facing along `+z` and standing on the origin, with
+    /// the side color at the bottom and the top color at the top.
+    pub fn buffers(&self, width: f32) -> MeshBuffers {
+        let (bottom, top) = self.heights;
+        let height = (top - bottom).max(1.0);
+        let half = width * 0.5;
+        let positions = vec![
+            [-half, 0.0, 0.0],
+            [half, 0.0, 0.0],
+            [half, height, 0.0],
+            [-half, height, 0.0],
+        ];
+        MeshBuffers {
+            positions,
+            shades: vec![self.shade; 4],
+            colors: vec![self.side, self.side, self.top, self.top],
+            normals: vec![[0.0, 0.0, 1.0]; 4],
+            emissions: vec![0.0; 4],
+            tints: vec![[1.0; 3]; 4],
+            randoms: vec![0.0; 4],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Impostor {
+    entity: Entity,
+    /// How many chunks the column had when it was baked, to bake it again once more are
+    /// generated.
+    chunks: usize,
+    visible: bool,
+}
+
+/// The impostors of the chunk columns of a map beyond `ImpostorConfig::distance`.
+///
+/// Add it to the map's entity to have `impostor_update` draw far columns as camera-facing
+/// billboards and hide their chunks. Columns are baked when they first move out of range, or
+/// when chunks are added to them, so edits to far chunks only show once they come closer.
+#[derive(Default)]
+pub struct Impostors {
+    material: Option<Handle<VoxelMaterial>>,
+    columns: HashMap<(i32, i32), Impostor>,
+}
+
+impl Impostors {
+    pub fn with_material(mut self, material: Handle<VoxelMaterial>) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    /// Returns whether the column of chunks at `(x, z)` is drawn as an impostor.
+    pub fn is_impostor(&self, column: (i32, i32)) -> bool {
+        matches!(self.columns.get(&column), Some(impostor) if impostor.visible)
+    }
+
+    /// The number of columns drawn as impostors.
+    pub fn len(&self) -> usize {
+        self.columns
+            .values()
+            .filter(|impostor| impostor.visible)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Swaps the meshes of chunk columns for their impostors as they move out of
+/// `ImpostorConfig::distance`, and back as they come closer. Add it after the system that
+/// meshes chunks, since that shows their render entities.
+#[allow(clippy::too_many_arguments)]
+pub fn impostor_update<T: VoxelExt>(
+    mut commands: Commands,
+    camera: Res<ActiveCameras>,
+    origin: Res<FloatingOrigin>,
+    config: Res<ImpostorConfig>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut maps: Query<(&Map<T>, &mut Impostors)>,
+    translations: Query<&mut Translation>,
+    rotations: Query<&mut Rotation>,
+    entities: Query<(&Handle<Mesh>, &mut Draw)>,
+) {
+    let camera = camera
+        .get(base::camera::CAMERA3D)
+        .and_then(|camera| translations.get::<Translation>(camera).ok())
+        .map(|translation| translation.0);
+    let camera = match camera {
+        Some(camera) => camera,
+        None => return,
+    };
+    let (camera_x, _, camera_z) = origin.to_world(camera);
+    for (map, mut impostors) in &mut maps.iter() {
+        let mut columns = HashMap::<_, Vec<_>>::new();
+        for chunk in map.iter() {
+            let (x, _, z) = chunk.position();
+            columns.entry((x, z)).or_default().push(chunk);
+        }
+        let material = *impostors
+            .material
+            .get_or_insert_with(|| materials.add(VoxelMaterial::default()));
+
+        // columns that were unloaded
+        let gone = impostors
+            .columns
+            .keys()
+            .filter(|column| !columns.contains_key(column))
+            .copied()
+            .collect::<Vec<_>>();
+        for column in gone {
+            let impostor = impostors.columns.remove(&column).unwrap();
+            if let Ok(mesh) = entities.get::<Handle<Mesh>>(impostor.entity) {
+                meshes.remove(&mesh);
+            }
+            commands.despawn(impostor.entity);
+        }
+
+        for (&(x, z), chunks) in &columns {
+            let width = chunks[0].width() as i32;
+            let distance = (camera_x - x).abs().max((camera_z - z).abs());
+            let current = impostors.is_impostor((x, z));
+            let far = config.is_impostor(distance, current);
+            let chunk_entities = chunks
+                .iter()
+                .flat_map(|chunk| chunk.entity().into_iter().chain(chunk.transparent_entity()));
+            if !far {
+                if current {
+                    // the chunks were hidden by this system, so they are shown again
+                    for e in chunk_entities {
+                        if let Ok(mut draw) = entities.get_mut::<Draw>(e) {
+                            draw.is_visible = true;
+                        }
+                    }
+                    let impostor = impostors.columns.get_mut(&(x, z)).unwrap();
+                    impostor.visible = false;
+                    if let Ok(mut draw) = entities.get_mut::<Draw>(impostor.entity) {
+                        draw.is_visible = false;
+                    }
+                }
+                continue;
+            }
+
+            // turn the billboard around the y axis to face the camera
+            let facing = camera - origin.to_local((x + width / 2, 0, z + width / 2));
+            let rotation = Rotation(Quat::from_rotation_y(facing.x().atan2(facing.z())));
+            let stale = !matches!(
+                impostors.columns.get(&(x, z)),
+                Some(impostor) if impostor.chunks == chunks.len() && current
+            );
+            if stale {
+                let capture = match ImpostorCapture::bake(map, chunks) {
+                    Some(capture) => capture,
+                    // nothing to draw, e.g. a column of air
+                    None => continue,
+                };
+                let mesh = buffers_to_mesh(capture.buffers(width as f32));
+                let center = (
+                    x + width / 2,
+                    capture.heights.0.floor() as i32,
+                    z + width / 2,
+                );
+                let translation = Translation(origin.to_local(center));
+                match impostors.columns.get_mut(&(x, z)) {
+                    Some(impostor) => {
+                        impostor.chunks = chunks.len();
+                        match entities.get::<Handle<Mesh>>(impostor.entity) {
+                            Ok(handle) => {
+                                if let Some(old_mesh) = meshes.get_mut(&handle) {
+                                    *old_mesh = mesh;
+                                }
+                            }
+                            Err(_) => {
+                                commands.insert_one(impostor.entity, meshes.add(mesh));
+                            }
+                        }
+                        if let Ok(mut old_translation) =
+                            translations.get_mut::<Translation>(impostor.entity)
+                        {
+                            *old_translation = translation;
+                        }
+                    }
+                    None => {
+                        let e = Entity::new();
+                        commands.spawn_as_entity(
+                            e,
+                            ChunkRenderComponents {
+                                mesh: meshes.add(mesh),
+                                material,
+                                translation,
+                                rotation,
+                                ..Default::default()
+                            },
+                        );
+                        impostors.columns.insert(
+                            (x, z),
+                            Impostor {
+                                entity: e,
+                                chunks: chunks.len(),
+                                visible: false,
+                            },
+                        );
+                    }
+                }
+            }
+
+            // meshing shows the chunks again, so they are hidden every frame
+            for e in chunk_entities {
+                if let Ok(mut draw) = entities.get_mut::<Draw>(e) {
+                    draw.is_visible = false;
+                }
+            }
+            let impostor = impostors.columns.get_mut(&(x, z)).unwrap();
+            impostor.visible = true;
+            if let Ok(mut draw) = entities.get_mut::<Draw>(impostor.entity) {
+                draw.is_visible = true;
+            }
+            if let Ok(mut old_rotation) = rotations.get_mut::<Rotation>(impostor.entity) {
+                *old_rotation = rotation;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple::Block;
+
+    fn assert_close(a: [f32; 4], b: [f32; 4]) {
+        for (a, b) in a.iter().zip(&b) {
+            assert!((a - b).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    pub fn hysteresis() {
+        let config = ImpostorConfig {
+            distance: 100,
+            hysteresis: 10,
+        };
+        assert!(!config.is_impostor(100, false));
+        assert!(!config.is_impostor(110, false));
+        assert!(config.is_impostor(111, false));
+        // an impostor stays one until the column comes well inside the distance
+        assert!(config.is_impostor(100, true));
+        assert!(config.is_impostor(91, true));
+        assert!(!config.is_impostor(90, true));
+        assert!(!config.is_impostor(0, true));
+    }
+
+    #[test]
+    pub fn bake() {
+        let stone = Block {
+            color: Color::rgb(0.5, 0.5, 0.5),
+            ..Default::default()
+        };
+        let grass = Block {
+            color: Color::rgb(0.0, 0.5, 0.0),
+            ..Default::default()
+        };
+        // a pillar of stone across two chunks with grass on top
+        let mut lower = Chunk::new(2, (0, 0, 0));
+        let mut upper = Chunk::new(2, (0, 4, 0));
+        for y in 0..4 {
+            lower.insert((1, y, 1), stone);
+        }
+        upper.insert((1, 0, 1), grass);
+        let map = Map::try_with_chunks(vec![lower, upper, Chunk::new(2, (0, 8, 0))]).unwrap();
+        let chunks = map.iter().collect::<Vec<_>>();
+        let capture = ImpostorCapture::bake(&map, &chunks).unwrap();
+        assert_close(capture.top, [0.0, 0.5, 0.0, 1.0]);
+        // 16 sides of stone and 4 of grass
+        assert_close(capture.side, [0.4, 0.5, 0.4, 1.0]);
+        assert_eq!(capture.heights, (0.0, 5.0));
+        assert!(capture.shade > 0.0 && capture.shade <= 1.0);
+
+        // air has nothing to bake
+        assert!(ImpostorCapture::bake(&map, &[map.get((0, 8, 0)).unwrap()]).is_none());
+
+        let buffers = capture.buffers(4.0);
+        assert_eq!(buffers.positions[2], [2.0, 5.0, 0.0]);
+        assert_eq!(
+            buffers.colors,
+            vec![capture.side, capture.side, capture.top, capture.top]
+        );
+        for len in &[
+            buffers.shades.len(),
+            buffers.normals.len(),
+            buffers.emissions.len(),
+            buffers.tints.len(),
+            buffers.randoms.len(),
+        ] {
+            assert_eq!(*len, 4);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::{
+    prelude::*,
+    render::{draw::Draw, render_graph::base::MainPass},
+};
+
+use crate::{collections::lod_tree::Voxel, world::Map};
+
+/// Which layer the chunks of a map are drawn in, so that several maps can be drawn by
+/// different cameras, e.g. a preview in an editor pane next to the live world.
+///
+/// Add it to the map's entity, `map_layer_update` copies it to the render entities of the
+/// map's chunks. Only layer `MapLayer::MAIN` is drawn by the main pass, other layers have
+/// to be picked up by a pass of their own, e.g. by giving the chunk entities with that
+/// layer the pass's marker component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapLayer {
+    pub layer: u32,
+    /// Hides every chunk of the map without unloading it.
+    pub visible: bool,
+}
+
+impl MapLayer {
+    pub const MAIN: u32 = 0;
+
+    pub fn new(layer: u32) -> Self {
+        Self {
+            layer,
+            visible: true,
+        }
+    }
+}
+
+impl Default for MapLayer {
+    fn default() -> Self {
+        Self::new(Self::MAIN)
+    }
+}
+
+/// The render entities of `map`'s chunks that aren't in `layer` yet, either because they're new
+/// or because the map changed layers.
+pub(crate) fn outdated_entities<T: Voxel>(
+    map: &Map<T>,
+    layer: MapLayer,
+    current: impl Fn(Entity) -> Option<MapLayer>,
+) -> Vec<Entity> {
+    map.iter()
+        .flat_map(|chunk| chunk.entity().into_iter().chain(chunk.transparent_entity()))
+        .filter(|&e| current(e) != Some(layer))
+        .collect()
+}
+
+/// Copies the `MapLayer` of every map to the render entities of its chunks, showing or hiding
+/// them and moving them in or out of the main pass.
+pub fn map_layer_update<T: Voxel>(
+    mut commands: Commands,
+    mut maps: Query<(&Map<T>, &MapLayer)>,
+    layers: Query<&MapLayer>,
+    draws: Query<&mut Draw>,
+) {
+    for (map, layer) in &mut maps.iter() {
+        let current = |e| layers.get::<MapLayer>(e).ok().map(|other| *other);
+        for e in outdated_entities(map, *layer, current) {
+            if let Ok(mut draw) = draws.get_mut::<Draw>(e) {
+                draw.is_visible = layer.visible;
+            }
+            commands.insert_one(e, *layer);
+            if layer.layer == MapLayer::MAIN {
+                commands.insert_one(e, MainPass);
+            } else {
+                commands.remove_one::<MainPass>(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::map;
+
+    #[test]
+    pub fn outdated_entities() {
+        let mut map = map();
+        let (opaque, transparent) = (Entity::from_id(1), Entity::from_id(2));
+        let chunk = map.get_mut((0, 0, 0)).unwrap();
+        chunk.set_entity(opaque);
+        chunk.set_transparent_entity(transparent);
+        let other = Entity::from_id(3);
+        map.get_mut((4, 0, 0)).unwrap().set_entity(other);
+
+        let preview = MapLayer::new(1);
+        let mut entities = super::outdated_entities(&map, preview, |_| None);
+        entities.sort_by_key(|e| e.id());
+        assert_eq!(entities, vec![opaque, transparent, other]);
+
+        // entities already in the map's layer are left alone
+        let main = MapLayer::default();
+        let current = |e| Some(if e == other { preview } else { main });
+        let entities = super::outdated_entities(&map, preview, current);
+        assert_eq!(entities.len(), 2);
+        assert!(!entities.contains(&other));
+
+        // hiding the map counts as a change
+        let hidden = MapLayer {
+            visible: false,
+            ..preview
+        };
+        let entities = super::outdated_entities(&map, hidden, |_| Some(preview));
+        assert_eq!(entities.len(), 3);
+    }
+}
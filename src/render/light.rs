@@ -1,126 +1,280 @@
 use std::sync::mpsc;
-use std::time::Instant;
-use std::collections::HashMap;
+use std::{collections::HashMap, mem, time::Duration};
 
+use instant::Instant;
+
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use bevy::prelude::*;
 use bevy::diagnostic::Diagnostic;
 use bevy::diagnostic::Diagnostics;
 use bevy::diagnostic::DiagnosticId;
 
-use line_drawing::{Bresenham3d, VoxelOrigin, WalkVoxels};
-
 use crate::{
-    render::entity::{Face, VoxelExt},
-    world::{ChunkUpdate, Map, MapUpdates},
+    lighting,
+    mesh::VoxelExt,
+    render::{lod::LodConfig, material::VoxelMaterial},
+    world::{
+        ChunkPipeline, ChunkUpdate, Invalidation, Map, MapUpdates, MergePolicy, VoxelWorld,
+    },
 };
 
+pub use crate::lighting::{AmbientLight, DirectionalLight, LightQuality, SkyAmbient, VoxelTracer};
+
 pub const LIGHT_MAP_DIAGNOSTIC: DiagnosticId = DiagnosticId::from_u128(1235078163485702);
 pub const LIGHT_UPDATE_DIAGNOSTIC: DiagnosticId = DiagnosticId::from_u128(1098234508917522);
 
-pub trait VoxelTracer: Iterator<Item = (i32, i32, i32)> {
-    fn new(start: (i32, i32, i32), end: (i32, i32, i32)) -> Self;
+/// Selects which lighting systems handle a chunk.
+///
+/// `Simple` chunks are lit by `simple_light_update`, `Shaded` chunks by `light_map_update`
+/// and `shaded_light_update`. `Auto` uses shaded lighting for chunks up to
+/// `LodConfig::max_shaded_lod` and simple lighting for everything further away, so all
+/// three systems have to be added.
+///
+/// `Unlit` chunks aren't lit at all and are drawn at full brightness, e.g. for stylized games
+/// or previews. `unlit_mode_update` switches the maps to `ChunkPipeline::unlit` and the
+/// materials to `VoxelMaterial::unlit`, so the lighting systems don't get any chunks.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightingMode {
+    Simple,
+    Shaded,
+    Auto,
+    Unlit,
 }
 
-impl VoxelTracer for Bresenham3d<i32> {
-    fn new(start: (i32, i32, i32), end: (i32, i32, i32)) -> Self {
-        Self::new(start, end)
+impl Default for LightingMode {
+    fn default() -> Self {
+        Self::Shaded
     }
 }
 
-impl VoxelTracer for WalkVoxels<f32, i32> {
-    fn new(start: (i32, i32, i32), end: (i32, i32, i32)) -> Self {
-        Self::new(
-            (start.0 as f32, start.1 as f32, start.2 as f32),
-            (end.0 as f32, end.1 as f32, end.2 as f32),
-            &VoxelOrigin::Center,
-        )
+impl LightingMode {
+    pub fn is_shaded(&self, config: &LodConfig, lod: usize) -> bool {
+        match self {
+            Self::Simple | Self::Unlit => false,
+            Self::Shaded => true,
+            Self::Auto => lod <= config.max_shaded_lod,
+        }
     }
 }
 
-pub struct DirectionalLight {
-    pub direction: Vec3,
-    pub intensity: f32,
+/// Where the angle between a face and the directional light is applied.
+///
+/// `Baked` multiplies it into the vertex shades, so changing the direction of the light means
+/// relighting every chunk. `Shader` only bakes how much of the light reaches every face and
+/// leaves the angle to the fragment shader, which needs `VoxelMaterial::shader_light` or
+/// `VoxelMaterial::face_shading`, and `shader_light_update`. Shadows in the light maps still follow the direction the chunks
+/// were last lit with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaceShading {
+    Baked,
+    Shader,
+}
+
+impl Default for FaceShading {
+    fn default() -> Self {
+        Self::Baked
+    }
 }
 
-pub struct AmbientLight {
-    pub intensity: f32,
+/// Identifies a region requested with `LightingRegions::request`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightRegionId(u64);
+
+#[derive(Debug, Clone)]
+struct LightRegion {
+    id: LightRegionId,
+    min: (i32, i32, i32),
+    max: (i32, i32, i32),
+    quality: LightQuality,
+    expires: Option<Instant>,
+}
+
+/// Regions lit with a different quality than the rest of the world, e.g. a higher quality
+/// around the player's base.
+///
+/// `shaded_light_update` lights every chunk with the quality of the most recently requested
+/// region intersecting it, or `default`. `light_region_update` relights the chunks of
+/// regions that were requested, released or expired.
+#[derive(Debug, Default)]
+pub struct LightingRegions {
+    pub default: LightQuality,
+    /// How many chunks `light_map_update` traces per map and frame, each counting once per
+    /// `LightQuality::shadow_rays`, so that chunks with soft shadows are spread over several
+    /// frames. At least one chunk is traced every frame. `None` for no limit.
+    pub light_map_budget: Option<usize>,
+    regions: Vec<LightRegion>,
+    next_id: u64,
+    /// The boxes of the regions that changed since the last `light_region_update`.
+    changed: Vec<((i32, i32, i32), (i32, i32, i32))>,
+}
+
+impl LightingRegions {
+    /// Lights the box from `min` to `max`, both inclusive, with `quality` until the region
+    /// is released or `duration` has passed.
+    pub fn request(
+        &mut self,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+        quality: LightQuality,
+        duration: Option<Duration>,
+    ) -> LightRegionId {
+        let id = LightRegionId(self.next_id);
+        self.next_id += 1;
+        self.regions.push(LightRegion {
+            id,
+            min,
+            max,
+            quality,
+            expires: duration.map(|duration| Instant::now() + duration),
+        });
+        self.changed.push((min, max));
+        id
+    }
+
+    /// Returns the region to the default quality. Returns `false` if it doesn't exist or
+    /// has expired.
+    pub fn release(&mut self, id: LightRegionId) -> bool {
+        let len = self.regions.len();
+        let changed = &mut self.changed;
+        self.regions.retain(|region| {
+            if region.id == id {
+                changed.push((region.min, region.max));
+            }
+            region.id != id
+        });
+        self.regions.len() != len
+    }
+
+    /// Returns the quality of the box from `min` to `max`, both inclusive.
+    pub fn quality(&self, min: (i32, i32, i32), max: (i32, i32, i32)) -> LightQuality {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| {
+                min.0 <= region.max.0
+                    && max.0 >= region.min.0
+                    && min.1 <= region.max.1
+                    && max.1 >= region.min.1
+                    && min.2 <= region.max.2
+                    && max.2 >= region.min.2
+            })
+            .map_or(self.default, |region| region.quality)
+    }
+
+    /// The quality of the chunk of width `width` at `coords`.
+    pub fn chunk_quality(&self, coords: (i32, i32, i32), width: usize) -> LightQuality {
+        let (x, y, z) = coords;
+        let width = width as i32;
+        self.quality(coords, (x + width - 1, y + width - 1, z + width - 1))
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let changed = &mut self.changed;
+        self.regions.retain(|region| match region.expires {
+            Some(expires) if expires <= now => {
+                changed.push((region.min, region.max));
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+/// Expires regions of `LightingRegions` and relights the chunks of all regions that changed.
+pub fn light_region_update(
+    mut regions: ResMut<LightingRegions>,
+    mut query: Query<&mut MapUpdates>,
+) {
+    regions.expire(Instant::now());
+    if regions.changed.is_empty() {
+        return;
+    }
+    let changed = mem::take(&mut regions.changed);
+    for mut update in &mut query.iter() {
+        for &(min, max) in &changed {
+            update.invalidate_region(min, max);
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn simple_light_update<T: VoxelExt>(
     directional: Res<DirectionalLight>,
     ambient: Res<AmbientLight>,
+    mode: Res<LightingMode>,
+    shading: Res<FaceShading>,
+    config: Res<LodConfig>,
+    merge: Res<MergePolicy>,
     mut diagnostics: ResMut<Diagnostics>,
     mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
 ) {
     let start = Instant::now();
+    let span = span!("simple_light_update", chunks);
+
+    let count = for_each_map(&mut query, |map, update| {
+        simple_light(
+            map,
+            update,
+            &directional,
+            &ambient,
+            *mode,
+            *shading,
+            &config,
+            *merge,
+        )
+    });
 
-    for (mut map, mut update) in &mut query.iter() {
-        let mut remove = Vec::new();
-        let mut insert = Vec::new();
-        for (&(x, y, z), update) in &update.updates {
-            match update {
-                ChunkUpdate::UpdateLightMap => {}
-                _ => continue,
-            }
-            remove.push((x, y, z));
-
-            let chunk = map.get_mut((x, y, z));
-            if chunk.is_none() {
-                continue;
-            }
-            let chunk = chunk.unwrap();
-
-            let light = -directional.direction;
-
-            for elem in chunk.iter_mut() {
-                elem.value.set_shade(
-                    Face::Top,
-                    light.dot(Vec3::new(0.0, 1.0, 0.0)).max(0.0).min(1.0) * directional.intensity
-                        + ambient.intensity,
-                );
-                elem.value.set_shade(
-                    Face::Bottom,
-                    light.dot(Vec3::new(0.0, -1.0, 0.0)).max(0.0).min(1.0) * directional.intensity
-                        + ambient.intensity,
-                );
-                elem.value.set_shade(
-                    Face::Front,
-                    light.dot(Vec3::new(0.0, 0.0, 1.0)).max(0.0).min(1.0) * directional.intensity
-                        + ambient.intensity,
-                );
-                elem.value.set_shade(
-                    Face::Back,
-                    light.dot(Vec3::new(0.0, 0.0, -1.0)).max(0.0).min(1.0) * directional.intensity
-                        + ambient.intensity,
-                );
-                elem.value.set_shade(
-                    Face::Left,
-                    light.dot(Vec3::new(1.0, 0.0, 0.0)).max(0.0).min(1.0) * directional.intensity
-                        + ambient.intensity,
-                );
-                elem.value.set_shade(
-                    Face::Right,
-                    light.dot(Vec3::new(-1.0, 0.0, 0.0)).max(0.0).min(1.0) * directional.intensity
-                        + ambient.intensity,
-                );
-            }
-
-            chunk.merge();
+    record!(span, chunks, count);
+    record_light_update(&mut diagnostics, start);
+}
 
-            insert.push(((x, y, z), ChunkUpdate::UpdateMesh));
-        }
-        for coords in remove {
-            update.updates.remove(&coords);
+#[allow(clippy::too_many_arguments)]
+fn simple_light<T: VoxelExt>(
+    map: &mut Map<T>,
+    update: &mut MapUpdates,
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+    mode: LightingMode,
+    shading: FaceShading,
+    config: &LodConfig,
+    merge: MergePolicy,
+) -> usize {
+    let chunks = update.drain_kind_filter(ChunkUpdate::UpdateLightMap, usize::MAX, |coords| {
+        map.get_at_origin(coords)
+            .map(|chunk| !mode.is_shaded(config, chunk.lod()))
+            .unwrap_or(true)
+    });
+    let count = chunks.len();
+    for (x, y, z) in chunks {
+        let chunk = match map.get_at_origin_mut((x, y, z)) {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        match shading {
+            FaceShading::Baked => lighting::simple_light_with(chunk, directional, ambient, merge),
+            FaceShading::Shader => lighting::simple_visibility_with(chunk, merge),
         }
-        for (coords, u) in insert {
-            update.updates.insert(coords, u);
+
+        // simple lighting doesn't need a light map, so it does both stages at once
+        let result = chunk
+            .transition(&update.pipeline, &ChunkUpdate::UpdateLightMap)
+            .and_then(|_| update.complete(chunk, ChunkUpdate::UpdateLight));
+        if let Err(e) = result {
+            log::warn!("{}", e);
         }
     }
+    count
+}
 
-    let end = Instant::now();
-    let duration = (end - start).as_secs_f64();
+fn record_light_update(diagnostics: &mut Diagnostics, start: Instant) {
+    let duration = (Instant::now() - start).as_secs_f64();
     if diagnostics.get(LIGHT_UPDATE_DIAGNOSTIC).is_none() {
         diagnostics.add(Diagnostic::new(LIGHT_UPDATE_DIAGNOSTIC, "light updates", 20));
     }
@@ -130,345 +284,481 @@ pub fn simple_light_update<T: VoxelExt>(
 pub fn shaded_light_update<T: VoxelExt>(
     directional: Res<DirectionalLight>,
     ambient: Res<AmbientLight>,
+    shading: Res<FaceShading>,
+    regions: Res<LightingRegions>,
+    merge: Res<MergePolicy>,
     mut diagnostics: ResMut<Diagnostics>,
     mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
 ) {
     let start = Instant::now();
-    
+    let span = span!("shaded_light_update", chunks);
+    let mut count = 0;
+
     for (mut map, mut update) in &mut query.iter() {
-        let mut remove = Vec::new();
-        let mut insert = Vec::new();
-        let (tx, rx) = mpsc::channel();
-        update.updates.par_iter().for_each_with(tx, |tx_lm, (&(cx, cy, cz), update)| {
-            match update {
-                ChunkUpdate::UpdateLight => {}
-                _ => return,
-            }
+        count += shaded_light(
+            &mut map,
+            &mut update,
+            &directional,
+            &ambient,
+            *shading,
+            &regions,
+            *merge,
+        );
+    }
 
-            let chunk = map.get((cx, cy, cz)).unwrap();
-
-            let width = chunk.width() as i32;
-
-            let lm_width = chunk.width() as i32 + 2;
-
-            let neighbour_top = map.get((cx, cy + width, cz));
-            let neighbour_bottom = map.get((cx, cy - width, cz));
-            let neighbour_left = map.get((cx + width, cy, cz));
-            let neighbour_right = map.get((cx - width, cy, cz));
-            let neighbour_front = map.get((cx, cy, cz + width));
-            let neighbour_back = map.get((cx, cy, cz - width));
-
-            let (tx, rx) = mpsc::channel();
-
-            (-1..lm_width - 1).into_par_iter().for_each_with(tx, |tx, x| {
-                for y in -1..lm_width - 1 {
-                    for z in -1..lm_width - 1 {
-                        let mut light = 0.0;
-                        let mut count = 0;
-                        let range = 1;
-                        for lx in -range..=range {
-                            for ly in -range..=range {
-                                for lz in -range..=range {
-                                    let x = x + lx;
-                                    let y = y + ly;
-                                    let z = z + lz;
-                                    if x < 0
-                                        || x >= width
-                                        || y < 0
-                                        || y >= width
-                                        || z < 0
-                                        || z >= width
-                                    {
-                                        let sx = if x < 0 {
-                                            -1
-                                        } else if x >= width {
-                                            1
-                                        } else {
-                                            0
-                                        };
-                                        let sy = if y < 0 {
-                                            -1
-                                        } else if y >= width {
-                                            1
-                                        } else {
-                                            0
-                                        };
-                                        let sz = if z < 0 {
-                                            -1
-                                        } else if z >= width {
-                                            1
-                                        } else {
-                                            0
-                                        };
-                                        let neighbour = match (sx, sy, sz) {
-                                            (1, 0, 0) => neighbour_left,
-                                            (-1, 0, 0) => neighbour_right,
-                                            (0, 1, 0) => neighbour_top,
-                                            (0, -1, 0) => neighbour_bottom,
-                                            (0, 0, 1) => neighbour_front,
-                                            (0, 0, -1) => neighbour_back,
-                                            _ => {
-                                                let cx = cx + width * sx;
-                                                let cy = cy + width * sy;
-                                                let cz = cz + width * sz;
-                                                map.get((cx, cy, cz))
-                                            }
-                                        };
-                                        if let Some(chunk) = neighbour {
-                                            if !chunk.has_light() {
-                                                return;
-                                            }
-                                            let x = x % width;
-                                            let y = y % width;
-                                            let z = z % width;
-                                            if let Some(l) = chunk.light((x, y, z)) {
-                                                light += l;
-                                                count += 1;
-                                            }
-                                        }
-                                    } else {
-                                        if let Some(l) = chunk.light((x, y, z)) {
-                                            light += l;
-                                            count += 1;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if count == 0 {
-                            count = 1;
-                        }
-                        let light = light / count as f32;
-                        tx.send(((x, y, z), light)).unwrap();
-                    }
-                }
-            });
-
-            let mut light_map = rx.try_iter().collect::<Vec<_>>();
-            light_map.sort_unstable_by_key(|(coords, _)| *coords);
-
-            let light_map = light_map.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
-
-            tx_lm.send(((cx, cy, cz), light_map)).unwrap();
-        });
-        
-        let light_maps = rx.try_iter().collect::<HashMap<_, _>>();
+    record!(span, chunks, count);
+    record_light_update(&mut diagnostics, start);
+}
 
-        for (&(cx, cy, cz), update) in &update.updates {
-            match update {
-                ChunkUpdate::UpdateLight => {}
-                _ => continue,
-            }
-            
-            let light_map = &light_maps[&(cx, cy, cz)];
-            let chunk = map.get_mut((cx, cy, cz)).unwrap();
-
-            let lm_width = chunk.width() as i32 + 2;
-
-            let dir = -directional.direction;
-
-            for elem in chunk.iter_mut() {
-                let x = elem.x;
-                let y = elem.y;
-                let z = elem.z;
-                let block = elem.value;
-
-                let idx = ((x + 1) * lm_width * lm_width) as usize
-                    + ((y + 2) * lm_width) as usize
-                    + (z + 1) as usize;
-                let light = light_map[idx];
-                block.set_shade(
-                    Face::Top,
-                    light
-                        * dir.dot(Vec3::new(0.0, 1.0, 0.0)).max(0.0).min(1.0)
-                        * directional.intensity
-                        + ambient.intensity,
-                );
+fn shaded_light<T: VoxelExt>(
+    map: &mut Map<T>,
+    update: &mut MapUpdates,
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+    shading: FaceShading,
+    regions: &LightingRegions,
+    merge: MergePolicy,
+) -> usize {
+    let chunks = update.drain_kind(ChunkUpdate::UpdateLight, usize::MAX);
+    let count = chunks.len();
+    let (tx, rx) = mpsc::channel();
+    let light_map = |tx_lm: &mut mpsc::Sender<_>, &coords: &(i32, i32, i32)| {
+        // chunks that were unloaded in the meantime are skipped below
+        let width = map
+            .get_at_origin(coords)
+            .map_or(0, |chunk| chunk.width() as i32);
+        let (x, y, z) = coords;
+        let max = (x + width - 1, y + width - 1, z + width - 1);
+        let quality = regions.quality(coords, max);
+        if let Some(light_map) = lighting::shaded_light_map_with(map, coords, &quality) {
+            tx_lm.send((coords, (light_map, quality))).unwrap();
+        }
+    };
+    #[cfg(feature = "parallel")]
+    chunks.par_iter().for_each_with(tx, light_map);
+    #[cfg(not(feature = "parallel"))]
+    {
+        let mut tx = tx;
+        chunks.iter().for_each(|coords| light_map(&mut tx, coords));
+    }
 
-                let idx = ((x + 1) * lm_width * lm_width) as usize
-                    + (y * lm_width) as usize
-                    + (z + 1) as usize;
-                let light = light_map[idx];
-                block.set_shade(
-                    Face::Bottom,
-                    light
-                        * dir.dot(Vec3::new(0.0, -1.0, 0.0)).max(0.0).min(1.0)
-                        * directional.intensity
-                        + ambient.intensity,
-                );
+    let light_maps = rx.try_iter().collect::<HashMap<_, _>>();
 
-                let idx = ((x + 1) * lm_width * lm_width) as usize
-                    + ((y + 1) * lm_width) as usize
-                    + (z + 2) as usize;
-                let light = light_map[idx];
-                block.set_shade(
-                    Face::Front,
-                    light
-                        * dir.dot(Vec3::new(0.0, 0.0, 1.0)).max(0.0).min(1.0)
-                        * directional.intensity
-                        + ambient.intensity,
-                );
+    for (cx, cy, cz) in chunks {
+        let light_map = light_maps.get(&(cx, cy, cz));
+        let ((light_map, quality), chunk) = match (light_map, map.get_at_origin_mut((cx, cy, cz))) {
+            (Some(light_map), Some(chunk)) => (light_map, chunk),
+            _ => {
+                log::warn!("chunk {:?} was unloaded before it could be lit", (cx, cy, cz));
+                continue;
+            }
+        };
+
+        match shading {
+            FaceShading::Baked => lighting::shaded_light_with_merge(
+                chunk,
+                light_map,
+                directional,
+                ambient,
+                quality,
+                merge,
+            ),
+            FaceShading::Shader => lighting::shaded_visibility_with(chunk, light_map, merge),
+        }
 
-                let idx = ((x + 1) * lm_width * lm_width) as usize
-                    + ((y + 1) * lm_width) as usize
-                    + z as usize;
-                let light = light_map[idx];
-                block.set_shade(
-                    Face::Back,
-                    light
-                        * dir.dot(Vec3::new(0.0, 0.0, -1.0)).max(0.0).min(1.0)
-                        * directional.intensity
-                        + ambient.intensity,
-                );
+        if let Err(e) = update.complete(chunk, ChunkUpdate::UpdateLight) {
+            log::warn!("{}", e);
+        }
+    }
+    count
+}
 
-                let idx = ((x + 2) * lm_width * lm_width) as usize
-                    + ((y + 1) * lm_width) as usize
-                    + (z + 1) as usize;
-                let light = light_map[idx];
-                block.set_shade(
-                    Face::Left,
-                    light
-                        * dir.dot(Vec3::new(1.0, 0.0, 0.0)).max(0.0).min(1.0)
-                        * directional.intensity
-                        + ambient.intensity,
-                );
+/// Traces the light maps of shaded chunks, with the `LightQuality::shadow_rays` of their
+/// `LightingRegions` quality and within its `light_map_budget`.
+pub fn light_map_update<T: VoxelExt, R: VoxelTracer>(
+    directional: Res<DirectionalLight>,
+    mode: Res<LightingMode>,
+    config: Res<LodConfig>,
+    regions: Res<LightingRegions>,
+    mut diagnostics: ResMut<Diagnostics>,
+    mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
+) {
+    let start = Instant::now();
+    let span = span!("light_map_update", chunks);
 
-                let idx = (x * lm_width * lm_width) as usize
-                    + ((y + 1) * lm_width) as usize
-                    + (z + 1) as usize;
-                let light = light_map[idx];
-                block.set_shade(
-                    Face::Right,
-                    light
-                        * dir.dot(Vec3::new(-1.0, 0.0, 0.0)).max(0.0).min(1.0)
-                        * directional.intensity
-                        + ambient.intensity,
-                );
-            }
+    // light maps only depend on the chunk itself, so the maps of a sharded world are
+    // updated in parallel
+    let count = for_each_map(&mut query, |map, update| {
+        light_maps::<T, R>(map, update, &directional, *mode, &config, &regions)
+    });
 
-            chunk.merge();
+    record!(span, chunks, count);
+    record_light_map(&mut diagnostics, start);
+}
 
-            remove.push((cx, cy, cz));
-            insert.push(((cx, cy, cz), ChunkUpdate::UpdateMesh));
+fn light_maps<T: VoxelExt, R: VoxelTracer>(
+    map: &mut Map<T>,
+    update: &mut MapUpdates,
+    directional: &DirectionalLight,
+    mode: LightingMode,
+    config: &LodConfig,
+    regions: &LightingRegions,
+) -> usize {
+    let mut spent = 0;
+    let chunks = update.drain_kind_filter(ChunkUpdate::UpdateLightMap, usize::MAX, |coords| {
+        let chunk = match map.get_at_origin(coords) {
+            Some(chunk) => chunk,
+            None => return true,
+        };
+        if !mode.is_shaded(config, chunk.lod()) {
+            return false;
         }
-        for coords in remove {
-            update.updates.remove(&coords);
+        let cost = regions
+            .chunk_quality(coords, chunk.width())
+            .shadow_rays
+            .max(1);
+        match regions.light_map_budget {
+            Some(budget) if spent > 0 && spent + cost > budget => false,
+            _ => {
+                spent += cost;
+                true
+            }
         }
-        for (coords, u) in insert {
-            update.updates.insert(coords, u);
+    });
+    let count = chunks.len();
+    for &coords in &chunks {
+        if let Some(chunk) = map.get_at_origin_mut(coords) {
+            let quality = regions.chunk_quality(coords, chunk.width());
+            lighting::light_map_with::<_, R>(chunk, directional, &quality);
         }
     }
 
-    let end = Instant::now();
-    let duration = (end - start).as_secs_f64();
-    if diagnostics.get(LIGHT_UPDATE_DIAGNOSTIC).is_none() {
-        diagnostics.add(Diagnostic::new(LIGHT_UPDATE_DIAGNOSTIC, "light updates", 20));
+    // publish the new light maps only after the whole pass, so that the result doesn't
+    // depend on the order the chunks were drained in
+    for coords in chunks {
+        let chunk = if let Some(chunk) = map.get_at_origin_mut(coords) {
+            chunk
+        } else {
+            continue;
+        };
+        chunk.swap_light();
+
+        if let Err(e) = update.complete(chunk, ChunkUpdate::UpdateLightMap) {
+            log::warn!("{}", e);
+        }
+        map.relight_border(coords, update);
     }
-    diagnostics.add_measurement(LIGHT_UPDATE_DIAGNOSTIC, duration);
+    count
 }
 
-pub fn light_map_update<T: VoxelExt, R: VoxelTracer>(
+fn record_light_map(diagnostics: &mut Diagnostics, start: Instant) {
+    let duration = (Instant::now() - start).as_secs_f64();
+    if diagnostics.get(LIGHT_MAP_DIAGNOSTIC).is_none() {
+        diagnostics.add(Diagnostic::new(LIGHT_MAP_DIAGNOSTIC, "light map calculation", 20));
+    }
+    diagnostics.add_measurement(LIGHT_MAP_DIAGNOSTIC, duration);
+}
+
+/// Lights the chunks of the `VoxelWorld<T>` resource, like `light_map_update`,
+/// `simple_light_update` and `shaded_light_update` together do for the maps of entities.
+#[allow(clippy::too_many_arguments)]
+pub fn voxel_world_light_update<T: VoxelExt, R: VoxelTracer>(
     directional: Res<DirectionalLight>,
+    ambient: Res<AmbientLight>,
+    mode: Res<LightingMode>,
+    shading: Res<FaceShading>,
+    config: Res<LodConfig>,
+    regions: Res<LightingRegions>,
+    merge: Res<MergePolicy>,
     mut diagnostics: ResMut<Diagnostics>,
-    mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
+    mut world: ResMut<VoxelWorld<T>>,
 ) {
     let start = Instant::now();
-    
-    for (mut map, mut update) in &mut query.iter() {
-        let mut remove = Vec::new();
-        let mut insert = Vec::new();
-        for (&(cx, cy, cz), update) in &update.updates {
-            match update {
-                ChunkUpdate::UpdateLightMap => {}
-                _ => continue,
-            }
-            remove.push((cx, cy, cz));
+    let (map, update) = world.parts_mut();
+    light_maps::<T, R>(map, update, &directional, *mode, &config, &regions);
+    record_light_map(&mut diagnostics, start);
 
-            let chunk = map.get_mut((cx, cy, cz));
-            if chunk.is_none() {
-                continue;
-            }
-            let chunk = chunk.unwrap();
-
-            let mut light_map = vec![None; chunk.width().pow(3)];
-
-            let lm_width = chunk.width() as i32;
-
-            for y in 0..lm_width {
-                for x in 0..lm_width {
-                    for z in 0..lm_width {
-                        let idx = (x * lm_width * lm_width) as usize
-                            + (y * lm_width) as usize
-                            + z as usize;
-                        if light_map[idx].is_some() {
-                            continue;
-                        }
-
-                        let light_source =
-                            Vec3::new(x as _, y as _, z as _) + directional.direction * -100.0;
-                        let mut light = 1.0;
-                        for (x, y, z) in R::new(
-                            (
-                                light_source.x() as _,
-                                light_source.y() as _,
-                                light_source.z() as _,
-                            ),
-                            (x, y, z),
-                        ) {
-                            let block = chunk.get((x, y, z));
-                            if block.is_some() {
-                                light = 0.0;
-                            }
-                            if x < 0
-                                || y < 0
-                                || z < 0
-                                || x >= lm_width
-                                || y >= lm_width
-                                || z >= lm_width
-                            {
-                                continue;
-                            }
-                            let idx = (x * lm_width * lm_width) as usize
-                                + (y * lm_width) as usize
-                                + z as usize;
-                            if let Some(map) = light_map.get_mut(idx) {
-                                if map.is_none() {
-                                    *map = Some(light);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    let start = Instant::now();
+    simple_light(
+        map,
+        update,
+        &directional,
+        &ambient,
+        *mode,
+        *shading,
+        &config,
+        *merge,
+    );
+    shaded_light(
+        map,
+        update,
+        &directional,
+        &ambient,
+        *shading,
+        &regions,
+        *merge,
+    );
+    record_light_update(&mut diagnostics, start);
+}
 
-            for x in 0..lm_width {
-                for y in 0..lm_width {
-                    for z in 0..lm_width {
-                        let idx = (x * lm_width * lm_width) as usize
-                            + (y * lm_width) as usize
-                            + z as usize;
-                        let light = light_map[idx];
-                        chunk.insert_light((x, y, z), light.unwrap_or_default());
-                    }
-                }
-            }
+/// Copies the directional and ambient light into every `VoxelMaterial` with `shader_light` or
+/// `face_shading`.
+pub fn shader_light_update(
+    directional: Res<DirectionalLight>,
+    ambient: Res<AmbientLight>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+) {
+    let ambient_sky = match &ambient.sky {
+        Some(sky) => Vec3::new(sky.sky, sky.horizon, sky.ground),
+        None => Vec3::zero(),
+    };
+    // only touch materials that changed, so their uniforms aren't uploaded every frame
+    let stale = materials
+        .iter()
+        .filter(|(_, material)| {
+            (material.shader_light || material.face_shading)
+                && (material.light_direction != directional.direction
+                    || material.light_intensity != directional.intensity
+                    || material.ambient_intensity != ambient.intensity
+                    || material.sky_ambient != ambient.sky.is_some()
+                    || material.ambient_sky != ambient_sky)
+        })
+        .map(|(handle, _)| handle)
+        .collect::<Vec<_>>();
+    for handle in stale {
+        if let Some(material) = materials.get_mut(&handle) {
+            material.light_direction = directional.direction;
+            material.light_intensity = directional.intensity;
+            material.ambient_intensity = ambient.intensity;
+            material.sky_ambient = ambient.sky.is_some();
+            material.ambient_sky = ambient_sky;
+            material.face_shades.clear();
+            material.face_shades.extend(&lighting::face_shades(&directional));
+            material.face_shades.resize(8, 0.0);
+        }
+    }
+}
 
-            chunk.set_light(true);
+/// Follows changes of the `LightingMode` to and from `Unlit`: switches the pipeline of every
+/// map, relighting all chunks when lighting is turned back on, and sets `VoxelMaterial::unlit`
+/// on every material.
+///
+/// Maps with a pipeline other than the default or `ChunkPipeline::unlit` are left alone.
+pub fn unlit_mode_update(
+    mode: Res<LightingMode>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut query: Query<&mut MapUpdates>,
+) {
+    let unlit = *mode == LightingMode::Unlit;
+    let stale = materials
+        .iter()
+        .filter(|(_, material)| material.unlit != unlit)
+        .map(|(handle, _)| handle)
+        .collect::<Vec<_>>();
+    for handle in stale {
+        if let Some(material) = materials.get_mut(&handle) {
+            material.unlit = unlit;
+        }
+    }
 
-            insert.push(((cx, cy, cz), ChunkUpdate::UpdateLight));
+    for mut update in &mut query.iter() {
+        if unlit && update.pipeline == ChunkPipeline::default() {
+            update.set_pipeline(ChunkPipeline::unlit());
+        } else if !unlit && update.pipeline == ChunkPipeline::unlit() {
+            // the shades of the chunks are stale, or were never computed
+            update.set_pipeline(ChunkPipeline::default());
+            update.invalidate(Invalidation::AllLighting);
         }
-        for coords in remove {
-            update.updates.remove(&coords);
+    }
+}
+
+/// Runs `f` on every map, in parallel with the `parallel` feature, and returns the sum of the
+/// results.
+fn for_each_map<T, F>(query: &mut Query<(&mut Map<T>, &mut MapUpdates)>, f: F) -> usize
+where
+    T: VoxelExt,
+    F: Fn(&mut Map<T>, &mut MapUpdates) -> usize + Send + Sync,
+{
+    let mut maps = query.iter();
+    let maps = (&mut maps).into_iter();
+    #[cfg(feature = "parallel")]
+    return maps
+        .collect::<Vec<_>>()
+        .par_iter_mut()
+        .map(|(map, update)| f(map, update))
+        .sum();
+    #[cfg(not(feature = "parallel"))]
+    maps.map(|(mut map, mut update)| f(&mut map, &mut update)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use line_drawing::Bresenham3d;
+
+    use super::*;
+    use crate::{
+        simple::Block,
+        terrain::{Biome, Layer, Program},
+        world::ChunkState,
+    };
+
+    #[test]
+    pub fn voxel_world_lighting() {
+        let program = Program::<Block>::build()
+            .chunk_size(3)
+            .biome(
+                Biome::build()
+                    .height(5.0)
+                    .layer(Layer::new(Block::default(), 5.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+            intensity: 0.8,
+        };
+        let ambient = AmbientLight::new(0.05);
+        let config = LodConfig::default();
+        let regions = LightingRegions::default();
+        let merge = MergePolicy::default();
+        for &mode in &[LightingMode::Shaded, LightingMode::Simple] {
+            let mut world = VoxelWorld::new(program.clone());
+            assert_eq!(world.request_area((0, 0, 0), (15, 7, 15)), 4);
+            assert_eq!(world.generate(&mut Vec::new()), 4);
+
+            // the stages of voxel_world_light_update, until the light maps of the neighbours
+            // stop relighting the borders
+            let (map, update) = world.parts_mut();
+            for _ in 0..4 {
+                light_maps::<_, Bresenham3d<i32>>(
+                    map,
+                    update,
+                    &directional,
+                    mode,
+                    &config,
+                    &regions,
+                );
+                let shading = FaceShading::Baked;
+                simple_light(
+                    map,
+                    update,
+                    &directional,
+                    &ambient,
+                    mode,
+                    shading,
+                    &config,
+                    merge,
+                );
+                shaded_light(
+                    map,
+                    update,
+                    &directional,
+                    &ambient,
+                    shading,
+                    &regions,
+                    merge,
+                );
+            }
+            let mut meshed = update.drain_kind(ChunkUpdate::UpdateMesh, usize::MAX);
+            meshed.sort();
+            assert_eq!(meshed, vec![(0, 0, 0), (0, 0, 8), (8, 0, 0), (8, 0, 8)]);
+            assert!(map.iter().all(|chunk| chunk.state() == ChunkState::Lit));
         }
-        for (coords, u) in insert {
-            update.updates.insert(coords, u);
+    }
+
+    #[test]
+    pub fn lighting_mode() {
+        let config = LodConfig {
+            max_shaded_lod: 1,
+            ..Default::default()
+        };
+        assert!(LightingMode::Shaded.is_shaded(&config, 3));
+        assert!(!LightingMode::Simple.is_shaded(&config, 0));
+        assert!(LightingMode::Auto.is_shaded(&config, 1));
+        assert!(!LightingMode::Auto.is_shaded(&config, 2));
+
+        let program = Program::<Block>::build()
+            .chunk_size(3)
+            .biome(
+                Biome::build()
+                    .height(5.0)
+                    .layer(Layer::new(Block::default(), 5.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut world = VoxelWorld::new(program);
+        assert_eq!(world.request_area((0, 0, 0), (15, 7, 15)), 4);
+        assert_eq!(world.generate(&mut Vec::new()), 4);
+        let (map, update) = world.parts_mut();
+        for chunk in map.iter_mut() {
+            if chunk.position().0 == 8 {
+                chunk.set_lod(2);
+            }
         }
+
+        // only the far chunks get simple lighting, the near ones wait for their light maps
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 0.8,
+        };
+        let ambient = AmbientLight::new(0.05);
+        let (shading, merge) = (FaceShading::Baked, MergePolicy::default());
+        let mode = LightingMode::Auto;
+        simple_light(
+            map,
+            update,
+            &directional,
+            &ambient,
+            mode,
+            shading,
+            &config,
+            merge,
+        );
+        let mut lit = map
+            .iter()
+            .filter(|chunk| chunk.state() == ChunkState::Lit)
+            .map(|chunk| chunk.position())
+            .collect::<Vec<_>>();
+        lit.sort();
+        assert_eq!(lit, vec![(8, 0, 0), (8, 0, 8)]);
+        let regions = LightingRegions::default();
+        light_maps::<_, Bresenham3d<i32>>(map, update, &directional, mode, &config, &regions);
+        let mut pending = update.iter_kind(ChunkUpdate::UpdateLightMap);
+        assert!(pending.all(|coords| map.get_at_origin(coords).is_none()));
     }
-    
-    let end = Instant::now();
-    let duration = (end - start).as_secs_f64();
-    if diagnostics.get(LIGHT_MAP_DIAGNOSTIC).is_none() {
-        diagnostics.add(Diagnostic::new(LIGHT_MAP_DIAGNOSTIC, "light map calculation", 20));
+
+    #[test]
+    pub fn lighting_regions() {
+        let mut regions = LightingRegions::default();
+        let base = regions.request((0, 0, 0), (15, 7, 15), LightQuality::HIGH, None);
+        assert_eq!(regions.chunk_quality((8, 0, 8), 8), LightQuality::HIGH);
+        assert_eq!(regions.chunk_quality((16, 0, 0), 8), LightQuality::STANDARD);
+        // chunks touching the region get its quality too
+        assert_eq!(regions.chunk_quality((-8, 0, 0), 9), LightQuality::HIGH);
+
+        // the most recent region wins where they overlap
+        let soft = LightQuality::SOFT_SHADOWS;
+        let duration = Some(Duration::from_secs(0));
+        regions.request((8, 0, 8), (8, 0, 8), soft, duration);
+        assert_eq!(regions.chunk_quality((8, 0, 8), 8), soft);
+        assert_eq!(regions.chunk_quality((0, 0, 0), 8), LightQuality::HIGH);
+        regions.expire(Instant::now());
+        assert_eq!(regions.chunk_quality((8, 0, 8), 8), LightQuality::HIGH);
+
+        assert!(regions.release(base));
+        assert!(!regions.release(base));
+        assert_eq!(regions.chunk_quality((8, 0, 8), 8), LightQuality::STANDARD);
+        // every request, expiry and release relights its box
+        assert_eq!(
+            regions.changed,
+            vec![
+                ((0, 0, 0), (15, 7, 15)),
+                ((8, 0, 8), (8, 0, 8)),
+                ((8, 0, 8), (8, 0, 8)),
+                ((0, 0, 0), (15, 7, 15)),
+            ]
+        );
     }
-    diagnostics.add_measurement(LIGHT_MAP_DIAGNOSTIC, duration);
 }
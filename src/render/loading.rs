@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    transform::prelude::{Transform, Translation},
+};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    render::origin::FloatingOrigin,
+    world::{ChunkUpdate, Map, MapUpdates},
+};
+
+/// Why a chunk isn't rendered yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LoadingStage {
+    /// Prefetched, waiting for the chunks that were requested first.
+    Queued,
+    Generating,
+    Lighting,
+    Meshing,
+}
+
+impl LoadingStage {
+    /// Returns the stage of a chunk with `update` pending.
+    pub fn pending(update: &ChunkUpdate) -> Self {
+        match update {
+            ChunkUpdate::GenerateChunk => Self::Generating,
+            ChunkUpdate::UpdateLightMap | ChunkUpdate::UpdateLight => Self::Lighting,
+            ChunkUpdate::UpdateMesh => Self::Meshing,
+        }
+    }
+}
+
+/// A placeholder entity for a chunk that is still going through the update pipeline.
+///
+/// The entity's `Translation` is the center of the chunk in render space, so UI crates can
+/// project it to the screen and draw a loading marker there. It's despawned once nothing is
+/// pending for the chunk anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadingMarker {
+    /// The chunk origin in world coordinates.
+    pub coords: (i32, i32, i32),
+    pub stage: LoadingStage,
+}
+
+/// The markers spawned by `loading_marker_update`, by chunk origin.
+#[derive(Debug, Default)]
+pub struct LoadingMarkers {
+    entities: HashMap<(i32, i32, i32), Entity>,
+}
+
+impl LoadingMarkers {
+    /// Returns the marker entity of the chunk at `coords`.
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<Entity> {
+        self.entities.get(&coords).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// Returns the stage of every chunk of `map` that has something pending.
+pub fn loading_stages<T: Voxel>(
+    map: &Map<T>,
+    updates: &MapUpdates,
+) -> HashMap<(i32, i32, i32), LoadingStage> {
+    let mut stages = updates
+        .updates
+        .iter()
+        .filter(|(&coords, update)| {
+            // chunks that are only relit keep their old mesh in the meantime
+            **update == ChunkUpdate::GenerateChunk
+                || map.get_at_origin(coords).map_or(true, |chunk| chunk.entity().is_none())
+        })
+        .map(|(&coords, update)| (coords, LoadingStage::pending(update)))
+        .collect::<HashMap<_, _>>();
+    for &coords in &updates.prefetch {
+        stages.entry(coords).or_insert(LoadingStage::Queued);
+    }
+    stages
+}
+
+/// Spawns, moves and despawns the `LoadingMarker`s of the first map.
+pub fn loading_marker_update<T: Voxel>(
+    mut commands: Commands,
+    origin: Res<FloatingOrigin>,
+    mut markers: ResMut<LoadingMarkers>,
+    mut maps: Query<(&Map<T>, &MapUpdates)>,
+    markers_query: Query<(&mut LoadingMarker, &mut Translation)>,
+) {
+    let mut maps = maps.iter();
+    let (map, updates) = if let Some(map) = (&mut maps).into_iter().next() {
+        map
+    } else {
+        return;
+    };
+    let width = if let Some(layout) = map.layout() {
+        layout.chunk_width as f32
+    } else {
+        return;
+    };
+    let stages = loading_stages(map, updates);
+    let center = |coords| origin.to_local(coords) + Vec3::splat(width * 0.5);
+
+    markers.entities.retain(|coords, &mut entity| {
+        if let Some(&stage) = stages.get(coords) {
+            if let Ok(mut marker) = markers_query.get_mut::<LoadingMarker>(entity) {
+                marker.stage = stage;
+            }
+            if let Ok(mut translation) = markers_query.get_mut::<Translation>(entity) {
+                translation.0 = center(*coords);
+            }
+            true
+        } else {
+            commands.despawn(entity);
+            false
+        }
+    });
+    for (coords, stage) in stages {
+        if markers.entities.contains_key(&coords) {
+            continue;
+        }
+        commands.spawn((
+            LoadingMarker { coords, stage },
+            Transform::default(),
+            Translation(center(coords)),
+        ));
+        if let Some(entity) = commands.current_entity() {
+            markers.entities.insert(coords, entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::map;
+
+    #[test]
+    pub fn loading_stages() {
+        let mut map = map();
+        map.get_mut((4, 0, 0)).unwrap().set_entity(Entity::new());
+        let mut updates = MapUpdates::default();
+        updates.request((8, 0, 0), ChunkUpdate::GenerateChunk);
+        updates.request((0, 0, 0), ChunkUpdate::UpdateLightMap);
+        updates.request((-4, 0, 0), ChunkUpdate::UpdateMesh);
+        // rendered chunks keep showing their old mesh while they're updated
+        updates.request((4, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.request_prefetch((12, 0, 0));
+
+        let stages = super::loading_stages(&map, &updates);
+        assert_eq!(stages.len(), 4);
+        assert_eq!(stages[&(8, 0, 0)], LoadingStage::Generating);
+        assert_eq!(stages[&(0, 0, 0)], LoadingStage::Lighting);
+        assert_eq!(stages[&(-4, 0, 0)], LoadingStage::Meshing);
+        assert_eq!(stages[&(12, 0, 0)], LoadingStage::Queued);
+    }
+}
@@ -1,37 +1,166 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use bevy::{
     prelude::*,
     render::{camera::ActiveCameras, render_graph::base},
     transform::prelude::Translation,
 };
 
-use crate::{collections::lod_tree::Voxel, world::{Map, MapUpdates, ChunkUpdate}};
+use crate::{
+    collections::lod_tree::Voxel,
+    render::{light::LightingMode, origin::FloatingOrigin},
+    world::{ChunkState, ChunkUpdate, Map, MapUpdates, UpdateCause},
+};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodConfig {
+    /// The distance from the camera at which the level of detail increases by one.
+    pub distance: i32,
+    /// The highest level of detail that still gets shaded lighting in `LightingMode::Auto`.
+    pub max_shaded_lod: usize,
+    /// How far past a lod boundary the camera has to move before a meshed chunk switches to
+    /// the new level of detail, so that chunks near a boundary don't keep switching back
+    /// and forth.
+    pub hysteresis: i32,
+    /// How much the vertex colors of chunks with a level of detail above `0` are jittered, see
+    /// `MeshBuffers::dither`. `0.0` turns dithering off.
+    pub dither: f32,
+    /// How much closer than they are chunks with more `Chunk::detail` than `flat_detail` are
+    /// treated when picking their level of detail, so that e.g. buildings keep their shape
+    /// farther away than flat terrain. `0.0` only goes by distance.
+    pub detail_bias: f32,
+    /// The `Chunk::detail` up to which chunks are treated as flat terrain.
+    pub flat_detail: f32,
+    /// The level of detail from which chunk meshes are simplified, see
+    /// `MeshBuffers::simplify`.
+    pub simplify_lod: usize,
+    /// How far simplified meshes may be off, relative to their distance from the camera,
+    /// which is roughly the angle in radians they may be off by on screen. `0.0` turns
+    /// simplification off.
+    pub simplify_error: f32,
+}
+
+impl Default for LodConfig {
+    fn default() -> Self {
+        Self {
+            distance: 128,
+            max_shaded_lod: 1,
+            hysteresis: 16,
+            dither: 0.0,
+            detail_bias: 0.25,
+            flat_detail: 4.0,
+            simplify_lod: 3,
+            simplify_error: 0.0,
+        }
+    }
+}
+
+impl LodConfig {
+    /// Returns the level of detail of a chunk at `distance` from the camera.
+    pub fn lod(&self, distance: i32) -> usize {
+        (distance / self.distance) as usize
+    }
+
+    /// Returns `distance` shortened by `detail_bias` for a chunk with `detail`, see
+    /// `Chunk::detail`.
+    pub fn detail_distance(&self, distance: i32, detail: f32) -> i32 {
+        let bias = 1.0 + self.detail_bias * (detail - self.flat_detail).max(0.0);
+        (distance as f32 / bias) as i32
+    }
+
+    /// Returns the `max_error` to simplify the meshes of chunks with `lod` by, or `None` if
+    /// they aren't simplified.
+    pub fn simplification(&self, lod: usize) -> Option<f32> {
+        if lod < self.simplify_lod || self.simplify_error <= 0.0 {
+            return None;
+        }
+        Some(self.simplify_error * (lod as i32 * self.distance) as f32)
+    }
+
+    /// Returns the level of detail a chunk at `distance` should switch to from `current`,
+    /// which only changes once the distance is `hysteresis` past the boundary.
+    pub fn next_lod(&self, distance: i32, current: usize) -> usize {
+        let far = self.lod((distance - self.hysteresis).max(0));
+        let near = self.lod(distance + self.hysteresis);
+        if far > current {
+            far
+        } else if near < current {
+            near
+        } else {
+            current
+        }
+    }
+}
 
 pub fn lod_update<T: Voxel>(
     camera: Res<ActiveCameras>,
+    origin: Res<FloatingOrigin>,
+    config: Res<LodConfig>,
+    mode: Res<LightingMode>,
     mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
     translation: Query<&Translation>,
 ) {
-    let (camera_x, camera_y, camera_z) = if let Some(camera) = camera.get(base::camera::CAMERA3D) {
-        let position = translation.get::<Translation>(camera).unwrap();
-        (
-            position.0.x() as i32,
-            position.0.y() as i32,
-            position.0.z() as i32,
-        )
-    } else {
-        (0, 0, 0)
-    };
+    let (camera_x, camera_y, camera_z) = camera
+        .get(base::camera::CAMERA3D)
+        .and_then(|camera| translation.get::<Translation>(camera).ok())
+        .map(|position| origin.to_world(position.0))
+        .unwrap_or(origin.origin);
     for (mut map, mut update) in &mut query.iter() {
         for chunk in &mut map.iter_mut() {
             let (x, y, z) = chunk.position();
-            let lod = ((camera_x - x).abs() / 128)
-                .max((camera_y - y).abs() / 128)
-                .max((camera_z - z).abs() / 128) as usize;
+            let distance = (camera_x - x)
+                .abs()
+                .max((camera_y - y).abs())
+                .max((camera_z - z).abs());
+            let distance = config.detail_distance(distance, chunk.detail());
             let old_lod = chunk.lod();
+            // chunks that haven't been meshed yet will pick up the new lod anyway
+            let meshed = chunk.state() == ChunkState::Meshed;
+            let lod = if meshed {
+                config.next_lod(distance, old_lod)
+            } else {
+                config.lod(distance)
+            };
             chunk.set_lod(lod);
-            if lod != old_lod && !update.updates.contains_key(&(x, y, z)) {
-                update.updates.insert((x, y, z), ChunkUpdate::UpdateMesh);
+            if lod != old_lod && meshed {
+                let stage = if mode.is_shaded(&config, lod) != mode.is_shaded(&config, old_lod) {
+                    ChunkUpdate::UpdateLightMap
+                } else {
+                    ChunkUpdate::UpdateMesh
+                };
+                update.request_because((x, y, z), stage, UpdateCause::Lod);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn next_lod() {
+        let config = LodConfig {
+            distance: 128,
+            hysteresis: 16,
+            ..Default::default()
+        };
+        // moving away switches to the farther lod only once past the boundary at 128
+        assert_eq!(config.next_lod(143, 0), 0);
+        assert_eq!(config.next_lod(144, 0), 1);
+        // moving closer switches back only once as far inside it
+        assert_eq!(config.next_lod(112, 1), 1);
+        assert_eq!(config.next_lod(111, 1), 0);
+        // inside the band either lod stays
+        for distance in 112..144 {
+            assert_eq!(config.next_lod(distance, 0), 0);
+            assert_eq!(config.next_lod(distance, 1), 1);
+        }
+        // a chunk far from its lod jumps right to the new one
+        assert_eq!(config.next_lod(1000, 0), 7);
+        assert_eq!(config.next_lod(0, 7), 0);
+    }
+}
@@ -3,7 +3,106 @@ use bevy::{
     render::{renderer::RenderResources, shader::ShaderDefs},
 };
 
+use crate::render::point_light::pack_point_lights;
+
 #[derive(RenderResources, ShaderDefs)]
 pub struct VoxelMaterial {
     pub albedo: Color,
+    /// Renders the material as a translucent preview.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub ghost: bool,
+    /// Treats `albedo` and the vertex colors as sRGB and converts them to linear in the
+    /// fragment shader, so blocks look like their authored colors on an sRGB target. Off by
+    /// default, which keeps the look of games that picked their colors without it.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub srgb_colors: bool,
+    /// Compresses colors above `1.0`, e.g. of emissive voxels, into the displayable range with
+    /// Reinhard tone mapping instead of clipping them. Leave this off when rendering to an HDR
+    /// target, so bloom can pick them up.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub tonemap: bool,
+    /// Applies the angle between faces and `light_direction` in the fragment shader instead
+    /// of baking it into the vertex shades, so the light can rotate without relighting any
+    /// chunk. Use it with `FaceShading::Shader` and `shader_light_update`.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub shader_light: bool,
+    /// Multiplies the vertex shades by `face_shades` by the `Voxel_FaceId` of every vertex,
+    /// which is cheaper than `shader_light` and changes with the light without remeshing.
+    /// Use it with `FaceShading::Shader`, meshes generated with `MeshAttributes::face_ids` and
+    /// `shader_light_update`.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub face_shading: bool,
+    /// Ignores the vertex shades and draws every face at full brightness, for
+    /// `LightingMode::Unlit`. `unlit_mode_update` keeps it in sync with the lighting mode.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub unlit: bool,
+    /// Adds the light of up to `MAX_POINT_LIGHTS` `VoxelPointLight`s in the fragment shader,
+    /// kept in `point_light_data` by `point_light_update`.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub point_lights: bool,
+    /// Grades the lit colors with `grading_saturation`, `grading_multiply` and
+    /// `grading_offset`, kept in sync with the map's `ColorGrading` by
+    /// `color_grading_update`.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub color_grading: bool,
+    /// Replaces `ambient_intensity` with `ambient_sky` in the shader, set by
+    /// `shader_light_update` if the `AmbientLight` has a `SkyAmbient`.
+    #[render_resources(ignore)]
+    #[shader_def]
+    pub sky_ambient: bool,
+    /// The direction and intensity of the directional light and the intensity of the ambient
+    /// light for `shader_light`, kept in sync with the light resources by
+    /// `shader_light_update`.
+    pub light_direction: Vec3,
+    pub light_intensity: f32,
+    pub ambient_intensity: f32,
+    /// Scales the color of every voxel by a random factor between `1.0 - color_variation`
+    /// and `1.0 + color_variation`, see `voxel_random`, so e.g. grass isn't one flat color.
+    pub color_variation: f32,
+    /// The part of the directional light every face gets for `face_shading`, in the order of
+    /// `Face::ALL` and padded to 8 values, kept in sync by `shader_light_update`.
+    pub face_shades: Vec<f32>,
+    /// The packed point lights for `point_lights`, see `pack_point_lights`.
+    pub point_light_data: Vec<f32>,
+    pub grading_multiply: Color,
+    pub grading_offset: Color,
+    pub grading_saturation: f32,
+    /// The ambient light from the sky, the horizon and the ground for `sky_ambient`, blended
+    /// by the normal of every fragment.
+    pub ambient_sky: Vec3,
+}
+
+impl Default for VoxelMaterial {
+    fn default() -> Self {
+        Self {
+            albedo: Color::WHITE,
+            ghost: false,
+            srgb_colors: false,
+            tonemap: false,
+            shader_light: false,
+            face_shading: false,
+            unlit: false,
+            point_lights: false,
+            color_grading: false,
+            sky_ambient: false,
+            light_direction: Vec3::new(0.0, -1.0, 0.0),
+            light_intensity: 1.0,
+            ambient_intensity: 0.0,
+            color_variation: 0.0,
+            face_shades: vec![1.0; 8],
+            point_light_data: pack_point_lights(&[]),
+            grading_multiply: Color::WHITE,
+            grading_offset: Color::rgba(0.0, 0.0, 0.0, 0.0),
+            grading_saturation: 1.0,
+            ambient_sky: Vec3::zero(),
+        }
+    }
 }
@@ -3,29 +3,78 @@ use bevy::{
     render::{render_graph::RenderGraph, shader},
 };
 
-use self::material::VoxelMaterial;
+use self::{
+    light::{FaceShading, LightingMode, LightingRegions},
+    impostor::ImpostorConfig, loading::LoadingMarkers, lod::LodConfig, material::VoxelMaterial,
+    origin::FloatingOrigin, pool::ChunkPool,
+    render_graph::pipeline::{PipelineSettings, VoxelShaders},
+};
+use crate::world::MergePolicy;
 
+pub mod debug;
 pub mod entity;
+pub mod ghost;
+pub mod grading;
+pub mod highlight;
+pub mod impostor;
+pub mod layer;
 pub mod light;
+pub mod loading;
 pub mod lod;
 pub mod material;
+pub mod origin;
+pub mod overlay;
+pub mod point_light;
+pub mod pool;
 pub mod render_graph;
 
 pub mod prelude {
-    pub use super::{entity::ChunkRenderComponents, material::VoxelMaterial, VoxelRenderPlugin};
+    pub use super::{
+        debug::{ChunkGrid, ChunkGridComponents, ChunkPicker},
+        entity::ChunkRenderComponents,
+        ghost::{GhostBlock, GhostBlockComponents},
+        grading::ColorGrading,
+        highlight::{Highlight, HighlightComponents, HighlightShape},
+        impostor::{ImpostorConfig, Impostors},
+        layer::MapLayer,
+        light::{FaceShading, LightQuality, LightingMode, LightingRegions},
+        loading::{LoadingMarker, LoadingMarkers, LoadingStage},
+        lod::LodConfig,
+        material::VoxelMaterial,
+        origin::FloatingOrigin,
+        overlay::OverlayRender,
+        point_light::VoxelPointLight,
+        pool::ChunkPool,
+        render_graph::pipeline::{PipelineSettings, ShaderSnippets, ShaderSource, VoxelShaders},
+        VoxelRenderPlugin,
+    };
 }
 
 #[derive(Debug, Default)]
-pub struct VoxelRenderPlugin;
+pub struct VoxelRenderPlugin {
+    /// Custom shaders or shader snippets for effects like dissolving or team colors.
+    pub shaders: VoxelShaders,
+    pub pipeline: PipelineSettings,
+}
 
 impl Plugin for VoxelRenderPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_asset::<VoxelMaterial>().add_system_to_stage(
-            stage::POST_UPDATE,
-            shader::asset_shader_defs_system::<VoxelMaterial>.system(),
-        );
+        app.add_asset::<VoxelMaterial>()
+            .init_resource::<FloatingOrigin>()
+            .init_resource::<LightingMode>()
+            .init_resource::<FaceShading>()
+            .init_resource::<LightingRegions>()
+            .init_resource::<MergePolicy>()
+            .init_resource::<LodConfig>()
+            .init_resource::<ImpostorConfig>()
+            .init_resource::<LoadingMarkers>()
+            .init_resource::<ChunkPool>()
+            .add_system_to_stage(
+                stage::POST_UPDATE,
+                shader::asset_shader_defs_system::<VoxelMaterial>.system(),
+            );
         let resources = app.resources();
         let mut render_graph = resources.get_mut::<RenderGraph>().unwrap();
-        render_graph::add_voxel_graph(&mut render_graph, resources);
+        render_graph::add_voxel_graph(&mut render_graph, resources, &self.shaders, &self.pipeline);
     }
 }
@@ -0,0 +1,146 @@
+use bevy::{
+    prelude::*,
+    render::{camera::ActiveCameras, render_graph::base},
+    transform::prelude::Translation,
+};
+
+use crate::{collections::lod_tree::Voxel, world::Map};
+
+/// The world position of the render space origin.
+///
+/// Voxel data is always addressed in `i32` world coordinates, while entity translations are
+/// relative to `origin`, so that they stay small no matter how far the camera travels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatingOrigin {
+    pub origin: (i32, i32, i32),
+    /// How far the camera may move from the origin before it is shifted.
+    pub threshold: f32,
+}
+
+impl Default for FloatingOrigin {
+    fn default() -> Self {
+        Self {
+            origin: (0, 0, 0),
+            threshold: 4096.0,
+        }
+    }
+}
+
+impl FloatingOrigin {
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the origin as a render space offset.
+    pub fn offset(&self) -> Vec3 {
+        let (ox, oy, oz) = self.origin;
+        Vec3::new(ox as f32, oy as f32, oz as f32)
+    }
+
+    /// Converts world coordinates into a render space position.
+    pub fn to_local(&self, (x, y, z): (i32, i32, i32)) -> Vec3 {
+        let (ox, oy, oz) = self.origin;
+        Vec3::new((x - ox) as f32, (y - oy) as f32, (z - oz) as f32)
+    }
+
+    /// Converts a render space position into world coordinates.
+    pub fn to_world(&self, position: Vec3) -> (i32, i32, i32) {
+        let (ox, oy, oz) = self.origin;
+        (
+            position.x().floor() as i32 + ox,
+            position.y().floor() as i32 + oy,
+            position.z().floor() as i32 + oz,
+        )
+    }
+
+    /// Returns how far the origin has to move, in whole chunks `width` voxels wide, to bring
+    /// a camera at render space `position` back close to it, or `None` if the camera is still
+    /// within `threshold`.
+    pub fn shift(&self, position: Vec3, width: usize) -> Option<(i32, i32, i32)> {
+        if position.x().abs() < self.threshold
+            && position.y().abs() < self.threshold
+            && position.z().abs() < self.threshold
+        {
+            return None;
+        }
+        // shift by whole chunks so that chunk translations stay integral
+        let width = width as f32;
+        let shift = |p: f32| ((p / width).round() * width) as i32;
+        Some((shift(position.x()), shift(position.y()), shift(position.z())))
+    }
+}
+
+pub fn floating_origin_update<T: Voxel>(
+    mut origin: ResMut<FloatingOrigin>,
+    camera: Res<ActiveCameras>,
+    mut maps: Query<&Map<T>>,
+    translations: Query<&mut Translation>,
+) {
+    let camera = if let Some(camera) = camera.get(base::camera::CAMERA3D) {
+        camera
+    } else {
+        return;
+    };
+    let position = if let Ok(translation) = translations.get::<Translation>(camera) {
+        translation.0
+    } else {
+        return;
+    };
+
+    let mut width = None;
+    for map in &mut maps.iter() {
+        if let Some(chunk) = map.iter().next() {
+            width = Some(chunk.width());
+            break;
+        }
+    }
+    let (dx, dy, dz) = match width.and_then(|width| origin.shift(position, width)) {
+        Some(shift) => shift,
+        None => return,
+    };
+    let delta = Vec3::new(dx as f32, dy as f32, dz as f32);
+
+    for map in &mut maps.iter() {
+        for chunk in map.iter() {
+            for entity in chunk.entity().into_iter().chain(chunk.transparent_entity()) {
+                if let Ok(mut translation) = translations.get_mut::<Translation>(entity) {
+                    translation.0 -= delta;
+                }
+            }
+        }
+    }
+    if let Ok(mut translation) = translations.get_mut::<Translation>(camera) {
+        translation.0 -= delta;
+    }
+
+    let (ox, oy, oz) = origin.origin;
+    origin.origin = (ox + dx, oy + dy, oz + dz);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn floating_origin() {
+        let mut origin = FloatingOrigin::new(100.0);
+        assert_eq!(origin.shift(Vec3::new(99.0, -99.0, 0.0), 16), None);
+
+        // the camera is moved back by whole chunks
+        let camera = Vec3::new(150.0, 20.0, -330.0);
+        let (dx, dy, dz) = origin.shift(camera, 16).unwrap();
+        assert_eq!((dx, dy, dz), (144, 16, -336));
+        let world = origin.to_world(camera);
+        origin.origin = (dx, dy, dz);
+        let camera = camera - Vec3::new(dx as f32, dy as f32, dz as f32);
+        assert_eq!(origin.shift(camera, 16), None);
+
+        // voxels keep their world coordinates
+        assert_eq!(origin.to_world(camera), world);
+        assert_eq!(origin.to_local((144, 16, -336)), Vec3::zero());
+        assert_eq!(origin.offset(), Vec3::new(144.0, 16.0, -336.0));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, render::draw::Draw, transform::prelude::Translation};
+
+use crate::{
+    mesh,
+    render::{
+        entity::{buffers_to_mesh, ChunkRenderComponents, MeshOrigin, VoxelExt},
+        material::VoxelMaterial,
+        origin::FloatingOrigin,
+    },
+    world::{ChunkUpdate, Map, MapUpdates},
+};
+
+/// A layer id and the origin of one of its chunks.
+type OverlayChunk = (u32, (i32, i32, i32));
+
+/// The materials and render entities of the overlay layers of a map, see `OverlayLayer`.
+///
+/// Add it to the map's entity to have `overlay_update` draw the overlays. Layers without a
+/// material of their own are drawn with a translucent `ghost` material.
+#[derive(Default)]
+pub struct OverlayRender {
+    materials: HashMap<u32, Handle<VoxelMaterial>>,
+    ghost: Option<Handle<VoxelMaterial>>,
+    /// The render entities of every layer and chunk origin.
+    entities: HashMap<OverlayChunk, Vec<Entity>>,
+}
+
+impl OverlayRender {
+    pub fn with_material(mut self, id: u32, material: Handle<VoxelMaterial>) -> Self {
+        self.set_material(id, material);
+        self
+    }
+
+    /// Draws the layer `id` with `material` from the next time its chunks are meshed.
+    pub fn set_material(&mut self, id: u32, material: Handle<VoxelMaterial>) {
+        self.materials.insert(id, material);
+    }
+}
+
+/// Meshes the chunks of overlay layers that changed, or whose terrain is about to be meshed,
+/// and shows or hides the render entities of every layer.
+pub fn overlay_update<T: VoxelExt>(
+    mut commands: Commands,
+    origin: Res<FloatingOrigin>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut maps: Query<(&mut Map<T>, &MapUpdates, &mut OverlayRender)>,
+    draws: Query<&mut Draw>,
+) {
+    for (mut map, updates, mut render) in &mut maps.iter() {
+        let remeshed = updates
+            .iter_kind(ChunkUpdate::UpdateMesh)
+            .collect::<Vec<_>>();
+        let mut dirty = Vec::new();
+        for (id, layer) in map.overlays_mut() {
+            for &position in &remeshed {
+                layer.mark_dirty(position);
+            }
+            dirty.extend(
+                layer
+                    .take_dirty()
+                    .into_iter()
+                    .map(|position| (id, position)),
+            );
+        }
+        // layers that were removed from the map
+        let removed = render
+            .entities
+            .keys()
+            .filter(|(id, _)| map.overlay(*id).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        dirty.extend(removed);
+
+        for (id, position) in dirty {
+            for e in render.entities.remove(&(id, position)).unwrap_or_default() {
+                commands.despawn(e);
+            }
+            let layer = match map.overlay(id) {
+                Some(layer) => layer,
+                None => continue,
+            };
+            let overlay = match layer.chunk(position) {
+                Some(overlay) => overlay,
+                None => continue,
+            };
+            let material = match render.materials.get(&id) {
+                Some(material) => *material,
+                None => *render.ghost.get_or_insert_with(|| {
+                    materials.add(VoxelMaterial {
+                        ghost: true,
+                        ..Default::default()
+                    })
+                }),
+            };
+
+            let (opaque, transparent) =
+                mesh::generate_overlay_buffers(&map, position, overlay, MeshOrigin::Corner);
+            let mut entities = Vec::new();
+            for (buffers, is_transparent) in opaque
+                .into_iter()
+                .map(|buffers| (buffers, false))
+                .chain(transparent.into_iter().map(|buffers| (buffers, true)))
+            {
+                let e = Entity::new();
+                commands.spawn_as_entity(
+                    e,
+                    ChunkRenderComponents {
+                        mesh: meshes.add(buffers_to_mesh(buffers)),
+                        material,
+                        draw: Draw {
+                            is_visible: layer.is_visible(),
+                            is_transparent,
+                            ..Default::default()
+                        },
+                        translation: Translation(origin.to_local(position)),
+                        ..Default::default()
+                    },
+                );
+                entities.push(e);
+            }
+            render.entities.insert((id, position), entities);
+        }
+
+        for (&(id, _), entities) in &render.entities {
+            let visible = map.overlay(id).map_or(false, |layer| layer.is_visible());
+            for &e in entities {
+                if let Ok(mut draw) = draws.get_mut::<Draw>(e) {
+                    draw.is_visible = visible;
+                }
+            }
+        }
+    }
+}
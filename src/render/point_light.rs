@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+
+use bevy::{
+    prelude::*,
+    render::{camera::ActiveCameras, render_graph::base},
+};
+
+use crate::render::material::VoxelMaterial;
+
+/// How many point lights `point_light_update` passes to the shader, which loops over as many.
+pub const MAX_POINT_LIGHTS: usize = 4;
+
+/// The number of floats every light takes in `VoxelMaterial::point_light_data`.
+const POINT_LIGHT_FLOATS: usize = 8;
+
+/// A light that moves with its entity's `Transform`, e.g. a torch in the player's hand.
+///
+/// Point lights are added to the baked shades in the fragment shader of materials with
+/// `VoxelMaterial::point_lights`, so moving them doesn't relight any chunk. They don't cast
+/// shadows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelPointLight {
+    pub color: Color,
+    pub intensity: f32,
+    /// How far the light reaches, in voxels. It fades out towards the edge.
+    pub radius: f32,
+}
+
+impl Default for VoxelPointLight {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(1.0, 0.8, 0.6),
+            intensity: 1.0,
+            radius: 8.0,
+        }
+    }
+}
+
+/// Packs `lights` into the layout of `VoxelMaterial::point_lights`: the position and radius,
+/// then the color and intensity of every light, padded with lights of radius `0.0`.
+pub fn pack_point_lights(lights: &[(Vec3, VoxelPointLight)]) -> Vec<f32> {
+    let mut packed = Vec::with_capacity(MAX_POINT_LIGHTS * POINT_LIGHT_FLOATS);
+    for (position, light) in lights.iter().take(MAX_POINT_LIGHTS) {
+        packed.extend(&[position.x(), position.y(), position.z(), light.radius]);
+        packed.extend(&[light.color.r, light.color.g, light.color.b, light.intensity]);
+    }
+    packed.resize(MAX_POINT_LIGHTS * POINT_LIGHT_FLOATS, 0.0);
+    packed
+}
+
+/// Sorts `lights` by their distance to `camera`, nearest first.
+pub fn sort_point_lights(lights: &mut [(Vec3, VoxelPointLight)], camera: Vec3) {
+    lights.sort_by(|(a, _), (b, _)| {
+        let a = (*a - camera).length_squared();
+        let b = (*b - camera).length_squared();
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Copies the `MAX_POINT_LIGHTS` point lights nearest to the camera into every
+/// `VoxelMaterial` with `point_lights`. The others are ignored.
+pub fn point_light_update(
+    cameras: Res<ActiveCameras>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut lights: Query<(&VoxelPointLight, &Transform)>,
+    transforms: Query<&Transform>,
+) {
+    // the global transform, so lights parented to e.g. the player are where they're drawn
+    let mut found = Vec::new();
+    for (light, transform) in &mut lights.iter() {
+        found.push((transform.value.w_axis().truncate(), *light));
+    }
+    let camera = cameras
+        .get(base::camera::CAMERA3D)
+        .and_then(|camera| transforms.get::<Transform>(camera).ok())
+        .map(|transform| transform.value.w_axis().truncate());
+    if let Some(camera) = camera {
+        sort_point_lights(&mut found, camera);
+    }
+    let packed = pack_point_lights(&found);
+
+    // only touch materials that changed, so their uniforms aren't uploaded every frame
+    let stale = materials
+        .iter()
+        .filter(|(_, material)| material.point_lights && material.point_light_data != packed)
+        .map(|(handle, _)| handle)
+        .collect::<Vec<_>>();
+    for handle in stale {
+        if let Some(material) = materials.get_mut(&handle) {
+            material.point_light_data.clone_from(&packed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn pack() {
+        let torch = VoxelPointLight {
+            color: Color::rgb(1.0, 0.5, 0.25),
+            intensity: 2.0,
+            radius: 6.0,
+        };
+        let packed = pack_point_lights(&[(Vec3::new(1.0, 2.0, 3.0), torch)]);
+        assert_eq!(packed.len(), MAX_POINT_LIGHTS * POINT_LIGHT_FLOATS);
+        assert_eq!(
+            &packed[..POINT_LIGHT_FLOATS],
+            &[1.0, 2.0, 3.0, 6.0, 1.0, 0.5, 0.25, 2.0]
+        );
+        // the shader skips lights of radius 0
+        assert!(packed[POINT_LIGHT_FLOATS..].iter().all(|&f| f == 0.0));
+
+        let many = (0..6)
+            .map(|i| (Vec3::new(i as f32, 0.0, 0.0), torch))
+            .collect::<Vec<_>>();
+        let packed = pack_point_lights(&many);
+        assert_eq!(packed.len(), MAX_POINT_LIGHTS * POINT_LIGHT_FLOATS);
+        let last = (MAX_POINT_LIGHTS - 1) * POINT_LIGHT_FLOATS;
+        assert_eq!(packed[last], (MAX_POINT_LIGHTS - 1) as f32);
+    }
+
+    #[test]
+    pub fn nearest_lights() {
+        let bright = VoxelPointLight {
+            intensity: 10.0,
+            radius: 16.0,
+            ..Default::default()
+        };
+        let dim = VoxelPointLight::default();
+        let mut lights = vec![
+            (Vec3::new(40.0, 0.0, 0.0), bright),
+            (Vec3::new(0.0, 0.0, -3.0), dim),
+            (Vec3::new(10.0, 0.0, 0.0), bright),
+            (Vec3::new(1.0, 1.0, 0.0), dim),
+        ];
+        sort_point_lights(&mut lights, Vec3::new(0.0, 1.0, 0.0));
+        let order = lights.iter().map(|(p, _)| p.x()).collect::<Vec<_>>();
+        assert_eq!(order, vec![1.0, 0.0, 10.0, 40.0]);
+    }
+}
@@ -0,0 +1,201 @@
+use bevy::{prelude::*, render::draw::Draw, transform::prelude::Translation};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    render::{entity::ChunkRenderComponents, material::VoxelMaterial},
+    world::Chunk,
+};
+
+/// Hidden render entities of chunks that were unloaded, reused with their mesh and material
+/// handles for chunks that are meshed later, instead of spawning entities and adding assets
+/// for every chunk that streams in.
+///
+/// At most `capacity` entities are kept, the ones released beyond that are despawned and
+/// their meshes freed.
+#[derive(Debug)]
+pub struct ChunkPool {
+    free: Vec<Entity>,
+    capacity: usize,
+    reused: usize,
+    spawned: usize,
+}
+
+impl Default for ChunkPool {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl ChunkPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            free: Vec::new(),
+            capacity,
+            reused: 0,
+            spawned: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of hidden entities waiting to be reused.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// How many entities `acquire` reused so far.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    /// How many entities `acquire` spawned so far, because the pool was empty.
+    pub fn spawned(&self) -> usize {
+        self.spawned
+    }
+
+    /// Hides the render entities of `chunk`, usually one just removed from its map, and keeps
+    /// them for `acquire`.
+    pub fn release<T: Voxel>(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        entities: &Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+        chunk: &Chunk<T>,
+    ) {
+        for e in chunk.entity().into_iter().chain(chunk.transparent_entity()) {
+            self.release_entity(commands, meshes, entities, e);
+        }
+    }
+
+    /// Hides the render entity `e` and keeps it for `acquire`, or despawns it and frees its
+    /// mesh if the pool is full.
+    pub fn release_entity(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        entities: &Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+        e: Entity,
+    ) {
+        if !self.is_full() {
+            if let Ok(mut draw) = entities.get_mut::<Draw>(e) {
+                draw.is_visible = false;
+                self.free.push(e);
+                return;
+            }
+        }
+        if let Ok(mesh) = entities.get::<Handle<Mesh>>(e) {
+            meshes.remove(&mesh);
+        }
+        commands.despawn(e);
+    }
+
+    /// Returns a render entity showing `mesh` at `translation`, a pooled one whose mesh is
+    /// replaced by `mesh` or a new one with a default `VoxelMaterial` if the pool is empty.
+    #[allow(clippy::too_many_arguments)]
+    pub fn acquire(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<VoxelMaterial>,
+        entities: &Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+        mesh: Mesh,
+        translation: Translation,
+        is_transparent: bool,
+    ) -> Entity {
+        let reusable = self.take(|e| entities.get::<Handle<Mesh>>(e).ok().map(|handle| *handle));
+        if let Some((e, handle)) = reusable {
+            if let Some(old_mesh) = meshes.get_mut(&handle) {
+                *old_mesh = mesh;
+            } else {
+                commands.insert_one(e, meshes.add(mesh));
+            }
+            if let Ok(mut draw) = entities.get_mut::<Draw>(e) {
+                draw.is_visible = true;
+                draw.is_transparent = is_transparent;
+            }
+            if let Ok(mut old_translation) = entities.get_mut::<Translation>(e) {
+                *old_translation = translation;
+            }
+            return e;
+        }
+
+        let e = Entity::new();
+        commands.spawn_as_entity(
+            e,
+            ChunkRenderComponents {
+                mesh: meshes.add(mesh),
+                material: materials.add(VoxelMaterial::default()),
+                draw: Draw {
+                    is_transparent,
+                    ..Default::default()
+                },
+                translation,
+                ..Default::default()
+            },
+        );
+        self.spawned += 1;
+        e
+    }
+
+    fn is_full(&self) -> bool {
+        self.free.len() >= self.capacity
+    }
+
+    /// Takes the most recently released entity and its mesh handle, dropping the entities
+    /// the game despawned since they were released.
+    fn take(
+        &mut self,
+        mesh_of: impl Fn(Entity) -> Option<Handle<Mesh>>,
+    ) -> Option<(Entity, Handle<Mesh>)> {
+        while let Some(e) = self.free.pop() {
+            if let Some(handle) = mesh_of(e) {
+                self.reused += 1;
+                return Some((e, handle));
+            }
+        }
+        None
+    }
+
+    /// Despawns every pooled entity and frees its mesh.
+    pub fn clear(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        entities: &Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+    ) {
+        for e in self.free.drain(..) {
+            if let Ok(mesh) = entities.get::<Handle<Mesh>>(e) {
+                meshes.remove(&mesh);
+            }
+            commands.despawn(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn chunk_pool() {
+        let mut pool = ChunkPool::new(3);
+        assert!(pool.is_empty() && !pool.is_full());
+        pool.free = (1..=3).map(Entity::from_id).collect();
+        assert!(pool.is_full());
+
+        // entity 3 was despawned by the game since it was released
+        let handle = Handle::<Mesh>::from_u128(7);
+        let mesh_of = |e: Entity| if e.id() == 3 { None } else { Some(handle) };
+        assert_eq!(pool.take(mesh_of), Some((Entity::from_id(2), handle)));
+        assert_eq!(pool.take(mesh_of), Some((Entity::from_id(1), handle)));
+        assert_eq!(pool.take(mesh_of), None);
+        assert_eq!((pool.reused(), pool.spawned()), (2, 0));
+        assert!(pool.is_empty());
+    }
+}
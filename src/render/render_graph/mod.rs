@@ -3,7 +3,10 @@ use bevy::{
     ecs::Resources,
     render::{
         pipeline::PipelineDescriptor,
-        render_graph::{base, AssetRenderResourcesNode, RenderGraph, RenderResourcesNode},
+        render_graph::{
+            base::{self, Msaa},
+            AssetRenderResourcesNode, RenderGraph, RenderResourcesNode,
+        },
         shader::Shader,
     },
     transform::prelude::Transform,
@@ -20,7 +23,12 @@ pub mod node {
 
 pub mod uniform {}
 
-pub(crate) fn add_voxel_graph(graph: &mut RenderGraph, resources: &Resources) {
+pub(crate) fn add_voxel_graph(
+    graph: &mut RenderGraph,
+    resources: &Resources,
+    shaders: &pipeline::VoxelShaders,
+    settings: &pipeline::PipelineSettings,
+) {
     graph.add_system_node(node::TRANSFORM, RenderResourcesNode::<Transform>::new(true));
     graph
         .add_node_edge(node::TRANSFORM, base::node::MAIN_PASS)
@@ -34,10 +42,14 @@ pub(crate) fn add_voxel_graph(graph: &mut RenderGraph, resources: &Resources) {
         .add_node_edge(node::VOXEL_MATERIAL, base::node::MAIN_PASS)
         .unwrap();
 
-    let mut shaders = resources.get_mut::<Assets<Shader>>().unwrap();
+    let sample_count = settings
+        .sample_count
+        .or_else(|| resources.get::<Msaa>().map(|msaa| msaa.samples))
+        .unwrap_or(1);
+    let mut shader_assets = resources.get_mut::<Assets<Shader>>().unwrap();
     let mut pipelines = resources.get_mut::<Assets<PipelineDescriptor>>().unwrap();
     pipelines.set(
         pipeline::PIPELINE_HANDLE,
-        pipeline::build_pipeline(&mut shaders),
+        pipeline::build_pipeline(&mut shader_assets, shaders, settings, sample_count),
     );
 }
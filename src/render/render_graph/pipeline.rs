@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use bevy::{
     asset::{Assets, Handle},
     render::{
@@ -14,7 +16,115 @@ use bevy::{
 
 pub const PIPELINE_HANDLE: Handle<PipelineDescriptor> = Handle::from_bytes(*b"voxelpipeline000");
 
-pub(crate) fn build_pipeline(shaders: &mut Assets<Shader>) -> PipelineDescriptor {
+pub const VERTEX_SHADER: &str = include_str!("voxel_vs.glsl");
+pub const FRAGMENT_SHADER: &str = include_str!("voxel_fs.glsl");
+
+/// Multisampling and depth testing of the voxel pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineSettings {
+    /// Samples per pixel for antialiasing the hard edges of voxels. Has to match the sample
+    /// count of the main pass, so `None` uses the one of bevy's `Msaa` resource, or `1`
+    /// without it.
+    pub sample_count: Option<u32>,
+    pub depth_stencil: DepthStencilStateDescriptor,
+}
+
+impl Default for PipelineSettings {
+    fn default() -> Self {
+        Self {
+            sample_count: None,
+            depth_stencil: DepthStencilStateDescriptor {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilStateDescriptor {
+                    front: StencilStateFaceDescriptor::IGNORE,
+                    back: StencilStateFaceDescriptor::IGNORE,
+                    read_mask: 0,
+                    write_mask: 0,
+                },
+            },
+        }
+    }
+}
+
+/// A shader stage replacing one of the built-in voxel shaders.
+#[derive(Debug, Clone)]
+pub enum ShaderSource {
+    Glsl(Cow<'static, str>),
+    /// A shader that may be added to `Assets<Shader>` later, e.g. once it's loaded.
+    Handle(Handle<Shader>),
+}
+
+/// GLSL inserted into the built-in voxel shaders at their `// voxel:*` markers.
+///
+/// Declarations go before `main`, e.g. uniforms and helper functions. The vertex body runs
+/// after the outputs are set and may change `v_position`, `v_shade` and `v_color` before the
+/// vertex is projected, and the fragment body runs last and may change `o_Target`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShaderSnippets {
+    pub vertex_declarations: String,
+    pub vertex_main: String,
+    pub fragment_declarations: String,
+    pub fragment_main: String,
+}
+
+/// Selects the shaders of the voxel pipeline. Snippets are ignored for stages that are
+/// replaced.
+#[derive(Debug, Clone, Default)]
+pub struct VoxelShaders {
+    pub vertex: Option<ShaderSource>,
+    pub fragment: Option<ShaderSource>,
+    pub snippets: ShaderSnippets,
+}
+
+impl VoxelShaders {
+    /// Returns the source of the built-in vertex shader with the snippets inserted.
+    pub fn vertex_source(&self) -> String {
+        VERTEX_SHADER
+            .replace("// voxel:vertex_declarations", &self.snippets.vertex_declarations)
+            .replace("// voxel:vertex_main", &self.snippets.vertex_main)
+    }
+
+    /// Returns the source of the built-in fragment shader with the snippets inserted.
+    pub fn fragment_source(&self) -> String {
+        FRAGMENT_SHADER
+            .replace("// voxel:fragment_declarations", &self.snippets.fragment_declarations)
+            .replace("// voxel:fragment_main", &self.snippets.fragment_main)
+    }
+
+    fn stage(
+        shaders: &mut Assets<Shader>,
+        stage: ShaderStage,
+        source: &Option<ShaderSource>,
+        default: String,
+    ) -> Handle<Shader> {
+        match source {
+            Some(ShaderSource::Handle(handle)) => *handle,
+            Some(ShaderSource::Glsl(glsl)) => shaders.add(Shader::from_glsl(stage, glsl)),
+            None => shaders.add(Shader::from_glsl(stage, &default)),
+        }
+    }
+}
+
+pub(crate) fn build_pipeline(
+    shaders: &mut Assets<Shader>,
+    config: &VoxelShaders,
+    settings: &PipelineSettings,
+    sample_count: u32,
+) -> PipelineDescriptor {
+    let vertex = VoxelShaders::stage(
+        shaders,
+        ShaderStage::Vertex,
+        &config.vertex,
+        config.vertex_source(),
+    );
+    let fragment = VoxelShaders::stage(
+        shaders,
+        ShaderStage::Fragment,
+        &config.fragment,
+        config.fragment_source(),
+    );
     PipelineDescriptor {
         index_format: IndexFormat::Uint32,
         rasterization_state: Some(RasterizationStateDescriptor {
@@ -25,17 +135,7 @@ pub(crate) fn build_pipeline(shaders: &mut Assets<Shader>) -> PipelineDescriptor
             depth_bias_clamp: 0.0,
             clamp_depth: false,
         }),
-        depth_stencil_state: Some(DepthStencilStateDescriptor {
-            format: TextureFormat::Depth32Float,
-            depth_write_enabled: true,
-            depth_compare: CompareFunction::Less,
-            stencil: StencilStateDescriptor {
-                front: StencilStateFaceDescriptor::IGNORE,
-                back: StencilStateFaceDescriptor::IGNORE,
-                read_mask: 0,
-                write_mask: 0,
-            },
-        }),
+        depth_stencil_state: Some(settings.depth_stencil.clone()),
         color_states: vec![ColorStateDescriptor {
             format: TextureFormat::Bgra8UnormSrgb,
             color_blend: BlendDescriptor {
@@ -50,15 +150,56 @@ pub(crate) fn build_pipeline(shaders: &mut Assets<Shader>) -> PipelineDescriptor
             },
             write_mask: ColorWrite::ALL,
         }],
+        sample_count,
         ..PipelineDescriptor::new(ShaderStages {
-            vertex: shaders.add(Shader::from_glsl(
-                ShaderStage::Vertex,
-                include_str!("voxel_vs.glsl"),
-            )),
-            fragment: Some(shaders.add(Shader::from_glsl(
-                ShaderStage::Fragment,
-                include_str!("voxel_fs.glsl"),
-            ))),
+            vertex,
+            fragment: Some(fragment),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn voxel_shaders() {
+        let mut config = VoxelShaders::default();
+        config.snippets.vertex_main = String::from("v_color.rgb *= team;");
+        config.snippets.fragment_declarations = String::from("uniform float dissolve;");
+        let vertex = config.vertex_source();
+        let fragment = config.fragment_source();
+        assert!(vertex.contains("v_color.rgb *= team;"));
+        assert!(fragment.contains("uniform float dissolve;"));
+        assert!(!vertex.contains("// voxel:") && !fragment.contains("// voxel:"));
+        // snippets go inside `main`, declarations before it
+        let main = fragment.find("void main()").unwrap();
+        assert!(fragment.find("uniform float dissolve;").unwrap() < main);
+        let main = vertex.find("void main()").unwrap();
+        assert!(vertex.find("v_color.rgb *= team;").unwrap() > main);
+
+        // replaced stages use the given shader as it is
+        let handle = Handle::<Shader>::from_u128(42);
+        config.fragment = Some(ShaderSource::Handle(handle));
+        let settings = PipelineSettings::default();
+        let pipeline = build_pipeline(&mut Assets::default(), &config, &settings, 1);
+        assert_eq!(pipeline.shader_stages.fragment, Some(handle));
+    }
+
+    #[test]
+    pub fn pipeline_settings() {
+        let config = VoxelShaders::default();
+        let pipeline = build_pipeline(&mut Assets::default(), &config, &Default::default(), 4);
+        assert_eq!(pipeline.sample_count, 4);
+        let depth = pipeline.depth_stencil_state.unwrap();
+        assert_eq!(depth.depth_compare, CompareFunction::Less);
+        assert!(depth.depth_write_enabled);
+
+        let mut settings = PipelineSettings::default();
+        settings.depth_stencil.depth_compare = CompareFunction::LessEqual;
+        settings.depth_stencil.depth_write_enabled = false;
+        let pipeline = build_pipeline(&mut Assets::default(), &config, &settings, 1);
+        assert_eq!(pipeline.sample_count, 1);
+        assert_eq!(pipeline.depth_stencil_state, Some(settings.depth_stencil));
+    }
+}
@@ -3,6 +3,14 @@
 layout(location = 0) in vec3 v_position;
 layout(location = 1) in float v_shade;
 layout(location = 2) in vec4 v_color;
+layout(location = 3) in vec3 v_normal;
+layout(location = 4) in float v_emission;
+layout(location = 5) in vec3 v_tint;
+layout(location = 6) in float v_random;
+# ifdef VOXELMATERIAL_FACE_SHADING
+layout(location = 7) in float v_face_id;
+# endif
+layout(location = 8) in vec3 v_world_position;
 
 layout(location = 0) out vec4 o_Target;
 
@@ -10,6 +18,129 @@ layout(set = 1, binding = 0) uniform VoxelMaterial_albedo {
     vec4 Albedo;
 };
 
+layout(set = 1, binding = 1) uniform VoxelMaterial_light_direction {
+    vec3 LightDirection;
+};
+
+layout(set = 1, binding = 2) uniform VoxelMaterial_light_intensity {
+    float LightIntensity;
+};
+
+layout(set = 1, binding = 3) uniform VoxelMaterial_ambient_intensity {
+    float AmbientIntensity;
+};
+
+layout(set = 1, binding = 4) uniform VoxelMaterial_color_variation {
+    float ColorVariation;
+};
+
+// the shade of every face, in the order of Face::ALL
+layout(set = 1, binding = 5) uniform VoxelMaterial_face_shades {
+    vec4 FaceShades[2];
+};
+
+// the position and radius, then the color and intensity of every point light
+layout(set = 1, binding = 6) uniform VoxelMaterial_point_light_data {
+    vec4 PointLights[8];
+};
+
+layout(set = 1, binding = 7) uniform VoxelMaterial_grading_multiply {
+    vec4 GradingMultiply;
+};
+
+layout(set = 1, binding = 8) uniform VoxelMaterial_grading_offset {
+    vec4 GradingOffset;
+};
+
+layout(set = 1, binding = 9) uniform VoxelMaterial_grading_saturation {
+    float GradingSaturation;
+};
+
+// the ambient light from the sky, the horizon and the ground
+layout(set = 1, binding = 10) uniform VoxelMaterial_ambient_sky {
+    vec3 AmbientSky;
+};
+
+float ambient_light(vec3 normal) {
+# ifdef VOXELMATERIAL_SKY_AMBIENT
+    float ground_or_sky = normal.y > 0.0 ? AmbientSky.x : AmbientSky.z;
+    return mix(AmbientSky.y, ground_or_sky, abs(normal.y));
+# else
+    return AmbientIntensity;
+# endif
+}
+
+# ifdef VOXELMATERIAL_POINT_LIGHTS
+vec3 point_light(vec3 position, vec3 normal) {
+    vec3 light = vec3(0.0);
+    for (int i = 0; i < 4; i++) {
+        vec4 point = PointLights[i * 2];
+        vec4 color = PointLights[i * 2 + 1];
+        if (point.w <= 0.0) {
+            continue;
+        }
+        vec3 to_light = point.xyz - position;
+        float falloff = max(1.0 - length(to_light) / point.w, 0.0);
+        float angle = max(dot(normalize(to_light), normal), 0.0);
+        light += color.rgb * color.a * falloff * falloff * angle;
+    }
+    return light;
+}
+# endif
+
+# ifdef VOXELMATERIAL_SRGB_COLORS
+vec3 srgb_to_linear(vec3 srgb) {
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, vec3(lessThanEqual(srgb, vec3(0.04045))));
+}
+# endif
+
+// voxel:fragment_declarations
+
 void main() {
-    o_Target = vec4(Albedo.rgb * v_color.rgb * v_shade, Albedo.a * v_color.a);
+    vec3 albedo = Albedo.rgb;
+    vec3 color = v_color.rgb;
+# ifdef VOXELMATERIAL_SRGB_COLORS
+    albedo = srgb_to_linear(albedo);
+    // emissive colors above 1.0 are scaled after conversion so they stay bright
+    float intensity = max(max(color.r, max(color.g, color.b)), 1.0);
+    color = srgb_to_linear(color / intensity) * intensity;
+# endif
+    // tints are linear, so they don't depend on VOXELMATERIAL_SRGB_COLORS
+    color *= v_tint;
+    color *= 1.0 + ColorVariation * (v_random * 2.0 - 1.0);
+    float shade = v_shade;
+    float ambient = ambient_light(normalize(v_normal));
+# ifdef VOXELMATERIAL_SHADER_LIGHT
+    // the vertex shade only holds how much of the light reaches the face
+    float visibility = v_shade - v_emission;
+    float angle = clamp(dot(-LightDirection, normalize(v_normal)), 0.0, 1.0);
+    shade = visibility * angle * LightIntensity + ambient + v_emission;
+# endif
+# ifdef VOXELMATERIAL_FACE_SHADING
+    int face = int(v_face_id + 0.5);
+    float face_shade = FaceShades[face / 4][face % 4];
+    shade = (v_shade - v_emission) * face_shade + ambient + v_emission;
+# endif
+# ifdef VOXELMATERIAL_UNLIT
+    shade = 1.0;
+# endif
+    vec3 light = vec3(shade);
+# ifdef VOXELMATERIAL_POINT_LIGHTS
+    light += point_light(v_world_position, normalize(v_normal));
+# endif
+    o_Target = vec4(albedo * color * light, Albedo.a * v_color.a);
+# ifdef VOXELMATERIAL_COLOR_GRADING
+    float luminance = dot(o_Target.rgb, vec3(0.2126, 0.7152, 0.0722));
+    o_Target.rgb = mix(vec3(luminance), o_Target.rgb, GradingSaturation);
+    o_Target.rgb = max(o_Target.rgb * GradingMultiply.rgb + GradingOffset.rgb, 0.0);
+# endif
+# ifdef VOXELMATERIAL_TONEMAP
+    o_Target.rgb = o_Target.rgb / (1.0 + o_Target.rgb);
+# endif
+# ifdef VOXELMATERIAL_GHOST
+    o_Target.a *= 0.5;
+# endif
+    // voxel:fragment_main
 }
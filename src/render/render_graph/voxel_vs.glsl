@@ -3,10 +3,26 @@
 layout(location = 0) in vec3 Voxel_Position;
 layout(location = 1) in float Voxel_Shade;
 layout(location = 2) in vec4 Voxel_Color;
+layout(location = 3) in vec3 Voxel_Normal;
+layout(location = 4) in float Voxel_Emission;
+layout(location = 5) in vec3 Voxel_Tint;
+layout(location = 6) in float Voxel_Random;
+# ifdef VOXELMATERIAL_FACE_SHADING
+layout(location = 7) in float Voxel_FaceId;
+# endif
 
 layout(location = 0) out flat vec3 v_position;
 layout(location = 1) out flat float v_shade;
 layout(location = 2) out flat vec4 v_color;
+layout(location = 3) out flat vec3 v_normal;
+layout(location = 4) out flat float v_emission;
+layout(location = 5) out flat vec3 v_tint;
+layout(location = 6) out flat float v_random;
+# ifdef VOXELMATERIAL_FACE_SHADING
+layout(location = 7) out flat float v_face_id;
+# endif
+// interpolated unlike v_position, for lighting that varies across a face
+layout(location = 8) out vec3 v_world_position;
 
 layout(set = 0, binding = 0) uniform Camera {
     mat4 ViewProj;
@@ -16,9 +32,20 @@ layout(set = 2, binding = 0) uniform Transform {
     mat4 Model;
 };
 
+// voxel:vertex_declarations
+
 void main() {
     v_position = (Model * vec4(Voxel_Position, 1.0)).xyz;
     v_shade = Voxel_Shade;
     v_color = Voxel_Color;
+    v_normal = mat3(Model) * Voxel_Normal;
+    v_emission = Voxel_Emission;
+    v_tint = Voxel_Tint;
+    v_random = Voxel_Random;
+# ifdef VOXELMATERIAL_FACE_SHADING
+    v_face_id = Voxel_FaceId;
+# endif
+    // voxel:vertex_main
+    v_world_position = v_position;
     gl_Position = ViewProj * vec4(v_position, 1.0);
 }
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Chunk, Map, MapUpdates},
+};
+
+/// A signed distance field over the voxels of one chunk.
+///
+/// Every voxel stores the distance to the nearest voxel of the other kind: positive in
+/// empty space, negative inside solid voxels, and clamped to `radius`. Voxels closer than
+/// `radius` to the chunk border also look into neighbouring chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceField {
+    width: usize,
+    radius: u32,
+    values: Vec<f32>,
+}
+
+impl DistanceField {
+    pub fn new<T: Voxel>(map: &Map<T>, chunk: &Chunk<T>, radius: u32) -> Self {
+        let _span = span!("distance_field");
+        let width = chunk.width() as i32;
+        let r = radius as i32;
+        let padded = width + 2 * r;
+
+        let (cx, cy, cz) = chunk.position();
+        let mut solid = Vec::with_capacity((padded * padded * padded) as usize);
+        for x in -r..width + r {
+            for y in -r..width + r {
+                for z in -r..width + r {
+                    let inside = (0..width).contains(&x)
+                        && (0..width).contains(&y)
+                        && (0..width).contains(&z);
+                    let voxel = if inside {
+                        chunk.get((x, y, z)).is_some()
+                    } else {
+                        map.voxel((cx + x, cy + y, cz + z)).is_some()
+                    };
+                    solid.push(voxel);
+                }
+            }
+        }
+        let solid_at = |x: i32, y: i32, z: i32| {
+            solid[((x + r) * padded * padded + (y + r) * padded + z + r) as usize]
+        };
+
+        let mut values = Vec::with_capacity(chunk.width().pow(3));
+        for x in 0..width {
+            for y in 0..width {
+                for z in 0..width {
+                    let this = solid_at(x, y, z);
+                    let mut nearest = radius as f32;
+                    for dx in -r..=r {
+                        for dy in -r..=r {
+                            for dz in -r..=r {
+                                if solid_at(x + dx, y + dy, z + dz) == this {
+                                    continue;
+                                }
+                                let d = ((dx * dx + dy * dy + dz * dz) as f32).sqrt();
+                                nearest = nearest.min(d);
+                            }
+                        }
+                    }
+                    values.push(if this { -nearest } else { nearest });
+                }
+            }
+        }
+
+        Self {
+            width: chunk.width(),
+            radius,
+            values,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn radius(&self) -> u32 {
+        self.radius
+    }
+
+    /// Returns the distance at chunk-local `coords`.
+    pub fn get(&self, (x, y, z): (i32, i32, i32)) -> Option<f32> {
+        let width = self.width as i32;
+        if !(0..width).contains(&x) || !(0..width).contains(&y) || !(0..width).contains(&z) {
+            return None;
+        }
+        self.values
+            .get((x * width * width + y * width + z) as usize)
+            .copied()
+    }
+
+    /// The distances of all voxels, indexed by `x * width * width + y * width + z`.
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+/// Lazily computed distance fields for the chunks of a map.
+///
+/// Fields are computed on first access and recomputed after the chunk or one of its
+/// neighbours was invalidated, e.g. by `distance_field_update`.
+#[derive(Debug, Clone)]
+pub struct DistanceFields {
+    radius: u32,
+    fields: HashMap<(i32, i32, i32), DistanceField>,
+}
+
+impl Default for DistanceFields {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl DistanceFields {
+    pub fn new(radius: u32) -> Self {
+        Self {
+            radius,
+            fields: HashMap::new(),
+        }
+    }
+
+    pub fn radius(&self) -> u32 {
+        self.radius
+    }
+
+    /// Returns the distance field of the chunk at `coords`, computing it if needed.
+    pub fn get<T: Voxel>(
+        &mut self,
+        map: &Map<T>,
+        coords: (i32, i32, i32),
+    ) -> Option<&DistanceField> {
+        let chunk = if let Some(chunk) = map.get_at_origin(coords) {
+            chunk
+        } else {
+            self.fields.remove(&coords);
+            return None;
+        };
+        let radius = self.radius;
+        Some(
+            self.fields
+                .entry(coords)
+                .or_insert_with(|| DistanceField::new(map, chunk, radius)),
+        )
+    }
+
+    /// Drops the field of the chunk at `coords` and of every chunk next to it, since
+    /// distances near the border reach into the neighbours.
+    pub fn invalidate(&mut self, (x, y, z): (i32, i32, i32), width: usize) {
+        let width = width as i32;
+        for lx in -1..=1 {
+            for ly in -1..=1 {
+                for lz in -1..=1 {
+                    self.fields.remove(&(x + lx * width, y + ly * width, z + lz * width));
+                }
+            }
+        }
+    }
+
+    /// Invalidates every chunk that has an update pending.
+    pub fn invalidate_pending<T: Voxel>(&mut self, map: &Map<T>, updates: &MapUpdates) {
+        for &coords in updates.updates.keys() {
+            if let Some(chunk) = map.get_at_origin(coords) {
+                self.invalidate(coords, chunk.width());
+            }
+        }
+    }
+}
+
+/// Invalidates the distance fields of chunks with pending updates. Add `DistanceFields` next
+/// to a `Map` to use it.
+#[cfg(feature = "bevy")]
+pub fn distance_field_update<T: Voxel>(
+    mut query: Query<(&Map<T>, &MapUpdates, &mut DistanceFields)>,
+) {
+    for (map, updates, mut fields) in &mut query.iter() {
+        fields.invalidate_pending(&map, &updates);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn distance_field() {
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((0, 0, 0), 1);
+        let map = Map::try_with_chunks(vec![chunk, Chunk::new(2, (4, 0, 0))]).unwrap();
+
+        let mut fields = DistanceFields::new(2);
+        let field = fields.get(&map, (0, 0, 0)).unwrap();
+        assert_eq!(field.get((0, 0, 0)), Some(-1.0));
+        assert_eq!(field.get((1, 0, 0)), Some(1.0));
+        assert_eq!(field.get((3, 3, 3)), Some(2.0));
+        assert_eq!(field.get((4, 0, 0)), None);
+
+        let field = fields.get(&map, (4, 0, 0)).unwrap();
+        assert_eq!(field.get((0, 0, 0)), Some(2.0));
+        assert!(fields.get(&map, (8, 0, 0)).is_none());
+    }
+}
@@ -0,0 +1,374 @@
+//! Checks that two neighbouring chunks agree along their shared border, so that the seams
+//! between chunks, the most common kind of visual glitch, are caught by unit tests.
+
+use std::{collections::HashSet, error::Error, fmt};
+
+use glam::Vec3;
+
+use line_drawing::Bresenham3d;
+
+use crate::{
+    lighting::{self, AmbientLight, DirectionalLight, LightQuality},
+    mesh::{self, Face, MeshBuffers, MeshOrigin, VoxelExt},
+    world::Map,
+};
+
+/// A disagreement between two chunks found by `SeamCheck`. Coordinates are the world
+/// coordinates of the voxel on the side of the first chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeamError {
+    /// Faces side by side across the border are shaded differently.
+    Shade {
+        coords: (i32, i32, i32),
+        face: Face,
+        shades: (f32, f32),
+    },
+    /// A face towards the other chunk is meshed although the voxel behind it hides it, or
+    /// isn't meshed although nothing hides it. `first` tells which chunk's face it is.
+    Face {
+        coords: (i32, i32, i32),
+        first: bool,
+        meshed: bool,
+    },
+}
+
+/// A reason `SeamCheck` couldn't compare two chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeamCheckError {
+    /// There is no chunk at this position.
+    MissingChunk((i32, i32, i32)),
+    /// The chunks aren't neighbours along one axis.
+    NotNeighbours {
+        a: (i32, i32, i32),
+        b: (i32, i32, i32),
+    },
+}
+
+impl fmt::Display for SeamCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingChunk(position) => write!(f, "no chunk at {:?}", position),
+            Self::NotNeighbours { a, b } => {
+                write!(f, "chunks at {:?} and {:?} aren't neighbours", a, b)
+            }
+        }
+    }
+}
+
+impl Error for SeamCheckError {}
+
+/// Lights and meshes two neighbouring chunks the way the render systems do and compares them
+/// along their border.
+///
+/// Faces towards the other chunk are expected wherever a voxel isn't hidden by the voxel
+/// behind it, so voxels that aren't meshed as cubes, like plants, should be kept off the
+/// border.
+pub struct SeamCheck {
+    pub directional: DirectionalLight,
+    pub ambient: AmbientLight,
+    pub quality: LightQuality,
+    /// How much the shades of faces side by side may differ.
+    pub tolerance: f32,
+}
+
+impl Default for SeamCheck {
+    fn default() -> Self {
+        Self {
+            directional: DirectionalLight {
+                direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+                intensity: 0.8,
+            },
+            ambient: AmbientLight::new(0.05),
+            quality: LightQuality::default(),
+            tolerance: 0.05,
+        }
+    }
+}
+
+impl SeamCheck {
+    /// Lights the chunks at `a` and `b` and meshes them, returning every disagreement.
+    ///
+    /// Fails if there is no chunk at `a` or `b`, or they aren't neighbours along one axis.
+    pub fn run<T: VoxelExt>(
+        &self,
+        map: &mut Map<T>,
+        a: (i32, i32, i32),
+        b: (i32, i32, i32),
+    ) -> Result<Vec<SeamError>, SeamCheckError> {
+        self.light(map, &[a, b]);
+        self.compare(map, a, b)
+    }
+
+    /// Traces and shades the chunks at `positions` and works out their visible faces.
+    pub fn light<T: VoxelExt>(&self, map: &mut Map<T>, positions: &[(i32, i32, i32)]) {
+        for &position in positions {
+            if let Some(chunk) = map.get_at_origin_mut(position) {
+                lighting::light_map::<T, Bresenham3d<i32>>(chunk, &self.directional);
+            }
+        }
+        for &position in positions {
+            if let Some(chunk) = map.get_at_origin_mut(position) {
+                chunk.swap_light();
+            }
+        }
+        for &position in positions {
+            mesh::update_visibility(map, position);
+            let light_map = match lighting::shaded_light_map_with(map, position, &self.quality) {
+                Some(light_map) => light_map,
+                None => continue,
+            };
+            let chunk = map.get_at_origin_mut(position).unwrap();
+            lighting::shaded_light_with(
+                chunk,
+                &light_map,
+                &self.directional,
+                &self.ambient,
+                &self.quality,
+            );
+        }
+    }
+
+    /// Meshes the already lit chunks at `a` and `b` and compares them along their border.
+    pub fn compare<T: VoxelExt>(
+        &self,
+        map: &Map<T>,
+        a: (i32, i32, i32),
+        b: (i32, i32, i32),
+    ) -> Result<Vec<SeamError>, SeamCheckError> {
+        let (first, second) = match (map.get_at_origin(a), map.get_at_origin(b)) {
+            (Some(first), Some(second)) => (first, second),
+            (None, _) => return Err(SeamCheckError::MissingChunk(a)),
+            (_, None) => return Err(SeamCheckError::MissingChunk(b)),
+        };
+        let width = first.width() as i32;
+        let offset = [b.0 - a.0, b.1 - a.1, b.2 - a.2];
+        let axis = match offset {
+            [w, 0, 0] if w.abs() == width => 0,
+            [0, w, 0] if w.abs() == width => 1,
+            [0, 0, w] if w.abs() == width => 2,
+            _ => return Err(SeamCheckError::NotNeighbours { a, b }),
+        };
+        let sign = offset[axis].signum();
+        let mut normal = [0; 3];
+        normal[axis] = sign;
+        let towards = Face::from_normal((normal[0], normal[1], normal[2])).unwrap();
+        let away = Face::from_normal((-normal[0], -normal[1], -normal[2])).unwrap();
+        // the layers of voxels touching the border, in local coordinates of either chunk
+        let (layer_a, layer_b) = if sign > 0 {
+            (width - 1, 0)
+        } else {
+            (0, width - 1)
+        };
+
+        let meshed_a = border_faces(map, a, axis, towards);
+        let meshed_b = border_faces(map, b, axis, away);
+
+        let (i, j) = other_axes(axis);
+        let local = |layer: i32, u: i32, v: i32| {
+            let mut local = [0; 3];
+            local[axis] = layer;
+            local[i] = u;
+            local[j] = v;
+            (local[0], local[1], local[2])
+        };
+
+        let mut errors = Vec::new();
+        for u in 0..width {
+            for v in 0..width {
+                let local_a = local(layer_a, u, v);
+                let local_b = local(layer_b, u, v);
+                let coords = (a.0 + local_a.0, a.1 + local_a.1, a.2 + local_a.2);
+
+                let voxel_a = first.voxel(local_a);
+                let voxel_b = second.voxel(local_b);
+                let expected_a = match (voxel_a, voxel_b) {
+                    (Some(voxel), Some(other)) => !voxel.face_hidden_by(other),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                let expected_b = match (voxel_b, voxel_a) {
+                    (Some(voxel), Some(other)) => !voxel.face_hidden_by(other),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                if meshed_a.contains(&(u, v)) != expected_a {
+                    errors.push(SeamError::Face {
+                        coords,
+                        first: true,
+                        meshed: !expected_a,
+                    });
+                }
+                if meshed_b.contains(&(u, v)) != expected_b {
+                    errors.push(SeamError::Face {
+                        coords,
+                        first: false,
+                        meshed: !expected_b,
+                    });
+                }
+
+                let (voxel_a, voxel_b) = match (voxel_a, voxel_b) {
+                    (Some(voxel_a), Some(voxel_b)) => (voxel_a, voxel_b),
+                    _ => continue,
+                };
+                let (visibility_a, visibility_b) = match (first.visibility(), second.visibility()) {
+                    (Some(visibility_a), Some(visibility_b)) => (visibility_a, visibility_b),
+                    _ => continue,
+                };
+                // faces along the border, which continue into the other chunk
+                for &face in &Face::ALL {
+                    if face == towards || face == away {
+                        continue;
+                    }
+                    if !visibility_a.is_visible(local_a, face)
+                        || !visibility_b.is_visible(local_b, face)
+                    {
+                        continue;
+                    }
+                    let shade_a = voxel_a.clone().shade(voxel_a.face_map().to_local(face));
+                    let shade_b = voxel_b.clone().shade(voxel_b.face_map().to_local(face));
+                    if let (Some(shade_a), Some(shade_b)) = (shade_a, shade_b) {
+                        if (shade_a - shade_b).abs() > self.tolerance {
+                            errors.push(SeamError::Shade {
+                                coords,
+                                face,
+                                shades: (shade_a, shade_b),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(errors)
+    }
+}
+
+/// Meshes the chunk at `position` and returns the cells of the border plane covered by faces
+/// pointing in the direction of `face`, in the other two axes' local coordinates.
+fn border_faces<T: VoxelExt>(
+    map: &Map<T>,
+    position: (i32, i32, i32),
+    axis: usize,
+    face: Face,
+) -> HashSet<(i32, i32)> {
+    let chunk = map.get_at_origin(position).unwrap();
+    let width = chunk.width() as f32;
+    let normal = face.normal();
+    let normal = [normal.0 as f32, normal.1 as f32, normal.2 as f32];
+    let plane = if normal[axis] > 0.0 { width } else { 0.0 };
+    let (i, j) = other_axes(axis);
+
+    let (opaque, transparent) = mesh::generate_chunk_buffers(map, chunk, MeshOrigin::Corner);
+    let mut cells = HashSet::new();
+    for buffers in opaque.iter().chain(&transparent) {
+        for triangle in buffers.indices.chunks_exact(3) {
+            if !in_plane(buffers, triangle, axis, plane, normal) {
+                continue;
+            }
+            let corner = |k: usize, f: fn(f32, f32) -> f32| {
+                triangle
+                    .iter()
+                    .map(|&n| buffers.positions[n as usize][k])
+                    .fold(buffers.positions[triangle[0] as usize][k], f)
+            };
+            let (min_u, max_u) = (corner(i, f32::min), corner(i, f32::max));
+            let (min_v, max_v) = (corner(j, f32::min), corner(j, f32::max));
+            for u in min_u.floor() as i32..max_u.ceil() as i32 {
+                for v in min_v.floor() as i32..max_v.ceil() as i32 {
+                    cells.insert((u, v));
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// The axes spanning the plane perpendicular to `axis`.
+fn other_axes(axis: usize) -> (usize, usize) {
+    match axis {
+        0 => (1, 2),
+        1 => (0, 2),
+        _ => (0, 1),
+    }
+}
+
+fn in_plane(
+    buffers: &MeshBuffers,
+    triangle: &[u32],
+    axis: usize,
+    plane: f32,
+    normal: [f32; 3],
+) -> bool {
+    triangle.iter().all(|&n| {
+        buffers.positions[n as usize][axis] == plane && buffers.normals[n as usize] == normal
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn seams() {
+        use crate::{
+            simple::Block,
+            terrain::{Biome, HeightMap, Layer, Program},
+        };
+
+        let program = Program::<Block>::build()
+            .chunk_size(3)
+            .biome(
+                Biome::build()
+                    .height(5.0)
+                    .layer(Layer::new(Block::default(), 5.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut height_map = HeightMap::new();
+        let chunks = vec![
+            program.execute(&mut height_map, (0, 0, 0)).unwrap(),
+            program.execute(&mut height_map, (8, 0, 0)).unwrap(),
+        ];
+        let mut map = Map::try_with_chunks(chunks).unwrap();
+        let check = SeamCheck::default();
+        assert_eq!(check.run(&mut map, (0, 0, 0), (8, 0, 0)), Ok(Vec::new()));
+
+        // a face darker on one side
+        let chunk = map.get_mut((8, 0, 0)).unwrap();
+        chunk.get_mut((0, 4, 3)).unwrap().set_shade(Face::Top, 0.0);
+        let errors = check.compare(&map, (0, 0, 0), (8, 0, 0)).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            SeamError::Shade {
+                coords: (7, 4, 3),
+                face: Face::Top,
+                ..
+            }
+        ));
+
+        // a hole dug behind the border without the neighbour noticing
+        check.light(&mut map, &[(0, 0, 0), (8, 0, 0)]);
+        map.get_mut((8, 0, 0)).unwrap().remove((0, 2, 2));
+        let errors = check.compare(&map, (0, 0, 0), (8, 0, 0)).unwrap();
+        assert_eq!(
+            errors,
+            vec![SeamError::Face {
+                coords: (7, 2, 2),
+                first: true,
+                meshed: false,
+            }]
+        );
+
+        // chunks that can't be compared
+        let missing = check.compare(&map, (0, 0, 0), (-8, 0, 0));
+        assert_eq!(missing, Err(SeamCheckError::MissingChunk((-8, 0, 0))));
+        let apart = check.compare(&map, (0, 0, 0), (0, 0, 0));
+        let error = SeamCheckError::NotNeighbours {
+            a: (0, 0, 0),
+            b: (0, 0, 0),
+        };
+        assert_eq!(apart, Err(error));
+    }
+}
@@ -1,3 +1,5 @@
+use std::io::{self, Write};
+
 pub trait SerDePartialEq<T: ?Sized> {
     fn serde_eq(&self, other: &T) -> bool;
 }
@@ -21,3 +23,42 @@ impl<T, U: SerDePartialEq<T>> SerDePartialEq<Option<T>> for Option<U> {
             .unwrap_or(true)
     }
 }
+
+/// A 64 bit FNV-1a hasher, used for content hashes that have to match across platforms and
+/// builds, unlike the hashers in `std`.
+#[derive(Debug, Clone)]
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
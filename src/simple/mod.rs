@@ -1,5 +1,5 @@
-#[cfg(feature = "savedata")]
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize};
 
 use bevy::prelude::*;
 
@@ -8,7 +8,7 @@ use crate::serialize::SerDePartialEq;
 
 use crate::{
     collections::lod_tree::Voxel,
-    render::entity::{Face, MeshPart, VoxelExt, Transparent},
+    mesh::{Face, MeshPart, Neighbours, Transparent, VoxelExt},
     world::{Chunk, Map},
 };
 
@@ -62,13 +62,140 @@ impl Default for MeshType {
     }
 }
 
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[derive(Default, Debug, Clone, Copy, PartialEq)]
 pub struct Block {
     #[cfg_attr(feature = "serde", serde(skip))]
     pub shade: Shade,
     pub color: Color,
     pub mesh_type: MeshType,
+    pub merge_group: u32,
+    pub emission: f32,
+    /// Multiplies the color by the biome tint, e.g. for grass and leaves.
+    pub tinted: bool,
+    /// The color of the sides of a cube covered by a block of the same color, e.g. dirt for
+    /// grass with more grass on top.
+    pub covered_color: Option<Color>,
+}
+
+/// `Block` as it is serialized now. Fields added later default, so older RON still reads.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(rename = "Block")]
+struct BlockSave {
+    color: Color,
+    mesh_type: MeshType,
+    #[serde(default)]
+    merge_group: u32,
+    #[serde(default)]
+    emission: f32,
+    #[serde(default)]
+    tinted: bool,
+    #[serde(default)]
+    covered_color: Option<Color>,
+}
+
+/// `Block` in chunks saved before `LegacyLayout::MergeGroup`.
+#[cfg(feature = "savedata")]
+#[derive(Deserialize)]
+#[serde(rename = "Block")]
+struct InitialBlock {
+    color: Color,
+    mesh_type: MeshType,
+}
+
+#[cfg(feature = "savedata")]
+#[derive(Deserialize)]
+#[serde(rename = "Block")]
+struct MergeGroupBlock {
+    color: Color,
+    mesh_type: MeshType,
+    merge_group: u32,
+}
+
+#[cfg(feature = "savedata")]
+#[derive(Deserialize)]
+#[serde(rename = "Block")]
+struct EmissionBlock {
+    color: Color,
+    mesh_type: MeshType,
+    merge_group: u32,
+    emission: f32,
+}
+
+#[cfg(feature = "savedata")]
+#[derive(Deserialize)]
+#[serde(rename = "Block")]
+struct TintedBlock {
+    color: Color,
+    mesh_type: MeshType,
+    merge_group: u32,
+    emission: f32,
+    tinted: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // bincode has no field names, so blocks of unversioned chunks are read field by
+        // field in the layout the chunk was saved in
+        #[cfg(feature = "savedata")]
+        {
+            use crate::world::{legacy_layout, LegacyLayout};
+
+            match legacy_layout() {
+                Some(layout) if layout < LegacyLayout::MergeGroup => {
+                    let block = InitialBlock::deserialize(deserializer)?;
+                    return Ok(Block {
+                        color: block.color,
+                        mesh_type: block.mesh_type,
+                        ..Default::default()
+                    });
+                }
+                Some(layout) if layout < LegacyLayout::Emission => {
+                    let block = MergeGroupBlock::deserialize(deserializer)?;
+                    return Ok(Block {
+                        color: block.color,
+                        mesh_type: block.mesh_type,
+                        merge_group: block.merge_group,
+                        ..Default::default()
+                    });
+                }
+                Some(layout) if layout < LegacyLayout::Tints => {
+                    let block = EmissionBlock::deserialize(deserializer)?;
+                    return Ok(Block {
+                        color: block.color,
+                        mesh_type: block.mesh_type,
+                        merge_group: block.merge_group,
+                        emission: block.emission,
+                        ..Default::default()
+                    });
+                }
+                Some(layout) if layout < LegacyLayout::CoveredColor => {
+                    let block = TintedBlock::deserialize(deserializer)?;
+                    return Ok(Block {
+                        color: block.color,
+                        mesh_type: block.mesh_type,
+                        merge_group: block.merge_group,
+                        emission: block.emission,
+                        tinted: block.tinted,
+                        ..Default::default()
+                    });
+                }
+                _ => {}
+            }
+        }
+        let block = BlockSave::deserialize(deserializer)?;
+        Ok(Block {
+            shade: Shade::default(),
+            color: block.color,
+            mesh_type: block.mesh_type,
+            merge_group: block.merge_group,
+            emission: block.emission,
+            tinted: block.tinted,
+            covered_color: block.covered_color,
+        })
+    }
 }
 
 impl Block {
@@ -80,6 +207,16 @@ impl Block {
         self.color.a < 1.0
     }
 
+    /// Returns the block with its color brightened or darkened by `factor`, for
+    /// `Layer::with_strata`.
+    pub fn strata(&self, factor: f32) -> Self {
+        let mut block = *self;
+        block.color.r = (block.color.r * factor).min(1.0);
+        block.color.g = (block.color.g * factor).min(1.0);
+        block.color.b = (block.color.b * factor).min(1.0);
+        block
+    }
+
     fn mesh_cube(
         &self,
         coords: (i32, i32, i32),
@@ -92,49 +229,68 @@ impl Block {
         let mut colors = Vec::new();
         let mut indices = Vec::new();
 
+        // faces may look different, but are culled like the block itself
+        let neighbours = Neighbours::new(map, chunk, coords, width);
+        let face_block = |face| match self.face_material(face, &neighbours) {
+            Some(material) => Block {
+                color: Color {
+                    a: self.color.a,
+                    ..material.color
+                },
+                ..*self
+            },
+            None => *self,
+        };
+
         let mut n = 0;
+        let block = face_block(Face::Top);
         if let Some((p, s, c)) =
-            generate_top_side(self, map, chunk, coords, width, &mut indices, &mut n)
+            generate_top_side(&block, map, chunk, coords, width, &mut indices, &mut n)
         {
             positions.extend(&p);
             shades.extend(&s);
             colors.extend(&c);
         }
 
+        let block = face_block(Face::Bottom);
         if let Some((p, s, c)) =
-            generate_bottom_side(self, map, chunk, coords, width, &mut indices, &mut n)
+            generate_bottom_side(&block, map, chunk, coords, width, &mut indices, &mut n)
         {
             positions.extend(&p);
             shades.extend(&s);
             colors.extend(&c);
         }
 
+        let block = face_block(Face::Front);
         if let Some((p, s, c)) =
-            generate_front_side(self, map, chunk, coords, width, &mut indices, &mut n)
+            generate_front_side(&block, map, chunk, coords, width, &mut indices, &mut n)
         {
             positions.extend(&p);
             shades.extend(&s);
             colors.extend(&c);
         }
 
+        let block = face_block(Face::Back);
         if let Some((p, s, c)) =
-            generate_back_side(self, map, chunk, coords, width, &mut indices, &mut n)
+            generate_back_side(&block, map, chunk, coords, width, &mut indices, &mut n)
         {
             positions.extend(&p);
             shades.extend(&s);
             colors.extend(&c);
         }
 
+        let block = face_block(Face::Left);
         if let Some((p, s, c)) =
-            generate_left_side(self, map, chunk, coords, width, &mut indices, &mut n)
+            generate_left_side(&block, map, chunk, coords, width, &mut indices, &mut n)
         {
             positions.extend(&p);
             shades.extend(&s);
             colors.extend(&c);
         }
 
+        let block = face_block(Face::Right);
         if let Some((p, s, c)) =
-            generate_right_side(self, map, chunk, coords, width, &mut indices, &mut n)
+            generate_right_side(&block, map, chunk, coords, width, &mut indices, &mut n)
         {
             positions.extend(&p);
             shades.extend(&s);
@@ -156,7 +312,7 @@ impl Block {
         &self,
         coords: (i32, i32, i32),
         _map: &Map<Self>,
-        _chunk: &Chunk<Self>,
+        chunk: &Chunk<Self>,
         width: usize,
     ) -> MeshPart {
         let x = coords.0 as f32;
@@ -190,10 +346,28 @@ impl Block {
         let shade_b = (front + right) * 0.5;
         let shade_c = (back + left) * 0.5;
         let shade_d = (back + right) * 0.5;
-        let shades = vec![
+        let sides = [
             shade_b, shade_b, shade_b, shade_b, shade_d, shade_d, shade_d, shade_d, shade_c,
             shade_c, shade_c, shade_c, shade_a, shade_a, shade_a, shade_a,
         ];
+        // plants in the open get as much light from above as the ground they grow on, as far
+        // as the light map lets it through to the voxel, and darken a little towards their
+        // base. Without a light map, e.g. with simple lighting, the sky is taken to be open.
+        let top = self.shade.top;
+        let bottom = self.shade.bottom;
+        let sky = chunk.light(coords).unwrap_or(1.0);
+        let shades = positions
+            .iter()
+            .zip(&sides)
+            .map(|(position, &side)| {
+                let upper = side + (top - side).max(0.0) * sky;
+                if position[1] > y {
+                    upper
+                } else {
+                    upper * 0.75 + bottom.min(upper) * 0.25
+                }
+            })
+            .collect();
         let colors = vec![self.color.into(); 16];
 
         let indices = vec![
@@ -216,17 +390,16 @@ impl Block {
 impl SerDePartialEq<Self> for Block {
     fn serde_eq(&self, other: &Self) -> bool {
         self.color == other.color
+            && self.merge_group == other.merge_group
+            && self.emission == other.emission
+            && self.tinted == other.tinted
+            && self.covered_color == other.covered_color
     }
 }
 
-impl Voxel for Block {
-    fn average(data: &[Self]) -> Option<Self> {
-        if data.is_empty() {
-            return None;
-        } else if data.len() == 1 {
-            return Some(data[0].clone());
-        };
-
+impl Block {
+    fn average_of<'a>(data: impl Iterator<Item = &'a Self>) -> Self {
+        let mut len = 0;
         let mut color = Color::rgba(0.0, 0.0, 0.0, 0.0);
         let mut top = 0.0_f32;
         let mut bottom = 0.0_f32;
@@ -234,6 +407,10 @@ impl Voxel for Block {
         let mut right = 0.0_f32;
         let mut front = 0.0_f32;
         let mut back = 0.0_f32;
+        let mut merge_group = None;
+        let mut emission = 0.0_f32;
+        let mut tinted = false;
+        let mut covered_color = None;
 
         for block in data {
             top = top.max(block.shade.top);
@@ -243,11 +420,17 @@ impl Voxel for Block {
             front = front.max(block.shade.front);
             back = back.max(block.shade.back);
             color += block.color;
+            emission += block.emission;
+            tinted |= block.tinted;
+            merge_group.get_or_insert(block.merge_group);
+            covered_color = covered_color.or(block.covered_color);
+            len += 1;
         }
 
-        color *= (data.len() as f32).recip();
+        color *= (len as f32).recip();
+        emission /= len as f32;
 
-        Some(Self {
+        Self {
             color,
             shade: Shade {
                 top,
@@ -258,7 +441,31 @@ impl Voxel for Block {
                 back,
             },
             mesh_type: MeshType::Cube,
-        })
+            merge_group: merge_group.unwrap_or_default(),
+            emission,
+            tinted,
+            covered_color,
+        }
+    }
+}
+
+impl Voxel for Block {
+    fn average(data: &[Self]) -> Option<Self> {
+        if data.is_empty() {
+            return None;
+        } else if data.len() == 1 {
+            return Some(data[0].clone());
+        };
+
+        // average opaque and transparent blocks separately so that e.g. water over land
+        // doesn't turn into a murky half-transparent block; opaque content wins ties
+        let opaque = data.iter().filter(|block| block.color.a >= 1.0).count();
+        let transparent = data.len() - opaque;
+        let prefer_opaque = opaque >= transparent;
+        let blocks = data
+            .iter()
+            .filter(|block| (block.color.a >= 1.0) == prefer_opaque);
+        Some(Self::average_of(blocks))
     }
 
     fn can_merge(&self) -> bool {
@@ -266,6 +473,23 @@ impl Voxel for Block {
     }
 }
 
+/// Looks the face up in the visibility mask of the chunk, if it has one and is at full
+/// detail.
+fn mask_visible(chunk: &Chunk<Block>, coords: (i32, i32, i32), face: Face) -> Option<bool> {
+    if chunk.lod() != 0 {
+        return None;
+    }
+    Some(chunk.visibility()?.is_visible(coords, face))
+}
+
+/// Returns whether `other` hides the face of `block` it touches.
+fn hides_face(block: &Block, other: &Block) -> bool {
+    block.solid() && other.solid()
+        || block.transparent()
+            && other.transparent()
+            && block.merge_group() == other.merge_group()
+}
+
 impl VoxelExt for Block {
     fn mesh(
         &self,
@@ -280,6 +504,42 @@ impl VoxelExt for Block {
         }
     }
 
+    fn merge_group(&self) -> u32 {
+        self.merge_group
+    }
+
+    fn density(&self) -> f32 {
+        match self.mesh_type {
+            MeshType::Cube => self.color.a,
+            MeshType::Cross => 0.1,
+        }
+    }
+
+    fn face_hidden_by(&self, neighbour: &Self) -> bool {
+        hides_face(self, neighbour)
+    }
+
+    fn emission(&self) -> f32 {
+        self.emission
+    }
+
+    fn tinted(&self) -> bool {
+        self.tinted
+    }
+
+    fn face_material(&self, face: Face, neighbours: &Neighbours<'_, Self>) -> Option<Self> {
+        let color = self.covered_color?;
+        if face == Face::Top || face == Face::Bottom {
+            return None;
+        }
+        let above = neighbours.get((0, 1, 0))?;
+        if above.color == self.color {
+            Some(Block { color, ..*self })
+        } else {
+            None
+        }
+    }
+
     fn set_shade(&mut self, face: Face, light: f32) {
         match face {
             Face::Top => self.shade.top = light,
@@ -316,21 +576,24 @@ fn generate_front_side(
     let cw = chunk.width() as i32;
     for dx in 0..width {
         for dy in 0..width {
-            let render = if z + width >= cw {
+            let voxel = (x + dx, y + dy, z + width - 1);
+            let render = if let Some(visible) = mask_visible(chunk, voxel, Face::Front) {
+                visible
+            } else if z + width >= cw {
                 let (cx, cy, cz) = chunk.position();
-                let cz = cz + cw;
-                if let Some(chunk) = map.get((cx, cy, cz)) {
+                let neighbor = (cx + x + dx, cy + y + dy, cz + cw);
+                if let Some(chunk) = map.chunk_containing(neighbor) {
                     !chunk
-                        .get((x + dx, y + dy, 0))
-                        .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                        .sampled_get(chunk.to_local(neighbor))
+                        .map(|other| hides_face(block, &other))
                         .unwrap_or(false)
                 } else {
                     false
                 }
             } else {
                 !chunk
-                    .get((x + dx, y + dy, z + width))
-                    .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                    .sampled_get((x + dx, y + dy, z + width))
+                    .map(|other| hides_face(block, &other))
                     .unwrap_or(false)
             };
             if render {
@@ -376,24 +639,26 @@ fn generate_back_side(
     n: &mut u32,
 ) -> Option<([[f32; 3]; 4], [f32; 4], [[f32; 4]; 4])> {
     let width = width as i32;
-    let cw = chunk.width() as i32;
     for dx in 0..width {
         for dy in 0..width {
-            let render = if z - 1 < 0 {
+            let voxel = (x + dx, y + dy, z);
+            let render = if let Some(visible) = mask_visible(chunk, voxel, Face::Back) {
+                visible
+            } else if z - 1 < 0 {
                 let (cx, cy, cz) = chunk.position();
-                let cz = cz - cw;
-                if let Some(chunk) = map.get((cx, cy, cz)) {
+                let neighbor = (cx + x + dx, cy + y + dy, cz - 1);
+                if let Some(chunk) = map.chunk_containing(neighbor) {
                     !chunk
-                        .get((x + dx, y + dy, cw - 1))
-                        .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                        .sampled_get(chunk.to_local(neighbor))
+                        .map(|other| hides_face(block, &other))
                         .unwrap_or(false)
                 } else {
                     false
                 }
             } else {
                 !chunk
-                    .get((x + dx, y + dy, z - 1))
-                    .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                    .sampled_get((x + dx, y + dy, z - 1))
+                    .map(|other| hides_face(block, &other))
                     .unwrap_or(false)
             };
             if render {
@@ -439,24 +704,26 @@ fn generate_right_side(
     n: &mut u32,
 ) -> Option<([[f32; 3]; 4], [f32; 4], [[f32; 4]; 4])> {
     let width = width as i32;
-    let cw = chunk.width() as i32;
     for dy in 0..width {
         for dz in 0..width {
-            let render = if x - 1 < 0 {
+            let voxel = (x, y + dy, z + dz);
+            let render = if let Some(visible) = mask_visible(chunk, voxel, Face::Right) {
+                visible
+            } else if x - 1 < 0 {
                 let (cx, cy, cz) = chunk.position();
-                let cx = cx - cw;
-                if let Some(chunk) = map.get((cx, cy, cz)) {
+                let neighbor = (cx - 1, cy + y + dy, cz + z + dz);
+                if let Some(chunk) = map.chunk_containing(neighbor) {
                     !chunk
-                        .get((cw - 1, y + dy, z + dz))
-                        .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                        .sampled_get(chunk.to_local(neighbor))
+                        .map(|other| hides_face(block, &other))
                         .unwrap_or(false)
                 } else {
                     false
                 }
             } else {
                 !chunk
-                    .get((x - 1, y + dy, z + dz))
-                    .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                    .sampled_get((x - 1, y + dy, z + dz))
+                    .map(|other| hides_face(block, &other))
                     .unwrap_or(false)
             };
             if render {
@@ -505,21 +772,24 @@ fn generate_left_side(
     let cw = chunk.width() as i32;
     for dy in 0..width {
         for dz in 0..width {
-            let render = if x + width >= cw {
+            let voxel = (x + width - 1, y + dy, z + dz);
+            let render = if let Some(visible) = mask_visible(chunk, voxel, Face::Left) {
+                visible
+            } else if x + width >= cw {
                 let (cx, cy, cz) = chunk.position();
-                let cx = cx + cw;
-                if let Some(chunk) = map.get((cx, cy, cz)) {
+                let neighbor = (cx + cw, cy + y + dy, cz + z + dz);
+                if let Some(chunk) = map.chunk_containing(neighbor) {
                     !chunk
-                        .get((0, y + dy, z + dz))
-                        .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                        .sampled_get(chunk.to_local(neighbor))
+                        .map(|other| hides_face(block, &other))
                         .unwrap_or(false)
                 } else {
                     false
                 }
             } else {
                 !chunk
-                    .get((x + width, y + dy, z + dz))
-                    .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                    .sampled_get((x + width, y + dy, z + dz))
+                    .map(|other| hides_face(block, &other))
                     .unwrap_or(false)
             };
             if render {
@@ -568,21 +838,24 @@ fn generate_top_side(
     let cw = chunk.width() as i32;
     for dx in 0..width {
         for dz in 0..width {
-            let render = if y + width >= cw {
+            let voxel = (x + dx, y + width - 1, z + dz);
+            let render = if let Some(visible) = mask_visible(chunk, voxel, Face::Top) {
+                visible
+            } else if y + width >= cw {
                 let (cx, cy, cz) = chunk.position();
-                let cy = cy + cw;
-                if let Some(chunk) = map.get((cx, cy, cz)) {
+                let neighbor = (cx + x + dx, cy + cw, cz + z + dz);
+                if let Some(chunk) = map.chunk_containing(neighbor) {
                     !chunk
-                        .get((x + dx, 0, z + dz))
-                        .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                        .sampled_get(chunk.to_local(neighbor))
+                        .map(|other| hides_face(block, &other))
                         .unwrap_or(false)
                 } else {
                     false
                 }
             } else {
                 !chunk
-                    .get((x + dx, y + width, z + dz))
-                    .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                    .sampled_get((x + dx, y + width, z + dz))
+                    .map(|other| hides_face(block, &other))
                     .unwrap_or(false)
             };
             if render {
@@ -628,24 +901,26 @@ fn generate_bottom_side(
     n: &mut u32,
 ) -> Option<([[f32; 3]; 4], [f32; 4], [[f32; 4]; 4])> {
     let width = width as i32;
-    let cw = chunk.width() as i32;
     for dx in 0..width {
         for dz in 0..width {
-            let render = if y - 1 < 0 {
+            let voxel = (x + dx, y, z + dz);
+            let render = if let Some(visible) = mask_visible(chunk, voxel, Face::Bottom) {
+                visible
+            } else if y - 1 < 0 {
                 let (cx, cy, cz) = chunk.position();
-                let cy = cy - cw;
-                if let Some(chunk) = map.get((cx, cy, cz)) {
+                let neighbor = (cx + x + dx, cy - 1, cz + z + dz);
+                if let Some(chunk) = map.chunk_containing(neighbor) {
                     !chunk
-                        .get((x + dx, cw - 1, z + dz))
-                        .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                        .sampled_get(chunk.to_local(neighbor))
+                        .map(|other| hides_face(block, &other))
                         .unwrap_or(false)
                 } else {
                     false
                 }
             } else {
                 !chunk
-                    .get((x + dx, y - 1, z + dz))
-                    .map(|other| block.solid() && other.solid() || block.transparent() && other.transparent())
+                    .sampled_get((x + dx, y - 1, z + dz))
+                    .map(|other| hides_face(block, &other))
                     .unwrap_or(false)
             };
             if render {
@@ -680,3 +955,103 @@ fn generate_bottom_side(
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn average() {
+        let block = |r, g, b, a| Block {
+            color: Color::rgba(r, g, b, a),
+            ..Default::default()
+        };
+        let grass = block(0.0, 1.0, 0.0, 1.0);
+        let dirt = block(1.0, 0.5, 0.0, 1.0);
+        let water = block(0.0, 0.0, 1.0, 0.5);
+
+        // the water over land doesn't tint the land
+        let land = Block::average(&[grass, dirt, dirt, water]).unwrap();
+        assert_eq!(land.color, Color::rgba(2.0 / 3.0, 2.0 / 3.0, 0.0, 1.0));
+        assert!(!land.transparent());
+
+        // mostly water stays water, opaque content wins ties
+        let sea = Block::average(&[water, water, water, grass]).unwrap();
+        assert_eq!(sea.color, water.color);
+        let shore = Block::average(&[water, water, grass, grass]).unwrap();
+        assert_eq!(shore.color, grass.color);
+    }
+
+    #[test]
+    pub fn merge_group() {
+        use crate::mesh::{generate_chunk_buffers, MeshOrigin};
+
+        let water = Block {
+            color: Color::rgba(0.0, 0.0, 1.0, 0.5),
+            ..Default::default()
+        };
+        let glass = Block {
+            merge_group: 1,
+            color: Color::rgba(1.0, 1.0, 1.0, 0.2),
+            ..Default::default()
+        };
+        let stone = Block::default();
+        assert!(hides_face(&water, &water));
+        assert!(!hides_face(&water, &glass));
+        assert!(!hides_face(&water, &stone));
+        assert!(hides_face(&stone, &stone));
+
+        // the faces between water and glass are kept, those between two waters culled
+        let vertices = |other: Block| {
+            let mut chunk = Chunk::new(2, (0, 0, 0));
+            chunk.insert((1, 1, 1), water);
+            chunk.insert((2, 1, 1), other);
+            let map = Map::try_with_chunks(vec![chunk]).unwrap();
+            let chunk = map.get((0, 0, 0)).unwrap();
+            let (_, transparent) = generate_chunk_buffers(&map, chunk, MeshOrigin::Corner);
+            transparent.unwrap().positions.len()
+        };
+        assert_eq!(vertices(glass), vertices(water) + 2 * 4);
+    }
+
+    #[test]
+    pub fn cross_shading() {
+        let plant = Block {
+            mesh_type: MeshType::Cross,
+            shade: Shade {
+                top: 1.0,
+                bottom: 0.5,
+                front: 0.6,
+                back: 0.6,
+                left: 0.6,
+                right: 0.6,
+            },
+            ..Default::default()
+        };
+        let shades = |light: Option<f32>| {
+            let mut chunk = Chunk::new(2, (0, 0, 0));
+            chunk.insert((1, 1, 1), plant);
+            if let Some(light) = light {
+                chunk.insert_light((1, 1, 1), light);
+            }
+            let map = Map::try_with_chunks(vec![chunk]).unwrap();
+            let chunk = map.get((0, 0, 0)).unwrap();
+            let part = plant.mesh_cross((1, 1, 1), &map, chunk, 1);
+            let (upper, lower): (Vec<_>, Vec<_>) = part
+                .positions
+                .iter()
+                .zip(part.shades)
+                .partition(|(position, _)| position[1] > 1.0);
+            let first = |shades: Vec<(&[f32; 3], f32)>| shades[0].1;
+            (first(upper), first(lower))
+        };
+
+        // in the open plants are lit from above like the ground, darker towards their base
+        assert_eq!(shades(Some(1.0)), (1.0, 0.875));
+        assert_eq!(shades(None), shades(Some(1.0)));
+        // in the dark only the side shades are left
+        let (upper, lower) = shades(Some(0.0));
+        assert_eq!(upper, 0.6);
+        assert!((lower - 0.575).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    terrain::{HeightChunk, Program},
+    world::Chunk,
+};
+
+/// What chunks copied from a `GenerationCache` do about the ores and `per_xz` statements of
+/// their biomes, which depend on where the chunk is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachedDecorations {
+    /// Decorates every chunk with its own random numbers, so the world is the same as without
+    /// the cache.
+    Reseed,
+    /// Leaves chunks copied from the cache undecorated, e.g. for open oceans nobody mines.
+    Skip,
+}
+
+impl Default for CachedDecorations {
+    fn default() -> Self {
+        Self::Reseed
+    }
+}
+
+/// Tells chunks apart by what their terrain is generated from: their height, and the biome
+/// and ground height of every column.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ColumnKey {
+    y: i32,
+    biomes: Vec<usize>,
+    heights: Vec<u32>,
+}
+
+impl ColumnKey {
+    /// Returns `None` for chunks whose terrain also depends on where the columns are, i.e.
+    /// with a biome whose strata are warped.
+    pub(crate) fn new<T: Voxel>(
+        program: &Program<T>,
+        height_chunk: &HeightChunk,
+        biomes: &[usize],
+        y: i32,
+    ) -> Option<Self> {
+        let mut warped = vec![None; program.biomes.len()];
+        for &biome in biomes {
+            let warped = warped[biome].get_or_insert_with(|| {
+                program.biomes[biome]
+                    .layers
+                    .iter()
+                    .any(|layer| match &layer.strata {
+                        Some(strata) => strata.warp != 0.0,
+                        None => false,
+                    })
+            });
+            if *warped {
+                return None;
+            }
+        }
+        let size = program.chunk_width() as i32;
+        let heights = (0..size)
+            .flat_map(|x| (0..size).map(move |z| (x, z)))
+            .map(|coords| height_chunk.get(coords).to_bits())
+            .collect();
+        Some(Self {
+            y,
+            biomes: biomes.to_vec(),
+            heights,
+        })
+    }
+}
+
+/// Remembers the undecorated terrain of generated chunks, so that chunks with the same
+/// columns, e.g. the thousands of chunks of a flat ocean, are copied instead of generated
+/// again. Pass it to `Program::execute_cached`, or add it as a resource for
+/// `terrain_generation`.
+///
+/// Chunks are told apart by their height and the biome and ground height of every column,
+/// so copies are exact. Chunks of biomes with warped `Strata` are never cached. Clear the
+/// cache when the `Program` changes.
+#[derive(Debug, Clone)]
+pub struct GenerationCache<T> {
+    /// How many chunks are kept, the oldest are dropped first. `0` turns the cache off.
+    pub capacity: usize,
+    pub decorations: CachedDecorations,
+    chunks: HashMap<ColumnKey, Chunk<T>>,
+    order: VecDeque<ColumnKey>,
+    hits: usize,
+    misses: usize,
+}
+
+impl<T> Default for GenerationCache<T> {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl<T> GenerationCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            decorations: CachedDecorations::default(),
+            chunks: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn with_decorations(mut self, decorations: CachedDecorations) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// The number of chunks copied from the cache so far.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of cacheable chunks that had to be generated so far.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.order.clear();
+    }
+}
+
+impl<T: Voxel> GenerationCache<T> {
+    /// Returns a copy of the cached chunk for `key` at `position`.
+    pub(crate) fn get(&mut self, key: &ColumnKey, position: (i32, i32, i32)) -> Option<Chunk<T>> {
+        match self.chunks.get(key) {
+            Some(chunk) => {
+                self.hits += 1;
+                Some(chunk.copy_voxels_to(position))
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: ColumnKey, chunk: &Chunk<T>) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.chunks.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => self.chunks.remove(&oldest),
+                None => break,
+            };
+        }
+        let position = chunk.position();
+        self.order.push_back(key.clone());
+        self.chunks.insert(key, chunk.copy_voxels_to(position));
+    }
+}
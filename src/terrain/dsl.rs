@@ -9,11 +9,16 @@ use serde::{Deserialize, Serialize};
 
 use rand::Rng;
 
-use bevy::math::Vec3;
+use glam::Vec3;
 
-use crate::collections::lod_tree::Voxel;
+#[cfg(feature = "savedata")]
+use crate::serialize::ContentHasher;
+use crate::{
+    collections::lod_tree::Voxel,
+    error::{self, Error, ProgramError},
+};
 
-use super::Chunk;
+use super::{chunk_seed, Chunk, HeightChunk, Ore};
 
 trait AsOption {
     fn as_option(self) -> Option<Value>;
@@ -108,10 +113,12 @@ impl Display for Type {
 }
 
 macro_rules! type_error {
-    ($e:expr, $t:expr) => {{
-        let e = $e;
-        panic!("{}: {} is not of type {}", e, e.type_of(), $t)
-    }};
+    ($e:expr, $t:expr) => {
+        Err(Error::Type {
+            value: $e.clone(),
+            expected: $t,
+        })
+    };
 }
 
 #[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
@@ -144,105 +151,109 @@ impl Value {
         }
     }
 
-    pub fn as_unit(&self) -> () {
+    pub fn as_unit(&self) -> error::Result<()> {
         match self {
-            Self::Unit => (),
+            Self::Unit => Ok(()),
             _ => type_error!(self, Type::Unit),
         }
     }
 
-    pub fn as_bool(&self) -> bool {
+    pub fn as_bool(&self) -> error::Result<bool> {
         match self {
-            Self::Bool(x) => *x,
+            Self::Bool(x) => Ok(*x),
             _ => type_error!(self, Type::Bool),
         }
     }
 
-    pub fn as_float(&self) -> f32 {
+    pub fn as_float(&self) -> error::Result<f32> {
         match self {
-            Self::Float(x) => *x,
+            Self::Float(x) => Ok(*x),
             _ => type_error!(self, Type::Float),
         }
     }
 
-    pub fn as_float3(&self) -> Vec3 {
+    pub fn as_float3(&self) -> error::Result<Vec3> {
         match self {
-            Self::Float3(x) => *x,
+            Self::Float3(x) => Ok(*x),
             _ => type_error!(self, Type::Float3),
         }
     }
 }
 
 impl Add for Value {
-    type Output = Self;
+    type Output = error::Result<Self>;
 
-    fn add(self, other: Self) -> Self {
+    fn add(self, other: Self) -> Self::Output {
         match self {
-            Self::Float(this) => Self::Float(this + other.as_float()),
-            Self::Float3(this) => Self::Float3(this + other.as_float3()),
+            Self::Float(this) => Ok(Self::Float(this + other.as_float()?)),
+            Self::Float3(this) => Ok(Self::Float3(this + other.as_float3()?)),
             _ => type_error!(self, Type::Float),
         }
     }
 }
 
 impl Sub for Value {
-    type Output = Self;
+    type Output = error::Result<Self>;
 
-    fn sub(self, other: Self) -> Self {
+    fn sub(self, other: Self) -> Self::Output {
         match self {
-            Self::Float(this) => Self::Float(this - other.as_float()),
-            Self::Float3(this) => Self::Float3(this - other.as_float3()),
+            Self::Float(this) => Ok(Self::Float(this - other.as_float()?)),
+            Self::Float3(this) => Ok(Self::Float3(this - other.as_float3()?)),
             _ => type_error!(self, Type::Float),
         }
     }
 }
 
 impl Mul for Value {
-    type Output = Self;
+    type Output = error::Result<Self>;
 
-    fn mul(self, other: Self) -> Self {
+    fn mul(self, other: Self) -> Self::Output {
         match self {
-            Self::Float(this) => Self::Float(this * other.as_float()),
-            Self::Float3(this) => Self::Float3(this * other.as_float3()),
+            Self::Float(this) => Ok(Self::Float(this * other.as_float()?)),
+            Self::Float3(this) => Ok(Self::Float3(this * other.as_float3()?)),
             _ => type_error!(self, Type::Float),
         }
     }
 }
 
 impl Div for Value {
-    type Output = Self;
+    type Output = error::Result<Self>;
 
-    fn div(self, other: Self) -> Self {
+    fn div(self, other: Self) -> Self::Output {
         match self {
-            Self::Float(this) => Self::Float(this / other.as_float()),
-            Self::Float3(this) => Self::Float3(this / other.as_float3()),
+            Self::Float(this) => Ok(Self::Float(this / other.as_float()?)),
+            Self::Float3(this) => Ok(Self::Float3(this / other.as_float3()?)),
             _ => type_error!(self, Type::Float),
         }
     }
 }
 
 impl Rem for Value {
-    type Output = Self;
+    type Output = error::Result<Self>;
 
-    fn rem(self, other: Self) -> Self {
+    fn rem(self, other: Self) -> Self::Output {
         match self {
-            Self::Float(this) => Self::Float(this + other.as_float()),
+            Self::Float(this) => Ok(Self::Float(this % other.as_float()?)),
             Self::Float3(this) => {
-                let other = other.as_float3();
-                Self::Float3(Vec3::new(
+                let other = other.as_float3()?;
+                Ok(Self::Float3(Vec3::new(
                     this.x() % other.x(),
                     this.y() % other.y(),
                     this.z() % other.z(),
-                ))
+                )))
             }
             _ => type_error!(self, Type::Float),
         }
     }
 }
 
+/// Only floats can be compared, other values are unordered.
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.as_float().partial_cmp(&other.as_float())
+        match (self, other) {
+            (Self::Float(this), Self::Float(other)) => this.partial_cmp(other),
+            _ => None,
+        }
     }
 }
 
@@ -264,20 +275,20 @@ pub enum Expression {
 }
 
 impl Expression {
-    pub fn execute<R: Rng>(&self, rng: &mut R) -> Value {
+    pub fn execute<R: Rng>(&self, rng: &mut R) -> error::Result<Value> {
         match self {
-            Self::Unit => Value::Unit,
-            Self::Bool(x) => Value::Bool(*x),
-            Self::Float(x) => Value::Float(*x),
-            Self::Float3(x) => Value::Float3(*x),
-            Self::Rand(t) => t.rand(rng),
-            Self::Ratio(n, d) => Value::Bool(rng.gen_ratio(*n, *d)),
-            Self::Add(a, b) => a.execute(rng) + b.execute(rng),
-            Self::Sub(a, b) => a.execute(rng) - b.execute(rng),
-            Self::Mul(a, b) => a.execute(rng) * b.execute(rng),
-            Self::Div(a, b) => a.execute(rng) / b.execute(rng),
-            Self::Rem(a, b) => a.execute(rng) % b.execute(rng),
-            Self::Cast(t, e) => t.cast(e.execute(rng)),
+            Self::Unit => Ok(Value::Unit),
+            Self::Bool(x) => Ok(Value::Bool(*x)),
+            Self::Float(x) => Ok(Value::Float(*x)),
+            Self::Float3(x) => Ok(Value::Float3(*x)),
+            Self::Rand(t) => Ok(t.rand(rng)),
+            Self::Ratio(n, d) => Ok(Value::Bool(rng.gen_ratio(*n, *d))),
+            Self::Add(a, b) => a.execute(rng)? + b.execute(rng)?,
+            Self::Sub(a, b) => a.execute(rng)? - b.execute(rng)?,
+            Self::Mul(a, b) => a.execute(rng)? * b.execute(rng)?,
+            Self::Div(a, b) => a.execute(rng)? / b.execute(rng)?,
+            Self::Rem(a, b) => a.execute(rng)? % b.execute(rng)?,
+            Self::Cast(t, e) => Ok(t.cast(e.execute(rng)?)),
         }
     }
 
@@ -288,8 +299,13 @@ impl Expression {
             Self::Float(_) => Type::Float,
             Self::Float3(_) => Type::Float3,
             Self::Rand(t) => *t,
+            Self::Ratio(_, _) => Type::Bool,
+            Self::Add(a, _)
+            | Self::Sub(a, _)
+            | Self::Mul(a, _)
+            | Self::Div(a, _)
+            | Self::Rem(a, _) => a.type_of(),
             Self::Cast(t, _) => *t,
-            _ => todo!(),
         }
     }
 
@@ -352,19 +368,25 @@ impl ComplexQuery {
         rng: &mut R,
         xz: Option<(i32, i32)>,
         chunk: &Chunk<T>,
-    ) -> Option<Value> {
+        heights: Option<&HeightChunk>,
+    ) -> error::Result<Option<Value>> {
         match self {
-            ComplexQuery::Map(q, e) => q.execute(rng, xz, chunk).map(|_| e.execute(rng)),
-            ComplexQuery::Not(q) => match q.execute(rng, xz, chunk) {
-                Some(_) => None,
-                None => Some(Value::Unit),
+            ComplexQuery::Map(q, e) => match q.execute(rng, xz, chunk, heights)? {
+                Some(_) => e.execute(rng).map(Some),
+                None => Ok(None),
+            },
+            ComplexQuery::Not(q) => match q.execute(rng, xz, chunk, heights)? {
+                Some(_) => Ok(None),
+                None => Ok(Some(Value::Unit)),
+            },
+            ComplexQuery::And(a, b) => match a.execute(rng, xz, chunk, heights)? {
+                Some(_) => b.execute(rng, xz, chunk, heights),
+                None => Ok(None),
+            },
+            ComplexQuery::Or(a, b) => match a.execute(rng, xz, chunk, heights)? {
+                Some(v) => Ok(Some(v)),
+                None => b.execute(rng, xz, chunk, heights),
             },
-            ComplexQuery::And(a, b) => a
-                .execute(rng, xz, chunk)
-                .and_then(|_| b.execute(rng, xz, chunk)),
-            ComplexQuery::Or(a, b) => a
-                .execute(rng, xz, chunk)
-                .or_else(|| b.execute(rng, xz, chunk)),
         }
     }
 }
@@ -384,43 +406,107 @@ pub enum ExpressionQuery {
 }
 
 impl ExpressionQuery {
-    pub fn execute<R: Rng>(&self, rng: &mut R) -> Option<Value> {
-        match self {
-            ExpressionQuery::ValueOf(e) => e.execute(rng).as_option(),
-            ExpressionQuery::IsTrue(e) => e.execute(rng).as_bool().as_option(),
+    pub fn execute<R: Rng>(&self, rng: &mut R) -> error::Result<Option<Value>> {
+        let float = |e: &Expression, rng: &mut R| e.execute(rng)?.as_float();
+        Ok(match self {
+            ExpressionQuery::ValueOf(e) => e.execute(rng)?.as_option(),
+            ExpressionQuery::IsTrue(e) => e.execute(rng)?.as_bool()?.as_option(),
             ExpressionQuery::TypeIs(t, e) => (e.type_of() == *t).as_option(),
-            ExpressionQuery::Eq(a, b) => (a.execute(rng) == b.execute(rng)).as_option(),
-            ExpressionQuery::Ne(a, b) => (a.execute(rng) != b.execute(rng)).as_option(),
-            ExpressionQuery::Lt(a, b) => (a.execute(rng) < b.execute(rng)).as_option(),
-            ExpressionQuery::Gt(a, b) => (a.execute(rng) > b.execute(rng)).as_option(),
-            ExpressionQuery::Le(a, b) => (a.execute(rng) <= b.execute(rng)).as_option(),
-            ExpressionQuery::Ge(a, b) => (a.execute(rng) >= b.execute(rng)).as_option(),
-        }
+            ExpressionQuery::Eq(a, b) => (a.execute(rng)? == b.execute(rng)?).as_option(),
+            ExpressionQuery::Ne(a, b) => (a.execute(rng)? != b.execute(rng)?).as_option(),
+            ExpressionQuery::Lt(a, b) => (float(a, rng)? < float(b, rng)?).as_option(),
+            ExpressionQuery::Gt(a, b) => (float(a, rng)? > float(b, rng)?).as_option(),
+            ExpressionQuery::Le(a, b) => (float(a, rng)? <= float(b, rng)?).as_option(),
+            ExpressionQuery::Ge(a, b) => (float(a, rng)? >= float(b, rng)?).as_option(),
+        })
     }
 }
 
 #[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ColumnQuery {
     YTop,
+    /// The steepness of the terrain, as height difference per unit of horizontal distance.
+    Slope,
+    /// The slope, if it's at most the given value.
+    SlopeBelow(f32),
+    /// Like `YTop`, but finds the highest surface with a world height between the two values,
+    /// including the first and excluding the second, e.g. a cave floor or the sea bed.
+    YRange(i32, i32),
+    /// The value of the named `ClimateField` in the column.
+    Climate(String),
+    /// The value of the named `ClimateField`, if it's between the two values, including the
+    /// first and excluding the second.
+    ClimateRange(String, f32, f32),
 }
 
 impl ColumnQuery {
-    pub fn execute<T: Voxel>(&self, (x, z): (i32, i32), chunk: &Chunk<T>) -> Option<Value> {
-        match self {
+    pub fn execute<T: Voxel>(
+        &self,
+        (x, z): (i32, i32),
+        chunk: &Chunk<T>,
+        heights: Option<&HeightChunk>,
+    ) -> error::Result<Option<Value>> {
+        let slope = || -> error::Result<f32> {
+            let heights = heights.ok_or(Error::Unsupported("slopes without a height map"))?;
+            // heights are stored per unit, which may span several voxels
+            let unit = (chunk.width() as i32 / heights.extent()).max(1);
+            Ok(heights.slope((x / unit, z / unit)))
+        };
+        let climate = |name: &str| -> error::Result<f32> {
+            let heights = heights.ok_or(Error::Unsupported("climate without a height map"))?;
+            let unit = (chunk.width() as i32 / heights.extent()).max(1);
+            heights
+                .climate(name, (x / unit, z / unit))
+                .ok_or_else(|| Error::UnknownClimate(name.to_owned()))
+        };
+        Ok(match self {
             ColumnQuery::YTop => {
                 let h = chunk.width() as i32;
                 if chunk.contains_key((x, h - 1, z)) {
-                    return None;
+                    return Ok(None);
                 }
                 for y in (0..chunk.width() as i32 - 1).rev() {
                     if chunk.contains_key((x, y, z)) {
-                        return Some(Value::Float3(Vec3::new(x as _, y as f32 + 1.0, z as _)));
+                        let top = Vec3::new(x as _, y as f32 + 1.0, z as _);
+                        return Ok(Some(Value::Float3(top)));
                     }
                 }
                 None
             }
-        }
+            ColumnQuery::Slope => Some(Value::Float(slope()?)),
+            ColumnQuery::SlopeBelow(max) => {
+                let slope = slope()?;
+                if slope <= *max {
+                    Some(Value::Float(slope))
+                } else {
+                    None
+                }
+            }
+            ColumnQuery::YRange(min, max) => {
+                let cy = chunk.position().1;
+                let h = chunk.width() as i32;
+                let low = (min - cy).max(0);
+                let high = (max - cy).min(h);
+                // the voxel above a surface has to be empty, so the top of the chunk can't be one
+                for y in (low..high.min(h - 1)).rev() {
+                    if chunk.contains_key((x, y, z)) && !chunk.contains_key((x, y + 1, z)) {
+                        let top = Vec3::new(x as _, y as f32 + 1.0, z as _);
+                        return Ok(Some(Value::Float3(top)));
+                    }
+                }
+                None
+            }
+            ColumnQuery::Climate(name) => Some(Value::Float(climate(name)?)),
+            ColumnQuery::ClimateRange(name, min, max) => {
+                let value = climate(name)?;
+                if (*min..*max).contains(&value) {
+                    Some(Value::Float(value))
+                } else {
+                    None
+                }
+            }
+        })
     }
 }
 
@@ -438,14 +524,12 @@ impl BlockQuery {
         rng: &mut R,
         xz: Option<(i32, i32)>,
         chunk: &Chunk<T>,
-    ) -> Option<Value> {
+        heights: Option<&HeightChunk>,
+    ) -> error::Result<Option<Value>> {
         match self {
-            BlockQuery::Complex(q) => q.execute(rng, xz, chunk),
+            BlockQuery::Complex(q) => q.execute(rng, xz, chunk, heights),
             BlockQuery::Expression(q) => q.execute(rng),
-            BlockQuery::Column(q) => q.execute(
-                xz.expect("column queries must be supplied with a xz coordinate"),
-                chunk,
-            ),
+            BlockQuery::Column(q) => q.execute(xz.ok_or(Error::MissingColumn)?, chunk, heights),
         }
     }
 
@@ -453,6 +537,30 @@ impl BlockQuery {
         BlockQuery::Column(ColumnQuery::YTop)
     }
 
+    pub fn slope() -> Self {
+        BlockQuery::Column(ColumnQuery::Slope)
+    }
+
+    /// Matches columns where the terrain is at most `max` steep, e.g. to keep trees off
+    /// cliffs.
+    pub fn slope_below(max: f32) -> Self {
+        BlockQuery::Column(ColumnQuery::SlopeBelow(max))
+    }
+
+    pub fn y_range(min: i32, max: i32) -> Self {
+        BlockQuery::Column(ColumnQuery::YRange(min, max))
+    }
+
+    pub fn climate(name: &str) -> Self {
+        BlockQuery::Column(ColumnQuery::Climate(name.to_owned()))
+    }
+
+    /// Matches columns where the climate field `name` is between `min` and `max`, e.g. to
+    /// only plant crops where it's warm enough.
+    pub fn climate_range(name: &str, min: f32, max: f32) -> Self {
+        BlockQuery::Column(ColumnQuery::ClimateRange(name.to_owned(), min, max))
+    }
+
     pub fn and_then(self, other: Self) -> Self {
         BlockQuery::Complex(ComplexQuery::And(Box::new(self), Box::new(other)))
     }
@@ -464,6 +572,26 @@ impl BlockQuery {
     pub fn set_block<T: Voxel>(self, block: T) -> Statement<T> {
         Statement::SetBlock { q: self, block }
     }
+
+    /// Fills up to `length` voxels downwards from the position of the query, see
+    /// `Statement::FillDown`.
+    pub fn fill_down<T: Voxel>(self, length: Expression, block: T) -> Statement<T> {
+        Statement::FillDown {
+            q: self,
+            length,
+            block,
+        }
+    }
+
+    /// Fills up to `length` voxels upwards from the position of the query, see
+    /// `Statement::FillUp`.
+    pub fn fill_up<T: Voxel>(self, length: Expression, block: T) -> Statement<T> {
+        Statement::FillUp {
+            q: self,
+            length,
+            block,
+        }
+    }
 }
 
 #[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
@@ -483,6 +611,20 @@ pub enum Statement<T: Voxel> {
         p2: BlockQuery,
         block: T,
     },
+    /// Fills a column of at most `length` voxels down from the position of `q`, e.g. vines or
+    /// icicles. The column only replaces voxels equal to the one it starts at, so it stops
+    /// at the first voxel that is different.
+    FillDown {
+        q: BlockQuery,
+        length: Expression,
+        block: T,
+    },
+    /// Like `FillDown`, but fills upwards, e.g. kelp growing through water from the sea bed.
+    FillUp {
+        q: BlockQuery,
+        length: Expression,
+        block: T,
+    },
 }
 
 impl<T: Voxel> Statement<T> {
@@ -491,23 +633,72 @@ impl<T: Voxel> Statement<T> {
         rng: &mut R,
         xz: Option<(i32, i32)>,
         chunk: &Chunk<T>,
-    ) -> Result<T> {
+        heights: Option<&HeightChunk>,
+    ) -> error::Result<Result<T>> {
         let block = match self {
-            Self::SetBlock { q, block } => q.execute(rng, xz, chunk).map(move |v| {
-                let pos = v.as_float3();
-                let (x, y, z) = (pos.x() as i32, pos.y() as i32, pos.z() as i32);
-                BlockDiff {
-                    at: (x, y, z),
-                    size: (1, 1, 1),
-                    data: vec![block.clone()],
+            Self::SetBlock { q, block } => match q.execute(rng, xz, chunk, heights)? {
+                Some(v) => {
+                    let pos = v.as_float3()?;
+                    let (x, y, z) = (pos.x() as i32, pos.y() as i32, pos.z() as i32);
+                    Some(BlockDiff {
+                        at: (x, y, z),
+                        size: (1, 1, 1),
+                        data: vec![block.clone()],
+                    })
                 }
-            }),
-            _ => todo!(),
+                None => None,
+            },
+            Self::SetColumn { .. } => return Err(Error::Unsupported("Statement::SetColumn")),
+            Self::Fill { .. } => return Err(Error::Unsupported("Statement::Fill")),
+            Self::FillDown { q, length, block } => {
+                fill_column(rng, xz, chunk, heights, (q, length, block), -1)?
+            }
+            Self::FillUp { q, length, block } => {
+                fill_column(rng, xz, chunk, heights, (q, length, block), 1)?
+            }
         };
-        Result { block }
+        Ok(Result { block })
     }
 }
 
+/// Executes `FillDown` if `dy` is `-1` and `FillUp` if it's `1`.
+fn fill_column<R: Rng, T: Voxel>(
+    rng: &mut R,
+    xz: Option<(i32, i32)>,
+    chunk: &Chunk<T>,
+    heights: Option<&HeightChunk>,
+    (q, length, block): (&BlockQuery, &Expression, &T),
+    dy: i32,
+) -> error::Result<Option<BlockDiff<T>>> {
+    let pos = match q.execute(rng, xz, chunk, heights)? {
+        Some(v) => v.as_float3()?,
+        None => return Ok(None),
+    };
+    let length = length.execute(rng)?.as_float()?.max(0.0) as i32;
+    let (x, y, z) = (pos.x() as i32, pos.y() as i32, pos.z() as i32);
+
+    let h = chunk.width() as i32;
+    let start = chunk.get((x, y, z)).map(|voxel| voxel.into_owned());
+    let mut count = 0;
+    while count < length {
+        let y = y + dy * count;
+        if y < 0 || y >= h || chunk.get((x, y, z)).as_deref() != start.as_ref() {
+            break;
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let bottom = if dy < 0 { y - count + 1 } else { y };
+    Ok(Some(BlockDiff {
+        at: (x, bottom, z),
+        size: (1, count as usize, 1),
+        data: vec![block.clone(); count as usize],
+    }))
+}
+
 #[derive(Debug, Clone)]
 pub struct BlockDiff<T: Voxel> {
     pub(crate) at: (i32, i32, i32),
@@ -537,15 +728,89 @@ impl Octave {
 }
 
 #[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy)]
 pub struct Layer<T: Voxel> {
     pub(crate) block: T,
     pub(crate) height: f64,
+    #[cfg_attr(feature = "savedata", serde(default))]
+    pub(crate) strata: Option<Strata>,
+    /// Functions aren't saved, so a loaded layer has to be given it again.
+    #[cfg_attr(feature = "savedata", serde(skip, default = "Option::default"))]
+    pub(crate) modulate: Option<fn(&T, f32) -> T>,
 }
 
 impl<T: Voxel> Layer<T> {
     pub fn new(block: T, height: f64) -> Self {
-        Self { block, height }
+        Self {
+            block,
+            height,
+            strata: None,
+            modulate: None,
+        }
+    }
+
+    /// Bands the layer with `strata`, placing `modulate(block, factor)` instead of the block,
+    /// where `factor` is the brightness of the voxel's band, e.g. `simple::Block::strata`.
+    pub fn with_strata(mut self, strata: Strata, modulate: fn(&T, f32) -> T) -> Self {
+        self.strata = Some(strata);
+        self.modulate = Some(modulate);
+        self
+    }
+
+    /// Returns the block placed at world height `y`, `salt` telling the bands of layers apart.
+    pub(crate) fn block_at(&self, seed: u32, salt: u64, y: f64) -> T {
+        match (&self.strata, self.modulate) {
+            (Some(strata), Some(modulate)) => modulate(&self.block, strata.factor(seed, salt, y)),
+            _ => self.block.clone(),
+        }
+    }
+}
+
+// functions can't be compared reliably, so layers compare their strata and not how they are
+// applied
+impl<T: Voxel> PartialEq for Layer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.block == other.block && self.height == other.height && self.strata == other.strata
+    }
+}
+
+/// Horizontal bands of lighter and darker voxels along the height of a layer, e.g. the rock
+/// strata of cliffs, so they don't need a layer for every shade.
+#[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Strata {
+    /// The thickness of a band in voxels.
+    pub thickness: f64,
+    /// How far the brightness of a band may stray from `1.0`.
+    pub variation: f32,
+    /// How many voxels noise bends the bands up or down, `0.0` for flat bands.
+    pub warp: f64,
+    pub warp_frequency: f64,
+}
+
+impl Strata {
+    pub fn new(thickness: f64, variation: f32) -> Self {
+        Self {
+            thickness,
+            variation,
+            warp: 0.0,
+            warp_frequency: 0.0,
+        }
+    }
+
+    pub fn with_warp(mut self, warp: f64, frequency: f64) -> Self {
+        self.warp = warp;
+        self.warp_frequency = frequency;
+        self
+    }
+
+    /// Returns the brightness of the band at world height `y`, between `1.0 - variation` and
+    /// `1.0 + variation`.
+    pub fn factor(&self, seed: u32, salt: u64, y: f64) -> f32 {
+        let band = (y / self.thickness.max(1.0)).floor() as i32;
+        let hash = chunk_seed(seed, salt, (band, 0, 0));
+        let t = (hash >> 40) as f32 / (1 << 24) as f32;
+        1.0 + self.variation * (t * 2.0 - 1.0)
     }
 }
 
@@ -555,6 +820,11 @@ pub enum NoiseType {
     Perlin,
     OpenSimplex,
     SuperSimplex,
+    /// Fractal noise sampled a height chunk at a time with SIMD instructions, see `SimdFbm`.
+    #[cfg(feature = "simd")]
+    SimdFbm {
+        octaves: u8,
+    },
 }
 
 impl Default for NoiseType {
@@ -609,6 +879,92 @@ impl Default for Filter {
     }
 }
 
+/// A color gradient over the terrain height, e.g. grass that gets paler up a mountain.
+///
+/// Chunks store the tint of every column, and voxels with `VoxelExt::tinted` have their
+/// colors multiplied by it, so biomes can color foliage differently with the same blocks.
+#[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tint {
+    pub low: [f32; 3],
+    pub high: [f32; 3],
+    pub min_height: f64,
+    pub max_height: f64,
+}
+
+impl Tint {
+    /// The same tint at every height.
+    pub fn constant(color: [f32; 3]) -> Self {
+        Self {
+            low: color,
+            high: color,
+            min_height: 0.0,
+            max_height: 0.0,
+        }
+    }
+
+    /// Goes from `low` at `min_height` to `high` at `max_height`.
+    pub fn gradient(low: [f32; 3], high: [f32; 3], min_height: f64, max_height: f64) -> Self {
+        Self {
+            low,
+            high,
+            min_height,
+            max_height,
+        }
+    }
+
+    pub fn at(&self, height: f64) -> [f32; 3] {
+        let t = if self.max_height > self.min_height {
+            ((height - self.min_height) / (self.max_height - self.min_height))
+                .max(0.0)
+                .min(1.0) as f32
+        } else {
+            0.0
+        };
+        let mut color = self.low;
+        for (c, high) in color.iter_mut().zip(&self.high) {
+            *c += (high - *c) * t;
+        }
+        color
+    }
+}
+
+/// A named noise field sampled in every column next to the heights, e.g. temperature or
+/// humidity, so that biome selection and the rules of biomes and games all see the same
+/// climate.
+///
+/// Values range from `min` to `max`. Height chunks store them per column, see
+/// `HeightChunk::climate`, and `Program::climate_at` works them out anywhere.
+#[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClimateField {
+    pub(crate) name: &'static str,
+    pub(crate) frequency: f64,
+    pub(crate) min: f32,
+    pub(crate) max: f32,
+}
+
+impl ClimateField {
+    pub fn new(name: &'static str, frequency: f64, min: f32, max: f32) -> Self {
+        Self {
+            name,
+            frequency,
+            min,
+            max,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Maps a `noise` value of the field's noise source to the field's range.
+    pub(crate) fn value(&self, noise: f64) -> f32 {
+        let t = noise * 0.5 + 0.5;
+        self.min + (self.max - self.min) * t.max(0.0).min(1.0) as f32
+    }
+}
+
 #[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 pub struct Biome<T: Voxel> {
@@ -618,8 +974,12 @@ pub struct Biome<T: Voxel> {
     pub(crate) octaves: Vec<Octave>,
     pub(crate) layers: Vec<Layer<T>>,
     pub(crate) water: Option<Layer<T>>,
+    pub(crate) tint: Option<Tint>,
+    /// The ranges of climate fields the biome is picked in, see `BiomeBuilder::climate`.
+    pub(crate) climate: Vec<(&'static str, f32, f32)>,
     pub(crate) per_xz: Vec<Statement<T>>,
     pub(crate) per_chunk: Vec<Statement<T>>,
+    pub(crate) ores: Vec<Ore<T>>,
 }
 
 impl<T: Voxel> Default for Biome<T> {
@@ -631,8 +991,11 @@ impl<T: Voxel> Default for Biome<T> {
             octaves: Vec::new(),
             layers: Vec::new(),
             water: None,
+            tint: None,
+            climate: Vec::new(),
             per_xz: Vec::new(),
             per_chunk: Vec::new(),
+            ores: Vec::new(),
         }
     }
 }
@@ -684,6 +1047,20 @@ impl<T: Voxel> BiomeBuilder<T> {
         self
     }
 
+    /// Tints the tinted voxels of the biome, see `Tint`.
+    pub fn tint(mut self, t: Tint) -> Self {
+        self.inner.tint = Some(t);
+        self
+    }
+
+    /// Only picks the biome in columns where the climate field `name` is between `min` and
+    /// `max`, including `min` and excluding `max`. Columns that fit no biome pick from all of
+    /// them.
+    pub fn climate(mut self, name: &'static str, min: f32, max: f32) -> Self {
+        self.inner.climate.push((name, min, max));
+        self
+    }
+
     pub fn per_xz(mut self, s: Statement<T>) -> Self {
         self.inner.per_xz.push(s);
         self
@@ -693,12 +1070,19 @@ impl<T: Voxel> BiomeBuilder<T> {
         self.inner.per_chunk.push(s);
         self
     }
+
+    /// Adds an ore that is only generated in columns of this biome.
+    pub fn ore(mut self, o: Ore<T>) -> Self {
+        self.inner.ores.push(o);
+        self
+    }
 }
 
 #[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 pub struct Program<T: Voxel> {
     pub(crate) name: Option<&'static str>,
+    pub(crate) version: u32,
     pub(crate) seed: u32,
     pub(crate) chunk_size: u32,
     pub(crate) subdivisions: u32,
@@ -707,12 +1091,15 @@ pub struct Program<T: Voxel> {
     pub(crate) dimensions: NoiseDimensions,
     pub(crate) noise_type: NoiseType,
     pub(crate) biomes: Vec<Biome<T>>,
+    pub(crate) ores: Vec<Ore<T>>,
+    pub(crate) climate: Vec<ClimateField>,
 }
 
 impl<T: Voxel> Default for Program<T> {
     fn default() -> Self {
         Self {
             name: None,
+            version: 0,
             seed: 0,
             chunk_size: 5,
             subdivisions: 0,
@@ -721,6 +1108,8 @@ impl<T: Voxel> Default for Program<T> {
             dimensions: Default::default(),
             noise_type: Default::default(),
             biomes: Vec::new(),
+            ores: Vec::new(),
+            climate: Vec::new(),
         }
     }
 }
@@ -733,12 +1122,31 @@ impl<T: Voxel> Program<T> {
     }
 }
 
+#[cfg(feature = "savedata")]
+impl<T: Voxel + Serialize> Program<T> {
+    /// A hash of everything that shapes the generated terrain: the seed, noise settings,
+    /// biomes with their layers and statements, ores and climate. Stays the same across runs
+    /// and platforms, see `SaveManifest::record_generator`.
+    ///
+    /// The functions of `Layer::with_strata` can't be hashed, so changing only those goes
+    /// unnoticed.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = ContentHasher::new();
+        bincode::serialize_into(&mut hasher, self).expect("couldn't serialize program");
+        hasher.finish()
+    }
+}
+
 pub struct ProgramBuilder<T: Voxel> {
     inner: Program<T>,
 }
 
 impl<T: Voxel> ProgramBuilder<T> {
-    pub fn build(mut self) -> Program<T> {
+    /// The largest chunk size whose chunk width still fits world coordinates.
+    pub const MAX_CHUNK_SIZE: u32 = 30;
+
+    pub fn build(mut self) -> std::result::Result<Program<T>, ProgramError> {
+        self.validate()?;
         let sum = self
             .inner
             .biomes
@@ -751,7 +1159,67 @@ impl<T: Voxel> ProgramBuilder<T> {
         self.inner
             .biomes
             .sort_unstable_by(|a, b| a.prob.partial_cmp(&b.prob).unwrap_or(Ordering::Equal));
-        self.inner
+        Ok(self.inner)
+    }
+
+    fn validate(&self) -> std::result::Result<(), ProgramError> {
+        let program = &self.inner;
+        if program.biomes.is_empty() {
+            return Err(ProgramError::NoBiomes);
+        }
+        for biome in &program.biomes {
+            if !biome.prob.is_finite() || biome.prob < 0.0 {
+                return Err(ProgramError::BiomeProbability {
+                    biome: biome.name,
+                    prob: biome.prob,
+                });
+            }
+        }
+        if program.biomes.iter().all(|biome| biome.prob == 0.0) {
+            return Err(ProgramError::ZeroProbability);
+        }
+        if program.chunk_size > Self::MAX_CHUNK_SIZE {
+            return Err(ProgramError::ChunkSize(program.chunk_size));
+        }
+        if program.subdivisions > program.chunk_size {
+            return Err(ProgramError::Subdivisions {
+                subdivisions: program.subdivisions,
+                chunk_size: program.chunk_size,
+            });
+        }
+        if let Filter::Bilinear(width) = program.filter {
+            if width <= 0 || !(width as u32).is_power_of_two() {
+                return Err(ProgramError::FilterWidth(width));
+            }
+        }
+        if !program.biome_frequency.is_finite() || program.biome_frequency <= 0.0 {
+            return Err(ProgramError::BiomeFrequency(program.biome_frequency));
+        }
+        for field in &program.climate {
+            if !field.frequency.is_finite() || field.frequency <= 0.0 {
+                return Err(ProgramError::ClimateFrequency {
+                    field: field.name,
+                    frequency: field.frequency,
+                });
+            }
+        }
+        for biome in &program.biomes {
+            for &(field, _, _) in &biome.climate {
+                if program.climate.iter().all(|f| f.name != field) {
+                    return Err(ProgramError::UnknownClimate {
+                        biome: biome.name,
+                        field,
+                    });
+                }
+            }
+        }
+        let biome_ores = program.biomes.iter().flat_map(|biome| &biome.ores);
+        for ore in program.ores.iter().chain(biome_ores) {
+            if !ore.veins.is_finite() || ore.veins < 0.0 {
+                return Err(ProgramError::OreVeins(ore.veins));
+            }
+        }
+        Ok(())
     }
 
     pub fn name(mut self, name: &'static str) -> Self {
@@ -759,6 +1227,11 @@ impl<T: Voxel> ProgramBuilder<T> {
         self
     }
 
+    pub fn version(mut self, version: u32) -> Self {
+        self.inner.version = version;
+        self
+    }
+
     pub fn biome_frequency(mut self, freq: f64) -> Self {
         self.inner.biome_frequency = freq;
         self
@@ -779,6 +1252,18 @@ impl<T: Voxel> ProgramBuilder<T> {
         self
     }
 
+    /// Adds an ore that is generated in every biome.
+    pub fn ore(mut self, o: Ore<T>) -> Self {
+        self.inner.ores.push(o);
+        self
+    }
+
+    /// Adds a climate field, see `ClimateField`.
+    pub fn climate(mut self, field: ClimateField) -> Self {
+        self.inner.climate.push(field);
+        self
+    }
+
     pub fn seed(mut self, seed: u32) -> Self {
         self.inner.seed = seed;
         self
@@ -795,14 +1280,63 @@ impl<T: Voxel> ProgramBuilder<T> {
     }
 
     pub fn filter(mut self, filter: Filter) -> Self {
-        match filter {
-            Filter::NearestNeighbour => {}
-            Filter::Bilinear(width) => assert!(
-                (width as u32).is_power_of_two(),
-                "bilinear filter must have a power of two width"
-            ),
-        }
         self.inner.filter = filter;
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::Filter;
+
+    #[test]
+    pub fn program_validation() {
+        let biome = || Biome::<i32>::build().layer(Layer::new(1, 4.0)).build();
+        let program = || Program::<i32>::build().biome(biome());
+        assert!(program().build().is_ok());
+
+        let error = |builder: ProgramBuilder<i32>| builder.build().unwrap_err();
+        assert_eq!(error(Program::build()), ProgramError::NoBiomes);
+        let unlikely = Biome::build().spawn_probability(0.0).build();
+        let never = Program::build().biome(unlikely);
+        assert_eq!(error(never), ProgramError::ZeroProbability);
+        let negative = Biome::build().name("cave").spawn_probability(-1.0).build();
+        assert_eq!(
+            error(program().biome(negative)),
+            ProgramError::BiomeProbability {
+                biome: Some("cave"),
+                prob: -1.0
+            }
+        );
+        assert_eq!(error(program().chunk_size(31)), ProgramError::ChunkSize(31));
+        assert_eq!(
+            error(program().chunk_size(3).subdivisions(4)),
+            ProgramError::Subdivisions {
+                subdivisions: 4,
+                chunk_size: 3
+            }
+        );
+        let filter = program().filter(Filter::Bilinear(3));
+        assert_eq!(error(filter), ProgramError::FilterWidth(3));
+        let frequency = program().biome_frequency(0.0);
+        assert_eq!(error(frequency), ProgramError::BiomeFrequency(0.0));
+    }
+
+    #[test]
+    pub fn value_rem() {
+        let rem = |a: f32, b: f32| (Value::Float(a) % Value::Float(b)).unwrap();
+        assert_eq!(rem(7.0, 3.0), Value::Float(1.0));
+        // like `%` on floats, the remainder takes the sign of the dividend
+        assert_eq!(rem(-7.0, 3.0), Value::Float(-1.0));
+        assert_eq!(rem(7.0, -3.0), Value::Float(1.0));
+        assert_eq!(rem(-7.0, -3.0), Value::Float(-1.0));
+
+        let this = Value::Float3(Vec3::new(-7.0, 7.5, -0.5));
+        let other = Value::Float3(Vec3::new(3.0, -2.0, 0.25));
+        let remainder = (this % other).unwrap();
+        assert_eq!(remainder, Value::Float3(Vec3::new(-1.0, 1.5, -0.0)));
+        assert!((Value::Bool(true) % Value::Float(2.0)).is_err());
+        assert!((Value::Float(1.0) % Value::Bool(true)).is_err());
+    }
+}
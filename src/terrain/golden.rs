@@ -0,0 +1,192 @@
+//! Golden files of generated chunks, to catch changes to the generator that change the world
+//! by accident.
+//!
+//! ```ignore
+//! #[test]
+//! fn plains() {
+//!     assert_golden(&program(), &[(0, 0, 0), (16, 0, 0)], "tests/golden/plains.ron");
+//! }
+//! ```
+//!
+//! Golden files that don't exist yet are written by the first run. After a change that is
+//! meant to change the world, run the tests with `UPDATE_GOLDEN=1` to write them again.
+
+use std::{fmt::Debug, fs, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    error,
+    terrain::{HeightMap, Program},
+    world::Chunk,
+};
+
+/// The environment variable that makes `assert_golden` write golden files instead of
+/// comparing against them.
+pub const UPDATE_GOLDEN: &str = "UPDATE_GOLDEN";
+
+/// The voxels of a chunk as runs of equal voxels in x, y, z order.
+///
+/// Unlike `RleTree`, the runs don't depend on how the voxels of the chunk are merged, so the
+/// same voxels always give the same runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoldenChunk<T> {
+    pub position: (i32, i32, i32),
+    pub width: usize,
+    pub runs: Vec<(usize, Option<T>)>,
+}
+
+impl<T: Voxel + PartialEq + Debug> GoldenChunk<T> {
+    pub fn new(chunk: &Chunk<T>) -> Self {
+        let mut runs: Vec<(usize, Option<T>)> = Vec::new();
+        for voxel in voxels(chunk) {
+            match runs.last_mut() {
+                Some((len, last)) if *last == voxel => *len += 1,
+                _ => runs.push((1, voxel)),
+            }
+        }
+        Self {
+            position: chunk.position(),
+            width: chunk.width(),
+            runs,
+        }
+    }
+
+    /// The voxels of the chunk in x, y, z order.
+    pub fn voxels(&self) -> impl Iterator<Item = &Option<T>> {
+        self.runs
+            .iter()
+            .flat_map(|(len, voxel)| std::iter::repeat(voxel).take(*len))
+    }
+
+    /// Describes the first voxel that differs from `expected`, or returns `None` if there is
+    /// none.
+    fn diff(&self, expected: &Self) -> Option<String> {
+        if self.width != expected.width {
+            return Some(format!(
+                "chunk {:?} is {} wide instead of {}",
+                self.position, self.width, expected.width
+            ));
+        }
+        let (i, (actual, expected)) = self
+            .voxels()
+            .zip(expected.voxels())
+            .enumerate()
+            .find(|(_, (actual, expected))| actual != expected)?;
+        let width = self.width;
+        let coords = (i / (width * width), i / width % width, i % width);
+        Some(format!(
+            "chunk {:?} has {:?} at {:?} instead of {:?}",
+            self.position, actual, coords, expected
+        ))
+    }
+}
+
+fn voxels<T: Voxel>(chunk: &Chunk<T>) -> impl Iterator<Item = Option<T>> + '_ {
+    let width = chunk.width() as i32;
+    (0..width)
+        .flat_map(move |x| (0..width).flat_map(move |y| (0..width).map(move |z| (x, y, z))))
+        .map(move |coords| chunk.voxel(coords).cloned())
+}
+
+/// The chunks of a golden file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Golden<T> {
+    pub chunks: Vec<GoldenChunk<T>>,
+}
+
+impl<T: Voxel + PartialEq + Debug> Golden<T> {
+    /// Generates the chunks at `positions` in order, starting from an empty `HeightMap`.
+    pub fn generate(program: &Program<T>, positions: &[(i32, i32, i32)]) -> error::Result<Self> {
+        let mut height_map = HeightMap::new();
+        let chunks = positions
+            .iter()
+            .map(|&coords| {
+                program
+                    .execute(&mut height_map, coords)
+                    .map(|chunk| GoldenChunk::new(&chunk))
+            })
+            .collect::<error::Result<_>>()?;
+        Ok(Self { chunks })
+    }
+
+    /// Describes how the chunks differ from `expected`, or returns `None` if they don't.
+    pub fn diff(&self, expected: &Self) -> Option<String> {
+        let mut differences = Vec::new();
+        for chunk in &self.chunks {
+            match expected
+                .chunks
+                .iter()
+                .find(|e| e.position == chunk.position)
+            {
+                Some(expected) => differences.extend(chunk.diff(expected)),
+                None => differences.push(format!("chunk {:?} is new", chunk.position)),
+            }
+        }
+        for expected in &expected.chunks {
+            if !self
+                .chunks
+                .iter()
+                .any(|chunk| chunk.position == expected.position)
+            {
+                differences.push(format!("chunk {:?} is missing", expected.position));
+            }
+        }
+        if differences.is_empty() {
+            None
+        } else {
+            Some(differences.join("\n"))
+        }
+    }
+}
+
+impl<T: Serialize> Golden<T> {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> bincode::Result<()> {
+        let path = path.as_ref();
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let ron = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|e| bincode::ErrorKind::Custom(e.to_string()))?;
+        fs::write(path, ron)?;
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Golden<T> {
+    pub fn load<P: AsRef<Path>>(path: P) -> bincode::Result<Self> {
+        let ron = fs::read_to_string(path)?;
+        ron::de::from_str(&ron).map_err(|e| Box::new(bincode::ErrorKind::Custom(e.to_string())))
+    }
+}
+
+/// Generates the chunks at `positions` and compares them with the golden file at `path`,
+/// panicking with the voxels that differ.
+///
+/// Writes the golden file instead if it doesn't exist yet, or if the `UPDATE_GOLDEN`
+/// environment variable is set.
+pub fn assert_golden<T, P>(program: &Program<T>, positions: &[(i32, i32, i32)], path: P)
+where
+    T: Voxel + PartialEq + Debug + Serialize + DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let actual = Golden::generate(program, positions).expect("couldn't generate chunks");
+    if !path.exists() || std::env::var_os(UPDATE_GOLDEN).is_some() {
+        actual
+            .save(path)
+            .unwrap_or_else(|e| panic!("couldn't write {}: {}", path.display(), e));
+        return;
+    }
+    let expected =
+        Golden::load(path).unwrap_or_else(|e| panic!("couldn't read {}: {}", path.display(), e));
+    if let Some(diff) = actual.diff(&expected) {
+        panic!(
+            "generated chunks differ from {}, run with {}=1 if that is intended:\n{}",
+            path.display(),
+            UPDATE_GOLDEN,
+            diff
+        );
+    }
+}
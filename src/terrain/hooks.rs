@@ -0,0 +1,173 @@
+use std::fmt;
+
+use rand::{rngs::SmallRng, SeedableRng};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    terrain::{chunk_seed, HeightChunk, Program},
+    world::{BlockChanged, ChangeCause, Chunk},
+};
+
+/// Tells the random numbers of hooks apart from the ones of ores.
+const HOOK_SALT: u64 = u64::MAX;
+
+type Hook<T> = Box<dyn Fn(&mut Generated<'_, T>) + Send + Sync>;
+
+/// Functions run on every chunk the terrain generator finishes, before it's inserted into the
+/// map, e.g. to place animals or loot next to terrain features while the chunk is at hand
+/// instead of scanning it again later.
+///
+/// Hooks run in the order they were added.
+pub struct GenerationHooks<T: Voxel> {
+    hooks: Vec<Hook<T>>,
+}
+
+impl<T: Voxel> Default for GenerationHooks<T> {
+    fn default() -> Self {
+        Self { hooks: Vec::new() }
+    }
+}
+
+impl<T: Voxel> fmt::Debug for GenerationHooks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GenerationHooks")
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl<T: Voxel> GenerationHooks<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut Generated<'_, T>) + Send + Sync + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Runs the hooks on `chunk`, generated by `program`, and returns the entities they asked
+    /// for.
+    pub fn run(
+        &self,
+        program: &Program<T>,
+        heights: Option<&HeightChunk>,
+        chunk: &mut Chunk<T>,
+    ) -> Vec<SpawnRequest> {
+        if self.hooks.is_empty() {
+            return Vec::new();
+        }
+        let seed = chunk_seed(program.seed, HOOK_SALT, chunk.position());
+        let mut generated = Generated {
+            chunk,
+            program,
+            heights,
+            rng: SmallRng::seed_from_u64(seed),
+            spawns: Vec::new(),
+        };
+        for hook in &self.hooks {
+            hook(&mut generated);
+        }
+        generated.spawns
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+}
+
+/// Returns the voxels of `chunk` that differ from `before`, the same chunk before the hooks
+/// ran, as changes with `ChangeCause::Generated`.
+pub(crate) fn hook_changes<T: Voxel>(before: &Chunk<T>, chunk: &Chunk<T>) -> Vec<BlockChanged<T>> {
+    let width = chunk.width() as i32;
+    let (cx, cy, cz) = chunk.position();
+    let mut changes = Vec::new();
+    for x in 0..width {
+        for y in 0..width {
+            for z in 0..width {
+                let old = before.get((x, y, z));
+                let new = chunk.get((x, y, z));
+                if old != new {
+                    changes.push(BlockChanged {
+                        coords: (cx + x, cy + y, cz + z),
+                        old: old.map(|voxel| voxel.into_owned()),
+                        new: new.map(|voxel| voxel.into_owned()),
+                        cause: ChangeCause::Generated,
+                    });
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// A chunk that was just generated, as seen by `GenerationHooks`.
+pub struct Generated<'a, T: Voxel> {
+    pub chunk: &'a mut Chunk<T>,
+    pub program: &'a Program<T>,
+    /// The heights and biomes of the chunk's columns, `None` for programs without heights.
+    pub heights: Option<&'a HeightChunk>,
+    /// Seeded with the program's seed and the chunk's position, so a chunk gets the same
+    /// numbers whenever and in whatever order it's generated.
+    pub rng: SmallRng,
+    spawns: Vec<SpawnRequest>,
+}
+
+impl<'a, T: Voxel> Generated<'a, T> {
+    /// Returns the biome of the voxel column at chunk-local `(x, z)`, as an index into the
+    /// biomes of the program.
+    pub fn biome(&self, (x, z): (i32, i32)) -> Option<usize> {
+        let subdivisions = self.program.subdivisions;
+        self.chunk.meta()?.biome(
+            self.program.chunk_width(),
+            (x >> subdivisions, z >> subdivisions),
+        )
+    }
+
+    /// Returns the name of the biome of the voxel column at chunk-local `(x, z)`.
+    pub fn biome_name(&self, coords: (i32, i32)) -> Option<&'static str> {
+        self.program.biomes.get(self.biome(coords)?)?.name
+    }
+
+    /// Returns the value of the climate field `name` in the voxel column at chunk-local
+    /// `(x, z)`.
+    pub fn climate(&self, name: &str, (x, z): (i32, i32)) -> Option<f32> {
+        let subdivisions = self.program.subdivisions;
+        self.heights?
+            .climate(name, (x >> subdivisions, z >> subdivisions))
+    }
+
+    /// Returns the world height of the terrain surface in the voxel column at chunk-local
+    /// `(x, z)`, before structures were placed on it.
+    pub fn height(&self, (x, z): (i32, i32)) -> Option<f32> {
+        let subdivisions = self.program.subdivisions;
+        let height = self.heights?.get((x >> subdivisions, z >> subdivisions));
+        Some(height * self.program.unit_width() as f32)
+    }
+
+    /// Asks for an entity of `kind` at chunk-local `coords`, see `SpawnRequest`.
+    pub fn spawn(&mut self, (x, y, z): (i32, i32, i32), kind: &'static str) {
+        let (cx, cy, cz) = self.chunk.position();
+        self.spawns.push(SpawnRequest {
+            coords: (cx + x, cy + y, cz + z),
+            kind,
+        });
+    }
+}
+
+/// An entity a `GenerationHooks` hook asked for. `generate_chunks` collects them and
+/// `terrain_generation` sends them as events, which are registered by `TerrainPlugin`.
+/// Spawning them is up to the game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpawnRequest {
+    /// In world coordinates.
+    pub coords: (i32, i32, i32),
+    pub kind: &'static str,
+}
@@ -1,23 +1,57 @@
-use std::time::Instant;
+#[cfg(feature = "bevy")]
+use std::marker::PhantomData;
 
+#[cfg(feature = "bevy")]
+use instant::Instant;
+
+#[cfg(feature = "bevy")]
 use bevy::prelude::*;
+#[cfg(feature = "bevy")]
 use bevy::diagnostic::Diagnostic;
+#[cfg(feature = "bevy")]
 use bevy::diagnostic::Diagnostics;
+#[cfg(feature = "bevy")]
 use bevy::diagnostic::DiagnosticId;
 
-use noise::{NoiseFn, OpenSimplex, Perlin, Seedable, SuperSimplex};
+#[cfg(feature = "bevy")]
+use crate::world::WorldReset;
+
 use rand::SeedableRng;
 use rstar::{PointDistance, RTree, RTreeObject, AABB};
 
 use crate::{
     collections::lod_tree::Voxel,
-    world::{Chunk, ChunkUpdate, Map, MapUpdates},
+    error::{self, Error},
+    terrain::cache::ColumnKey,
+    world::{
+        Chunk, ChunkMeta, ChunkUpdate, Flow, Map, MapLayout, MapUpdates, Neighborhood, Structure,
+        UpdateCause,
+    },
 };
 
+pub mod cache;
 pub mod dsl;
+#[cfg(feature = "savedata")]
+pub mod golden;
+pub mod hooks;
+pub mod ore;
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+pub mod pregen;
+pub mod source;
 
+pub use cache::{CachedDecorations, GenerationCache};
 pub use dsl::*;
+#[cfg(feature = "savedata")]
+pub use golden::{assert_golden, Golden, GoldenChunk};
+pub use hooks::{Generated, GenerationHooks, SpawnRequest};
+pub use ore::{DepthCurve, Ore, OreBuilder, VeinShape};
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+pub use pregen::{pregenerate, PregenStage};
+#[cfg(feature = "simd")]
+pub use source::SimdFbm;
+pub use source::{noise_source, NoiseSource};
 
+#[cfg(feature = "bevy")]
 pub const WORLD_GEN_DIAGNOSTIC: DiagnosticId = DiagnosticId::from_u128(1234057812345871);
 
 #[derive(Debug, Clone)]
@@ -27,6 +61,9 @@ pub struct HeightChunk {
     filter: Filter,
     array: Vec<f32>,
     water: Vec<Option<f32>>,
+    biomes: Vec<usize>,
+    /// The name and samples of every climate field, in the same order as the heights.
+    climate: Vec<(&'static str, Vec<f32>)>,
 }
 
 impl HeightChunk {
@@ -37,9 +74,43 @@ impl HeightChunk {
             filter,
             array,
             water,
+            biomes: Vec::new(),
+            climate: Vec::new(),
         }
     }
 
+    /// Attaches the biome of every height sample, in the same order as the heights.
+    pub fn with_biomes(mut self, biomes: Vec<usize>) -> Self {
+        self.biomes = biomes;
+        self
+    }
+
+    /// Returns the biome the heights of column `(x, z)` were generated with, or `None` if the
+    /// chunk has no biomes. Columns between two samples get the biome of the sample before them.
+    pub fn biome(&self, (x, z): (i32, i32)) -> Option<usize> {
+        self.biomes.get(self.sample_index((x, z))).copied()
+    }
+
+    /// Attaches the samples of climate fields, each in the same order as the heights.
+    pub fn with_climate(mut self, climate: Vec<(&'static str, Vec<f32>)>) -> Self {
+        self.climate = climate;
+        self
+    }
+
+    /// Returns the value of the climate field `name` in column `(x, z)`, or `None` if the
+    /// chunk has no such field. Like biomes, columns between two samples get the value of the
+    /// sample before them.
+    pub fn climate(&self, name: &str, (x, z): (i32, i32)) -> Option<f32> {
+        let (_, samples) = self.climate.iter().find(|(field, _)| *field == name)?;
+        samples.get(self.sample_index((x, z))).copied()
+    }
+
+    /// The index of the sample at or before column `(x, z)`.
+    fn sample_index(&self, (x, z): (i32, i32)) -> usize {
+        let filter = self.filter.as_i32();
+        ((x / filter) * self.width as i32 + z / filter) as usize
+    }
+
     pub fn get(&self, (x, z): (i32, i32)) -> f32 {
         match self.filter {
             Filter::NearestNeighbour => self.array[(x * self.width as i32 + z) as usize],
@@ -66,6 +137,36 @@ impl HeightChunk {
     pub fn insert(&mut self, (x, z): (i32, i32), value: f32) {
         self.array[(x * self.width as i32 + z) as usize] = value;
     }
+
+    /// The number of columns along each side that `get` accepts.
+    pub fn extent(&self) -> i32 {
+        (self.width as i32 - self.filter.aux_width()) * self.filter.as_i32()
+    }
+
+    /// Returns the rate of change of the height along x and z at `(x, z)`. Columns at the
+    /// border only look at their neighbour inside the chunk.
+    pub fn gradient(&self, (x, z): (i32, i32)) -> (f32, f32) {
+        let max = self.extent() - 1;
+        let difference = |a: (i32, i32), b: (i32, i32), run: i32| {
+            if run == 0 {
+                0.0
+            } else {
+                (self.get(b) - self.get(a)) / run as f32
+            }
+        };
+        let (x0, x1) = ((x - 1).max(0), (x + 1).min(max));
+        let (z0, z1) = ((z - 1).max(0), (z + 1).min(max));
+        (
+            difference((x0, z), (x1, z), x1 - x0),
+            difference((x, z0), (x, z1), z1 - z0),
+        )
+    }
+
+    /// Returns the steepness of the terrain at `(x, z)`, the length of its `gradient`.
+    pub fn slope(&self, (x, z): (i32, i32)) -> f32 {
+        let (dx, dz) = self.gradient((x, z));
+        (dx * dx + dz * dz).sqrt()
+    }
 }
 
 impl RTreeObject for HeightChunk {
@@ -136,67 +237,71 @@ impl HeightMap {
 }
 
 impl<T: Voxel> Program<T> {
-    pub fn height_chunk<N: NoiseFn<[f64; 2]> + Seedable + Default>(
-        &self,
-        (cx, cz): (i32, i32),
-    ) -> HeightChunk {
+    pub fn height_chunk(&self, (cx, cz): (i32, i32)) -> HeightChunk {
         let a = self.filter.aux_width();
-        let mut chunk =
-            Vec::with_capacity((self.chunk_width() / self.filter.as_usize() + a as usize).pow(2));
-        let mut water =
-            Vec::with_capacity((self.chunk_width() / self.filter.as_usize() + a as usize).pow(2));
-
-        let noise = N::default().set_seed(self.seed);
-        let unit_width = self.unit_width() as i32;
-
-        let size = self.chunk_width() as i32 / self.filter.as_i32();
-
-        let mut biome_map = Vec::with_capacity(chunk.capacity());
-
-        for x in 0..size + a {
-            let ax = cx + x * unit_width * self.filter.as_i32();
-            let fx = ax as f64;
-            for z in 0..size + a {
-                let az = cz + z * unit_width * self.filter.as_i32();
-                let fz = az as f64;
-                let mut height =
-                    noise.get([fx * self.biome_frequency, fz * self.biome_frequency]) * 0.5 + 0.5;
-                let mut idx = 0_usize;
-                for (i, biome) in self.biomes.iter().enumerate() {
-                    if height < biome.prob {
-                        idx = i;
-                        break;
-                    }
-                    height -= biome.prob;
+        let samples = (self.chunk_width() / self.filter.as_usize() + a as usize).pow(2);
+        let mut chunk = Vec::with_capacity(samples);
+        let mut water = Vec::with_capacity(samples);
+
+        let noise = self.noise_source(self.seed);
+        let width = (self.chunk_width() as i32 / self.filter.as_i32() + a) as usize;
+        let origin = [cx as f64, cz as f64];
+        let step = (self.unit_width() as i32 * self.filter.as_i32()) as f64;
+
+        let climate = self
+            .climate
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                self.noise_source(self.climate_seed(i))
+                    .grid(origin, step, width, field.frequency)
+                    .into_iter()
+                    .map(|value| field.value(value))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut column_climate = vec![0.0; self.climate.len()];
+        let biome_map = noise
+            .grid(origin, step, width, self.biome_frequency)
+            .into_iter()
+            .enumerate()
+            .map(|(n, roll)| {
+                for (i, field) in climate.iter().enumerate() {
+                    column_climate[i] = field[n];
                 }
-                biome_map.push(idx);
+                self.pick_biome(roll * 0.5 + 0.5, &column_climate)
+            })
+            .collect::<Vec<_>>();
+
+        // every octave of the biomes in the chunk, sampled a grid at a time
+        let mut octaves = vec![Vec::new(); self.biomes.len()];
+        for &biome in &biome_map {
+            if octaves[biome].is_empty() {
+                octaves[biome] = self.biomes[biome]
+                    .octaves
+                    .iter()
+                    .map(|octave| noise.grid(origin, step, width, octave.frequency))
+                    .collect();
             }
         }
 
-        for x in 0..size + a {
-            let ax = cx + x * unit_width * self.filter.as_i32();
-            let fx = ax as f64;
-            for z in 0..size + a {
-                let az = cz + z * unit_width * self.filter.as_i32();
-                let fz = az as f64;
-                let biome = biome_map[(x * (size + a) + z) as usize];
-                let biome = &self.biomes[biome];
-                let mut height = biome.height;
-                for octave in &biome.octaves {
-                    height += noise.get([fx * octave.frequency, fz * octave.frequency])
-                        * octave.amplitude;
-                }
-                chunk.push(height as f32);
-                if let Some(water_layer) = &biome.water {
-                    if water_layer.height > height {
-                        let water_height = water_layer.height;
-                        water.push(Some(water_height as f32))
-                    } else {
-                        water.push(None)
-                    }
+        for (n, &biome) in biome_map.iter().enumerate() {
+            let octave_noise = &octaves[biome];
+            let biome = &self.biomes[biome];
+            let mut height = biome.height;
+            for (octave, values) in biome.octaves.iter().zip(octave_noise) {
+                height += values[n] * octave.amplitude;
+            }
+            chunk.push(height as f32);
+            if let Some(water_layer) = &biome.water {
+                if water_layer.height > height {
+                    let water_height = water_layer.height;
+                    water.push(Some(water_height as f32))
                 } else {
                     water.push(None)
                 }
+            } else {
+                water.push(None)
             }
         }
 
@@ -207,6 +312,69 @@ impl<T: Voxel> Program<T> {
             chunk,
             water,
         )
+        .with_biomes(biome_map)
+        .with_climate(
+            self.climate
+                .iter()
+                .map(|field| field.name)
+                .zip(climate)
+                .collect(),
+        )
+    }
+
+    /// Picks the biome of a column from a `roll` between `0.0` and `1.0`, among the biomes
+    /// whose climate ranges fit the column's `climate`.
+    fn pick_biome(&self, roll: f64, climate: &[f32]) -> usize {
+        let fits = |biome: &Biome<T>| {
+            biome.climate.iter().all(|&(name, min, max)| {
+                self.climate
+                    .iter()
+                    .position(|field| field.name == name)
+                    .map_or(false, |i| (min..max).contains(&climate[i]))
+            })
+        };
+        let mut candidates = self
+            .biomes
+            .iter()
+            .enumerate()
+            .filter(|(_, biome)| fits(biome))
+            .collect::<Vec<_>>();
+        // columns that fit no biome pick from all of them
+        if candidates.iter().all(|(_, biome)| biome.prob == 0.0) {
+            candidates = self.biomes.iter().enumerate().collect();
+        }
+        let mut roll = roll * candidates.iter().map(|(_, biome)| biome.prob).sum::<f64>();
+        for &(i, biome) in &candidates {
+            if roll < biome.prob {
+                return i;
+            }
+            roll -= biome.prob;
+        }
+        candidates[0].0
+    }
+
+    /// Returns the value of the climate field `name` at world coordinates `(x, z)`, the same
+    /// value height chunks store for a column there.
+    pub fn climate_at(&self, name: &str, (x, z): (i32, i32)) -> Option<f32> {
+        let i = self.climate.iter().position(|field| field.name == name)?;
+        let field = &self.climate[i];
+        let noise = self.noise_source(self.climate_seed(i));
+        let value = noise.get([x as f64 * field.frequency, z as f64 * field.frequency]);
+        Some(field.value(value))
+    }
+
+    pub fn climate_fields(&self) -> &[ClimateField] {
+        &self.climate
+    }
+
+    /// Returns the program's noise seeded with `seed`.
+    pub fn noise_source(&self, seed: u32) -> Box<dyn NoiseSource> {
+        noise_source(self.noise_type, seed)
+    }
+
+    /// Every climate field gets its own noise, so temperature and humidity aren't the same.
+    fn climate_seed(&self, field: usize) -> u32 {
+        self.seed ^ 0x9e37_79b9_u32.wrapping_mul(field as u32 + 1)
     }
 
     pub fn chunk_width(&self) -> usize {
@@ -217,82 +385,196 @@ impl<T: Voxel> Program<T> {
         2_usize.pow(self.subdivisions)
     }
 
-    pub fn execute(&self, height_map: &mut HeightMap, coords: (i32, i32, i32)) -> Chunk<T> {
+    pub fn execute(
+        &self,
+        height_map: &mut HeightMap,
+        coords: (i32, i32, i32),
+    ) -> error::Result<Chunk<T>> {
+        match self.dimensions {
+            NoiseDimensions::Two => terrain_gen2_impl(self, height_map, None, coords),
+            NoiseDimensions::Three => terrain_gen3_impl(self, coords),
+        }
+    }
+
+    /// Like `execute`, but copies the terrain of chunks with the same columns as a chunk
+    /// generated before from `cache`, see `GenerationCache`.
+    pub fn execute_cached(
+        &self,
+        height_map: &mut HeightMap,
+        cache: &mut GenerationCache<T>,
+        coords: (i32, i32, i32),
+    ) -> error::Result<Chunk<T>> {
         match self.dimensions {
-            NoiseDimensions::Two => match self.noise_type {
-                NoiseType::Perlin => terrain_gen2_impl::<_, Perlin>(self, height_map, coords),
-                NoiseType::OpenSimplex => {
-                    terrain_gen2_impl::<_, OpenSimplex>(self, height_map, coords)
-                }
-                NoiseType::SuperSimplex => {
-                    terrain_gen2_impl::<_, SuperSimplex>(self, height_map, coords)
-                }
-            },
-            NoiseDimensions::Three => match self.noise_type {
-                NoiseType::Perlin => terrain_gen3_impl::<_, Perlin>(self, coords),
-                NoiseType::OpenSimplex => terrain_gen3_impl::<_, OpenSimplex>(self, coords),
-                NoiseType::SuperSimplex => terrain_gen3_impl::<_, SuperSimplex>(self, coords),
-            },
+            NoiseDimensions::Two => terrain_gen2_impl(self, height_map, Some(cache), coords),
+            NoiseDimensions::Three => terrain_gen3_impl(self, coords),
         }
     }
 }
 
+/// Generates up to `limit` chunks queued for `ChunkUpdate::GenerateChunk` in `updates`,
+/// followed by prefetched chunks, and queues their neighbours for a light map update. Returns
+/// the number of chunks drained.
+///
+/// `hooks` run on every chunk before it's inserted, and the entities they ask for are added
+/// to `spawns`. The voxels they change are recorded as `ChangeCause::Generated` if the map
+/// tracks changes. Chunks are generated with `Program::execute_cached`.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_chunks<T: Voxel>(
+    program: &Program<T>,
+    hooks: &GenerationHooks<T>,
+    height_map: &mut HeightMap,
+    cache: &mut GenerationCache<T>,
+    map: &mut Map<T>,
+    map_update: &mut MapUpdates,
+    limit: usize,
+    spawns: &mut Vec<SpawnRequest>,
+) -> usize {
+    let mut count = 0;
+    let mut neighbors = Vec::new();
+    let mut generated = Vec::new();
+    let mut queue = map_update.drain_kind(ChunkUpdate::GenerateChunk, limit);
+    while queue.len() < limit && !map_update.prefetch.is_empty() {
+        // prefetched chunks may have been generated since they were queued
+        let prefetched = map_update.drain_prefetch(limit - queue.len());
+        queue.extend(
+            prefetched
+                .into_iter()
+                .filter(|&coords| map.chunk_containing(coords).is_none()),
+        );
+    }
+    let width = program.chunk_width();
+    for (x, y, z) in queue {
+        // with a mixed layout, the place may be covered by a larger chunk or partly by smaller
+        // ones, e.g. after `Map::split`
+        let last = width as i32 - 1;
+        if map
+            .chunks_in((x, y, z), (x + last, y + last, z + last))
+            .any(|chunk| chunk.position() != (x, y, z) || chunk.width() != width)
+        {
+            continue;
+        }
+        count += 1;
+        let mut chunk = match program.execute_cached(height_map, cache, (x, y, z)) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                log::error!("failed to generate chunk {:?}: {}", (x, y, z), e);
+                continue;
+            }
+        };
+        let heights = match program.dimensions {
+            NoiseDimensions::Two => height_map.get((x, z)),
+            NoiseDimensions::Three => None,
+        };
+        // the changes of the hooks are only worth working out if someone listens
+        let before = if map.track_changes() && !hooks.is_empty() {
+            Some(chunk.clone())
+        } else {
+            None
+        };
+        spawns.extend(hooks.run(program, heights, &mut chunk));
+        if let Some(before) = before {
+            for change in hooks::hook_changes(&before, &chunk) {
+                map.record_change(change);
+            }
+        }
+        chunk.update_detail();
+        let layout = MapLayout::uniform(chunk.width());
+        if let Err(e) = map_update.complete(&mut chunk, ChunkUpdate::GenerateChunk) {
+            log::warn!("{}", e);
+        }
+        generated.push(chunk);
+        for (_, coords) in layout.neighbors((x, y, z), Neighborhood::All) {
+            // the neighbour may be part of a larger chunk
+            let coords = map
+                .chunk_containing(coords)
+                .map_or(coords, |chunk| chunk.position());
+            neighbors.push(coords);
+        }
+    }
+    if matches!(map.layout(), Some(layout) if layout.is_mixed() || layout.chunk_width != width) {
+        for chunk in generated {
+            let position = chunk.position();
+            if let Err(e) = map.try_insert(chunk) {
+                log::error!("failed to insert chunk {:?}: {}", position, e);
+            }
+        }
+    } else if let Err(e) = map.try_insert_many(generated) {
+        log::error!("failed to insert the generated chunks: {}", e);
+    }
+    // the light of the neighbours depends on the new chunks
+    for coords in neighbors {
+        map_update.request_because(coords, ChunkUpdate::UpdateLightMap, UpdateCause::Dependency);
+    }
+    count
+}
+
+/// Adds the resources `terrain_generation` needs besides the `Program<T>`: the `HeightMap`,
+/// the `SpawnRequest` events, and default `GenerationHooks<T>` and `GenerationCache<T>`
+/// unless they were added before.
+#[cfg(feature = "bevy")]
+pub struct TerrainPlugin<T>(PhantomData<T>);
+
+#[cfg(feature = "bevy")]
+impl<T> Default for TerrainPlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[cfg(feature = "bevy")]
+impl<T: Voxel> Plugin for TerrainPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        if !app.resources().contains::<HeightMap>() {
+            app.init_resource::<HeightMap>();
+        }
+        if !app.resources().contains::<GenerationHooks<T>>() {
+            app.init_resource::<GenerationHooks<T>>();
+        }
+        if !app.resources().contains::<GenerationCache<T>>() {
+            app.init_resource::<GenerationCache<T>>();
+        }
+        if !app.resources().contains::<Events<SpawnRequest>>() {
+            app.add_event::<SpawnRequest>();
+        }
+    }
+}
+
+/// Runs `generate_chunks` on every map and sends the entities the hooks ask for as
+/// `SpawnRequest` events. Needs the `Program<T>` resource and the ones added by
+/// `TerrainPlugin<T>`.
+#[cfg(feature = "bevy")]
 pub fn terrain_generation<T: Voxel>(
     params: Res<Program<T>>,
+    hooks: Res<GenerationHooks<T>>,
     mut height_map: ResMut<HeightMap>,
+    mut cache: ResMut<GenerationCache<T>>,
     mut diagnostics: ResMut<Diagnostics>,
+    mut events: ResMut<Events<SpawnRequest>>,
     mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
 ) {
     let start = Instant::now();
-    
+    let span = span!("terrain_generation", chunks);
+
     let max_count = 32;
     let mut count = 0;
+    let mut spawns = Vec::new();
     for (mut map, mut map_update) in &mut query.iter() {
-        let mut remove = Vec::new();
-        let mut insert = Vec::new();
-        for (&(x, y, z), update) in &map_update.updates {
-            match update {
-                ChunkUpdate::GenerateChunk => {}
-                _ => continue,
-            }
-            if count == max_count {
-                break;
-            }
-            count += 1;
-            remove.push((x, y, z));
-            let chunk = params.execute(&mut height_map, (x, y, z));
-            let width = chunk.width() as i32;
-            map.insert(chunk);
-            let range = 1;
-            for lx in -range..=range {
-                for ly in -range..=range {
-                    for lz in -range..=range {
-                        let x = x + lx * width;
-                        let y = y + ly * width;
-                        let z = z + lz * width;
-                        if lx != 0 && ly != 0 && lz != 0 {
-                            if let Some(u) = map_update.updates.get(&(x, y, z)) {
-                                if u > &ChunkUpdate::UpdateLightMap {
-                                    insert.push(((x, y, z), ChunkUpdate::UpdateLightMap));
-                                }
-                                continue;
-                            }
-                        }
-                        insert.push(((x, y, z), ChunkUpdate::UpdateLightMap));
-                    }
-                }
-            }
-        }
-        for coords in remove {
-            map_update.updates.remove(&coords);
-        }
-        for (coords, u) in insert {
-            if !map_update.updates.contains_key(&coords) {
-                map_update.updates.insert(coords, u);
-            }
-        }
+        count += generate_chunks(
+            &params,
+            &hooks,
+            &mut height_map,
+            &mut cache,
+            &mut map,
+            &mut map_update,
+            max_count - count,
+            &mut spawns,
+        );
+    }
+    for spawn in spawns {
+        events.send(spawn);
     }
-    
+    record!(span, chunks, count);
+
     let end = Instant::now();
     let duration = (end - start).as_secs_f64();
     if diagnostics.get(WORLD_GEN_DIAGNOSTIC).is_none() {
@@ -301,50 +583,233 @@ pub fn terrain_generation<T: Voxel>(
     diagnostics.add_measurement(WORLD_GEN_DIAGNOSTIC, duration);
 }
 
-fn terrain_gen2_impl<T: Voxel, N: NoiseFn<[f64; 2]> + Seedable + Default>(
+/// Drops every height chunk when a `WorldReset` is sent, so the next world doesn't get the
+/// heights of the last one.
+#[cfg(feature = "bevy")]
+pub fn height_map_reset_update(
+    mut reader: Local<EventReader<WorldReset>>,
+    events: Res<Events<WorldReset>>,
+    mut height_map: ResMut<HeightMap>,
+) {
+    if reader.iter(&events).next().is_some() {
+        *height_map = HeightMap::new();
+    }
+}
+
+/// Seeds the noise bending the bands of `Strata`.
+const STRATA_SALT: u32 = 0x5157_a7a0;
+
+/// Hashes a program seed, a `salt` telling apart what the numbers are for and chunk
+/// coordinates into a seed for a chunk's random numbers.
+pub(crate) fn chunk_seed(seed: u32, salt: u64, (x, y, z): (i32, i32, i32)) -> u64 {
+    let mut hash = seed as u64;
+    for value in &[salt, x as u32 as u64, y as u32 as u64, z as u32 as u64] {
+        hash = (hash ^ value)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            .rotate_left(31);
+    }
+    hash
+}
+
+fn terrain_gen2_impl<T: Voxel>(
     params: &Program<T>,
     height_map: &mut HeightMap,
+    cache: Option<&mut GenerationCache<T>>,
     (cx, cy, cz): (i32, i32, i32),
-) -> Chunk<T> {
-    let height_chunk = height_map.get_mut_or_else((cx, cz), || params.height_chunk::<N>((cx, cz)));
+) -> error::Result<Chunk<T>> {
+    let height_chunk = height_map.get_mut_or_else((cx, cz), || params.height_chunk((cx, cz)));
 
-    let mut chunk = Chunk::new(params.chunk_size, (cx, cy, cz));
     let unit_width = params.unit_width() as i32;
 
     let size = params.chunk_width() as i32;
 
-    let noise = N::default().set_seed(params.seed);
-    let mut biome_map = Vec::with_capacity(params.chunk_size.pow(2) as usize);
+    // the biomes come from the height chunk, so they always match the heights
+    let mut biome_map = Vec::with_capacity((size * size) as usize);
+    for x in 0..size {
+        for z in 0..size {
+            biome_map.push(height_chunk.biome((x, z)).unwrap_or_default());
+        }
+    }
+
+    let mut cached = None;
+    let mut decorate = true;
+    if let Some(cache) = cache {
+        if let Some(key) = ColumnKey::new(params, height_chunk, &biome_map, cy) {
+            cached = cache.get(&key, (cx, cy, cz));
+            if cached.is_some() {
+                decorate = cache.decorations == CachedDecorations::Reseed;
+            } else {
+                let chunk = generate_columns(params, height_chunk, &biome_map, (cx, cy, cz));
+                cache.insert(key, &chunk);
+                cached = Some(chunk);
+            }
+        }
+    }
+    let mut chunk = match cached {
+        Some(chunk) => chunk,
+        None => generate_columns(params, height_chunk, &biome_map, (cx, cy, cz)),
+    };
+
+    // ores and statements are seeded with the position of the chunk, so copies get their own
+    if decorate {
+        for (i, ore) in params.ores.iter().enumerate() {
+            ore.generate(&mut chunk, params.seed, i as u64, |_, _| true);
+        }
+        for (b, biome) in params.biomes.iter().enumerate() {
+            for (i, ore) in biome.ores.iter().enumerate() {
+                let salt = (b as u64 + 1) << 32 | i as u64;
+                ore.generate(&mut chunk, params.seed, salt, |x, z| {
+                    let (x, z) = (x >> params.subdivisions, z >> params.subdivisions);
+                    biome_map[(x * size + z) as usize] == b
+                });
+            }
+        }
+    }
+
+    let mut tints = Vec::new();
+    if params.biomes.iter().any(|biome| biome.tint.is_some()) {
+        let width = chunk.width();
+        tints = vec![[1.0; 3]; width * width];
+        for x in 0..size {
+            for z in 0..size {
+                let biome = &params.biomes[biome_map[(x * size + z) as usize]];
+                let tint = match &biome.tint {
+                    Some(tint) => tint.at(height_chunk.get((x, z)) as f64),
+                    None => continue,
+                };
+                for ix in 0..unit_width {
+                    for iz in 0..unit_width {
+                        let vx = (x << params.subdivisions) + ix;
+                        let vz = (z << params.subdivisions) + iz;
+                        tints[vx as usize * width + vz as usize] = tint;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut flows = Vec::new();
+    if params.biomes.iter().any(|biome| biome.water.is_some()) {
+        let width = chunk.width();
+        flows = vec![None; width * width];
+        for x in 0..size {
+            for z in 0..size {
+                let biome = &params.biomes[biome_map[(x * size + z) as usize]];
+                let height = height_chunk.get((x, z)) as f64;
+                let water = match &biome.water {
+                    Some(water) if water.height > height => water,
+                    _ => continue,
+                };
+                // the water runs down the ground under it
+                let (dx, dz) = height_chunk.gradient((x, z));
+                let flow = Flow {
+                    direction: [-dx, -dz],
+                    surface: (water.height as i32) << params.subdivisions,
+                    bed: (height as i32) << params.subdivisions,
+                };
+                for ix in 0..unit_width {
+                    for iz in 0..unit_width {
+                        let vx = (x << params.subdivisions) + ix;
+                        let vz = (z << params.subdivisions) + iz;
+                        flows[vx as usize * width + vz as usize] = Some(flow);
+                    }
+                }
+            }
+        }
+        if flows.iter().all(Option::is_none) {
+            flows.clear();
+        }
+    }
+
+    let mut rng = rand::rngs::SmallRng::seed_from_u64((cx as u64) << 32 | cz as u64);
+    let mut structures = Vec::new();
 
     for x in 0..size {
-        let ax = cx + x * unit_width * params.filter.as_i32();
-        let fx = ax as f64;
         for z in 0..size {
-            let az = cz + z * unit_width * params.filter.as_i32();
-            let fz = az as f64;
-            let mut height =
-                noise.get([fx * params.biome_frequency, fz * params.biome_frequency]) * 0.5 + 0.5;
-            let mut idx = 0_usize;
-            for (i, biome) in params.biomes.iter().enumerate() {
-                if height < biome.prob {
-                    idx = i;
-                    break;
+            let biome = biome_map[(x * size + z) as usize];
+            let biome = &params.biomes[biome];
+            let x = x << params.subdivisions;
+            let z = z << params.subdivisions;
+            for stmt in biome.per_xz.iter().filter(|_| decorate) {
+                let result = stmt.execute(&mut rng, Some((x, z)), &chunk, Some(height_chunk))?;
+                if let Some(diff) = result.block {
+                    structures.push(Structure {
+                        position: diff.at,
+                        size: diff.size,
+                    });
+                    for ux in 0..diff.size.0 {
+                        for uy in 0..diff.size.1 {
+                            for uz in 0..diff.size.2 {
+                                for ix in 0..params.unit_width() as i32 {
+                                    for iy in 0..params.unit_width() as i32 {
+                                        for iz in 0..params.unit_width() as i32 {
+                                            let x = diff.at.0 + ux as i32 + ix;
+                                            let y = diff.at.1 + uy as i32 + iy;
+                                            let z = diff.at.2 + uz as i32 + iz;
+                                            chunk.insert(
+                                                (x, y, z),
+                                                diff.data[ux * diff.size.1 * diff.size.2
+                                                    + uy * diff.size.2
+                                                    + uz]
+                                                    .clone(),
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
                 }
-                height -= biome.prob;
             }
-            biome_map.push(idx);
         }
     }
 
+    chunk.set_meta(ChunkMeta {
+        generator: params.name.map(String::from),
+        version: params.version,
+        biomes: biome_map,
+        structures,
+        tints,
+        flows,
+    });
+
+    Ok(chunk)
+}
+
+/// Fills the layers and the water of every column of the chunk at `(cx, cy, cz)`, the part
+/// of its terrain `GenerationCache` keeps.
+fn generate_columns<T: Voxel>(
+    params: &Program<T>,
+    height_chunk: &HeightChunk,
+    biome_map: &[usize],
+    (cx, cy, cz): (i32, i32, i32),
+) -> Chunk<T> {
+    let mut chunk = Chunk::new(params.chunk_size, (cx, cy, cz));
+    let unit_width = params.unit_width() as i32;
+    let size = params.chunk_width() as i32;
+
     let by = cy / unit_width;
+    let mut strata_noise = None;
     for x in 0..size {
         for z in 0..size {
             let biome = biome_map[(x * size + z) as usize];
             let biome = &params.biomes[biome];
             let height = height_chunk.get((x, z)) as f64;
             let mut y = height as i32 - by;
-            for layer in biome.layers.iter().rev() {
+            for (i, layer) in biome.layers.iter().enumerate().rev() {
                 let layer_height = layer.height as i32;
+                // bends the bands of the layer in this column
+                let warp = match &layer.strata {
+                    Some(strata) if strata.warp != 0.0 => {
+                        let noise = strata_noise
+                            .get_or_insert_with(|| params.noise_source(params.seed ^ STRATA_SALT));
+                        let wx = (cx + (x << params.subdivisions)) as f64;
+                        let wz = (cz + (z << params.subdivisions)) as f64;
+                        let frequency = strata.warp_frequency;
+                        noise.get([wx * frequency, wz * frequency]) * strata.warp
+                    }
+                    _ => 0.0,
+                };
                 for _ in 0..layer_height {
                     y -= 1;
                     if y >= size {
@@ -356,10 +821,11 @@ fn terrain_gen2_impl<T: Voxel, N: NoiseFn<[f64; 2]> + Seedable + Default>(
                     let x = x << params.subdivisions;
                     let y = y << params.subdivisions;
                     let z = z << params.subdivisions;
+                    let block = layer.block_at(params.seed, i as u64, (cy + y) as f64 + warp);
                     for ix in 0..params.unit_width() as i32 {
                         for iy in 0..params.unit_width() as i32 {
                             for iz in 0..params.unit_width() as i32 {
-                                chunk.insert((x + ix, y + iy, z + iz), layer.block.clone());
+                                chunk.insert((x + ix, y + iy, z + iz), block.clone());
                             }
                         }
                     }
@@ -391,50 +857,648 @@ fn terrain_gen2_impl<T: Voxel, N: NoiseFn<[f64; 2]> + Seedable + Default>(
         }
     }
 
-    let mut rng = rand::rngs::SmallRng::seed_from_u64((cx as u64) << 32 | cz as u64);
+    chunk
+}
 
-    for x in 0..size {
-        for z in 0..size {
-            let biome = biome_map[(x * size + z) as usize];
-            let biome = &params.biomes[biome];
-            let x = x << params.subdivisions;
-            let z = z << params.subdivisions;
-            for stmt in &biome.per_xz {
-                let result = stmt.execute(&mut rng, Some((x, z)), &chunk);
-                if let Some(diff) = result.block {
-                    for ux in 0..diff.size.0 {
-                        for uy in 0..diff.size.1 {
-                            for uz in 0..diff.size.2 {
-                                for ix in 0..params.unit_width() as i32 {
-                                    for iy in 0..params.unit_width() as i32 {
-                                        for iz in 0..params.unit_width() as i32 {
-                                            let x = diff.at.0 + ux as i32 + ix;
-                                            let y = diff.at.1 + uy as i32 + iy;
-                                            let z = diff.at.2 + uz as i32 + iz;
-                                            chunk.insert(
-                                                (x, y, z),
-                                                diff.data[ux * diff.size.1 * diff.size.2
-                                                    + uy * diff.size.2
-                                                    + uz]
-                                                    .clone(),
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                        }
+fn terrain_gen3_impl<T: Voxel>(
+    _params: &Program<T>,
+    (_cx, _cy, _cz): (i32, i32, i32),
+) -> error::Result<Chunk<T>> {
+    Err(Error::Unsupported("3d terrain generation"))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::world::Eviction;
+
+    #[test]
+    pub fn gradient() {
+        // a ramp rising by 2 along x, with a flat strip at z = 3
+        let mut array = Vec::new();
+        for x in 0..4 {
+            for z in 0..4 {
+                array.push(if z == 3 { 0.0 } else { 2.0 * x as f32 });
+            }
+        }
+        let heights = HeightChunk::new((0, 0), 4, Filter::NearestNeighbour, array, vec![None; 16]);
+        assert_eq!(heights.gradient((1, 0)), (2.0, 0.0));
+        assert_eq!(heights.gradient((0, 0)), (2.0, 0.0));
+        assert_eq!(heights.gradient((3, 3)), (0.0, -6.0));
+        assert_eq!(heights.slope((1, 1)), 2.0);
+
+        let chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        let query = BlockQuery::slope_below(1.0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        let steep = query.execute(&mut rng, Some((1, 1)), &chunk, Some(&heights));
+        assert_eq!(steep.unwrap(), None);
+        let missing = BlockQuery::slope_below(3.0).execute(&mut rng, Some((1, 1)), &chunk, None);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    pub fn tint() {
+        let tint = Tint::gradient([0.0, 1.0, 0.0], [1.0, 1.0, 1.0], 0.0, 10.0);
+        assert_eq!(tint.at(-5.0), [0.0, 1.0, 0.0]);
+        assert_eq!(tint.at(5.0), [0.5, 1.0, 0.5]);
+        assert_eq!(tint.at(20.0), [1.0, 1.0, 1.0]);
+
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .subdivisions(1)
+            .biome(
+                Biome::build()
+                    .layer(Layer::new(1, 4.0))
+                    .tint(Tint::constant([0.2, 0.8, 0.2]))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let chunk = program.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        let meta = chunk.meta().unwrap();
+        assert_eq!(meta.tints.len(), 64);
+        assert_eq!(meta.tint(8, (7, 7)), Some([0.2, 0.8, 0.2]));
+
+        let plain = Program::<i32>::build()
+            .biome(Biome::build().layer(Layer::new(1, 4.0)).build())
+            .build()
+            .unwrap();
+        let chunk = plain.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        assert!(chunk.meta().unwrap().tints.is_empty());
+    }
+
+    #[test]
+    pub fn flow() {
+        let program = Program::<i32>::build()
+            .chunk_size(2)
+            .biome(
+                Biome::build()
+                    .layer(Layer::new(1, 4.0))
+                    .water(Layer::new(2, 6.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+
+        // the ground rises along x, so the water runs towards -x
+        let array = (0..16).map(|i| (i / 4) as f32).collect();
+        let heights = HeightChunk::new((0, 0), 4, Filter::NearestNeighbour, array, vec![None; 16]);
+        let mut height_map = HeightMap::with_chunks(vec![heights]);
+        let chunk = program.execute(&mut height_map, (0, 0, 0)).unwrap();
+        let river = chunk.meta().unwrap().flow(4, (2, 1)).unwrap();
+        assert_eq!(river.direction, [-1.0, 0.0]);
+        assert_eq!((river.surface, river.bed), (6, 2));
+        assert_eq!(river.depth(5), Some(0));
+        assert_eq!(river.depth(2), Some(3));
+        assert_eq!(river.depth(6), None);
+        // the riverbed and the ground under it are dry
+        assert_eq!(river.depth(1), None);
+        assert_eq!(river.depth(-3), None);
+
+        // over flat ground it stands still, and chunks without water have no flows
+        let chunk = program.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        let meta = chunk.meta().unwrap();
+        assert_eq!(meta.flows.len(), 16);
+        assert_eq!(meta.flow(4, (3, 3)).unwrap().direction, [0.0, 0.0]);
+        let above = program.execute(&mut HeightMap::new(), (0, 4, 0)).unwrap();
+        let mut map = Map::try_with_chunks(vec![chunk, above]).unwrap();
+        assert!(map.flow((1, 5, 1)).is_some());
+        assert!(map.flow((1, 6, 1)).is_none());
+
+        let dry = Program::<i32>::build()
+            .chunk_size(2)
+            .biome(Biome::build().layer(Layer::new(1, 4.0)).build())
+            .build()
+            .unwrap();
+        let chunk = dry.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        assert!(chunk.meta().unwrap().flows.is_empty());
+        map.try_insert(chunk).unwrap();
+        assert!(map.flow((1, 1, 1)).is_none());
+        assert!(map.flow((1, 5, 1)).is_some());
+    }
+
+    #[test]
+    pub fn fill_column() {
+        // ground up to y = 2, water up to y = 5
+        let mut chunk = Chunk::<i32>::new(3, (0, 0, 0));
+        for y in 0..6 {
+            chunk.insert((1, y, 1), if y < 3 { 1 } else { 2 });
+        }
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+
+        let top = BlockQuery::y_range(0, 8).execute(&mut rng, Some((1, 1)), &chunk, None);
+        assert_eq!(top.unwrap(), Some(Value::Float3(Vec3::new(1.0, 6.0, 1.0))));
+        let sea_bed = BlockQuery::y_range(0, 3).execute(&mut rng, Some((1, 1)), &chunk, None);
+        assert_eq!(sea_bed.unwrap(), None);
+
+        // kelp grows through the water and stops at the surface
+        let kelp = Expression::Float3(Vec3::new(1.0, 3.0, 1.0))
+            .to_query()
+            .fill_up(Expression::Float(10.0), 3);
+        let diff = kelp
+            .execute(&mut rng, Some((1, 1)), &chunk, None)
+            .unwrap()
+            .block
+            .unwrap();
+        assert_eq!((diff.at, diff.size), ((1, 3, 1), (1, 3, 1)));
+
+        // vines hang down through the air until they reach the water
+        let vines = Expression::Float3(Vec3::new(1.0, 7.0, 1.0))
+            .to_query()
+            .fill_down(Expression::Float(3.0), 4);
+        let diff = vines
+            .execute(&mut rng, Some((1, 1)), &chunk, None)
+            .unwrap()
+            .block
+            .unwrap();
+        assert_eq!((diff.at, diff.size), ((1, 6, 1), (1, 2, 1)));
+        assert_eq!(diff.data, vec![4, 4]);
+
+        let rem = Expression::Float(7.0).rem(Expression::Float(3.0));
+        assert_eq!(rem.execute(&mut rng).unwrap(), Value::Float(1.0));
+    }
+
+    #[test]
+    pub fn biome_cache() {
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .biome_frequency(0.1)
+            .biome(Biome::build().layer(Layer::new(1, 4.0)).build())
+            .biome(Biome::build().height(8.0).layer(Layer::new(2, 4.0)).build())
+            .build()
+            .unwrap();
+        let mut height_map = HeightMap::new();
+        let chunk = program.execute(&mut height_map, (0, 0, 0)).unwrap();
+        let heights = height_map.get((0, 0)).unwrap();
+        for x in 0..8 {
+            for z in 0..8 {
+                let biome = chunk.meta().unwrap().biome(8, (x, z));
+                assert_eq!(biome, heights.biome((x, z)));
+            }
+        }
+
+        // chunks above reuse the cached heights and biomes
+        let above = program.execute(&mut height_map, (0, 8, 0)).unwrap();
+        assert_eq!(above.meta().unwrap().biomes, chunk.meta().unwrap().biomes);
+    }
+
+    #[test]
+    pub fn mixed_generation() {
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .biome(
+                Biome::build()
+                    .height(10.0)
+                    .layer(Layer::new(1, 6.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut map = Map::with_layout(MapLayout::mixed(2, 4));
+        // a small chunk covers part of the first place, a chunk 16 wide all of the second
+        map.try_insert(Chunk::new(2, (4, 0, 0))).unwrap();
+        map.try_insert(Chunk::new(4, (16, 0, 0))).unwrap();
+        let mut updates = MapUpdates::default();
+        for &coords in &[(0, 0, 0), (24, 8, 8), (0, 8, 0)] {
+            updates.request(coords, ChunkUpdate::GenerateChunk);
+        }
+        let (mut height_map, mut cache) = (HeightMap::new(), GenerationCache::default());
+        let hooks = GenerationHooks::default();
+        let count = generate_chunks(
+            &program,
+            &hooks,
+            &mut height_map,
+            &mut cache,
+            &mut map,
+            &mut updates,
+            3,
+            &mut Vec::new(),
+        );
+        assert_eq!(count, 1);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get((0, 8, 0)).unwrap().width(), 8);
+        assert!(map.get((0, 0, 0)).is_none());
+    }
+
+    #[test]
+    pub fn neighbor_requests() {
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .biome(Biome::build().layer(Layer::new(1, 6.0)).build())
+            .build()
+            .unwrap();
+        let mut map = Map::new();
+        let mut updates = MapUpdates::default();
+        updates.track_causes = true;
+        updates.limits.light_map = Some(27);
+        updates.limits.eviction = Eviction::Oldest;
+        updates.request((800, 0, 0), ChunkUpdate::UpdateLightMap);
+        updates.request((0, 0, 0), ChunkUpdate::GenerateChunk);
+        let (mut height_map, mut cache) = (HeightMap::new(), GenerationCache::default());
+        let hooks = GenerationHooks::default();
+        generate_chunks(
+            &program,
+            &hooks,
+            &mut height_map,
+            &mut cache,
+            &mut map,
+            &mut updates,
+            1,
+            &mut Vec::new(),
+        );
+        assert_eq!(updates.updates[&(8, 8, -8)], ChunkUpdate::UpdateLightMap);
+        assert_eq!(updates.cause((8, 8, -8)), Some(UpdateCause::Dependency));
+        // the requests for the neighbours are newer than the one far away
+        let evicted = updates.enforce_limits();
+        assert_eq!(evicted, vec![((800, 0, 0), ChunkUpdate::UpdateLightMap)]);
+    }
+
+    #[test]
+    pub fn strata() {
+        let strata = Strata::new(2.0, 0.5);
+        let program = Program::<i32>::build()
+            .chunk_size(4)
+            .filter(Filter::NearestNeighbour)
+            .biome(
+                Biome::build()
+                    .height(16.0)
+                    .layer(Layer::new(1, 16.0).with_strata(strata, |&block, factor| {
+                        (block as f32 * factor * 100.0) as i32
+                    }))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut height_map = HeightMap::new();
+        let chunk = program.execute(&mut height_map, (0, 0, 0)).unwrap();
+        let column = (0..16)
+            .map(|y| *chunk.get((3, y, 5)).unwrap())
+            .collect::<Vec<_>>();
+        for (y, &voxel) in column.iter().enumerate() {
+            assert!((50..=150).contains(&voxel));
+            // bands are two voxels thick and flat without warp
+            assert_eq!(voxel, column[y & !1]);
+            assert_eq!(chunk.get((12, y as i32, 0)).as_deref(), Some(&voxel));
+        }
+        assert!(column.iter().any(|&voxel| voxel != column[0]));
+    }
+
+    #[test]
+    pub fn generation_cache() {
+        // a flat world, so every chunk at the same height has the same columns
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .seed(7)
+            .biome(
+                Biome::build()
+                    .height(10.0)
+                    .layer(Layer::new(1, 6.0))
+                    .layer(Layer::new(2, 4.0))
+                    .build(),
+            )
+            .ore(
+                Ore::build(3)
+                    .host(1)
+                    .veins_per_chunk(4.0)
+                    .shape(VeinShape::Blob { radius: 3.0 })
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let voxels = |chunk: &Chunk<i32>| {
+            (0..8 * 8 * 8)
+                .map(|i| chunk.voxel((i / 64, i / 8 % 8, i % 8)).copied())
+                .collect::<Vec<_>>()
+        };
+
+        let mut height_map = HeightMap::new();
+        let mut cache = GenerationCache::default();
+        for &coords in &[(0, 0, 0), (8, 0, 0), (16, 0, 8)] {
+            let cached = program
+                .execute_cached(&mut height_map, &mut cache, coords)
+                .unwrap();
+            let plain = program.execute(&mut height_map, coords).unwrap();
+            assert_eq!(cached.position(), coords);
+            // every copy gets its own ore
+            assert_eq!(voxels(&cached), voxels(&plain));
+        }
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (2, 1, 1));
+        program
+            .execute_cached(&mut height_map, &mut cache, (0, 8, 0))
+            .unwrap();
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (2, 2, 2));
+
+        let mut skip = GenerationCache::new(1).with_decorations(CachedDecorations::Skip);
+        program
+            .execute_cached(&mut height_map, &mut skip, (0, 0, 0))
+            .unwrap();
+        let copy = program
+            .execute_cached(&mut height_map, &mut skip, (8, 0, 0))
+            .unwrap();
+        let plain = program.execute(&mut height_map, (8, 0, 0)).unwrap();
+        for (copy, plain) in voxels(&copy).into_iter().zip(voxels(&plain)) {
+            match plain {
+                Some(3) => assert_eq!(copy, Some(1)),
+                _ => assert_eq!(copy, plain),
+            }
+        }
+        // only the newest chunk is kept
+        program
+            .execute_cached(&mut height_map, &mut skip, (0, 8, 0))
+            .unwrap();
+        assert_eq!(skip.len(), 1);
+        let mut off = GenerationCache::new(0);
+        program
+            .execute_cached(&mut height_map, &mut off, (0, 0, 0))
+            .unwrap();
+        assert!(off.is_empty());
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn golden() {
+        let program = |seed| {
+            Program::<i32>::build()
+                .chunk_size(3)
+                .seed(seed)
+                .noise_type(NoiseType::SuperSimplex)
+                .biome(
+                    Biome::build()
+                        .octave(Octave::new(6.0, 0.05))
+                        .layer(Layer::new(1, f64::INFINITY))
+                        .layer(Layer::new(2, 2.0))
+                        .layer(Layer::new(3, 1.0))
+                        .water(Layer::new(4, 0.0))
+                        .build(),
+                )
+                .ore(
+                    Ore::build(5)
+                        .host(1)
+                        .veins_per_chunk(2.0)
+                        .shape(VeinShape::Blob { radius: 2.0 })
+                        .build(),
+                )
+                .build()
+                .unwrap()
+        };
+        let positions = [(0, -8, 0), (0, 0, 0), (8, 0, 0), (0, 0, -8)];
+        // changes to the generator that change this file have to be checked and written with
+        // `UPDATE_GOLDEN=1`
+        assert_golden(
+            &program(7),
+            &positions,
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/terrain.ron"),
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("bevy_voxel_golden_test_{}.ron", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_golden(&program(7), &positions, &path);
+        assert_golden(&program(7), &positions, &path);
+        let expected = Golden::<i32>::load(&path).unwrap();
+        assert_eq!(Golden::generate(&program(7), &positions).unwrap(), expected);
+        let other = Golden::generate(&program(8), &positions).unwrap();
+        let diff = other.diff(&expected).unwrap();
+        assert!(diff.contains("chunk (0, 0, 0) has"));
+        let fewer = Golden::generate(&program(7), &positions[1..]).unwrap();
+        let diff = fewer.diff(&expected).unwrap();
+        assert_eq!(diff, "chunk (0, -8, 0) is missing");
+        std::fs::remove_file(&path).unwrap();
+
+        // the runs don't depend on how the voxels are merged
+        let mut merged = Chunk::<i32>::new(2, (0, 0, 0));
+        let mut unmerged = Chunk::<i32>::new(2, (0, 0, 0));
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    merged.insert((x, y, z), 1);
+                    unmerged.insert((x, y, z), 1);
+                }
+            }
+        }
+        merged.merge();
+        assert_eq!(GoldenChunk::new(&merged), GoldenChunk::new(&unmerged));
+        assert_eq!(GoldenChunk::new(&merged).runs, vec![(64, Some(1))]);
+    }
+
+    #[test]
+    pub fn ore() {
+        let curve = DepthCurve::new(vec![(0.0, 1.0), (-10.0, 0.0)]);
+        assert_eq!(curve.at(-20.0), 0.0);
+        assert_eq!(curve.at(-5.0), 0.5);
+        assert_eq!(curve.at(5.0), 1.0);
+
+        // stone up to y = 5 with dirt on top, ore only replaces the stone
+        let program = |depth| {
+            Program::<i32>::build()
+                .chunk_size(3)
+                .seed(7)
+                .biome(
+                    Biome::build()
+                        .height(10.0)
+                        .layer(Layer::new(1, 6.0))
+                        .layer(Layer::new(2, 4.0))
+                        .build(),
+                )
+                .ore(
+                    Ore::build(3)
+                        .host(1)
+                        .veins_per_chunk(4.0)
+                        .depth(depth)
+                        .shape(VeinShape::Blob { radius: 3.0 })
+                        .build(),
+                )
+                .build()
+                .unwrap()
+        };
+        let plain = program(DepthCurve::constant(0.0));
+        let plain = plain.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        let ores = program(DepthCurve::constant(1.0));
+        let chunk = ores.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        let again = ores.execute(&mut HeightMap::new(), (0, 0, 0)).unwrap();
+        let mut count = 0;
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    let voxel = chunk.voxel((x, y, z)).copied();
+                    assert_eq!(voxel, again.voxel((x, y, z)).copied());
+                    let before = plain.voxel((x, y, z)).copied();
+                    assert_ne!(before, Some(3));
+                    if voxel == Some(3) {
+                        assert_eq!(before, Some(1));
+                        count += 1;
+                    } else {
+                        assert_eq!(voxel, before);
                     }
                 }
             }
         }
+        assert!(count > 0);
+
+        let negative = Program::<i32>::build()
+            .biome(Biome::build().layer(Layer::new(1, 4.0)).build())
+            .ore(Ore::build(3).veins_per_chunk(-1.0).build())
+            .build();
+        assert_eq!(negative.unwrap_err(), error::ProgramError::OreVeins(-1.0));
     }
 
-    chunk
-}
+    #[test]
+    pub fn hooks() {
+        use rand::Rng;
 
-fn terrain_gen3_impl<T: Voxel, N: NoiseFn<[f64; 3]> + Seedable + Default>(
-    _params: &Program<T>,
-    (_cx, _cy, _cz): (i32, i32, i32),
-) -> Chunk<T> {
-    todo!()
+        use crate::world::{BlockChanged, ChangeCause};
+
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .seed(3)
+            .biome(
+                Biome::build()
+                    .name("plains")
+                    .height(5.0)
+                    .layer(Layer::new(1, 5.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let hooks = GenerationHooks::new()
+            .with_hook(|generated: &mut Generated<'_, i32>| {
+                for x in 0..8 {
+                    for z in 0..8 {
+                        assert_eq!(generated.biome_name((x, z)), Some("plains"));
+                        let y = generated.height((x, z)).unwrap() as i32;
+                        if generated.rng.gen_bool(0.25) {
+                            generated.spawn((x, y, z), "sheep");
+                        }
+                    }
+                }
+            })
+            .with_hook(|generated: &mut Generated<'_, i32>| generated.chunk.insert((0, 7, 0), 9));
+
+        let generate = || {
+            let mut map = Map::new();
+            map.set_track_changes(true);
+            let mut updates = MapUpdates::default();
+            updates.request((0, 0, 0), ChunkUpdate::GenerateChunk);
+            let mut height_map = HeightMap::new();
+            let mut cache = GenerationCache::default();
+            let mut spawns = Vec::new();
+            generate_chunks(
+                &program,
+                &hooks,
+                &mut height_map,
+                &mut cache,
+                &mut map,
+                &mut updates,
+                1,
+                &mut spawns,
+            );
+            (map, spawns)
+        };
+        let (mut map, spawns) = generate();
+        assert_eq!(map.voxel((0, 7, 0)).unwrap().into_owned(), 9);
+        assert_eq!(
+            map.drain_changes(),
+            vec![BlockChanged {
+                coords: (0, 7, 0),
+                old: None,
+                new: Some(9),
+                cause: ChangeCause::Generated,
+            }]
+        );
+        assert!(!spawns.is_empty());
+        for spawn in &spawns {
+            assert_eq!(spawn.kind, "sheep");
+            assert_eq!(spawn.coords.1, 5);
+            assert!(map.voxel((spawn.coords.0, 4, spawn.coords.2)).is_some());
+        }
+        assert_eq!(generate().1, spawns);
+    }
+
+    #[test]
+    pub fn climate() {
+        let program = Program::<i32>::build()
+            .chunk_size(4)
+            .filter(Filter::NearestNeighbour)
+            .climate(ClimateField::new("temperature", 0.05, -10.0, 30.0))
+            .climate(ClimateField::new("humidity", 0.05, 0.0, 1.0))
+            .biome(
+                Biome::build()
+                    .name("tundra")
+                    .climate("temperature", -10.0, 10.0)
+                    .layer(Layer::new(1, 4.0))
+                    .build(),
+            )
+            .biome(
+                Biome::build()
+                    .name("desert")
+                    .climate("temperature", 10.0, 30.0)
+                    .layer(Layer::new(2, 4.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut height_map = HeightMap::new();
+        let chunk = program.execute(&mut height_map, (16, 0, -16)).unwrap();
+        let heights = height_map.get((16, -16)).unwrap();
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        for x in 0..16 {
+            for z in 0..16 {
+                let temperature = heights.climate("temperature", (x, z)).unwrap();
+                let at = program.climate_at("temperature", (16 + x, -16 + z));
+                assert_eq!(at, Some(temperature));
+                let humidity = heights.climate("humidity", (x, z)).unwrap();
+                assert!((0.0..=1.0).contains(&humidity));
+                assert_ne!(humidity, temperature);
+
+                let biome = program.biomes[heights.biome((x, z)).unwrap()].name;
+                let warm = temperature >= 10.0;
+                assert_eq!(biome, Some(if warm { "desert" } else { "tundra" }));
+
+                let query = BlockQuery::climate_range("temperature", 10.0, 30.0);
+                let result = query.execute(&mut rng, Some((x, z)), &chunk, Some(heights));
+                assert_eq!(result.unwrap().is_some(), warm);
+            }
+        }
+        assert_eq!(heights.climate("rain", (0, 0)), None);
+        let rain = BlockQuery::climate("rain");
+        let rain = rain.execute(&mut rng, Some((0, 0)), &chunk, Some(heights));
+        assert!(matches!(rain, Err(Error::UnknownClimate(_))));
+
+        let unknown = Program::<i32>::build()
+            .biome(Biome::build().climate("rain", 0.0, 1.0).build())
+            .build();
+        assert_eq!(
+            unknown.unwrap_err(),
+            error::ProgramError::UnknownClimate {
+                biome: None,
+                field: "rain",
+            }
+        );
+        let still = Program::<i32>::build()
+            .climate(ClimateField::new("wind", 0.0, 0.0, 1.0))
+            .biome(Biome::build().build())
+            .build();
+        assert!(matches!(
+            still.unwrap_err(),
+            error::ProgramError::ClimateFrequency { field: "wind", .. }
+        ));
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn fingerprint() {
+        let program = |seed, height| {
+            Program::<i32>::build()
+                .seed(seed)
+                .biome(Biome::build().layer(Layer::new(1, height)).build())
+                .build()
+                .unwrap()
+        };
+        let fingerprint = program(1, 4.0).fingerprint();
+        assert_eq!(program(1, 4.0).fingerprint(), fingerprint);
+        assert_ne!(program(2, 4.0).fingerprint(), fingerprint);
+        assert_ne!(program(1, 5.0).fingerprint(), fingerprint);
+    }
 }
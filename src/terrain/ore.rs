@@ -0,0 +1,265 @@
+use std::f64::consts::PI;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{collections::lod_tree::Voxel, terrain::chunk_seed, world::Chunk};
+
+/// How likely an ore vein is to start at a world height, interpolated linearly between
+/// `(height, probability)` points and clamped to the first and last point.
+#[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl DepthCurve {
+    pub fn new(mut points: Vec<(f64, f64)>) -> Self {
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Self { points }
+    }
+
+    /// The same probability at every height.
+    pub fn constant(probability: f64) -> Self {
+        Self::new(vec![(0.0, probability)])
+    }
+
+    pub fn at(&self, height: f64) -> f64 {
+        let (first, last) = match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if height <= first.0 {
+            return first.1;
+        }
+        if height >= last.0 {
+            return last.1;
+        }
+        for pair in self.points.windows(2) {
+            let ((h0, p0), (h1, p1)) = (pair[0], pair[1]);
+            if height <= h1 {
+                let t = if h1 > h0 {
+                    (height - h0) / (h1 - h0)
+                } else {
+                    1.0
+                };
+                return p0 + (p1 - p0) * t;
+            }
+        }
+        last.1
+    }
+}
+
+impl Default for DepthCurve {
+    fn default() -> Self {
+        Self::constant(1.0)
+    }
+}
+
+#[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VeinShape {
+    /// A lumpy ellipsoid with radii of up to `radius` voxels.
+    Blob { radius: f64 },
+    /// A curved tube `length` voxels long and `radius` voxels thick.
+    Spline { length: f64, radius: f64 },
+}
+
+impl VeinShape {
+    /// How far from its start a vein can reach.
+    fn reach(&self) -> f64 {
+        match *self {
+            Self::Blob { radius } => radius,
+            Self::Spline { length, radius } => length + radius,
+        }
+    }
+}
+
+impl Default for VeinShape {
+    fn default() -> Self {
+        Self::Blob { radius: 2.0 }
+    }
+}
+
+/// Veins of `block` scattered through the terrain, replacing only host voxels.
+///
+/// Every chunk gets the same veins for the same seed, and veins that start in a neighbouring
+/// chunk continue into it, so chunks can be generated in any order.
+#[cfg_attr(feature = "savedata", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Ore<T: Voxel> {
+    pub(crate) block: T,
+    pub(crate) hosts: Vec<T>,
+    pub(crate) veins: f64,
+    pub(crate) depth: DepthCurve,
+    pub(crate) shape: VeinShape,
+}
+
+impl<T: Voxel> Ore<T> {
+    pub fn build(block: T) -> OreBuilder<T> {
+        OreBuilder {
+            inner: Self {
+                block,
+                hosts: Vec::new(),
+                veins: 1.0,
+                depth: DepthCurve::default(),
+                shape: VeinShape::default(),
+            },
+        }
+    }
+
+    /// Places the parts of the veins that reach into `chunk`. `salt` tells apart the ores of
+    /// a program, and veins are only placed into the columns, in chunk-local voxel
+    /// coordinates, for which `columns` returns `true`.
+    pub(crate) fn generate<F>(&self, chunk: &mut Chunk<T>, seed: u32, salt: u64, columns: F)
+    where
+        F: Fn(i32, i32) -> bool,
+    {
+        let width = chunk.width() as i32;
+        let (px, py, pz) = chunk.position();
+        let n = (self.shape.reach() / width as f64).ceil().max(0.0) as i32;
+        for ox in -n..=n {
+            for oy in -n..=n {
+                for oz in -n..=n {
+                    let origin = (px + ox * width, py + oy * width, pz + oz * width);
+                    let mut rng = SmallRng::seed_from_u64(chunk_seed(seed, salt, origin));
+                    let mut count = self.veins.floor() as usize;
+                    if rng.gen::<f64>() < self.veins.fract() {
+                        count += 1;
+                    }
+                    for _ in 0..count {
+                        let start = [
+                            (origin.0 + rng.gen_range(0, width)) as f64 + 0.5,
+                            (origin.1 + rng.gen_range(0, width)) as f64 + 0.5,
+                            (origin.2 + rng.gen_range(0, width)) as f64 + 0.5,
+                        ];
+                        if rng.gen::<f64>() >= self.depth.at(start[1]) {
+                            continue;
+                        }
+                        for (center, radii) in self.vein(&mut rng, start) {
+                            self.carve(chunk, center, radii, &columns);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The ellipsoids making up a vein starting at `start`.
+    fn vein(&self, rng: &mut SmallRng, start: [f64; 3]) -> Vec<([f64; 3], [f64; 3])> {
+        match self.shape {
+            VeinShape::Blob { radius } => {
+                let mut radii = [0.0; 3];
+                for r in &mut radii {
+                    *r = radius * rng.gen_range(0.5, 1.0);
+                }
+                vec![(start, radii)]
+            }
+            VeinShape::Spline { length, radius } => {
+                let yaw: f64 = rng.gen_range(0.0, 2.0 * PI);
+                let pitch: f64 = rng.gen_range(-0.5, 0.5);
+                let direction = [
+                    yaw.cos() * pitch.cos(),
+                    pitch.sin(),
+                    yaw.sin() * pitch.cos(),
+                ];
+                let mut end = start;
+                let mut control = start;
+                for i in 0..3 {
+                    end[i] += direction[i] * length;
+                    control[i] +=
+                        direction[i] * length / 2.0 + rng.gen_range(-1.0, 1.0) * length / 3.0;
+                }
+                // a quadratic bezier curve from start to end, one sphere per voxel of length
+                let steps = length.ceil().max(1.0) as usize;
+                (0..=steps)
+                    .map(|step| {
+                        let t = step as f64 / steps as f64;
+                        let mut point = [0.0; 3];
+                        for i in 0..3 {
+                            point[i] = (1.0 - t).powi(2) * start[i]
+                                + 2.0 * (1.0 - t) * t * control[i]
+                                + t.powi(2) * end[i];
+                        }
+                        (point, [radius; 3])
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn carve<F>(&self, chunk: &mut Chunk<T>, center: [f64; 3], radii: [f64; 3], columns: &F)
+    where
+        F: Fn(i32, i32) -> bool,
+    {
+        let width = chunk.width() as i32;
+        let position = chunk.position();
+        let position = [position.0, position.1, position.2];
+        let mut min = [0; 3];
+        let mut max = [0; 3];
+        for i in 0..3 {
+            min[i] = ((center[i] - radii[i]).floor() as i32 - position[i]).max(0);
+            max[i] = ((center[i] + radii[i]).ceil() as i32 - position[i]).min(width - 1);
+        }
+        for x in min[0]..=max[0] {
+            for z in min[2]..=max[2] {
+                if !columns(x, z) {
+                    continue;
+                }
+                for y in min[1]..=max[1] {
+                    let local = [x, y, z];
+                    let mut distance = 0.0;
+                    for i in 0..3 {
+                        let d = (position[i] + local[i]) as f64 + 0.5 - center[i];
+                        distance += (d / radii[i].max(0.5)).powi(2);
+                    }
+                    if distance > 1.0 {
+                        continue;
+                    }
+                    let host = match chunk.voxel((x, y, z)) {
+                        Some(voxel) => self.hosts.is_empty() || self.hosts.contains(voxel),
+                        None => false,
+                    };
+                    if host {
+                        chunk.insert((x, y, z), self.block.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct OreBuilder<T: Voxel> {
+    inner: Ore<T>,
+}
+
+impl<T: Voxel> OreBuilder<T> {
+    pub fn build(self) -> Ore<T> {
+        self.inner
+    }
+
+    /// Adds a voxel the ore can replace. Without hosts, the ore replaces any voxel that isn't
+    /// empty.
+    pub fn host(mut self, host: T) -> Self {
+        self.inner.hosts.push(host);
+        self
+    }
+
+    /// The average number of veins starting in every chunk, before `depth` thins them out.
+    pub fn veins_per_chunk(mut self, veins: f64) -> Self {
+        self.inner.veins = veins;
+        self
+    }
+
+    pub fn depth(mut self, depth: DepthCurve) -> Self {
+        self.inner.depth = depth;
+        self
+    }
+
+    pub fn shape(mut self, shape: VeinShape) -> Self {
+        self.inner.shape = shape;
+        self
+    }
+}
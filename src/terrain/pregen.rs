@@ -0,0 +1,149 @@
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use line_drawing::Bresenham3d;
+
+use crate::{
+    error,
+    lighting::{self, AmbientLight, DirectionalLight},
+    mesh::VoxelExt,
+    terrain::{GenerationCache, HeightMap, Program},
+    world::Map,
+};
+
+/// The stages of `pregenerate`, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PregenStage {
+    Generate,
+    LightMap,
+    Light,
+    Save,
+}
+
+/// Generates, lights and saves every chunk intersecting the region from `min` to `max`, both
+/// inclusive, in world coordinates, without running an `App`.
+///
+/// `progress` is called with the current stage and the number of chunks done out of the
+/// total after every chunk. Returns the generated map.
+pub fn pregenerate<T, P, F>(
+    program: &Program<T>,
+    min: (i32, i32, i32),
+    max: (i32, i32, i32),
+    directional: &DirectionalLight,
+    ambient: &AmbientLight,
+    save_directory: P,
+    mut progress: F,
+) -> error::Result<Map<T>>
+where
+    T: VoxelExt + Serialize + DeserializeOwned,
+    P: AsRef<Path>,
+    F: FnMut(PregenStage, usize, usize),
+{
+    let _span = span!("pregenerate");
+    let width = 1 << program.chunk_size as i32;
+    let chunk_range = |min: i32, max: i32| {
+        (min.div_euclid(width)..=max.div_euclid(width)).map(move |c| c * width)
+    };
+
+    let mut coords = Vec::new();
+    for x in chunk_range(min.0, max.0) {
+        for y in chunk_range(min.1, max.1) {
+            for z in chunk_range(min.2, max.2) {
+                coords.push((x, y, z));
+            }
+        }
+    }
+    let total = coords.len();
+
+    let mut height_map = HeightMap::new();
+    let mut cache = GenerationCache::default();
+    let mut map = Map::new();
+    for (i, &coords) in coords.iter().enumerate() {
+        map.try_insert(program.execute_cached(&mut height_map, &mut cache, coords)?)?;
+        progress(PregenStage::Generate, i + 1, total);
+    }
+
+    for (i, chunk) in map.iter_mut().enumerate() {
+        lighting::light_map::<_, Bresenham3d<i32>>(chunk, directional);
+        progress(PregenStage::LightMap, i + 1, total);
+    }
+    for chunk in map.iter_mut() {
+        chunk.swap_light();
+    }
+
+    // the light maps of all neighbours have to be done before any chunk can be shaded
+    let light_maps = coords
+        .iter()
+        .filter_map(|&coords| Some((coords, lighting::shaded_light_map(&map, coords)?)))
+        .collect::<Vec<_>>();
+    for (i, (coords, light_map)) in light_maps.into_iter().enumerate() {
+        if let Some(chunk) = map.get_at_origin_mut(coords) {
+            lighting::shaded_light(chunk, &light_map, directional, ambient);
+        }
+        progress(PregenStage::Light, i + 1, total);
+    }
+
+    map.save(save_directory)?;
+    progress(PregenStage::Save, total, total);
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use glam::Vec3;
+
+    use super::*;
+    use crate::terrain::{Biome, Layer};
+
+    #[test]
+    pub fn pregenerate() {
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .biome(Biome::build().height(4.0).layer(Layer::new(1, 4.0)).build())
+            .build()
+            .unwrap();
+        let directional = DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: 0.8,
+        };
+        let ambient = AmbientLight::new(0.2);
+        let dir = std::env::temp_dir().join(format!("bevy_voxel_pregen_{}", std::process::id()));
+
+        // the region touches two chunks along x
+        let mut calls = Vec::new();
+        let map = super::pregenerate(
+            &program,
+            (2, 0, 0),
+            (9, 3, 3),
+            &directional,
+            &ambient,
+            &dir,
+            |stage, done, total| calls.push((stage, done, total)),
+        )
+        .unwrap();
+        assert_eq!(map.len(), 2);
+        assert!(map.iter().all(|chunk| chunk.has_light()));
+        assert_eq!(
+            calls,
+            vec![
+                (PregenStage::Generate, 1, 2),
+                (PregenStage::Generate, 2, 2),
+                (PregenStage::LightMap, 1, 2),
+                (PregenStage::LightMap, 2, 2),
+                (PregenStage::Light, 1, 2),
+                (PregenStage::Light, 2, 2),
+                (PregenStage::Save, 2, 2),
+            ]
+        );
+
+        let loaded = Map::<i32>::load(&dir).unwrap();
+        assert!(loaded.get_at_origin((0, 0, 0)).is_some());
+        assert!(loaded.get_at_origin((8, 0, 0)).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use noise::{NoiseFn, OpenSimplex, Perlin, Seedable, SuperSimplex};
+
+#[cfg(feature = "simd")]
+use simdnoise::NoiseBuilder;
+
+use crate::terrain::NoiseType;
+
+/// The 2D noise the terrain generator samples biomes, heights and climate from.
+pub trait NoiseSource: Send + Sync {
+    /// Returns the noise at `point`, roughly between `-1.0` and `1.0`.
+    fn get(&self, point: [f64; 2]) -> f64;
+
+    /// Samples `width` by `width` points `step` apart from `origin`, scaled by `frequency`,
+    /// in the x-major order of height chunks.
+    ///
+    /// Backends that can sample a whole grid at once should override this, the default calls
+    /// `get` for every point.
+    fn grid(&self, origin: [f64; 2], step: f64, width: usize, frequency: f64) -> Vec<f64> {
+        let mut values = Vec::with_capacity(width * width);
+        for x in 0..width {
+            let fx = origin[0] + x as f64 * step;
+            for z in 0..width {
+                let fz = origin[1] + z as f64 * step;
+                values.push(self.get([fx * frequency, fz * frequency]));
+            }
+        }
+        values
+    }
+}
+
+macro_rules! noise_fn_source {
+    ($($noise:ty),*) => {
+        $(
+            impl NoiseSource for $noise {
+                fn get(&self, point: [f64; 2]) -> f64 {
+                    NoiseFn::get(self, point)
+                }
+            }
+        )*
+    };
+}
+
+noise_fn_source!(Perlin, OpenSimplex, SuperSimplex);
+
+/// Returns the noise of `noise_type` seeded with `seed`.
+pub fn noise_source(noise_type: NoiseType, seed: u32) -> Box<dyn NoiseSource> {
+    match noise_type {
+        NoiseType::Perlin => Box::new(Perlin::default().set_seed(seed)),
+        NoiseType::OpenSimplex => Box::new(OpenSimplex::default().set_seed(seed)),
+        NoiseType::SuperSimplex => Box::new(SuperSimplex::default().set_seed(seed)),
+        #[cfg(feature = "simd")]
+        NoiseType::SimdFbm { octaves } => Box::new(SimdFbm::new(seed, octaves)),
+    }
+}
+
+/// Fractal noise from `simdnoise`, which samples whole grids with SIMD instructions.
+///
+/// It samples in `f32`, so it loses precision far away from the world origin, and its values
+/// differ from the other noise types, so switching to it changes the terrain of a seed.
+#[cfg(feature = "simd")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimdFbm {
+    seed: i32,
+    octaves: u8,
+}
+
+#[cfg(feature = "simd")]
+impl SimdFbm {
+    /// Sums `octaves` layers of noise, each at twice the frequency and half the amplitude of
+    /// the last.
+    pub fn new(seed: u32, octaves: u8) -> Self {
+        Self {
+            seed: seed as i32,
+            octaves: octaves.max(1),
+        }
+    }
+
+    /// The sum of the octaves' amplitudes, to bring the noise back to `-1.0..1.0`.
+    fn amplitude(&self) -> f64 {
+        (0..self.octaves).map(|i| 0.5_f64.powi(i as i32)).sum()
+    }
+}
+
+#[cfg(feature = "simd")]
+impl NoiseSource for SimdFbm {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        self.grid(point, 1.0, 1, 1.0)[0]
+    }
+
+    fn grid(&self, origin: [f64; 2], step: f64, width: usize, frequency: f64) -> Vec<f64> {
+        // simdnoise samples whole steps from the offset and is row-major, so z is its x axis
+        let (values, _, _) = NoiseBuilder::fbm_2d_offset(
+            (origin[1] / step) as f32,
+            width,
+            (origin[0] / step) as f32,
+            width,
+        )
+        .with_freq((step * frequency) as f32)
+        .with_octaves(self.octaves)
+        .with_lacunarity(2.0)
+        .with_gain(0.5)
+        .with_seed(self.seed)
+        .generate();
+        let amplitude = self.amplitude();
+        values
+            .into_iter()
+            .map(|value| value as f64 / amplitude)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn grid() {
+        let noise = noise_source(NoiseType::Perlin, 7);
+        let values = noise.grid([16.0, -8.0], 4.0, 3, 0.01);
+        assert_eq!(values.len(), 9);
+        assert_eq!(values[1], noise.get([16.0 * 0.01, -4.0 * 0.01]));
+        assert_eq!(values[5], noise.get([20.0 * 0.01, 0.0]));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    pub fn simd_grid() {
+        let noise = SimdFbm::new(7, 3);
+        let values = noise.grid([16.0, -8.0], 4.0, 3, 0.01);
+        assert_eq!(values.len(), 9);
+        let expected = noise.get([20.0 * 0.01, 0.0]);
+        assert!((values[5] - expected).abs() < 1e-4);
+        assert!(values.iter().all(|value| value.abs() <= 1.0));
+    }
+}
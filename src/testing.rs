@@ -0,0 +1,268 @@
+//! A headless app running the voxel pipeline, for end-to-end tests of how chunks move from
+//! generation through lighting to meshing.
+//!
+//! ```ignore
+//! let mut harness = Harness::new(program);
+//! harness.request((0, 0, 0));
+//! harness.run_until_idle(16);
+//! harness.assert_idle();
+//! harness.assert_no_empty_meshes();
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+};
+
+use bevy::{diagnostic::Diagnostics, prelude::*};
+
+use glam::Vec3;
+
+use crate::{
+    mesh::{self, VoxelExt},
+    render::{
+        entity::MeshOrigin,
+        light::{
+            light_map_update, shaded_light_update, simple_light_update, AmbientLight,
+            DirectionalLight, FaceShading, LightingMode, LightingRegions,
+        },
+        lod::LodConfig,
+    },
+    terrain::{terrain_generation, Program, TerrainPlugin},
+    world::{invalidation_update, ChunkUpdate, Map, MapLayout, MapUpdates, MergePolicy},
+};
+
+/// The vertex and index counts of a meshed chunk, `None` for the parts without faces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshedChunk {
+    pub opaque: Option<(usize, usize)>,
+    pub transparent: Option<(usize, usize)>,
+    /// How many times the chunk was meshed so far.
+    pub times: usize,
+}
+
+/// The meshes `harness_mesh_update` built, by chunk position. Stands in for the render
+/// entities of a real app.
+#[derive(Debug, Default)]
+pub struct MeshedChunks {
+    pub chunks: HashMap<(i32, i32, i32), MeshedChunk>,
+}
+
+/// Meshes the chunks of every map like the render systems of a game would, but records the
+/// size of the meshes in `MeshedChunks` instead of spawning entities.
+pub fn harness_mesh_update<T: VoxelExt>(
+    mut meshed: ResMut<MeshedChunks>,
+    mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
+) {
+    for (mut map, mut update) in &mut query.iter() {
+        for coords in update.drain_kind(ChunkUpdate::UpdateMesh, usize::MAX) {
+            mesh::update_visibility(&mut map, coords);
+            let (opaque, transparent) = match map.get_at_origin(coords) {
+                Some(chunk) => mesh::generate_chunk_buffers(&map, chunk, MeshOrigin::Corner),
+                None => continue,
+            };
+            let entry = meshed.chunks.entry(coords).or_default();
+            entry.opaque = opaque.map(|b| (b.positions.len(), b.indices.len()));
+            entry.transparent = transparent.map(|b| (b.positions.len(), b.indices.len()));
+            entry.times += 1;
+
+            let chunk = map.get_at_origin_mut(coords).unwrap();
+            if let Err(e) = update.complete(chunk, ChunkUpdate::UpdateMesh) {
+                log::warn!("{}", e);
+            }
+        }
+    }
+}
+
+/// An `App` without window or renderer that generates, lights and meshes the chunks of a
+/// single map, one `step` per frame.
+///
+/// The map is generated by `program` and lit with the default `LightingMode`. Everything the
+/// harness adds can be changed through `app_mut` before the first step.
+pub struct Harness<T: VoxelExt> {
+    app: App,
+    map: Entity,
+    _marker: PhantomData<T>,
+}
+
+impl<T: VoxelExt> Harness<T> {
+    pub fn new(program: Program<T>) -> Self {
+        let layout = MapLayout::new(program.chunk_size);
+        let mut builder = App::build();
+        builder
+            .add_resource(program)
+            .add_resource(DirectionalLight {
+                direction: Vec3::new(0.8, -1.0, 0.5).normalize(),
+                intensity: 0.8,
+            })
+            .add_resource(AmbientLight::new(0.05))
+            .add_plugin(TerrainPlugin::<T>::default())
+            .init_resource::<Diagnostics>()
+            .init_resource::<LightingMode>()
+            .init_resource::<FaceShading>()
+            .init_resource::<LightingRegions>()
+            .init_resource::<MergePolicy>()
+            .init_resource::<LodConfig>()
+            .init_resource::<MeshedChunks>()
+            .add_system_to_stage(stage::PRE_UPDATE, terrain_generation::<T>.system())
+            .add_system_to_stage(stage::UPDATE, invalidation_update::<T>.system())
+            .add_system_to_stage(
+                stage::UPDATE,
+                light_map_update::<T, line_drawing::Bresenham3d<i32>>.system(),
+            )
+            .add_system_to_stage(stage::UPDATE, shaded_light_update::<T>.system())
+            .add_system_to_stage(stage::UPDATE, simple_light_update::<T>.system())
+            .add_system_to_stage(stage::POST_UPDATE, harness_mesh_update::<T>.system());
+        let mut app = builder.app;
+        let map = app
+            .world
+            .spawn((Map::<T>::with_layout(layout), MapUpdates::default()));
+        Self {
+            app,
+            map,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Requests the generation of the chunk at `coords`.
+    pub fn request(&mut self, coords: (i32, i32, i32)) {
+        self.updates_mut()
+            .request(coords, ChunkUpdate::GenerateChunk);
+    }
+
+    /// Runs one frame.
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    pub fn run(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
+    /// Runs frames until no updates are pending, at most `max_frames`, and returns how many
+    /// frames ran.
+    pub fn run_until_idle(&mut self, max_frames: usize) -> usize {
+        for frame in 0..max_frames {
+            if self.is_idle() {
+                return frame;
+            }
+            self.step();
+        }
+        max_frames
+    }
+
+    pub fn is_idle(&self) -> bool {
+        let updates = self.updates();
+        updates.updates.is_empty() && updates.prefetch.is_empty()
+    }
+
+    pub fn map(&self) -> Ref<'_, Map<T>> {
+        self.app.world.get::<Map<T>>(self.map).unwrap()
+    }
+
+    pub fn map_mut(&mut self) -> RefMut<'_, Map<T>> {
+        self.app.world.get_mut::<Map<T>>(self.map).unwrap()
+    }
+
+    pub fn updates(&self) -> Ref<'_, MapUpdates> {
+        self.app.world.get::<MapUpdates>(self.map).unwrap()
+    }
+
+    pub fn updates_mut(&mut self) -> RefMut<'_, MapUpdates> {
+        self.app.world.get_mut::<MapUpdates>(self.map).unwrap()
+    }
+
+    /// Returns what `harness_mesh_update` built for the chunk at `coords`, if it was meshed.
+    pub fn meshed(&self, coords: (i32, i32, i32)) -> Option<MeshedChunk> {
+        let meshed = self.app.resources.get::<MeshedChunks>().unwrap();
+        meshed.chunks.get(&coords).copied()
+    }
+
+    pub fn app_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    /// Panics if any chunk still has an update pending, listing the chunks stuck in every
+    /// stage.
+    pub fn assert_idle(&self) {
+        let updates = self.updates();
+        let mut stuck = BTreeMap::<ChunkUpdate, Vec<_>>::new();
+        for (&coords, update) in &updates.updates {
+            stuck.entry(update.clone()).or_default().push(coords);
+        }
+        for chunks in stuck.values_mut() {
+            chunks.sort_unstable();
+        }
+        assert!(
+            stuck.is_empty() && updates.prefetch.is_empty(),
+            "chunks stuck in the pipeline: {:?}, prefetch: {:?}",
+            stuck,
+            updates.prefetch,
+        );
+    }
+
+    /// Panics if a chunk was meshed to a mesh without vertices or with an index count that
+    /// isn't a whole number of triangles, or if a loaded chunk was never meshed.
+    pub fn assert_no_empty_meshes(&self) {
+        let meshed = self.app.resources.get::<MeshedChunks>().unwrap();
+        let mut bad = meshed
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| {
+                chunk
+                    .opaque
+                    .iter()
+                    .chain(&chunk.transparent)
+                    .any(|&(vertices, indices)| vertices == 0 || indices == 0 || indices % 3 != 0)
+            })
+            .map(|(&coords, _)| coords)
+            .collect::<Vec<_>>();
+        bad.sort_unstable();
+        assert!(bad.is_empty(), "empty or broken meshes: {:?}", bad);
+
+        let mut unmeshed = self
+            .map()
+            .iter()
+            .map(|chunk| chunk.position())
+            .filter(|coords| !meshed.chunks.contains_key(coords))
+            .collect::<Vec<_>>();
+        unmeshed.sort_unstable();
+        assert!(unmeshed.is_empty(), "chunks never meshed: {:?}", unmeshed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        simple::Block,
+        terrain::{Biome, Layer},
+    };
+
+    #[test]
+    pub fn pipeline() {
+        let program = Program::<Block>::build()
+            .chunk_size(3)
+            .biome(
+                Biome::build()
+                    .height(5.0)
+                    .layer(Layer::new(Block::default(), 5.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut harness = Harness::new(program);
+        for &coords in &[(0, 0, 0), (8, 0, 0), (0, 8, 0)] {
+            harness.request(coords);
+        }
+        assert!(harness.run_until_idle(16) < 16);
+        harness.assert_idle();
+        harness.assert_no_empty_meshes();
+        assert_eq!(harness.map().len(), 3);
+        assert!(harness.meshed((0, 0, 0)).unwrap().opaque.is_some());
+        // the chunk above the surface is empty, so it gets no mesh at all
+        assert_eq!(harness.meshed((0, 8, 0)).unwrap().opaque, None);
+    }
+}
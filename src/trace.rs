@@ -0,0 +1,108 @@
+//! Profiling spans for the heavy systems, compiled out unless the `trace` feature is enabled.
+
+/// Enters a span that lasts until the end of the enclosing scope. The listed fields start
+/// out empty and can be filled in with `record!`.
+#[cfg(feature = "trace")]
+macro_rules! span {
+    ($name:expr $(, $field:ident)* $(,)?) => {
+        tracing::info_span!($name $(, $field = tracing::field::Empty)*).entered()
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! span {
+    ($name:expr $(, $field:ident)* $(,)?) => {
+        $crate::trace::NoSpan
+    };
+}
+
+/// Records a counter on a span created by `span!`.
+#[cfg(feature = "trace")]
+#[allow(unused_macros)]
+macro_rules! record {
+    ($span:expr, $field:ident, $value:expr) => {
+        $span.record(stringify!($field), &($value as u64));
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+#[allow(unused_macros)]
+macro_rules! record {
+    ($span:expr, $field:ident, $value:expr) => {
+        let _ = (&$span, &$value);
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+pub(crate) struct NoSpan;
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "trace")]
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    #[cfg(feature = "trace")]
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    /// Writes down the spans that are created and the counters recorded on them.
+    #[cfg(feature = "trace")]
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[cfg(feature = "trace")]
+    impl Visit for &Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let record = format!("{}={:?}", field.name(), value);
+            self.0.lock().unwrap().push(record);
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let name = span.metadata().name().to_string();
+            self.0.lock().unwrap().push(name);
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            values.record(&mut &*self);
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    pub fn span() {
+        let run = || {
+            let span = span!("test_span", chunks);
+            record!(span, chunks, 3usize);
+        };
+        #[cfg(not(feature = "trace"))]
+        run();
+        #[cfg(feature = "trace")]
+        {
+            let recorder = Recorder::default();
+            let records = recorder.0.clone();
+            tracing::subscriber::with_default(recorder, run);
+            assert_eq!(*records.lock().unwrap(), vec!["test_span", "chunks=3"]);
+        }
+    }
+}
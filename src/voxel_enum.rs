@@ -0,0 +1,434 @@
+//! Maps with several kinds of voxels, e.g. cube blocks, plants and fluids, stored as one enum.
+
+use std::{collections::HashMap, mem};
+
+use crate::{
+    mesh::{Face, MeshPart, Neighbours, VoxelExt},
+    world::{Chunk, Map},
+};
+
+/// Meshes one kind of voxel of a `voxel_enum!` in a map of the whole enum.
+///
+/// `VoxelExt::mesh`, `mesh_transition` and `face_material` of a kind only work in a map of
+/// that kind, so every kind of an enum implements this as well.
+pub trait MeshVariant<E: VoxelExt> {
+    fn mesh_variant(
+        &self,
+        coords: (i32, i32, i32),
+        map: &Map<E>,
+        chunk: &Chunk<E>,
+        width: usize,
+    ) -> MeshPart;
+
+    /// The `VoxelExt::mesh_transition` of this kind.
+    fn mesh_variant_transition(
+        &self,
+        coords: (i32, i32, i32),
+        map: &Map<E>,
+        chunk: &Chunk<E>,
+        width: usize,
+        _target: Option<&E>,
+        _progress: f32,
+    ) -> MeshPart {
+        self.mesh_variant(coords, map, chunk, width)
+    }
+
+    /// The `VoxelExt::face_material` of this kind.
+    fn face_material_variant(&self, _face: Face, _neighbours: &Neighbours<'_, E>) -> Option<E> {
+        None
+    }
+}
+
+/// Returns the voxel whose variant is the most common in `data`, the first one on ties.
+#[doc(hidden)]
+pub fn most_common<E>(data: &[E]) -> Option<&E> {
+    let mut counts = HashMap::new();
+    let mut best: Option<(&E, usize)> = None;
+    for voxel in data {
+        let count = counts.entry(mem::discriminant(voxel)).or_insert(0);
+        *count += 1;
+        if best.map(|(_, most)| *count > most).unwrap_or(true) {
+            best = Some((voxel, *count));
+        }
+    }
+    best.map(|(voxel, _)| voxel)
+}
+
+/// Declares an enum with one tuple variant per kind of voxel and implements `Voxel`,
+/// `VoxelExt` and `From` for every kind by dispatching to the kinds.
+///
+/// Every kind has to implement `VoxelExt` and `MeshVariant` for the enum; the methods that look
+/// at the map are forwarded to `MeshVariant`, all others to `VoxelExt`. Averaging keeps the
+/// most common kind, and voxels of different kinds never hide each other's faces.
+///
+/// ```ignore
+/// voxel_enum! {
+///     #[derive(Debug, Clone, PartialEq)]
+///     pub enum Voxels {
+///         Cube(Cube),
+///         Plant(Plant),
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! voxel_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident($kind:ty)),+ $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $($variant($kind)),+
+        }
+
+        $(
+            impl From<$kind> for $name {
+                fn from(voxel: $kind) -> Self {
+                    $name::$variant(voxel)
+                }
+            }
+        )+
+
+        $crate::__voxel_enum_serde_eq!($name { $($variant),+ });
+
+        impl $crate::collections::lod_tree::Voxel for $name {
+            #[allow(unreachable_patterns)]
+            fn average(data: &[Self]) -> Option<Self> {
+                match $crate::voxel_enum::most_common(data)? {
+                    $(
+                        $name::$variant(_) => {
+                            let kinds = data
+                                .iter()
+                                .filter_map(|voxel| match voxel {
+                                    $name::$variant(kind) => Some(kind.clone()),
+                                    _ => None,
+                                })
+                                .collect::<Vec<_>>();
+                            <$kind as $crate::collections::lod_tree::Voxel>::average(&kinds)
+                                .map($name::$variant)
+                        }
+                    )+
+                }
+            }
+
+            fn can_merge(&self) -> bool {
+                match self {
+                    $(
+                        $name::$variant(kind) => {
+                            $crate::collections::lod_tree::Voxel::can_merge(kind)
+                        }
+                    )+
+                }
+            }
+        }
+
+        impl $crate::mesh::VoxelExt for $name {
+            fn mesh(
+                &self,
+                coords: (i32, i32, i32),
+                map: &$crate::world::Map<Self>,
+                chunk: &$crate::world::Chunk<Self>,
+                width: usize,
+            ) -> $crate::mesh::MeshPart {
+                match self {
+                    $(
+                        $name::$variant(kind) => {
+                            $crate::voxel_enum::MeshVariant::<Self>::mesh_variant(
+                                kind, coords, map, chunk, width,
+                            )
+                        }
+                    )+
+                }
+            }
+
+            fn mesh_transition(
+                &self,
+                coords: (i32, i32, i32),
+                map: &$crate::world::Map<Self>,
+                chunk: &$crate::world::Chunk<Self>,
+                width: usize,
+                target: Option<&Self>,
+                progress: f32,
+            ) -> $crate::mesh::MeshPart {
+                match self {
+                    $(
+                        $name::$variant(kind) => {
+                            $crate::voxel_enum::MeshVariant::<Self>::mesh_variant_transition(
+                                kind, coords, map, chunk, width, target, progress,
+                            )
+                        }
+                    )+
+                }
+            }
+
+            fn merge_group(&self) -> u32 {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::merge_group(kind)),+
+                }
+            }
+
+            fn density(&self) -> f32 {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::density(kind)),+
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn face_hidden_by(&self, neighbour: &Self) -> bool {
+                match (self, neighbour) {
+                    $(
+                        ($name::$variant(kind), $name::$variant(other)) => {
+                            $crate::mesh::VoxelExt::face_hidden_by(kind, other)
+                        }
+                    )+
+                    _ => false,
+                }
+            }
+
+            fn emission(&self) -> f32 {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::emission(kind)),+
+                }
+            }
+
+            fn tinted(&self) -> bool {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::tinted(kind)),+
+                }
+            }
+
+            fn flags(&self) -> $crate::mesh::VoxelFlags {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::flags(kind)),+
+                }
+            }
+
+            fn face_map(&self) -> $crate::mesh::FaceMap {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::face_map(kind)),+
+                }
+            }
+
+            fn face_material(
+                &self,
+                face: $crate::mesh::Face,
+                neighbours: &$crate::mesh::Neighbours<'_, Self>,
+            ) -> Option<Self> {
+                match self {
+                    $(
+                        $name::$variant(kind) => {
+                            $crate::voxel_enum::MeshVariant::<Self>::face_material_variant(
+                                kind, face, neighbours,
+                            )
+                        }
+                    )+
+                }
+            }
+
+            fn set_shade(&mut self, face: $crate::mesh::Face, light: f32) {
+                match self {
+                    $(
+                        $name::$variant(kind) => {
+                            $crate::mesh::VoxelExt::set_shade(kind, face, light)
+                        }
+                    )+
+                }
+            }
+
+            fn shade(&mut self, face: $crate::mesh::Face) -> Option<f32> {
+                match self {
+                    $($name::$variant(kind) => $crate::mesh::VoxelExt::shade(kind, face)),+
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "savedata")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __voxel_enum_serde_eq {
+    ($name:ident { $($variant:ident),+ }) => {
+        impl $crate::serialize::SerDePartialEq<Self> for $name {
+            #[allow(unreachable_patterns)]
+            fn serde_eq(&self, other: &Self) -> bool {
+                match (self, other) {
+                    $(
+                        ($name::$variant(kind), $name::$variant(other)) => {
+                            $crate::serialize::SerDePartialEq::serde_eq(kind, other)
+                        }
+                    )+
+                    _ => false,
+                }
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "savedata"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __voxel_enum_serde_eq {
+    ($name:ident { $($variant:ident),+ }) => {};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        collections::lod_tree::Voxel,
+        mesh::{Face, MeshPart, Neighbours, Transparent, VoxelExt, VoxelFlags},
+        world::{Chunk, Map},
+    };
+
+    use super::MeshVariant;
+
+    fn empty_part() -> MeshPart {
+        MeshPart {
+            positions: Vec::new(),
+            shades: Vec::new(),
+            colors: Vec::new(),
+            indices: Vec::new(),
+            transparent: Transparent::No,
+        }
+    }
+
+    macro_rules! kind {
+        ($kind:ident, $emission:expr) => {
+            #[derive(Debug, Clone, PartialEq)]
+            struct $kind(i32);
+
+            impl Voxel for $kind {
+                fn average(data: &[Self]) -> Option<Self> {
+                    let sum = data.iter().map(|voxel| voxel.0).sum::<i32>();
+                    Some($kind(sum / data.len().max(1) as i32))
+                }
+
+                fn can_merge(&self) -> bool {
+                    true
+                }
+            }
+
+            #[cfg(feature = "savedata")]
+            impl crate::serialize::SerDePartialEq<Self> for $kind {
+                fn serde_eq(&self, other: &Self) -> bool {
+                    self == other
+                }
+            }
+
+            impl VoxelExt for $kind {
+                fn mesh(
+                    &self,
+                    _coords: (i32, i32, i32),
+                    _map: &Map<Self>,
+                    _chunk: &Chunk<Self>,
+                    _width: usize,
+                ) -> MeshPart {
+                    empty_part()
+                }
+
+                fn emission(&self) -> f32 {
+                    $emission
+                }
+
+                fn face_hidden_by(&self, _neighbour: &Self) -> bool {
+                    true
+                }
+            }
+
+            impl MeshVariant<Voxels> for $kind {
+                fn mesh_variant(
+                    &self,
+                    _coords: (i32, i32, i32),
+                    _map: &Map<Voxels>,
+                    _chunk: &Chunk<Voxels>,
+                    _width: usize,
+                ) -> MeshPart {
+                    empty_part()
+                }
+
+                fn mesh_variant_transition(
+                    &self,
+                    _coords: (i32, i32, i32),
+                    _map: &Map<Voxels>,
+                    _chunk: &Chunk<Voxels>,
+                    _width: usize,
+                    target: Option<&Voxels>,
+                    _progress: f32,
+                ) -> MeshPart {
+                    let mut part = empty_part();
+                    if target.is_some() {
+                        part.indices.push(0);
+                    }
+                    part
+                }
+
+                fn face_material_variant(
+                    &self,
+                    face: Face,
+                    neighbours: &Neighbours<'_, Voxels>,
+                ) -> Option<Voxels> {
+                    neighbours.facing(face).map(|voxel| voxel.into_owned())
+                }
+            }
+        };
+    }
+
+    kind!(Stone, 0.0);
+    kind!(Lava, 2.0);
+
+    voxel_enum! {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Voxels {
+            Stone(Stone),
+            Lava(Lava),
+        }
+    }
+
+    #[test]
+    pub fn dispatch() {
+        let stone = Voxels::from(Stone(1));
+        let lava = Voxels::from(Lava(4));
+        assert_eq!(stone.emission(), 0.0);
+        assert_eq!(lava.emission(), 2.0);
+        assert_eq!(stone.flags(), VoxelFlags::empty());
+        assert_eq!(lava.flags(), VoxelFlags::EMITS_LIGHT);
+        assert!(stone.face_hidden_by(&Voxels::Stone(Stone(2))));
+        assert!(!stone.face_hidden_by(&lava));
+
+        let data = [Voxels::Lava(Lava(2)), stone, lava];
+        assert_eq!(Voxels::average(&data), Some(Voxels::Lava(Lava(3))));
+
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((0, 0, 0), Voxels::Stone(Stone(1)));
+        let mut map = Map::new();
+        map.try_insert(chunk.clone()).unwrap();
+        let voxel = chunk.get((0, 0, 0)).unwrap().into_owned();
+        assert!(voxel.mesh((0, 0, 0), &map, &chunk, 1).positions.is_empty());
+        assert!(voxel.clone().shade(Face::Top).is_none());
+    }
+
+    #[test]
+    pub fn dispatch_map() {
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((0, 0, 0), Voxels::Lava(Lava(1)));
+        chunk.insert((0, 1, 0), Voxels::Stone(Stone(2)));
+        let mut map = Map::new();
+        map.try_insert(chunk.clone()).unwrap();
+
+        let lava = Voxels::Lava(Lava(1));
+        let neighbours = Neighbours::new(&map, &chunk, (0, 0, 0), 1);
+        assert_eq!(
+            lava.face_material(Face::Top, &neighbours),
+            Some(Voxels::Stone(Stone(2)))
+        );
+        assert_eq!(lava.face_material(Face::Bottom, &neighbours), None);
+
+        let still = lava.mesh_transition((0, 0, 0), &map, &chunk, 1, None, 0.5);
+        let stone = Voxels::Stone(Stone(1));
+        let fading = lava.mesh_transition((0, 0, 0), &map, &chunk, 1, Some(&stone), 0.5);
+        assert!(still.indices.is_empty());
+        assert_eq!(fading.indices, vec![0]);
+    }
+}
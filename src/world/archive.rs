@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, io, sync::Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, io::Read, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::SaveBackend;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::world::{backend::write_temp, FileBackend};
+
+/// Starts every archive, followed by the gzipped contents.
+#[cfg(not(target_arch = "wasm32"))]
+const ARCHIVE_MAGIC: &[u8; 8] = b"BVOXARC1";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct ArchiveContents {
+    manifest: Option<Vec<u8>>,
+    chunks: BTreeMap<(i32, i32, i32), Vec<u8>>,
+    /// Other files of the save directory, e.g. `world.ron`, by name.
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+/// A whole save in memory, read from and written to a single archive file, e.g. to share a
+/// world or attach it to a bug report.
+///
+/// Use it as the backend of `Map::save_to` and `Map::load_from`, or pack and unpack a save
+/// directory with `from_directory` and `unpack`. Chunks are kept compressed like in a save
+/// directory, and the archive is compressed once more as a whole.
+#[derive(Debug, Default)]
+pub struct ArchiveBackend {
+    contents: Mutex<ArchiveContents>,
+}
+
+impl ArchiveBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the archive at `path`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read<P: AsRef<Path>>(path: P) -> bincode::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != ARCHIVE_MAGIC {
+            let e = io::Error::new(io::ErrorKind::InvalidData, "not a voxel save archive");
+            return Err(e.into());
+        }
+        let contents = bincode::deserialize_from(flate2::read::GzDecoder::new(file))?;
+        Ok(Self {
+            contents: Mutex::new(contents),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Writes the archive to `path`, replacing it only once it's complete.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> bincode::Result<()> {
+        let path = path.as_ref();
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        let mut encoder = flate2::write::GzEncoder::new(&mut bytes, flate2::Compression::fast());
+        bincode::serialize_into(&mut encoder, &*self.contents.lock().unwrap())?;
+        encoder.finish()?;
+        let temp = write_temp(path, &bytes)?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Packs the chunks, manifest and other files of the save in `save_directory`, without
+    /// the chunks' backups.
+    pub fn from_directory<P: AsRef<Path>>(save_directory: P) -> bincode::Result<Self> {
+        let save_directory = save_directory.as_ref();
+        let backend = FileBackend::new(save_directory);
+        let mut contents = ArchiveContents {
+            manifest: backend.read_manifest()?,
+            ..Default::default()
+        };
+        for position in backend.list()? {
+            if let Some(bytes) = backend.read_chunk(position)? {
+                contents.chunks.insert(position, bytes);
+            }
+        }
+        for entry in save_directory.read_dir()? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            if path.is_file() && is_side_file(name) {
+                contents.files.insert(name.to_string(), fs::read(&path)?);
+            }
+        }
+        Ok(Self {
+            contents: Mutex::new(contents),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Writes the chunks, manifest and other files of the archive to `save_directory`.
+    pub fn unpack<P: AsRef<Path>>(&self, save_directory: P) -> bincode::Result<()> {
+        let save_directory = save_directory.as_ref();
+        let backend = FileBackend::new(save_directory);
+        let contents = self.contents.lock().unwrap();
+        if let Some(manifest) = &contents.manifest {
+            backend.write_manifest(manifest)?;
+        }
+        for (&position, bytes) in &contents.chunks {
+            backend.write_chunk(position, bytes)?;
+        }
+        for (name, bytes) in &contents.files {
+            let path = save_directory.join(name);
+            let temp = write_temp(&path, bytes)?;
+            fs::rename(temp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a file to store next to the chunks, e.g. the `WorldMeta` of the world.
+    pub fn insert_file<S: Into<String>>(&self, name: S, bytes: Vec<u8>) {
+        self.contents
+            .lock()
+            .unwrap()
+            .files
+            .insert(name.into(), bytes);
+    }
+
+    pub fn file(&self, name: &str) -> Option<Vec<u8>> {
+        self.contents.lock().unwrap().files.get(name).cloned()
+    }
+}
+
+/// Whether the file `name` of a save directory is neither a chunk, a backup, a temporary
+/// file nor the manifest.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_side_file(name: &str) -> bool {
+    !name.starts_with("chunk.") && !name.ends_with(".tmp") && name != "manifest.bin"
+}
+
+impl SaveBackend for ArchiveBackend {
+    fn read_chunk(&self, position: (i32, i32, i32)) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.contents.lock().unwrap().chunks.get(&position).cloned())
+    }
+
+    fn write_chunk(&self, position: (i32, i32, i32), bytes: &[u8]) -> io::Result<()> {
+        let mut contents = self.contents.lock().unwrap();
+        contents.chunks.insert(position, bytes.to_vec());
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<(i32, i32, i32)>> {
+        Ok(self
+            .contents
+            .lock()
+            .unwrap()
+            .chunks
+            .keys()
+            .copied()
+            .collect())
+    }
+
+    fn delete(&self, position: (i32, i32, i32)) -> io::Result<()> {
+        self.contents.lock().unwrap().chunks.remove(&position);
+        Ok(())
+    }
+
+    fn read_manifest(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.contents.lock().unwrap().manifest.clone())
+    }
+
+    fn write_manifest(&self, bytes: &[u8]) -> io::Result<()> {
+        self.contents.lock().unwrap().manifest = Some(bytes.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::world::{tests::map, Map, MapUpdates};
+
+    #[test]
+    pub fn archive() {
+        let dir = std::env::temp_dir().join(format!("bevy_voxel_archive_{}", std::process::id()));
+        let save = dir.join("save");
+        let backend = FileBackend::new(&save);
+        backend.write_chunk((0, 0, 0), b"chunk").unwrap();
+        backend.write_manifest(b"manifest").unwrap();
+        fs::write(save.join("world.ron"), "()").unwrap();
+
+        let archive = ArchiveBackend::from_directory(&save).unwrap();
+        assert_eq!(archive.file("world.ron"), Some(b"()".to_vec()));
+        archive.write(dir.join("save.bvox")).unwrap();
+        assert!(ArchiveBackend::read(save.join("world.ron")).is_err());
+
+        let read = ArchiveBackend::read(dir.join("save.bvox")).unwrap();
+        read.unpack(dir.join("copy")).unwrap();
+        let copy = FileBackend::new(dir.join("copy"));
+        let chunk = copy.read_chunk((0, 0, 0));
+        let manifest = copy.read_manifest();
+        let world = fs::read(dir.join("copy").join("world.ron"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(chunk.unwrap(), Some(b"chunk".to_vec()));
+        assert_eq!(manifest.unwrap(), Some(b"manifest".to_vec()));
+        assert_eq!(world.unwrap(), b"()");
+    }
+
+    #[test]
+    pub fn export_archive() {
+        let mut updates = MapUpdates::default();
+        let mut map = map();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        let path = std::env::temp_dir().join(format!("bevy_voxel_{}.bvox", std::process::id()));
+        map.export_archive(&path).unwrap();
+        let loaded = Map::<i32>::import_archive(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.iter().count(), 27);
+        assert_eq!(loaded.voxel((1, 1, 1)).unwrap().into_owned(), 1);
+    }
+}
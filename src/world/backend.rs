@@ -0,0 +1,233 @@
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Where a map stores its chunks.
+///
+/// A backend only moves the bytes of compressed chunks around, so a map can be saved to
+/// anything from a directory to a database or a server.
+pub trait SaveBackend: Send + Sync {
+    /// Reads the chunk at `position`, or returns `None` if it was never written.
+    fn read_chunk(&self, position: (i32, i32, i32)) -> io::Result<Option<Vec<u8>>>;
+
+    /// Replaces the chunk at `position`.
+    fn write_chunk(&self, position: (i32, i32, i32), bytes: &[u8]) -> io::Result<()>;
+
+    /// Returns the positions of all stored chunks.
+    fn list(&self) -> io::Result<Vec<(i32, i32, i32)>>;
+
+    /// Removes the chunk at `position`, doing nothing if it doesn't exist.
+    fn delete(&self, position: (i32, i32, i32)) -> io::Result<()>;
+
+    /// Reads the `n`th previous version of the chunk at `position`, starting at 1. Loading
+    /// falls back to them when a chunk is corrupted. Backends without backups return `None`.
+    fn read_backup(&self, _position: (i32, i32, i32), _n: usize) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Reads the manifest of the save, see `SaveManifest`.
+    fn read_manifest(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Replaces the manifest of the save. Saving with a zstd dictionary fails on backends
+    /// that can't store one.
+    fn write_manifest(&self, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "the save backend can't store a manifest",
+        ))
+    }
+}
+
+/// Stores every chunk in its own file in a directory. There is no filesystem on the web, so
+/// it isn't available on wasm32.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileBackend {
+    directory: PathBuf,
+    backups: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileBackend {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            backups: 0,
+        }
+    }
+
+    /// Keeps `backups` previous versions of every chunk file.
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.directory.join("manifest.bin")
+    }
+
+    fn path(&self, (x, y, z): (i32, i32, i32)) -> PathBuf {
+        self.directory.join(format!("chunk.{}.{}.{}.gz", x, y, z))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SaveBackend for FileBackend {
+    fn read_chunk(&self, position: (i32, i32, i32)) -> io::Result<Option<Vec<u8>>> {
+        read_file(&self.path(position))
+    }
+
+    fn write_chunk(&self, position: (i32, i32, i32), bytes: &[u8]) -> io::Result<()> {
+        let path = self.path(position);
+        let temp = write_temp(&path, bytes)?;
+
+        if self.backups > 0 && path.exists() {
+            for n in (1..self.backups).rev() {
+                let backup = backup_path(&path, n);
+                if backup.exists() {
+                    fs::rename(backup, backup_path(&path, n + 1))?;
+                }
+            }
+            fs::rename(&path, backup_path(&path, 1))?;
+        }
+        fs::rename(temp, path)
+    }
+
+    fn list(&self) -> io::Result<Vec<(i32, i32, i32)>> {
+        let mut positions = Vec::new();
+        for entry in self.directory.read_dir()? {
+            if let Some(position) = chunk_position(&entry?.path()) {
+                positions.push(position);
+            }
+        }
+        // a chunk whose file is gone may still have backups
+        positions.sort_unstable();
+        positions.dedup();
+        Ok(positions)
+    }
+
+    fn delete(&self, position: (i32, i32, i32)) -> io::Result<()> {
+        let path = self.path(position);
+        let mut n = 1;
+        while backup_path(&path, n).exists() {
+            fs::remove_file(backup_path(&path, n))?;
+            n += 1;
+        }
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_backup(&self, position: (i32, i32, i32), n: usize) -> io::Result<Option<Vec<u8>>> {
+        read_file(&backup_path(&self.path(position), n))
+    }
+
+    fn read_manifest(&self) -> io::Result<Option<Vec<u8>>> {
+        read_file(&self.manifest_path())
+    }
+
+    fn write_manifest(&self, bytes: &[u8]) -> io::Result<()> {
+        let path = self.manifest_path();
+        let temp = write_temp(&path, bytes)?;
+        fs::rename(temp, path)
+    }
+}
+
+/// Writes `bytes` to a temporary file next to `path` and returns it, so that a crash never
+/// leaves a half written file behind.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn write_temp(path: &Path, bytes: &[u8]) -> io::Result<PathBuf> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut file = File::create(&temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(temp)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+}
+
+/// Returns the position of the chunk `path` belongs to, if it is a chunk file or one of its
+/// backups.
+#[cfg(not(target_arch = "wasm32"))]
+fn chunk_position(path: &Path) -> Option<(i32, i32, i32)> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.strip_prefix("chunk.")?.split('.');
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+    if parts.next()? != "gz" {
+        return None;
+    }
+    match parts.next() {
+        None => Some((x, y, z)),
+        Some(n) if n.parse::<usize>().is_ok() && parts.next().is_none() => Some((x, y, z)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{tests::map, IoProgress, Map, MapUpdates, SaveOptions};
+
+    #[test]
+    pub fn save_backups() {
+        let dir = std::env::temp_dir().join(format!("bevy_voxel_backups_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let options = SaveOptions {
+            backups: 2,
+            ..Default::default()
+        };
+        let mut updates = MapUpdates::default();
+        let progress = IoProgress::new();
+
+        let mut map = map();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        map.save_with_options(&dir, &options, &progress).unwrap();
+        map.set_voxel((1, 1, 1), Some(2), &mut updates);
+        map.save_with_options(&dir, &options, &progress).unwrap();
+        map.set_voxel((1, 1, 1), Some(3), &mut updates);
+        map.save_with_options(&dir, &options, &progress).unwrap();
+
+        let path = dir.join("chunk.0.0.0.gz");
+        assert!(backup_path(&path, 2).exists());
+        assert!(!backup_path(&path, 3).exists());
+
+        fs::write(&path, b"corrupted").unwrap();
+        let loaded = Map::<i32>::load(&dir).unwrap();
+        assert_eq!(loaded.iter().count(), 27);
+        assert_eq!(loaded.voxel((1, 1, 1)).unwrap().into_owned(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::{borrow::Cow, collections::HashSet};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{restart_stage, ChangeCause, ChunkUpdate, Map, MapUpdates, UpdateCause},
+};
+
+/// Sets voxels of a map inside `Map::batch`, which schedules the chunk updates once the
+/// batch is done.
+pub struct MapEditor<'a, T: Voxel> {
+    map: &'a mut Map<T>,
+    /// The chunks whose voxels were set, in the order they were first edited.
+    edited: Vec<(i32, i32, i32)>,
+    /// The chunks whose meshes touch a set voxel, in the same order.
+    touched: Vec<(i32, i32, i32)>,
+    seen: HashSet<(i32, i32, i32)>,
+    edits: usize,
+}
+
+impl<'a, T: Voxel> MapEditor<'a, T> {
+    /// Like `Map::set_voxel`, without scheduling any updates yet.
+    pub fn set_voxel(&mut self, coords: (i32, i32, i32), voxel: Option<T>) -> bool {
+        self.set_voxel_because(coords, voxel, ChangeCause::Edit)
+    }
+
+    /// Like `Map::set_voxel_because`, without scheduling any updates yet.
+    pub fn set_voxel_because(
+        &mut self,
+        coords: (i32, i32, i32),
+        voxel: Option<T>,
+        cause: ChangeCause,
+    ) -> bool {
+        let (position, neighbors) = match self.map.write_voxel(coords, voxel, cause) {
+            Some(written) => written,
+            None => return false,
+        };
+        self.edits += 1;
+        if self.seen.insert(position) {
+            self.edited.push(position);
+        }
+        // `seen` only holds edited chunks, so a chunk can be touched before and edited after
+        for coords in neighbors {
+            if !self.seen.contains(&coords) && !self.touched.contains(&coords) {
+                self.touched.push(coords);
+            }
+        }
+        true
+    }
+
+    /// Returns the voxel at world coordinates `coords`, with the edits of the batch so far.
+    pub fn voxel(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        self.map.voxel(coords)
+    }
+
+    /// The map being edited, e.g. to look at its chunks.
+    pub fn map(&self) -> &Map<T> {
+        self.map
+    }
+
+    /// The number of voxels set so far.
+    pub fn edits(&self) -> usize {
+        self.edits
+    }
+}
+
+impl<T: Voxel> Map<T> {
+    /// Runs `edit` with a `MapEditor` that sets voxels like `set_voxel`, then schedules the
+    /// updates of the chunks it edited and of their neighbours once, instead of once per
+    /// voxel. Meant for explosions, stamping structures and other edits of many voxels.
+    pub fn batch<F, R>(&mut self, updates: &mut MapUpdates, edit: F) -> R
+    where
+        F: FnOnce(&mut MapEditor<'_, T>) -> R,
+    {
+        let mut editor = MapEditor {
+            map: self,
+            edited: Vec::new(),
+            touched: Vec::new(),
+            seen: HashSet::new(),
+            edits: 0,
+        };
+        let result = edit(&mut editor);
+        let MapEditor {
+            edited,
+            touched,
+            seen,
+            ..
+        } = editor;
+
+        for coords in edited {
+            updates.request_because(coords, ChunkUpdate::UpdateLightMap, UpdateCause::Edit);
+        }
+        // edited chunks get a new mesh anyway
+        for coords in touched.into_iter().filter(|coords| !seen.contains(coords)) {
+            if let Some(chunk) = self.get_at_origin_mut(coords) {
+                chunk.set_visibility(None);
+                let stage =
+                    restart_stage(&updates.pipeline, chunk.state(), ChunkUpdate::UpdateMesh);
+                updates.request_because(coords, stage, UpdateCause::Dependency);
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,52 @@
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+use crate::collections::lod_tree::Voxel;
+#[cfg(feature = "bevy")]
+use crate::world::Map;
+
+/// What changed a voxel, see `BlockChanged`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeCause {
+    /// `Map::set_voxel`, e.g. placed or broken by a player or the editor.
+    Edit,
+    /// `Map::move_voxel`, which changes the voxel it moves from and the one it moves to.
+    Move,
+    /// Scheduled changes of `VoxelTicks` and random ticks.
+    Tick,
+    /// Snow or other `Overlay`s deposited or melted.
+    Weather,
+    /// A `DenseBuffer` written back with `Map::apply_dense_buffer`, e.g. by a fluid
+    /// simulation.
+    Simulation,
+    /// Changed by `GenerationHooks` while the chunk was generated, before it was inserted.
+    /// `old` is the voxel the terrain program generated.
+    Generated,
+}
+
+/// A voxel of a map that changed, with its value before and after the change.
+///
+/// Maps only record changes after `Map::set_track_changes`, and `block_changed_update` sends
+/// them as events, which have to be registered with `add_event::<BlockChanged<T>>()`.
+/// Chunks edited directly through `Map::get_mut` aren't noticed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockChanged<T: Voxel> {
+    /// In world coordinates.
+    pub coords: (i32, i32, i32),
+    pub old: Option<T>,
+    pub new: Option<T>,
+    pub cause: ChangeCause,
+}
+
+/// Sends the changes recorded by every map since the last frame as `BlockChanged` events.
+#[cfg(feature = "bevy")]
+pub fn block_changed_update<T: Voxel>(
+    mut events: ResMut<Events<BlockChanged<T>>>,
+    mut query: Query<&mut Map<T>>,
+) {
+    for mut map in &mut query.iter() {
+        for change in map.drain_changes() {
+            events.send(change);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use instant::Instant;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::world::{IoProgress, MapLayout, SaveBackend, SaveData, SavePalette};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// How chunks are compressed when a map is saved. Loading detects the compression of every
+/// chunk on its own, so a save can mix chunks written with different settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    Gzip,
+    /// zstd at `level`. With `dictionary`, the first save trains a dictionary on a sample of
+    /// the map's chunks and stores it in the save's manifest, later saves keep using it.
+    #[cfg(feature = "zstd")]
+    Zstd {
+        level: i32,
+        dictionary: bool,
+    },
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Gzip
+    }
+}
+
+/// Stored next to the chunks of a save, holds what is needed to decode them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveManifest {
+    /// The zstd dictionary chunks were compressed with.
+    pub dictionary: Option<Vec<u8>>,
+    /// The ids of the voxels of chunks saved with `Map::save_with_palette`.
+    pub palette: SavePalette,
+    /// The `Program::fingerprint` of the generator of the saved chunks, see
+    /// `record_generator`.
+    pub generator: Option<u64>,
+    /// The layout of the saved map, so that maps with chunks of several widths load again.
+    pub layout: Option<MapLayout>,
+}
+
+/// The manifest as it was written before it had a palette.
+#[derive(Deserialize)]
+struct LegacyManifest {
+    dictionary: Option<Vec<u8>>,
+}
+
+/// The manifest as it was written before it had a generator fingerprint.
+#[derive(Deserialize)]
+struct PaletteManifest {
+    dictionary: Option<Vec<u8>>,
+    palette: SavePalette,
+}
+
+/// The manifest as it was written before it had a layout.
+#[derive(Deserialize)]
+struct GeneratorManifest {
+    dictionary: Option<Vec<u8>>,
+    palette: SavePalette,
+    generator: Option<u64>,
+}
+
+/// How the generator a save was made with compares to the current one, see
+/// `SaveManifest::check_generator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeneratorDrift {
+    /// The save has no fingerprint, e.g. it was written before they were recorded.
+    Unknown,
+    Unchanged,
+    /// Chunks generated from now on may not line up with the saved ones, e.g. leaving seams
+    /// at the edge of the explored area.
+    Changed {
+        saved: u64,
+        current: u64,
+    },
+}
+
+impl SaveManifest {
+    /// Reads the manifest of `backend`, or returns an empty one if there is none.
+    pub fn load(backend: &dyn SaveBackend) -> bincode::Result<Self> {
+        let bytes = match backend.read_manifest()? {
+            Some(bytes) => bytes,
+            None => return Ok(Self::default()),
+        };
+        bincode::deserialize(&bytes).or_else(|e| {
+            if let Ok(manifest) = bincode::deserialize::<GeneratorManifest>(&bytes) {
+                return Ok(Self {
+                    dictionary: manifest.dictionary,
+                    palette: manifest.palette,
+                    generator: manifest.generator,
+                    ..Self::default()
+                });
+            }
+            if let Ok(manifest) = bincode::deserialize::<PaletteManifest>(&bytes) {
+                return Ok(Self {
+                    dictionary: manifest.dictionary,
+                    palette: manifest.palette,
+                    ..Self::default()
+                });
+            }
+            let legacy = bincode::deserialize::<LegacyManifest>(&bytes).map_err(|_| e)?;
+            Ok(Self {
+                dictionary: legacy.dictionary,
+                ..Self::default()
+            })
+        })
+    }
+
+    pub fn save(&self, backend: &dyn SaveBackend) -> bincode::Result<()> {
+        backend.write_manifest(&bincode::serialize(self)?)?;
+        Ok(())
+    }
+
+    /// Stores `fingerprint`, usually the `Program::fingerprint` of the terrain generator, in
+    /// the manifest of `backend`, so that later sessions can tell if they generate chunks
+    /// with a different program.
+    pub fn record_generator(backend: &dyn SaveBackend, fingerprint: u64) -> bincode::Result<()> {
+        let mut manifest = Self::load(backend)?;
+        if manifest.generator != Some(fingerprint) {
+            manifest.generator = Some(fingerprint);
+            manifest.save(backend)?;
+        }
+        Ok(())
+    }
+
+    /// Compares the generator recorded in the manifest of `backend` with `fingerprint`, and
+    /// logs a warning if it changed.
+    pub fn check_generator(
+        backend: &dyn SaveBackend,
+        fingerprint: u64,
+    ) -> bincode::Result<GeneratorDrift> {
+        let drift = match Self::load(backend)?.generator {
+            None => GeneratorDrift::Unknown,
+            Some(saved) if saved == fingerprint => GeneratorDrift::Unchanged,
+            Some(saved) => {
+                log::warn!(
+                    "the world was generated by another program ({:016x}, now {:016x}), new \
+                     chunks may not line up with the saved ones",
+                    saved,
+                    fingerprint,
+                );
+                GeneratorDrift::Changed {
+                    saved,
+                    current: fingerprint,
+                }
+            }
+        };
+        Ok(drift)
+    }
+}
+
+/// The position and compressed bytes of a chunk.
+pub(crate) type EncodedChunk = ((i32, i32, i32), Vec<u8>);
+
+/// Compresses and decompresses the chunks of one save.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChunkCodec {
+    compression: Compression,
+    #[cfg_attr(not(feature = "zstd"), allow(dead_code))]
+    dictionary: Option<Vec<u8>>,
+}
+
+impl ChunkCodec {
+    pub fn new(compression: Compression, manifest: SaveManifest) -> Self {
+        Self {
+            compression,
+            dictionary: manifest.dictionary,
+        }
+    }
+
+    pub fn encode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self.compression {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level, dictionary } => {
+                let dictionary = match &self.dictionary {
+                    Some(data) if dictionary => &data[..],
+                    _ => &[],
+                };
+                let mut encoder =
+                    zstd::stream::write::Encoder::with_dictionary(Vec::new(), level, dictionary)?;
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Serializes and compresses `saves`, on every core with the `parallel` feature, and
+    /// returns the positions and bytes of the chunks, ready to be written.
+    pub fn encode_batch<T: Serialize + Send>(
+        &self,
+        saves: Vec<SaveData<T>>,
+        progress: &IoProgress,
+    ) -> bincode::Result<Vec<EncodedChunk>> {
+        let encode = |save: SaveData<T>| -> bincode::Result<EncodedChunk> {
+            let start = Instant::now();
+            let raw = save.to_bytes()?;
+            let bytes = self.encode(&raw)?;
+            progress.record_encode(raw.len(), start);
+            Ok((save.position, bytes))
+        };
+        #[cfg(feature = "parallel")]
+        return saves.into_par_iter().map(encode).collect();
+        #[cfg(not(feature = "parallel"))]
+        saves.into_iter().map(encode).collect()
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if bytes.starts_with(&GZIP_MAGIC) {
+            // reading everything makes the decoder check the crc of the file
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut data)?;
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            self.decode_zstd(bytes, &mut data)?;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown chunk compression",
+            ));
+        }
+        Ok(data)
+    }
+
+    #[cfg(feature = "zstd")]
+    fn decode_zstd(&self, bytes: &[u8], data: &mut Vec<u8>) -> io::Result<()> {
+        let dictionary = self.dictionary.as_deref().unwrap_or_default();
+        zstd::stream::read::Decoder::with_dictionary(bytes, dictionary)?.read_to_end(data)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn decode_zstd(&self, _bytes: &[u8], _data: &mut Vec<u8>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the chunk is compressed with zstd, enable the zstd feature to load it",
+        ))
+    }
+}
+
+/// The largest dictionary `train_dictionary` builds.
+#[cfg(feature = "zstd")]
+const DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// Trains a zstd dictionary on serialized chunks. Returns `None` if there are too few of
+/// them to train on.
+#[cfg(feature = "zstd")]
+pub(crate) fn train_dictionary(samples: &[Vec<u8>]) -> Option<Vec<u8>> {
+    match zstd::dict::from_samples(samples, DICTIONARY_SIZE) {
+        Ok(dictionary) => Some(dictionary),
+        Err(e) => {
+            log::warn!(
+                "couldn't train a dictionary on {} chunks: {}",
+                samples.len(),
+                e
+            );
+            None
+        }
+    }
+}
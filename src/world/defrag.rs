@@ -0,0 +1,75 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+#[cfg(feature = "bevy")]
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Map, MapUpdates},
+};
+
+/// How much of the voxel trees `defrag_update` rebuilds per frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DefragBudget {
+    /// The number of tree nodes visited per frame, over all maps. A chunk of width 32 has
+    /// 32768 of them, and about as many again to merge.
+    pub nodes_per_frame: usize,
+}
+
+impl Default for DefragBudget {
+    fn default() -> Self {
+        Self {
+            nodes_per_frame: 4096,
+        }
+    }
+}
+
+/// When the lighting systems merge the voxels of a chunk after shading them, see
+/// `Chunk::merge_with`. Shading flattens the voxels of chunks whose shades changed, and
+/// merging them again takes about as long as shading.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Right away, which keeps chunks small at the cost of every light update.
+    Always,
+    /// Never, e.g. for chunks that are edited all the time. Flattened chunks take more
+    /// memory and mesh slower.
+    Never,
+    /// Right away once the chunk had at least this many voxel edits since it was last
+    /// merged, otherwise like `Idle`.
+    AfterEdits(usize),
+    /// A few voxels at a time by `defrag_update`, while no chunk updates are pending.
+    Idle,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self::Always
+    }
+}
+
+/// Defragments the chunks whose voxels were edited, see `Chunk::defragment`, a few nodes per
+/// frame and only while their map has no pending chunk updates.
+#[cfg(feature = "bevy")]
+pub fn defrag_update<T: Voxel>(
+    budget: Res<DefragBudget>,
+    mut query: Query<(&mut Map<T>, &MapUpdates)>,
+) {
+    let mut budget = budget.nodes_per_frame;
+    for (mut map, updates) in &mut query.iter() {
+        if budget == 0 {
+            return;
+        }
+        if !updates.updates.is_empty() || !map.iter().any(|chunk| chunk.is_fragmented()) {
+            continue;
+        }
+        for chunk in map.iter_mut().filter(|chunk| chunk.is_fragmented()) {
+            budget -= chunk.defragment(budget).min(budget);
+            if budget == 0 {
+                return;
+            }
+        }
+    }
+}
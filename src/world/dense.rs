@@ -0,0 +1,169 @@
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{ChangeCause, Chunk, Map, MapUpdates},
+};
+
+/// The voxels of a chunk as one palette index per voxel, e.g. to upload them to the GPU.
+///
+/// `indices` holds `width³` indices in x-major order, the voxel at `(x, y, z)` is at
+/// `(x * width + y) * width + z`. Index `0` is an empty voxel and index `i` is
+/// `palette[i - 1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenseBuffer<T> {
+    pub width: usize,
+    pub palette: Vec<T>,
+    pub indices: Vec<u32>,
+}
+
+impl<T: Voxel> DenseBuffer<T> {
+    /// An empty buffer for a chunk `width` voxels wide.
+    pub fn new(width: usize) -> Self {
+        Self {
+            width,
+            palette: Vec::new(),
+            indices: vec![0; width * width * width],
+        }
+    }
+
+    pub fn index(&self, (x, y, z): (i32, i32, i32)) -> Option<usize> {
+        let width = self.width as i32;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= width || z >= width {
+            return None;
+        }
+        Some(((x * width + y) * width + z) as usize)
+    }
+
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        let index = self.indices[self.index(coords)?] as usize;
+        index
+            .checked_sub(1)
+            .and_then(|index| self.palette.get(index))
+    }
+
+    /// Sets the voxel at `coords`, adding it to the palette if it isn't in it yet.
+    pub fn set(&mut self, coords: (i32, i32, i32), voxel: Option<T>) {
+        let index = match self.index(coords) {
+            Some(index) => index,
+            None => return,
+        };
+        self.indices[index] = match voxel {
+            Some(voxel) => self.palette_index(voxel),
+            None => 0,
+        };
+    }
+
+    /// The indices as little endian bytes, ready to be copied into a GPU buffer.
+    pub fn index_bytes(&self) -> Vec<u8> {
+        self.indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn palette_index(&mut self, voxel: T) -> u32 {
+        let index = match self.palette.iter().position(|other| *other == voxel) {
+            Some(index) => index,
+            None => {
+                self.palette.push(voxel);
+                self.palette.len() - 1
+            }
+        };
+        index as u32 + 1
+    }
+}
+
+impl<T: Voxel> Chunk<T> {
+    /// Packs the voxels of the chunk at full detail into a `DenseBuffer`.
+    pub fn to_dense_buffer(&self) -> DenseBuffer<T> {
+        let _span = span!("Chunk::to_dense_buffer");
+        let width = self.width();
+        let mut buffer = DenseBuffer::new(width);
+        let mut last: Option<(&T, u32)> = None;
+        for (index, coords) in dense_coords(width).enumerate() {
+            let voxel = match self.voxel(coords) {
+                Some(voxel) => voxel,
+                None => continue,
+            };
+            // neighbouring voxels are usually the same, so skip the palette search for runs
+            let palette_index = match last {
+                Some((previous, palette_index)) if previous == voxel => palette_index,
+                _ => buffer.palette_index(voxel.clone()),
+            };
+            last = Some((voxel, palette_index));
+            buffer.indices[index] = palette_index;
+        }
+        buffer
+    }
+
+    /// Creates a chunk at `position` from the voxels of `buffer`.
+    pub fn from_dense_buffer(position: (i32, i32, i32), buffer: &DenseBuffer<T>) -> Self {
+        let mut chunk = Self::new(buffer.width.trailing_zeros(), position);
+        chunk.apply_dense_buffer(buffer);
+        chunk
+    }
+
+    /// Copies the voxels of `buffer` that differ from the chunk into it, returning their
+    /// chunk-local coordinates.
+    pub fn apply_dense_buffer(&mut self, buffer: &DenseBuffer<T>) -> Vec<(i32, i32, i32)> {
+        dense_changes(self, buffer)
+            .into_iter()
+            .map(|(coords, voxel)| {
+                match voxel {
+                    Some(voxel) => self.insert(coords, voxel),
+                    None => self.remove(coords),
+                }
+                coords
+            })
+            .collect()
+    }
+}
+
+impl<T: Voxel> Map<T> {
+    /// Writes the voxels of `buffer` that differ from the chunk at `position` back into it
+    /// with `set_voxel`, so the chunk and its neighbours get the usual updates. Returns the
+    /// number of changed voxels.
+    pub fn apply_dense_buffer(
+        &mut self,
+        position: (i32, i32, i32),
+        buffer: &DenseBuffer<T>,
+        updates: &mut MapUpdates,
+    ) -> usize {
+        let changes = match self.get_at_origin(position) {
+            Some(chunk) => dense_changes(chunk, buffer),
+            None => return 0,
+        };
+        let (cx, cy, cz) = position;
+        for &((x, y, z), ref voxel) in &changes {
+            let coords = (cx + x, cy + y, cz + z);
+            self.set_voxel_because(coords, voxel.clone(), updates, ChangeCause::Simulation);
+        }
+        changes.len()
+    }
+}
+
+/// Iterates over the coordinates of a chunk in the order of `DenseBuffer::indices`.
+fn dense_coords(width: usize) -> impl Iterator<Item = (i32, i32, i32)> {
+    let width = width as i32;
+    (0..width).flat_map(move |x| (0..width).flat_map(move |y| (0..width).map(move |z| (x, y, z))))
+}
+
+fn dense_changes<T: Voxel>(
+    chunk: &Chunk<T>,
+    buffer: &DenseBuffer<T>,
+) -> Vec<((i32, i32, i32), Option<T>)> {
+    assert_eq!(
+        chunk.width(),
+        buffer.width,
+        "the buffer doesn't fit the chunk"
+    );
+    dense_coords(buffer.width)
+        .filter_map(|coords| {
+            let voxel = buffer.get(coords);
+            if chunk.voxel(coords) == voxel {
+                None
+            } else {
+                Some((coords, voxel.cloned()))
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,274 @@
+use std::borrow::Cow;
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+use std::path::Path;
+
+use glam::Vec3;
+
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+#[cfg(feature = "savedata")]
+use crate::config::VoxelConfig;
+
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+use crate::world::IoProgress;
+#[cfg(feature = "savedata")]
+use crate::world::SaveOptions;
+use crate::{
+    collections::lod_tree::Voxel,
+    error::{Error, Result},
+    terrain::{self, GenerationCache, GenerationHooks, HeightMap, Program, SpawnRequest},
+    world::{ChunkState, ChunkUpdate, Map, MapLayout, MapUpdates, RaycastHit, UpdateCause},
+};
+
+/// A map together with its pending updates and the generator that fills it, for games that
+/// would rather talk to one object than keep `Map`, `MapUpdates`, `HeightMap` and `Program`
+/// in step themselves.
+///
+/// As a resource, `voxel_world_update` generates its chunks and `voxel_world_light_update`
+/// lights them. Meshing is up to the game like for any map: its mesh system drains
+/// `ChunkUpdate::UpdateMesh` from `parts_mut` instead of querying map entities. Systems that
+/// only query map entities, e.g. `lod_update` or `chunk_unload_update`, don't see the
+/// resource, so games that need them put the map on an entity with `into_parts`.
+pub struct VoxelWorld<T: Voxel> {
+    map: Map<T>,
+    updates: MapUpdates,
+    height_map: HeightMap,
+    program: Program<T>,
+    hooks: GenerationHooks<T>,
+    cache: GenerationCache<T>,
+    /// How many chunks `generate` generates per call at most.
+    pub generation_budget: usize,
+    #[cfg(feature = "savedata")]
+    pub save_options: SaveOptions,
+    #[cfg(feature = "savedata")]
+    config: VoxelConfig,
+}
+
+impl<T: Voxel> VoxelWorld<T> {
+    /// Creates an empty world whose chunks are generated by `program`.
+    pub fn new(program: Program<T>) -> Self {
+        let layout = MapLayout::uniform(program.chunk_width());
+        Self::with_layout_map(program, Map::with_layout(layout))
+    }
+
+    /// Creates a world from `map`, e.g. one that was loaded, generating the chunks it's
+    /// missing with `program`. Fails if the chunks of `map` aren't as wide as the ones of
+    /// `program`.
+    pub fn with_map(program: Program<T>, map: Map<T>) -> Result<Self> {
+        let width = program.chunk_width();
+        let layout = map.layout().unwrap_or_else(|| MapLayout::uniform(width));
+        if layout.chunk_width != width {
+            return Err(Error::LayoutWidth {
+                layout: layout.chunk_width,
+                program: width,
+            });
+        }
+        let map = if map.layout().is_none() {
+            Map::with_layout(layout)
+        } else {
+            map
+        };
+        Ok(Self::with_layout_map(program, map))
+    }
+
+    fn with_layout_map(program: Program<T>, map: Map<T>) -> Self {
+        Self {
+            map,
+            updates: MapUpdates::default(),
+            height_map: HeightMap::new(),
+            program,
+            hooks: GenerationHooks::default(),
+            cache: GenerationCache::default(),
+            generation_budget: 32,
+            #[cfg(feature = "savedata")]
+            save_options: SaveOptions::default(),
+            #[cfg(feature = "savedata")]
+            config: VoxelConfig::default(),
+        }
+    }
+
+    pub fn with_hooks(mut self, hooks: GenerationHooks<T>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn map(&self) -> &Map<T> {
+        &self.map
+    }
+
+    pub fn map_mut(&mut self) -> &mut Map<T> {
+        &mut self.map
+    }
+
+    pub fn updates(&self) -> &MapUpdates {
+        &self.updates
+    }
+
+    pub fn updates_mut(&mut self) -> &mut MapUpdates {
+        &mut self.updates
+    }
+
+    /// Borrows the map and its updates at once, e.g. for a system that meshes the chunks.
+    pub fn parts_mut(&mut self) -> (&mut Map<T>, &mut MapUpdates) {
+        (&mut self.map, &mut self.updates)
+    }
+
+    pub fn height_map(&self) -> &HeightMap {
+        &self.height_map
+    }
+
+    pub fn program(&self) -> &Program<T> {
+        &self.program
+    }
+
+    /// Applies the update limits and save policy of `config`. Lighting and LOD settings are
+    /// resources, see `ConfigPlugin`.
+    #[cfg(feature = "savedata")]
+    pub fn with_config(mut self, config: VoxelConfig) -> Self {
+        self.set_config(config);
+        self
+    }
+
+    #[cfg(feature = "savedata")]
+    pub fn config(&self) -> &VoxelConfig {
+        &self.config
+    }
+
+    #[cfg(feature = "savedata")]
+    pub fn set_config(&mut self, config: VoxelConfig) {
+        self.updates.limits = config.limits;
+        self.save_options = config.save.options();
+        self.config = config;
+    }
+
+    /// Replaces the generator. Chunks that were generated already are kept, call
+    /// `regenerate` to throw them away.
+    pub fn set_program(&mut self, program: Program<T>) {
+        self.program = program;
+        self.height_map = HeightMap::new();
+        self.cache.clear();
+    }
+
+    /// Takes the world apart, e.g. to put the map and its updates on an entity.
+    pub fn into_parts(self) -> (Map<T>, MapUpdates, HeightMap) {
+        (self.map, self.updates, self.height_map)
+    }
+
+    /// Returns the block at world coordinates `coords`, or `None` if it's air or its chunk
+    /// isn't loaded.
+    pub fn get_block(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        self.map.voxel(coords)
+    }
+
+    /// Sets or clears the block at world coordinates `coords` and schedules the chunks that
+    /// have to be lit and meshed again. Returns `false` if its chunk isn't loaded.
+    pub fn set_block(&mut self, coords: (i32, i32, i32), block: Option<T>) -> bool {
+        self.map.set_voxel(coords, block, &mut self.updates)
+    }
+
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        self.map.raycast(origin, direction, max_distance)
+    }
+
+    /// Returns how far the chunk containing world coordinates `coords` made it through the
+    /// pipeline, or `None` if it isn't loaded.
+    pub fn chunk_state(&self, coords: (i32, i32, i32)) -> Option<ChunkState> {
+        self.map.chunk_containing(coords).map(|chunk| chunk.state())
+    }
+
+    /// Requests the chunks between world coordinates `min` and `max`, both inclusive, that
+    /// aren't loaded yet. Returns how many were requested.
+    pub fn request_area(&mut self, min: (i32, i32, i32), max: (i32, i32, i32)) -> usize {
+        let width = self.program.chunk_width() as i32;
+        let layout = MapLayout::uniform(width as usize);
+        let (x0, y0, z0) = layout.chunk_origin(min);
+        let mut count = 0;
+        for x in (x0..=max.0).step_by(width as usize) {
+            for y in (y0..=max.1).step_by(width as usize) {
+                for z in (z0..=max.2).step_by(width as usize) {
+                    if self.map.chunk_containing((x, y, z)).is_none() {
+                        self.updates.request_because(
+                            (x, y, z),
+                            ChunkUpdate::GenerateChunk,
+                            UpdateCause::Streaming,
+                        );
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Generates up to `generation_budget` of the requested chunks. Returns how many were
+    /// generated, the entities the hooks ask for are added to `spawns`.
+    pub fn generate(&mut self, spawns: &mut Vec<SpawnRequest>) -> usize {
+        terrain::generate_chunks(
+            &self.program,
+            &self.hooks,
+            &mut self.height_map,
+            &mut self.cache,
+            &mut self.map,
+            &mut self.updates,
+            self.generation_budget,
+            spawns,
+        )
+    }
+
+    /// Requests every chunk from the generator again, throwing away their edits, e.g. after
+    /// `set_program`.
+    pub fn regenerate(&mut self) {
+        let positions = self
+            .map
+            .iter()
+            .map(|chunk| chunk.position())
+            .collect::<Vec<_>>();
+        for position in positions {
+            self.updates.request_because(
+                position,
+                ChunkUpdate::GenerateChunk,
+                UpdateCause::Invalidation,
+            );
+        }
+    }
+}
+
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+impl<T: Voxel + Serialize + DeserializeOwned> VoxelWorld<T> {
+    /// Loads the map saved in `save_directory`, generating the chunks it's missing with
+    /// `program`. The loaded chunks are requested to be lit again. Fails like `with_map` if
+    /// the saved chunks aren't as wide as the ones of `program`.
+    pub fn load<P: AsRef<Path>>(program: Program<T>, save_directory: P) -> Result<Self> {
+        let map = Map::load(save_directory)?;
+        let mut world = Self::with_map(program, map)?;
+        world
+            .map
+            .invalidate_all(ChunkUpdate::UpdateLightMap, &mut world.updates);
+        Ok(world)
+    }
+
+    /// Saves the map to `save_directory` with `save_options`.
+    pub fn save<P: AsRef<Path>>(&self, save_directory: P) -> bincode::Result<()> {
+        self.map
+            .save_with_options(save_directory, &self.save_options, &IoProgress::new())
+    }
+}
+
+/// Generates the requested chunks of the `VoxelWorld<T>` resource and sends the entities its
+/// hooks ask for as `SpawnRequest` events, which have to be registered with
+/// `add_event::<SpawnRequest>()`.
+#[cfg(feature = "bevy")]
+pub fn voxel_world_update<T: Voxel>(
+    mut world: ResMut<VoxelWorld<T>>,
+    mut events: ResMut<Events<SpawnRequest>>,
+) {
+    let mut spawns = Vec::new();
+    world.generate(&mut spawns);
+    for spawn in spawns {
+        events.send(spawn);
+    }
+}
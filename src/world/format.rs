@@ -0,0 +1,385 @@
+use std::{cell::Cell, collections::HashMap};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    collections::RleTree,
+    world::{BlockData, BorderLight, ChunkMeta, Flow, SaveContent, SaveData, Structure, UserData},
+};
+
+/// Starts every chunk saved with a format version, followed by the version. Chunks saved
+/// before start with the x of their position instead, which is never this large.
+const CHUNK_MAGIC: [u8; 4] = *b"BVCK";
+
+/// The version of the layout of `SaveData`, to be bumped whenever it or one of the types in
+/// it changes. Chunks saved without a version are still read in the layouts they had.
+pub const CHUNK_FORMAT: u32 = 1;
+
+/// The layouts chunks were saved in before they had a format version, oldest first, named
+/// after what was added to `SaveData` or the types in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LegacyLayout {
+    /// Only the position and the voxels.
+    Initial,
+    Meta,
+    /// `SaveContent` instead of the voxels.
+    Content,
+    BlockData,
+    /// `Block::merge_group`.
+    MergeGroup,
+    /// `Block::emission`.
+    Emission,
+    BorderLight,
+    /// `ChunkMeta::tints` and `Block::tinted`.
+    Tints,
+    Edited,
+    /// `Block::covered_color`.
+    CoveredColor,
+    /// `ChunkMeta::flows`, without `Flow::bed`.
+    Flows,
+    UserData,
+    /// `Flow::bed`, the layout of `CHUNK_FORMAT` 1.
+    FlowBed,
+}
+
+impl LegacyLayout {
+    /// The order unversioned chunks are tried in, newest first as most chunks are recent.
+    const NEWEST_FIRST: [LegacyLayout; 13] = [
+        LegacyLayout::FlowBed,
+        LegacyLayout::UserData,
+        LegacyLayout::Flows,
+        LegacyLayout::CoveredColor,
+        LegacyLayout::Edited,
+        LegacyLayout::Tints,
+        LegacyLayout::BorderLight,
+        LegacyLayout::Emission,
+        LegacyLayout::MergeGroup,
+        LegacyLayout::BlockData,
+        LegacyLayout::Content,
+        LegacyLayout::Meta,
+        LegacyLayout::Initial,
+    ];
+
+    fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> bincode::Result<SaveData<T>> {
+        match self {
+            LegacyLayout::Initial => deserialize_exact::<InitialSave<T>>(bytes).map(Into::into),
+            LegacyLayout::Meta => deserialize_exact::<MetaSave<T>>(bytes).map(Into::into),
+            LegacyLayout::Content => deserialize_exact::<ContentSave<T>>(bytes).map(Into::into),
+            LegacyLayout::BlockData | LegacyLayout::MergeGroup | LegacyLayout::Emission => {
+                deserialize_exact::<BlockDataSave<T>>(bytes).map(Into::into)
+            }
+            LegacyLayout::BorderLight => {
+                deserialize_exact::<BorderLightSave<T, InitialMeta>>(bytes).map(Into::into)
+            }
+            LegacyLayout::Tints => {
+                deserialize_exact::<BorderLightSave<T, TintsMeta>>(bytes).map(Into::into)
+            }
+            LegacyLayout::Edited | LegacyLayout::CoveredColor => {
+                deserialize_exact::<EditedSave<T, TintsMeta>>(bytes).map(Into::into)
+            }
+            LegacyLayout::Flows => {
+                deserialize_exact::<EditedSave<T, FlowsMeta>>(bytes).map(Into::into)
+            }
+            LegacyLayout::UserData => {
+                deserialize_exact::<UserDataSave<T, FlowsMeta>>(bytes).map(Into::into)
+            }
+            LegacyLayout::FlowBed => deserialize_exact(bytes),
+        }
+    }
+}
+
+/// Deserializes all of `bytes`. Unlike `bincode::deserialize` trailing bytes are an error, so
+/// a chunk isn't read in a layout that only fits its start.
+fn deserialize_exact<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .deserialize(bytes)
+}
+
+thread_local! {
+    static DECODING: Cell<Option<LegacyLayout>> = Cell::new(None);
+}
+
+/// The layout of the unversioned chunk being read on this thread, for voxels whose own
+/// layout changed along with it.
+pub fn legacy_layout() -> Option<LegacyLayout> {
+    DECODING.with(Cell::get)
+}
+
+impl<T: Serialize> SaveData<T> {
+    /// Serializes the chunk, headed by the `CHUNK_FORMAT` it is in.
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        let mut bytes = CHUNK_MAGIC.to_vec();
+        bytes.extend_from_slice(&CHUNK_FORMAT.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+}
+
+impl<T: DeserializeOwned> SaveData<T> {
+    /// Deserializes a chunk serialized with `to_bytes`, or saved before chunks had a format
+    /// version.
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        if bytes.len() < 8 || bytes[..4] != CHUNK_MAGIC {
+            return Self::from_legacy_bytes(bytes);
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        match u32::from_le_bytes(version) {
+            CHUNK_FORMAT => bincode::deserialize(&bytes[8..]),
+            version => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "chunk format {} is newer than {}",
+                version, CHUNK_FORMAT
+            )))),
+        }
+    }
+
+    /// Tries the `LegacyLayout`s, returning the error of the newest one if none fits.
+    fn from_legacy_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        let mut error = None;
+        for layout in LegacyLayout::NEWEST_FIRST.iter() {
+            DECODING.with(|decoding| decoding.set(Some(*layout)));
+            let save = layout.decode(bytes);
+            DECODING.with(|decoding| decoding.set(None));
+            match save {
+                Ok(save) => return Ok(save),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap())
+    }
+}
+
+#[derive(Deserialize)]
+struct InitialSave<T> {
+    position: (i32, i32, i32),
+    data: RleTree<T>,
+}
+
+#[derive(Deserialize)]
+struct MetaSave<T> {
+    position: (i32, i32, i32),
+    data: RleTree<T>,
+    meta: Option<InitialMeta>,
+}
+
+#[derive(Deserialize)]
+struct ContentSave<T> {
+    position: (i32, i32, i32),
+    data: SaveContent<T>,
+    meta: Option<InitialMeta>,
+}
+
+#[derive(Deserialize)]
+struct BlockDataSave<T> {
+    position: (i32, i32, i32),
+    data: SaveContent<T>,
+    meta: Option<InitialMeta>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+}
+
+#[derive(Deserialize)]
+struct BorderLightSave<T, M> {
+    position: (i32, i32, i32),
+    data: SaveContent<T>,
+    meta: Option<M>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+    border_light: Option<BorderLight>,
+}
+
+#[derive(Deserialize)]
+struct EditedSave<T, M> {
+    position: (i32, i32, i32),
+    data: SaveContent<T>,
+    meta: Option<M>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+    border_light: Option<BorderLight>,
+    edited: bool,
+}
+
+#[derive(Deserialize)]
+struct UserDataSave<T, M> {
+    position: (i32, i32, i32),
+    data: SaveContent<T>,
+    meta: Option<M>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+    border_light: Option<BorderLight>,
+    edited: bool,
+    user_data: Option<UserData>,
+}
+
+#[derive(Deserialize)]
+struct InitialMeta {
+    generator: Option<String>,
+    version: u32,
+    biomes: Vec<usize>,
+    structures: Vec<Structure>,
+}
+
+#[derive(Deserialize)]
+struct TintsMeta {
+    generator: Option<String>,
+    version: u32,
+    biomes: Vec<usize>,
+    structures: Vec<Structure>,
+    tints: Vec<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+struct FlowsMeta {
+    generator: Option<String>,
+    version: u32,
+    biomes: Vec<usize>,
+    structures: Vec<Structure>,
+    tints: Vec<[f32; 3]>,
+    flows: Vec<Option<FlowsFlow>>,
+}
+
+#[derive(Deserialize)]
+struct FlowsFlow {
+    direction: [f32; 2],
+    surface: i32,
+}
+
+impl From<InitialMeta> for ChunkMeta {
+    fn from(meta: InitialMeta) -> Self {
+        Self {
+            generator: meta.generator,
+            version: meta.version,
+            biomes: meta.biomes,
+            structures: meta.structures,
+            tints: Vec::new(),
+            flows: Vec::new(),
+        }
+    }
+}
+
+impl From<TintsMeta> for ChunkMeta {
+    fn from(meta: TintsMeta) -> Self {
+        Self {
+            generator: meta.generator,
+            version: meta.version,
+            biomes: meta.biomes,
+            structures: meta.structures,
+            tints: meta.tints,
+            flows: Vec::new(),
+        }
+    }
+}
+
+impl From<FlowsMeta> for ChunkMeta {
+    fn from(meta: FlowsMeta) -> Self {
+        let flows = meta
+            .flows
+            .into_iter()
+            .map(|flow| {
+                flow.map(|flow| Flow {
+                    direction: flow.direction,
+                    surface: flow.surface,
+                    // without a bed the water reached all the way down
+                    bed: i32::MIN,
+                })
+            })
+            .collect();
+        Self {
+            generator: meta.generator,
+            version: meta.version,
+            biomes: meta.biomes,
+            structures: meta.structures,
+            tints: meta.tints,
+            flows,
+        }
+    }
+}
+
+/// Chunks saved before `SaveData::edited` are kept as they are, like edited ones.
+fn legacy_save<T>(
+    position: (i32, i32, i32),
+    data: SaveContent<T>,
+    meta: Option<ChunkMeta>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+    border_light: Option<BorderLight>,
+) -> SaveData<T> {
+    SaveData {
+        position,
+        data,
+        meta,
+        block_data,
+        border_light,
+        edited: true,
+        user_data: None,
+    }
+}
+
+impl<T> From<InitialSave<T>> for SaveData<T> {
+    fn from(save: InitialSave<T>) -> Self {
+        let data = SaveContent::Full(save.data);
+        legacy_save(save.position, data, None, HashMap::new(), None)
+    }
+}
+
+impl<T> From<MetaSave<T>> for SaveData<T> {
+    fn from(save: MetaSave<T>) -> Self {
+        let data = SaveContent::Full(save.data);
+        let meta = save.meta.map(Into::into);
+        legacy_save(save.position, data, meta, HashMap::new(), None)
+    }
+}
+
+impl<T> From<ContentSave<T>> for SaveData<T> {
+    fn from(save: ContentSave<T>) -> Self {
+        let meta = save.meta.map(Into::into);
+        legacy_save(save.position, save.data, meta, HashMap::new(), None)
+    }
+}
+
+impl<T> From<BlockDataSave<T>> for SaveData<T> {
+    fn from(save: BlockDataSave<T>) -> Self {
+        let meta = save.meta.map(Into::into);
+        legacy_save(save.position, save.data, meta, save.block_data, None)
+    }
+}
+
+impl<T, M: Into<ChunkMeta>> From<BorderLightSave<T, M>> for SaveData<T> {
+    fn from(save: BorderLightSave<T, M>) -> Self {
+        let meta = save.meta.map(Into::into);
+        legacy_save(
+            save.position,
+            save.data,
+            meta,
+            save.block_data,
+            save.border_light,
+        )
+    }
+}
+
+impl<T, M: Into<ChunkMeta>> From<EditedSave<T, M>> for SaveData<T> {
+    fn from(save: EditedSave<T, M>) -> Self {
+        Self {
+            position: save.position,
+            data: save.data,
+            meta: save.meta.map(Into::into),
+            block_data: save.block_data,
+            border_light: save.border_light,
+            edited: save.edited,
+            user_data: None,
+        }
+    }
+}
+
+impl<T, M: Into<ChunkMeta>> From<UserDataSave<T, M>> for SaveData<T> {
+    fn from(save: UserDataSave<T, M>) -> Self {
+        Self {
+            position: save.position,
+            data: save.data,
+            meta: save.meta.map(Into::into),
+            block_data: save.block_data,
+            border_light: save.border_light,
+            edited: save.edited,
+            user_data: save.user_data,
+        }
+    }
+}
@@ -0,0 +1,401 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    path::Path,
+    thread::{self, JoinHandle},
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use instant::Instant;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::world::FileBackend;
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Map, SaveBackend},
+};
+
+#[derive(Debug, Default)]
+struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    raw_bytes: AtomicUsize,
+    bytes: AtomicUsize,
+    encode_micros: AtomicU64,
+    write_micros: AtomicU64,
+}
+
+/// Shared progress of a map save or load, which can also be used to cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct IoProgress(Arc<Progress>);
+
+impl IoProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of chunks saved or loaded so far.
+    pub fn done(&self) -> usize {
+        self.0.done.load(Ordering::Relaxed)
+    }
+
+    /// The total number of chunks, or zero if it isn't known yet.
+    pub fn total(&self) -> usize {
+        self.0.total.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.load(Ordering::Acquire)
+    }
+
+    /// The size of the chunks compressed so far before compression.
+    pub fn raw_bytes(&self) -> usize {
+        self.0.raw_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The size of the chunks written so far.
+    pub fn bytes(&self) -> usize {
+        self.0.bytes.load(Ordering::Relaxed)
+    }
+
+    /// The time spent serializing and compressing chunks, summed over all threads.
+    pub fn encode_time(&self) -> Duration {
+        Duration::from_micros(self.0.encode_micros.load(Ordering::Relaxed))
+    }
+
+    /// The time spent writing chunks to the backend.
+    pub fn write_time(&self) -> Duration {
+        Duration::from_micros(self.0.write_micros.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_total(&self, total: usize) {
+        self.0.total.store(total, Ordering::Relaxed);
+    }
+
+    pub(crate) fn advance(&self) {
+        self.0.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_encode(&self, raw_bytes: usize, start: Instant) {
+        let micros = start.elapsed().as_micros() as u64;
+        self.0.raw_bytes.fetch_add(raw_bytes, Ordering::Relaxed);
+        self.0.encode_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_write(&self, bytes: usize, start: Instant) {
+        let micros = start.elapsed().as_micros() as u64;
+        self.0.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.0.write_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn finish(&self) {
+        self.0.finished.store(true, Ordering::Release);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapIoKind {
+    Save,
+    Load,
+}
+
+/// Sent by `map_task_update` for every running `MapTask`. The event has to be registered
+/// with `add_event::<MapIoEvent>()`.
+#[cfg(feature = "bevy")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapIoEvent {
+    Progress {
+        entity: Entity,
+        kind: MapIoKind,
+        done: usize,
+        total: usize,
+    },
+    Finished {
+        entity: Entity,
+        kind: MapIoKind,
+    },
+    Cancelled {
+        entity: Entity,
+        kind: MapIoKind,
+    },
+    Failed {
+        entity: Entity,
+        kind: MapIoKind,
+        error: String,
+    },
+}
+
+/// Megabytes written per second by running saves.
+#[cfg(feature = "bevy")]
+pub const SAVE_THROUGHPUT_DIAGNOSTIC: DiagnosticId = DiagnosticId::from_u128(1297340582716340);
+/// Written bytes per byte of serialized chunks, of running saves.
+#[cfg(feature = "bevy")]
+pub const SAVE_COMPRESSION_DIAGNOSTIC: DiagnosticId = DiagnosticId::from_u128(1297340582716341);
+
+#[cfg(not(target_arch = "wasm32"))]
+type Worker<T> = JoinHandle<bincode::Result<Option<Map<T>>>>;
+// there are no threads on the web, so tasks run to completion when they're created
+#[cfg(target_arch = "wasm32")]
+type Worker<T> = bincode::Result<Option<Map<T>>>;
+
+/// A map save or load running on a background thread.
+///
+/// When a load finishes, `map_task_update` adds the loaded map to the task's entity.
+pub struct MapTask<T: Voxel> {
+    kind: MapIoKind,
+    progress: IoProgress,
+    thread: Option<Worker<T>>,
+    started: Instant,
+}
+
+impl<T: Voxel + Serialize + DeserializeOwned> MapTask<T> {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(save_directory: P) -> Self {
+        Self::load_from(Arc::new(FileBackend::new(save_directory)))
+    }
+
+    pub fn load_from(backend: Arc<dyn SaveBackend>) -> Self {
+        Self::spawn(MapIoKind::Load, move |progress| {
+            Map::load_from(&*backend, &progress)
+        })
+    }
+
+    /// Saves a snapshot of `map`, so that it can keep changing while the save is running.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(map: &Map<T>, save_directory: P) -> Self {
+        Self::save_to(map, Arc::new(FileBackend::new(save_directory)))
+    }
+
+    /// Like `save`, but writes to `backend`.
+    pub fn save_to(map: &Map<T>, backend: Arc<dyn SaveBackend>) -> Self {
+        let map = map.clone();
+        Self::spawn(MapIoKind::Save, move |progress| {
+            map.save_to(&*backend, &progress).map(|_| None)
+        })
+    }
+
+    fn spawn<F>(kind: MapIoKind, f: F) -> Self
+    where
+        F: FnOnce(IoProgress) -> bincode::Result<Option<Map<T>>> + Send + 'static,
+    {
+        let progress = IoProgress::new();
+        let thread_progress = progress.clone();
+        let run = move || {
+            let result = f(thread_progress.clone());
+            thread_progress.finish();
+            result
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let thread = thread::spawn(run);
+        #[cfg(target_arch = "wasm32")]
+        let thread = run();
+        Self {
+            kind,
+            progress,
+            thread: Some(thread),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn kind(&self) -> MapIoKind {
+        self.kind
+    }
+
+    pub fn progress(&self) -> &IoProgress {
+        &self.progress
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    /// The bytes written per second since the task started.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.started.elapsed().as_secs_f64();
+        if seconds > 0.0 {
+            self.progress.bytes() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Joins the background thread once the task has finished, returning the loaded map
+    /// for loads. Returns `None` while the task is still running or after it was joined.
+    pub fn try_join(&mut self) -> Option<bincode::Result<Option<Map<T>>>> {
+        if !self.progress.is_finished() {
+            return None;
+        }
+        let thread = self.thread.take()?;
+        #[cfg(target_arch = "wasm32")]
+        return Some(thread);
+        #[cfg(not(target_arch = "wasm32"))]
+        Some(thread.join().unwrap_or_else(|_| {
+            Err(Box::new(bincode::ErrorKind::Custom(String::from(
+                "map io thread panicked",
+            ))))
+        }))
+    }
+}
+
+#[cfg(feature = "bevy")]
+pub fn map_task_update<T: Voxel + Serialize + DeserializeOwned>(
+    mut commands: Commands,
+    mut events: ResMut<Events<MapIoEvent>>,
+    mut tasks: Query<(Entity, &mut MapTask<T>)>,
+) {
+    for (entity, mut task) in &mut tasks.iter() {
+        let kind = task.kind;
+        events.send(MapIoEvent::Progress {
+            entity,
+            kind,
+            done: task.progress.done(),
+            total: task.progress.total(),
+        });
+
+        let result = if let Some(result) = task.try_join() {
+            result
+        } else {
+            continue;
+        };
+        commands.remove_one::<MapTask<T>>(entity);
+
+        match result {
+            Ok(map) if task.progress.is_cancelled() => {
+                drop(map);
+                events.send(MapIoEvent::Cancelled { entity, kind });
+            }
+            Ok(map) => {
+                if let Some(map) = map {
+                    commands.insert_one(entity, map);
+                }
+                events.send(MapIoEvent::Finished { entity, kind });
+            }
+            Err(error) => events.send(MapIoEvent::Failed {
+                entity,
+                kind,
+                error: error.to_string(),
+            }),
+        }
+    }
+}
+
+/// Measures the throughput and compression ratio of running saves, see
+/// `SAVE_THROUGHPUT_DIAGNOSTIC` and `SAVE_COMPRESSION_DIAGNOSTIC`.
+#[cfg(feature = "bevy")]
+pub fn save_diagnostics_update<T: Voxel + Serialize + DeserializeOwned>(
+    mut diagnostics: ResMut<Diagnostics>,
+    mut tasks: Query<&MapTask<T>>,
+) {
+    if diagnostics.get(SAVE_THROUGHPUT_DIAGNOSTIC).is_none() {
+        diagnostics.add(Diagnostic::new(
+            SAVE_THROUGHPUT_DIAGNOSTIC,
+            "save throughput",
+            20,
+        ));
+        diagnostics.add(Diagnostic::new(
+            SAVE_COMPRESSION_DIAGNOSTIC,
+            "save compression",
+            20,
+        ));
+    }
+    for task in &mut tasks.iter() {
+        if task.kind != MapIoKind::Save {
+            continue;
+        }
+        diagnostics.add_measurement(SAVE_THROUGHPUT_DIAGNOSTIC, task.throughput() / 1.0e6);
+        let raw_bytes = task.progress.raw_bytes();
+        if raw_bytes > 0 {
+            let ratio = task.progress.bytes() as f64 / raw_bytes as f64;
+            diagnostics.add_measurement(SAVE_COMPRESSION_DIAGNOSTIC, ratio);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        tests::{map, MemoryBackend},
+        Chunk, MapUpdates,
+    };
+
+    #[test]
+    pub fn io_progress() {
+        let backend = Arc::new(MemoryBackend::default());
+        let mut task = MapTask::save_to(&map(), backend.clone());
+        let result = loop {
+            if let Some(result) = task.try_join() {
+                break result;
+            }
+            std::thread::yield_now();
+        };
+        assert!(result.unwrap().is_none());
+        assert_eq!(task.kind(), MapIoKind::Save);
+        assert_eq!((task.progress().done(), task.progress().total()), (27, 27));
+        assert!(task.progress().bytes() > 0);
+        assert!(task.try_join().is_none());
+
+        let progress = IoProgress::new();
+        let loaded = Map::<i32>::load_from(&*backend, &progress).unwrap();
+        assert_eq!(loaded.map(|map| map.len()), Some(27));
+        assert_eq!((progress.done(), progress.total()), (27, 27));
+
+        // a cancelled load stops before reading any chunk and returns no map
+        let progress = IoProgress::new();
+        progress.cancel();
+        let loaded = Map::<i32>::load_from(&*backend, &progress).unwrap();
+        assert!(loaded.is_none());
+        assert_eq!(progress.done(), 0);
+    }
+
+    #[test]
+    pub fn save_batches() {
+        // enough chunks for the writes of one batch to overlap the compression of the next
+        let mut chunks = Vec::new();
+        for x in -3..=3 {
+            for y in -1..=1 {
+                for z in -3..=3 {
+                    chunks.push(Chunk::new(2, (x * 4, y * 4, z * 4)));
+                }
+            }
+        }
+        let mut map = Map::<i32>::try_with_chunks(chunks).unwrap();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((-12, 0, 12), Some(1), &mut updates);
+        map.set_voxel((12, 5, -12), Some(2), &mut updates);
+
+        let backend = MemoryBackend::default();
+        let progress = IoProgress::new();
+        map.save_to(&backend, &progress).unwrap();
+        assert_eq!((progress.done(), progress.total()), (147, 147));
+        assert!(progress.raw_bytes() > 0 && progress.bytes() > 0);
+
+        let loaded = Map::<i32>::load_from(&backend, &progress).unwrap().unwrap();
+        assert_eq!(loaded.len(), 147);
+        assert_eq!(loaded.voxel((-12, 0, 12)).as_deref(), Some(&1));
+        assert_eq!(loaded.voxel((12, 5, -12)).as_deref(), Some(&2));
+    }
+}
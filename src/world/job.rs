@@ -0,0 +1,117 @@
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Chunk, ChunkUpdate, Map, MapUpdates, UpdateCause},
+};
+
+/// A stage of a chunk's pipeline that runs away from the map, e.g. meshing a copy of the chunk
+/// on another thread, with the version and epoch of the chunk it started from.
+///
+/// Edits that land while the job runs make its result stale, and so does replacing the chunk,
+/// e.g. by reloading or regenerating it. Hand the ticket back to
+/// `Map::finish_job` when the job is done to get the chunk to apply the result to, which
+/// requests the stage again instead if the chunk changed in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobTicket {
+    pub position: (i32, i32, i32),
+    pub stage: ChunkUpdate,
+    version: u64,
+    epoch: u64,
+}
+
+impl JobTicket {
+    pub fn new<T: Voxel>(chunk: &Chunk<T>, stage: ChunkUpdate) -> Self {
+        Self {
+            position: chunk.position(),
+            stage,
+            version: chunk.version(),
+            epoch: chunk.epoch(),
+        }
+    }
+
+    /// The version of the chunk when the job started, see `Chunk::version`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The epoch of the chunk when the job started, see `Chunk::epoch`.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns whether the voxels of `chunk` changed since the job started, or `chunk` isn't
+    /// the chunk the job started from.
+    pub fn is_stale<T: Voxel>(&self, chunk: &Chunk<T>) -> bool {
+        chunk.version() != self.version || chunk.epoch() != self.epoch
+    }
+}
+
+impl<T: Voxel> Map<T> {
+    /// Starts a job for `stage` of the chunk at `coords`, or returns `None` if it isn't
+    /// loaded.
+    pub fn start_job(&self, coords: (i32, i32, i32), stage: ChunkUpdate) -> Option<JobTicket> {
+        self.get_at_origin(coords)
+            .map(|chunk| JobTicket::new(chunk, stage))
+    }
+
+    /// Returns the chunk the job of `ticket` ran for, to apply its result to.
+    ///
+    /// Returns `None` if the result has to be thrown away: if the chunk was unloaded, or if it
+    /// was edited or replaced since the job started, in which case the stage is requested
+    /// again.
+    pub fn finish_job(
+        &mut self,
+        ticket: &JobTicket,
+        updates: &mut MapUpdates,
+    ) -> Option<&mut Chunk<T>> {
+        let stale = ticket.is_stale(self.get_at_origin(ticket.position)?);
+        if stale {
+            let stage = ticket.stage.clone();
+            updates.request_because(ticket.position, stage, UpdateCause::Edit);
+            return None;
+        }
+        self.get_at_origin_mut(ticket.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::map;
+
+    #[test]
+    pub fn job_ticket() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        let ticket = map.start_job((0, 0, 0), ChunkUpdate::UpdateMesh).unwrap();
+        map.get_mut((0, 0, 0)).unwrap().set_light(true);
+        assert!(map.finish_job(&ticket, &mut updates).is_some());
+        assert!(updates.updates.is_empty());
+
+        // the chunk was edited while the job ran
+        let ticket = map.start_job((0, 0, 0), ChunkUpdate::UpdateMesh).unwrap();
+        map.get_mut((0, 0, 0)).unwrap().insert((1, 1, 1), 1);
+        assert!(ticket.is_stale(map.get((0, 0, 0)).unwrap()));
+        assert!(map.finish_job(&ticket, &mut updates).is_none());
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateMesh);
+
+        // unloaded chunks aren't requested again
+        let ticket = map.start_job((4, 0, 0), ChunkUpdate::UpdateMesh).unwrap();
+        map.remove((4, 0, 0));
+        assert!(map.finish_job(&ticket, &mut updates).is_none());
+        assert!(!updates.updates.contains_key(&(4, 0, 0)));
+        assert!(map.start_job((4, 0, 0), ChunkUpdate::UpdateMesh).is_none());
+
+        // the chunk was reloaded or regenerated while the job ran, at the same version
+        map.try_insert(Chunk::new(2, (4, 0, 0))).unwrap();
+        let ticket = map.start_job((4, 0, 0), ChunkUpdate::UpdateMesh).unwrap();
+        map.remove((4, 0, 0));
+        map.try_insert(Chunk::new(2, (4, 0, 0))).unwrap();
+        assert_eq!(map.get((4, 0, 0)).unwrap().version(), ticket.version());
+        assert!(map.finish_job(&ticket, &mut updates).is_none());
+        assert_eq!(updates.updates[&(4, 0, 0)], ChunkUpdate::UpdateMesh);
+
+        let ticket = map.start_job((4, 0, 0), ChunkUpdate::UpdateMesh).unwrap();
+        map.try_insert_many(vec![Chunk::new(2, (4, 0, 0))]).unwrap();
+        assert!(map.finish_job(&ticket, &mut updates).is_none());
+    }
+}
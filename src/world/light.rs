@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+
+use crate::collections::{lod_tree::Element, LodTree, VoxelStorage};
+
+/// How precisely chunks store their light maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LightPrecision {
+    /// One `f32` per voxel.
+    Full,
+    /// One `u8` per voxel in a flat array, a fraction of the memory of the tree nodes of
+    /// `Full`. Light is clamped to `0.0..=1.0` and rounded to 256 levels, and voxels that were
+    /// never lit are dark instead of missing.
+    Quantized,
+}
+
+impl Default for LightPrecision {
+    fn default() -> Self {
+        Self::Full
+    }
+}
+
+pub(crate) fn quantize(light: f32) -> u8 {
+    (light.max(0.0).min(1.0) * u8::MAX as f32).round() as u8
+}
+
+pub(crate) fn dequantize(light: u8) -> f32 {
+    light as f32 / u8::MAX as f32
+}
+
+/// The light map of a chunk, stored with a `LightPrecision`. Light is always read and written
+/// as `f32`, whatever the precision.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightTree {
+    Full(LodTree<f32>),
+    Quantized { width: usize, light: Vec<u8> },
+}
+
+impl LightTree {
+    pub fn new(width: usize, precision: LightPrecision) -> Self {
+        match precision {
+            LightPrecision::Full => Self::Full(LodTree::new(width)),
+            LightPrecision::Quantized => Self::Quantized {
+                width,
+                light: vec![0; width * width * width],
+            },
+        }
+    }
+
+    pub fn precision(&self) -> LightPrecision {
+        match self {
+            Self::Full(_) => LightPrecision::Full,
+            Self::Quantized { .. } => LightPrecision::Quantized,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            Self::Full(tree) => tree.width(),
+            Self::Quantized { width, .. } => *width,
+        }
+    }
+
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<f32> {
+        match self {
+            Self::Full(tree) => tree.voxel(coords).copied(),
+            Self::Quantized { width, light } => {
+                Some(dequantize(light[Self::index(*width, coords)?]))
+            }
+        }
+    }
+
+    pub fn insert(&mut self, coords: (i32, i32, i32), light: f32) {
+        match self {
+            Self::Full(tree) => {
+                tree.insert(coords, light);
+            }
+            Self::Quantized { width, light: data } => {
+                if let Some(index) = Self::index(*width, coords) {
+                    data[index] = quantize(light);
+                }
+            }
+        }
+    }
+
+    pub fn elements(&self) -> Box<dyn Iterator<Item = Element<'_, f32>> + '_> {
+        match self {
+            Self::Full(tree) => Box::new(tree.elements()),
+            Self::Quantized { width, light } => {
+                let width = *width as i32;
+                Box::new(light.iter().enumerate().map(move |(index, &light)| {
+                    let index = index as i32;
+                    Element {
+                        x: index / (width * width),
+                        y: index / width % width,
+                        z: index % width,
+                        width: 1,
+                        value: Cow::Owned(dequantize(light)),
+                    }
+                }))
+            }
+        }
+    }
+
+    /// Returns a copy of the light map stored with `precision`.
+    pub fn with_precision(&self, precision: LightPrecision) -> Self {
+        let mut tree = Self::new(self.width(), precision);
+        let width = self.width() as i32;
+        for x in 0..width {
+            for y in 0..width {
+                for z in 0..width {
+                    if let Some(light) = self.get((x, y, z)) {
+                        tree.insert((x, y, z), light);
+                    }
+                }
+            }
+        }
+        tree
+    }
+
+    /// The index of `coords` in a quantized light map, x-major like `DenseBuffer`.
+    fn index(width: usize, (x, y, z): (i32, i32, i32)) -> Option<usize> {
+        let width = width as i32;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= width || z >= width {
+            return None;
+        }
+        Some(((x * width + y) * width + z) as usize)
+    }
+}
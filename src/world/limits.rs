@@ -0,0 +1,233 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+
+#[cfg(feature = "bevy")]
+use crate::collections::lod_tree::Voxel;
+#[cfg(feature = "bevy")]
+use crate::world::Map;
+use crate::world::{ChunkUpdate, MapUpdates};
+
+/// Which pending updates `MapUpdates::enforce_limits` drops first.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    /// The ones requested first.
+    Oldest,
+    /// The ones farthest from `MapUpdates::focus`, or the oldest ones without a focus.
+    Farthest,
+}
+
+impl Default for Eviction {
+    fn default() -> Self {
+        Self::Farthest
+    }
+}
+
+/// Caps on the number of chunks pending per stage, `None` for no cap.
+///
+/// Dropped generation requests are requested again by streaming once the chunks are near
+/// enough, but loaded chunks dropped from the later stages stay in their state until
+/// something requests them again, so those are usually left uncapped.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UpdateLimits {
+    pub generate: Option<usize>,
+    pub light_map: Option<usize>,
+    pub light: Option<usize>,
+    pub mesh: Option<usize>,
+    pub eviction: Eviction,
+}
+
+impl UpdateLimits {
+    pub fn get(&self, stage: &ChunkUpdate) -> Option<usize> {
+        match stage {
+            ChunkUpdate::GenerateChunk => self.generate,
+            ChunkUpdate::UpdateLightMap => self.light_map,
+            ChunkUpdate::UpdateLight => self.light,
+            ChunkUpdate::UpdateMesh => self.mesh,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.generate.is_none()
+            && self.light_map.is_none()
+            && self.light.is_none()
+            && self.mesh.is_none()
+    }
+}
+
+/// Sent by `update_limits_update` for every capped stage that is full, so that streaming can
+/// slow down. Has to be registered with `add_event::<UpdateBackpressure>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateBackpressure {
+    pub stage: ChunkUpdate,
+    pub pending: usize,
+    pub limit: usize,
+    /// The number of requests that were dropped this frame.
+    pub evicted: usize,
+}
+
+const STAGES: [ChunkUpdate; 4] = [
+    ChunkUpdate::GenerateChunk,
+    ChunkUpdate::UpdateLightMap,
+    ChunkUpdate::UpdateLight,
+    ChunkUpdate::UpdateMesh,
+];
+
+impl MapUpdates {
+    /// Returns how full the queue of `stage` is, `1.0` or more once it's at its limit and
+    /// always `0.0` for stages without one.
+    pub fn pressure(&self, stage: ChunkUpdate) -> f32 {
+        match self.limits.get(&stage) {
+            Some(0) => 1.0,
+            Some(limit) => self.iter_kind(stage).count() as f32 / limit as f32,
+            None => 0.0,
+        }
+    }
+
+    /// Whether no more chunks should be requested for `stage`, because they would only
+    /// push others out.
+    pub fn is_saturated(&self, stage: ChunkUpdate) -> bool {
+        self.pressure(stage) >= 1.0
+    }
+
+    /// Drops the pending updates of every stage above its limit, see `UpdateLimits`, and
+    /// returns them.
+    pub fn enforce_limits(&mut self) -> Vec<((i32, i32, i32), ChunkUpdate)> {
+        let updates = &self.updates;
+        self.order.retain(|coords, _| updates.contains_key(coords));
+        if self.limits.is_empty() {
+            return Vec::new();
+        }
+
+        let mut evicted = Vec::new();
+        for stage in &STAGES {
+            let limit = match self.limits.get(stage) {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let mut pending = self.iter_kind(stage.clone()).collect::<Vec<_>>();
+            if pending.len() <= limit {
+                continue;
+            }
+            // the requests to keep come first
+            let order = |coords: &(i32, i32, i32)| self.order.get(coords).copied().unwrap_or(0);
+            match (self.limits.eviction, self.focus) {
+                (Eviction::Farthest, Some((fx, fy, fz))) => {
+                    pending.sort_by_key(|&(x, y, z)| {
+                        let distance = (x - fx).abs().max((y - fy).abs()).max((z - fz).abs());
+                        (distance, std::cmp::Reverse(order(&(x, y, z))))
+                    });
+                }
+                _ => pending.sort_by_key(|coords| std::cmp::Reverse(order(coords))),
+            }
+            for coords in pending.drain(limit..) {
+                self.updates.remove(&coords);
+                self.order.remove(&coords);
+                self.causes.remove(&coords);
+                evicted.push((coords, stage.clone()));
+            }
+        }
+        evicted
+    }
+
+    /// Cancels the pending and prefetched generation of every chunk whose column is more than
+    /// `interest` voxels away from `focus`, e.g. the chunks queued before the viewer moved
+    /// away, and returns them. Nothing is cancelled unless both are set.
+    ///
+    /// Columns are measured from their chunk origins, so `interest` should leave a chunk of
+    /// margin beyond the streaming distance, or chunks at its edge are requested and cancelled
+    /// in turn.
+    pub fn cancel_generation(&mut self) -> Vec<(i32, i32, i32)> {
+        let ((fx, _, fz), interest) = match (self.focus, self.interest) {
+            (Some(focus), Some(interest)) => (focus, interest),
+            _ => return Vec::new(),
+        };
+        let outside = |&(x, _, z): &(i32, i32, i32)| (x - fx).abs().max((z - fz).abs()) > interest;
+        let mut cancelled = self
+            .iter_kind(ChunkUpdate::GenerateChunk)
+            .filter(outside)
+            .collect::<Vec<_>>();
+        for coords in &cancelled {
+            self.updates.remove(coords);
+            self.order.remove(coords);
+            self.causes.remove(coords);
+        }
+        cancelled.extend(self.prefetch.iter().filter(|coords| outside(coords)));
+        self.prefetch.retain(|coords| !outside(coords));
+        self.cancelled += cancelled.len();
+        cancelled
+    }
+
+    /// The number of generation requests `cancel_generation` dropped so far.
+    pub fn cancelled(&self) -> usize {
+        self.cancelled
+    }
+}
+
+/// Generation requests cancelled per frame by `generation_cancel_update`.
+#[cfg(feature = "bevy")]
+pub const GENERATION_CANCEL_DIAGNOSTIC: DiagnosticId = DiagnosticId::from_u128(1297340582716342);
+
+/// Cancels the generation of chunks out of the `MapUpdates::interest` of every map, before
+/// `terrain_generation` gets to them, and publishes how many were cancelled.
+///
+/// Chunks are generated within the frame they're drained in, so only queued requests can be
+/// cancelled.
+#[cfg(feature = "bevy")]
+pub fn generation_cancel_update<T: Voxel>(
+    mut diagnostics: ResMut<Diagnostics>,
+    mut query: Query<(&Map<T>, &mut MapUpdates)>,
+) {
+    let mut count = 0;
+    let mut tracked = false;
+    for (_, mut updates) in &mut query.iter() {
+        tracked |= updates.interest.is_some();
+        count += updates.cancel_generation().len();
+    }
+    if !tracked {
+        return;
+    }
+    if diagnostics.get(GENERATION_CANCEL_DIAGNOSTIC).is_none() {
+        let diagnostic = Diagnostic::new(GENERATION_CANCEL_DIAGNOSTIC, "cancelled chunks", 20);
+        diagnostics.add(diagnostic);
+    }
+    diagnostics.add_measurement(GENERATION_CANCEL_DIAGNOSTIC, count as f64);
+}
+
+/// Enforces the `UpdateLimits` of every map and sends an `UpdateBackpressure` event for
+/// every stage that is full.
+#[cfg(feature = "bevy")]
+pub fn update_limits_update<T: Voxel>(
+    mut events: ResMut<Events<UpdateBackpressure>>,
+    mut query: Query<(&Map<T>, &mut MapUpdates)>,
+) {
+    for (_, mut updates) in &mut query.iter() {
+        if updates.limits.is_empty() {
+            continue;
+        }
+        let evicted = updates.enforce_limits();
+        for stage in &STAGES {
+            let limit = match updates.limits.get(stage) {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let pending = updates.iter_kind(stage.clone()).count();
+            if pending >= limit {
+                events.send(UpdateBackpressure {
+                    stage: stage.clone(),
+                    pending,
+                    limit,
+                    evicted: evicted.iter().filter(|(_, s)| s == stage).count(),
+                });
+            }
+        }
+    }
+}
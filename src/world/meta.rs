@@ -0,0 +1,91 @@
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+use std::{fs, path::Path};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{collections::lod_tree::Voxel, world::Map};
+
+/// Metadata about a world, stored next to its chunks in `world.ron`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorldMeta {
+    pub name: Option<String>,
+    pub seed: Option<u32>,
+    /// Where players enter the world, e.g. from `find_spawn`.
+    pub spawn: Option<(i32, i32, i32)>,
+}
+
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+impl WorldMeta {
+    pub const FILE_NAME: &'static str = "world.ron";
+
+    pub fn save<P: AsRef<Path>>(&self, save_directory: P) -> bincode::Result<()> {
+        let save_directory = save_directory.as_ref();
+        fs::create_dir_all(save_directory)?;
+        let path = save_directory.join(Self::FILE_NAME);
+        let temp = path.with_extension("ron.tmp");
+        let ron = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|e| bincode::ErrorKind::Custom(e.to_string()))?;
+        fs::write(&temp, ron)?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Loads the metadata of a world, or returns `None` if it was saved without any.
+    pub fn load<P: AsRef<Path>>(save_directory: P) -> bincode::Result<Option<Self>> {
+        let path = save_directory.as_ref().join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let ron = fs::read_to_string(path)?;
+        ron::de::from_str(&ron)
+            .map(Some)
+            .map_err(|e| Box::new(bincode::ErrorKind::Custom(e.to_string())))
+    }
+}
+
+/// Searches the loaded chunks outwards from the column at `center` for a place to spawn.
+///
+/// A column qualifies if its surface voxel passes `ground`, which should reject e.g. water,
+/// and has `clearance` empty voxels in loaded chunks above it. Returns the first empty voxel
+/// above the ground of the nearest such column within `radius`.
+pub fn find_spawn<T, F>(
+    map: &Map<T>,
+    center: (i32, i32),
+    radius: i32,
+    clearance: i32,
+    ground: F,
+) -> Option<(i32, i32, i32)>
+where
+    T: Voxel,
+    F: Fn(&T) -> bool,
+{
+    let (cx, cz) = center;
+    for r in 0..=radius {
+        for x in cx - r..=cx + r {
+            for z in cz - r..=cz + r {
+                // only the ring at distance `r`
+                if (x - cx).abs() != r && (z - cz).abs() != r {
+                    continue;
+                }
+                let y = if let Some(y) = map.surface_height((x, z)) {
+                    y
+                } else {
+                    continue;
+                };
+                if !map.voxel((x, y, z)).map(|v| ground(&v)).unwrap_or(false) {
+                    continue;
+                }
+                let clear = (1..=clearance).all(|dy| {
+                    let p = (x, y + dy, z);
+                    map.chunk_containing(p).is_some() && map.voxel(p).is_none()
+                });
+                if clear {
+                    return Some((x, y + 1, z));
+                }
+            }
+        }
+    }
+    None
+}
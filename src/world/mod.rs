@@ -1,82 +1,695 @@
-use std::{borrow::Cow, collections::HashMap};
-#[cfg(feature = "savedata")]
 use std::{
-    fs::{self, File},
-    io::Read,
-    path::Path,
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt, mem,
 };
+#[cfg(feature = "savedata")]
+use std::{convert::TryFrom, io::Read};
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+use std::path::Path;
 
 #[cfg(feature = "serde")]
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use either::Either;
 use rstar::{PointDistance, RTree, RTreeObject, AABB};
 
-use bevy::{ecs::Bundle, prelude::*};
+#[cfg(feature = "savedata")]
+use instant::Instant;
+#[cfg(all(feature = "savedata", feature = "parallel"))]
+use rayon::join;
+
+#[cfg(feature = "bevy")]
+use bevy::{
+    diagnostic::{Diagnostic, Diagnostics},
+    ecs::Bundle,
+    prelude::*,
+};
+
+#[cfg(feature = "savedata")]
+use crate::{
+    collections::RleTree,
+    serialize::ContentHasher,
+    world::codec::{ChunkCodec, EncodedChunk},
+};
+
+use crate::{
+    collections::{
+        lod_tree::{Defrag, Element, ElementMut, Voxel},
+        LodTree, SparseOctree, VoxelStorage,
+    },
+    mesh::VisibilityMask,
+    world::storage::ChunkData,
+};
 
 #[cfg(feature = "savedata")]
-use crate::collections::RleTree;
+pub mod archive;
+#[cfg(feature = "savedata")]
+pub mod backend;
+pub mod batch;
+pub mod change;
+#[cfg(feature = "savedata")]
+pub mod codec;
+pub mod defrag;
+pub mod dense;
+pub mod facade;
+#[cfg(feature = "savedata")]
+pub mod format;
+#[cfg(feature = "savedata")]
+pub mod io;
+pub mod job;
+pub mod light;
+pub mod limits;
+pub mod meta;
+pub mod overlay;
+#[cfg(feature = "savedata")]
+pub mod palette;
+pub mod pipeline;
+pub mod placement;
+pub mod prefetch;
+pub mod raycast;
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+pub mod region;
+#[cfg(feature = "bevy")]
+pub mod reset;
+pub mod shard;
+pub mod storage;
+pub mod tick;
+pub mod trigger;
+pub mod unload;
+pub mod warmup;
+pub mod weather;
 
-use crate::collections::{
-    lod_tree::{Element, ElementMut, Voxel},
-    LodTree,
+#[cfg(feature = "savedata")]
+pub use archive::ArchiveBackend;
+#[cfg(feature = "savedata")]
+pub use backend::SaveBackend;
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+pub use backend::FileBackend;
+pub use batch::MapEditor;
+#[cfg(feature = "bevy")]
+pub use change::block_changed_update;
+pub use change::{BlockChanged, ChangeCause};
+#[cfg(feature = "savedata")]
+pub use codec::{Compression, GeneratorDrift, SaveManifest};
+#[cfg(feature = "bevy")]
+pub use defrag::defrag_update;
+pub use defrag::{DefragBudget, MergePolicy};
+pub use dense::DenseBuffer;
+#[cfg(feature = "bevy")]
+pub use facade::voxel_world_update;
+pub use facade::VoxelWorld;
+#[cfg(feature = "savedata")]
+pub use format::{legacy_layout, LegacyLayout, CHUNK_FORMAT};
+#[cfg(all(feature = "savedata", feature = "bevy"))]
+pub use io::{
+    map_task_update, save_diagnostics_update, MapIoEvent, SAVE_COMPRESSION_DIAGNOSTIC,
+    SAVE_THROUGHPUT_DIAGNOSTIC,
 };
+#[cfg(feature = "savedata")]
+pub use io::{IoProgress, MapIoKind, MapTask};
+pub use job::JobTicket;
+pub use light::{LightPrecision, LightTree};
+#[cfg(feature = "bevy")]
+pub use limits::{generation_cancel_update, update_limits_update, GENERATION_CANCEL_DIAGNOSTIC};
+pub use limits::{Eviction, UpdateBackpressure, UpdateLimits};
+pub use meta::{find_spawn, WorldMeta};
+pub use overlay::OverlayLayer;
+#[cfg(feature = "savedata")]
+pub use palette::{PaletteVoxel, SavePalette};
+pub use pipeline::{ChunkPipeline, ChunkState, ChunkStateError, UpdateCause};
+pub use placement::{PlacementRejected, PlacementRules};
+pub use prefetch::Prefetch;
+#[cfg(feature = "bevy")]
+pub use prefetch::{prefetch_update, PrefetchViewer};
+pub use raycast::{ChunkPick, RaycastHit};
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+pub use region::{bake_regions, RegionLayout, RegionMap};
+#[cfg(feature = "bevy")]
+pub use reset::{world_reset_update, WorldReset};
+#[cfg(feature = "bevy")]
+pub use shard::shard_route_update;
+pub use shard::{MapShard, ShardLayout};
+pub use storage::StorageKind;
+#[cfg(feature = "bevy")]
+pub use tick::{random_tick_update, voxel_tick_update, TickViewer};
+pub use tick::{random_ticks, RandomTick, ScheduledVoxel, TickPolicy, VoxelTicks};
+#[cfg(feature = "bevy")]
+pub use trigger::{area_trigger_update, AreaEvent, AreaTracker};
+pub use trigger::{TriggerArea, TriggerAreas};
+#[cfg(feature = "bevy")]
+pub use unload::{chunk_unload_update, ChunkUnloaded};
+pub use unload::{ChunkUnloader, UnloadPolicy};
+#[cfg(feature = "bevy")]
+pub use warmup::warm_up_update;
+pub use warmup::{WarmUp, WarmUpProgress, WorldReady};
+#[cfg(feature = "bevy")]
+pub use weather::overlay_update;
+pub use weather::Overlay;
 
 #[cfg(feature = "savedata")]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SaveData<T> {
     position: (i32, i32, i32),
-    data: RleTree<T>,
+    data: SaveContent<T>,
+    meta: Option<ChunkMeta>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+    border_light: Option<BorderLight>,
+    edited: bool,
+    user_data: Option<UserData>,
+}
+
+/// The voxel content of a saved chunk.
+///
+/// `Diff` only stores the voxels that differ from the chunk the generator produces for the
+/// same position, and has to be applied on top of a freshly generated baseline when loaded.
+/// `Thinned` is only used for chunks that were never edited, see `SaveThinning`.
+#[cfg(feature = "savedata")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SaveContent<T> {
+    Full(RleTree<T>),
+    Diff { width: usize, edits: Vec<Edit<T>> },
+    /// The voxels averaged down to `lod`, only good enough to show the chunk from afar until
+    /// the generator rebuilds it.
+    Thinned { lod: usize, tree: RleTree<T> },
+    /// Indices into the `SavePalette` of the save, see `Map::save_with_palette`.
+    Palette(RleTree<u32>),
+}
+
+/// A single voxel change relative to a baseline chunk. `None` means the voxel was removed.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit<T> {
+    pub coords: (i32, i32, i32),
+    pub value: Option<T>,
+}
+
+/// A structure placed by the generator, in chunk-local coordinates.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Structure {
+    pub position: (i32, i32, i32),
+    pub size: (usize, usize, usize),
+}
+
+/// How the water of a voxel column flows, see `ChunkMeta::flows`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct Flow {
+    /// The direction the water flows in along x and z, as long as the slope of the ground
+    /// under it. Water over flat ground, e.g. of oceans and lakes, stands still.
+    pub direction: [f32; 2],
+    /// The world y of the first voxel above the water.
+    pub surface: i32,
+    /// The world y of the lowest voxel of the water, right above the ground.
+    pub bed: i32,
+}
+
+impl Flow {
+    /// How many voxels below the surface `y` is, `0` for the top voxel of the water, or `None`
+    /// above the water and in the ground under it.
+    pub fn depth(&self, y: i32) -> Option<i32> {
+        if y >= self.bed && y < self.surface {
+            Some(self.surface - 1 - y)
+        } else {
+            None
+        }
+    }
+}
+
+/// Information about how a chunk was generated.
+///
+/// `biomes` holds one biome index per xz unit column of the chunk (x-major), indexing into
+/// the biomes of the program that generated it. `tints` holds the biome tint of every xz
+/// voxel column (x-major), or is empty if no biome of the chunk has one. `flows` holds how
+/// the water of every xz voxel column flows the same way, with `None` for dry columns, or is
+/// empty if the chunk has no water.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct ChunkMeta {
+    pub generator: Option<String>,
+    pub version: u32,
+    pub biomes: Vec<usize>,
+    pub structures: Vec<Structure>,
+    pub tints: Vec<[f32; 3]>,
+    pub flows: Vec<Option<Flow>>,
+}
+
+impl ChunkMeta {
+    pub fn biome(&self, width: usize, (x, z): (i32, i32)) -> Option<usize> {
+        self.biomes.get(x as usize * width + z as usize).copied()
+    }
+
+    /// The tint of the voxel column at chunk-local `(x, z)` in a chunk `width` voxels wide.
+    pub fn tint(&self, width: usize, (x, z): (i32, i32)) -> Option<[f32; 3]> {
+        self.tints.get(x as usize * width + z as usize).copied()
+    }
+
+    /// The flow of the water of the voxel column at chunk-local `(x, z)` in a chunk `width`
+    /// voxels wide, or `None` if it's dry.
+    pub fn flow(&self, width: usize, (x, z): (i32, i32)) -> Option<Flow> {
+        self.flows
+            .get(x as usize * width + z as usize)
+            .copied()
+            .flatten()
+    }
+}
+
+/// The light of the one voxel thick shell around a chunk, saved with the chunk so that it can
+/// be shaded as if its neighbours were there when only part of a world is loaded.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorderLight {
+    width: usize,
+    light: Vec<u8>,
+}
+
+impl BorderLight {
+    /// A fully lit shell around a chunk `width` voxels wide.
+    pub fn new(width: usize) -> Self {
+        let side = width + 2;
+        Self {
+            width,
+            light: vec![u8::MAX; 2 * side * side + width * (4 * width + 4)],
+        }
+    }
+
+    /// Iterates over the chunk-local coordinates of the shell.
+    pub fn coords(width: usize) -> impl Iterator<Item = (i32, i32, i32)> {
+        let width = width as i32;
+        (-1..=width).flat_map(move |x| {
+            (-1..=width).flat_map(move |y| {
+                (-1..=width)
+                    .map(move |z| (x, y, z))
+                    .filter(move |&coords| Self::index(width, coords).is_some())
+            })
+        })
+    }
+
+    /// Returns the light at chunk-local `coords`, or `None` if they aren't part of the shell.
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<f32> {
+        let index = Self::index(self.width as i32, coords)?;
+        Some(light::dequantize(self.light[index]))
+    }
+
+    pub fn set(&mut self, coords: (i32, i32, i32), light: f32) {
+        if let Some(index) = Self::index(self.width as i32, coords) {
+            self.light[index] = light::quantize(light);
+        }
+    }
+
+    /// Stores the two faces on x first, then the rings around the chunk for every x in
+    /// between.
+    fn index(width: i32, (x, y, z): (i32, i32, i32)) -> Option<usize> {
+        let side = width + 2;
+        let inside = |v: i32| v >= -1 && v <= width;
+        if !inside(x) || !inside(y) || !inside(z) {
+            return None;
+        }
+        let ring = 4 * width + 4;
+        let index = if x == -1 {
+            (y + 1) * side + z + 1
+        } else if x == width {
+            side * side + width * ring + (y + 1) * side + z + 1
+        } else {
+            let ring_start = side * side + x * ring;
+            if y == -1 {
+                ring_start + z + 1
+            } else if y == width {
+                ring_start + side + 2 * width + z + 1
+            } else if z == -1 || z == width {
+                ring_start + side + 2 * y + (z == width) as i32
+            } else {
+                return None;
+            }
+        };
+        Some(index as usize)
+    }
+}
+
+/// One bit per voxel of a chunk, set if the voxel isn't empty.
+///
+/// Chunks keep it up to date as voxels change, so testing whether a voxel is there is a
+/// single bit read instead of a lookup in the tree.
+///
+/// It also counts the voxels in each cell of a coarse grid of up to 8³ cells, so that rays
+/// can skip whole empty cells, see `empty_cell`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occupancy {
+    width: usize,
+    bits: Vec<u64>,
+    cell_width: usize,
+    cells: Vec<u32>,
+}
+
+impl Occupancy {
+    /// The number of cells along every axis of the coarse grid, fewer in narrower chunks.
+    pub const CELLS: usize = 8;
+
+    pub fn new(width: usize) -> Self {
+        let cells = Self::CELLS.min(width.max(1));
+        Self {
+            width,
+            bits: vec![0; (width * width * width + 63) / 64],
+            cell_width: (width / cells).max(1),
+            cells: vec![0; cells * cells * cells],
+        }
+    }
+
+    pub fn from_tree<T: Voxel>(tree: &LodTree<T>) -> Self {
+        Self::from_storage(tree)
+    }
+
+    pub fn from_storage<T, S: VoxelStorage<T>>(storage: &S) -> Self {
+        let mut occupancy = Self::new(storage.width());
+        storage.for_each_voxel(|coords, _| occupancy.set(coords, true));
+        occupancy
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns `false` for coordinates outside the chunk.
+    pub fn contains(&self, coords: (i32, i32, i32)) -> bool {
+        match self.index(coords) {
+            Some(index) => self.bits[index / 64] & (1 << (index % 64)) != 0,
+            None => false,
+        }
+    }
+
+    pub fn set(&mut self, coords: (i32, i32, i32), occupied: bool) {
+        let index = match self.index(coords) {
+            Some(index) => index,
+            None => return,
+        };
+        if self.contains(coords) == occupied {
+            return;
+        }
+        let cell = self.cell_index(coords);
+        if occupied {
+            self.bits[index / 64] |= 1 << (index % 64);
+            self.cells[cell] += 1;
+        } else {
+            self.bits[index / 64] &= !(1 << (index % 64));
+            self.cells[cell] -= 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|&count| count == 0)
+    }
+
+    /// The width of a cell of the coarse grid in voxels.
+    pub fn cell_width(&self) -> usize {
+        self.cell_width
+    }
+
+    /// Returns the largest empty cube around `coords` a ray can skip as its chunk-local
+    /// origin and width, the whole chunk if it is empty or else the cell of `coords` if that
+    /// is empty. Returns `None` for occupied cells and coordinates outside the chunk.
+    pub fn empty_cell(&self, (x, y, z): (i32, i32, i32)) -> Option<((i32, i32, i32), i32)> {
+        self.index((x, y, z))?;
+        if self.is_empty() {
+            return Some(((0, 0, 0), self.width as i32));
+        }
+        if self.cells[self.cell_index((x, y, z))] > 0 {
+            return None;
+        }
+        let width = self.cell_width as i32;
+        Some(((x - x % width, y - y % width, z - z % width), width))
+    }
+
+    /// The number of voxels that aren't empty.
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    /// The bits in x-major order, bit `i % 64` of word `i / 64` for voxel `i`.
+    pub fn as_slice(&self) -> &[u64] {
+        &self.bits
+    }
+
+    fn index(&self, (x, y, z): (i32, i32, i32)) -> Option<usize> {
+        let width = self.width as i32;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= width || z >= width {
+            return None;
+        }
+        Some(((x * width + y) * width + z) as usize)
+    }
+
+    /// The index of the cell of `coords`, which have to be inside the chunk.
+    fn cell_index(&self, (x, y, z): (i32, i32, i32)) -> usize {
+        let cells = (self.width / self.cell_width).max(1);
+        let cell = |v: i32| v as usize / self.cell_width;
+        (cell(x) * cells + cell(y)) * cells + cell(z)
+    }
+}
+
+/// Extra data attached to a single voxel, e.g. the contents of a chest or the text of a sign.
+///
+/// The payload is opaque to the chunk. With the `savedata` feature, any serializable value
+/// can be stored with `BlockData::encode`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockData(pub Vec<u8>);
+
+#[cfg(feature = "savedata")]
+impl BlockData {
+    pub fn encode<V: Serialize>(value: &V) -> bincode::Result<Self> {
+        bincode::serialize(value).map(Self)
+    }
+
+    pub fn decode<V: DeserializeOwned>(&self) -> bincode::Result<V> {
+        bincode::deserialize(&self.0)
+    }
+}
+
+/// Gameplay state attached to a whole chunk, e.g. who claimed it or how dangerous it is.
+///
+/// Like `BlockData`, the payload is opaque to the chunk and saved with it. With the
+/// `savedata` feature, any serializable value can be stored with `UserData::encode`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserData(pub Vec<u8>);
+
+#[cfg(feature = "savedata")]
+impl UserData {
+    pub fn encode<V: Serialize>(value: &V) -> bincode::Result<Self> {
+        bincode::serialize(value).map(Self)
+    }
+
+    pub fn decode<V: DeserializeOwned>(&self) -> bincode::Result<V> {
+        bincode::deserialize(&self.0)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Chunk<T> {
     position: (i32, i32, i32),
-    data: LodTree<T>,
-    light: LodTree<f32>,
+    data: ChunkData<T>,
+    occupancy: Occupancy,
+    visibility: Option<VisibilityMask>,
+    light: LightTree,
+    next_light: Option<LightTree>,
     has_light: bool,
+    meta: Option<ChunkMeta>,
+    block_data: HashMap<(i32, i32, i32), BlockData>,
+    user_data: Option<UserData>,
+    border_light: Option<BorderLight>,
+    state: ChunkState,
+    /// Whether the voxels were changed through `Map::set_voxel` since the chunk was generated.
+    edited: bool,
+    /// The lod the chunk was loaded at if it was saved thinned.
+    thinned: Option<usize>,
+    /// Whether single voxels were edited since the voxels were last defragmented.
+    fragmented: bool,
+    defrag: Option<Defrag<T>>,
+    /// The number of edits since the voxels were last merged, for `MergePolicy::AfterEdits`.
+    edits: usize,
+    /// See `version`.
+    version: u64,
+    /// See `epoch`.
+    epoch: u64,
+    /// See `detail`.
+    detail: f32,
+    #[cfg(feature = "bevy")]
     entity: Option<Entity>,
+    #[cfg(feature = "bevy")]
     t_entity: Option<Entity>,
 }
 
 impl<T: Voxel> Chunk<T> {
     pub fn new(size: u32, position: (i32, i32, i32)) -> Self {
         let chunk_size = 1 << size;
-        let data = LodTree::new(chunk_size);
-        let light = LodTree::new(chunk_size);
+        let data = ChunkData::Tree(LodTree::new(chunk_size));
+        let light = LightTree::new(chunk_size, LightPrecision::default());
         Self {
             position,
             data,
+            occupancy: Occupancy::new(chunk_size),
+            visibility: None,
             light,
+            next_light: None,
             has_light: false,
+            meta: None,
+            block_data: HashMap::new(),
+            user_data: None,
+            border_light: None,
+            state: ChunkState::Generated,
+            edited: false,
+            thinned: None,
+            fragmented: false,
+            defrag: None,
+            edits: 0,
+            version: 0,
+            epoch: 0,
+            detail: 0.0,
+            #[cfg(feature = "bevy")]
             entity: None,
+            #[cfg(feature = "bevy")]
             t_entity: None,
         }
     }
 
+    pub fn meta(&self) -> Option<&ChunkMeta> {
+        self.meta.as_ref()
+    }
+
+    pub fn meta_mut(&mut self) -> Option<&mut ChunkMeta> {
+        self.meta.as_mut()
+    }
+
+    pub fn set_meta(&mut self, meta: ChunkMeta) {
+        self.meta = Some(meta);
+    }
+
+    pub fn state(&self) -> ChunkState {
+        self.state
+    }
+
+    /// Moves the chunk to the state after `stage`, failing if the stages of `pipeline`
+    /// before it haven't run yet.
+    ///
+    /// Running an earlier stage again moves the chunk back, e.g. a new light map means the
+    /// chunk has to be lit and meshed again.
+    pub fn transition(
+        &mut self,
+        pipeline: &ChunkPipeline,
+        stage: &ChunkUpdate,
+    ) -> Result<(), ChunkStateError> {
+        if let Some(required) = pipeline.required(stage) {
+            if self.state < required {
+                return Err(ChunkStateError {
+                    position: self.position,
+                    state: self.state,
+                    stage: stage.clone(),
+                });
+            }
+        }
+        self.state = ChunkState::after(stage);
+        Ok(())
+    }
+
+    #[cfg(feature = "bevy")]
     pub fn entity(&self) -> Option<Entity> {
         self.entity
     }
 
+    #[cfg(feature = "bevy")]
     pub fn set_entity(&mut self, e: Entity) {
         self.entity = Some(e);
     }
 
+    #[cfg(feature = "bevy")]
     pub fn transparent_entity(&self) -> Option<Entity> {
         self.t_entity
     }
 
+    #[cfg(feature = "bevy")]
     pub fn set_transparent_entity(&mut self, e: Entity) {
         self.t_entity = Some(e);
     }
 
+    /// Forgets the render entities of the chunk, e.g. after they were released to a
+    /// `ChunkPool`.
+    #[cfg(feature = "bevy")]
+    pub fn clear_entities(&mut self) {
+        self.entity = None;
+        self.t_entity = None;
+    }
+
     pub fn has_light(&self) -> bool {
         self.has_light
     }
 
+    /// The light around the chunk from when it was saved, used for neighbours that aren't
+    /// loaded.
+    pub fn border_light(&self) -> Option<&BorderLight> {
+        self.border_light.as_ref()
+    }
+
+    pub fn set_border_light(&mut self, border_light: Option<BorderLight>) {
+        self.border_light = border_light;
+    }
+
     pub fn set_light(&mut self, light: bool) {
         self.has_light = light;
     }
 
+    pub fn is_edited(&self) -> bool {
+        self.edited
+    }
+
+    pub fn set_edited(&mut self, edited: bool) {
+        self.edited = edited;
+    }
+
+    /// A counter that goes up whenever the voxels of the chunk change, so that work done on
+    /// a copy of the chunk can tell that it's out of date, see `JobTicket`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Tells apart the chunks inserted at the same position, e.g. a chunk and the one it was
+    /// regenerated or reloaded as, whose versions both start at 0. The map draws it from a
+    /// counter when the chunk is inserted.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The lod the chunk was saved at if it was thinned. Its voxels are only averages then,
+    /// and should be replaced by the generator's before the chunk gets edited.
+    pub fn thinned(&self) -> Option<usize> {
+        self.thinned
+    }
+
+    /// How often the voxels change from one to the next per column of the chunk, e.g. about
+    /// `3.0` for flat terrain of grass, dirt and stone and a lot more for buildings. Biases the
+    /// level of detail of the chunk, see `LodConfig::detail_bias`.
+    pub fn detail(&self) -> f32 {
+        self.detail
+    }
+
+    /// Computes `detail` from the voxels at full detail. Chunks are scored when they're
+    /// generated or loaded, edits keep the old score until this is called again.
+    pub fn update_detail(&mut self) {
+        let width = self.width() as i32;
+        let mut changes = 0;
+        for i in 0..width.pow(3) {
+            let (x, y, z) = (i / width / width, i / width % width, i % width);
+            let voxel = self.voxel((x, y, z));
+            for &(dx, dy, dz) in &[(1, 0, 0), (0, 1, 0), (0, 0, 1)] {
+                let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                if nx < width && ny < width && nz < width && self.voxel((nx, ny, nz)) != voxel {
+                    changes += 1;
+                }
+            }
+        }
+        self.detail = changes as f32 / width.pow(2) as f32;
+    }
+
     pub fn set_lod(&mut self, lod: usize) {
         self.data.set_lod(lod);
     }
@@ -87,12 +700,88 @@ impl<T: Voxel> Chunk<T> {
 
     pub fn merge(&mut self) {
         self.data.merge();
+        self.edits = 0;
+    }
+
+    pub fn storage(&self) -> StorageKind {
+        self.data.kind()
+    }
+
+    /// Copies the voxels into the layout of `storage`. Chunks are created and loaded as
+    /// `StorageKind::LodTree`.
+    pub fn set_storage(&mut self, storage: StorageKind) {
+        self.data.convert(storage);
+        self.fragmented = false;
+        self.defrag = None;
+        self.version += 1;
+    }
+
+    /// Merges the voxels after they were flattened by `iter_mut`, or leaves them to
+    /// `defrag_update`, as `policy` says.
+    pub fn merge_with(&mut self, policy: MergePolicy) {
+        match policy {
+            MergePolicy::Always => self.merge(),
+            MergePolicy::Never => {}
+            MergePolicy::AfterEdits(edits) if self.edits >= edits => self.merge(),
+            MergePolicy::AfterEdits(_) | MergePolicy::Idle => {
+                self.fragmented = true;
+                self.defrag = None;
+            }
+        }
     }
 
     pub fn position(&self) -> (i32, i32, i32) {
         self.position
     }
 
+    /// Returns a new chunk at `position` with the voxels of this one and nothing else, for
+    /// `GenerationCache`.
+    pub(crate) fn copy_voxels_to(&self, position: (i32, i32, i32)) -> Self {
+        Self {
+            data: self.data.clone(),
+            occupancy: self.occupancy.clone(),
+            ..Self::new(self.width().trailing_zeros(), position)
+        }
+    }
+
+    /// Copies the voxels and block data of `from` where it overlaps this chunk, for
+    /// `Map::coarsen` and `Map::split`.
+    fn copy_overlap(&mut self, from: &Self) {
+        let (fx, fy, fz) = from.position;
+        let (tx, ty, tz) = self.position;
+        let (fw, tw) = (from.width() as i32, self.width() as i32);
+        let min = (fx.max(tx), fy.max(ty), fz.max(tz));
+        let max = (
+            (fx + fw).min(tx + tw),
+            (fy + fw).min(ty + tw),
+            (fz + fw).min(tz + tw),
+        );
+        for x in min.0..max.0 {
+            for y in min.1..max.1 {
+                for z in min.2..max.2 {
+                    if let Some(voxel) = from.get(from.to_local((x, y, z))) {
+                        self.insert(self.to_local((x, y, z)), voxel.into_owned());
+                    }
+                }
+            }
+        }
+        for ((x, y, z), data) in from.iter_block_data() {
+            let local = self.to_local((fx + x, fy + y, fz + z));
+            if (0..tw).contains(&local.0)
+                && (0..tw).contains(&local.1)
+                && (0..tw).contains(&local.2)
+            {
+                self.set_block_data(local, data.clone());
+            }
+        }
+        self.edited |= from.edited;
+    }
+
+    /// Converts world coordinates to coordinates relative to the chunk's origin.
+    pub fn to_local(&self, (x, y, z): (i32, i32, i32)) -> (i32, i32, i32) {
+        (x - self.position.0, y - self.position.1, z - self.position.2)
+    }
+
     pub fn width(&self) -> usize {
         self.data.width()
     }
@@ -101,189 +790,4268 @@ impl<T: Voxel> Chunk<T> {
         self.data.elements()
     }
 
+    /// Flattens the voxels, so the `merge` that usually follows merges them from scratch and
+    /// the chunk isn't fragmented anymore.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = ElementMut<'_, T>> {
+        self.fragmented = false;
+        self.defrag = None;
+        self.version += 1;
         self.data.elements_mut()
     }
 
+    /// Exports the voxels as a `SparseOctree`, e.g. to raymarch the chunk on the GPU.
+    pub fn sparse_octree(&self) -> SparseOctree<T> {
+        SparseOctree::from(&*self.data.tree())
+    }
+
     pub fn lights(&self) -> impl Iterator<Item = Element<'_, f32>> {
         self.light.elements()
     }
 
+    /// Stores the light map with `LightPrecision::Full` first if it is quantized, which has no
+    /// `f32`s to hand out.
+    #[deprecated(note = "use `insert_light`, which works with every `LightPrecision`")]
     pub fn lights_mut(&mut self) -> impl Iterator<Item = ElementMut<'_, f32>> {
-        self.light.elements_mut()
+        self.full_light().elements_mut()
+    }
+
+    pub fn light_precision(&self) -> LightPrecision {
+        self.light.precision()
+    }
+
+    /// Converts the light map, and the back buffer if there is one, to `precision`.
+    pub fn set_light_precision(&mut self, precision: LightPrecision) {
+        if self.light.precision() == precision {
+            return;
+        }
+        self.light = self.light.with_precision(precision);
+        if let Some(next_light) = &mut self.next_light {
+            *next_light = next_light.with_precision(precision);
+        }
     }
 
+    /// Sets the voxel at `coords`, dropping the block data of the voxel it replaces.
     pub fn insert(&mut self, coords: (i32, i32, i32), voxel: T) {
         self.data.insert(coords, voxel);
+        self.set_fragmented();
+        self.occupancy.set(coords, true);
+        self.visibility = None;
+        self.remove_block_data(coords);
     }
 
-    pub fn insert_light(&mut self, coords: (i32, i32, i32), light: f32) {
-        self.light.insert(coords, light);
+    /// Removes the voxel at `coords` together with its block data.
+    pub fn remove(&mut self, coords: (i32, i32, i32)) {
+        self.data.remove(coords);
+        self.set_fragmented();
+        self.occupancy.set(coords, false);
+        self.visibility = None;
+        self.remove_block_data(coords);
     }
 
-    pub fn get(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
-        self.data.get(coords)
+    /// Whether edits may have broken merges of the voxels since they were last defragmented.
+    pub fn is_fragmented(&self) -> bool {
+        self.fragmented
     }
 
-    pub fn get_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut T> {
-        self.data.get_mut(coords)
+    /// Continues defragmenting the voxels of a fragmented chunk, see `LodTree::defragment`,
+    /// and returns how many nodes it visited out of `budget`. Edits start it over.
+    pub fn defragment(&mut self, budget: usize) -> usize {
+        if !self.fragmented {
+            return 0;
+        }
+        let defrag = self.defrag.get_or_insert_with(Defrag::default);
+        // bricks are never merged, so there is nothing to defragment
+        let visited = self.data.defragment(defrag, budget);
+        if visited.is_none() || defrag.is_done() {
+            self.fragmented = false;
+            self.defrag = None;
+            self.edits = 0;
+        }
+        visited.unwrap_or(0)
     }
 
-    pub fn light(&self, coords: (i32, i32, i32)) -> Option<f32> {
-        self.light.get(coords).map(Cow::into_owned)
+    fn set_fragmented(&mut self) {
+        self.fragmented = true;
+        self.defrag = None;
+        self.edits += 1;
+        self.version += 1;
     }
 
-    pub fn light_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut f32> {
-        self.light.get_mut(coords)
+    pub fn block_data(&self, coords: (i32, i32, i32)) -> Option<&BlockData> {
+        self.block_data.get(&coords)
     }
 
-    pub fn contains_key(&self, coords: (i32, i32, i32)) -> bool {
-        self.data.contains_key(coords)
+    pub fn block_data_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut BlockData> {
+        self.block_data.get_mut(&coords)
     }
-}
 
-#[cfg(feature = "savedata")]
-impl<T: Voxel + Serialize + DeserializeOwned> Chunk<T> {
-    pub fn load<R: Read>(reader: R) -> bincode::Result<Self> {
-        Ok(Self::from(bincode::deserialize_from::<_, SaveData<T>>(
-            reader,
-        )?))
+    /// Attaches `data` to the voxel at `coords`. Returns `false` if there is no voxel to
+    /// attach it to.
+    pub fn set_block_data(&mut self, coords: (i32, i32, i32), data: BlockData) -> bool {
+        if !self.occupancy.contains(coords) {
+            return false;
+        }
+        self.block_data.insert(coords, data);
+        true
     }
 
-    pub fn serializable(&self) -> SaveData<T> {
-        SaveData {
-            position: self.position,
-            data: RleTree::with_tree(&self.data),
+    pub fn remove_block_data(&mut self, coords: (i32, i32, i32)) -> Option<BlockData> {
+        if self.block_data.is_empty() {
+            return None;
         }
+        self.block_data.remove(&coords)
     }
-}
 
-#[cfg(feature = "savedata")]
-impl<T: Voxel> From<SaveData<T>> for Chunk<T> {
-    fn from(save: SaveData<T>) -> Self {
-        let data = LodTree::from(save.data);
-        let width = data.width();
-        Self {
-            position: save.position,
-            data,
-            light: LodTree::new(width),
-            has_light: false,
-            entity: None,
-            t_entity: None,
-        }
+    /// Iterates over all voxels with block data, in chunk-local coordinates.
+    pub fn iter_block_data(&self) -> impl Iterator<Item = ((i32, i32, i32), &BlockData)> {
+        self.block_data.iter().map(|(&coords, data)| (coords, data))
     }
-}
 
-impl<T: Voxel> RTreeObject for Chunk<T> {
-    type Envelope = AABB<[i32; 3]>;
+    pub fn user_data(&self) -> Option<&UserData> {
+        self.user_data.as_ref()
+    }
 
-    fn envelope(&self) -> Self::Envelope {
-        let w = self.width() as i32;
-        let x0 = self.position.0;
-        let y0 = self.position.1;
-        let z0 = self.position.2;
-        let x1 = self.position.0 + w - 1;
-        let y1 = self.position.1 + w - 1;
-        let z1 = self.position.2 + w - 1;
-        AABB::from_corners([x0, y0, z0], [x1, y1, z1])
+    pub fn user_data_mut(&mut self) -> Option<&mut UserData> {
+        self.user_data.as_mut()
     }
-}
 
-impl<T: Voxel> PointDistance for Chunk<T> {
-    fn distance_2(&self, point: &[i32; 3]) -> i32 {
-        self.envelope().distance_2(point)
+    /// Attaches `data` to the whole chunk, returning the data attached before.
+    pub fn set_user_data(&mut self, data: UserData) -> Option<UserData> {
+        self.user_data.replace(data)
     }
-}
 
-/// The map represents visible chunks.
-#[derive(Default, Debug, Clone)]
-pub struct Map<T: Voxel> {
-    map: RTree<Chunk<T>>,
-}
+    pub fn remove_user_data(&mut self) -> Option<UserData> {
+        self.user_data.take()
+    }
 
-impl<T: Voxel> Map<T> {
-    pub fn new() -> Self {
-        Self { map: RTree::new() }
+    pub fn apply<I: IntoIterator<Item = Edit<T>>>(&mut self, edits: I) {
+        for edit in edits {
+            match edit.value {
+                Some(voxel) => self.insert(edit.coords, voxel),
+                None => self.remove(edit.coords),
+            }
+        }
     }
 
-    pub fn with_chunks(initial: Vec<Chunk<T>>) -> Self {
-        Self {
-            map: RTree::bulk_load(initial),
+    pub fn insert_light(&mut self, coords: (i32, i32, i32), light: f32) {
+        self.light.insert(coords, light);
+    }
+
+    /// Writes a light value into the back buffer, which neighbours don't see until
+    /// `swap_light` is called.
+    pub fn insert_next_light(&mut self, coords: (i32, i32, i32), light: f32) {
+        let width = self.width();
+        let precision = self.light.precision();
+        self.next_light
+            .get_or_insert_with(|| LightTree::new(width, precision))
+            .insert(coords, light);
+    }
+
+    /// Replaces the light map with the back buffer, if anything was written to it.
+    /// Returns `true` if the light map changed.
+    pub fn swap_light(&mut self) -> bool {
+        if let Some(light) = self.next_light.take() {
+            self.light = light;
+            self.has_light = true;
+            true
+        } else {
+            false
         }
     }
 
-    pub fn get(&self, (x, y, z): (i32, i32, i32)) -> Option<&Chunk<T>> {
-        self.map.locate_at_point(&[x, y, z])
+    /// Returns the voxel at `coords` at full detail. Like `get_mut`, `voxel` and
+    /// `contains_key` this ignores the lod of the chunk, only `sampled_get` doesn't.
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        self.data.get(coords)
     }
 
-    pub fn get_mut(&mut self, (x, y, z): (i32, i32, i32)) -> Option<&mut Chunk<T>> {
-        self.map.locate_at_point_mut(&[x, y, z])
+    /// Returns the voxel at `coords` as it's meshed at the lod of the chunk.
+    pub fn sampled_get(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        self.data.sampled_get(coords)
     }
 
-    pub fn insert(&mut self, value: Chunk<T>) {
-        let (x, y, z) = value.position;
-        self.map.remove_at_point(&[x, y, z]);
-        self.map.insert(value);
+    pub fn get_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut T> {
+        self.set_fragmented();
+        self.data.get_mut(coords)
     }
 
-    pub fn remove(&mut self, (x, y, z): (i32, i32, i32)) -> Option<Chunk<T>> {
-        self.map.remove_at_point(&[x, y, z])
+    /// Like `get`, but without the `Cow`.
+    pub fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        self.data.voxel(coords)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &'_ Chunk<T>> {
-        self.map.iter()
+    pub fn light(&self, coords: (i32, i32, i32)) -> Option<f32> {
+        self.light.get(coords)
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &'_ mut Chunk<T>> {
-        self.map.iter_mut()
+    /// Stores the light map with `LightPrecision::Full` first if it is quantized, like
+    /// `lights_mut`.
+    #[deprecated(note = "use `insert_light`, which works with every `LightPrecision`")]
+    pub fn light_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut f32> {
+        self.full_light().get_mut(coords)
     }
-}
 
-#[cfg(feature = "savedata")]
-impl<T: Voxel + Serialize + DeserializeOwned> Map<T> {
-    pub fn save<P: AsRef<Path>>(&self, save_directory: P) -> bincode::Result<()> {
-        let save_directory = save_directory.as_ref();
-        fs::create_dir_all(save_directory)?;
-        for chunk in &self.map {
-            let mut path = save_directory.to_path_buf();
-            let (x, y, z) = chunk.position();
-            path.push(format!("chunk.{}.{}.{}.gz", x, y, z));
-            let file = File::create(path)?;
-            let savedata = chunk.serializable();
-            bincode::serialize_into(
-                flate2::write::GzEncoder::new(file, flate2::Compression::default()),
-                &savedata,
-            )?;
+    fn full_light(&mut self) -> &mut LodTree<f32> {
+        self.set_light_precision(LightPrecision::Full);
+        match &mut self.light {
+            LightTree::Full(tree) => tree,
+            LightTree::Quantized { .. } => unreachable!(),
         }
-        Ok(())
     }
 
-    pub fn load<P: AsRef<Path>>(save_directory: P) -> bincode::Result<Self> {
-        let save_directory = save_directory.as_ref();
-        let mut chunks = Vec::new();
-        for entry in save_directory.read_dir()? {
-            let file = flate2::read::GzDecoder::new(File::open(entry?.path())?);
-            let chunk = Chunk::load(file)?;
-            chunks.push(chunk);
-        }
-        Ok(Self::with_chunks(chunks))
+    pub fn contains_key(&self, coords: (i32, i32, i32)) -> bool {
+        self.occupancy.contains(coords)
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum ChunkUpdate {
-    GenerateChunk,
-    UpdateLightMap,
-    UpdateLight,
-    UpdateMesh,
+    /// Which voxels of the chunk aren't empty.
+    pub fn occupancy(&self) -> &Occupancy {
+        &self.occupancy
+    }
+
+    /// The visible faces of the voxels, if they were worked out since the last edit.
+    pub fn visibility(&self) -> Option<&VisibilityMask> {
+        self.visibility.as_ref()
+    }
+
+    pub fn set_visibility(&mut self, visibility: Option<VisibilityMask>) {
+        self.visibility = visibility;
+    }
 }
 
+impl<T: Voxel> VoxelStorage<T> for Chunk<T> {
+    fn width(&self) -> usize {
+        self.width()
+    }
+
+    fn len(&self) -> usize {
+        VoxelStorage::len(&self.data)
+    }
+
+    fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        self.voxel(coords)
+    }
+
+    fn set_voxel(&mut self, coords: (i32, i32, i32), value: Option<T>) {
+        match value {
+            Some(value) => self.insert(coords, value),
+            None => self.remove(coords),
+        }
+    }
+
+    fn for_each_voxel<F: FnMut((i32, i32, i32), &T)>(&self, f: F) {
+        self.data.for_each_voxel(f);
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: Voxel + Serialize + DeserializeOwned> Chunk<T> {
+    /// Reads a chunk serialized with `SaveData::to_bytes`.
+    pub fn load<R: Read>(mut reader: R) -> bincode::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::try_from(SaveData::from_bytes(&bytes)?)
+    }
+
+    /// Hashes the voxels of the chunk, ignoring its light, metadata and lod. See
+    /// `LodTree::content_hash`.
+    pub fn content_hash(&self) -> u64 {
+        self.data.tree().content_hash()
+    }
+
+    pub fn serializable(&self) -> SaveData<T> {
+        SaveData {
+            position: self.position,
+            data: SaveContent::Full(RleTree::with_tree(&self.data.tree())),
+            meta: self.meta.clone(),
+            block_data: self.block_data.clone(),
+            border_light: self.border_light.clone(),
+            edited: self.edited,
+            user_data: self.user_data.clone(),
+        }
+    }
+
+    /// Like `serializable`, but only keeps the voxels averaged down to `lod`.
+    pub fn serializable_thinned(&self, lod: usize) -> SaveData<T> {
+        SaveData {
+            position: self.position,
+            data: SaveContent::Thinned {
+                lod,
+                tree: RleTree::with_tree(&self.thin(lod)),
+            },
+            meta: self.meta.clone(),
+            block_data: self.block_data.clone(),
+            border_light: self.border_light.clone(),
+            edited: self.edited,
+            user_data: self.user_data.clone(),
+        }
+    }
+
+    /// Returns the save data of the edits that turn `baseline` into this chunk, or of the
+    /// whole chunk if `baseline` has a different width.
+    pub fn serializable_diff(&self, baseline: &Self) -> SaveData<T> {
+        let edits = match self.diff(baseline) {
+            Some(edits) => edits,
+            None => return self.serializable(),
+        };
+        SaveData {
+            position: self.position,
+            data: SaveContent::Diff {
+                width: self.width(),
+                edits,
+            },
+            meta: self.meta.clone(),
+            block_data: self.block_data.clone(),
+            border_light: self.border_light.clone(),
+            edited: self.edited,
+            user_data: self.user_data.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: PaletteVoxel> Chunk<T> {
+    /// Like `serializable`, but stores the voxels as indices into `palette`, adding the ids
+    /// that aren't in it yet.
+    pub fn serializable_with_palette(&self, palette: &mut SavePalette) -> SaveData<T> {
+        let mut last: Option<(T, u32)> = None;
+        let tree = RleTree::with_tree(&self.data.tree()).map(|voxel| match &last {
+            Some((previous, index)) if *previous == voxel => *index,
+            _ => {
+                let index = palette.index(&voxel.palette_id());
+                last = Some((voxel, index));
+                index
+            }
+        });
+        SaveData {
+            position: self.position,
+            data: SaveContent::Palette(tree),
+            meta: self.meta.clone(),
+            block_data: self.block_data.clone(),
+            border_light: self.border_light.clone(),
+            edited: self.edited,
+            user_data: self.user_data.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: Voxel> SaveData<T> {
+    /// Turns voxels stored as palette indices back into voxels, `voxels` being the voxel of
+    /// every id of the palette, see `SavePalette::voxels`.
+    pub fn resolve_palette(&mut self, voxels: &[T]) -> bincode::Result<()> {
+        let tree = match &mut self.data {
+            SaveContent::Palette(tree) => std::mem::take(tree),
+            _ => return Ok(()),
+        };
+        let position = self.position;
+        let tree = tree.try_map(|index| {
+            voxels.get(index as usize).cloned().ok_or_else(|| {
+                bincode::ErrorKind::Custom(format!(
+                    "chunk {:?} uses palette index {} of {}",
+                    position,
+                    index,
+                    voxels.len()
+                ))
+            })
+        })?;
+        self.data = SaveContent::Full(tree);
+        Ok(())
+    }
+
+    pub fn uses_palette(&self) -> bool {
+        matches!(self.data, SaveContent::Palette(_))
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: Voxel> Chunk<T> {
+    /// Returns the edits that turn `baseline` into this chunk, or `None` if the chunks have
+    /// different widths.
+    pub fn diff(&self, baseline: &Self) -> Option<Vec<Edit<T>>> {
+        let diff = self.data.tree().diff(&baseline.data.tree())?;
+        Some(
+            diff.into_iter()
+                .map(|(coords, value)| Edit { coords, value })
+                .collect(),
+        )
+    }
+
+    /// Returns the voxels with every `2^lod` wide cube replaced by its average and merged.
+    fn thin(&self, lod: usize) -> LodTree<T> {
+        let mut data = self.data.tree().into_owned();
+        data.set_lod(lod);
+        let mut tree = LodTree::new(self.width());
+        for elem in data.elements() {
+            let width = elem.width as i32;
+            for x in elem.x..elem.x + width {
+                for y in elem.y..elem.y + width {
+                    for z in elem.z..elem.z + width {
+                        tree.insert((x, y, z), elem.value.clone().into_owned());
+                    }
+                }
+            }
+        }
+        tree.merge();
+        tree
+    }
+
+    /// Restores a chunk from save data. Diffs are applied on top of `baseline`, or on top of
+    /// an empty chunk if no baseline is given. Thinned chunks are replaced by `baseline`, or
+    /// keep their averaged voxels at the lod they were saved at if no baseline is given.
+    ///
+    /// Fails if the voxels were saved with a palette that wasn't resolved with
+    /// `SaveData::resolve_palette`.
+    pub fn from_save_data(save: SaveData<T>, baseline: Option<Self>) -> bincode::Result<Self> {
+        let position = save.position;
+        let mut thinned = None;
+        let (data, meta) = match save.data {
+            SaveContent::Full(tree) => (ChunkData::Tree(LodTree::from(tree)), save.meta),
+            SaveContent::Thinned { lod, tree } => match baseline {
+                Some(chunk) => (chunk.data, save.meta.or(chunk.meta)),
+                None => {
+                    let mut data = LodTree::from(tree);
+                    data.set_lod(lod);
+                    thinned = Some(lod);
+                    (ChunkData::Tree(data), save.meta)
+                }
+            },
+            SaveContent::Diff { width, edits } => {
+                let mut chunk =
+                    baseline.unwrap_or_else(|| Self::new(width.trailing_zeros(), position));
+                chunk.apply(edits);
+                (chunk.data, save.meta.or(chunk.meta))
+            }
+            SaveContent::Palette(_) => {
+                return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                    "chunk {:?} was saved with a palette, load it with `load_with_palette`",
+                    position
+                ))));
+            }
+        };
+        let mut chunk = Self::new(data.width().trailing_zeros(), position);
+        chunk.occupancy = Occupancy::from_storage(&data);
+        chunk.data = data;
+        chunk.meta = meta;
+        chunk.block_data = save.block_data;
+        chunk.user_data = save.user_data;
+        chunk.border_light = save.border_light;
+        chunk.edited = save.edited;
+        chunk.thinned = thinned;
+        chunk.update_detail();
+        Ok(chunk)
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: Voxel> TryFrom<SaveData<T>> for Chunk<T> {
+    type Error = bincode::Error;
+
+    fn try_from(save: SaveData<T>) -> bincode::Result<Self> {
+        Self::from_save_data(save, None)
+    }
+}
+
+impl<T: Voxel> RTreeObject for Chunk<T> {
+    type Envelope = AABB<[i32; 3]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        chunk_envelope(self.position, self.width())
+    }
+}
+
+impl<T: Voxel> PointDistance for Chunk<T> {
+    fn distance_2(&self, point: &[i32; 3]) -> i32 {
+        self.envelope().distance_2(point)
+    }
+}
+
+fn chunk_envelope((x, y, z): (i32, i32, i32), width: usize) -> AABB<[i32; 3]> {
+    let w = width as i32;
+    AABB::from_corners([x, y, z], [x + w - 1, y + w - 1, z + w - 1])
+}
+
+/// The space taken by a chunk, for range queries on a `Map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChunkBounds {
+    position: (i32, i32, i32),
+    width: usize,
+}
+
+impl RTreeObject for ChunkBounds {
+    type Envelope = AABB<[i32; 3]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        chunk_envelope(self.position, self.width)
+    }
+}
+
+impl PointDistance for ChunkBounds {
+    fn distance_2(&self, point: &[i32; 3]) -> i32 {
+        self.envelope().distance_2(point)
+    }
+}
+
+/// Returns `stage`, or the first stage a chunk in `state` is missing if it hasn't made it far
+/// enough for `stage`.
+fn restart_stage(pipeline: &ChunkPipeline, state: ChunkState, stage: ChunkUpdate) -> ChunkUpdate {
+    match pipeline.required(&stage) {
+        Some(required) if state < required => pipeline
+            .stages()
+            .iter()
+            .find(|s| ChunkState::after(s) > state)
+            .cloned()
+            .unwrap_or(stage),
+        _ => stage,
+    }
+}
+
+/// The offsets of the 26 chunks around a chunk, in chunk widths.
+fn neighbour_offsets() -> impl Iterator<Item = (i32, i32, i32)> {
+    (-1..=1)
+        .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| (x, y, z))))
+        .filter(|&offset| offset != (0, 0, 0))
+}
+
+/// The widths of the chunks of a map.
+///
+/// Usually all chunks have the same width. A mixed layout also allows chunks that are a power
+/// of two multiple of `chunk_width` wide, up to `max_chunk_width`, e.g. huge chunks for the
+/// empty sky and small ones around the surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MapLayout {
+    pub chunk_width: usize,
+    pub max_chunk_width: usize,
+}
+
+impl MapLayout {
+    /// Creates the layout for chunks created with `Chunk::new(chunk_size, _)`.
+    pub fn new(chunk_size: u32) -> Self {
+        Self::uniform(1 << chunk_size)
+    }
+
+    /// Creates the layout for chunks that are all `chunk_width` wide.
+    pub fn uniform(chunk_width: usize) -> Self {
+        Self {
+            chunk_width,
+            max_chunk_width: chunk_width,
+        }
+    }
+
+    /// Creates the layout for chunks created with `Chunk::new(size, _)` for any `size` from
+    /// `min_size` to `max_size`.
+    pub fn mixed(min_size: u32, max_size: u32) -> Self {
+        assert!(min_size <= max_size, "min_size is larger than max_size");
+        Self {
+            chunk_width: 1 << min_size,
+            max_chunk_width: 1 << max_size,
+        }
+    }
+
+    /// Returns whether chunks may have different widths.
+    pub fn is_mixed(&self) -> bool {
+        self.max_chunk_width != self.chunk_width
+    }
+
+    /// Iterates over the widths chunks may have, from the smallest.
+    pub fn widths(&self) -> impl Iterator<Item = usize> {
+        let max = self.max_chunk_width;
+        std::iter::successors(Some(self.chunk_width), |width| Some(width * 2))
+            .take_while(move |&width| width <= max)
+    }
+
+    /// Returns whether chunks may be `width` wide.
+    pub fn allows(&self, width: usize) -> bool {
+        self.widths().any(|w| w == width)
+    }
+
+    /// Returns the origin of the chunk that contains world coordinates `coords`, if the chunk
+    /// is `chunk_width` wide.
+    pub fn chunk_origin(&self, coords: (i32, i32, i32)) -> (i32, i32, i32) {
+        Self::origin(coords, self.chunk_width)
+    }
+
+    /// Returns the origin of the chunk `width` wide that contains world coordinates `coords`.
+    pub fn origin((x, y, z): (i32, i32, i32), width: usize) -> (i32, i32, i32) {
+        let width = width as i32;
+        (
+            x.div_euclid(width) * width,
+            y.div_euclid(width) * width,
+            z.div_euclid(width) * width,
+        )
+    }
+
+    /// Returns the origins of the chunks next to the chunk at `position`, with their offsets
+    /// in chunks.
+    pub fn neighbors(
+        &self,
+        (x, y, z): (i32, i32, i32),
+        neighborhood: Neighborhood,
+    ) -> impl Iterator<Item = ((i32, i32, i32), (i32, i32, i32))> {
+        let width = self.chunk_width as i32;
+        neighborhood.offsets().map(move |(dx, dy, dz)| {
+            let position = (x + dx * width, y + dy * width, z + dz * width);
+            ((dx, dy, dz), position)
+        })
+    }
+}
+
+/// Which chunks count as the neighbors of a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Neighborhood {
+    /// The 6 chunks sharing a face with it.
+    Faces,
+    /// The 26 chunks sharing a face, an edge or a corner with it.
+    All,
+}
+
+impl Default for Neighborhood {
+    fn default() -> Self {
+        Self::Faces
+    }
+}
+
+impl Neighborhood {
+    /// The offsets of the neighbors in chunks.
+    pub fn offsets(self) -> impl Iterator<Item = (i32, i32, i32)> {
+        (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| (x, y, z))))
+            .filter(move |&(x, y, z): &(i32, i32, i32)| {
+                let axes = x.abs() + y.abs() + z.abs();
+                axes != 0 && (self == Neighborhood::All || axes == 1)
+            })
+    }
+}
+
+/// A chunk was inserted into a map whose chunks have a different width.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkWidthError {
+    pub position: (i32, i32, i32),
+    /// The width of the map's chunks, or with a mixed layout the width of the chunk that the
+    /// inserted chunk overlaps.
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for ChunkWidthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk {:?} is {} voxels wide, but the map's chunks are {} voxels wide",
+            self.position, self.found, self.expected
+        )
+    }
+}
+
+impl Error for ChunkWidthError {}
+
+/// The map represents visible chunks.
+///
+/// All chunks of a map have the same width. It's taken from the first chunk inserted, unless
+/// the map was created with `with_layout`. Chunks are aligned to multiples of their width, so
+/// that the chunk containing a voxel is found with a single hash lookup. Range queries go
+/// through an `RTree` of the chunk bounds.
+///
+/// Maps created with a `MapLayout::mixed` layout take chunks of different widths, as long as
+/// they don't overlap. Finding the chunk containing a voxel then takes a lookup for every
+/// width, and the neighbours of a chunk are all the chunks touching it.
 #[derive(Default, Debug, Clone)]
-pub struct MapUpdates {
-    pub updates: HashMap<(i32, i32, i32), ChunkUpdate>,
+pub struct Map<T: Voxel> {
+    chunks: HashMap<(i32, i32, i32), Chunk<T>>,
+    bounds: RTree<ChunkBounds>,
+    layout: Option<MapLayout>,
+    light_precision: LightPrecision,
+    overlays: HashMap<u32, OverlayLayer<T>>,
+    track_changes: bool,
+    /// The voxels changed since `drain_changes` was last called, if `track_changes` is set.
+    changes: Vec<BlockChanged<T>>,
+    /// The epoch of the next chunk inserted, see `Chunk::epoch`.
+    next_epoch: u64,
 }
 
-#[derive(Default, Bundle)]
-pub struct MapComponents {
-    pub map_update: MapUpdates,
+impl<T: Voxel> Map<T> {
+    pub fn new() -> Self {
+        Self {
+            chunks: HashMap::new(),
+            bounds: RTree::new(),
+            layout: None,
+            light_precision: LightPrecision::default(),
+            overlays: HashMap::new(),
+            track_changes: false,
+            changes: Vec::new(),
+            next_epoch: 0,
+        }
+    }
+
+    pub fn with_layout(layout: MapLayout) -> Self {
+        Self {
+            layout: Some(layout),
+            ..Self::new()
+        }
+    }
+
+    /// Creates a map from `initial`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunks don't all have the same width.
+    #[deprecated(note = "use `try_with_chunks`, which returns the width mismatch instead")]
+    pub fn with_chunks(initial: Vec<Chunk<T>>) -> Self {
+        match Self::try_with_chunks(initial) {
+            Ok(map) => map,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a map from `initial`, with a uniform layout for the width of the first chunk.
+    /// Fails if the chunks don't all have the same width, use `with_layout` and
+    /// `try_insert_many` for a mixed layout.
+    pub fn try_with_chunks(mut initial: Vec<Chunk<T>>) -> Result<Self, ChunkWidthError> {
+        let layout = initial
+            .first()
+            .map(|chunk| MapLayout::uniform(chunk.width()));
+        if let Some(layout) = layout {
+            for chunk in &initial {
+                Self::check_width(layout, chunk)?;
+            }
+        }
+        for (epoch, chunk) in initial.iter_mut().enumerate() {
+            chunk.epoch = epoch as u64;
+        }
+        let next_epoch = initial.len() as u64;
+        let bounds = initial
+            .iter()
+            .map(|chunk| ChunkBounds {
+                position: chunk.position(),
+                width: chunk.width(),
+            })
+            .collect();
+        Ok(Self {
+            chunks: initial
+                .into_iter()
+                .map(|chunk| (chunk.position(), chunk))
+                .collect(),
+            bounds: RTree::bulk_load(bounds),
+            layout,
+            light_precision: LightPrecision::default(),
+            overlays: HashMap::new(),
+            track_changes: false,
+            changes: Vec::new(),
+            next_epoch,
+        })
+    }
+
+    /// Returns the layout of the map, or `None` if it has no layout yet because no chunks
+    /// were inserted.
+    pub fn layout(&self) -> Option<MapLayout> {
+        self.layout
+    }
+
+    pub fn light_precision(&self) -> LightPrecision {
+        self.light_precision
+    }
+
+    /// Stores the light maps of all chunks, including the ones inserted later, with
+    /// `precision`.
+    pub fn set_light_precision(&mut self, precision: LightPrecision) {
+        self.light_precision = precision;
+        for chunk in self.chunks.values_mut() {
+            chunk.set_light_precision(precision);
+        }
+    }
+
+    pub fn track_changes(&self) -> bool {
+        self.track_changes
+    }
+
+    /// Records a `BlockChanged` for every voxel changed through the map from now on, until
+    /// it's turned off again. Changes are kept until `drain_changes` is called, which
+    /// `block_changed_update` does every frame.
+    pub fn set_track_changes(&mut self, track: bool) {
+        self.track_changes = track;
+        if !track {
+            self.changes.clear();
+        }
+    }
+
+    /// Records `change` if `track_changes` is set and the voxel really changed, for changes
+    /// made outside of the map, e.g. by `GenerationHooks`.
+    pub(crate) fn record_change(&mut self, change: BlockChanged<T>) {
+        if self.track_changes && change.old != change.new {
+            self.changes.push(change);
+        }
+    }
+
+    /// Removes the changes recorded since the last call and returns them, oldest first.
+    pub fn drain_changes(&mut self) -> Vec<BlockChanged<T>> {
+        mem::take(&mut self.changes)
+    }
+
+    /// Adds an overlay layer with id `id`, replacing the layer with that id if there is one.
+    pub fn insert_overlay(&mut self, id: u32, layer: OverlayLayer<T>) -> Option<OverlayLayer<T>> {
+        self.overlays.insert(id, layer)
+    }
+
+    pub fn remove_overlay(&mut self, id: u32) -> Option<OverlayLayer<T>> {
+        self.overlays.remove(&id)
+    }
+
+    pub fn overlay(&self, id: u32) -> Option<&OverlayLayer<T>> {
+        self.overlays.get(&id)
+    }
+
+    pub fn overlay_mut(&mut self, id: u32) -> Option<&mut OverlayLayer<T>> {
+        self.overlays.get_mut(&id)
+    }
+
+    pub fn overlays(&self) -> impl Iterator<Item = (u32, &OverlayLayer<T>)> {
+        self.overlays.iter().map(|(&id, layer)| (id, layer))
+    }
+
+    pub fn overlays_mut(&mut self) -> impl Iterator<Item = (u32, &mut OverlayLayer<T>)> {
+        self.overlays.iter_mut().map(|(&id, layer)| (id, layer))
+    }
+
+    fn check_width(layout: MapLayout, chunk: &Chunk<T>) -> Result<(), ChunkWidthError> {
+        if !layout.allows(chunk.width()) {
+            return Err(ChunkWidthError {
+                position: chunk.position(),
+                expected: layout.chunk_width,
+                found: chunk.width(),
+            });
+        }
+        debug_assert_eq!(
+            MapLayout::origin(chunk.position(), chunk.width()),
+            chunk.position(),
+            "chunk isn't aligned to the map's layout"
+        );
+        Ok(())
+    }
+
+    /// Checks that `chunk` doesn't overlap a chunk of a different width, which only happens
+    /// with a mixed layout.
+    fn check_overlap(&self, chunk: &Chunk<T>) -> Result<(), ChunkWidthError> {
+        let (x, y, z) = chunk.position();
+        let last = chunk.width() as i32 - 1;
+        let overlapping = self
+            .chunks_in((x, y, z), (x + last, y + last, z + last))
+            .find(|other| other.width() != chunk.width());
+        match overlapping {
+            Some(other) => Err(ChunkWidthError {
+                position: chunk.position(),
+                expected: other.width(),
+                found: chunk.width(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the chunk containing the voxel at world coordinates `coords`, which don't have
+    /// to be the origin of the chunk, see `chunk_containing`.
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<&Chunk<T>> {
+        self.chunk_containing(coords)
+    }
+
+    pub fn get_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut Chunk<T>> {
+        self.chunk_containing_mut(coords)
+    }
+
+    /// Returns the chunk whose origin is exactly at `position`, e.g. for positions taken from
+    /// `MapUpdates`. Unlike `get` it doesn't look for a larger chunk covering `position`.
+    pub fn get_at_origin(&self, position: (i32, i32, i32)) -> Option<&Chunk<T>> {
+        self.chunks.get(&position)
+    }
+
+    pub fn get_at_origin_mut(&mut self, position: (i32, i32, i32)) -> Option<&mut Chunk<T>> {
+        self.chunks.get_mut(&position)
+    }
+
+    /// Returns the chunk containing the voxel at world coordinates `coords`.
+    pub fn chunk_containing(&self, coords: (i32, i32, i32)) -> Option<&Chunk<T>> {
+        let layout = self.layout?;
+        if !layout.is_mixed() {
+            return self.chunks.get(&layout.chunk_origin(coords));
+        }
+        layout.widths().find_map(|width| {
+            let origin = MapLayout::origin(coords, width);
+            self.chunks
+                .get(&origin)
+                .filter(|chunk| chunk.width() == width)
+        })
+    }
+
+    pub fn chunk_containing_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut Chunk<T>> {
+        let layout = self.layout?;
+        let origin = if layout.is_mixed() {
+            self.chunk_containing(coords)?.position()
+        } else {
+            layout.chunk_origin(coords)
+        };
+        self.chunks.get_mut(&origin)
+    }
+
+    /// Iterates over the loaded chunks next to the chunk at `position`, with their offsets in
+    /// chunks, e.g. `(1, 0, 0)` for the chunk one chunk width along x.
+    ///
+    /// With a mixed layout, these are the chunks touching the cells of the chunk's width next
+    /// to it, so several smaller chunks may share an offset. A larger chunk is only returned
+    /// once, with the first offset it touches.
+    pub fn neighbors(
+        &self,
+        position: (i32, i32, i32),
+        neighborhood: Neighborhood,
+    ) -> impl Iterator<Item = ((i32, i32, i32), &Chunk<T>)> {
+        let positions = match self.layout {
+            Some(layout) if layout.is_mixed() => {
+                Either::Right(self.mixed_neighbors(position, neighborhood).into_iter())
+            }
+            layout => Either::Left(
+                layout
+                    .into_iter()
+                    .flat_map(move |layout| layout.neighbors(position, neighborhood)),
+            ),
+        };
+        positions.filter_map(move |(offset, position)| Some((offset, self.chunks.get(&position)?)))
+    }
+
+    /// Returns the offsets and positions of the chunks next to the chunk at `position` in a
+    /// mixed layout, see `neighbors`.
+    fn mixed_neighbors(
+        &self,
+        (x, y, z): (i32, i32, i32),
+        neighborhood: Neighborhood,
+    ) -> Vec<((i32, i32, i32), (i32, i32, i32))> {
+        let width = match self.chunks.get(&(x, y, z)) {
+            Some(chunk) => chunk.width() as i32,
+            None => return Vec::new(),
+        };
+        let mut neighbors = Vec::new();
+        for (dx, dy, dz) in neighborhood.offsets() {
+            let min = (x + dx * width, y + dy * width, z + dz * width);
+            let max = (min.0 + width - 1, min.1 + width - 1, min.2 + width - 1);
+            for chunk in self.chunks_in(min, max) {
+                let position = chunk.position();
+                if !neighbors.iter().any(|&(_, other)| other == position) {
+                    neighbors.push(((dx, dy, dz), position));
+                }
+            }
+        }
+        neighbors
+    }
+
+    /// Calls `f` with every loaded chunk next to the chunk at `position` and its offset.
+    pub fn for_each_neighbor_mut<F>(
+        &mut self,
+        position: (i32, i32, i32),
+        neighborhood: Neighborhood,
+        mut f: F,
+    ) where
+        F: FnMut((i32, i32, i32), &mut Chunk<T>),
+    {
+        let layout = if let Some(layout) = self.layout {
+            layout
+        } else {
+            return;
+        };
+        let neighbors = if layout.is_mixed() {
+            self.mixed_neighbors(position, neighborhood)
+        } else {
+            layout.neighbors(position, neighborhood).collect::<Vec<_>>()
+        };
+        for (offset, position) in neighbors {
+            if let Some(chunk) = self.chunks.get_mut(&position) {
+                f(offset, chunk);
+            }
+        }
+    }
+
+    /// Iterates over the chunks intersecting the box from `min` to `max`, both inclusive, in
+    /// world coordinates.
+    pub fn chunks_in(
+        &self,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+    ) -> impl Iterator<Item = &'_ Chunk<T>> {
+        let envelope = AABB::from_corners([min.0, min.1, min.2], [max.0, max.1, max.2]);
+        self.bounds
+            .locate_in_envelope_intersecting(&envelope)
+            .filter_map(move |bounds| self.chunks.get(&bounds.position))
+    }
+
+    /// Iterates over the chunks with at least one voxel within `radius` voxels of `center`,
+    /// in world coordinates, e.g. for systems that only care about the chunks near a player.
+    pub fn iter_within(
+        &self,
+        center: (i32, i32, i32),
+        radius: i32,
+    ) -> impl Iterator<Item = &'_ Chunk<T>> {
+        let (x, y, z) = center;
+        self.bounds
+            .locate_within_distance([x, y, z], radius.saturating_mul(radius))
+            .filter_map(move |bounds| self.chunks.get(&bounds.position))
+    }
+
+    /// Iterates over the chunks at level of detail `lod`.
+    ///
+    /// The level of detail of a chunk changes without the map noticing, so unlike
+    /// `iter_within` this checks every chunk.
+    pub fn iter_by_lod(&self, lod: usize) -> impl Iterator<Item = &'_ Chunk<T>> {
+        self.iter().filter(move |chunk| chunk.lod() == lod)
+    }
+
+    /// Inserts a chunk, replacing the chunk at the same position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk's width doesn't match the map's layout.
+    #[deprecated(note = "use `try_insert`, which returns the width mismatch instead")]
+    pub fn insert(&mut self, value: Chunk<T>) {
+        if let Err(e) = self.try_insert(value) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Inserts a chunk, replacing the chunk at the same position. Fails if the chunk's width
+    /// doesn't match the map's layout, or if it overlaps a chunk of a different width.
+    pub fn try_insert(&mut self, mut value: Chunk<T>) -> Result<(), ChunkWidthError> {
+        value.set_light_precision(self.light_precision);
+        let layout = *self
+            .layout
+            .get_or_insert_with(|| MapLayout::uniform(value.width()));
+        Self::check_width(layout, &value)?;
+        if layout.is_mixed() {
+            self.check_overlap(&value)?;
+        }
+        value.epoch = self.next_epoch();
+        let bounds = ChunkBounds {
+            position: value.position(),
+            width: value.width(),
+        };
+        if self.chunks.insert(value.position(), value).is_none() {
+            self.bounds.insert(bounds);
+        }
+        Ok(())
+    }
+
+    fn next_epoch(&mut self) -> u64 {
+        self.next_epoch += 1;
+        self.next_epoch - 1
+    }
+
+    /// Inserts many chunks at once, see `try_insert_many`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width of any chunk doesn't match the map's layout.
+    #[deprecated(note = "use `try_insert_many`, which returns the width mismatch instead")]
+    pub fn insert_many<I: IntoIterator<Item = Chunk<T>>>(&mut self, chunks: I) {
+        if let Err(e) = self.try_insert_many(chunks) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Inserts many chunks at once, e.g. everything generated in one frame. The bounds used
+    /// for range queries are rebuilt in bulk when the batch is large compared to the map,
+    /// which keeps them balanced better than inserting the chunks one at a time.
+    ///
+    /// Fails before inserting any chunk if the width of one doesn't match the map's layout.
+    /// With a mixed layout the chunks are inserted one at a time, and it stops at the first
+    /// chunk overlapping a chunk of a different width.
+    pub fn try_insert_many<I: IntoIterator<Item = Chunk<T>>>(
+        &mut self,
+        chunks: I,
+    ) -> Result<(), ChunkWidthError> {
+        let chunks = chunks.into_iter().collect::<Vec<_>>();
+        let layout = match (self.layout, chunks.first()) {
+            (Some(layout), _) => layout,
+            (None, Some(first)) => MapLayout::uniform(first.width()),
+            (None, None) => return Ok(()),
+        };
+        for chunk in &chunks {
+            Self::check_width(layout, chunk)?;
+        }
+        self.layout = Some(layout);
+
+        if layout.is_mixed() || chunks.len() * 4 < self.chunks.len() {
+            for chunk in chunks {
+                self.try_insert(chunk)?;
+            }
+            return Ok(());
+        }
+        for mut chunk in chunks {
+            chunk.epoch = self.next_epoch();
+            self.chunks.insert(chunk.position(), chunk);
+        }
+        let bounds = self
+            .chunks
+            .values()
+            .map(|chunk| ChunkBounds {
+                position: chunk.position(),
+                width: chunk.width(),
+            })
+            .collect();
+        self.bounds = RTree::bulk_load(bounds);
+        Ok(())
+    }
+
+    /// Removes every chunk and overlay and despawns the render entities of the chunks, e.g.
+    /// before going back to the main menu. The layout and light precision are kept.
+    ///
+    /// The meshes of the chunks stay in `Assets<Mesh>`, `world_reset_update` frees them too.
+    #[cfg(feature = "bevy")]
+    pub fn clear(&mut self, commands: &mut Commands) {
+        for (_, chunk) in self.chunks.drain() {
+            for e in chunk.entity().into_iter().chain(chunk.transparent_entity()) {
+                commands.despawn(e);
+            }
+        }
+        self.bounds = RTree::new();
+        self.overlays.clear();
+    }
+
+    pub fn remove(&mut self, coords: (i32, i32, i32)) -> Option<Chunk<T>> {
+        let chunk = self.chunks.remove(&coords)?;
+        self.bounds.remove(&ChunkBounds {
+            position: coords,
+            width: chunk.width(),
+        });
+        Some(chunk)
+    }
+
+    /// Replaces the smaller chunks covering the cube `width` voxels wide at `position` with a
+    /// single chunk holding their voxels, e.g. once a region of sky turned out to be empty.
+    /// Needs a mixed layout that allows `width`.
+    ///
+    /// Returns the replaced chunks, so that their render entities can be despawned, or `None`
+    /// if the cube isn't covered by loaded chunks smaller than `width`. The new chunk is
+    /// generated, but has to be lit and meshed again.
+    pub fn coarsen(&mut self, position: (i32, i32, i32), width: usize) -> Option<Vec<Chunk<T>>> {
+        let layout = self.layout?;
+        if !layout.is_mixed()
+            || !layout.allows(width)
+            || MapLayout::origin(position, width) != position
+            || !self.is_covered(position, width as i32)
+        {
+            return None;
+        }
+        let (x, y, z) = position;
+        let last = width as i32 - 1;
+        let mut positions = Vec::new();
+        for chunk in self.chunks_in(position, (x + last, y + last, z + last)) {
+            if chunk.width() >= width {
+                return None;
+            }
+            positions.push(chunk.position());
+        }
+        let replaced = positions
+            .into_iter()
+            .filter_map(|position| self.remove(position))
+            .collect::<Vec<_>>();
+        let mut chunk = Chunk::new(width.trailing_zeros(), position);
+        for old in &replaced {
+            chunk.copy_overlap(old);
+        }
+        chunk.merge();
+        let inserted = self.try_insert(chunk);
+        debug_assert!(inserted.is_ok(), "coarsened chunk doesn't fit");
+        Some(replaced)
+    }
+
+    /// Splits the chunk at `position` into chunks of the layout's `chunk_width`, e.g. before
+    /// the player starts editing a coarse chunk.
+    ///
+    /// Returns the removed chunk, so that its render entities can be despawned, or `None` if
+    /// there is no chunk at `position` or it's as small as chunks get. The new chunks are
+    /// generated, but have to be lit and meshed again.
+    pub fn split(&mut self, position: (i32, i32, i32)) -> Option<Chunk<T>> {
+        let layout = self.layout?;
+        if self.get_at_origin(position)?.width() <= layout.chunk_width {
+            return None;
+        }
+        let chunk = self.remove(position)?;
+        let (x, y, z) = position;
+        let step = layout.chunk_width as i32;
+        let count = (chunk.width() / layout.chunk_width) as i32;
+        let size = layout.chunk_width.trailing_zeros();
+        let mut parts = Vec::new();
+        for i in 0..count {
+            for j in 0..count {
+                for k in 0..count {
+                    let mut part = Chunk::new(size, (x + i * step, y + j * step, z + k * step));
+                    part.copy_overlap(&chunk);
+                    part.merge();
+                    parts.push(part);
+                }
+            }
+        }
+        let inserted = self.try_insert_many(parts);
+        debug_assert!(inserted.is_ok(), "split chunk doesn't fit");
+        Some(chunk)
+    }
+
+    /// Sets or clears the voxel at world coordinates `coords` and schedules the chunks whose
+    /// meshes are affected by the edit.
+    ///
+    /// The edited chunk gets its light map updated, while neighbouring chunks touching the
+    /// voxel with one of their faces only need a new mesh. Returns `false` if the voxel lies
+    /// in a chunk that isn't loaded. Use `batch` to set many voxels at once.
+    pub fn set_voxel(
+        &mut self,
+        coords: (i32, i32, i32),
+        voxel: Option<T>,
+        updates: &mut MapUpdates,
+    ) -> bool {
+        self.set_voxel_because(coords, voxel, updates, ChangeCause::Edit)
+    }
+
+    /// Like `set_voxel`, but records the change with `cause` if `track_changes` is set.
+    pub fn set_voxel_because(
+        &mut self,
+        coords: (i32, i32, i32),
+        voxel: Option<T>,
+        updates: &mut MapUpdates,
+        cause: ChangeCause,
+    ) -> bool {
+        let (position, neighbors) = match self.write_voxel(coords, voxel, cause) {
+            Some(written) => written,
+            None => return false,
+        };
+        updates.request_because(position, ChunkUpdate::UpdateLightMap, UpdateCause::Edit);
+        for coords in neighbors {
+            if let Some(chunk) = self.get_at_origin_mut(coords) {
+                chunk.set_visibility(None);
+                // neighbours that weren't lit yet have to be lit before they're meshed
+                let stage =
+                    restart_stage(&updates.pipeline, chunk.state(), ChunkUpdate::UpdateMesh);
+                updates.request_because(coords, stage, UpdateCause::Dependency);
+            }
+        }
+        true
+    }
+
+    /// Sets or clears the voxel at world coordinates `coords` without scheduling any updates.
+    /// Returns the position of the edited chunk and of the neighbours whose meshes touch the
+    /// voxel, or `None` if the voxel lies in a chunk that isn't loaded.
+    fn write_voxel(
+        &mut self,
+        (x, y, z): (i32, i32, i32),
+        voxel: Option<T>,
+        cause: ChangeCause,
+    ) -> Option<((i32, i32, i32), Vec<(i32, i32, i32)>)> {
+        let track_changes = self.track_changes;
+        let chunk = self.chunk_containing_mut((x, y, z))?;
+        let (cx, cy, cz) = chunk.position();
+        let width = chunk.width() as i32;
+        let local = (x - cx, y - cy, z - cz);
+        let change = if track_changes {
+            let old = chunk.get(local).map(Cow::into_owned);
+            Some(BlockChanged {
+                coords: (x, y, z),
+                old,
+                new: voxel.clone(),
+                cause,
+            })
+        } else {
+            None
+        };
+        match voxel {
+            Some(voxel) => chunk.insert(local, voxel),
+            None => chunk.remove(local),
+        }
+        chunk.edited = true;
+
+        let (lx, ly, lz) = local;
+        // the voxels across the faces of the chunk, whose chunks may have other widths
+        let mut across = Vec::new();
+        if lx == 0 {
+            across.push((x - 1, y, z));
+        }
+        if lx == width - 1 {
+            across.push((x + 1, y, z));
+        }
+        if ly == 0 {
+            across.push((x, y - 1, z));
+        }
+        if ly == width - 1 {
+            across.push((x, y + 1, z));
+        }
+        if lz == 0 {
+            across.push((x, y, z - 1));
+        }
+        if lz == width - 1 {
+            across.push((x, y, z + 1));
+        }
+        let mut neighbors = Vec::new();
+        for coords in across {
+            let neighbor = match self.layout {
+                Some(layout) if layout.is_mixed() => {
+                    self.chunk_containing(coords).map(|chunk| chunk.position())
+                }
+                _ => self.layout.map(|layout| layout.chunk_origin(coords)),
+            };
+            neighbors.extend(neighbor.filter(|n| !neighbors.contains(n)));
+        }
+        if let Some(change) = change.filter(|change| change.old != change.new) {
+            self.changes.push(change);
+        }
+        Some(((cx, cy, cz), neighbors))
+    }
+
+    /// Returns the voxel at world coordinates `coords` at full detail.
+    pub fn voxel(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        let chunk = self.chunk_containing(coords)?;
+        chunk.get(chunk.to_local(coords))
+    }
+
+    /// Returns the biome the generator picked for the column at world coordinates `coords`.
+    pub fn biome(&self, coords: (i32, i32, i32)) -> Option<usize> {
+        let chunk = self.chunk_containing(coords)?;
+        let meta = chunk.meta()?;
+        // there is one biome per unit column, which may be wider than a voxel
+        let units = (meta.biomes.len() as f64).sqrt() as usize;
+        if units == 0 {
+            return None;
+        }
+        let unit_width = (chunk.width() / units).max(1) as i32;
+        let (x, _, z) = chunk.to_local(coords);
+        meta.biome(units, (x / unit_width, z / unit_width))
+    }
+
+    /// Returns how the generated water at world coordinates `coords` flows, e.g. to push
+    /// entities swimming in it, or `None` if there is no water there.
+    pub fn flow(&self, coords: (i32, i32, i32)) -> Option<Flow> {
+        let chunk = self.chunk_containing(coords)?;
+        let (x, _, z) = chunk.to_local(coords);
+        let flow = chunk.meta()?.flow(chunk.width(), (x, z))?;
+        flow.depth(coords.1).map(|_| flow)
+    }
+
+    /// Captures the light around the chunk at `coords` from its lit neighbours. Voxels of
+    /// neighbours that aren't loaded keep the chunk's current `BorderLight`, and `None` is
+    /// returned if nothing is known about any of them.
+    pub fn capture_border_light(&self, coords: (i32, i32, i32)) -> Option<BorderLight> {
+        let chunk = self.get_at_origin(coords)?;
+        let mut known = chunk.border_light().is_some();
+        let mut border = chunk
+            .border_light()
+            .cloned()
+            .unwrap_or_else(|| BorderLight::new(chunk.width()));
+        let (cx, cy, cz) = coords;
+        for (x, y, z) in BorderLight::coords(chunk.width()) {
+            let world = (cx + x, cy + y, cz + z);
+            let neighbour = self.chunk_containing(world).filter(|n| n.has_light());
+            if let Some(light) = neighbour.and_then(|n| n.light(n.to_local(world))) {
+                border.set((x, y, z), light);
+                known = true;
+            }
+        }
+        if known {
+            Some(border)
+        } else {
+            None
+        }
+    }
+
+    /// Relights the lit neighbours of the chunk at `coords` that were shaded with their
+    /// `BorderLight`, e.g. once the chunk was loaded and got its light map. Neighbours whose
+    /// surroundings are all loaded don't need their border light anymore and drop it.
+    pub fn relight_border(&mut self, coords: (i32, i32, i32), updates: &mut MapUpdates) {
+        if self.get_at_origin(coords).is_none() {
+            return;
+        }
+        let lit = self
+            .neighbors(coords, Neighborhood::All)
+            .filter(|(_, chunk)| chunk.border_light().is_some() && chunk.state() >= ChunkState::Lit)
+            .map(|(_, chunk)| (chunk.position(), chunk.width() as i32))
+            .collect::<Vec<_>>();
+        for (neighbour, width) in lit {
+            let (nx, ny, nz) = neighbour;
+            let surrounded = neighbour_offsets().all(|(x, y, z)| {
+                self.is_covered((nx + x * width, ny + y * width, nz + z * width), width)
+            });
+            if surrounded {
+                if let Some(chunk) = self.get_at_origin_mut(neighbour) {
+                    chunk.set_border_light(None);
+                }
+            }
+            updates.request_because(neighbour, ChunkUpdate::UpdateLight, UpdateCause::Dependency);
+        }
+    }
+
+    /// Returns whether the cube `width` voxels wide at `min` is covered by loaded chunks.
+    fn is_covered(&self, min: (i32, i32, i32), width: i32) -> bool {
+        match self.layout {
+            Some(layout) if layout.is_mixed() => {}
+            _ => return self.get_at_origin(min).is_some(),
+        }
+        let (x, y, z) = min;
+        let max = (x + width, y + width, z + width);
+        let volume = self
+            .chunks_in(min, (max.0 - 1, max.1 - 1, max.2 - 1))
+            .map(|chunk| {
+                let (cx, cy, cz) = chunk.position();
+                let w = chunk.width() as i32;
+                let overlap = |from: i32, to: i32, c: i32| (to.min(c + w) - from.max(c)) as i64;
+                overlap(x, max.0, cx) * overlap(y, max.1, cy) * overlap(z, max.2, cz)
+            })
+            .sum::<i64>();
+        volume == (width as i64).pow(3)
+    }
+
+    /// Sends the chunk at `coords` back to `stage` of the pipeline to refresh it, together with
+    /// the neighbours that depend on what `stage` produces. Returns `false` if the chunk isn't
+    /// loaded.
+    ///
+    /// A new light map also relights the neighbours that are lit already, since their shading
+    /// reads it. Regenerating a chunk throws away its edits, and the generator takes care of
+    /// the neighbours. Chunks that haven't made it to `stage` yet restart from the stage they
+    /// are missing.
+    pub fn invalidate(
+        &mut self,
+        coords: (i32, i32, i32),
+        stage: ChunkUpdate,
+        updates: &mut MapUpdates,
+    ) -> bool {
+        let chunk = if let Some(chunk) = self.get_at_origin_mut(coords) {
+            chunk
+        } else {
+            return false;
+        };
+        chunk.set_visibility(None);
+        let restart = restart_stage(&updates.pipeline, chunk.state(), stage);
+        updates.request_because(coords, restart.clone(), UpdateCause::Invalidation);
+
+        if restart == ChunkUpdate::UpdateLightMap {
+            let lit = self
+                .neighbors(coords, Neighborhood::All)
+                .filter(|(_, chunk)| chunk.state() >= ChunkState::LightMapped)
+                .map(|(_, chunk)| chunk.position())
+                .collect::<Vec<_>>();
+            for neighbour in lit {
+                updates.request_because(
+                    neighbour,
+                    ChunkUpdate::UpdateLight,
+                    UpdateCause::Dependency,
+                );
+            }
+        }
+        true
+    }
+
+    /// Sends every loaded chunk back to `stage` of the pipeline, see `invalidate`. Unlike
+    /// `MapUpdates::invalidate_all_lighting` this requests all chunks at once.
+    pub fn invalidate_all(&mut self, stage: ChunkUpdate, updates: &mut MapUpdates) {
+        for chunk in self.chunks.values_mut() {
+            chunk.set_visibility(None);
+            let restart = restart_stage(&updates.pipeline, chunk.state(), stage.clone());
+            updates.request_because(chunk.position(), restart, UpdateCause::Invalidation);
+        }
+    }
+
+    /// Returns the height of the topmost voxel in the column at `(x, z)` among the loaded
+    /// chunks.
+    pub fn surface_height(&self, (x, z): (i32, i32)) -> Option<i32> {
+        let mut chunks = self
+            .chunks_in((x, i32::MIN, z), (x, i32::MAX, z))
+            .collect::<Vec<_>>();
+        chunks.sort_by_key(|chunk| -chunk.position().1);
+        for chunk in chunks {
+            let (cx, cy, cz) = chunk.position();
+            for y in (0..chunk.width() as i32).rev() {
+                if chunk.get((x - cx, y, z - cz)).is_some() {
+                    return Some(cy + y);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the block data of the voxel at world coordinates `coords`.
+    pub fn block_data(&self, coords: (i32, i32, i32)) -> Option<&BlockData> {
+        let chunk = self.chunk_containing(coords)?;
+        chunk.block_data(chunk.to_local(coords))
+    }
+
+    /// Attaches `data` to the voxel at world coordinates `coords`. Returns `false` if there
+    /// is no voxel there.
+    pub fn set_block_data(&mut self, coords: (i32, i32, i32), data: BlockData) -> bool {
+        if let Some(chunk) = self.chunk_containing_mut(coords) {
+            let local = chunk.to_local(coords);
+            chunk.set_block_data(local, data)
+        } else {
+            false
+        }
+    }
+
+    /// Moves the voxel at `from` to `to` together with its block data, replacing whatever
+    /// was at `to`. Returns `false` and leaves `to` alone if either position lies in a chunk
+    /// that isn't loaded or there is no voxel at `from`.
+    pub fn move_voxel(
+        &mut self,
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+        updates: &mut MapUpdates,
+    ) -> bool {
+        if self.chunk_containing(to).is_none() {
+            return false;
+        }
+        let chunk = if let Some(chunk) = self.chunk_containing_mut(from) {
+            chunk
+        } else {
+            return false;
+        };
+        let local = chunk.to_local(from);
+        let voxel = if let Some(voxel) = chunk.get(local) {
+            voxel.into_owned()
+        } else {
+            return false;
+        };
+        if from == to {
+            return true;
+        }
+        let data = chunk.remove_block_data(local);
+
+        self.set_voxel_because(from, None, updates, ChangeCause::Move);
+        self.set_voxel_because(to, Some(voxel), updates, ChangeCause::Move);
+        if let Some(data) = data {
+            self.set_block_data(to, data);
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'_ Chunk<T>> {
+        self.chunks.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &'_ mut Chunk<T>> {
+        self.chunks.values_mut()
+    }
+
+    /// Iterates over all generated structures in the map, in world coordinates.
+    pub fn structures(&self) -> impl Iterator<Item = Structure> + '_ {
+        self.iter().flat_map(|chunk| {
+            let (cx, cy, cz) = chunk.position();
+            chunk
+                .meta()
+                .into_iter()
+                .flat_map(|meta| meta.structures.iter())
+                .map(move |structure| Structure {
+                    position: (
+                        cx + structure.position.0,
+                        cy + structure.position.1,
+                        cz + structure.position.2,
+                    ),
+                    size: structure.size,
+                })
+        })
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: Voxel + Serialize + DeserializeOwned> Map<T> {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(&self, save_directory: P) -> bincode::Result<()> {
+        self.save_with_progress(save_directory, &IoProgress::new())
+    }
+
+    /// Like `save`, but reports every written chunk to `progress` and stops early when it
+    /// gets cancelled.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_with_progress<P: AsRef<Path>>(
+        &self,
+        save_directory: P,
+        progress: &IoProgress,
+    ) -> bincode::Result<()> {
+        self.save_with_options(save_directory, &SaveOptions::default(), progress)
+    }
+
+    /// Like `save_with_progress`, but keeps the backups and uses the compression configured
+    /// in `options`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        save_directory: P,
+        options: &SaveOptions,
+        progress: &IoProgress,
+    ) -> bincode::Result<()> {
+        let backend = FileBackend::new(save_directory).with_backups(options.backups);
+        self.save_to_with_options(&backend, options, progress)
+    }
+
+    /// Like `save_with_options`, but writes to `backend`, which is left to keep its own
+    /// backups.
+    pub fn save_to_with_options(
+        &self,
+        backend: &dyn SaveBackend,
+        options: &SaveOptions,
+        progress: &IoProgress,
+    ) -> bincode::Result<()> {
+        self.write_chunks(backend, options.compression, progress, |chunk| {
+            Ok(serializable(chunk, options.thinning))
+        })
+    }
+
+    /// Writes every chunk to `backend`.
+    pub fn save_to(&self, backend: &dyn SaveBackend, progress: &IoProgress) -> bincode::Result<()> {
+        self.save_compressed(backend, Compression::default(), progress)
+    }
+
+    /// Like `save_to`, but compresses the chunks with `compression`.
+    pub fn save_compressed(
+        &self,
+        backend: &dyn SaveBackend,
+        compression: Compression,
+        progress: &IoProgress,
+    ) -> bincode::Result<()> {
+        self.write_chunks(backend, compression, progress, |chunk| {
+            Ok(chunk.serializable())
+        })
+    }
+
+    fn write_chunks<F>(
+        &self,
+        backend: &dyn SaveBackend,
+        compression: Compression,
+        progress: &IoProgress,
+        mut serialize: F,
+    ) -> bincode::Result<()>
+    where
+        F: FnMut(&Chunk<T>) -> bincode::Result<SaveData<T>>,
+    {
+        let codec = self.codec(backend, compression)?;
+        progress.set_total(self.len());
+        let mut chunks = self.iter();
+        let mut encoded = Vec::new();
+        loop {
+            if progress.is_cancelled() {
+                break;
+            }
+            let batch = chunks
+                .by_ref()
+                .take(SAVE_BATCH)
+                .map(|chunk| {
+                    let mut save = serialize(chunk)?;
+                    save.border_light = self.capture_border_light(chunk.position());
+                    Ok(save)
+                })
+                .collect::<bincode::Result<Vec<_>>>()?;
+            if batch.is_empty() && encoded.is_empty() {
+                break;
+            }
+            // the last batch is written while the next one is compressed, so at most two
+            // batches are held in memory however slow the backend is
+            let (written, next) = join(
+                || write_encoded(backend, &encoded, progress),
+                || codec.encode_batch(batch, progress),
+            );
+            written?;
+            encoded = next?;
+        }
+        Ok(())
+    }
+
+    /// Hashes the content and positions of all chunks intersecting the region from `min` to
+    /// `max`, both inclusive, in world coordinates, e.g. to check that a client's copy of the
+    /// region is in sync.
+    pub fn region_hash(&self, min: (i32, i32, i32), max: (i32, i32, i32)) -> u64 {
+        let mut chunks = self.chunks_in(min, max).collect::<Vec<_>>();
+        chunks.sort_by_key(|chunk| chunk.position());
+
+        let mut hasher = ContentHasher::new();
+        for chunk in chunks {
+            let (x, y, z) = chunk.position();
+            hasher.update(&x.to_le_bytes());
+            hasher.update(&y.to_le_bytes());
+            hasher.update(&z.to_le_bytes());
+            hasher.update(&chunk.content_hash().to_le_bytes());
+        }
+        hasher.finish()
+    }
+
+    /// Saves only the differences between each chunk and the chunk `baseline` returns for
+    /// its position, usually the output of the terrain generator.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_diff<P, F>(&self, save_directory: P, baseline: F) -> bincode::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut((i32, i32, i32)) -> Chunk<T>,
+    {
+        self.save_diff_to(&FileBackend::new(save_directory), baseline)
+    }
+
+    /// Like `save_diff`, but writes to `backend`.
+    pub fn save_diff_to<F>(&self, backend: &dyn SaveBackend, mut baseline: F) -> bincode::Result<()>
+    where
+        F: FnMut((i32, i32, i32)) -> Chunk<T>,
+    {
+        let codec = self.codec(backend, Compression::default())?;
+        for chunk in self.iter() {
+            let base = baseline(chunk.position());
+            let mut save = chunk.serializable_diff(&base);
+            save.border_light = self.capture_border_light(chunk.position());
+            write_chunk(backend, &codec, &save)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(save_directory: P) -> bincode::Result<Self> {
+        Self::load_with_progress(save_directory, &IoProgress::new())
+            .map(|map| map.unwrap_or_else(Self::new))
+    }
+
+    /// Saves every chunk to a single archive file at `path`, see `ArchiveBackend`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_archive<P: AsRef<Path>>(&self, path: P) -> bincode::Result<()> {
+        let archive = ArchiveBackend::new();
+        self.save_to(&archive, &IoProgress::new())?;
+        archive.write(path)
+    }
+
+    /// Loads a map exported with `export_archive`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn import_archive<P: AsRef<Path>>(path: P) -> bincode::Result<Self> {
+        let archive = ArchiveBackend::read(path)?;
+        Self::load_from(&archive, &IoProgress::new()).map(|map| map.unwrap_or_else(Self::new))
+    }
+
+    /// Like `load`, but reports every loaded chunk to `progress`. Returns `None` if the load
+    /// was cancelled.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_with_progress<P: AsRef<Path>>(
+        save_directory: P,
+        progress: &IoProgress,
+    ) -> bincode::Result<Option<Self>> {
+        Self::load_from(&FileBackend::new(save_directory), progress)
+    }
+
+    /// Reads every chunk from `backend`. Returns `None` if the load was cancelled.
+    pub fn load_from(
+        backend: &dyn SaveBackend,
+        progress: &IoProgress,
+    ) -> bincode::Result<Option<Self>> {
+        Self::load_chunks(backend, progress, None, Chunk::try_from)
+    }
+
+    /// Loads a map saved with `save_diff` or with `SaveThinning`, calling `baseline` to
+    /// regenerate every chunk that was stored as a diff or thinned.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_with<P, F>(save_directory: P, baseline: F) -> bincode::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnMut((i32, i32, i32)) -> Chunk<T>,
+    {
+        Self::load_from_with(&FileBackend::new(save_directory), baseline)
+    }
+
+    /// Like `load_with`, but reads from `backend`.
+    pub fn load_from_with<F>(backend: &dyn SaveBackend, mut baseline: F) -> bincode::Result<Self>
+    where
+        F: FnMut((i32, i32, i32)) -> Chunk<T>,
+    {
+        Self::load_chunks(backend, &IoProgress::new(), None, |save| {
+            let base = match save.data {
+                SaveContent::Full(_) | SaveContent::Palette(_) => None,
+                SaveContent::Diff { .. } | SaveContent::Thinned { .. } => {
+                    Some(baseline(save.position))
+                }
+            };
+            Chunk::from_save_data(save, base)
+        })
+        .map(|map| map.unwrap_or_else(Self::new))
+    }
+
+    /// Reads every chunk from `backend`, resolving the chunks saved with a palette with
+    /// `palette`, see `SaveData::resolve_palette`.
+    fn load_chunks<F>(
+        backend: &dyn SaveBackend,
+        progress: &IoProgress,
+        palette: Option<&[T]>,
+        mut restore: F,
+    ) -> bincode::Result<Option<Self>>
+    where
+        F: FnMut(SaveData<T>) -> bincode::Result<Chunk<T>>,
+    {
+        let manifest = SaveManifest::load(backend)?;
+        // saves without a layout were written by maps with chunks of a single width
+        let mut map = match manifest.layout {
+            Some(layout) => Self::with_layout(layout),
+            None => Self::new(),
+        };
+        let codec = ChunkCodec::new(Compression::default(), manifest);
+        // chunks are read one at a time, the first pass only collects the positions
+        let positions = backend.list()?;
+        progress.set_total(positions.len());
+        for position in positions {
+            if progress.is_cancelled() {
+                return Ok(None);
+            }
+            let mut save = read_chunk(backend, &codec, position)?;
+            if save.uses_palette() {
+                let voxels = palette.ok_or_else(|| {
+                    bincode::ErrorKind::Custom(format!(
+                        "chunk {:?} was saved with a palette, load it with `load_with_palette`",
+                        position
+                    ))
+                })?;
+                save.resolve_palette(voxels)?;
+            }
+            map.try_insert(restore(save)?)
+                .map_err(|e| bincode::ErrorKind::Custom(e.to_string()))?;
+            progress.advance();
+        }
+        Ok(Some(map))
+    }
+
+    /// Returns the codec for saving to `backend`, recording the layout of the map in the
+    /// manifest and training and storing a dictionary first if `compression` asks for one
+    /// and the save doesn't have one yet.
+    fn codec(
+        &self,
+        backend: &dyn SaveBackend,
+        compression: Compression,
+    ) -> bincode::Result<ChunkCodec> {
+        let mut manifest = SaveManifest::load(backend)?;
+        if self.layout.is_some() && manifest.layout != self.layout {
+            manifest.layout = self.layout;
+            manifest.save(backend)?;
+        }
+        #[cfg(feature = "zstd")]
+        {
+            if let Compression::Zstd {
+                dictionary: true, ..
+            } = compression
+            {
+                if manifest.dictionary.is_none() {
+                    // a few hundred chunks from all over the map are plenty to train on
+                    let step = (self.len() / 256).max(1);
+                    let samples = self
+                        .iter()
+                        .step_by(step)
+                        .map(|chunk| chunk.serializable().to_bytes())
+                        .collect::<bincode::Result<Vec<_>>>()?;
+                    manifest.dictionary = codec::train_dictionary(&samples);
+                    if manifest.dictionary.is_some() {
+                        manifest.save(backend)?;
+                    }
+                }
+            }
+        }
+        Ok(ChunkCodec::new(compression, manifest))
+    }
+}
+
+#[cfg(feature = "savedata")]
+impl<T: PaletteVoxel + Serialize + DeserializeOwned> Map<T> {
+    /// Like `save_to_with_options`, but stores the voxels of every chunk as indices into the
+    /// `SavePalette` in the save's manifest, which holds the id of every kind of voxel once.
+    /// Chunks thinned by `options.thinning` still store their averaged voxels.
+    pub fn save_with_palette(
+        &self,
+        backend: &dyn SaveBackend,
+        options: &SaveOptions,
+        progress: &IoProgress,
+    ) -> bincode::Result<()> {
+        let mut palette = SaveManifest::load(backend)?.palette;
+        self.write_chunks(backend, options.compression, progress, |chunk| {
+            let save = match options.thinning {
+                Some(thinning) if thinning.applies_to(chunk) => {
+                    chunk.serializable_thinned(thinning.lod)
+                }
+                _ => {
+                    let len = palette.len();
+                    let save = chunk.serializable_with_palette(&mut palette);
+                    // the new ids have to be stored before any chunk refers to them
+                    if palette.len() > len {
+                        let mut manifest = SaveManifest::load(backend)?;
+                        manifest.palette = palette.clone();
+                        manifest.save(backend)?;
+                    }
+                    save
+                }
+            };
+            Ok(save)
+        })
+    }
+
+    /// Reads every chunk from `backend`, including the chunks saved with
+    /// `save_with_palette`. Fails if the palette has an id `T` doesn't know. Returns `None`
+    /// if the load was cancelled.
+    pub fn load_with_palette(
+        backend: &dyn SaveBackend,
+        progress: &IoProgress,
+    ) -> bincode::Result<Option<Self>> {
+        let voxels = SaveManifest::load(backend)?.palette.voxels::<T>()?;
+        Self::load_chunks(backend, progress, Some(&voxels), Chunk::try_from)
+    }
+}
+
+/// Options for `Map::save_with_options`.
+#[cfg(feature = "savedata")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// How many previous versions of every chunk file to keep. Loading falls back to them,
+    /// newest first, when a chunk file is corrupted.
+    pub backups: usize,
+    pub compression: Compression,
+    pub thinning: Option<SaveThinning>,
+}
+
+/// Saves chunks far from `center` that were never edited at a reduced lod, to keep the saves
+/// of long explorations small. Load them with `Map::load_with` to get their full detail back
+/// from the generator, edited chunks are always saved losslessly.
+#[cfg(feature = "savedata")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveThinning {
+    pub center: (i32, i32, i32),
+    /// How far on any axis, in voxels, a chunk's origin has to be from `center` to be thinned.
+    pub distance: i32,
+    pub lod: usize,
+}
+
+#[cfg(feature = "savedata")]
+impl SaveThinning {
+    pub fn applies_to<T: Voxel>(&self, chunk: &Chunk<T>) -> bool {
+        let (x, y, z) = chunk.position();
+        let (cx, cy, cz) = self.center;
+        let distance = (x - cx).abs().max((y - cy).abs()).max((z - cz).abs());
+        !chunk.is_edited() && distance > self.distance
+    }
+}
+
+#[cfg(feature = "savedata")]
+fn serializable<T>(chunk: &Chunk<T>, thinning: Option<SaveThinning>) -> SaveData<T>
+where
+    T: Voxel + Serialize + DeserializeOwned,
+{
+    match thinning {
+        Some(thinning) if thinning.applies_to(chunk) => chunk.serializable_thinned(thinning.lod),
+        _ => chunk.serializable(),
+    }
+}
+
+#[cfg(feature = "savedata")]
+fn write_chunk<T: Serialize>(
+    backend: &dyn SaveBackend,
+    codec: &ChunkCodec,
+    savedata: &SaveData<T>,
+) -> bincode::Result<()> {
+    let bytes = codec.encode(&savedata.to_bytes()?)?;
+    backend.write_chunk(savedata.position, &bytes)?;
+    Ok(())
+}
+
+/// How many chunks saves compress ahead of the ones being written.
+#[cfg(feature = "savedata")]
+const SAVE_BATCH: usize = 64;
+
+#[cfg(feature = "savedata")]
+fn write_encoded(
+    backend: &dyn SaveBackend,
+    encoded: &[EncodedChunk],
+    progress: &IoProgress,
+) -> bincode::Result<()> {
+    for (position, bytes) in encoded {
+        let start = Instant::now();
+        backend.write_chunk(*position, bytes)?;
+        progress.record_write(bytes.len(), start);
+        progress.advance();
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "savedata", not(feature = "parallel")))]
+fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB,
+{
+    (a(), b())
+}
+
+/// Reads a chunk, falling back to its backups, newest first, if it is missing or corrupted.
+#[cfg(feature = "savedata")]
+fn read_chunk<T: DeserializeOwned>(
+    backend: &dyn SaveBackend,
+    codec: &ChunkCodec,
+    position: (i32, i32, i32),
+) -> bincode::Result<SaveData<T>> {
+    let decode = |bytes: Option<Vec<u8>>| -> bincode::Result<SaveData<T>> {
+        let bytes = bytes.ok_or_else(|| {
+            bincode::ErrorKind::Custom(format!("chunk {:?} is missing", position))
+        })?;
+        SaveData::from_bytes(&codec.decode(&bytes)?)
+    };
+    let read = backend.read_chunk(position).map_err(Into::into);
+    let error = match read.and_then(decode) {
+        Ok(save) => return Ok(save),
+        Err(e) => e,
+    };
+    let mut n = 1;
+    loop {
+        let backup = match backend.read_backup(position, n)? {
+            Some(backup) => backup,
+            None => return Err(error),
+        };
+        match decode(Some(backup)) {
+            Ok(save) => {
+                log::warn!(
+                    "chunk {:?} is corrupted ({}), loaded backup {} instead",
+                    position,
+                    error,
+                    n
+                );
+                return Ok(save);
+            }
+            Err(e) => log::warn!("backup {} of chunk {:?} is corrupted: {}", n, position, e),
+        }
+        n += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChunkUpdate {
+    GenerateChunk,
+    UpdateLightMap,
+    UpdateLight,
+    UpdateMesh,
+}
+
+/// Lighting invalidated for many chunks at once, see `MapUpdates::invalidate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidation {
+    /// Every loaded chunk, e.g. after the directional light changed.
+    AllLighting,
+    /// The chunks intersecting the box from `min` to `max`, both inclusive.
+    Region {
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+    },
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct MapUpdates {
+    pub updates: HashMap<(i32, i32, i32), ChunkUpdate>,
+    pub pipeline: ChunkPipeline,
+    /// Chunks to generate once no other chunks wait for generation, in order.
+    pub prefetch: Vec<(i32, i32, i32)>,
+    /// Remembers why every pending update was requested, for debugging update storms.
+    pub track_causes: bool,
+    causes: HashMap<(i32, i32, i32), UpdateCause>,
+    invalidations: VecDeque<Invalidation>,
+    /// The chunks of the invalidation being expanded.
+    expanding: Vec<(i32, i32, i32)>,
+    /// Caps on the pending updates, enforced by `enforce_limits`.
+    pub limits: UpdateLimits,
+    /// Where the viewer is in world coordinates, for `Eviction::Farthest` and `interest`.
+    pub focus: Option<(i32, i32, i32)>,
+    /// How far from `focus` chunks are still worth generating, in voxels along the x and z
+    /// axes. `cancel_generation` drops the generation requests of columns farther away.
+    pub interest: Option<i32>,
+    /// The number of generation requests cancelled so far.
+    cancelled: usize,
+    /// When the pending update of every chunk was requested, for `Eviction::Oldest`.
+    order: HashMap<(i32, i32, i32), u64>,
+    next_order: u64,
+}
+
+impl MapUpdates {
+    pub fn with_pipeline(pipeline: ChunkPipeline) -> Self {
+        Self {
+            pipeline,
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the pipeline. Pending updates of stages `pipeline` skips are forwarded to the
+    /// next stage it has, or dropped if there is none.
+    pub fn set_pipeline(&mut self, pipeline: ChunkPipeline) {
+        let mut dropped = Vec::new();
+        for (&coords, update) in &mut self.updates {
+            match pipeline.resolve(update) {
+                Some(resolved) => *update = resolved,
+                None => dropped.push(coords),
+            }
+        }
+        for coords in dropped {
+            self.updates.remove(&coords);
+            self.order.remove(&coords);
+            self.causes.remove(&coords);
+        }
+        self.pipeline = pipeline;
+    }
+
+    /// Drops every pending update, prefetch and invalidation. The pipeline, limits, `interest`
+    /// and `track_causes` are kept.
+    pub fn clear(&mut self) {
+        *self = Self {
+            pipeline: self.pipeline.clone(),
+            track_causes: self.track_causes,
+            limits: self.limits,
+            interest: self.interest,
+            ..Default::default()
+        };
+    }
+
+    /// Schedules `update` for the chunk at `coords`, unless an earlier stage is already
+    /// pending for it. Stages skipped by the pipeline are forwarded to the next one.
+    pub fn request(&mut self, coords: (i32, i32, i32), update: ChunkUpdate) {
+        self.request_because(coords, update, UpdateCause::Unknown);
+    }
+
+    /// Like `request`, but also records `cause` if `track_causes` is set. A chunk keeps the
+    /// cause of the earliest stage pending for it.
+    pub fn request_because(
+        &mut self,
+        coords: (i32, i32, i32),
+        update: ChunkUpdate,
+        cause: UpdateCause,
+    ) {
+        let update = if let Some(update) = self.pipeline.resolve(&update) {
+            update
+        } else {
+            return;
+        };
+        let mut requested = true;
+        match self.updates.get_mut(&coords) {
+            Some(pending) if *pending <= update => requested = false,
+            Some(pending) => *pending = update,
+            None => {
+                self.updates.insert(coords, update);
+            }
+        }
+        if requested {
+            self.order.insert(coords, self.next_order);
+            self.next_order += 1;
+        }
+        if requested && self.track_causes {
+            self.causes.insert(coords, cause);
+        }
+    }
+
+    /// Returns why the update pending for the chunk at `coords` was requested. Always `None`
+    /// unless `track_causes` is set.
+    pub fn cause(&self, coords: (i32, i32, i32)) -> Option<UpdateCause> {
+        self.updates.get(&coords)?;
+        self.causes.get(&coords).copied()
+    }
+
+    /// Returns the number of pending updates per cause.
+    pub fn cause_counts(&self) -> HashMap<UpdateCause, usize> {
+        let mut counts = HashMap::new();
+        for &coords in self.updates.keys() {
+            if let Some(cause) = self.causes.get(&coords) {
+                *counts.entry(*cause).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Marks `stage` as done for `chunk` and schedules the next stage of the pipeline. The
+    /// next stage keeps the cause of the update that started the chunk down the pipeline.
+    pub fn complete<T: Voxel>(
+        &mut self,
+        chunk: &mut Chunk<T>,
+        stage: ChunkUpdate,
+    ) -> Result<(), ChunkStateError> {
+        chunk.transition(&self.pipeline, &stage)?;
+        let coords = chunk.position();
+        let cause = if self.updates.contains_key(&coords) {
+            None
+        } else {
+            self.causes.remove(&coords)
+        };
+        if let Some(next) = self.pipeline.next(&stage) {
+            self.request_because(coords, next, cause.unwrap_or(UpdateCause::Pipeline));
+        }
+        Ok(())
+    }
+
+    /// Queues the chunk at `coords` for generation with a lower priority than
+    /// `ChunkUpdate::GenerateChunk`, unless it already has an update pending.
+    pub fn request_prefetch(&mut self, coords: (i32, i32, i32)) {
+        if !self.updates.contains_key(&coords) && !self.prefetch.contains(&coords) {
+            self.prefetch.push(coords);
+        }
+    }
+
+    /// Removes up to `limit` chunks from the front of the prefetch queue and returns them.
+    pub fn drain_prefetch(&mut self, limit: usize) -> Vec<(i32, i32, i32)> {
+        let limit = limit.min(self.prefetch.len());
+        self.prefetch.drain(..limit).collect()
+    }
+
+    /// Queues a light map update for many chunks without requesting them all at once. Call
+    /// `expand_invalidations` every frame, e.g. with `invalidation_update`, to turn them into
+    /// chunk updates a few at a time.
+    pub fn invalidate(&mut self, invalidation: Invalidation) {
+        if invalidation == Invalidation::AllLighting {
+            // covers everything queued so far
+            self.invalidations.clear();
+            self.expanding.clear();
+        } else if self.invalidations.contains(&Invalidation::AllLighting) {
+            return;
+        }
+        self.invalidations.push_back(invalidation);
+    }
+
+    pub fn invalidate_all_lighting(&mut self) {
+        self.invalidate(Invalidation::AllLighting);
+    }
+
+    pub fn invalidate_region(&mut self, min: (i32, i32, i32), max: (i32, i32, i32)) {
+        self.invalidate(Invalidation::Region { min, max });
+    }
+
+    /// Returns `true` if some invalidations haven't been expanded completely.
+    pub fn has_invalidations(&self) -> bool {
+        !self.invalidations.is_empty() || !self.expanding.is_empty()
+    }
+
+    /// Requests `ChunkUpdate::UpdateLightMap` for up to `limit` chunks of the queued
+    /// invalidations. Chunks are taken from `map` when an invalidation starts expanding, so
+    /// chunks loaded later aren't included. Returns the number of chunks requested.
+    pub fn expand_invalidations<T: Voxel>(&mut self, map: &Map<T>, limit: usize) -> usize {
+        let mut count = 0;
+        while count < limit {
+            if let Some(coords) = self.expanding.pop() {
+                if map.get_at_origin(coords).is_some() {
+                    self.request_because(
+                        coords,
+                        ChunkUpdate::UpdateLightMap,
+                        UpdateCause::Invalidation,
+                    );
+                    count += 1;
+                }
+                continue;
+            }
+            self.expanding = match self.invalidations.pop_front() {
+                Some(Invalidation::AllLighting) => map.iter().map(Chunk::position).collect(),
+                Some(Invalidation::Region { min, max }) => {
+                    map.chunks_in(min, max).map(Chunk::position).collect()
+                }
+                None => break,
+            };
+        }
+        count
+    }
+
+    /// Iterates over the chunks that have `kind` pending.
+    pub fn iter_kind(&self, kind: ChunkUpdate) -> impl Iterator<Item = (i32, i32, i32)> + '_ {
+        self.updates
+            .iter()
+            .filter(move |(_, update)| **update == kind)
+            .map(|(&coords, _)| coords)
+    }
+
+    /// Removes up to `limit` chunks that have `kind` pending and returns them.
+    pub fn drain_kind(&mut self, kind: ChunkUpdate, limit: usize) -> Vec<(i32, i32, i32)> {
+        self.drain_kind_filter(kind, limit, |_| true)
+    }
+
+    /// Like `drain_kind`, but leaves chunks for which `filter` returns `false` pending.
+    ///
+    /// The causes of drained chunks are kept until `complete` or `forget` is called for them,
+    /// even if other stages drain in the meantime.
+    pub fn drain_kind_filter<F>(
+        &mut self,
+        kind: ChunkUpdate,
+        limit: usize,
+        mut filter: F,
+    ) -> Vec<(i32, i32, i32)>
+    where
+        F: FnMut((i32, i32, i32)) -> bool,
+    {
+        let drained = self
+            .iter_kind(kind)
+            .filter(|&coords| filter(coords))
+            .take(limit)
+            .collect::<Vec<_>>();
+        for coords in &drained {
+            self.updates.remove(coords);
+            self.order.remove(coords);
+        }
+        drained
+    }
+}
+
+#[cfg(feature = "bevy")]
+pub const INVALIDATIONS_PER_FRAME: usize = 256;
+
+/// Expands the invalidations of every map into at most `INVALIDATIONS_PER_FRAME` chunk
+/// updates per frame.
+#[cfg(feature = "bevy")]
+pub fn invalidation_update<T: Voxel>(mut query: Query<(&Map<T>, &mut MapUpdates)>) {
+    for (map, mut updates) in &mut query.iter() {
+        updates.expand_invalidations(&map, INVALIDATIONS_PER_FRAME);
+    }
+}
+
+/// Publishes the number of updates pending per `UpdateCause` over all maps that have
+/// `MapUpdates::track_causes` set.
+#[cfg(feature = "bevy")]
+pub fn update_cause_diagnostics(
+    mut diagnostics: ResMut<Diagnostics>,
+    mut query: Query<&MapUpdates>,
+) {
+    let mut counts = HashMap::new();
+    let mut tracked = false;
+    for updates in &mut query.iter() {
+        tracked |= updates.track_causes;
+        for (cause, count) in updates.cause_counts() {
+            *counts.entry(cause).or_insert(0) += count;
+        }
+    }
+    if !tracked {
+        return;
+    }
+    for cause in &UpdateCause::ALL {
+        let id = cause.diagnostic_id();
+        if diagnostics.get(id).is_none() {
+            let name = format!("{} updates", cause.name());
+            diagnostics.add(Diagnostic::new(id, &name, 20));
+        }
+        let count = counts.get(cause).copied().unwrap_or(0);
+        diagnostics.add_measurement(id, count as f64);
+    }
+}
+
+#[cfg(feature = "bevy")]
+#[derive(Default, Bundle)]
+pub struct MapComponents {
+    pub map_update: MapUpdates,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashSet;
+    #[cfg(feature = "savedata")]
+    use std::{fs, io, sync::Mutex};
+
+    use super::*;
+
+    pub(crate) fn map() -> Map<i32> {
+        let mut chunks = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    chunks.push(Chunk::new(2, (x * 4, y * 4, z * 4)));
+                }
+            }
+        }
+        Map::try_with_chunks(chunks).unwrap()
+    }
+
+    /// `map` with every chunk all the way through the pipeline.
+    fn meshed_map() -> Map<i32> {
+        let mut map = map();
+        for chunk in map.chunks.values_mut() {
+            chunk.state = ChunkState::Meshed;
+        }
+        map
+    }
+
+    fn updated(updates: &MapUpdates) -> HashSet<(i32, i32, i32)> {
+        updates.updates.keys().copied().collect()
+    }
+
+    #[test]
+    pub fn edit_inside() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        assert!(map.set_voxel((1, 2, 1), Some(1), &mut updates));
+        assert_eq!(map.voxel((1, 2, 1)).unwrap().into_owned(), 1);
+        assert_eq!(updated(&updates), [(0, 0, 0)].iter().copied().collect());
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateLightMap);
+    }
+
+    #[test]
+    pub fn edit_border() {
+        let mut map = meshed_map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((3, 1, 0), Some(1), &mut updates);
+        assert_eq!(
+            updated(&updates),
+            [(0, 0, 0), (4, 0, 0), (0, 0, -4)].iter().copied().collect()
+        );
+        assert_eq!(updates.updates[&(4, 0, 0)], ChunkUpdate::UpdateMesh);
+        assert_eq!(updates.updates[&(0, 0, -4)], ChunkUpdate::UpdateMesh);
+
+        // neighbours that weren't lit yet get their next stage
+        let mut map = self::map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((3, 1, 0), Some(1), &mut updates);
+        assert_eq!(updates.updates[&(4, 0, 0)], ChunkUpdate::UpdateLightMap);
+    }
+
+    #[test]
+    pub fn batch() {
+        let mut map = meshed_map();
+        let mut updates = MapUpdates::default();
+        map.set_track_changes(true);
+        // a 3x3x3 crater on the border of four chunks, touching four more
+        let edits = map.batch(&mut updates, |editor| {
+            for (x, y, z) in (0..27).map(|i| (1 + i / 9, -1 + i / 3 % 3, -2 + i % 3)) {
+                editor.set_voxel((x, y, z), Some(1));
+            }
+            assert_eq!(editor.voxel((3, 0, -1)).unwrap().into_owned(), 1);
+            assert!(!editor.set_voxel((8, 0, 0), Some(1)));
+            editor.edits()
+        });
+        assert_eq!(edits, 27);
+        assert_eq!(map.drain_changes().len(), 27);
+
+        // the same updates as setting the voxels one by one, each requested once
+        let mut single = MapUpdates::default();
+        let mut other = meshed_map();
+        for (x, y, z) in (0..27).map(|i| (1 + i / 9, -1 + i / 3 % 3, -2 + i % 3)) {
+            other.set_voxel((x, y, z), Some(1), &mut single);
+        }
+        assert_eq!(updates.updates, single.updates);
+        let mut unlit = MapUpdates::default();
+        self::map().batch(&mut unlit, |editor| editor.set_voxel((3, 1, 0), Some(1)));
+        assert_eq!(unlit.updates[&(4, 0, 0)], ChunkUpdate::UpdateLightMap);
+        assert_eq!(updates.next_order, updates.updates.len() as u64);
+        assert!(single.next_order > updates.next_order);
+        assert_eq!(updates.updates[&(0, -4, -4)], ChunkUpdate::UpdateLightMap);
+        assert_eq!(updates.updates[&(4, 0, 0)], ChunkUpdate::UpdateMesh);
+    }
+
+    #[test]
+    pub fn block_changes() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        assert!(map.drain_changes().is_empty());
+
+        map.set_track_changes(true);
+        map.set_voxel((1, 1, 1), Some(2), &mut updates);
+        // setting a voxel to what it already is isn't a change
+        map.set_voxel((1, 1, 1), Some(2), &mut updates);
+        map.move_voxel((1, 1, 1), (5, 1, 1), &mut updates);
+        let changes = map.drain_changes();
+        let change = |coords, old, new, cause| BlockChanged {
+            coords,
+            old,
+            new,
+            cause,
+        };
+        assert_eq!(
+            changes,
+            vec![
+                change((1, 1, 1), Some(1), Some(2), ChangeCause::Edit),
+                change((1, 1, 1), Some(2), None, ChangeCause::Move),
+                change((5, 1, 1), None, Some(2), ChangeCause::Move),
+            ]
+        );
+        assert!(map.drain_changes().is_empty());
+    }
+
+    #[test]
+    pub fn edit_corner() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((-1, -1, -1), None, &mut updates);
+        assert_eq!(
+            updated(&updates),
+            [(-4, -4, -4), (0, -4, -4), (-4, 0, -4), (-4, -4, 0)]
+                .iter()
+                .copied()
+                .collect()
+        );
+    }
+
+    #[test]
+    pub fn edit_edge_of_map() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((7, 7, 7), Some(1), &mut updates);
+        assert_eq!(updated(&updates), [(4, 4, 4)].iter().copied().collect());
+        assert!(!map.set_voxel((8, 0, 0), Some(1), &mut updates));
+    }
+
+    #[test]
+    pub fn request_keeps_earlier_stage() {
+        let mut updates = MapUpdates::default();
+        updates.request((0, 0, 0), ChunkUpdate::UpdateLightMap);
+        updates.request((0, 0, 0), ChunkUpdate::UpdateMesh);
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateLightMap);
+        updates.request((0, 0, 0), ChunkUpdate::GenerateChunk);
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::GenerateChunk);
+    }
+
+    #[test]
+    pub fn set_pipeline() {
+        let mut updates = MapUpdates::default();
+        updates.request((0, 0, 0), ChunkUpdate::UpdateLight);
+        updates.request((4, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.set_pipeline(ChunkPipeline::unlit());
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateMesh);
+        updates.request((8, 0, 0), ChunkUpdate::UpdateLightMap);
+        assert_eq!(updates.updates[&(8, 0, 0)], ChunkUpdate::UpdateMesh);
+
+        updates.set_pipeline(ChunkPipeline::new().skip(ChunkUpdate::UpdateMesh));
+        assert!(updates.updates.is_empty());
+    }
+
+    #[test]
+    pub fn drain_kind() {
+        let mut updates = MapUpdates::default();
+        updates.request((0, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.request((4, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.request((8, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.request((0, 4, 0), ChunkUpdate::UpdateLight);
+
+        let drained = updates.drain_kind(ChunkUpdate::UpdateMesh, 2);
+        assert_eq!(drained.len(), 2);
+        assert_eq!(updates.iter_kind(ChunkUpdate::UpdateMesh).count(), 1);
+        assert_eq!(
+            updates.iter_kind(ChunkUpdate::UpdateLight).collect::<Vec<_>>(),
+            vec![(0, 4, 0)]
+        );
+        assert!(drained.iter().all(|coords| !updates.updates.contains_key(coords)));
+    }
+
+    #[test]
+    pub fn clear_updates() {
+        let mut updates = MapUpdates::default();
+        updates.track_causes = true;
+        updates.limits.mesh = Some(8);
+        updates.request((0, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.prefetch.push((4, 0, 0));
+        updates.clear();
+        assert!(updates.updates.is_empty());
+        assert!(updates.prefetch.is_empty());
+        assert!(updates.track_causes);
+        assert_eq!(updates.limits.mesh, Some(8));
+
+        let mut ticks = VoxelTicks::<i32>::new();
+        ticks.schedule((0, 0, 0), Some(1), 4);
+        ticks.clear();
+        assert!(ticks.is_empty());
+        assert_eq!(ticks.tick(), 0);
+    }
+
+    #[cfg(feature = "bevy")]
+    #[test]
+    pub fn clear_map() {
+        let mut map = Map::with_layout(MapLayout::new(2));
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((0, 0, 0), 1);
+        chunk.set_entity(Entity::new());
+        map.try_insert(chunk).unwrap();
+        map.insert_overlay(0, OverlayLayer::new(map.layout().unwrap()));
+        map.clear(&mut Commands::default());
+        assert!(map.get((0, 0, 0)).is_none());
+        assert!(map.chunk_containing((0, 0, 0)).is_none());
+        assert!(map.overlay(0).is_none());
+        assert_eq!(map.layout(), Some(MapLayout::new(2)));
+    }
+
+    #[test]
+    pub fn pipeline_transitions() {
+        let mut updates = MapUpdates::default();
+        let mut chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        assert_eq!(chunk.state(), ChunkState::Generated);
+        assert!(updates.complete(&mut chunk, ChunkUpdate::UpdateLight).is_err());
+
+        updates.complete(&mut chunk, ChunkUpdate::UpdateLightMap).unwrap();
+        assert_eq!(chunk.state(), ChunkState::LightMapped);
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateLight);
+
+        updates.updates.clear();
+        updates.complete(&mut chunk, ChunkUpdate::UpdateLight).unwrap();
+        updates.updates.clear();
+        updates.complete(&mut chunk, ChunkUpdate::UpdateMesh).unwrap();
+        assert_eq!(chunk.state(), ChunkState::Meshed);
+        assert!(updates.updates.is_empty());
+
+        // remeshing is always fine, a new light map moves the chunk back
+        updates.complete(&mut chunk, ChunkUpdate::UpdateMesh).unwrap();
+        updates.complete(&mut chunk, ChunkUpdate::UpdateLightMap).unwrap();
+        assert_eq!(chunk.state(), ChunkState::LightMapped);
+    }
+
+    #[test]
+    pub fn unlit_pipeline() {
+        let mut map = map();
+        let mut updates = MapUpdates::with_pipeline(ChunkPipeline::unlit());
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateMesh);
+
+        let chunk = map.get_mut((0, 0, 0)).unwrap();
+        updates.updates.clear();
+        updates.complete(chunk, ChunkUpdate::GenerateChunk).unwrap();
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateMesh);
+        updates.complete(chunk, ChunkUpdate::UpdateMesh).unwrap();
+        assert_eq!(chunk.state(), ChunkState::Meshed);
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn region_hash() {
+        let mut edited = map();
+        let mut updates = MapUpdates::default();
+        let hash = edited.region_hash((0, 0, 0), (7, 7, 7));
+        assert_eq!(hash, map().region_hash((0, 0, 0), (7, 7, 7)));
+
+        edited.set_voxel((-1, 0, 0), Some(1), &mut updates);
+        assert_eq!(edited.region_hash((0, 0, 0), (7, 7, 7)), hash);
+
+        edited.set_voxel((5, 0, 0), Some(1), &mut updates);
+        assert_ne!(edited.region_hash((0, 0, 0), (7, 7, 7)), hash);
+
+        edited.remove((4, 4, 4));
+        edited.set_voxel((5, 0, 0), None, &mut updates);
+        assert_ne!(edited.region_hash((0, 0, 0), (7, 7, 7)), hash);
+    }
+
+    #[test]
+    pub fn block_data_follows_voxel() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        let data = BlockData(vec![1, 2, 3]);
+        assert!(!map.set_block_data((1, 1, 1), data.clone()));
+
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        assert!(map.set_block_data((1, 1, 1), data.clone()));
+        assert!(map.move_voxel((1, 1, 1), (5, 1, 1), &mut updates));
+        assert_eq!(map.voxel((1, 1, 1)), None);
+        assert_eq!(map.block_data((1, 1, 1)), None);
+        assert_eq!(map.block_data((5, 1, 1)), Some(&data));
+
+        map.set_voxel((5, 1, 1), Some(2), &mut updates);
+        assert_eq!(map.block_data((5, 1, 1)), None);
+
+        // moving nothing doesn't clear the target
+        assert!(!map.move_voxel((1, 1, 1), (5, 1, 1), &mut updates));
+        assert_eq!(map.voxel((5, 1, 1)).as_deref(), Some(&2));
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn block_data_save() {
+        let mut chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        chunk.insert((1, 2, 3), 1);
+        chunk.set_block_data((1, 2, 3), BlockData::encode(&"sign").unwrap());
+
+        let loaded = Chunk::try_from(chunk.serializable()).unwrap();
+        let text: String = loaded.block_data((1, 2, 3)).unwrap().decode().unwrap();
+        assert_eq!(text, "sign");
+
+        let diff = chunk.serializable_diff(&Chunk::new(2, (0, 0, 0)));
+        let loaded = Chunk::from_save_data(diff, None).unwrap();
+        assert_eq!(loaded.block_data((1, 2, 3)), chunk.block_data((1, 2, 3)));
+
+        // a baseline of another width can't be diffed, the whole chunk is saved instead
+        let full = chunk.serializable_diff(&Chunk::new(3, (0, 0, 0)));
+        assert!(matches!(full.data, SaveContent::Full(_)));
+        let loaded = Chunk::from_save_data(full, Some(Chunk::new(3, (0, 0, 0)))).unwrap();
+        assert_eq!(loaded.voxel((1, 2, 3)), Some(&1));
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn user_data_save() {
+        let mut chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        chunk.insert((1, 2, 3), 1);
+        let claim = UserData::encode(&("alice", 3u8)).unwrap();
+        assert_eq!(chunk.set_user_data(claim), None);
+
+        let loaded = Chunk::try_from(chunk.serializable()).unwrap();
+        let claim: (String, u8) = loaded.user_data().unwrap().decode().unwrap();
+        assert_eq!(claim, ("alice".to_string(), 3));
+
+        let thinned = Chunk::try_from(chunk.serializable_thinned(1)).unwrap();
+        assert_eq!(thinned.user_data(), chunk.user_data());
+        let diff = chunk.serializable_diff(&Chunk::new(2, (0, 0, 0)));
+        let mut loaded = Chunk::from_save_data(diff, None).unwrap();
+        assert_eq!(loaded.user_data(), chunk.user_data());
+        assert!(loaded.remove_user_data().is_some());
+        let reloaded = Chunk::try_from(loaded.serializable()).unwrap();
+        assert_eq!(reloaded.user_data(), None);
+    }
+
+    #[test]
+    pub fn occupancy() {
+        let mut chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        chunk.insert((1, 2, 3), 1);
+        chunk.insert((3, 3, 3), 2);
+        chunk.remove((3, 3, 3));
+        assert!(chunk.contains_key((1, 2, 3)));
+        assert!(!chunk.contains_key((3, 3, 3)));
+        assert!(!chunk.contains_key((4, 0, 0)));
+        assert_eq!(chunk.occupancy().count(), 1);
+
+        #[cfg(feature = "savedata")]
+        {
+            let loaded = Chunk::try_from(chunk.serializable()).unwrap();
+            assert_eq!(loaded.occupancy(), chunk.occupancy());
+        }
+    }
+
+    #[test]
+    pub fn brick_storage() {
+        let mut tree = Chunk::<i32>::new(3, (0, 0, 0));
+        for x in 0..8 {
+            for z in 0..8 {
+                tree.insert((x, 0, z), 1);
+            }
+        }
+        tree.insert((3, 5, 2), 2);
+        tree.merge();
+
+        let mut bricks = tree.clone();
+        bricks.set_storage(StorageKind::BrickMap);
+        assert_eq!(bricks.storage(), StorageKind::BrickMap);
+        assert_eq!(bricks.get((3, 5, 2)).as_deref(), Some(&2));
+        assert_eq!(bricks.voxel((7, 0, 7)), Some(&1));
+        assert_eq!(VoxelStorage::len(&bricks), 65);
+        assert!(bricks.iter().all(|elem| elem.width == 1));
+        assert_eq!(bricks.occupancy(), tree.occupancy());
+
+        // bricks remember their lod, but have nothing to defragment
+        bricks.set_lod(1);
+        assert_eq!(bricks.lod(), 1);
+        bricks.set_lod(0);
+        bricks.insert((3, 5, 2), 3);
+        assert!(bricks.is_fragmented());
+        bricks.defragment(usize::MAX);
+        assert!(!bricks.is_fragmented());
+        for elem in bricks.iter_mut().filter(|elem| elem.y == 0) {
+            *elem.value = 4;
+        }
+        assert_eq!(bricks.voxel((2, 0, 6)), Some(&4));
+
+        bricks.set_storage(StorageKind::LodTree);
+        assert_eq!(bricks.storage(), StorageKind::LodTree);
+        assert_eq!(bricks.voxel((3, 5, 2)), Some(&3));
+        assert_eq!(bricks.voxel((2, 0, 6)), Some(&4));
+        assert_eq!(bricks.occupancy(), tree.occupancy());
+
+        #[cfg(feature = "savedata")]
+        {
+            let mut bricks = tree.clone();
+            bricks.set_storage(StorageKind::BrickMap);
+            assert_eq!(bricks.content_hash(), tree.content_hash());
+            let loaded = Chunk::try_from(bricks.serializable()).unwrap();
+            assert_eq!(loaded.storage(), StorageKind::LodTree);
+            assert_eq!(loaded.content_hash(), tree.content_hash());
+        }
+    }
+
+    #[test]
+    pub fn occupancy_cells() {
+        let mut chunk = Chunk::<i32>::new(4, (0, 0, 0));
+        assert_eq!(chunk.occupancy().cell_width(), 2);
+        assert_eq!(chunk.occupancy().empty_cell((5, 5, 5)), Some(((0, 0, 0), 16)));
+        chunk.insert((5, 5, 5), 1);
+        assert_eq!(chunk.occupancy().empty_cell((0, 1, 0)), Some(((0, 0, 0), 2)));
+        assert_eq!(chunk.occupancy().empty_cell((4, 5, 4)), None);
+        assert_eq!(chunk.occupancy().empty_cell((16, 0, 0)), None);
+        chunk.remove((5, 5, 5));
+        assert!(chunk.occupancy().is_empty());
+
+        // rays skip the empty chunk, the unloaded chunks and the empty cells on the way
+        let mut map = Map::with_layout(MapLayout::new(4));
+        map.try_insert(Chunk::new(4, (0, 0, 0))).unwrap();
+        let mut chunk = Chunk::new(4, (16, 0, 0));
+        chunk.insert((9, 3, 7), 1);
+        map.try_insert(chunk).unwrap();
+        let mut chunk = Chunk::new(4, (16, 16, 16));
+        chunk.insert((4, 4, 4), 2);
+        map.try_insert(chunk).unwrap();
+
+        let origin = glam::Vec3::new(0.5, 3.5, 7.5);
+        let hit = map.raycast(origin, glam::Vec3::new(1.0, 0.0, 0.0), 100.0).unwrap();
+        assert_eq!(hit.position, (25, 3, 7));
+        assert_eq!(hit.normal, (-1, 0, 0));
+        assert_eq!(hit.distance, 24.5);
+        assert!(map.raycast(origin, glam::Vec3::new(-1.0, 0.0, 0.0), 100.0).is_none());
+
+        let origin = glam::Vec3::new(0.5, 0.5, 0.5);
+        let direction = glam::Vec3::new(1.0, 1.0, 1.0);
+        let hit = map.raycast(origin, direction, 100.0).unwrap();
+        assert_eq!(hit.position, (20, 20, 20));
+        assert!((hit.distance - 19.5 * 3.0f32.sqrt()).abs() < 1e-3);
+        assert!(map.raycast(origin, direction, 30.0).is_none());
+    }
+
+    #[test]
+    pub fn dense_buffer() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        map.set_voxel((1, 2, 1), Some(1), &mut updates);
+        map.set_voxel((3, 0, 2), Some(7), &mut updates);
+
+        let mut buffer = map.get((0, 0, 0)).unwrap().to_dense_buffer();
+        assert_eq!(buffer.palette, vec![1, 7]);
+        assert_eq!(buffer.indices.len(), 64);
+        assert_eq!(buffer.indices[(1 * 4 + 2) * 4 + 1], 1);
+        assert_eq!(buffer.get((3, 0, 2)), Some(&7));
+        assert_eq!(buffer.index_bytes().len(), 256);
+
+        let copy = Chunk::from_dense_buffer((0, 0, 0), &buffer);
+        assert_eq!(copy.to_dense_buffer(), buffer);
+
+        buffer.set((1, 2, 1), None);
+        buffer.set((3, 3, 3), Some(2));
+        let mut updates = MapUpdates::default();
+        assert_eq!(map.apply_dense_buffer((0, 0, 0), &buffer, &mut updates), 2);
+        assert_eq!(map.voxel((3, 3, 3)).unwrap().into_owned(), 2);
+        assert!(map.voxel((1, 2, 1)).is_none());
+        assert!(updated(&updates).contains(&(4, 0, 0)));
+    }
+
+    #[test]
+    pub fn chunk_containing() {
+        let mut map = map();
+        assert_eq!(map.get((-4, 0, 4)).unwrap().position(), (-4, 0, 4));
+        // `get` takes any voxel of the chunk, `get_at_origin` only its origin
+        assert_eq!(map.get((-3, 1, 5)).unwrap().position(), (-4, 0, 4));
+        assert!(map.get_at_origin((-3, 1, 5)).is_none());
+        assert_eq!(map.get_mut((-1, 3, 7)).unwrap().position(), (-4, 0, 4));
+        assert!(map.get_at_origin_mut((-1, 3, 7)).is_none());
+        assert_eq!(map.chunk_containing((-3, 1, 5)).unwrap().position(), (-4, 0, 4));
+        assert_eq!(map.chunk_containing((-1, -1, 7)).unwrap().position(), (-4, -4, 4));
+        assert!(map.chunk_containing((8, 0, 0)).is_none());
+    }
+
+    #[test]
+    pub fn scheduled_voxel() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        let mut ticks = VoxelTicks::new();
+        ticks.schedule((1, 2, 1), Some(1), 2);
+        ticks.schedule_animated((5, 0, 0), Some(2), 4);
+        ticks.schedule((8, 0, 0), Some(3), 1);
+
+        assert_eq!(ticks.advance(&mut map, &mut updates), 0);
+        assert!(map.voxel((1, 2, 1)).is_none());
+        assert_eq!(ticks.progress((5, 0, 0)), Some(0.25));
+        assert_eq!(updates.updates[&(4, 0, 0)], ChunkUpdate::UpdateMesh);
+
+        assert_eq!(ticks.advance(&mut map, &mut updates), 1);
+        assert_eq!(map.voxel((1, 2, 1)).unwrap().into_owned(), 1);
+        assert!(ticks.get((8, 0, 0)).is_none());
+        assert_eq!(ticks.len(), 1);
+    }
+
+    #[test]
+    pub fn light_double_buffer() {
+        let mut chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        chunk.insert_light((1, 1, 1), 0.5);
+        chunk.insert_next_light((1, 1, 1), 1.0);
+        assert_eq!(chunk.light((1, 1, 1)), Some(0.5));
+        assert!(!chunk.has_light());
+
+        assert!(chunk.swap_light());
+        assert_eq!(chunk.light((1, 1, 1)), Some(1.0));
+        assert!(chunk.has_light());
+        assert!(!chunk.swap_light());
+    }
+
+    #[test]
+    pub fn light_precision() {
+        let mut chunk = Chunk::<i32>::new(2, (0, 0, 0));
+        chunk.insert_light((1, 1, 1), 0.5);
+        chunk.insert_next_light((1, 2, 3), 0.25);
+        let mut map = Map::new();
+        map.set_light_precision(LightPrecision::Quantized);
+        map.try_insert(chunk).unwrap();
+
+        let chunk = map.get_mut((0, 0, 0)).unwrap();
+        assert_eq!(chunk.light_precision(), LightPrecision::Quantized);
+        assert!((chunk.light((1, 1, 1)).unwrap() - 0.5).abs() < 0.01);
+        // quantized light maps have no holes
+        assert_eq!(chunk.light((0, 0, 0)), Some(0.0));
+        assert_eq!(chunk.light((4, 0, 0)), None);
+        chunk.insert_light((0, 0, 0), 2.0);
+        assert_eq!(chunk.light((0, 0, 0)), Some(1.0));
+        assert_eq!(chunk.lights().count(), 64);
+
+        assert!(chunk.swap_light());
+        assert!((chunk.light((1, 2, 3)).unwrap() - 0.25).abs() < 0.01);
+        chunk.set_light_precision(LightPrecision::Full);
+        assert!((chunk.light((1, 2, 3)).unwrap() - 0.25).abs() < 0.01);
+
+        chunk.set_light_precision(LightPrecision::Quantized);
+        #[allow(deprecated)]
+        {
+            *chunk.light_mut((1, 2, 3)).unwrap() = 0.75;
+        }
+        assert_eq!(chunk.light_precision(), LightPrecision::Full);
+        assert_eq!(chunk.light((1, 2, 3)), Some(0.75));
+    }
+
+    #[test]
+    pub fn tick_policy() {
+        let policy = TickPolicy {
+            budget: 20,
+            chunk_cap: 8,
+            falloff: 16,
+            range: 100,
+        };
+        let shares = policy.distribute(&[0, 16, 48, 200]);
+        assert_eq!(shares.iter().sum::<usize>(), 20);
+        assert!(shares[0] >= shares[1] && shares[1] >= shares[2]);
+        assert!(shares.iter().all(|&share| share <= 8));
+        assert_eq!(shares[3], 0);
+
+        // a budget larger than all caps together is cut off
+        let shares = policy.distribute(&[0, 0]);
+        assert_eq!(shares, vec![8, 8]);
+    }
+
+    impl RandomTick for i32 {
+        fn random_tick(&self, _: (i32, i32, i32), _: &Map<Self>) -> Option<Option<Self>> {
+            if *self == 3 {
+                Some(Some(5))
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    pub fn random_tick() {
+        use rand::SeedableRng;
+
+        // no voxel sets `VoxelFlags::RANDOM_TICK`, which doesn't matter without `FLAGGED_ONLY`
+        let mut map = Map::try_with_chunks(vec![Chunk::new(1, (0, 0, 0))]).unwrap();
+        let mut updates = MapUpdates::default();
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    map.set_voxel((x, y, z), Some(3), &mut updates);
+                }
+            }
+        }
+        let policy = TickPolicy {
+            budget: 64,
+            chunk_cap: 64,
+            ..Default::default()
+        };
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(1);
+        let changed = random_ticks(&mut map, &mut updates, &policy, &[(0, 0, 0)], &mut rng);
+        assert!(changed > 0);
+        assert_eq!(map.voxel((0, 0, 0)).as_deref(), Some(&5));
+    }
+
+    #[cfg(feature = "savedata")]
+    #[derive(Default)]
+    pub(crate) struct MemoryBackend {
+        chunks: Mutex<HashMap<(i32, i32, i32), Vec<u8>>>,
+        manifest: Mutex<Option<Vec<u8>>>,
+    }
+
+    #[cfg(feature = "savedata")]
+    impl SaveBackend for MemoryBackend {
+        fn read_chunk(&self, position: (i32, i32, i32)) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.chunks.lock().unwrap().get(&position).cloned())
+        }
+
+        fn write_chunk(&self, position: (i32, i32, i32), bytes: &[u8]) -> io::Result<()> {
+            self.chunks.lock().unwrap().insert(position, bytes.to_vec());
+            Ok(())
+        }
+
+        fn list(&self) -> io::Result<Vec<(i32, i32, i32)>> {
+            Ok(self.chunks.lock().unwrap().keys().copied().collect())
+        }
+
+        fn delete(&self, position: (i32, i32, i32)) -> io::Result<()> {
+            self.chunks.lock().unwrap().remove(&position);
+            Ok(())
+        }
+
+        fn read_manifest(&self) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.manifest.lock().unwrap().clone())
+        }
+
+        fn write_manifest(&self, bytes: &[u8]) -> io::Result<()> {
+            *self.manifest.lock().unwrap() = Some(bytes.to_vec());
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn save_backend() {
+        let backend = MemoryBackend::default();
+        let mut updates = MapUpdates::default();
+        let mut map = map();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        let progress = IoProgress::new();
+        map.save_to(&backend, &progress).unwrap();
+        assert_eq!(backend.list().unwrap().len(), 27);
+        assert_eq!(progress.done(), 27);
+        let written = backend.chunks.lock().unwrap().values().map(Vec::len).sum();
+        assert_eq!(progress.bytes(), written);
+        assert!(progress.raw_bytes() > 0);
+
+        backend.delete((4, 4, 4)).unwrap();
+        let loaded = Map::<i32>::load_from(&backend, &IoProgress::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.iter().count(), 26);
+        assert_eq!(loaded.voxel((1, 1, 1)).unwrap().into_owned(), 1);
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn save_meta() {
+        let backend = MemoryBackend::default();
+        let mut map = map();
+        let tower = Structure {
+            position: (1, 2, 3),
+            size: (2, 8, 2),
+        };
+        // two units per axis, so every biome covers 2x2 voxel columns
+        let meta = ChunkMeta {
+            biomes: vec![0, 1, 2, 3],
+            structures: vec![tower],
+            ..Default::default()
+        };
+        map.get_at_origin_mut((4, 0, -4)).unwrap().set_meta(meta);
+        map.save_to(&backend, &IoProgress::new()).unwrap();
+
+        let loaded = Map::<i32>::load_from(&backend, &IoProgress::new())
+            .unwrap()
+            .unwrap();
+        let meta = loaded.get_at_origin((4, 0, -4)).unwrap().meta().unwrap();
+        assert_eq!(meta.biomes, vec![0, 1, 2, 3]);
+        assert_eq!(meta.structures, vec![tower]);
+        assert_eq!(loaded.biome((4, 0, -4)), Some(0));
+        assert_eq!(loaded.biome((7, 3, -1)), Some(3));
+        assert_eq!(loaded.biome((0, 0, 0)), None);
+
+        let structures = loaded.structures().collect::<Vec<_>>();
+        let placed = Structure {
+            position: (5, 2, -1),
+            size: (2, 8, 2),
+        };
+        assert_eq!(structures, vec![placed]);
+    }
+
+    #[cfg(feature = "savedata")]
+    impl PaletteVoxel for i32 {
+        fn palette_id(&self) -> Cow<'_, str> {
+            match self {
+                1 => "stone".into(),
+                2 => "dirt".into(),
+                _ => format!("block{}", self).into(),
+            }
+        }
+
+        fn from_palette_id(id: &str) -> Option<Self> {
+            match id {
+                "stone" => Some(1),
+                "dirt" => Some(2),
+                _ => id.trim_start_matches("block").parse().ok(),
+            }
+        }
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn save_palette() {
+        let backend = MemoryBackend::default();
+        let mut updates = MapUpdates::default();
+        let mut map = map();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        map.set_voxel((2, 1, 1), Some(2), &mut updates);
+        map.set_voxel((5, 0, 0), Some(1), &mut updates);
+        map.save_with_palette(&backend, &SaveOptions::default(), &IoProgress::new())
+            .unwrap();
+        let mut manifest = SaveManifest::load(&backend).unwrap();
+        assert_eq!(manifest.palette.ids(), ["stone", "dirt"]);
+        assert!(Map::<i32>::load_from(&backend, &IoProgress::new()).is_err());
+        // so does restoring a single chunk without its palette
+        let chunk = map.get((0, 0, 0)).unwrap();
+        let save = chunk.serializable_with_palette(&mut manifest.palette);
+        assert!(Chunk::try_from(save).is_err());
+
+        let loaded = Map::<i32>::load_with_palette(&backend, &IoProgress::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.iter().count(), 27);
+        assert_eq!(loaded.voxel((2, 1, 1)).unwrap().into_owned(), 2);
+        assert_eq!(loaded.voxel((5, 0, 0)).unwrap().into_owned(), 1);
+
+        // saving again only appends to the palette
+        map.set_voxel((1, 1, 1), Some(7), &mut updates);
+        map.save_with_palette(&backend, &SaveOptions::default(), &IoProgress::new())
+            .unwrap();
+        manifest = SaveManifest::load(&backend).unwrap();
+        assert_eq!(manifest.palette.ids(), ["stone", "dirt", "block7"]);
+
+        manifest.palette = SavePalette::default();
+        manifest.palette.index("stone");
+        manifest.palette.index("marble");
+        manifest.save(&backend).unwrap();
+        assert!(Map::<i32>::load_with_palette(&backend, &IoProgress::new()).is_err());
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn generator_drift() {
+        let backend = MemoryBackend::default();
+        let drift = SaveManifest::check_generator(&backend, 1).unwrap();
+        assert_eq!(drift, GeneratorDrift::Unknown);
+
+        // manifests written before the fingerprint are still read
+        #[derive(Serialize)]
+        struct PaletteManifest {
+            dictionary: Option<Vec<u8>>,
+            palette: SavePalette,
+        }
+        let mut palette = SavePalette::default();
+        palette.index("stone");
+        let old = PaletteManifest {
+            dictionary: None,
+            palette,
+        };
+        let bytes = bincode::serialize(&old).unwrap();
+        backend.write_manifest(&bytes).unwrap();
+        let manifest = SaveManifest::load(&backend).unwrap();
+        assert_eq!(manifest.palette.ids(), ["stone"]);
+
+        SaveManifest::record_generator(&backend, 1).unwrap();
+        let manifest = SaveManifest::load(&backend).unwrap();
+        assert_eq!(manifest.generator, Some(1));
+        assert_eq!(manifest.palette.ids(), ["stone"]);
+        let drift = SaveManifest::check_generator(&backend, 1).unwrap();
+        assert_eq!(drift, GeneratorDrift::Unchanged);
+        let drift = SaveManifest::check_generator(&backend, 2).unwrap();
+        let changed = GeneratorDrift::Changed {
+            saved: 1,
+            current: 2,
+        };
+        assert_eq!(drift, changed);
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn mixed_layout_save() {
+        let layout = MapLayout::mixed(2, 3);
+        let mut map = Map::<i32>::with_layout(layout);
+        let mut small = Chunk::new(2, (0, 0, 0));
+        small.insert((1, 2, 3), 1);
+        let mut big = Chunk::new(3, (8, 0, 0));
+        big.insert((7, 6, 5), 2);
+        map.try_insert(small).unwrap();
+        map.try_insert(big).unwrap();
+
+        let backend = MemoryBackend::default();
+        map.save_to(&backend, &IoProgress::new()).unwrap();
+        assert_eq!(SaveManifest::load(&backend).unwrap().layout, Some(layout));
+        let loaded = Map::<i32>::load_from(&backend, &IoProgress::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.layout(), Some(layout));
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.voxel((1, 2, 3)).as_deref(), Some(&1));
+        assert_eq!(loaded.voxel((15, 6, 5)).as_deref(), Some(&2));
+        assert_eq!(loaded.get((8, 0, 0)).unwrap().width(), 8);
+
+        // manifests written before the layout are still read
+        #[derive(Serialize)]
+        struct GeneratorManifest {
+            dictionary: Option<Vec<u8>>,
+            palette: SavePalette,
+            generator: Option<u64>,
+        }
+        let old = GeneratorManifest {
+            dictionary: None,
+            palette: SavePalette::default(),
+            generator: Some(3),
+        };
+        let backend = MemoryBackend::default();
+        let bytes = bincode::serialize(&old).unwrap();
+        backend.write_manifest(&bytes).unwrap();
+        let manifest = SaveManifest::load(&backend).unwrap();
+        assert_eq!((manifest.generator, manifest.layout), (Some(3), None));
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn save_format() {
+        let mut chunk = Chunk::new(2, (4, 0, -4));
+        chunk.insert((1, 2, 3), 7);
+        let save = chunk.serializable();
+        let bytes = save.to_bytes().unwrap();
+        assert_eq!(&bytes[4..8], &CHUNK_FORMAT.to_le_bytes());
+        assert_eq!(SaveData::<i32>::from_bytes(&bytes).unwrap(), save);
+        // chunks serialized before the format had a version
+        let unversioned = bincode::serialize(&save).unwrap();
+        assert_eq!(SaveData::<i32>::from_bytes(&unversioned).unwrap(), save);
+
+        let mut newer = bytes.clone();
+        newer[4..8].copy_from_slice(&(CHUNK_FORMAT + 1).to_le_bytes());
+        assert!(SaveData::<i32>::from_bytes(&newer).is_err());
+
+        // chunks saved before the format had a version, in some of the layouts they had,
+        // serialized as tuples like bincode does with structs
+        let tree = RleTree::with_tree(&chunk.data.tree());
+        let content = SaveContent::Full(tree.clone());
+        let no_data = HashMap::<(i32, i32, i32), BlockData>::new();
+        let meta = (
+            Some(String::from("hills")),
+            2u32,
+            vec![1usize],
+            Vec::<Structure>::new(),
+        );
+        let decode =
+            |bytes: Vec<u8>| Chunk::try_from(SaveData::<i32>::from_bytes(&bytes).unwrap()).unwrap();
+
+        let initial = decode(bincode::serialize(&((4, 0, -4), &tree)).unwrap());
+        assert_eq!(initial.voxel((1, 2, 3)), Some(&7));
+        assert!(initial.is_edited());
+        assert!(initial.meta().is_none());
+
+        let meta_save = ((4, 0, -4), &tree, Some(&meta));
+        let with_meta = decode(bincode::serialize(&meta_save).unwrap());
+        assert_eq!(
+            with_meta.meta().unwrap().generator.as_deref(),
+            Some("hills")
+        );
+
+        let mut block_data = HashMap::new();
+        block_data.insert((1, 2, 3), BlockData(vec![5]));
+        let block_data_save = ((4, 0, -4), &content, Some(&meta), &block_data);
+        let with_data = decode(bincode::serialize(&block_data_save).unwrap());
+        assert_eq!(with_data.block_data((1, 2, 3)), Some(&BlockData(vec![5])));
+        assert_eq!(with_data.meta().unwrap().biomes, vec![1]);
+
+        // before the bed of water was saved
+        let (generator, version, biomes, structures) = meta.clone();
+        let flow = Some(([0.5f32, 0.0f32], 3));
+        let flows_meta = (
+            generator,
+            version,
+            biomes,
+            structures,
+            vec![[1.0f32; 3]],
+            vec![flow],
+        );
+        let flows_save = (
+            (4, 0, -4),
+            &content,
+            Some(&flows_meta),
+            &no_data,
+            None::<()>,
+            false,
+        );
+        let with_flows = decode(bincode::serialize(&flows_save).unwrap());
+        assert!(!with_flows.is_edited());
+        let flow = with_flows.meta().unwrap().flow(4, (0, 0)).unwrap();
+        assert_eq!((flow.surface, flow.depth(-100)), (3, Some(102)));
+        assert_eq!(with_flows.meta().unwrap().tints, vec![[1.0; 3]]);
+
+        let user_save = (
+            (4, 0, -4),
+            &content,
+            Some(&flows_meta),
+            &no_data,
+            None::<()>,
+            true,
+            Some(UserData(vec![9])),
+        );
+        let with_user_data = decode(bincode::serialize(&user_save).unwrap());
+        assert_eq!(with_user_data.user_data(), Some(&UserData(vec![9])));
+        assert_eq!(with_user_data.voxel((1, 2, 3)), Some(&7));
+    }
+
+    #[cfg(all(feature = "savedata", feature = "bevy"))]
+    #[test]
+    pub fn save_format_blocks() {
+        use crate::simple::{Block, MeshType};
+
+        let grass = Block {
+            color: Color::rgb(0.2, 0.6, 0.1),
+            mesh_type: MeshType::Cube,
+            merge_group: 3,
+            emission: 0.5,
+            tinted: true,
+            covered_color: Some(Color::rgb(0.4, 0.3, 0.2)),
+            ..Default::default()
+        };
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.insert((1, 0, 1), grass);
+        let save = chunk.serializable();
+        let unversioned = bincode::serialize(&save).unwrap();
+        assert_eq!(SaveData::<Block>::from_bytes(&unversioned).unwrap(), save);
+
+        // blocks saved before the format had a version, in the layouts of their time
+        let tree = RleTree::with_tree(&chunk.data.tree());
+        let meta = (
+            None::<String>,
+            0u32,
+            Vec::<usize>::new(),
+            Vec::<Structure>::new(),
+        );
+        let no_data = HashMap::<(i32, i32, i32), BlockData>::new();
+        let decode = |bytes: Vec<u8>| {
+            let chunk = Chunk::try_from(SaveData::<Block>::from_bytes(&bytes).unwrap()).unwrap();
+            *chunk.voxel((1, 0, 1)).unwrap()
+        };
+
+        let initial = tree.clone().map(|block| (block.color, block.mesh_type));
+        let initial_save = ((0, 0, 0), SaveContent::Full(initial), Some(&meta), &no_data);
+        let initial_block = decode(bincode::serialize(&initial_save).unwrap());
+        assert_eq!(initial_block.color, grass.color);
+        assert_eq!(
+            (initial_block.merge_group, initial_block.emission),
+            (0, 0.0)
+        );
+
+        let emission = tree.clone().map(|block| {
+            (
+                block.color,
+                block.mesh_type,
+                block.merge_group,
+                block.emission,
+            )
+        });
+        let emission_save = (
+            (0, 0, 0),
+            SaveContent::Full(emission),
+            Some(&meta),
+            &no_data,
+        );
+        let emission_block = decode(bincode::serialize(&emission_save).unwrap());
+        assert_eq!(
+            (emission_block.merge_group, emission_block.emission),
+            (3, 0.5)
+        );
+        assert!(!emission_block.tinted);
+
+        let (generator, version, biomes, structures) = meta.clone();
+        let tints_meta = (
+            generator,
+            version,
+            biomes,
+            structures,
+            Vec::<[f32; 3]>::new(),
+        );
+        let tinted = tree.map(|block| {
+            let Block {
+                color,
+                mesh_type,
+                merge_group,
+                emission,
+                tinted,
+                ..
+            } = block;
+            (color, mesh_type, merge_group, emission, tinted)
+        });
+        let edited_save = (
+            (0, 0, 0),
+            SaveContent::Full(tinted),
+            Some(&tints_meta),
+            &no_data,
+            None::<()>,
+            true,
+        );
+        let tinted_block = decode(bincode::serialize(&edited_save).unwrap());
+        assert!(tinted_block.tinted);
+        assert_eq!(tinted_block.covered_color, None);
+        assert_eq!(
+            tinted_block,
+            Block {
+                covered_color: None,
+                ..grass
+            }
+        );
+    }
+
+    #[cfg(feature = "savedata")]
+    #[test]
+    pub fn save_thinning() {
+        let generate = |position| {
+            let mut chunk = Chunk::new(2, position);
+            for x in 0..4 {
+                for z in 0..4 {
+                    chunk.insert((x, 0, z), 1 + (x + z) % 2);
+                }
+            }
+            chunk
+        };
+        let mut map = Map::new();
+        for x in 0..3 {
+            map.try_insert(generate((x * 4, 0, 0))).unwrap();
+        }
+        let mut updates = MapUpdates::default();
+        map.set_voxel((9, 3, 1), Some(5), &mut updates);
+        assert!(map.get((8, 0, 0)).unwrap().is_edited());
+
+        let backend = MemoryBackend::default();
+        let options = SaveOptions {
+            thinning: Some(SaveThinning {
+                center: (0, 0, 0),
+                distance: 0,
+                lod: 1,
+            }),
+            ..Default::default()
+        };
+        map.save_to_with_options(&backend, &options, &IoProgress::new())
+            .unwrap();
+
+        let thinned = Map::<i32>::load_from(&backend, &IoProgress::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(thinned.get((0, 0, 0)).unwrap().thinned(), None);
+        let far = thinned.get((4, 0, 0)).unwrap();
+        assert_eq!(far.thinned(), Some(1));
+        assert_eq!(far.iter().count(), 4);
+        let hash = |map: &Map<i32>, position| map.get(position).unwrap().content_hash();
+        assert_eq!(hash(&thinned, (8, 0, 0)), hash(&map, (8, 0, 0)));
+
+        let loaded = Map::<i32>::load_from_with(&backend, generate).unwrap();
+        assert_eq!(hash(&loaded, (4, 0, 0)), hash(&map, (4, 0, 0)));
+        assert_eq!(loaded.voxel((9, 3, 1)).unwrap().into_owned(), 5);
+    }
+
+    #[cfg(all(feature = "savedata", feature = "zstd"))]
+    #[test]
+    pub fn zstd_dictionary() {
+        let backend = MemoryBackend::default();
+        let mut updates = MapUpdates::default();
+        let mut map = Map::new();
+        for x in -8..8 {
+            for z in -8..8 {
+                map.try_insert(Chunk::new(4, (x * 16, 0, z * 16))).unwrap();
+            }
+        }
+        for x in -128..128 {
+            for z in -128..128 {
+                let y = (x * x + z * z) % 7;
+                map.set_voxel((x, y, z), Some(1 + (x & 3)), &mut updates);
+            }
+        }
+        let compression = Compression::Zstd {
+            level: 3,
+            dictionary: true,
+        };
+        map.save_compressed(&backend, compression, &IoProgress::new())
+            .unwrap();
+        assert!(SaveManifest::load(&backend).unwrap().dictionary.is_some());
+
+        // chunks saved with gzip later still load next to the zstd ones
+        map.set_voxel((0, 10, 0), Some(5), &mut updates);
+        let mut edited = Map::new();
+        let chunk = map.get((0, 0, 0)).unwrap().clone();
+        edited.try_insert(chunk).unwrap();
+        edited.save_to(&backend, &IoProgress::new()).unwrap();
+        let loaded = Map::<i32>::load_from(&backend, &IoProgress::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.iter().count(), 256);
+        assert_eq!(loaded.voxel((0, 10, 0)).unwrap().into_owned(), 5);
+        assert_eq!(loaded.voxel((5, 4, 3)), map.voxel((5, 4, 3)));
+    }
+
+    #[test]
+    pub fn spawn() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        assert_eq!(map.surface_height((1, 1)), None);
+        map.set_voxel((0, -3, 0), Some(1), &mut updates);
+        map.set_voxel((1, 2, 0), Some(2), &mut updates);
+        map.set_voxel((1, 4, 0), Some(1), &mut updates);
+        assert_eq!(map.surface_height((1, 0)), Some(4));
+
+        // water at the center and a column without enough room next to it
+        map.set_voxel((0, 0, 0), Some(-1), &mut updates);
+        map.set_voxel((1, 6, 1), Some(1), &mut updates);
+        let spawn = find_spawn(&map, (0, 0), 2, 2, |&v| v > 0);
+        assert_eq!(spawn, Some((1, 5, 0)));
+        assert_eq!(find_spawn(&map, (0, 0), 0, 2, |&v| v > 0), None);
+    }
+
+    #[test]
+    pub fn warm_up() {
+        let mut warm_up = WarmUp::new(MapLayout::new(2), (1, 1, 1), 1).with_heights(0, 4);
+        let chunks = warm_up.chunks();
+        assert_eq!(chunks.len(), 18);
+        assert_eq!(chunks[0], (0, 0, 0));
+
+        let mut map = Map::<i32>::new();
+        map.try_insert(Chunk::new(2, (0, 0, 0))).unwrap();
+        let mut updates = MapUpdates::default();
+        updates.request_prefetch((4, 4, 4));
+        updates.request_prefetch((4, 8, 4));
+        assert_eq!(warm_up.queue(&map, &mut updates), 17);
+        assert!(warm_up.is_queued());
+        assert_eq!(updates.prefetch, vec![(4, 8, 4)]);
+        assert_eq!(updates.updates[&(-4, 4, 4)], ChunkUpdate::GenerateChunk);
+
+        let progress = warm_up.progress(&map, &updates);
+        assert_eq!(progress, WarmUpProgress { ready: 1, total: 18 });
+        for coords in chunks {
+            map.try_insert(Chunk::new(2, coords)).unwrap();
+        }
+        updates.updates.clear();
+        let progress = warm_up.progress(&map, &updates);
+        assert!(progress.is_done());
+        assert_eq!(progress.percent(), 100.0);
+    }
+
+    #[test]
+    pub fn placement_rules() {
+        let mut map = map();
+        let mut chunk = Chunk::new(2, (0, 0, 0));
+        chunk.set_meta(ChunkMeta {
+            biomes: vec![0, 0, 1, 1],
+            ..Default::default()
+        });
+        map.try_insert(chunk).unwrap();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((0, 0, 0), Some(1), &mut updates);
+
+        // negative voxels need ground below them, 7 never grows in biome 1
+        let rules = PlacementRules::new()
+            .require_support(|&v| v < 0, |&v| v > 0)
+            .restrict_biomes(|&v, biome| v != 7 || biome != 1);
+        assert_eq!(map.place((0, 1, 0), Some(-1), &rules, &mut updates), Ok(true));
+        let rejected = map.place((1, 1, 0), Some(-1), &rules, &mut updates).unwrap_err();
+        assert_eq!(rejected.coords, (1, 1, 0));
+        assert!(map.voxel((1, 1, 0)).is_none());
+
+        assert_eq!(map.biome((3, 0, 1)), Some(1));
+        assert!(map.place((3, 0, 1), Some(7), &rules, &mut updates).is_err());
+        assert_eq!(map.place((1, 0, 1), Some(7), &rules, &mut updates), Ok(true));
+        assert_eq!(map.place((0, 0, 0), None, &rules, &mut updates), Ok(true));
+    }
+
+    #[test]
+    pub fn neighbors() {
+        assert_eq!(Neighborhood::Faces.offsets().count(), 6);
+        assert_eq!(Neighborhood::All.offsets().count(), 26);
+
+        let mut map = map();
+        map.remove((4, 0, 0));
+        let mut faces = map
+            .neighbors((0, 0, 0), Neighborhood::Faces)
+            .map(|(offset, chunk)| (offset, chunk.position()))
+            .collect::<Vec<_>>();
+        faces.sort_unstable();
+        assert_eq!(
+            faces,
+            vec![
+                ((-1, 0, 0), (-4, 0, 0)),
+                ((0, -1, 0), (0, -4, 0)),
+                ((0, 0, -1), (0, 0, -4)),
+                ((0, 0, 1), (0, 0, 4)),
+                ((0, 1, 0), (0, 4, 0)),
+            ]
+        );
+        assert_eq!(map.neighbors((4, 4, 4), Neighborhood::All).count(), 6);
+
+        let mut count = 0;
+        map.for_each_neighbor_mut((0, 0, 0), Neighborhood::All, |_, chunk| {
+            chunk.set_light(true);
+            count += 1;
+        });
+        assert_eq!(count, 25);
+        assert!(map.get((-4, -4, -4)).unwrap().has_light());
+        assert!(!map.get((0, 0, 0)).unwrap().has_light());
+    }
+
+    #[test]
+    pub fn pick() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        updates.track_causes = true;
+        map.set_voxel((5, 1, 1), Some(1), &mut updates);
+        let origin = glam::Vec3::new(0.5, 1.5, 1.5);
+        let direction = glam::Vec3::new(1.0, 0.0, 0.0);
+
+        let pick = map.pick(origin, direction, 16.0, &updates).unwrap();
+        assert_eq!(pick.hit.position, (5, 1, 1));
+        assert_eq!(pick.chunk, (4, 0, 0));
+        assert_eq!(pick.lod, 0);
+        assert!(!pick.has_light);
+        assert_eq!(pick.pending, Some(ChunkUpdate::UpdateLightMap));
+        assert_eq!(pick.cause, Some(UpdateCause::Edit));
+        assert!(!pick.prefetched);
+        assert!(map.pick(origin, -direction, 16.0, &updates).is_none());
+    }
+
+    #[test]
+    pub fn chunk_width() {
+        let mut map = Map::<i32>::new();
+        assert_eq!(map.layout(), None);
+        map.try_insert(Chunk::new(2, (0, 0, 0))).unwrap();
+        assert_eq!(map.layout(), Some(MapLayout::new(2)));
+        assert_eq!(MapLayout::new(2).chunk_origin((-1, 5, 4)), (-4, 4, 4));
+
+        let err = map.try_insert(Chunk::new(3, (4, 0, 0))).unwrap_err();
+        assert_eq!(err.expected, 4);
+        assert_eq!(err.found, 8);
+        assert!(map.get((4, 0, 0)).is_none());
+
+        let mut map = Map::<i32>::with_layout(MapLayout::new(3));
+        assert!(map.try_insert(Chunk::new(2, (0, 0, 0))).is_err());
+    }
+
+    #[test]
+    pub fn chunk_width_mixed() {
+        let chunks = vec![Chunk::new(2, (0, 0, 0)), Chunk::new(3, (8, 0, 0))];
+        let error = Map::<i32>::try_with_chunks(chunks.clone()).unwrap_err();
+        assert_eq!(error.position, (8, 0, 0));
+        assert_eq!((error.expected, error.found), (4, 8));
+
+        // none of the chunks are inserted if one doesn't fit
+        let mut map = Map::<i32>::new();
+        assert!(map.try_insert_many(chunks).is_err());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    pub fn mixed_layout() {
+        let layout = MapLayout::mixed(2, 4);
+        assert!(layout.is_mixed() && !MapLayout::new(2).is_mixed());
+        assert_eq!(layout.widths().collect::<Vec<_>>(), vec![4, 8, 16]);
+
+        let mut map = Map::<i32>::with_layout(layout);
+        for x in 0..4 {
+            let mut chunk = Chunk::new(2, (x * 4, 0, 0));
+            chunk.insert((0, 0, 0), x + 1);
+            map.try_insert(chunk).unwrap();
+        }
+        let mut big = Chunk::new(3, (16, 0, 0));
+        big.insert((0, 0, 0), 5);
+        map.try_insert(big).unwrap();
+        assert!(map.try_insert(Chunk::new(5, (32, 0, 0))).is_err());
+        let error = map.try_insert(Chunk::new(2, (20, 4, 4))).unwrap_err();
+        assert_eq!((error.expected, error.found), (8, 4));
+        assert_eq!(
+            map.chunk_containing((19, 7, 7)).unwrap().position(),
+            (16, 0, 0)
+        );
+        assert_eq!(
+            map.chunk_containing((15, 0, 0)).unwrap().position(),
+            (12, 0, 0)
+        );
+        assert!(map.chunk_containing((24, 0, 0)).is_none());
+
+        // the big chunk touches the small one with its face, the small one a quarter of it
+        let of_small = map
+            .neighbors((12, 0, 0), Neighborhood::Faces)
+            .map(|(offset, chunk)| (offset, chunk.position()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            of_small,
+            vec![((-1, 0, 0), (8, 0, 0)), ((1, 0, 0), (16, 0, 0))]
+        );
+        let mut of_big = map
+            .neighbors((16, 0, 0), Neighborhood::All)
+            .map(|(_, chunk)| chunk.position())
+            .collect::<Vec<_>>();
+        of_big.sort_unstable();
+        assert_eq!(of_big, vec![(8, 0, 0), (12, 0, 0)]);
+
+        // edits on the border schedule the neighbour of the other width
+        let mut updates = MapUpdates::default();
+        assert!(map.set_voxel((15, 1, 1), Some(6), &mut updates));
+        assert_eq!(updates.updates[&(16, 0, 0)], ChunkUpdate::UpdateLightMap);
+        let mut updates = MapUpdates::default();
+        assert!(map.set_voxel((16, 1, 1), Some(7), &mut updates));
+        assert_eq!(updates.updates[&(12, 0, 0)], ChunkUpdate::UpdateLightMap);
+
+        // the small chunks only cover half of the cube 16 wide at the origin
+        assert!(map.coarsen((0, 0, 0), 16).is_none());
+        assert!(map.coarsen((0, 0, 0), 8).is_none());
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    if (y, z) != (0, 0) {
+                        let chunk = Chunk::new(2, (x * 4, y * 4, z * 4));
+                        map.try_insert(chunk).unwrap();
+                    }
+                }
+            }
+        }
+        let replaced = map.coarsen((0, 0, 0), 8).unwrap();
+        assert_eq!(replaced.len(), 8);
+        assert_eq!(map.get((0, 0, 0)).unwrap().width(), 8);
+        assert_eq!(
+            map.chunk_containing((4, 0, 0)).unwrap().voxel((4, 0, 0)),
+            Some(&2)
+        );
+        assert_eq!(map.get((4, 0, 0)).unwrap().position(), (0, 0, 0));
+        assert!(map.get_at_origin((4, 0, 0)).is_none());
+
+        let big = map.split((16, 0, 0)).unwrap();
+        assert_eq!(big.width(), 8);
+        assert_eq!(map.chunks_in((16, 0, 0), (23, 7, 7)).count(), 8);
+        assert_eq!(
+            map.chunk_containing((16, 1, 1)).unwrap().voxel((0, 1, 1)),
+            Some(&7)
+        );
+        assert_eq!(
+            map.chunk_containing((16, 0, 0)).unwrap().voxel((0, 0, 0)),
+            Some(&5)
+        );
+        assert!(map.split((16, 0, 0)).is_none());
+    }
+
+    #[test]
+    pub fn voxel_world() {
+        use glam::Vec3;
+
+        use crate::terrain::{Biome, Layer, Program};
+
+        let program = Program::<i32>::build()
+            .chunk_size(3)
+            .biome(
+                Biome::build()
+                    .height(10.0)
+                    .layer(Layer::new(1, 6.0))
+                    .layer(Layer::new(2, 4.0))
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        // the first generated chunk would not fit the map
+        let narrow = Map::with_layout(MapLayout::new(2));
+        assert!(matches!(
+            VoxelWorld::with_map(program.clone(), narrow),
+            Err(crate::error::Error::LayoutWidth {
+                layout: 4,
+                program: 8
+            })
+        ));
+        assert!(VoxelWorld::with_map(program.clone(), Map::new()).is_ok());
+
+        let mut world = VoxelWorld::new(program.clone());
+        assert_eq!(world.request_area((0, 0, 0), (15, 15, 15)), 8);
+        assert_eq!(world.generate(&mut Vec::new()), 8);
+        assert_eq!(world.request_area((0, 0, 0), (15, 15, 15)), 0);
+        assert_eq!(world.map().len(), 8);
+        assert_eq!(world.chunk_state((15, 3, 0)), Some(ChunkState::Generated));
+        assert_eq!(world.chunk_state((16, 0, 0)), None);
+
+        assert!(world.get_block((3, 2, 3)).is_some());
+        assert!(world.get_block((3, 14, 3)).is_none());
+        assert!(world.set_block((3, 14, 3), Some(7)));
+        assert!(!world.set_block((3, 16, 3), Some(7)));
+        assert_eq!(world.get_block((3, 14, 3)).as_deref(), Some(&7));
+        assert_eq!(
+            world.updates().updates[&(0, 8, 0)],
+            ChunkUpdate::UpdateLightMap
+        );
+        let hit = world
+            .raycast(Vec3::new(3.5, 15.5, 3.5), Vec3::new(0.0, -1.0, 0.0), 8.0)
+            .unwrap();
+        assert_eq!(hit.position, (3, 14, 3));
+
+        #[cfg(feature = "savedata")]
+        {
+            let mut config = crate::config::VoxelConfig::default();
+            config.limits.generate = Some(3);
+            config.save.backups = 2;
+            let configured = VoxelWorld::new(program.clone()).with_config(config.clone());
+            assert_eq!(configured.updates().limits.generate, Some(3));
+            assert_eq!(configured.save_options.backups, 2);
+            assert_eq!(configured.config(), &config);
+        }
+
+        #[cfg(feature = "savedata")]
+        {
+            let dir = std::env::temp_dir().join(format!("bevy_voxel_world_{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            world.save(&dir).unwrap();
+            let loaded = VoxelWorld::load(program, &dir).unwrap();
+            assert_eq!(loaded.map().len(), 8);
+            assert_eq!(loaded.get_block((3, 14, 3)).as_deref(), Some(&7));
+            assert_eq!(loaded.updates().updates.len(), 8);
+            let small = Program::<i32>::build()
+                .chunk_size(2)
+                .biome(Biome::build().layer(Layer::new(1, 6.0)).build())
+                .build()
+                .unwrap();
+            assert!(VoxelWorld::load(small, &dir).is_err());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn prefetch() {
+        let map = map();
+        let prefetch = Prefetch {
+            lookahead: 1.0,
+            radius: 0,
+            heights: Some((0, 0)),
+            max_chunks: 64,
+        };
+        let position = glam::Vec3::new(1.0, 1.0, 1.0);
+        let chunks = prefetch.chunks_along(&map, position, glam::Vec3::new(12.0, 0.0, 0.0));
+        assert_eq!(chunks, vec![(8, 0, 0), (12, 0, 0)]);
+        let chunks = prefetch.chunks_along(&map, position, glam::Vec3::zero());
+        assert!(chunks.is_empty());
+
+        let mut updates = MapUpdates::default();
+        updates.request((8, 0, 0), ChunkUpdate::GenerateChunk);
+        for coords in vec![(8, 0, 0), (12, 0, 0), (16, 0, 0), (12, 0, 0)] {
+            updates.request_prefetch(coords);
+        }
+        assert_eq!(updates.drain_prefetch(1), vec![(12, 0, 0)]);
+        assert_eq!(updates.drain_prefetch(4), vec![(16, 0, 0)]);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Dense(f32);
+
+    impl Voxel for Dense {
+        fn average(data: &[Self]) -> Option<Self> {
+            data.first().cloned()
+        }
+
+        fn can_merge(&self) -> bool {
+            false
+        }
+    }
+
+    #[cfg(feature = "savedata")]
+    impl crate::serialize::SerDePartialEq<Self> for Dense {
+        fn serde_eq(&self, other: &Self) -> bool {
+            self == other
+        }
+    }
+
+    impl crate::mesh::VoxelExt for Dense {
+        fn mesh(
+            &self,
+            _coords: (i32, i32, i32),
+            _map: &Map<Self>,
+            _chunk: &Chunk<Self>,
+            _width: usize,
+        ) -> crate::mesh::MeshPart {
+            // only the density matters to these tests, so there is nothing to draw
+            crate::mesh::MeshPart {
+                positions: Vec::new(),
+                shades: Vec::new(),
+                colors: Vec::new(),
+                indices: Vec::new(),
+                transparent: crate::mesh::Transparent::No,
+            }
+        }
+
+        fn density(&self) -> f32 {
+            self.0
+        }
+
+        fn face_hidden_by(&self, neighbour: &Self) -> bool {
+            neighbour.0 >= 1.0
+        }
+    }
+
+    #[test]
+    pub fn visibility() {
+        use crate::mesh::{update_visibility, Face};
+
+        let chunks = vec![Chunk::new(2, (0, 0, 0)), Chunk::new(2, (4, 0, 0))];
+        let mut map = Map::try_with_chunks(chunks).unwrap();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((3, 1, 1), Some(Dense(1.0)), &mut updates);
+        map.set_voxel((4, 1, 1), Some(Dense(1.0)), &mut updates);
+        map.set_voxel((3, 2, 1), Some(Dense(0.5)), &mut updates);
+        map.set_voxel((0, 1, 1), Some(Dense(1.0)), &mut updates);
+        assert!(update_visibility(&mut map, (0, 0, 0)));
+
+        let visibility = map.get((0, 0, 0)).unwrap().visibility().unwrap().clone();
+        // hidden by the voxel in the next chunk, but not by the glass above
+        assert!(!visibility.is_visible((3, 1, 1), Face::Left));
+        assert!(visibility.is_visible((3, 1, 1), Face::Top));
+        assert!(!visibility.is_visible((3, 2, 1), Face::Bottom));
+        // faces towards chunks that aren't loaded are hidden
+        assert!(!visibility.is_visible((0, 1, 1), Face::Right));
+        assert!(visibility.is_visible((0, 1, 1), Face::Top));
+        assert_eq!(visibility.get((0, 0, 0)), 0);
+
+        // editing the border of the neighbour clears the mask
+        map.set_voxel((4, 1, 1), None, &mut updates);
+        assert!(map.get((0, 0, 0)).unwrap().visibility().is_none());
+        update_visibility(&mut map, (0, 0, 0));
+        let visibility = map.get((0, 0, 0)).unwrap().visibility().unwrap();
+        assert!(visibility.is_visible((3, 1, 1), Face::Left));
+    }
+
+    #[test]
+    pub fn occlusion_between() {
+        let mut map = Map::try_with_chunks(vec![Chunk::new(3, (0, 0, 0))]).unwrap();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((0, 0, 0), Some(Dense(1.0)), &mut updates);
+        assert_eq!(map.occlusion_between((0, 0, 0), (6, 0, 0)), 0.0);
+
+        map.set_voxel((2, 0, 0), Some(Dense(0.5)), &mut updates);
+        map.set_voxel((4, 0, 0), Some(Dense(0.5)), &mut updates);
+        assert_eq!(map.occlusion_between((0, 0, 0), (6, 0, 0)), 0.75);
+
+        map.set_voxel((3, 0, 0), Some(Dense(1.0)), &mut updates);
+        assert_eq!(map.occlusion_between((0, 0, 0), (6, 0, 0)), 1.0);
+        assert_eq!(map.occlusion_between((0, 1, 0), (6, 1, 0)), 0.0);
+    }
+
+    #[test]
+    pub fn overlay() {
+        use rand::SeedableRng;
+
+        let chunks = vec![Chunk::new(2, (0, 0, 0)), Chunk::new(2, (0, 4, 0))];
+        let mut map = Map::try_with_chunks(chunks).unwrap();
+        let mut updates = MapUpdates::default();
+        for x in 0..4 {
+            for z in 0..4 {
+                map.set_voxel((x, 1, z), Some(1), &mut updates);
+            }
+        }
+        // water doesn't get covered
+        map.set_voxel((0, 2, 0), Some(2), &mut updates);
+        // and the top of the map has no room for snow
+        map.set_voxel((3, 7, 3), Some(1), &mut updates);
+        updates.updates.clear();
+
+        let mut snow = Overlay::new(-1, |&v| v == 1, (0, 0), (3, 3));
+        snow.rate = 256;
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        assert_eq!(snow.step(&mut map, &mut updates, &mut rng), 14);
+        assert_eq!(map.voxel((1, 2, 2)).unwrap().into_owned(), -1);
+        assert!(map.voxel((0, 3, 0)).is_none());
+        assert_eq!(updated(&updates), [(0, 0, 0)].iter().copied().collect());
+        assert_eq!(snow.step(&mut map, &mut updates, &mut rng), 0);
+
+        snow.falling = false;
+        assert_eq!(snow.step(&mut map, &mut updates, &mut rng), 14);
+        assert!(map.voxel((1, 2, 2)).is_none());
+    }
+
+    #[test]
+    pub fn insert_many() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((0, 0, 0), Some(1), &mut updates);
+        let chunks = (0..4).map(|x| Chunk::new(2, (x * 4, 8, 0)));
+        map.try_insert_many(chunks).unwrap();
+        assert_eq!(map.len(), 31);
+        assert_eq!(map.chunks_in((0, 8, 0), (20, 8, 0)).count(), 4);
+        assert_eq!(map.voxel((0, 0, 0)).unwrap().into_owned(), 1);
+
+        // replacing chunks keeps a single entry per position
+        map.try_insert_many(vec![Chunk::new(2, (0, 0, 0))]).unwrap();
+        assert_eq!(map.len(), 31);
+        assert_eq!(map.chunks_in((0, 0, 0), (0, 0, 0)).count(), 1);
+        assert!(map.voxel((0, 0, 0)).is_none());
+    }
+
+    #[test]
+    pub fn iter_within() {
+        let mut map = map();
+        let positions = |map: &Map<i32>, radius| {
+            let mut positions = map
+                .iter_within((0, 0, 0), radius)
+                .map(Chunk::position)
+                .collect::<Vec<_>>();
+            positions.sort_unstable();
+            positions
+        };
+        assert_eq!(positions(&map, 0), vec![(0, 0, 0)]);
+        assert_eq!(
+            positions(&map, 1),
+            vec![(-4, 0, 0), (0, -4, 0), (0, 0, -4), (0, 0, 0)]
+        );
+        // the corner chunk below is sqrt(3) voxels away
+        assert_eq!(positions(&map, 2).len(), 8);
+        assert_eq!(positions(&map, 100).len(), 27);
+
+        map.get_mut((4, 0, 0)).unwrap().set_lod(1);
+        let lod = map.iter_by_lod(1).map(Chunk::position).collect::<Vec<_>>();
+        assert_eq!(lod, vec![(4, 0, 0)]);
+        assert_eq!(map.iter_by_lod(0).count(), 26);
+    }
+
+    #[test]
+    pub fn invalidations() {
+        let map = map();
+        let mut updates = MapUpdates::default();
+        updates.invalidate_region((0, 0, 0), (4, 0, 0));
+        updates.invalidate_all_lighting();
+        updates.invalidate_region((0, 0, 0), (0, 0, 0));
+        assert_eq!(updates.expand_invalidations(&map, 10), 10);
+        assert_eq!(updates.updates.len(), 10);
+        assert!(updates.has_invalidations());
+        assert_eq!(updates.expand_invalidations(&map, 100), 17);
+        assert!(!updates.has_invalidations());
+        assert!(updates
+            .updates
+            .values()
+            .all(|update| *update == ChunkUpdate::UpdateLightMap));
+
+        let mut updates = MapUpdates::default();
+        updates.invalidate_region((0, 0, 0), (4, 0, 0));
+        updates.invalidate_region((-8, -8, -8), (-5, -5, -5));
+        assert_eq!(updates.expand_invalidations(&map, 100), 2);
+        assert_eq!(updated(&updates), [(0, 0, 0), (4, 0, 0)].iter().copied().collect());
+    }
+
+    #[test]
+    pub fn shard_split() {
+        let shards = ShardLayout::new(MapLayout::new(2), 2);
+        assert_eq!(shards.region_of((-1, 100, 8)), (-1, 1));
+        let regions = shards.split(map()).unwrap();
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions[&(0, 0)].len(), 12);
+        assert_eq!(regions[&(-1, -1)].len(), 3);
+        assert_eq!(regions.values().map(Map::len).sum::<usize>(), 27);
+        assert!(regions[&(-1, 0)].get((-4, 4, 4)).is_some());
+    }
+
+    #[test]
+    pub fn invalidate() {
+        let mut map = map();
+        let mut updates = MapUpdates::default();
+        assert!(!map.invalidate((8, 0, 0), ChunkUpdate::UpdateMesh, &mut updates));
+
+        // a chunk without a light map can't just be meshed again
+        assert!(map.invalidate((0, 0, 0), ChunkUpdate::UpdateMesh, &mut updates));
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateLightMap);
+        assert_eq!(updates.updates.len(), 1);
+
+        let pipeline = ChunkPipeline::default();
+        for chunk in map.iter_mut() {
+            chunk.transition(&pipeline, &ChunkUpdate::UpdateLightMap).unwrap();
+            chunk.transition(&pipeline, &ChunkUpdate::UpdateLight).unwrap();
+        }
+        let mut updates = MapUpdates::default();
+        map.invalidate((0, 0, 0), ChunkUpdate::UpdateMesh, &mut updates);
+        assert_eq!(updates.updates.len(), 1);
+        map.invalidate((0, 0, 0), ChunkUpdate::UpdateLightMap, &mut updates);
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateLightMap);
+        assert_eq!(updates.updates[&(4, 4, 4)], ChunkUpdate::UpdateLight);
+        assert_eq!(updates.updates.len(), 27);
+
+        let mut updates = MapUpdates::with_pipeline(ChunkPipeline::unlit());
+        map.invalidate_all(ChunkUpdate::UpdateLightMap, &mut updates);
+        assert_eq!(updates.iter_kind(ChunkUpdate::UpdateMesh).count(), 27);
+    }
+
+    #[test]
+    pub fn update_limits() {
+        let mut updates = MapUpdates::default();
+        updates.limits.generate = Some(2);
+        updates.limits.eviction = Eviction::Oldest;
+        for x in 0..4 {
+            updates.request((x * 4, 0, 0), ChunkUpdate::GenerateChunk);
+        }
+        updates.request((0, 0, 0), ChunkUpdate::UpdateMesh);
+        assert!(updates.is_saturated(ChunkUpdate::GenerateChunk));
+        assert_eq!(updates.pressure(ChunkUpdate::GenerateChunk), 2.0);
+        assert_eq!(updates.pressure(ChunkUpdate::UpdateMesh), 0.0);
+
+        let mut evicted = updates.enforce_limits();
+        evicted.sort_unstable();
+        let generate = ChunkUpdate::GenerateChunk;
+        assert_eq!(evicted, vec![((0, 0, 0), generate.clone()), ((4, 0, 0), generate)]);
+        assert_eq!(updates.updates.len(), 2);
+        assert!(updates.enforce_limits().is_empty());
+
+        // the chunks farthest from the focus go first
+        updates.limits.eviction = Eviction::Farthest;
+        updates.focus = Some((16, 0, 0));
+        updates.request((20, 0, 0), ChunkUpdate::GenerateChunk);
+        let evicted = updates.enforce_limits();
+        assert_eq!(evicted, vec![((8, 0, 0), ChunkUpdate::GenerateChunk)]);
+        assert!(updates.updates.contains_key(&(20, 0, 0)));
+        assert!(updates.is_saturated(ChunkUpdate::GenerateChunk));
+    }
+
+    #[test]
+    pub fn cancel_generation() {
+        let mut updates = MapUpdates::default();
+        for x in 0..4 {
+            updates.request((x * 8, 0, 0), ChunkUpdate::GenerateChunk);
+        }
+        updates.request((32, 0, 0), ChunkUpdate::UpdateMesh);
+        updates.request((24, 8, 0), ChunkUpdate::GenerateChunk);
+        updates.request_prefetch((0, 0, 24));
+        assert!(updates.cancel_generation().is_empty());
+
+        // whole columns are cancelled, other stages are left alone
+        updates.focus = Some((0, 0, 0));
+        updates.interest = Some(16);
+        let mut cancelled = updates.cancel_generation();
+        cancelled.sort_unstable();
+        assert_eq!(cancelled, vec![(0, 0, 24), (24, 0, 0), (24, 8, 0)]);
+        assert_eq!(updates.iter_kind(ChunkUpdate::GenerateChunk).count(), 3);
+        assert_eq!(updates.updates[&(32, 0, 0)], ChunkUpdate::UpdateMesh);
+        assert!(updates.prefetch.is_empty());
+        assert_eq!(updates.cancelled(), 3);
+
+        updates.clear();
+        assert_eq!(updates.interest, Some(16));
+    }
+
+    #[test]
+    pub fn update_causes() {
+        let mut map = meshed_map();
+        let mut updates = MapUpdates::default();
+        map.set_voxel((0, 0, 0), Some(1), &mut updates);
+        assert_eq!(updates.cause((0, 0, 0)), None);
+
+        let mut updates = MapUpdates::default();
+        updates.track_causes = true;
+        map.set_voxel((0, 0, 0), Some(1), &mut updates);
+        assert_eq!(updates.cause((0, 0, 0)), Some(UpdateCause::Edit));
+        assert_eq!(updates.cause((-4, 0, 0)), Some(UpdateCause::Dependency));
+        // an earlier stage takes over the cause, a later one doesn't
+        updates.request_because((-4, 0, 0), ChunkUpdate::GenerateChunk, UpdateCause::Streaming);
+        updates.request_because((0, 0, 0), ChunkUpdate::UpdateMesh, UpdateCause::Lod);
+        assert_eq!(updates.cause((-4, 0, 0)), Some(UpdateCause::Streaming));
+        assert_eq!(updates.cause((0, 0, 0)), Some(UpdateCause::Edit));
+
+        assert_eq!(updates.drain_kind(ChunkUpdate::UpdateLightMap, 1), vec![(0, 0, 0)]);
+        let chunk = map.get_mut((0, 0, 0)).unwrap();
+        updates.complete(chunk, ChunkUpdate::UpdateLightMap).unwrap();
+        assert_eq!(updates.updates[&(0, 0, 0)], ChunkUpdate::UpdateLight);
+        assert_eq!(updates.cause((0, 0, 0)), Some(UpdateCause::Edit));
+
+        let counts = updates.cause_counts();
+        assert_eq!(counts[&UpdateCause::Streaming], 1);
+        assert_eq!(counts[&UpdateCause::Dependency], 2);
+
+        // completing the last stage drops the cause
+        for stage in &[ChunkUpdate::UpdateLight, ChunkUpdate::UpdateMesh] {
+            let drained = updates.drain_kind_filter(stage.clone(), 1, |c| c == (0, 0, 0));
+            assert_eq!(drained, vec![(0, 0, 0)]);
+            let chunk = map.get_mut((0, 0, 0)).unwrap();
+            updates.complete(chunk, stage.clone()).unwrap();
+        }
+        assert!(!updates.causes.contains_key(&(0, 0, 0)));
+
+        // other stages draining before a chunk completes keep its cause, forgetting the chunk
+        // drops it
+        assert_eq!(
+            updates.drain_kind(ChunkUpdate::GenerateChunk, usize::MAX),
+            vec![(-4, 0, 0)]
+        );
+        updates.drain_kind(ChunkUpdate::UpdateLightMap, usize::MAX);
+        assert_eq!(
+            updates.causes.get(&(-4, 0, 0)),
+            Some(&UpdateCause::Streaming)
+        );
+        updates.forget((-4, 0, 0));
+        assert!(!updates.causes.contains_key(&(-4, 0, 0)));
+
+        let mut chunk = Chunk::<i32>::new(2, (40, 0, 0));
+        let streaming = UpdateCause::Streaming;
+        updates.request_because((40, 0, 0), ChunkUpdate::GenerateChunk, streaming);
+        updates.request_because((0, 0, 0), ChunkUpdate::UpdateMesh, UpdateCause::Lod);
+        updates.drain_kind(ChunkUpdate::GenerateChunk, usize::MAX);
+        updates.drain_kind(ChunkUpdate::UpdateMesh, usize::MAX);
+        let stage = ChunkUpdate::GenerateChunk;
+        updates.complete(&mut chunk, stage).unwrap();
+        assert_eq!(updates.cause((40, 0, 0)), Some(UpdateCause::Streaming));
+    }
+
+    #[test]
+    pub fn border_light() {
+        let indices = BorderLight::coords(4)
+            .map(|coords| BorderLight::index(4, coords).unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(indices.len(), 6 * 6 * 6 - 4 * 4 * 4);
+        assert_eq!(indices.len(), BorderLight::new(4).light.len());
+        assert!(indices.iter().all(|&index| index < indices.len()));
+
+        let mut map = map();
+        assert_eq!(map.capture_border_light((0, 0, 0)), None);
+        let neighbour = map.get_mut((4, 0, 0)).unwrap();
+        neighbour.insert_light((0, 1, 2), 0.5);
+        neighbour.set_light(true);
+        let border = map.capture_border_light((0, 0, 0)).unwrap();
+        assert!((border.get((4, 1, 2)).unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(border.get((-1, 1, 2)), Some(1.0));
+        assert_eq!(border.get((1, 1, 2)), None);
+
+        let chunk = map.get_mut((0, 0, 0)).unwrap();
+        chunk.set_border_light(Some(border.clone()));
+        #[cfg(feature = "savedata")]
+        {
+            let loaded = Chunk::try_from(chunk.serializable()).unwrap();
+            assert_eq!(loaded.border_light(), Some(&border));
+        }
+    }
+
+    #[test]
+    pub fn overlay_layer() {
+        let mut map = map();
+        let mut layer = OverlayLayer::new(map.layout().unwrap());
+        layer.insert((1, 2, 3), 7);
+        layer.insert((-1, 0, 0), 8);
+        assert_eq!(layer.get((1, 2, 3)), Some(&7));
+        assert_eq!(layer.chunks().count(), 2);
+        let mut dirty = layer.take_dirty();
+        dirty.sort();
+        assert_eq!(dirty, vec![(-4, 0, 0), (0, 0, 0)]);
+        assert!(layer.take_dirty().is_empty());
+        assert!(map.insert_overlay(1, layer).is_none());
+
+        // the terrain doesn't see the overlay
+        assert!(map.voxel((1, 2, 3)).is_none());
+
+        let layer = map.overlay_mut(1).unwrap();
+        assert_eq!(layer.remove((-1, 0, 0)), Some(8));
+        assert_eq!(layer.remove((-1, 0, 0)), None);
+        assert!(layer.chunk((-4, 0, 0)).is_none());
+        layer.mark_dirty((0, 0, 0));
+        layer.mark_dirty((8, 0, 0));
+        let mut dirty = layer.take_dirty();
+        dirty.sort();
+        assert_eq!(dirty, vec![(-4, 0, 0), (0, 0, 0)]);
+        layer.clear();
+        assert!(layer.is_empty());
+        assert_eq!(layer.take_dirty(), vec![(0, 0, 0)]);
+        assert!(map.remove_overlay(1).is_some());
+        assert_eq!(map.overlays().count(), 0);
+    }
+
+    #[test]
+    pub fn detail() {
+        // grass, dirt and stone
+        let mut flat = Chunk::<i32>::new(3, (0, 0, 0));
+        for (x, z) in (0..8).flat_map(|x| (0..8).map(move |z| (x, z))) {
+            for y in 0..4 {
+                flat.insert((x, y, z), 1 + y.min(2));
+            }
+        }
+        flat.update_detail();
+        assert_eq!(flat.detail(), 3.0);
+
+        // a house with a window on the same terrain
+        let mut house = flat.clone();
+        for (x, y, z) in (0..27).map(|i| (2 + i / 9, 4 + i / 3 % 3, 2 + i % 3)) {
+            if (x, y, z) != (3, 5, 2) {
+                house.insert((x, y, z), 4);
+            }
+        }
+        assert_eq!(house.detail(), 3.0);
+        house.update_detail();
+        assert!(house.detail() > flat.detail());
+
+        #[cfg(feature = "savedata")]
+        {
+            let loaded = Chunk::from_save_data(house.serializable(), None).unwrap();
+            assert_eq!(loaded.detail(), house.detail());
+        }
+    }
 }
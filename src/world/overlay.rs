@@ -0,0 +1,115 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    collections::{lod_tree::Voxel, LodTree, VoxelStorage},
+    world::MapLayout,
+};
+
+/// A sparse layer of voxels kept apart from the terrain of a map, e.g. construction previews
+/// or zone markers.
+///
+/// Only the chunks with overlay voxels in them are stored. Overlays aren't saved with the
+/// map, and raycasts, collisions and lighting only see the terrain. They are composited with
+/// the terrain when they are meshed, see `mesh::generate_overlay_buffers`, so faces hidden
+/// by terrain are culled.
+#[derive(Debug, Clone)]
+pub struct OverlayLayer<T> {
+    layout: MapLayout,
+    chunks: HashMap<(i32, i32, i32), LodTree<T>>,
+    visible: bool,
+    /// The chunks changed since the layer was last meshed.
+    dirty: HashSet<(i32, i32, i32)>,
+}
+
+impl<T: Voxel> OverlayLayer<T> {
+    pub fn new(layout: MapLayout) -> Self {
+        Self {
+            layout,
+            chunks: HashMap::new(),
+            visible: true,
+            dirty: HashSet::new(),
+        }
+    }
+
+    pub fn layout(&self) -> MapLayout {
+        self.layout
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Shows or hides the layer without dropping its voxels.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the overlay voxel at world coordinates `coords`.
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        let origin = self.layout.chunk_origin(coords);
+        self.chunks.get(&origin)?.voxel(local(origin, coords))
+    }
+
+    /// Sets the overlay voxel at world coordinates `coords`.
+    pub fn insert(&mut self, coords: (i32, i32, i32), voxel: T) {
+        let origin = self.layout.chunk_origin(coords);
+        let width = self.layout.chunk_width;
+        self.chunks
+            .entry(origin)
+            .or_insert_with(|| LodTree::new(width))
+            .insert(local(origin, coords), voxel);
+        self.dirty.insert(origin);
+    }
+
+    /// Removes the overlay voxel at world coordinates `coords`, dropping its chunk if it was
+    /// the last one in it.
+    pub fn remove(&mut self, coords: (i32, i32, i32)) -> Option<T> {
+        let origin = self.layout.chunk_origin(coords);
+        let tree = self.chunks.get_mut(&origin)?;
+        let removed = tree
+            .remove(local(origin, coords))
+            .map(|voxel| voxel.into_owned());
+        if removed.is_some() {
+            if tree.elements().next().is_none() {
+                self.chunks.remove(&origin);
+            }
+            self.dirty.insert(origin);
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.dirty.extend(self.chunks.keys().copied());
+        self.chunks.clear();
+    }
+
+    /// Returns the overlay voxels of the chunk whose origin is at `origin`.
+    pub fn chunk(&self, origin: (i32, i32, i32)) -> Option<&LodTree<T>> {
+        self.chunks.get(&origin)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = ((i32, i32, i32), &LodTree<T>)> {
+        self.chunks.iter().map(|(&origin, tree)| (origin, tree))
+    }
+
+    /// Has the chunk at `origin` meshed again, e.g. because the terrain around it changed.
+    pub fn mark_dirty(&mut self, origin: (i32, i32, i32)) {
+        if self.chunks.contains_key(&origin) {
+            self.dirty.insert(origin);
+        }
+    }
+
+    /// Returns the origins of the chunks changed since this was last called, including the
+    /// ones that lost all their voxels.
+    pub fn take_dirty(&mut self) -> Vec<(i32, i32, i32)> {
+        self.dirty.drain().collect()
+    }
+}
+
+fn local(origin: (i32, i32, i32), (x, y, z): (i32, i32, i32)) -> (i32, i32, i32) {
+    (x - origin.0, y - origin.1, z - origin.2)
+}
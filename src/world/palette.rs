@@ -0,0 +1,67 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::lod_tree::Voxel;
+
+/// Voxels that can be saved by name with `Map::save_with_palette`, so that renumbering or
+/// reordering the kinds of voxels between versions of a game doesn't change old saves.
+///
+/// A voxel is restored from its id alone, so voxels with the same id have to be
+/// interchangeable.
+pub trait PaletteVoxel: Voxel {
+    /// A name for the kind of the voxel that stays the same between versions, e.g. `"stone"`.
+    fn palette_id(&self) -> Cow<'_, str>;
+
+    /// The voxel named `id`, or `None` if the game doesn't know it (anymore).
+    fn from_palette_id(id: &str) -> Option<Self>;
+}
+
+/// The ids of every kind of voxel in a save, stored once in its `SaveManifest`. Chunks saved
+/// with a palette store indices into it instead of the voxels themselves.
+///
+/// Ids are only ever appended, so chunks written by earlier saves stay valid.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavePalette {
+    ids: Vec<String>,
+}
+
+impl SavePalette {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    /// Returns the index of `id`, adding it to the palette if it isn't in it yet.
+    pub fn index(&mut self, id: &str) -> u32 {
+        let index = match self.ids.iter().position(|other| other == id) {
+            Some(index) => index,
+            None => {
+                self.ids.push(id.to_string());
+                self.ids.len() - 1
+            }
+        };
+        index as u32
+    }
+
+    /// Looks up the voxel of every id, in palette order. Fails on the first id the game
+    /// doesn't know instead of loading chunks with missing voxels.
+    pub fn voxels<T: PaletteVoxel>(&self) -> bincode::Result<Vec<T>> {
+        self.ids
+            .iter()
+            .map(|id| {
+                T::from_palette_id(id).ok_or_else(|| {
+                    bincode::ErrorKind::Custom(format!("unknown voxel id {:?} in palette", id))
+                        .into()
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,176 @@
+use std::{error::Error, fmt};
+
+#[cfg(feature = "bevy")]
+use bevy::diagnostic::DiagnosticId;
+
+use crate::world::ChunkUpdate;
+
+/// How far a chunk has made it through the update pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChunkState {
+    Generated,
+    LightMapped,
+    Lit,
+    Meshed,
+}
+
+impl Default for ChunkState {
+    fn default() -> Self {
+        Self::Generated
+    }
+}
+
+impl ChunkState {
+    /// Returns the state of a chunk after `stage` ran on it.
+    pub fn after(stage: &ChunkUpdate) -> Self {
+        match stage {
+            ChunkUpdate::GenerateChunk => Self::Generated,
+            ChunkUpdate::UpdateLightMap => Self::LightMapped,
+            ChunkUpdate::UpdateLight => Self::Lit,
+            ChunkUpdate::UpdateMesh => Self::Meshed,
+        }
+    }
+}
+
+/// A pipeline stage was run on a chunk that hadn't passed the stages before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkStateError {
+    pub position: (i32, i32, i32),
+    pub state: ChunkState,
+    pub stage: ChunkUpdate,
+}
+
+impl fmt::Display for ChunkStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "can't run {:?} on chunk {:?} in state {:?}",
+            self.stage, self.position, self.state
+        )
+    }
+}
+
+impl Error for ChunkStateError {}
+
+/// The stages every chunk passes through, in order.
+///
+/// Stages can be skipped, e.g. to render without lighting: requests for a skipped stage are
+/// forwarded to the next stage that is part of the pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkPipeline {
+    stages: Vec<ChunkUpdate>,
+}
+
+impl Default for ChunkPipeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![
+                ChunkUpdate::GenerateChunk,
+                ChunkUpdate::UpdateLightMap,
+                ChunkUpdate::UpdateLight,
+                ChunkUpdate::UpdateMesh,
+            ],
+        }
+    }
+}
+
+impl ChunkPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A pipeline that meshes chunks right after generating them.
+    pub fn unlit() -> Self {
+        Self::new()
+            .skip(ChunkUpdate::UpdateLightMap)
+            .skip(ChunkUpdate::UpdateLight)
+    }
+
+    pub fn skip(mut self, stage: ChunkUpdate) -> Self {
+        self.stages.retain(|s| *s != stage);
+        self
+    }
+
+    pub fn stages(&self) -> &[ChunkUpdate] {
+        &self.stages
+    }
+
+    pub fn contains(&self, stage: &ChunkUpdate) -> bool {
+        self.stages.contains(stage)
+    }
+
+    /// Returns `stage` if it's part of the pipeline, or the first stage after it that is.
+    pub fn resolve(&self, stage: &ChunkUpdate) -> Option<ChunkUpdate> {
+        self.stages.iter().find(|s| *s >= stage).cloned()
+    }
+
+    /// Returns the stage that follows `stage`.
+    pub fn next(&self, stage: &ChunkUpdate) -> Option<ChunkUpdate> {
+        self.stages.iter().find(|s| *s > stage).cloned()
+    }
+
+    /// Returns the stage that has to run before `stage`.
+    pub fn previous(&self, stage: &ChunkUpdate) -> Option<ChunkUpdate> {
+        self.stages.iter().rev().find(|s| *s < stage).cloned()
+    }
+
+    /// Returns the state a chunk must at least be in for `stage` to run on it.
+    pub fn required(&self, stage: &ChunkUpdate) -> Option<ChunkState> {
+        self.previous(stage).map(|stage| ChunkState::after(&stage))
+    }
+}
+
+/// Why a chunk update was requested, see `MapUpdates::request_because`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UpdateCause {
+    /// Chunks loaded or generated around the viewers.
+    Streaming,
+    /// A voxel of the chunk was changed.
+    Edit,
+    /// The chunk depends on a chunk that changed, e.g. its faces touch an edited voxel.
+    Dependency,
+    /// The next stage after the chunk completed one.
+    Pipeline,
+    /// Lighting was invalidated for a region or the whole map.
+    Invalidation,
+    /// The chunk's level of detail changed.
+    Lod,
+    /// Scheduled or animated voxels.
+    Tick,
+    /// Requested without a cause.
+    Unknown,
+}
+
+impl UpdateCause {
+    pub const ALL: [UpdateCause; 8] = [
+        Self::Streaming,
+        Self::Edit,
+        Self::Dependency,
+        Self::Pipeline,
+        Self::Invalidation,
+        Self::Lod,
+        Self::Tick,
+        Self::Unknown,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Streaming => "streaming",
+            Self::Edit => "edit",
+            Self::Dependency => "dependency",
+            Self::Pipeline => "pipeline",
+            Self::Invalidation => "invalidation",
+            Self::Lod => "lod",
+            Self::Tick => "tick",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// The id under which `update_cause_diagnostics` publishes the pending updates with this
+    /// cause.
+    #[cfg(feature = "bevy")]
+    pub fn diagnostic_id(&self) -> DiagnosticId {
+        let index = Self::ALL.iter().position(|cause| cause == self).unwrap_or(0);
+        DiagnosticId::from_u128(1563201447102384 + index as u128)
+    }
+}
@@ -0,0 +1,138 @@
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Map, MapUpdates},
+};
+
+type Rule<T> =
+    Box<dyn Fn(&Map<T>, (i32, i32, i32), Option<&T>) -> Result<(), String> + Send + Sync>;
+
+/// Decides which edits `Map::place` allows, e.g. that plants need ground below them or that
+/// some voxels only go into some biomes.
+///
+/// A rule gets the map, the world coordinates of the edit and the new voxel, `None` for
+/// removals, and returns why the edit isn't allowed if it isn't. Rules are checked in the
+/// order they were added, the first rejection wins.
+pub struct PlacementRules<T: Voxel> {
+    rules: Vec<Rule<T>>,
+}
+
+impl<T: Voxel> Default for PlacementRules<T> {
+    fn default() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+impl<T: Voxel> fmt::Debug for PlacementRules<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlacementRules")
+            .field("rules", &self.rules.len())
+            .finish()
+    }
+}
+
+impl<T: Voxel> PlacementRules<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&Map<T>, (i32, i32, i32), Option<&T>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Voxels for which `needs_support` returns `true` can only be placed on top of voxels
+    /// for which `supports` returns `true`.
+    pub fn require_support<N, S>(self, needs_support: N, supports: S) -> Self
+    where
+        N: Fn(&T) -> bool + Send + Sync + 'static,
+        S: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        self.with_rule(move |map, (x, y, z), voxel| match voxel {
+            Some(voxel) if needs_support(voxel) => {
+                match map.voxel((x, y - 1, z)).as_ref().map(Cow::as_ref) {
+                    Some(below) if supports(below) => Ok(()),
+                    _ => Err("there is nothing to support the voxel".to_string()),
+                }
+            }
+            _ => Ok(()),
+        })
+    }
+
+    /// Voxels can only be placed in the biomes for which `allowed` returns `true`, by index
+    /// into the biomes of the program that generated the chunk. Voxels in chunks without
+    /// biomes are always allowed.
+    pub fn restrict_biomes<F>(self, allowed: F) -> Self
+    where
+        F: Fn(&T, usize) -> bool + Send + Sync + 'static,
+    {
+        self.with_rule(move |map, coords, voxel| match (voxel, map.biome(coords)) {
+            (Some(voxel), Some(biome)) if !allowed(voxel, biome) => {
+                Err(format!("the voxel can't be placed in biome {}", biome))
+            }
+            _ => Ok(()),
+        })
+    }
+
+    /// Returns why placing `voxel` at world coordinates `coords` isn't allowed, if it isn't.
+    pub fn check(
+        &self,
+        map: &Map<T>,
+        coords: (i32, i32, i32),
+        voxel: Option<&T>,
+    ) -> Result<(), PlacementRejected> {
+        for rule in &self.rules {
+            if let Err(reason) = rule(map, coords, voxel) {
+                return Err(PlacementRejected { coords, reason });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// An edit rejected by `PlacementRules`, returned by `Map::place`. The editor sends them as
+/// events, which are registered by `EditorPlugin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementRejected {
+    /// In world coordinates.
+    pub coords: (i32, i32, i32),
+    pub reason: String,
+}
+
+impl fmt::Display for PlacementRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "can't edit the voxel at {:?}: {}",
+            self.coords, self.reason
+        )
+    }
+}
+
+impl std::error::Error for PlacementRejected {}
+
+impl<T: Voxel> Map<T> {
+    /// Like `set_voxel`, but only if `rules` allow it.
+    pub fn place(
+        &mut self,
+        coords: (i32, i32, i32),
+        voxel: Option<T>,
+        rules: &PlacementRules<T>,
+        updates: &mut MapUpdates,
+    ) -> Result<bool, PlacementRejected> {
+        rules.check(self, coords, voxel.as_ref())?;
+        Ok(self.set_voxel(coords, voxel, updates))
+    }
+}
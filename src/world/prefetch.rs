@@ -0,0 +1,158 @@
+use glam::Vec3;
+
+#[cfg(feature = "bevy")]
+use bevy::{prelude::*, transform::prelude::Translation};
+
+#[cfg(feature = "bevy")]
+use crate::{render::origin::FloatingOrigin, world::MapUpdates};
+use crate::{collections::lod_tree::Voxel, world::Map};
+
+/// How far ahead of moving viewers chunks are generated.
+///
+/// The path a viewer takes in the next `lookahead` seconds is sampled once per chunk, and the
+/// missing chunks within `radius` chunks of every sample are queued with
+/// `MapUpdates::prefetch`, nearest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prefetch {
+    pub lookahead: f32,
+    pub radius: i32,
+    /// The lowest and highest chunk origins to prefetch, in world coordinates.
+    pub heights: Option<(i32, i32)>,
+    /// The most chunks queued per viewer.
+    pub max_chunks: usize,
+}
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        Self {
+            lookahead: 2.0,
+            radius: 1,
+            heights: None,
+            max_chunks: 64,
+        }
+    }
+}
+
+impl Prefetch {
+    /// Returns the origins of the chunks missing from `map` along the path of a viewer at
+    /// world coordinates `position` moving at `velocity` voxels per second.
+    pub fn chunks_along<T: Voxel>(
+        &self,
+        map: &Map<T>,
+        position: Vec3,
+        velocity: Vec3,
+    ) -> Vec<(i32, i32, i32)> {
+        let layout = if let Some(layout) = map.layout() {
+            layout
+        } else {
+            return Vec::new();
+        };
+        let width = layout.chunk_width as i32;
+        let distance = velocity.length() * self.lookahead;
+        if distance <= 0.0 || !distance.is_finite() {
+            return Vec::new();
+        }
+        let steps = (distance / width as f32).ceil() as i32;
+        let direction = velocity / velocity.length();
+
+        let mut chunks = Vec::new();
+        // the chunks around the viewer itself are left to regular streaming
+        for step in 1..=steps {
+            let point = position + direction * (distance * step as f32 / steps as f32);
+            let (x, y, z) = layout.chunk_origin((
+                point.x().floor() as i32,
+                point.y().floor() as i32,
+                point.z().floor() as i32,
+            ));
+            for lx in -self.radius..=self.radius {
+                for ly in -self.radius..=self.radius {
+                    for lz in -self.radius..=self.radius {
+                        let coords = (x + lx * width, y + ly * width, z + lz * width);
+                        if let Some((min, max)) = self.heights {
+                            if coords.1 < min || coords.1 > max {
+                                continue;
+                            }
+                        }
+                        if map.get_at_origin(coords).is_none() && !chunks.contains(&coords) {
+                            chunks.push(coords);
+                        }
+                        if chunks.len() >= self.max_chunks {
+                            return chunks;
+                        }
+                    }
+                }
+            }
+        }
+        chunks
+    }
+}
+
+/// Marks an entity whose movement is used to prefetch chunks.
+///
+/// If `track` is set, `velocity` is measured from changes of the entity's `Translation`.
+/// Otherwise it's left for the application to set, e.g. from its physics.
+#[cfg(feature = "bevy")]
+#[derive(Debug, Clone, Copy)]
+pub struct PrefetchViewer {
+    pub velocity: Vec3,
+    pub track: bool,
+    last: Option<Vec3>,
+}
+
+#[cfg(feature = "bevy")]
+impl Default for PrefetchViewer {
+    fn default() -> Self {
+        Self {
+            velocity: Vec3::zero(),
+            track: true,
+            last: None,
+        }
+    }
+}
+
+#[cfg(feature = "bevy")]
+impl PrefetchViewer {
+    /// A viewer with a velocity set by the application.
+    pub fn with_velocity(velocity: Vec3) -> Self {
+        Self {
+            velocity,
+            track: false,
+            last: None,
+        }
+    }
+}
+
+/// Replaces the prefetch queue of every map with the chunks ahead of the `PrefetchViewer`s.
+#[cfg(feature = "bevy")]
+pub fn prefetch_update<T: Voxel>(
+    time: Res<Time>,
+    prefetch: Res<Prefetch>,
+    origin: Res<FloatingOrigin>,
+    mut viewers: Query<(&mut PrefetchViewer, &Translation)>,
+    mut query: Query<(&Map<T>, &mut MapUpdates)>,
+) {
+    let mut paths = Vec::new();
+    for (mut viewer, translation) in &mut viewers.iter() {
+        // world positions don't jump when the floating origin moves
+        let position = origin.offset() + translation.0;
+        if viewer.track {
+            if let Some(last) = viewer.last {
+                if time.delta_seconds > 0.0 {
+                    let measured = (position - last) / time.delta_seconds;
+                    // smooths out uneven frame times
+                    viewer.velocity = viewer.velocity.lerp(measured, 0.5);
+                }
+            }
+            viewer.last = Some(position);
+        }
+        paths.push((position, viewer.velocity));
+    }
+    for (map, mut updates) in &mut query.iter() {
+        updates.prefetch.clear();
+        for &(position, velocity) in &paths {
+            for coords in prefetch.chunks_along(&map, position, velocity) {
+                updates.request_prefetch(coords);
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use glam::Vec3;
+
+use line_drawing::WalkVoxels;
+
+use crate::{
+    collections::lod_tree::Voxel,
+    lighting::VoxelTracer,
+    mesh::{VoxelExt, VoxelFlags},
+    world::{ChunkState, ChunkUpdate, Map, MapUpdates, UpdateCause},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    /// The voxel that was hit, in world coordinates.
+    pub position: (i32, i32, i32),
+    /// The normal of the face that was hit, or zero if the ray started inside the voxel.
+    pub normal: (i32, i32, i32),
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    /// Returns the empty voxel in front of the face that was hit, e.g. for placing blocks.
+    pub fn adjacent(&self) -> (i32, i32, i32) {
+        (
+            self.position.0 + self.normal.0,
+            self.position.1 + self.normal.1,
+            self.position.2 + self.normal.2,
+        )
+    }
+}
+
+impl<T: Voxel> Map<T> {
+    /// Casts a ray from `origin` in world space and returns the first voxel it hits within
+    /// `max_distance`.
+    ///
+    /// Empty cells of the chunks' `Occupancy`, empty chunks and, in maps with a layout,
+    /// chunks that aren't loaded are crossed in a single step.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        self.raycast_with(origin, direction, max_distance, |_| true)
+    }
+
+    /// Like `raycast`, but goes through the voxels for which `hits` returns `false`.
+    pub fn raycast_with<F: Fn(&T) -> bool>(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        hits: F,
+    ) -> Option<RaycastHit> {
+        if direction.length_squared() == 0.0 {
+            return None;
+        }
+        let direction = direction.normalize();
+        let origin = [origin.x(), origin.y(), origin.z()];
+        let direction = [direction.x(), direction.y(), direction.z()];
+
+        let mut voxel = [0; 3];
+        let mut step = [0; 3];
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for i in 0..3 {
+            voxel[i] = origin[i].floor() as i32;
+            if direction[i] > 0.0 {
+                step[i] = 1;
+                t_max[i] = (origin[i].floor() + 1.0 - origin[i]) / direction[i];
+                t_delta[i] = direction[i].recip();
+            } else if direction[i] < 0.0 {
+                step[i] = -1;
+                t_max[i] = (origin[i] - origin[i].floor()) / -direction[i];
+                t_delta[i] = -direction[i].recip();
+            }
+        }
+
+        let mut normal = [0; 3];
+        let mut distance = 0.0;
+        loop {
+            let position = (voxel[0], voxel[1], voxel[2]);
+            if let Some((min, width)) = self.empty_cell(position) {
+                // leave the cell through the first of its faces the ray reaches
+                let min = [min.0, min.1, min.2];
+                let mut exit = f32::INFINITY;
+                let mut axis = 0;
+                for i in 0..3 {
+                    let t = match step[i] {
+                        1 => (min[i] + width) as f32 - origin[i],
+                        -1 => min[i] as f32 - origin[i],
+                        _ => continue,
+                    } / direction[i];
+                    if t < exit {
+                        exit = t;
+                        axis = i;
+                    }
+                }
+                if exit > max_distance {
+                    return None;
+                }
+                for i in 0..3 {
+                    if i == axis {
+                        voxel[i] = if step[i] > 0 {
+                            min[i] + width
+                        } else {
+                            min[i] - 1
+                        };
+                    } else {
+                        let coord = (origin[i] + direction[i] * exit).floor() as i32;
+                        voxel[i] = coord.max(min[i]).min(min[i] + width - 1);
+                    }
+                    if step[i] != 0 {
+                        let boundary = voxel[i] + (step[i] > 0) as i32;
+                        t_max[i] = (boundary as f32 - origin[i]) / direction[i];
+                    }
+                }
+                distance = exit;
+                normal = [0; 3];
+                normal[axis] = -step[axis];
+                continue;
+            }
+            if self.voxel(position).map_or(false, |voxel| hits(&voxel)) {
+                return Some(RaycastHit {
+                    position,
+                    normal: (normal[0], normal[1], normal[2]),
+                    distance,
+                });
+            }
+
+            let axis = if t_max[0] < t_max[1] && t_max[0] < t_max[2] {
+                0
+            } else if t_max[1] < t_max[2] {
+                1
+            } else {
+                2
+            };
+            if t_max[axis] > max_distance {
+                return None;
+            }
+            distance = t_max[axis];
+            voxel[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = [0; 3];
+            normal[axis] = -step[axis];
+        }
+    }
+}
+
+impl<T: Voxel> Map<T> {
+    /// The empty cube around world coordinates `position` as its origin in world coordinates
+    /// and width, see `Occupancy::empty_cell`.
+    fn empty_cell(&self, position: (i32, i32, i32)) -> Option<((i32, i32, i32), i32)> {
+        let chunk = match self.chunk_containing(position) {
+            Some(chunk) => chunk,
+            None => {
+                let layout = self.layout()?;
+                return Some((layout.chunk_origin(position), layout.chunk_width as i32));
+            }
+        };
+        let ((x, y, z), width) = chunk.occupancy().empty_cell(chunk.to_local(position))?;
+        let (cx, cy, cz) = chunk.position();
+        Some(((cx + x, cy + y, cz + z), width))
+    }
+}
+
+/// What `Map::pick` found under a ray, for diagnosing holes and lighting artifacts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkPick {
+    pub hit: RaycastHit,
+    /// The origin of the chunk holding the hit voxel, in world coordinates.
+    pub chunk: (i32, i32, i32),
+    pub state: ChunkState,
+    pub lod: usize,
+    pub has_light: bool,
+    /// The light of the hit voxel.
+    pub light: Option<f32>,
+    pub pending: Option<ChunkUpdate>,
+    /// Only known if the updates track causes.
+    pub cause: Option<UpdateCause>,
+    pub prefetched: bool,
+}
+
+impl<T: Voxel> Map<T> {
+    /// Casts a ray like `raycast` and records the state of the chunk that was hit and what's
+    /// queued for it in `updates`.
+    pub fn pick(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        updates: &MapUpdates,
+    ) -> Option<ChunkPick> {
+        let hit = self.raycast(origin, direction, max_distance)?;
+        let data = self.chunk_containing(hit.position)?;
+        let chunk = data.position();
+        Some(ChunkPick {
+            hit,
+            chunk,
+            state: data.state(),
+            lod: data.lod(),
+            has_light: data.has_light(),
+            light: data.light(data.to_local(hit.position)),
+            pending: updates.updates.get(&chunk).cloned(),
+            cause: updates.cause(chunk),
+            prefetched: updates.prefetch.contains(&chunk),
+        })
+    }
+}
+
+impl<T: VoxelExt> Map<T> {
+    /// Like `raycast`, but goes through voxels with `VoxelFlags::FLUID` or
+    /// `VoxelFlags::NO_COLLISION`, e.g. to find the ground under water and grass.
+    pub fn raycast_solid(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+    ) -> Option<RaycastHit> {
+        let passable = VoxelFlags::FLUID | VoxelFlags::NO_COLLISION;
+        self.raycast_with(origin, direction, max_distance, |voxel| {
+            !voxel.flags().intersects(passable)
+        })
+    }
+
+    /// Returns how much the voxels between world coordinates `a` and `b` muffle a sound
+    /// travelling from one to the other, from `0.0` for a clear line to `1.0` for a solid
+    /// wall. The voxels at `a` and `b` themselves are ignored.
+    pub fn occlusion_between(&self, a: (i32, i32, i32), b: (i32, i32, i32)) -> f32 {
+        let mut transmission = 1.0;
+        for coords in <WalkVoxels<f32, i32> as VoxelTracer>::new(a, b) {
+            if coords == a || coords == b {
+                continue;
+            }
+            if let Some(voxel) = self.voxel(coords) {
+                transmission *= 1.0 - voxel.density().max(0.0).min(1.0);
+                if transmission <= 0.0 {
+                    break;
+                }
+            }
+        }
+        1.0 - transmission
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "mmap")]
+use memmap::Mmap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{
+        codec::ChunkCodec, Chunk, Compression, Map, MapLayout, MapUpdates, SaveData, SaveManifest,
+    },
+};
+
+const REGION_MAGIC: [u8; 4] = *b"BVRG";
+
+/// How a prebaked world is split into region files, stored next to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionLayout {
+    pub chunk_width: usize,
+    /// The number of chunks along every side of a region.
+    pub region_chunks: usize,
+}
+
+impl RegionLayout {
+    pub const FILE_NAME: &'static str = "regions.bin";
+
+    /// The region that holds the chunk at `position`.
+    pub fn region(&self, (x, y, z): (i32, i32, i32)) -> (i32, i32, i32) {
+        let width = (self.chunk_width * self.region_chunks) as i32;
+        (x.div_euclid(width), y.div_euclid(width), z.div_euclid(width))
+    }
+
+    fn path(directory: &Path, (x, y, z): (i32, i32, i32)) -> PathBuf {
+        directory.join(format!("r.{}.{}.{}.bin", x, y, z))
+    }
+
+    fn is_region(path: &Path) -> bool {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return false,
+        };
+        name.starts_with("r.")
+            && name.ends_with(".bin")
+            && name[2..name.len() - 4]
+                .split('.')
+                .map(|part| part.parse::<i32>())
+                .filter(Result::is_ok)
+                .count()
+                == 3
+    }
+}
+
+/// Where the compressed bytes of a chunk are in the data section of its region file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct RegionEntry {
+    position: (i32, i32, i32),
+    offset: u64,
+    len: u64,
+}
+
+/// The bytes of a region file, memory-mapped with the `mmap` feature.
+#[cfg(feature = "mmap")]
+type RegionData = Mmap;
+#[cfg(not(feature = "mmap"))]
+type RegionData = Vec<u8>;
+
+/// An open region file. Only the table of its chunks is decoded when it's opened.
+struct Region {
+    data: RegionData,
+    /// Where the data section starts.
+    start: usize,
+    entries: HashMap<(i32, i32, i32), (usize, usize)>,
+}
+
+impl Region {
+    fn open(path: &Path) -> bincode::Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        #[cfg(feature = "mmap")]
+        // region files are only ever replaced as a whole by `bake_regions`
+        let data = unsafe { Mmap::map(&file)? };
+        #[cfg(not(feature = "mmap"))]
+        let data = {
+            use std::io::Read;
+            let mut data = Vec::new();
+            (&file).read_to_end(&mut data)?;
+            data
+        };
+
+        let invalid = || bincode::ErrorKind::Custom(format!("{} isn't a region", path.display()));
+        if data.len() < 12 || data[..4] != REGION_MAGIC {
+            return Err(invalid().into());
+        }
+        let mut len = [0; 8];
+        len.copy_from_slice(&data[4..12]);
+        let start = 12 + u64::from_le_bytes(len) as usize;
+        if data.len() < start {
+            return Err(invalid().into());
+        }
+        let entries = bincode::deserialize::<Vec<RegionEntry>>(&data[12..start])?
+            .into_iter()
+            .map(|entry| (entry.position, (entry.offset as usize, entry.len as usize)))
+            .collect();
+        Ok(Some(Self {
+            data,
+            start,
+            entries,
+        }))
+    }
+
+    fn chunk_bytes(&self, position: (i32, i32, i32)) -> Option<&[u8]> {
+        let &(offset, len) = self.entries.get(&position)?;
+        let start = self.start + offset;
+        self.data.get(start..start + len)
+    }
+}
+
+/// Writes the chunks of `map` into region files of `region_chunks`³ chunks in `directory`,
+/// to be opened with `RegionMap`.
+pub fn bake_regions<T, P>(
+    map: &Map<T>,
+    directory: P,
+    region_chunks: usize,
+    compression: Compression,
+) -> bincode::Result<()>
+where
+    T: Voxel + Serialize + DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let directory = directory.as_ref();
+    let chunk_width = match map.layout() {
+        Some(layout) => layout.chunk_width,
+        None => {
+            return Err(bincode::ErrorKind::Custom("can't bake a map without chunks".into()).into())
+        }
+    };
+    let layout = RegionLayout {
+        chunk_width,
+        region_chunks,
+    };
+    fs::create_dir_all(directory)?;
+    fs::write(
+        directory.join(RegionLayout::FILE_NAME),
+        bincode::serialize(&layout)?,
+    )?;
+
+    // a baked world has no manifest to keep a zstd dictionary in
+    let codec = ChunkCodec::new(compression, SaveManifest::default());
+    let mut regions = HashMap::<_, Vec<_>>::new();
+    for chunk in map.iter() {
+        regions
+            .entry(layout.region(chunk.position()))
+            .or_default()
+            .push(chunk);
+    }
+    let mut baked = Vec::with_capacity(regions.len());
+    for (region, chunks) in regions {
+        let mut entries = Vec::new();
+        let mut data = Vec::new();
+        for chunk in chunks {
+            let mut save = chunk.serializable();
+            save.border_light = map.capture_border_light(chunk.position());
+            let bytes = codec.encode(&save.to_bytes()?)?;
+            entries.push(RegionEntry {
+                position: chunk.position(),
+                offset: data.len() as u64,
+                len: bytes.len() as u64,
+            });
+            data.extend_from_slice(&bytes);
+        }
+        let header = bincode::serialize(&entries)?;
+
+        let path = RegionLayout::path(directory, region);
+        let temp = path.with_extension("bin.tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(&REGION_MAGIC)?;
+        file.write_all(&(header.len() as u64).to_le_bytes())?;
+        file.write_all(&header)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        // a mapped region must never change under a reader, so it's replaced instead
+        fs::rename(&temp, &path)?;
+        baked.push(path);
+    }
+
+    // regions of an earlier bake that this one didn't write would still be opened
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if RegionLayout::is_region(&path) && !baked.contains(&path) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// A read-only view of a world baked with `bake_regions`, for worlds too big to load.
+///
+/// Region files are opened, and memory-mapped with the `mmap` feature, the first time one of
+/// their chunks is needed, and chunks are only decoded when they're accessed. The last
+/// `capacity` decoded chunks are kept around. Edits never touch the region files, they go to
+/// an overlay map holding copies of the edited chunks, which can be saved like any map.
+pub struct RegionMap<T: Voxel> {
+    directory: PathBuf,
+    layout: RegionLayout,
+    codec: ChunkCodec,
+    regions: HashMap<(i32, i32, i32), Option<Region>>,
+    cache: HashMap<(i32, i32, i32), Chunk<T>>,
+    /// The cached chunks, least recently used first.
+    recent: VecDeque<(i32, i32, i32)>,
+    capacity: usize,
+    overlay: Map<T>,
+}
+
+impl<T: Voxel + Serialize + DeserializeOwned> RegionMap<T> {
+    pub fn open<P: AsRef<Path>>(directory: P, capacity: usize) -> bincode::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let layout: RegionLayout =
+            bincode::deserialize(&fs::read(directory.join(RegionLayout::FILE_NAME))?)?;
+        Ok(Self {
+            directory,
+            layout,
+            codec: ChunkCodec::default(),
+            regions: HashMap::new(),
+            cache: HashMap::new(),
+            recent: VecDeque::new(),
+            capacity: capacity.max(1),
+            overlay: Map::with_layout(MapLayout::uniform(layout.chunk_width)),
+        })
+    }
+
+    pub fn layout(&self) -> RegionLayout {
+        self.layout
+    }
+
+    /// The edited chunks.
+    pub fn overlay(&self) -> &Map<T> {
+        &self.overlay
+    }
+
+    pub fn overlay_mut(&mut self) -> &mut Map<T> {
+        &mut self.overlay
+    }
+
+    /// The number of decoded chunks in the cache.
+    pub fn cached(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the chunk at `position`, from the overlay if it was edited.
+    pub fn chunk(&mut self, position: (i32, i32, i32)) -> bincode::Result<Option<&Chunk<T>>> {
+        if self.overlay.get_at_origin(position).is_some() {
+            return Ok(self.overlay.get_at_origin(position));
+        }
+        if self.cache.contains_key(&position) {
+            if let Some(index) = self.recent.iter().position(|&p| p == position) {
+                self.recent.remove(index);
+            }
+            self.recent.push_back(position);
+            return Ok(self.cache.get(&position));
+        }
+
+        let chunk = match self.decode(position)? {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        while self.cache.len() >= self.capacity {
+            match self.recent.pop_front() {
+                Some(oldest) => {
+                    self.cache.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.recent.push_back(position);
+        Ok(Some(self.cache.entry(position).or_insert(chunk)))
+    }
+
+    /// Returns the voxel at world coordinates `coords`.
+    pub fn voxel(&mut self, coords: (i32, i32, i32)) -> bincode::Result<Option<T>> {
+        let layout = MapLayout::uniform(self.layout.chunk_width);
+        let position = layout.chunk_origin(coords);
+        Ok(self
+            .chunk(position)?
+            .and_then(|chunk| chunk.get(chunk.to_local(coords)))
+            .map(|voxel| voxel.into_owned()))
+    }
+
+    /// Sets or clears the voxel at world coordinates `coords` in the overlay, copying its
+    /// chunk there first. Returns `false` if there is no chunk at `coords`.
+    pub fn set_voxel(
+        &mut self,
+        coords: (i32, i32, i32),
+        voxel: Option<T>,
+        updates: &mut MapUpdates,
+    ) -> bincode::Result<bool> {
+        let layout = MapLayout::uniform(self.layout.chunk_width);
+        let position = layout.chunk_origin(coords);
+        if self.overlay.get_at_origin(position).is_none() {
+            let chunk = match self.cache.remove(&position) {
+                Some(chunk) => chunk,
+                None => match self.decode(position)? {
+                    Some(chunk) => chunk,
+                    None => return Ok(false),
+                },
+            };
+            self.recent.retain(|&p| p != position);
+            self.overlay
+                .try_insert(chunk)
+                .map_err(|e| bincode::ErrorKind::Custom(e.to_string()))?;
+        }
+        Ok(self.overlay.set_voxel(coords, voxel, updates))
+    }
+
+    fn decode(&mut self, position: (i32, i32, i32)) -> bincode::Result<Option<Chunk<T>>> {
+        let region = self.layout.region(position);
+        if !self.regions.contains_key(&region) {
+            let path = RegionLayout::path(&self.directory, region);
+            self.regions.insert(region, Region::open(&path)?);
+        }
+        let bytes = match self.regions[&region]
+            .as_ref()
+            .and_then(|region| region.chunk_bytes(position))
+        {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let save = SaveData::from_bytes(&self.codec.decode(bytes)?)?;
+        Chunk::try_from(save).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::map;
+
+    #[test]
+    pub fn region_map() {
+        let dir = std::env::temp_dir().join(format!("bevy_voxel_regions_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut updates = MapUpdates::default();
+        let mut map = map();
+        map.set_voxel((1, 1, 1), Some(1), &mut updates);
+        map.set_voxel((-3, 2, 5), Some(2), &mut updates);
+        bake_regions(&map, &dir, 2, Compression::default()).unwrap();
+        // chunks from -4 to 4 fall into two regions along every axis
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 9);
+
+        let mut regions = RegionMap::<i32>::open(&dir, 2).unwrap();
+        assert_eq!(regions.voxel((1, 1, 1)).unwrap(), Some(1));
+        assert_eq!(regions.voxel((-3, 2, 5)).unwrap(), Some(2));
+        assert_eq!(regions.voxel((0, 0, 0)).unwrap(), None);
+        assert_eq!(regions.cached(), 2);
+        assert!(regions.chunk((16, 0, 0)).unwrap().is_none());
+
+        assert!(regions.set_voxel((1, 1, 1), Some(3), &mut updates).unwrap());
+        assert_eq!(regions.voxel((1, 1, 1)).unwrap(), Some(3));
+        assert_eq!(regions.overlay().iter().count(), 1);
+        let mut reopened = RegionMap::<i32>::open(&dir, 2).unwrap();
+        assert_eq!(reopened.voxel((1, 1, 1)).unwrap(), Some(1));
+
+        // rebaking a smaller world removes the regions it no longer has
+        let negative: Vec<_> = map
+            .iter()
+            .map(Chunk::position)
+            .filter(|&(x, y, z)| x < 0 || y < 0 || z < 0)
+            .collect();
+        for position in negative {
+            map.remove(position);
+        }
+        bake_regions(&map, &dir, 2, Compression::default()).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        let mut rebaked = RegionMap::<i32>::open(&dir, 2).unwrap();
+        assert_eq!(rebaked.voxel((-3, 2, 5)).unwrap(), None);
+        assert!(bake_regions(&Map::<i32>::new(), &dir, 2, Compression::default()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
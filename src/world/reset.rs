@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Map, MapUpdates, VoxelTicks},
+};
+
+/// Tears down the voxel world, e.g. before going back to the main menu or loading another
+/// save. Register it with `add_event::<WorldReset>()`.
+///
+/// `world_reset_update` empties the maps and `height_map_reset_update` the `HeightMap`. The
+/// render entities of overlays and loading markers go away on their own once the maps are
+/// empty. The map entities themselves are left to the game, which may despawn them or fill
+/// them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WorldReset;
+
+/// Clears every map, see `Map::clear`, frees the meshes of its chunks and drops its pending
+/// updates and scheduled voxel changes when a `WorldReset` is sent.
+pub fn world_reset_update<T: Voxel>(
+    mut commands: Commands,
+    mut reader: Local<EventReader<WorldReset>>,
+    events: Res<Events<WorldReset>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut maps: Query<(&mut Map<T>, &mut MapUpdates)>,
+    mut ticks: Query<&mut VoxelTicks<T>>,
+    handles: Query<&Handle<Mesh>>,
+) {
+    if reader.iter(&events).next().is_none() {
+        return;
+    }
+    for (mut map, mut updates) in &mut maps.iter() {
+        for chunk in map.iter() {
+            for e in chunk.entity().into_iter().chain(chunk.transparent_entity()) {
+                if let Ok(handle) = handles.get::<Handle<Mesh>>(e) {
+                    meshes.remove(&handle);
+                }
+            }
+        }
+        map.clear(&mut commands);
+        updates.clear();
+    }
+    for mut ticks in &mut ticks.iter() {
+        ticks.clear();
+    }
+}
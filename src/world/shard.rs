@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+#[cfg(feature = "bevy")]
+use crate::world::{ChunkUpdate, MapUpdates};
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Chunk, ChunkWidthError, Map, MapLayout},
+};
+
+/// Marks a map entity that holds the chunks of one region of a sharded map.
+///
+/// Every shard is a regular map entity, so the systems that go through all maps handle each
+/// region separately. Chunks at the border of a region are lit and meshed as if the chunks of
+/// the neighbouring regions weren't loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MapShard {
+    pub region: (i32, i32),
+}
+
+/// How a map is split into square regions of `region_chunks` by `region_chunks` chunk columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardLayout {
+    pub layout: MapLayout,
+    pub region_chunks: usize,
+}
+
+impl ShardLayout {
+    pub fn new(layout: MapLayout, region_chunks: usize) -> Self {
+        Self {
+            layout,
+            region_chunks,
+        }
+    }
+
+    /// The width of a region in voxels.
+    pub fn region_width(&self) -> i32 {
+        (self.layout.chunk_width * self.region_chunks) as i32
+    }
+
+    /// Returns the region that contains world coordinates `coords`.
+    pub fn region_of(&self, (x, _, z): (i32, i32, i32)) -> (i32, i32) {
+        let width = self.region_width();
+        (x.div_euclid(width), z.div_euclid(width))
+    }
+
+    /// Moves the chunks of `map` into one map per region. Fails if the chunks of `map` don't
+    /// fit `layout`.
+    pub fn split<T: Voxel>(
+        &self,
+        mut map: Map<T>,
+    ) -> Result<HashMap<(i32, i32), Map<T>>, ChunkWidthError> {
+        let positions = map.iter().map(Chunk::position).collect::<Vec<_>>();
+        let mut regions = HashMap::<_, Vec<_>>::new();
+        for position in positions {
+            if let Some(chunk) = map.remove(position) {
+                regions.entry(self.region_of(position)).or_default().push(chunk);
+            }
+        }
+        regions
+            .into_iter()
+            .map(|(region, chunks)| {
+                let mut map = Map::with_layout(self.layout);
+                map.try_insert_many(chunks)?;
+                Ok((region, map))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "bevy")]
+type ByRegion<V> = HashMap<(i32, i32), Vec<V>>;
+
+/// Chunks and pending updates on their way to the shard of their region.
+#[cfg(feature = "bevy")]
+struct Strays<T> {
+    chunks: ByRegion<Chunk<T>>,
+    updates: ByRegion<((i32, i32, i32), ChunkUpdate)>,
+    prefetch: ByRegion<(i32, i32, i32)>,
+}
+
+#[cfg(feature = "bevy")]
+impl<T: Voxel> Strays<T> {
+    fn new() -> Self {
+        Self {
+            chunks: HashMap::new(),
+            updates: HashMap::new(),
+            prefetch: HashMap::new(),
+        }
+    }
+
+    /// Takes everything that doesn't belong to `region` out of a shard.
+    fn collect(
+        &mut self,
+        shards: &ShardLayout,
+        region: (i32, i32),
+        map: &mut Map<T>,
+        map_updates: &mut MapUpdates,
+    ) {
+        let chunks = map
+            .iter()
+            .map(Chunk::position)
+            .filter(|&coords| shards.region_of(coords) != region)
+            .collect::<Vec<_>>();
+        for coords in chunks {
+            if let Some(chunk) = map.remove(coords) {
+                let owner = shards.region_of(coords);
+                self.chunks.entry(owner).or_default().push(chunk);
+            }
+        }
+        let updates = map_updates
+            .updates
+            .keys()
+            .copied()
+            .filter(|&coords| shards.region_of(coords) != region)
+            .collect::<Vec<_>>();
+        for coords in updates {
+            if let Some(update) = map_updates.updates.remove(&coords) {
+                let owner = shards.region_of(coords);
+                self.updates.entry(owner).or_default().push((coords, update));
+            }
+        }
+        let prefetch = &mut self.prefetch;
+        map_updates.prefetch.retain(|&coords| {
+            let owner = shards.region_of(coords);
+            if owner != region {
+                prefetch.entry(owner).or_default().push(coords);
+            }
+            owner == region
+        });
+    }
+
+    /// Hands everything that belongs to `region` to its shard.
+    fn deliver(&mut self, region: (i32, i32), map: &mut Map<T>, map_updates: &mut MapUpdates) {
+        if let Some(chunks) = self.chunks.remove(&region) {
+            if let Err(e) = map.try_insert_many(chunks) {
+                log::error!("failed to move chunks to region {:?}: {}", region, e);
+            }
+        }
+        for (coords, update) in self.updates.remove(&region).unwrap_or_default() {
+            map_updates.request(coords, update);
+        }
+        for coords in self.prefetch.remove(&region).unwrap_or_default() {
+            map_updates.request_prefetch(coords);
+        }
+    }
+
+    /// The regions that still have strays to deliver.
+    fn regions(&self) -> Vec<(i32, i32)> {
+        let mut regions = self
+            .chunks
+            .keys()
+            .chain(self.updates.keys())
+            .chain(self.prefetch.keys())
+            .copied()
+            .collect::<Vec<_>>();
+        regions.sort_unstable();
+        regions.dedup();
+        regions
+    }
+}
+
+/// Moves chunks and pending updates that ended up in the wrong shard, e.g. requests made
+/// for coordinates outside of a shard's region, to the shard of their region. Shards are
+/// spawned for regions that don't have one yet.
+#[cfg(feature = "bevy")]
+pub fn shard_route_update<T: Voxel>(
+    mut commands: Commands,
+    shards: Res<ShardLayout>,
+    mut query: Query<(&MapShard, &mut Map<T>, &mut MapUpdates)>,
+) {
+    let mut strays = Strays::new();
+    let mut pipeline = None;
+    for (shard, mut map, mut map_updates) in &mut query.iter() {
+        pipeline = Some(map_updates.pipeline.clone());
+        strays.collect(&shards, shard.region, &mut map, &mut map_updates);
+    }
+    if strays.regions().is_empty() {
+        return;
+    }
+
+    for (shard, mut map, mut map_updates) in &mut query.iter() {
+        strays.deliver(shard.region, &mut map, &mut map_updates);
+    }
+    for region in strays.regions() {
+        let mut map = Map::with_layout(shards.layout);
+        let mut map_updates = MapUpdates::with_pipeline(pipeline.clone().unwrap_or_default());
+        strays.deliver(region, &mut map, &mut map_updates);
+        commands.spawn((MapShard { region }, map, map_updates));
+    }
+}
@@ -0,0 +1,222 @@
+use std::borrow::Cow;
+
+use either::Either;
+
+use crate::collections::{
+    lod_tree::{Defrag, Element, ElementMut, Voxel},
+    BrickMap, LodTree, VoxelStorage,
+};
+
+/// How the voxels of a chunk are laid out, see `Chunk::set_storage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageKind {
+    /// A `LodTree`, which merges equal voxels and supports levels of detail.
+    LodTree,
+    /// A `BrickMap`, which is faster to mesh and query neighbours in, but uses more memory
+    /// for uniform chunks and is always meshed at full detail.
+    BrickMap,
+}
+
+impl Default for StorageKind {
+    fn default() -> Self {
+        StorageKind::LodTree
+    }
+}
+
+/// The voxels of a chunk in one of the layouts of `StorageKind`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ChunkData<T> {
+    Tree(LodTree<T>),
+    /// Bricks have no levels of detail, so the lod is only remembered for `Chunk::lod`.
+    Bricks {
+        bricks: BrickMap<T>,
+        lod: usize,
+    },
+}
+
+impl<T: Voxel> ChunkData<T> {
+    pub fn kind(&self) -> StorageKind {
+        match self {
+            ChunkData::Tree(_) => StorageKind::LodTree,
+            ChunkData::Bricks { .. } => StorageKind::BrickMap,
+        }
+    }
+
+    /// Copies the voxels into the layout of `kind`, keeping the lod.
+    pub fn convert(&mut self, kind: StorageKind) {
+        if self.kind() == kind {
+            return;
+        }
+        *self = match (&*self, kind) {
+            (ChunkData::Tree(tree), StorageKind::BrickMap) => ChunkData::Bricks {
+                bricks: BrickMap::from(tree),
+                lod: tree.lod(),
+            },
+            (ChunkData::Bricks { bricks, lod }, StorageKind::LodTree) => {
+                let mut tree = LodTree::from(bricks);
+                tree.set_lod(*lod);
+                ChunkData::Tree(tree)
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    /// The voxels as a `LodTree`, copied if they are stored in bricks.
+    pub fn tree(&self) -> Cow<'_, LodTree<T>> {
+        match self {
+            ChunkData::Tree(tree) => Cow::Borrowed(tree),
+            ChunkData::Bricks { bricks, lod } => {
+                let mut tree = LodTree::from(bricks);
+                tree.set_lod(*lod);
+                Cow::Owned(tree)
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            ChunkData::Tree(tree) => tree.width(),
+            ChunkData::Bricks { bricks, .. } => bricks.width(),
+        }
+    }
+
+    pub fn lod(&self) -> usize {
+        match self {
+            ChunkData::Tree(tree) => tree.lod(),
+            ChunkData::Bricks { lod, .. } => *lod,
+        }
+    }
+
+    pub fn set_lod(&mut self, new_lod: usize) {
+        match self {
+            ChunkData::Tree(tree) => tree.set_lod(new_lod),
+            ChunkData::Bricks { lod, .. } => *lod = new_lod,
+        }
+    }
+
+    pub fn merge(&mut self) {
+        if let ChunkData::Tree(tree) = self {
+            tree.merge();
+        }
+    }
+
+    /// Returns `None` if there is nothing to defragment, see `LodTree::defragment`.
+    pub fn defragment(&mut self, defrag: &mut Defrag<T>, budget: usize) -> Option<usize> {
+        match self {
+            ChunkData::Tree(tree) => Some(tree.defragment(defrag, budget)),
+            ChunkData::Bricks { .. } => None,
+        }
+    }
+
+    pub fn insert(&mut self, coords: (i32, i32, i32), voxel: T) {
+        match self {
+            ChunkData::Tree(tree) => {
+                tree.insert(coords, voxel);
+            }
+            ChunkData::Bricks { bricks, .. } => {
+                bricks.insert(coords, voxel);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, coords: (i32, i32, i32)) {
+        match self {
+            ChunkData::Tree(tree) => {
+                tree.remove(coords);
+            }
+            ChunkData::Bricks { bricks, .. } => {
+                bricks.remove(coords);
+            }
+        }
+    }
+
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        match self {
+            ChunkData::Tree(tree) => tree.get(coords),
+            ChunkData::Bricks { bricks, .. } => bricks.get(coords).map(Cow::Borrowed),
+        }
+    }
+
+    pub fn sampled_get(&self, coords: (i32, i32, i32)) -> Option<Cow<'_, T>> {
+        match self {
+            ChunkData::Tree(tree) => tree.sampled_get(coords),
+            ChunkData::Bricks { bricks, .. } => bricks.get(coords).map(Cow::Borrowed),
+        }
+    }
+
+    pub fn get_mut(&mut self, coords: (i32, i32, i32)) -> Option<&mut T> {
+        match self {
+            ChunkData::Tree(tree) => tree.get_mut(coords),
+            ChunkData::Bricks { bricks, .. } => bricks.get_mut(coords),
+        }
+    }
+
+    pub fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        match self {
+            ChunkData::Tree(tree) => tree.voxel(coords),
+            ChunkData::Bricks { bricks, .. } => bricks.get(coords),
+        }
+    }
+
+    /// Voxels in bricks are one voxel wide elements.
+    pub fn elements(&self) -> impl Iterator<Item = Element<'_, T>> {
+        match self {
+            ChunkData::Tree(tree) => Either::Left(tree.elements()),
+            ChunkData::Bricks { bricks, .. } => {
+                Either::Right(bricks.iter().map(|((x, y, z), value)| Element {
+                    x,
+                    y,
+                    z,
+                    width: 1,
+                    value: Cow::Borrowed(value),
+                }))
+            }
+        }
+    }
+
+    pub fn elements_mut(&mut self) -> impl Iterator<Item = ElementMut<'_, T>> {
+        match self {
+            ChunkData::Tree(tree) => Either::Left(tree.elements_mut()),
+            ChunkData::Bricks { bricks, .. } => {
+                Either::Right(bricks.iter_mut().map(|((x, y, z), value)| ElementMut {
+                    x,
+                    y,
+                    z,
+                    width: 1,
+                    value,
+                }))
+            }
+        }
+    }
+}
+
+impl<T: Voxel> VoxelStorage<T> for ChunkData<T> {
+    fn width(&self) -> usize {
+        self.width()
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            ChunkData::Tree(tree) => VoxelStorage::len(tree),
+            ChunkData::Bricks { bricks, .. } => bricks.len(),
+        }
+    }
+
+    fn voxel(&self, coords: (i32, i32, i32)) -> Option<&T> {
+        self.voxel(coords)
+    }
+
+    fn set_voxel(&mut self, coords: (i32, i32, i32), value: Option<T>) {
+        match value {
+            Some(value) => self.insert(coords, value),
+            None => self.remove(coords),
+        }
+    }
+
+    fn for_each_voxel<F: FnMut((i32, i32, i32), &T)>(&self, f: F) {
+        match self {
+            ChunkData::Tree(tree) => tree.for_each_voxel(f),
+            ChunkData::Bricks { bricks, .. } => bricks.for_each_voxel(f),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::{prelude::*, transform::prelude::Translation};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    mesh::{VoxelExt, VoxelFlags},
+    world::{ChangeCause, ChunkUpdate, Map, MapUpdates, UpdateCause},
+};
+
+/// A voxel change waiting for its tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledVoxel<T> {
+    /// The voxel that replaces the current one, or `None` to remove it.
+    pub voxel: Option<T>,
+    pub start: u64,
+    pub end: u64,
+    /// Whether the chunk is meshed again on every tick until the change is applied, so
+    /// that `VoxelExt::mesh_transition` can interpolate between the two voxels.
+    pub animated: bool,
+}
+
+impl<T> ScheduledVoxel<T> {
+    /// Returns how far along the change is at `tick`, from `0.0` to `1.0`.
+    pub fn progress(&self, tick: u64) -> f32 {
+        if self.end <= self.start {
+            return 1.0;
+        }
+        let done = tick.saturating_sub(self.start).min(self.end - self.start);
+        done as f32 / (self.end - self.start) as f32
+    }
+}
+
+/// Counts ticks for a map and applies the voxel changes scheduled for them.
+///
+/// Add it next to a `Map` and `MapUpdates` and advance it with `voxel_tick_update`, or call
+/// `advance` directly.
+#[derive(Debug, Clone)]
+pub struct VoxelTicks<T> {
+    tick: u64,
+    scheduled: HashMap<(i32, i32, i32), ScheduledVoxel<T>>,
+}
+
+impl<T> Default for VoxelTicks<T> {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            scheduled: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Voxel> VoxelTicks<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of ticks so far.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Changes the voxel at world coordinates `coords` to `voxel` after `ticks` ticks,
+    /// replacing any change already scheduled there.
+    pub fn schedule(&mut self, coords: (i32, i32, i32), voxel: Option<T>, ticks: u64) {
+        self.insert(coords, voxel, ticks, false);
+    }
+
+    /// Like `schedule`, but meshes the voxel with `VoxelExt::mesh_transition` until the
+    /// change is applied.
+    pub fn schedule_animated(&mut self, coords: (i32, i32, i32), voxel: Option<T>, ticks: u64) {
+        self.insert(coords, voxel, ticks, true);
+    }
+
+    fn insert(&mut self, coords: (i32, i32, i32), voxel: Option<T>, ticks: u64, animated: bool) {
+        self.scheduled.insert(
+            coords,
+            ScheduledVoxel {
+                voxel,
+                start: self.tick,
+                end: self.tick + ticks,
+                animated,
+            },
+        );
+    }
+
+    pub fn cancel(&mut self, coords: (i32, i32, i32)) -> Option<ScheduledVoxel<T>> {
+        self.scheduled.remove(&coords)
+    }
+
+    pub fn get(&self, coords: (i32, i32, i32)) -> Option<&ScheduledVoxel<T>> {
+        self.scheduled.get(&coords)
+    }
+
+    /// Returns how far along the change scheduled at `coords` is.
+    pub fn progress(&self, coords: (i32, i32, i32)) -> Option<f32> {
+        self.get(coords).map(|scheduled| scheduled.progress(self.tick))
+    }
+
+    /// Drops every scheduled change and counts ticks from `0` again.
+    pub fn clear(&mut self) {
+        self.tick = 0;
+        self.scheduled.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scheduled.is_empty()
+    }
+
+    /// Advances by one tick and applies the changes that are due. Changes in chunks that
+    /// aren't loaded are dropped. Returns the number of voxels changed.
+    pub fn advance(&mut self, map: &mut Map<T>, updates: &mut MapUpdates) -> usize {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let due = self
+            .scheduled
+            .iter()
+            .filter(|(_, scheduled)| scheduled.end <= tick)
+            .map(|(&coords, _)| coords)
+            .collect::<Vec<_>>();
+        let mut count = 0;
+        for coords in due {
+            if let Some(scheduled) = self.scheduled.remove(&coords) {
+                if map.set_voxel_because(coords, scheduled.voxel, updates, ChangeCause::Tick) {
+                    count += 1;
+                }
+            }
+        }
+
+        for (&coords, _) in self.scheduled.iter().filter(|(_, s)| s.animated) {
+            if let Some(chunk) = map.chunk_containing(coords) {
+                updates.request_because(
+                    chunk.position(),
+                    ChunkUpdate::UpdateMesh,
+                    UpdateCause::Tick,
+                );
+            }
+        }
+        count
+    }
+}
+
+/// Advances the `VoxelTicks` of every map by one tick per frame.
+#[cfg(feature = "bevy")]
+pub fn voxel_tick_update<T: Voxel>(
+    mut query: Query<(&mut Map<T>, &mut MapUpdates, &mut VoxelTicks<T>)>,
+) {
+    for (mut map, mut updates, mut ticks) in &mut query.iter() {
+        ticks.advance(&mut map, &mut updates);
+    }
+}
+
+/// Voxels that change on random ticks, like grass spreading or crops growing. Every voxel gets
+/// them, unless `FLAGGED_ONLY` is set.
+pub trait RandomTick: VoxelExt {
+    /// Only gives random ticks to voxels with `VoxelFlags::RANDOM_TICK`, so the ones that never
+    /// change on them can be skipped. Set it if `flags` sets the flag.
+    const FLAGGED_ONLY: bool = false;
+
+    /// Called for a randomly picked voxel at world coordinates `coords`. Returns the voxel to
+    /// replace it with, `Some(None)` to remove it, or `None` to leave it as it is.
+    fn random_tick(&self, coords: (i32, i32, i32), map: &Map<Self>) -> Option<Option<Self>>;
+}
+
+/// How random ticks are spread across the loaded chunks.
+///
+/// Every frame `budget` ticks are split between chunks within `range` of a viewer, weighted
+/// by `1 / (1 + distance / falloff)` so that nearby chunks tick more often. No chunk gets more
+/// than `chunk_cap` ticks, and what it can't take goes to the other chunks.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickPolicy {
+    pub budget: usize,
+    pub chunk_cap: usize,
+    pub falloff: i32,
+    pub range: i32,
+}
+
+impl Default for TickPolicy {
+    fn default() -> Self {
+        Self {
+            budget: 256,
+            chunk_cap: 16,
+            falloff: 64,
+            range: 256,
+        }
+    }
+}
+
+impl TickPolicy {
+    /// Splits the budget between chunks at the given distances from the nearest viewer.
+    pub fn distribute(&self, distances: &[i32]) -> Vec<usize> {
+        let weights = distances
+            .iter()
+            .map(|&d| 1.0 / (1.0 + d.max(0) as f32 / self.falloff.max(1) as f32))
+            .collect::<Vec<_>>();
+        let mut shares = vec![0; distances.len()];
+
+        // nearer chunks first, so that they get the last few ticks that can't be split evenly
+        let mut open = (0..distances.len())
+            .filter(|&i| distances[i] <= self.range)
+            .collect::<Vec<_>>();
+        open.sort_by_key(|&i| distances[i]);
+
+        let mut remaining = self.budget;
+        while remaining > 0 && !open.is_empty() {
+            let total = open.iter().map(|&i| weights[i]).sum::<f32>();
+            let mut given = 0;
+            for &i in &open {
+                let share = ((remaining as f32 * weights[i] / total) as usize)
+                    .max(1)
+                    .min(self.chunk_cap - shares[i])
+                    .min(remaining - given);
+                shares[i] += share;
+                given += share;
+            }
+            remaining -= given;
+            open.retain(|&i| shares[i] < self.chunk_cap);
+            if given == 0 {
+                break;
+            }
+        }
+        shares
+    }
+}
+
+/// Runs random ticks on the chunks of `map` as distributed by `policy` between the chunks
+/// near `viewers`. Returns the number of voxels changed.
+pub fn random_ticks<T: RandomTick, R: Rng>(
+    map: &mut Map<T>,
+    updates: &mut MapUpdates,
+    policy: &TickPolicy,
+    viewers: &[(i32, i32, i32)],
+    rng: &mut R,
+) -> usize {
+    let _span = span!("random_ticks");
+    let chunks = map
+        .iter()
+        .map(|chunk| {
+            let (x, y, z) = chunk.position();
+            let half = chunk.width() as i32 / 2;
+            let distance = viewers
+                .iter()
+                .map(|&(vx, vy, vz)| {
+                    (vx - x - half)
+                        .abs()
+                        .max((vy - y - half).abs())
+                        .max((vz - z - half).abs())
+                })
+                .min()
+                .unwrap_or(i32::MAX);
+            (chunk.position(), chunk.width() as i32, distance)
+        })
+        .collect::<Vec<_>>();
+    let shares = policy.distribute(&chunks.iter().map(|c| c.2).collect::<Vec<_>>());
+
+    let mut changes = Vec::new();
+    for (&((cx, cy, cz), width, _), &share) in chunks.iter().zip(&shares) {
+        for _ in 0..share {
+            let coords = (
+                cx + rng.gen_range(0, width),
+                cy + rng.gen_range(0, width),
+                cz + rng.gen_range(0, width),
+            );
+            let change = map
+                .voxel(coords)
+                .filter(|voxel| !T::FLAGGED_ONLY || voxel.flags().contains(VoxelFlags::RANDOM_TICK))
+                .and_then(|voxel| voxel.random_tick(coords, map));
+            if let Some(change) = change {
+                changes.push((coords, change));
+            }
+        }
+    }
+
+    let count = changes.len();
+    for (coords, voxel) in changes {
+        map.set_voxel_because(coords, voxel, updates, ChangeCause::Tick);
+    }
+    count
+}
+
+/// Marks an entity whose `Translation` attracts random ticks to the chunks around it.
+#[cfg(feature = "bevy")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TickViewer;
+
+/// Runs random ticks around every `TickViewer` according to the `TickPolicy` resource.
+#[cfg(feature = "bevy")]
+pub fn random_tick_update<T: RandomTick>(
+    policy: Res<TickPolicy>,
+    mut viewers: Query<(&TickViewer, &Translation)>,
+    mut query: Query<(&mut Map<T>, &mut MapUpdates)>,
+) {
+    let mut positions = Vec::new();
+    for (_, translation) in &mut viewers.iter() {
+        let position = translation.0;
+        positions.push((position.x() as i32, position.y() as i32, position.z() as i32));
+    }
+    let mut rng = rand::thread_rng();
+    for (mut map, mut updates) in &mut query.iter() {
+        random_ticks(&mut map, &mut updates, &policy, &positions, &mut rng);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "bevy")]
+use std::collections::HashSet;
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+use std::{fs, path::Path};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::{prelude::*, transform::prelude::Translation};
+
+#[cfg(feature = "bevy")]
+use crate::render::origin::FloatingOrigin;
+
+/// A box of voxels, e.g. a doorway, a spawn zone or the area of a scripted event, that fires
+/// `AreaEvent`s when tracked entities enter or leave it.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerArea {
+    pub name: String,
+    /// The corners of the box in world coordinates, both inclusive.
+    pub min: (i32, i32, i32),
+    pub max: (i32, i32, i32),
+}
+
+impl TriggerArea {
+    /// Creates an area spanning from corner `a` to corner `b`, both inclusive.
+    pub fn new<S: Into<String>>(name: S, a: (i32, i32, i32), b: (i32, i32, i32)) -> Self {
+        Self {
+            name: name.into(),
+            min: (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    pub fn contains(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+}
+
+/// The trigger areas of a world, stored next to its chunks in `triggers.ron`.
+///
+/// Areas keep their id for as long as they exist, also across saves.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriggerAreas {
+    areas: BTreeMap<u32, TriggerArea>,
+    next_id: u32,
+}
+
+impl TriggerAreas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `area` and returns its id.
+    pub fn insert(&mut self, area: TriggerArea) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.areas.insert(id, area);
+        id
+    }
+
+    /// Removes the area `id`. Entities inside it get an `AreaEvent::Left` on the next update.
+    pub fn remove(&mut self, id: u32) -> Option<TriggerArea> {
+        self.areas.remove(&id)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&TriggerArea> {
+        self.areas.get(&id)
+    }
+
+    /// Returns the id of the first area named `name`.
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.iter()
+            .find(|(_, area)| area.name == name)
+            .map(|(id, _)| id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &TriggerArea)> {
+        self.areas.iter().map(|(&id, area)| (id, area))
+    }
+
+    /// Returns the ids of the areas containing world coordinates `coords`.
+    ///
+    /// Every area is checked, which is plenty fast for the few dozen areas of a level.
+    pub fn at(&self, coords: (i32, i32, i32)) -> impl Iterator<Item = u32> + '_ {
+        self.iter()
+            .filter(move |(_, area)| area.contains(coords))
+            .map(|(id, _)| id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.areas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.areas.is_empty()
+    }
+}
+
+#[cfg(all(feature = "savedata", not(target_arch = "wasm32")))]
+impl TriggerAreas {
+    pub const FILE_NAME: &'static str = "triggers.ron";
+
+    pub fn save<P: AsRef<Path>>(&self, save_directory: P) -> bincode::Result<()> {
+        let save_directory = save_directory.as_ref();
+        fs::create_dir_all(save_directory)?;
+        let path = save_directory.join(Self::FILE_NAME);
+        let temp = path.with_extension("ron.tmp");
+        let ron = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|e| bincode::ErrorKind::Custom(e.to_string()))?;
+        fs::write(&temp, ron)?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Loads the trigger areas of a world, or returns no areas if it was saved without any.
+    pub fn load<P: AsRef<Path>>(save_directory: P) -> bincode::Result<Self> {
+        let path = save_directory.as_ref().join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let ron = fs::read_to_string(path)?;
+        ron::de::from_str(&ron).map_err(|e| Box::new(bincode::ErrorKind::Custom(e.to_string())))
+    }
+}
+
+/// Marks an entity whose `Translation` is checked against the `TriggerAreas`.
+#[cfg(feature = "bevy")]
+#[derive(Debug, Clone, Default)]
+pub struct AreaTracker {
+    /// The areas the entity was in at the last update.
+    inside: HashSet<u32>,
+}
+
+#[cfg(feature = "bevy")]
+impl AreaTracker {
+    pub fn is_inside(&self, id: u32) -> bool {
+        self.inside.contains(&id)
+    }
+
+    pub fn areas(&self) -> impl Iterator<Item = u32> + '_ {
+        self.inside.iter().copied()
+    }
+}
+
+/// Sent by `area_trigger_update` when an entity with an `AreaTracker` enters or leaves a
+/// trigger area. The event has to be registered with `add_event::<AreaEvent>()`.
+#[cfg(feature = "bevy")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaEvent {
+    Entered { entity: Entity, area: u32 },
+    Left { entity: Entity, area: u32 },
+}
+
+/// Checks the voxel every tracked entity is in against the trigger areas and sends an
+/// `AreaEvent` for every area it entered or left since the last update.
+#[cfg(feature = "bevy")]
+pub fn area_trigger_update(
+    origin: Res<FloatingOrigin>,
+    areas: Res<TriggerAreas>,
+    mut events: ResMut<Events<AreaEvent>>,
+    mut trackers: Query<(Entity, &mut AreaTracker, &Translation)>,
+) {
+    for (entity, mut tracker, translation) in &mut trackers.iter() {
+        let coords = origin.to_world(translation.0);
+        let inside = areas.at(coords).collect::<HashSet<_>>();
+        let mut left = tracker
+            .inside
+            .difference(&inside)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut entered = inside
+            .difference(&tracker.inside)
+            .copied()
+            .collect::<Vec<_>>();
+        left.sort_unstable();
+        entered.sort_unstable();
+        for area in left {
+            events.send(AreaEvent::Left { entity, area });
+        }
+        for area in entered {
+            events.send(AreaEvent::Entered { entity, area });
+        }
+        tracker.inside = inside;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn trigger_areas() {
+        let mut areas = TriggerAreas::new();
+        let door = areas.insert(TriggerArea::new("door", (4, 0, 2), (3, 2, 2)));
+        let hall = areas.insert(TriggerArea::new("hall", (0, 0, 0), (10, 4, 10)));
+        assert_eq!(areas.get(door).unwrap().min, (3, 0, 2));
+        assert_eq!(areas.find("hall"), Some(hall));
+        assert_eq!(areas.at((3, 1, 2)).collect::<Vec<_>>(), vec![door, hall]);
+        assert_eq!(areas.at((5, 1, 2)).collect::<Vec<_>>(), vec![hall]);
+        assert_eq!(areas.at((11, 1, 2)).count(), 0);
+
+        // ids aren't reused after an area is removed
+        areas.remove(door);
+        let vault = areas.insert(TriggerArea::new("vault", (20, 0, 0), (22, 2, 2)));
+        assert_ne!(vault, door);
+        assert_eq!(areas.len(), 2);
+
+        #[cfg(feature = "savedata")]
+        {
+            let dir =
+                std::env::temp_dir().join(format!("bevy_voxel_triggers_{}", std::process::id()));
+            assert_eq!(TriggerAreas::load(&dir).unwrap(), TriggerAreas::default());
+            areas.save(&dir).unwrap();
+            let loaded = TriggerAreas::load(&dir).unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            assert_eq!(loaded, areas);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use bevy::{prelude::*, render::draw::Draw, transform::prelude::Translation};
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{Map, MapUpdates},
+};
+#[cfg(feature = "bevy")]
+use crate::{render::pool::ChunkPool, world::Chunk};
+
+/// When `ChunkUnloader` unloads the chunks the viewer left behind.
+///
+/// `distance` should be larger than the distance chunks are streamed in within, so that a
+/// viewer moving back and forth over the edge of the view distance doesn't unload chunks and
+/// generate them again in turn.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnloadPolicy {
+    /// How many chunks from the viewer chunks are kept on the horizontal axes.
+    pub distance: i32,
+    /// Seconds a chunk has to stay farther away than `distance` before it's unloaded.
+    pub grace: f32,
+    /// Keeps the edited chunks loaded wherever the viewer goes, so that they're saved with
+    /// the rest of the map instead of being lost. Turn it off to save them from the
+    /// `ChunkUnloaded` events instead.
+    pub keep_edited: bool,
+}
+
+impl Default for UnloadPolicy {
+    fn default() -> Self {
+        Self {
+            distance: 10,
+            grace: 5.0,
+            keep_edited: true,
+        }
+    }
+}
+
+/// Tracks how long the chunks of a map have been out of range of the viewer. Added to the
+/// map entity, next to its `MapUpdates`.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkUnloader {
+    pub policy: UnloadPolicy,
+    /// Seconds every chunk out of range has been out of it.
+    away: HashMap<(i32, i32, i32), f32>,
+    unloaded: usize,
+}
+
+impl ChunkUnloader {
+    pub fn new(policy: UnloadPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Advances the timers by `delta` seconds with the viewer at `focus`, in world
+    /// coordinates, and returns the chunks of `map` that have been out of range for longer
+    /// than the grace time.
+    ///
+    /// Chunks that come back in range before that are kept, and wait the whole grace time
+    /// again the next time they're left behind. Edited chunks are never returned if the policy
+    /// keeps them.
+    pub fn update<T: Voxel>(
+        &mut self,
+        map: &Map<T>,
+        focus: (i32, i32, i32),
+        delta: f32,
+    ) -> Vec<(i32, i32, i32)> {
+        let (fx, _, fz) = focus;
+        let distance = self.policy.distance;
+        let mut away = HashMap::with_capacity(self.away.len());
+        let mut expired = Vec::new();
+        for chunk in map.iter() {
+            let (x, _, z) = chunk.position();
+            let width = chunk.width() as i32;
+            let dx = (x.div_euclid(width) - fx.div_euclid(width)).abs();
+            let dz = (z.div_euclid(width) - fz.div_euclid(width)).abs();
+            if dx.max(dz) <= distance || (self.policy.keep_edited && chunk.is_edited()) {
+                continue;
+            }
+            let coords = chunk.position();
+            let time = self.away.get(&coords).copied().unwrap_or(0.0) + delta;
+            if time >= self.policy.grace {
+                expired.push(coords);
+            } else {
+                away.insert(coords, time);
+            }
+        }
+        self.away = away;
+        self.unloaded += expired.len();
+        expired
+    }
+
+    /// How many chunks `update` returned so far.
+    pub fn unloaded(&self) -> usize {
+        self.unloaded
+    }
+}
+
+impl MapUpdates {
+    /// Drops the pending update and prefetch of the chunk at `coords`, e.g. one that was
+    /// unloaded.
+    pub fn forget(&mut self, coords: (i32, i32, i32)) {
+        self.updates.remove(&coords);
+        self.order.remove(&coords);
+        self.causes.remove(&coords);
+        self.prefetch.retain(|&prefetch| prefetch != coords);
+    }
+}
+
+/// A chunk `chunk_unload_update` removed from its map, for the game to save it if it was
+/// edited and `UnloadPolicy::keep_edited` is off. Its render entities were already released to
+/// the `ChunkPool`. Has to be registered with `add_event::<ChunkUnloaded<T>>()`.
+#[cfg(feature = "bevy")]
+#[derive(Debug, Clone)]
+pub struct ChunkUnloaded<T: Voxel> {
+    pub map: Entity,
+    pub chunk: Chunk<T>,
+}
+
+/// Unloads the chunks of every map with a `ChunkUnloader` that were out of range of the
+/// `MapUpdates::focus` for longer than the grace time, releases their render entities to the
+/// `ChunkPool` and sends them as `ChunkUnloaded` events. Maps without a focus keep all their
+/// chunks.
+#[cfg(feature = "bevy")]
+pub fn chunk_unload_update<T: Voxel>(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pool: ResMut<ChunkPool>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut events: ResMut<Events<ChunkUnloaded<T>>>,
+    mut query: Query<(Entity, &mut Map<T>, &mut MapUpdates, &mut ChunkUnloader)>,
+    entities: Query<(&Handle<Mesh>, &mut Draw, &mut Translation)>,
+) {
+    for (e, mut map, mut updates, mut unloader) in &mut query.iter() {
+        let focus = match updates.focus {
+            Some(focus) => focus,
+            None => continue,
+        };
+        for coords in unloader.update(&map, focus, time.delta_seconds) {
+            updates.forget(coords);
+            if let Some(mut chunk) = map.remove(coords) {
+                pool.release(&mut commands, &mut meshes, &entities, &chunk);
+                chunk.clear_entities();
+                events.send(ChunkUnloaded { map: e, chunk });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Chunk, ChunkUpdate};
+
+    #[test]
+    pub fn chunk_unloader() {
+        // streams in the columns within one chunk of the viewer, generating them at once, and
+        // returns how many chunks were generated
+        fn stream(
+            map: &mut Map<i32>,
+            updates: &mut MapUpdates,
+            unloader: &mut ChunkUnloader,
+            x: i32,
+        ) -> usize {
+            updates.focus = Some((x, 0, 0));
+            for cx in x.div_euclid(4) - 1..=x.div_euclid(4) + 1 {
+                if map.get((cx * 4, 0, 0)).is_none() {
+                    updates.request((cx * 4, 0, 0), ChunkUpdate::GenerateChunk);
+                }
+            }
+            let generated = updates.drain_kind(ChunkUpdate::GenerateChunk, usize::MAX);
+            for &coords in &generated {
+                map.try_insert(Chunk::new(2, coords)).unwrap();
+            }
+            for coords in unloader.update(map, (x, 0, 0), 0.5) {
+                updates.forget(coords);
+                map.remove(coords);
+            }
+            generated.len()
+        }
+
+        // a viewer moving back and forth over a chunk border keeps its chunks
+        let mut map = Map::new();
+        let mut updates = MapUpdates::default();
+        let policy = UnloadPolicy {
+            distance: 2,
+            grace: 2.0,
+            ..Default::default()
+        };
+        let mut unloader = ChunkUnloader::new(policy);
+        assert_eq!(stream(&mut map, &mut updates, &mut unloader, 2), 3);
+        for i in 0..20 {
+            let x = if i % 2 == 0 { 6 } else { 2 };
+            let generated = stream(&mut map, &mut updates, &mut unloader, x);
+            assert_eq!(generated, if i == 0 { 1 } else { 0 });
+        }
+        assert_eq!(unloader.unloaded(), 0);
+        assert_eq!(map.len(), 4);
+
+        // chunks stay out of range for the whole grace time before they're unloaded
+        let generated = (0..3)
+            .map(|_| stream(&mut map, &mut updates, &mut unloader, 14))
+            .sum::<usize>();
+        assert_eq!(generated, 2);
+        assert_eq!(map.len(), 6);
+        assert_eq!(unloader.unloaded(), 0);
+        stream(&mut map, &mut updates, &mut unloader, 14);
+        assert_eq!(unloader.unloaded(), 2);
+        assert!(map.get((-4, 0, 0)).is_none() && map.get((0, 0, 0)).is_none());
+
+        // without hysteresis, the same viewer unloads and generates chunks all the time
+        let mut map = Map::new();
+        let mut updates = MapUpdates::default();
+        let policy = UnloadPolicy {
+            distance: 1,
+            grace: 0.0,
+            ..Default::default()
+        };
+        let mut unloader = ChunkUnloader::new(policy);
+        let mut generated = 0;
+        for i in 0..20 {
+            let x = if i % 2 == 0 { 6 } else { 2 };
+            generated += stream(&mut map, &mut updates, &mut unloader, x);
+        }
+        assert_eq!(generated, 3 + 19);
+        assert_eq!(unloader.unloaded(), 19);
+    }
+
+    #[test]
+    pub fn unload_edited() {
+        let mut map = Map::<i32>::new();
+        map.try_insert(Chunk::new(2, (0, 0, 0))).unwrap();
+        map.try_insert(Chunk::new(2, (4, 0, 0))).unwrap();
+        map.get_at_origin_mut((4, 0, 0)).unwrap().set_edited(true);
+        let policy = UnloadPolicy {
+            distance: 1,
+            grace: 0.0,
+            ..Default::default()
+        };
+
+        // edited chunks stay loaded until they're saved with the map
+        let mut unloader = ChunkUnloader::new(policy);
+        assert_eq!(unloader.update(&map, (40, 0, 0), 1.0), vec![(0, 0, 0)]);
+
+        let mut unloader = ChunkUnloader::new(UnloadPolicy {
+            keep_edited: false,
+            ..policy
+        });
+        let mut unloaded = unloader.update(&map, (40, 0, 0), 1.0);
+        unloaded.sort();
+        assert_eq!(unloaded, vec![(0, 0, 0), (4, 0, 0)]);
+    }
+}
@@ -0,0 +1,169 @@
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{ChunkUpdate, Map, MapLayout, MapUpdates, UpdateCause},
+};
+
+/// Streams in the chunks around a starting point before the game starts, instead of
+/// generating them all at once and freezing while doing it.
+///
+/// The chunks within `radius` chunks of `center` are queued for generation ahead of the
+/// prefetched ones, nearest first. The world is ready once all of them went through the
+/// update pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmUp {
+    pub layout: MapLayout,
+    /// In world coordinates.
+    pub center: (i32, i32, i32),
+    pub radius: i32,
+    /// The lowest and highest chunk origins to warm up, in world coordinates.
+    pub heights: Option<(i32, i32)>,
+    queued: bool,
+    ready: usize,
+    finished: bool,
+}
+
+impl WarmUp {
+    pub fn new(layout: MapLayout, center: (i32, i32, i32), radius: i32) -> Self {
+        Self {
+            layout,
+            center,
+            radius,
+            heights: None,
+            queued: false,
+            ready: 0,
+            finished: false,
+        }
+    }
+
+    pub fn with_heights(mut self, min: i32, max: i32) -> Self {
+        self.heights = Some((min, max));
+        self
+    }
+
+    /// Returns the origins of the chunks to warm up, nearest to `center` first.
+    pub fn chunks(&self) -> Vec<(i32, i32, i32)> {
+        let width = self.layout.chunk_width as i32;
+        let (x, y, z) = self.layout.chunk_origin(self.center);
+        let mut chunks = Vec::new();
+        for lx in -self.radius..=self.radius {
+            for ly in -self.radius..=self.radius {
+                for lz in -self.radius..=self.radius {
+                    let coords = (x + lx * width, y + ly * width, z + lz * width);
+                    if let Some((min, max)) = self.heights {
+                        if coords.1 < min || coords.1 > max {
+                            continue;
+                        }
+                    }
+                    chunks.push(coords);
+                }
+            }
+        }
+        chunks.sort_by_key(|&(cx, cy, cz)| {
+            let (dx, dy, dz) = (cx - x, cy - y, cz - z);
+            dx * dx + dy * dy + dz * dz
+        });
+        chunks
+    }
+
+    /// Requests generation of the chunks missing from `map` and takes them out of the
+    /// prefetch queue. Returns the number of requested chunks.
+    pub fn queue<T: Voxel>(&mut self, map: &Map<T>, updates: &mut MapUpdates) -> usize {
+        self.queued = true;
+        let chunks = self
+            .chunks()
+            .into_iter()
+            .filter(|&coords| map.get_at_origin(coords).is_none())
+            .collect::<Vec<_>>();
+        updates.prefetch.retain(|coords| !chunks.contains(coords));
+        for &coords in &chunks {
+            updates.request_because(coords, ChunkUpdate::GenerateChunk, UpdateCause::Streaming);
+        }
+        chunks.len()
+    }
+
+    /// Counts the chunks that are loaded and have nothing pending anymore.
+    pub fn progress<T: Voxel>(&self, map: &Map<T>, updates: &MapUpdates) -> WarmUpProgress {
+        let chunks = self.chunks();
+        let ready = chunks
+            .iter()
+            .filter(|coords| {
+                map.get_at_origin(**coords).is_some() && !updates.updates.contains_key(coords)
+            })
+            .count();
+        WarmUpProgress {
+            ready,
+            total: chunks.len(),
+        }
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.queued
+    }
+
+    /// Whether `warm_up_update` sent `WorldReady`.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Sent by `warm_up_update` whenever more chunks of the `WarmUp` are ready. Has to be
+/// registered with `add_event::<WarmUpProgress>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarmUpProgress {
+    pub ready: usize,
+    pub total: usize,
+}
+
+impl WarmUpProgress {
+    /// The percentage of ready chunks, from 0 to 100.
+    pub fn percent(&self) -> f32 {
+        if self.total == 0 {
+            100.0
+        } else {
+            self.ready as f32 * 100.0 / self.total as f32
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.ready >= self.total
+    }
+}
+
+/// Sent once by `warm_up_update` when all chunks of the `WarmUp` are ready. Has to be
+/// registered with `add_event::<WorldReady>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldReady;
+
+/// Queues the chunks of the `WarmUp` resource in the first map and reports its progress.
+#[cfg(feature = "bevy")]
+pub fn warm_up_update<T: Voxel>(
+    mut warm_up: ResMut<WarmUp>,
+    mut progress_events: ResMut<Events<WarmUpProgress>>,
+    mut ready_events: ResMut<Events<WorldReady>>,
+    mut query: Query<(&Map<T>, &mut MapUpdates)>,
+) {
+    if warm_up.finished {
+        return;
+    }
+    let mut maps = query.iter();
+    let (map, mut updates) = if let Some(map) = (&mut maps).into_iter().next() {
+        map
+    } else {
+        return;
+    };
+    if !warm_up.queued {
+        warm_up.queue(&map, &mut updates);
+    }
+    let progress = warm_up.progress(&map, &updates);
+    if progress.ready != warm_up.ready || progress.is_done() {
+        warm_up.ready = progress.ready;
+        progress_events.send(progress);
+    }
+    if progress.is_done() {
+        warm_up.finished = true;
+        ready_events.send(WorldReady);
+    }
+}
@@ -0,0 +1,98 @@
+use rand::Rng;
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+use crate::{
+    collections::lod_tree::Voxel,
+    world::{ChangeCause, Map, MapUpdates},
+};
+
+/// A thin layer of voxels, like snow, that builds up on exposed top surfaces in a region and
+/// melts away again.
+///
+/// Every step `rate` random columns between `min` and `max`, both inclusive, are visited.
+/// While `falling` is set, the overlay is placed on top of the columns whose surface voxel
+/// passes `accepts`, otherwise it's removed from the columns it covers.
+#[derive(Debug, Clone)]
+pub struct Overlay<T> {
+    pub voxel: T,
+    pub accepts: fn(&T) -> bool,
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+    pub rate: usize,
+    pub falling: bool,
+}
+
+impl<T: Voxel> Overlay<T> {
+    pub fn new(voxel: T, accepts: fn(&T) -> bool, min: (i32, i32), max: (i32, i32)) -> Self {
+        Self {
+            voxel,
+            accepts,
+            min,
+            max,
+            rate: 32,
+            falling: true,
+        }
+    }
+
+    /// Deposits or melts the overlay on `rate` columns. Returns the number of voxels changed.
+    pub fn step<R: Rng>(&self, map: &mut Map<T>, updates: &mut MapUpdates, rng: &mut R) -> usize {
+        let _span = span!("overlay_step");
+        let mut count = 0;
+        for _ in 0..self.rate {
+            let x = rng.gen_range(self.min.0, self.max.0 + 1);
+            let z = rng.gen_range(self.min.1, self.max.1 + 1);
+            let changed = if self.falling {
+                self.deposit(map, updates, (x, z))
+            } else {
+                self.melt(map, updates, (x, z))
+            };
+            if changed {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Places the overlay on top of the column at `(x, z)`. Returns `false` if the column is
+    /// covered already, its surface doesn't accept the overlay or the voxel above it isn't
+    /// loaded.
+    pub fn deposit(&self, map: &mut Map<T>, updates: &mut MapUpdates, (x, z): (i32, i32)) -> bool {
+        let y = if let Some(y) = map.surface_height((x, z)) {
+            y
+        } else {
+            return false;
+        };
+        let accepted = map
+            .voxel((x, y, z))
+            .map(|top| *top != self.voxel && (self.accepts)(&top))
+            .unwrap_or(false);
+        let voxel = Some(self.voxel.clone());
+        accepted && map.set_voxel_because((x, y + 1, z), voxel, updates, ChangeCause::Weather)
+    }
+
+    /// Removes the overlay from the top of the column at `(x, z)`. Returns `false` if the
+    /// column isn't covered.
+    pub fn melt(&self, map: &mut Map<T>, updates: &mut MapUpdates, (x, z): (i32, i32)) -> bool {
+        let y = if let Some(y) = map.surface_height((x, z)) {
+            y
+        } else {
+            return false;
+        };
+        let covered = map
+            .voxel((x, y, z))
+            .map(|top| *top == self.voxel)
+            .unwrap_or(false);
+        covered && map.set_voxel_because((x, y, z), None, updates, ChangeCause::Weather)
+    }
+}
+
+/// Steps the `Overlay` of every map once per frame.
+#[cfg(feature = "bevy")]
+pub fn overlay_update<T: Voxel>(mut query: Query<(&Overlay<T>, &mut Map<T>, &mut MapUpdates)>) {
+    let mut rng = rand::thread_rng();
+    for (overlay, mut map, mut updates) in &mut query.iter() {
+        overlay.step(&mut map, &mut updates, &mut rng);
+    }
+}